@@ -0,0 +1,8 @@
+-- Single settings row shared by every API and worker instance
+CREATE TABLE outbox_state (
+  id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+  draining_since TIMESTAMPTZ NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO outbox_state DEFAULT VALUES;
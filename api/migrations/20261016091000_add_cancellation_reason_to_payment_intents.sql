@@ -0,0 +1,2 @@
+ALTER TABLE payment_intents
+ADD COLUMN cancellation_reason TEXT NULL;
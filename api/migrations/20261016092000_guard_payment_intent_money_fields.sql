@@ -0,0 +1,16 @@
+-- Amount and currency are frozen once an intent is processing or terminal,
+-- regardless of which code path (or manual SQL) attempts the change.
+CREATE FUNCTION payment_intents_guard_money_fields() RETURNS trigger AS $$
+BEGIN
+  IF OLD.status IN ('processing', 'requires_capture', 'succeeded', 'canceled')
+     AND (NEW.amount IS DISTINCT FROM OLD.amount OR NEW.currency IS DISTINCT FROM OLD.currency) THEN
+    RAISE EXCEPTION 'cannot change amount or currency of payment_intent % in status %', OLD.id, OLD.status
+      USING ERRCODE = 'check_violation', CONSTRAINT = 'payment_intents_money_immutable';
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER payment_intents_money_immutable
+BEFORE UPDATE ON payment_intents
+FOR EACH ROW EXECUTE FUNCTION payment_intents_guard_money_fields();
@@ -0,0 +1,9 @@
+CREATE TABLE audit_log (
+  id UUID PRIMARY KEY,
+  action TEXT NOT NULL,
+  resource_id UUID NOT NULL,
+  details JSONB NOT NULL DEFAULT '{}'::jsonb,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_log_resource_action_idx ON audit_log (resource_id, action, created_at);
@@ -0,0 +1,3 @@
+-- Shape version of response_body; existing rows predate cancellation_reason
+ALTER TABLE idempotency_keys
+ADD COLUMN body_version INT NOT NULL DEFAULT 1;
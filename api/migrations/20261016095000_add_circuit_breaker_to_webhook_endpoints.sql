@@ -0,0 +1,5 @@
+-- Per-endpoint circuit breaker state maintained by the worker.
+-- circuit_open_until NULL = closed, in the future = open, in the past = half-open (next delivery is a probe)
+ALTER TABLE webhook_endpoints
+ADD COLUMN consecutive_failures INT NOT NULL DEFAULT 0,
+ADD COLUMN circuit_open_until TIMESTAMPTZ NULL;
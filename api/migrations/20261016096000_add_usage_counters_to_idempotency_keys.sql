@@ -0,0 +1,7 @@
+-- Usage counters for GET /v1/admin/idempotency/stats
+ALTER TABLE idempotency_keys
+ADD COLUMN replay_count INT NOT NULL DEFAULT 0,
+ADD COLUMN conflict_count INT NOT NULL DEFAULT 0,
+ADD COLUMN reconstructed_count INT NOT NULL DEFAULT 0;
+
+CREATE INDEX idempotency_keys_created_at_idx ON idempotency_keys (created_at);
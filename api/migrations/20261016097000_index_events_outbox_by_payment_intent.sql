@@ -0,0 +1,3 @@
+-- Lets GET /v1/payment_intents/{id} look up the intent's latest event
+CREATE INDEX events_outbox_payment_intent_idx
+  ON events_outbox ((payload -> 'payment_intent' ->> 'id'), created_at DESC);
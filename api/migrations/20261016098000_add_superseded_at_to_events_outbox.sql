@@ -0,0 +1,4 @@
+-- Set by the worker when a compactable event is collapsed into a later one for the same resource.
+-- Superseded events are never delivered.
+ALTER TABLE events_outbox
+ADD COLUMN superseded_at TIMESTAMPTZ NULL;
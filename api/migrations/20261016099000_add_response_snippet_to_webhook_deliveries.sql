@@ -0,0 +1,6 @@
+-- What the receiver answered on the latest attempt. The snippet is bounded and sanitized by the worker.
+ALTER TABLE webhook_deliveries
+ADD COLUMN response_status INT NULL,
+ADD COLUMN response_snippet TEXT NULL,
+ADD COLUMN response_content_length BIGINT NULL,
+ADD COLUMN response_truncated BOOLEAN NOT NULL DEFAULT false;
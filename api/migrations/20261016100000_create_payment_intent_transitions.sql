@@ -0,0 +1,25 @@
+-- Every payment intent status change, written in the same transaction as the change.
+-- Append-only: rows can never be updated or deleted, by the app or by manual SQL.
+CREATE TABLE payment_intent_transitions (
+  id UUID PRIMARY KEY,
+  payment_intent_id UUID NOT NULL REFERENCES payment_intents (id),
+  from_status TEXT,
+  to_status TEXT NOT NULL,
+  -- What caused it: `api` for API calls, otherwise the policy/job name
+  cause TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX payment_intent_transitions_intent_idx
+  ON payment_intent_transitions (payment_intent_id, created_at);
+
+CREATE FUNCTION payment_intent_transitions_append_only() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'payment_intent_transitions is append-only'
+    USING ERRCODE = 'check_violation', CONSTRAINT = 'payment_intent_transitions_append_only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER payment_intent_transitions_append_only
+BEFORE UPDATE OR DELETE ON payment_intent_transitions
+FOR EACH ROW EXECUTE FUNCTION payment_intent_transitions_append_only();
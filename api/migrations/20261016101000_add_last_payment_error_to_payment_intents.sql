@@ -0,0 +1,2 @@
+-- `{code, message, simulated}` from the most recent failed confirm; cleared on success
+ALTER TABLE payment_intents ADD COLUMN last_payment_error JSONB;
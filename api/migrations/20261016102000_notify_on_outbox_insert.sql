@@ -0,0 +1,12 @@
+-- Wakes the worker as soon as events commit (NOTIFY is delivered on commit and dropped on
+-- rollback). One notification per statement; the worker drains everything due when woken.
+CREATE FUNCTION events_outbox_notify() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify('outbox_new_event', '');
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER events_outbox_notify
+AFTER INSERT ON events_outbox
+FOR EACH STATEMENT EXECUTE FUNCTION events_outbox_notify();
@@ -0,0 +1,7 @@
+-- Receiver latency of the latest attempt (time to response headers), for endpoint stats
+ALTER TABLE webhook_deliveries ADD COLUMN response_time_ms INT NULL;
+
+-- Endpoint stats and delivery history both scan one endpoint's deliveries by creation time
+DROP INDEX webhook_deliveries_endpoint_idx;
+CREATE INDEX webhook_deliveries_endpoint_created_idx
+  ON webhook_deliveries (webhook_endpoint_id, created_at);
@@ -0,0 +1,7 @@
+-- Backstops for the API validation: a malformed currency or oversized key is a CHECK violation,
+-- never an oversized index entry. NOT VALID: only new writes are checked.
+ALTER TABLE payment_intents
+ADD CONSTRAINT payment_intents_currency_format CHECK (currency ~ '^[A-Za-z]{3}$') NOT VALID;
+
+ALTER TABLE idempotency_keys
+ADD CONSTRAINT idempotency_keys_key_length CHECK (octet_length(key) BETWEEN 1 AND 255) NOT VALID;
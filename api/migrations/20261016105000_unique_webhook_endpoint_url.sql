@@ -0,0 +1,2 @@
+-- URLs are normalized by the API before insert, so equal strings mean the same receiver
+ALTER TABLE webhook_endpoints ADD CONSTRAINT webhook_endpoints_url_unique UNIQUE (url);
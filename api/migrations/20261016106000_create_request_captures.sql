@@ -0,0 +1,15 @@
+-- Opt-in copies of mutating API requests for debugging client integrations.
+-- Short-lived: rows older than REQUEST_CAPTURE_TTL are pruned by the API.
+CREATE TABLE request_captures (
+  id UUID PRIMARY KEY,
+  method TEXT NOT NULL,
+  path TEXT NOT NULL,
+  headers JSONB NOT NULL DEFAULT '{}'::jsonb,
+  -- Capped and redacted; may be cut mid-document when body_truncated
+  body TEXT NOT NULL,
+  body_truncated BOOLEAN NOT NULL DEFAULT false,
+  response_status INT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX request_captures_created_at_idx ON request_captures (created_at);
@@ -0,0 +1,3 @@
+-- First 2xx delivery of this intent's payment_intent.succeeded event to any endpoint.
+-- Kept up to date by the worker so GET does not have to join through deliveries.
+ALTER TABLE payment_intents ADD COLUMN webhook_acknowledged_at TIMESTAMPTZ NULL;
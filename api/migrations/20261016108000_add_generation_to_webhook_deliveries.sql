@@ -0,0 +1,13 @@
+-- Redelivery adds a new generation of an (event, endpoint) delivery instead of resetting the old row.
+-- At most one generation may be active (pending or in_progress) at a time.
+ALTER TABLE webhook_deliveries ADD COLUMN generation INT NOT NULL DEFAULT 1;
+
+ALTER TABLE webhook_deliveries
+  DROP CONSTRAINT webhook_deliveries_event_id_webhook_endpoint_id_key;
+ALTER TABLE webhook_deliveries
+  ADD CONSTRAINT webhook_deliveries_generation_unique
+  UNIQUE (event_id, webhook_endpoint_id, generation);
+
+CREATE UNIQUE INDEX webhook_deliveries_one_active_idx
+  ON webhook_deliveries (event_id, webhook_endpoint_id)
+  WHERE status IN ('pending', 'in_progress');
@@ -0,0 +1,2 @@
+-- Sandbox-only soft delete for finished intents: hidden from lists, still readable by id.
+ALTER TABLE payment_intents ADD COLUMN archived_at TIMESTAMPTZ NULL;
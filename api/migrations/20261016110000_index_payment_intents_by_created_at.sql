@@ -0,0 +1,2 @@
+-- Keyset pagination walks (created_at, id) newest first
+CREATE INDEX payment_intents_created_at_id_idx ON payment_intents (created_at, id);
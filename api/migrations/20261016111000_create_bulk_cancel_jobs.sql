@@ -0,0 +1,26 @@
+-- Admin bulk cancellation. The matching intents are snapshotted when the job is created; each
+-- call works through up to a capped number of them and can be resumed with the job id.
+CREATE TABLE bulk_cancel_jobs (
+  id UUID PRIMARY KEY,
+  created_after TIMESTAMPTZ NULL,
+  created_before TIMESTAMPTZ NULL,
+  matched INT NOT NULL,
+  canceled INT NOT NULL DEFAULT 0,
+  -- No longer requires_confirmation by the time the job reached them
+  skipped INT NOT NULL DEFAULT 0,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  completed_at TIMESTAMPTZ NULL
+);
+
+CREATE TABLE bulk_cancel_job_items (
+  job_id UUID NOT NULL REFERENCES bulk_cancel_jobs (id) ON DELETE CASCADE,
+  payment_intent_id UUID NOT NULL REFERENCES payment_intents (id),
+  -- canceled or skipped; NULL until processed
+  outcome TEXT NULL,
+  PRIMARY KEY (job_id, payment_intent_id)
+);
+
+CREATE INDEX bulk_cancel_job_items_pending_idx
+  ON bulk_cancel_job_items (job_id)
+  WHERE outcome IS NULL;
@@ -0,0 +1,14 @@
+-- Last-run marker for the worker's daily failed-delivery digest, so a restart (or a second
+-- worker) never sends the same day twice
+CREATE TABLE webhook_digest_state (
+  id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+  -- The UTC day the most recent digest covered
+  last_digest_date DATE NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO webhook_digest_state DEFAULT VALUES;
+
+CREATE INDEX webhook_deliveries_failed_updated_at_idx
+  ON webhook_deliveries (updated_at)
+  WHERE status = 'failed';
@@ -0,0 +1,3 @@
+-- Job-style endpoints (bulk_cancel) store the job they started instead of a response body
+ALTER TABLE idempotency_keys
+ADD COLUMN job_id UUID NULL;
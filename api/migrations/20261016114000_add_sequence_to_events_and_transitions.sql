@@ -0,0 +1,7 @@
+-- Rows written in one transaction share created_at (now() is the transaction start), e.g. the
+-- created and succeeded events of a create with confirm. The sequence orders them.
+ALTER TABLE events_outbox
+ADD COLUMN sequence BIGINT GENERATED ALWAYS AS IDENTITY;
+
+ALTER TABLE payment_intent_transitions
+ADD COLUMN sequence BIGINT GENERATED ALWAYS AS IDENTITY;
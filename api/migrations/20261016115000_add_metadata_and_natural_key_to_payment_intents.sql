@@ -0,0 +1,12 @@
+-- Free-form string metadata from the create request
+ALTER TABLE payment_intents
+ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}'::jsonb;
+
+-- The configured NATURAL_IDEMPOTENCY_KEY value at create time (e.g. metadata.order_id).
+-- At most one intent that isn't canceled may hold each value.
+ALTER TABLE payment_intents
+ADD COLUMN natural_key TEXT NULL;
+
+CREATE UNIQUE INDEX payment_intents_natural_key_unique
+ON payment_intents (natural_key)
+WHERE natural_key IS NOT NULL AND status <> 'canceled';
@@ -0,0 +1,4 @@
+-- How deliveries to the endpoint are signed: v1 (HMAC-SHA256) or v2 (HMAC-SHA512, timestamped)
+ALTER TABLE webhook_endpoints
+ADD COLUMN signature_scheme TEXT NOT NULL DEFAULT 'v1'
+CHECK (signature_scheme IN ('v1', 'v2'));
@@ -0,0 +1,20 @@
+-- One row per running webhook worker process, rewritten every heartbeat interval. A row whose
+-- last_heartbeat_at stops moving is a worker that died or hung.
+CREATE TABLE worker_heartbeats (
+  instance_id UUID PRIMARY KEY,
+  hostname TEXT NOT NULL,
+  pid INTEGER NOT NULL,
+  started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  last_heartbeat_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  -- Totals since the process started
+  deliveries_claimed BIGINT NOT NULL DEFAULT 0,
+  deliveries_succeeded BIGINT NOT NULL DEFAULT 0,
+  deliveries_failed BIGINT NOT NULL DEFAULT 0,
+  -- Claims that found due deliveries but all of them locked by other workers
+  claim_contention BIGINT NOT NULL DEFAULT 0,
+  -- How long the last and the slowest pass over due deliveries took
+  last_loop_ms BIGINT NOT NULL DEFAULT 0,
+  max_loop_ms BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE INDEX worker_heartbeats_last_heartbeat_at_idx ON worker_heartbeats (last_heartbeat_at);
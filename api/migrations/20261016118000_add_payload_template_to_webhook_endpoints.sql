@@ -0,0 +1,4 @@
+-- Optional reshaping of payment_intent.* webhook bodies (see mini_stripe_types::payload_template).
+-- Validated by the API on write; the outbox keeps the original envelope either way.
+ALTER TABLE webhook_endpoints
+ADD COLUMN payload_template JSONB;
@@ -0,0 +1,19 @@
+-- The id of the transaction that wrote the event. Once every transaction older than a snapshot's
+-- xmin has finished, no event with a smaller txid can still commit, so the event exporter can
+-- follow (txid, sequence) without skipping events that commit late. Rows from before this
+-- migration all get the migration's own id and are ordered by sequence.
+ALTER TABLE events_outbox
+ADD COLUMN txid BIGINT NOT NULL DEFAULT pg_current_xact_id()::text::bigint;
+
+CREATE INDEX events_outbox_txid_sequence_idx ON events_outbox (txid, sequence);
+
+-- How far the event exporter has got in each output partition (the UTC day of created_at).
+-- It resumes from the furthest (last_txid, last_sequence) of any partition.
+CREATE TABLE event_export_watermarks (
+  partition_date DATE PRIMARY KEY,
+  last_event_id UUID NOT NULL,
+  last_txid BIGINT NOT NULL,
+  last_sequence BIGINT NOT NULL,
+  exported_count BIGINT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
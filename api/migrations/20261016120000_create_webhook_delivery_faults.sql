@@ -0,0 +1,13 @@
+-- Sandbox fault injection: webhook_timeout rules, mirrored here by POST /v1/admin/faults so the
+-- worker (a separate process) can see them. Only a worker running with SANDBOX_MODE reads it.
+CREATE TABLE webhook_delivery_faults (
+  id UUID PRIMARY KEY,
+  webhook_endpoint_id UUID NOT NULL REFERENCES webhook_endpoints (id) ON DELETE CASCADE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  -- Deliveries the worker failed because of this rule
+  activations BIGINT NOT NULL DEFAULT 0,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhook_delivery_faults_endpoint_idx
+  ON webhook_delivery_faults (webhook_endpoint_id, expires_at);
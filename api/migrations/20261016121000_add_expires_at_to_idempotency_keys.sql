@@ -0,0 +1,20 @@
+-- Idempotency keys expire IDEMPOTENCY_KEY_TTL after they are reserved; NULL (keys from before
+-- this, or with no TTL configured) never expires. Deleted in batches by idempotency_cleanup.rs.
+ALTER TABLE idempotency_keys ADD COLUMN expires_at TIMESTAMPTZ NULL;
+
+-- The cleanup's progress, so a restarted API resumes a run instead of rescanning from the start.
+-- One row. A run in progress has a cutoff; it deletes keys that expired before it, in
+-- (key, endpoint) order, and the cursor is the last one it deleted.
+CREATE TABLE idempotency_cleanup_state (
+  id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+  run_cutoff TIMESTAMPTZ NULL,
+  cursor_key TEXT NOT NULL DEFAULT '',
+  cursor_endpoint TEXT NOT NULL DEFAULT '',
+  run_started_at TIMESTAMPTZ NULL,
+  run_deleted BIGINT NOT NULL DEFAULT 0,
+  last_run_finished_at TIMESTAMPTZ NULL,
+  last_run_deleted BIGINT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO idempotency_cleanup_state DEFAULT VALUES;
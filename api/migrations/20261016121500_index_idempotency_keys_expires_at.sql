@@ -0,0 +1,6 @@
+-- no-transaction
+-- Built concurrently so a large idempotency_keys table stays writable meanwhile. Only rows with
+-- an expiry are indexed, which is all the cleanup ever scans for.
+CREATE INDEX CONCURRENTLY IF NOT EXISTS idempotency_keys_expires_at_idx
+  ON idempotency_keys (expires_at)
+  WHERE expires_at IS NOT NULL;
@@ -0,0 +1,15 @@
+-- A client certificate the worker presents to receivers that require mutual TLS. The PEM
+-- certificate and private key are stored together, encrypted like the secret (see
+-- mini_stripe_types::crypto). The other columns are read from the certificate on upload.
+ALTER TABLE webhook_endpoints
+  ADD COLUMN client_certificate TEXT NULL,
+  -- Hex SHA-256 of the leaf certificate (DER); the worker caches its TLS clients by it
+  ADD COLUMN client_certificate_fingerprint TEXT NULL,
+  ADD COLUMN client_certificate_subject TEXT NULL,
+  ADD COLUMN client_certificate_expires_at TIMESTAMPTZ NULL,
+  -- When webhook_endpoint.certificate_expiring was emitted for this certificate
+  ADD COLUMN client_certificate_expiry_notified_at TIMESTAMPTZ NULL,
+  ADD CONSTRAINT webhook_endpoints_client_certificate_complete CHECK (
+    (client_certificate IS NULL) = (client_certificate_fingerprint IS NULL)
+    AND (client_certificate IS NULL) = (client_certificate_expires_at IS NULL)
+  );
@@ -0,0 +1,8 @@
+-- Declarative sync (PUT /v1/webhook_endpoints/sync). external_id is the caller's stable name for
+-- an endpoint; only endpoints that have one are managed by sync. enabled_events lists the event
+-- types delivered to the endpoint, '*' meaning all of them.
+ALTER TABLE webhook_endpoints
+  ADD COLUMN external_id TEXT NULL,
+  ADD COLUMN description TEXT NULL,
+  ADD COLUMN enabled_events TEXT[] NOT NULL DEFAULT '{*}',
+  ADD CONSTRAINT webhook_endpoints_external_id_unique UNIQUE (external_id);
@@ -0,0 +1,5 @@
+-- `manual` intents stop at requires_capture when confirmed and succeed on
+-- POST /v1/payment_intents/{id}/capture. Existing intents were all captured on confirm.
+ALTER TABLE payment_intents
+ADD COLUMN capture_method TEXT NOT NULL DEFAULT 'automatic'
+  CONSTRAINT payment_intents_capture_method_check CHECK (capture_method IN ('automatic', 'manual'));
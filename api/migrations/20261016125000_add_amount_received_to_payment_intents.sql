@@ -0,0 +1,7 @@
+-- What was actually captured: the full amount when an automatic intent succeeds, or the
+-- capture's amount_to_capture (at most the authorized amount) for a manual one. 0 until then.
+ALTER TABLE payment_intents
+ADD COLUMN amount_received BIGINT NOT NULL DEFAULT 0
+  CONSTRAINT payment_intents_amount_received_check CHECK (amount_received BETWEEN 0 AND amount);
+
+UPDATE payment_intents SET amount_received = amount WHERE status = 'succeeded';
@@ -0,0 +1,4 @@
+-- Free text set on create or PATCH, returned as given. The API caps it at 1000 bytes first.
+ALTER TABLE payment_intents
+ADD COLUMN description TEXT NULL
+  CONSTRAINT payment_intents_description_length CHECK (octet_length(description) <= 1000);
@@ -0,0 +1,7 @@
+-- Backstop for PaymentIntentStatus: the API parses this column and treats anything else as a bug.
+-- Which status may follow which is enforced in the API (PaymentIntentStatus::can_transition_to).
+ALTER TABLE payment_intents
+ADD CONSTRAINT payment_intents_status_check CHECK (status IN (
+  'requires_confirmation', 'requires_payment_method', 'requires_action', 'processing',
+  'requires_capture', 'succeeded', 'canceled'
+));
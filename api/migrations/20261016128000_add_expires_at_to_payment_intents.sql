@@ -0,0 +1,11 @@
+-- An intent still requires_confirmation at this time is canceled with cancellation_reason
+-- 'expired', by the API's expirer or by the confirm that finds it first.
+ALTER TABLE payment_intents
+ADD COLUMN expires_at TIMESTAMPTZ NOT NULL DEFAULT now() + interval '24 hours';
+
+UPDATE payment_intents SET expires_at = created_at + interval '24 hours';
+
+-- The expirer's scan: only intents that can still expire
+CREATE INDEX payment_intents_expiring_idx
+ON payment_intents (expires_at)
+WHERE status = 'requires_confirmation';
@@ -0,0 +1,4 @@
+-- The payment method the last confirm was processed with (a test token such as pm_card_visa).
+-- NULL for intents confirmed without one.
+ALTER TABLE payment_intents
+ADD COLUMN payment_method TEXT NULL;
@@ -0,0 +1,21 @@
+-- One row per successful payment: the money actually moved, kept apart from the intent so
+-- refunds and disputes have something to point at. Written in the transaction that moves the
+-- intent to succeeded, for `amount_received`.
+CREATE TABLE charges (
+  id UUID PRIMARY KEY,
+  payment_intent_id UUID NOT NULL REFERENCES payment_intents (id),
+  amount BIGINT NOT NULL CHECK (amount > 0),
+  currency TEXT NOT NULL,
+  status TEXT NOT NULL CHECK (status IN ('succeeded')),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- An intent succeeds once, so it has at most one charge
+CREATE UNIQUE INDEX charges_payment_intent_idx ON charges (payment_intent_id);
+CREATE INDEX charges_created_at_idx ON charges (created_at DESC, id DESC);
+
+-- Intents that succeeded before charges existed
+INSERT INTO charges (id, payment_intent_id, amount, currency, status, created_at)
+SELECT gen_random_uuid(), id, amount_received, currency, 'succeeded', created_at
+FROM payment_intents
+WHERE status = 'succeeded' AND amount_received > 0;
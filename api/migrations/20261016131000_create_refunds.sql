@@ -0,0 +1,21 @@
+-- What has been refunded of each charge. Kept on the charge and checked here as a backstop: the
+-- API locks the charge and refuses a refund that would go over before writing anything.
+ALTER TABLE charges
+  ADD COLUMN amount_refunded BIGINT NOT NULL DEFAULT 0,
+  ADD CONSTRAINT charges_amount_refunded_check CHECK (amount_refunded BETWEEN 0 AND amount);
+
+-- Money given back from a charge, in one or more parts. Written with the charge's new
+-- amount_refunded and the refund.created event in one transaction.
+CREATE TABLE refunds (
+  id UUID PRIMARY KEY,
+  charge_id UUID NOT NULL REFERENCES charges (id),
+  payment_intent_id UUID NOT NULL REFERENCES payment_intents (id),
+  amount BIGINT NOT NULL CHECK (amount > 0),
+  currency TEXT NOT NULL,
+  status TEXT NOT NULL CHECK (status IN ('succeeded')),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX refunds_charge_idx ON refunds (charge_id, created_at DESC);
+CREATE INDEX refunds_payment_intent_idx ON refunds (payment_intent_id, created_at DESC);
+CREATE INDEX refunds_created_at_idx ON refunds (created_at DESC, id DESC);
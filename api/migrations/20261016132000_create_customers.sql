@@ -0,0 +1,20 @@
+-- Payers, so a merchant can group payments by who made them. Deleting one only sets deleted_at:
+-- its intents keep pointing at it, but the API no longer returns it or lets new intents use it.
+CREATE TABLE customers (
+  id UUID PRIMARY KEY,
+  email TEXT,
+  name TEXT,
+  description TEXT,
+  metadata JSONB NOT NULL DEFAULT '{}'::jsonb,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  deleted_at TIMESTAMPTZ
+);
+
+CREATE INDEX customers_created_at_idx ON customers (created_at DESC, id DESC)
+  WHERE deleted_at IS NULL;
+CREATE INDEX customers_email_idx ON customers (email) WHERE deleted_at IS NULL;
+
+ALTER TABLE payment_intents ADD COLUMN customer_id UUID REFERENCES customers (id);
+
+CREATE INDEX payment_intents_customer_idx ON payment_intents (customer_id, created_at DESC, id DESC)
+  WHERE customer_id IS NOT NULL;
@@ -0,0 +1,16 @@
+-- Saved cards. Only what can be shown back is kept: brand, last4 and expiry, never the number.
+-- processor_token is the test token (pm_card_visa, pm_card_declined, ...) the number behaved
+-- like, so a confirm with the saved method gets the same outcome as with the test card.
+CREATE TABLE payment_methods (
+  id UUID PRIMARY KEY,
+  customer_id UUID REFERENCES customers (id),
+  brand TEXT NOT NULL,
+  last4 TEXT NOT NULL CHECK (last4 ~ '^[0-9]{4}$'),
+  exp_month INT NOT NULL CHECK (exp_month BETWEEN 1 AND 12),
+  exp_year INT NOT NULL,
+  processor_token TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX payment_methods_customer_idx ON payment_methods (customer_id, created_at DESC, id DESC)
+  WHERE customer_id IS NOT NULL;
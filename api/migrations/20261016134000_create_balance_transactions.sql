@@ -0,0 +1,26 @@
+-- The ledger behind GET /v1/balance: one row per charge and refund, written in the same
+-- transaction as it. net = amount - fee; a row counts as pending until available_on.
+CREATE TABLE balance_transactions (
+  id UUID PRIMARY KEY,
+  type TEXT NOT NULL CHECK (type IN ('charge', 'refund')),
+  source_id UUID NOT NULL,
+  amount BIGINT NOT NULL,
+  fee BIGINT NOT NULL CHECK (fee >= 0),
+  net BIGINT NOT NULL,
+  currency TEXT NOT NULL,
+  available_on TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  CHECK (net = amount - fee)
+);
+
+CREATE UNIQUE INDEX balance_transactions_source_idx ON balance_transactions (type, source_id);
+CREATE INDEX balance_transactions_created_at_idx ON balance_transactions (created_at DESC, id DESC);
+
+-- Charges and refunds from before the ledger, with no fee and available from when they happened
+INSERT INTO balance_transactions (id, type, source_id, amount, fee, net, currency, available_on, created_at)
+SELECT gen_random_uuid(), 'charge', id, amount, 0, amount, currency, created_at, created_at
+FROM charges;
+
+INSERT INTO balance_transactions (id, type, source_id, amount, fee, net, currency, available_on, created_at)
+SELECT gen_random_uuid(), 'refund', id, -amount, 0, -amount, currency, created_at, created_at
+FROM refunds;
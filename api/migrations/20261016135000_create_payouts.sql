@@ -0,0 +1,24 @@
+-- Manual payouts out of the available balance. Created pending with their balance transaction;
+-- the settlement worker marks them paid or failed once arrival_date has passed.
+CREATE TABLE payouts (
+  id UUID PRIMARY KEY,
+  amount BIGINT NOT NULL CHECK (amount > 0),
+  currency TEXT NOT NULL,
+  status TEXT NOT NULL CHECK (status IN ('pending', 'paid', 'failed')),
+  destination TEXT NOT NULL,
+  description TEXT,
+  arrival_date TIMESTAMPTZ NOT NULL,
+  failure_code TEXT,
+  failure_message TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  CHECK ((status = 'failed') = (failure_code IS NOT NULL))
+);
+
+CREATE INDEX payouts_created_at_idx ON payouts (created_at DESC, id DESC);
+-- What the settlement worker scans
+CREATE INDEX payouts_pending_idx ON payouts (arrival_date) WHERE status = 'pending';
+
+-- A payout takes its amount out of the balance; a failed one puts it back
+ALTER TABLE balance_transactions DROP CONSTRAINT balance_transactions_type_check;
+ALTER TABLE balance_transactions ADD CONSTRAINT balance_transactions_type_check
+  CHECK (type IN ('charge', 'refund', 'payout', 'payout_failure'));
@@ -0,0 +1,5 @@
+-- The attempt limit a delivery was created with (the worker's WEBHOOK_MAX_DELIVERY_ATTEMPTS).
+-- Kept per row so the API shows the retries the worker will actually make, whatever either
+-- process is configured with now.
+ALTER TABLE webhook_deliveries
+  ADD COLUMN max_attempts INT NOT NULL DEFAULT 10 CHECK (max_attempts >= 1);
@@ -0,0 +1,7 @@
+-- A declined intent can be confirmed again with another payment method, and one waiting on
+-- customer action can still go through, so both expire like an unconfirmed one.
+DROP INDEX payment_intents_expiring_idx;
+
+CREATE INDEX payment_intents_expiring_idx
+ON payment_intents (expires_at)
+WHERE status IN ('requires_confirmation', 'requires_payment_method', 'requires_action');
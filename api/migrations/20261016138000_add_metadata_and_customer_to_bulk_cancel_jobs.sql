@@ -0,0 +1,5 @@
+-- The rest of a job's filter: the metadata pairs a matching intent had to carry ('{}' for none)
+-- and the customer it had to belong to
+ALTER TABLE bulk_cancel_jobs
+ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}',
+ADD COLUMN customer_id UUID NULL;
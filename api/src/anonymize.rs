@@ -0,0 +1,299 @@
+use rand::distr::{Alphanumeric, SampleString};
+use sqlx::{PgConnection, PgPool, Row};
+
+// Rows rewritten per statement, so a big copy never holds long row locks or one huge transaction
+const BATCH_SIZE: i64 = 500;
+
+// One rewrite of the declarative map. `set` is the SET list of an UPDATE run on every row of
+// `table`, batched in `key` order; it can use the pg_temp helpers created by `helpers`.
+struct Rule {
+    table: &'static str,
+    // Primary key columns and their types
+    key: &'static [(&'static str, &'static str)],
+    set: &'static str,
+}
+
+// Every column that can hold PII or a live credential. Metadata is where merchants put emails,
+// names and order references; its values map deterministically (within one run), so equal
+// values stay equal and unique ones stay unique.
+const RULES: &[Rule] = &[
+    Rule {
+        table: "webhook_endpoints",
+        key: &[("id", "uuid")],
+        set: "url = pg_temp.example_url(id), secret = pg_temp.new_secret()",
+    },
+    // natural_key is a copy of one metadata value, so it gets the same mapping
+    Rule {
+        table: "payment_intents",
+        key: &[("id", "uuid")],
+        set: "metadata = pg_temp.anon_metadata(metadata), natural_key = pg_temp.anon(natural_key)",
+    },
+    // A job's filter can name metadata pairs; mapped the same way, it still names its intents
+    Rule {
+        table: "bulk_cancel_jobs",
+        key: &[("id", "uuid")],
+        set: "metadata = pg_temp.anon_metadata(metadata)",
+    },
+    // Payloads embed the intent (with metadata) or the endpoint (with its URL)
+    Rule {
+        table: "events_outbox",
+        key: &[("id", "uuid")],
+        set: "payload = CASE
+                WHEN payload->'payment_intent' ? 'metadata' THEN jsonb_set(
+                    payload, '{payment_intent,metadata}',
+                    pg_temp.anon_metadata(payload->'payment_intent'->'metadata'))
+                WHEN payload->'webhook_endpoint' ? 'url' THEN jsonb_set(
+                    payload, '{webhook_endpoint,url}',
+                    to_jsonb(pg_temp.example_url((payload->'webhook_endpoint'->>'id')::uuid)))
+                ELSE payload
+              END",
+    },
+    // Stored create responses; ones without a body are rebuilt from the intent on replay. The
+    // request fingerprint embeds the metadata too, so after this a retry of an original request
+    // is a 409 rather than a replay.
+    Rule {
+        table: "idempotency_keys",
+        key: &[("key", "text"), ("endpoint", "text")],
+        set: "request_hash = CASE
+                WHEN request_hash LIKE '%metadata=%' THEN pg_temp.anon(request_hash)
+                ELSE request_hash
+              END,
+              response_body = CASE
+                WHEN response_body ? 'metadata' THEN jsonb_set(
+                    response_body, '{metadata}', pg_temp.anon_metadata(response_body->'metadata'))
+                ELSE response_body
+              END",
+    },
+    // Receivers' response bodies are arbitrary, and errors quote the URL that was called
+    Rule {
+        table: "webhook_deliveries",
+        key: &[("id", "uuid")],
+        set: "response_snippet = NULL,
+              last_error = regexp_replace(last_error, 'https?://[^\\s)\"]+', 'https://redacted.example.org', 'g')",
+    },
+];
+
+// Raw request bodies: there is nothing worth keeping, so they are deleted
+const DELETED_TABLES: &[&str] = &["request_captures"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum AnonymizeError {
+    #[error("refusing to anonymize: set CONFIRM_ANONYMIZE={database} to confirm the target")]
+    NotConfirmed { database: String },
+    #[error("original domains still present after anonymizing: {0:?}")]
+    VerificationFailed(Vec<DomainHit>),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DomainHit {
+    pub domain: String,
+    pub table: String,
+    pub rows: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct AnonymizeReport {
+    // (table, rows rewritten or deleted), in the order they ran
+    pub tables: Vec<(&'static str, u64)>,
+}
+
+// Rewrites the PII in the database `db` points at, in place. Meant for a copy of production:
+// it refuses unless `confirm` (CONFIRM_ANONYMIZE) names the connected database. Afterwards every
+// table is searched for `domains` (the original customer and receiver domains), and any hit is
+// an error.
+pub async fn run(
+    db: &PgPool,
+    confirm: Option<&str>,
+    domains: &[String],
+) -> Result<AnonymizeReport, AnonymizeError> {
+    let database: String = sqlx::query_scalar("SELECT current_database()")
+        .fetch_one(db)
+        .await?;
+    if confirm != Some(database.as_str()) {
+        return Err(AnonymizeError::NotConfirmed { database });
+    }
+
+    // The pg_temp helpers only exist on the connection that created them
+    let mut conn = db.acquire().await?;
+    helpers(&mut conn).await?;
+
+    let mut report = AnonymizeReport::default();
+    for rule in RULES {
+        report
+            .tables
+            .push((rule.table, rewrite(&mut conn, rule).await?));
+    }
+    for table in DELETED_TABLES {
+        report
+            .tables
+            .push((table, delete_all(&mut conn, table).await?));
+    }
+
+    let hits = find_domains(&mut conn, domains).await?;
+    if !hits.is_empty() {
+        return Err(AnonymizeError::VerificationFailed(hits));
+    }
+    Ok(report)
+}
+
+async fn helpers(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    // Salted so a mapped value can't be reversed by hashing guesses; alphanumeric, so it is
+    // safe to inline
+    let salt = Alphanumeric.sample_string(&mut rand::rng(), 32);
+    let statements = [
+        format!(
+            "CREATE OR REPLACE FUNCTION pg_temp.anon(v text) RETURNS text
+             AS $$ SELECT 'anon_' || left(md5('{salt}' || v), 20) $$ LANGUAGE sql IMMUTABLE"
+        ),
+        "CREATE OR REPLACE FUNCTION pg_temp.anon_metadata(m jsonb) RETURNS jsonb
+         AS $$
+             SELECT CASE WHEN jsonb_typeof(m) = 'object' THEN
+                 (SELECT COALESCE(jsonb_object_agg(k, pg_temp.anon(v)), '{}'::jsonb)
+                  FROM jsonb_each_text(m) AS e(k, v))
+             ELSE m END
+         $$ LANGUAGE sql IMMUTABLE"
+            .to_string(),
+        // The endpoint id makes it unique, as webhook_endpoints_url_unique requires
+        "CREATE OR REPLACE FUNCTION pg_temp.example_url(id uuid) RETURNS text
+         AS $$ SELECT 'https://' || id || '.example.org/webhooks' $$ LANGUAGE sql IMMUTABLE"
+            .to_string(),
+        // 32 characters like webhook_endpoints::generate_secret, from gen_random_uuid's CSPRNG
+        "CREATE OR REPLACE FUNCTION pg_temp.new_secret() RETURNS text
+         AS $$ SELECT left(replace(gen_random_uuid()::text || gen_random_uuid()::text, '-', ''), 32) $$
+         LANGUAGE sql VOLATILE"
+            .to_string(),
+    ];
+    for statement in statements {
+        sqlx::query(&statement).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+async fn rewrite(conn: &mut PgConnection, rule: &Rule) -> Result<u64, sqlx::Error> {
+    let columns = rule.key.iter().map(|(c, _)| *c).collect::<Vec<_>>();
+    let aliases = (0..columns.len())
+        .map(|i| format!("k{i}"))
+        .collect::<Vec<_>>();
+    let select = columns
+        .iter()
+        .zip(&aliases)
+        .map(|(c, a)| format!("{c} AS {a}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let params = rule
+        .key
+        .iter()
+        .enumerate()
+        .map(|(i, (_, ty))| format!("${}::{ty}", i + 1))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let last = aliases
+        .iter()
+        .map(|a| format!("{a}::text"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let descending = aliases
+        .iter()
+        .map(|a| format!("{a} DESC"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let (columns, aliases) = (columns.join(", "), aliases.join(", "));
+    let table = rule.table;
+
+    let statement = |after: bool| {
+        let filter = if after {
+            format!("WHERE ({columns}) > ({params})")
+        } else {
+            String::new()
+        };
+        format!(
+            "WITH batch AS (
+                 SELECT {select} FROM {table} {filter} ORDER BY {columns} LIMIT {BATCH_SIZE}
+             ),
+             updated AS (
+                 UPDATE {table} SET {set}
+                 FROM batch WHERE ({columns}) = ({aliases})
+                 RETURNING 1
+             )
+             SELECT (SELECT COUNT(*) FROM updated) AS rewritten,
+                    (SELECT ARRAY[{last}] FROM batch ORDER BY {descending} LIMIT 1) AS last",
+            set = rule.set,
+        )
+    };
+    let (first, next) = (statement(false), statement(true));
+
+    let mut total = 0;
+    let mut cursor: Option<Vec<String>> = None;
+    loop {
+        let mut query = sqlx::query(if cursor.is_some() { &next } else { &first });
+        for value in cursor.iter().flatten() {
+            query = query.bind(value);
+        }
+        let row = query.fetch_one(&mut *conn).await?;
+        total += row.get::<i64, _>("rewritten") as u64;
+        match row.get::<Option<Vec<String>>, _>("last") {
+            Some(last) => cursor = Some(last),
+            None => return Ok(total),
+        }
+    }
+}
+
+async fn delete_all(conn: &mut PgConnection, table: &str) -> Result<u64, sqlx::Error> {
+    let statement =
+        format!("DELETE FROM {table} WHERE id IN (SELECT id FROM {table} LIMIT {BATCH_SIZE})");
+    let mut total = 0;
+    loop {
+        let deleted = sqlx::query(&statement)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Ok(total);
+        }
+        total += deleted;
+    }
+}
+
+// The verification pass: searches the text of every row of every table
+async fn find_domains(
+    conn: &mut PgConnection,
+    domains: &[String],
+) -> Result<Vec<DomainHit>, sqlx::Error> {
+    let tables: Vec<String> = sqlx::query_scalar(
+        "SELECT table_name::text FROM information_schema.tables
+         WHERE table_schema = 'public' AND table_type = 'BASE TABLE'
+           AND table_name <> '_sqlx_migrations'
+         ORDER BY table_name",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut hits = Vec::new();
+    for domain in domains {
+        let pattern = format!(
+            "%{}%",
+            domain
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        for table in &tables {
+            let rows: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(*) FROM {table} AS r WHERE r::text ILIKE $1"
+            ))
+            .bind(&pattern)
+            .fetch_one(&mut *conn)
+            .await?;
+            if rows > 0 {
+                hits.push(DomainHit {
+                    domain: domain.clone(),
+                    table: table.clone(),
+                    rows,
+                });
+            }
+        }
+    }
+    Ok(hits)
+}
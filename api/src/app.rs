@@ -1,39 +1,330 @@
 use axum::{
     Router,
-    routing::{get, post},
+    extract::Request,
+    http::{Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
 };
+use tokio::net::TcpListener;
+use tracing::warn;
 
-use crate::{payment_intents, state::AppState, webhook_endpoints};
+use crate::error::{ApiError, negotiate_errors};
+use crate::{
+    backpressure, balance, caching, charges, client_certificates, customers, event_export,
+    events_outbox, examples, faults, idempotency, idempotency_cleanup, in_flight, latency_alerts,
+    metrics, payment_intents, payment_methods, payouts, permissions, refunds, request_capture,
+    response_signing, routes, sandbox, seed, slow_queries, state::AppState, warmup,
+    webhook_endpoints, webhook_sync, workers,
+};
 
 async fn health() -> &'static str {
     "ok"
 }
 
+async fn route_not_found() -> ApiError {
+    ApiError::not_found("unrecognized request URL")
+}
+
+// OPTIONS on any route: 204 listing what the route allows. No route registers OPTIONS itself,
+// so the router answers 405 with an Allow header and no handler runs.
+async fn answer_options(req: Request, next: Next) -> Response {
+    if req.method() != Method::OPTIONS {
+        return next.run(req).await;
+    }
+
+    let res = next.run(req).await;
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return res;
+    }
+    let Some(allow) = res
+        .headers()
+        .get(header::ALLOW)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return res;
+    };
+
+    let allow = format!("{allow},OPTIONS");
+    (StatusCode::NO_CONTENT, [(header::ALLOW, allow)]).into_response()
+}
+
 pub fn build_app(state: AppState) -> Router {
-    Router::new()
-        .route("/health", get(health))
+    let signing = state.config.response_signing_secret.is_some();
+    let backlog_limited = state.config.event_backlog_high_water.is_some();
+    let latency_alerting = state.config.latency_alerting.is_some();
+    let routes = Router::new()
+        .route(routes::HEALTH, get(health))
+        .route(routes::READYZ, get(warmup::readyz))
+        .route(routes::METRICS, get(metrics::get_metrics))
+        .route(
+            routes::PAYMENT_INTENTS,
+            post(payment_intents::create_payment_intent).get(payment_intents::list_payment_intents),
+        )
+        .route(
+            routes::PAYMENT_INTENTS_BULK_CANCEL,
+            post(payment_intents::bulk_cancel_payment_intents),
+        )
+        .route(
+            routes::PAYMENT_INTENT,
+            get(payment_intents::get_payment_intent).patch(payment_intents::update_payment_intent),
+        )
         .route(
-            "/v1/payment_intents",
-            post(payment_intents::create_payment_intent),
+            routes::PAYMENT_INTENT_ARCHIVE,
+            post(payment_intents::archive_payment_intent),
         )
         .route(
-            "/v1/payment_intents/{id}",
-            get(payment_intents::get_payment_intent),
+            routes::PAYMENT_INTENT_TRANSITIONS,
+            get(payment_intents::list_payment_intent_transitions),
+        )
+        .route(
+            routes::PAYMENT_INTENT_WAIT,
+            get(payment_intents::wait_for_payment_intent),
         )
         .with_state(state.clone())
         .route(
-            "/v1/payment_intents/{id}/confirm",
+            routes::PAYMENT_INTENT_CONFIRM,
             post(payment_intents::confirm_payment_intent),
         )
+        .route(
+            routes::PAYMENT_INTENT_CAPTURE,
+            post(payment_intents::capture_payment_intent),
+        )
+        .with_state(state.clone())
+        .route(routes::CHARGES, get(charges::list_charges))
+        .route(routes::CHARGE, get(charges::get_charge))
+        .route(
+            routes::REFUNDS,
+            post(refunds::create_refund).get(refunds::list_refunds),
+        )
+        .route(routes::REFUND, get(refunds::get_refund))
+        .route(
+            routes::CUSTOMERS,
+            post(customers::create_customer).get(customers::list_customers),
+        )
+        .route(
+            routes::CUSTOMER,
+            get(customers::get_customer)
+                .patch(customers::update_customer)
+                .delete(customers::delete_customer),
+        )
+        .route(
+            routes::PAYMENT_METHODS,
+            post(payment_methods::create_payment_method).get(payment_methods::list_payment_methods),
+        )
+        .route(
+            routes::PAYMENT_METHOD,
+            get(payment_methods::get_payment_method),
+        )
+        .route(
+            routes::PAYMENT_METHOD_ATTACH,
+            post(payment_methods::attach_payment_method),
+        )
+        .route(
+            routes::PAYMENT_METHOD_DETACH,
+            post(payment_methods::detach_payment_method),
+        )
+        .route(routes::BALANCE, get(balance::get_balance))
+        .route(
+            routes::BALANCE_TRANSACTIONS,
+            get(balance::list_balance_transactions),
+        )
+        .route(
+            routes::BALANCE_TRANSACTION,
+            get(balance::get_balance_transaction),
+        )
+        .route(
+            routes::PAYOUTS,
+            post(payouts::create_payout).get(payouts::list_payouts),
+        )
+        .route(routes::PAYOUT, get(payouts::get_payout))
         .with_state(state.clone())
         .route(
-            "/v1/webhook_endpoints",
+            routes::WEBHOOK_ENDPOINTS,
             post(webhook_endpoints::create_webhook_endpoint),
         )
         .with_state(state.clone())
         .route(
-            "/v1/webhook_endpoints",
+            routes::WEBHOOK_ENDPOINTS,
             get(webhook_endpoints::list_webhook_endpoints),
         )
         .with_state(state.clone())
+        .route(
+            routes::WEBHOOK_ENDPOINTS_SYNC,
+            put(webhook_sync::sync_webhook_endpoints),
+        )
+        .route(
+            routes::WEBHOOK_ENDPOINT,
+            get(webhook_endpoints::get_webhook_endpoint)
+                .patch(webhook_endpoints::update_webhook_endpoint),
+        )
+        .route(
+            routes::WEBHOOK_ENDPOINT_DELIVERIES,
+            get(webhook_endpoints::list_webhook_endpoint_deliveries),
+        )
+        .route(
+            routes::WEBHOOK_ENDPOINT_STATS,
+            get(webhook_endpoints::get_webhook_endpoint_stats),
+        )
+        .route(
+            routes::WEBHOOK_ENDPOINT_REVEAL_SECRET,
+            post(webhook_endpoints::reveal_webhook_endpoint_secret),
+        )
+        .route(
+            routes::WEBHOOK_ENDPOINT_PREVIEW,
+            post(webhook_endpoints::preview_webhook_endpoint_payload),
+        )
+        .route(
+            routes::WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE,
+            put(client_certificates::upload_client_certificate)
+                .delete(client_certificates::delete_client_certificate),
+        )
+        .with_state(state.clone())
+        .route(
+            routes::EVENT_REDELIVER,
+            post(events_outbox::redeliver_event),
+        )
+        .route(routes::EXAMPLE, get(examples::get_example))
+        .route(
+            routes::ADMIN_OUTBOX_DRAIN,
+            post(events_outbox::start_outbox_drain),
+        )
+        .route(
+            routes::ADMIN_OUTBOX_DRAIN_STATUS,
+            get(events_outbox::get_outbox_drain_status),
+        )
+        .route(
+            routes::ADMIN_OUTBOX_RESUME,
+            post(events_outbox::resume_outbox),
+        )
+        .with_state(state.clone())
+        .route(
+            routes::ADMIN_IDEMPOTENCY_STATS,
+            get(idempotency::get_idempotency_stats),
+        )
+        .route(
+            routes::ADMIN_IDEMPOTENCY_CLEANUP,
+            get(idempotency_cleanup::get_cleanup_state).post(idempotency_cleanup::run_cleanup),
+        )
+        .route(routes::ADMIN_SEED, post(seed::seed))
+        .route(routes::ADMIN_DIAGNOSTICS, get(in_flight::get_diagnostics))
+        .route(routes::ADMIN_WORKERS, get(workers::list_workers))
+        .route(
+            routes::ADMIN_EVENT_EXPORT,
+            get(event_export::get_event_export_status),
+        )
+        .route(
+            routes::ADMIN_FAULTS,
+            post(faults::create_fault)
+                .get(faults::list_faults)
+                .delete(faults::clear_faults),
+        )
+        .route(
+            routes::ADMIN_REQUEST_CAPTURES,
+            get(request_capture::list_request_captures),
+        )
+        .route(
+            routes::ADMIN_REQUEST_CAPTURE_REPLAY,
+            post(request_capture::replay_request_capture),
+        )
+        .route(routes::SANDBOX_ECHO, post(sandbox::receive_echo_delivery))
+        .route(
+            routes::SANDBOX_ECHO_DELIVERIES,
+            get(sandbox::list_echo_deliveries),
+        )
+        .with_state(state.clone())
+        .fallback(route_not_found)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            slow_queries::instrument,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_capture::capture,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            caching::cache_headers,
+        ));
+
+    // All off by default, and then not layered at all
+    let routes = if latency_alerting {
+        routes.layer(middleware::from_fn_with_state(
+            state.clone(),
+            latency_alerts::observe,
+        ))
+    } else {
+        routes
+    };
+    let routes = if backlog_limited {
+        routes.layer(middleware::from_fn_with_state(
+            state.clone(),
+            backpressure::reject_when_backlogged,
+        ))
+    } else {
+        routes
+    };
+    let routes = if signing {
+        routes.layer(middleware::from_fn_with_state(
+            state.clone(),
+            response_signing::sign,
+        ))
+    } else {
+        routes
+    };
+    // Outermost of the route layers, so nothing else runs for a request it turns away
+    let routes = routes.layer(middleware::from_fn_with_state(
+        state,
+        permissions::authorize,
+    ));
+
+    // Outside the router so it sees the Allow header axum adds to its 405s
+    Router::new()
+        .fallback_service(routes)
+        .layer(middleware::from_fn(answer_options))
+        .layer(middleware::from_fn(negotiate_errors))
+        .layer(middleware::from_fn(in_flight::scope_request_id))
+}
+
+// Serves until `shutdown` resolves, then stops accepting and waits (up to
+// config.shutdown_timeout) for in-flight payment mutations. Anything still running after that
+// is logged and dropped with the server.
+pub async fn serve(
+    listener: TcpListener,
+    state: AppState,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let in_flight = state.in_flight.clone();
+    let deadline = state.config.shutdown_timeout;
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+
+    let server = tokio::spawn(
+        axum::serve(listener, build_app(state))
+            .with_graceful_shutdown(async {
+                stop_rx.await.ok();
+            })
+            .into_future(),
+    );
+
+    shutdown.await;
+    stop_tx.send(()).ok();
+
+    if let Err(stragglers) = in_flight.wait_idle(deadline).await {
+        for straggler in &stragglers {
+            warn!(
+                operation = %straggler.operation,
+                request_id = straggler.request_id.as_deref(),
+                ?deadline,
+                "shutdown: gave up waiting for in-flight operation"
+            );
+        }
+        server.abort();
+        return Ok(());
+    }
+
+    // Mutations are done; give their responses a moment to flush
+    match tokio::time::timeout(std::time::Duration::from_secs(1), server).await {
+        Ok(Ok(result)) => result,
+        _ => Ok(()),
+    }
 }
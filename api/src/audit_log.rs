@@ -0,0 +1,23 @@
+use uuid::Uuid;
+
+pub async fn insert_audit_entry(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    action: &str,
+    resource_id: Uuid,
+    details: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (id, action, resource_id, details)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::new_v4(),
+        action,
+        resource_id,
+        details
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
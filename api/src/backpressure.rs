@@ -0,0 +1,128 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use mini_stripe_types::error::codes;
+use tracing::warn;
+
+use crate::error::ApiError;
+use crate::events_outbox::pending_event_count;
+use crate::routes;
+use crate::state::AppState;
+
+// Pending outbox depth as last sampled, and whether mutations are being refused because of it
+#[derive(Clone, Default)]
+pub struct Backpressure {
+    depth: Arc<AtomicI64>,
+    engaged: Arc<AtomicBool>,
+}
+
+impl Backpressure {
+    pub fn depth(&self) -> i64 {
+        self.depth.load(Ordering::Acquire)
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.engaged.load(Ordering::Acquire)
+    }
+
+    // Engages above `high`, releases at or below `low`, and holds in between so a backlog
+    // hovering around one mark doesn't flap
+    pub fn record(&self, depth: i64, high: i64, low: i64) {
+        self.depth.store(depth, Ordering::Release);
+        if depth > high {
+            self.engaged.store(true, Ordering::Release);
+        } else if depth <= low {
+            self.engaged.store(false, Ordering::Release);
+        }
+    }
+}
+
+// Low-water mark; half the high one unless configured
+fn low_water(state: &AppState, high: i64) -> i64 {
+    state.config.event_backlog_low_water.unwrap_or(high / 2)
+}
+
+// One sample of the pending depth into state.backpressure (no-op when backpressure is off)
+pub async fn sample(state: &AppState) -> Result<(), sqlx::Error> {
+    let Some(high) = state.config.event_backlog_high_water else {
+        return Ok(());
+    };
+
+    let depth = pending_event_count(&state.db, None).await?;
+    state
+        .backpressure
+        .record(depth, high, low_water(state, high));
+    Ok(())
+}
+
+// Samples every config.event_backlog_sample_interval until the process exits
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(state.config.event_backlog_sample_interval);
+    loop {
+        interval.tick().await;
+        if let Err(e) = sample(&state).await {
+            warn!(error = %e, "event backlog sample failed");
+        }
+    }
+}
+
+// Middleware, only layered when config.event_backlog_high_water is set. Reads always pass, and so
+// do admin and sandbox calls: they are how the backlog gets drained.
+pub async fn reject_when_backlogged(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let path = req.uri().path();
+    let exempt = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || path.starts_with(routes::ADMIN_PREFIX)
+        || path.starts_with(routes::SANDBOX_PREFIX);
+    if exempt || !state.backpressure.is_engaged() {
+        return next.run(req).await;
+    }
+
+    let retry_after = state.config.event_backlog_sample_interval.as_secs().max(1);
+    let mut response = ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        codes::EVENT_BACKLOG,
+        format!(
+            "{} events are waiting for delivery; retry later",
+            state.backpressure.depth()
+        ),
+    )
+    .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_applies_hysteresis() {
+        let backpressure = Backpressure::default();
+
+        backpressure.record(100, 100, 50);
+        assert!(!backpressure.is_engaged());
+        backpressure.record(101, 100, 50);
+        assert!(backpressure.is_engaged());
+
+        // Between the marks nothing changes, in either direction
+        backpressure.record(75, 100, 50);
+        assert!(backpressure.is_engaged());
+        backpressure.record(50, 100, 50);
+        assert!(!backpressure.is_engaged());
+        backpressure.record(75, 100, 50);
+        assert!(!backpressure.is_engaged());
+        assert_eq!(backpressure.depth(), 75);
+    }
+}
@@ -0,0 +1,168 @@
+pub mod storage;
+
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::pagination::PageLimit;
+use crate::services::DomainError;
+use crate::state::AppState;
+use storage::{
+    BalanceTransaction, balance_totals, fetch_balance_transaction,
+    list_balance_transactions as list_balance_transaction_rows,
+};
+
+pub use mini_stripe_types::balance::{
+    BalanceAmount, BalanceResponse, BalanceTransactionList, BalanceTransactionResponse,
+    BalanceTransactionStatus, BalanceTransactionType,
+};
+
+// What a charge costs and when its money can be paid out, from PROCESSING_FEE_BPS and
+// BALANCE_AVAILABLE_AFTER
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BalancePolicy {
+    pub fee_bps: i64,
+    pub available_after: Duration,
+}
+
+impl BalancePolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            fee_bps: config.processing_fee_bps.unwrap_or(0),
+            available_after: config.balance_available_after.unwrap_or_default(),
+        }
+    }
+
+    // The one place a fee is rounded: basis points of the amount, to the nearest minor unit with
+    // halves rounded up. Config keeps fee_bps within 0..=10000, so it never exceeds the amount.
+    pub fn fee(self, amount: i64) -> i64 {
+        ((i128::from(amount) * i128::from(self.fee_bps) + 5_000) / 10_000) as i64
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListBalanceTransactionsQuery {
+    // The last id of the previous page
+    starting_after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+impl From<BalanceTransaction> for BalanceTransactionResponse {
+    fn from(txn: BalanceTransaction) -> Self {
+        Self {
+            kind: txn.kind(),
+            status: if txn.available_on <= Utc::now() {
+                BalanceTransactionStatus::Available
+            } else {
+                BalanceTransactionStatus::Pending
+            },
+            id: txn.id,
+            amount: txn.amount,
+            fee: txn.fee,
+            net: txn.net,
+            currency: txn.currency,
+            source: txn.source_id,
+            available_on: txn.available_on,
+            created_at: txn.created_at,
+        }
+    }
+}
+
+// From the primary: a balance that lags a refund just made would look like money to pay out
+pub async fn get_balance(State(state): State<AppState>) -> Result<Json<BalanceResponse>, ApiError> {
+    let totals = balance_totals(&state.db).await?;
+    let amounts = |pick: fn(&storage::BalanceTotals) -> i64| {
+        totals
+            .iter()
+            .map(|t| BalanceAmount {
+                amount: pick(t),
+                currency: t.currency.clone(),
+            })
+            .collect()
+    };
+    Ok(Json(BalanceResponse {
+        available: amounts(|t| t.available),
+        pending: amounts(|t| t.pending),
+    }))
+}
+
+pub async fn get_balance_transaction(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BalanceTransactionResponse>, ApiError> {
+    let txn = fetch_balance_transaction(&state.db, id)
+        .await?
+        .ok_or(DomainError::NotFound("balance_transaction"))?;
+    Ok(Json(BalanceTransactionResponse::from(txn)))
+}
+
+// From the read replica like the other lists
+pub async fn list_balance_transactions(
+    State(state): State<AppState>,
+    Query(query): Query<ListBalanceTransactionsQuery>,
+) -> Result<Json<BalanceTransactionList>, ApiError> {
+    let page = PageLimit::resolve(&state.config, query.limit)?;
+    if let Some(after) = query.starting_after
+        && fetch_balance_transaction(&state.read_db, after)
+            .await?
+            .is_none()
+    {
+        return Err(DomainError::InvalidParameter(format!(
+            "starting_after: no such balance_transaction: {after}"
+        ))
+        .into());
+    }
+    let mut txns =
+        list_balance_transaction_rows(&state.read_db, query.starting_after, page.limit + 1).await?;
+    let has_more = txns.len() as i64 > page.limit;
+    txns.truncate(page.limit as usize);
+
+    Ok(Json(BalanceTransactionList {
+        object: "list".to_string(),
+        data: txns
+            .into_iter()
+            .map(BalanceTransactionResponse::from)
+            .collect(),
+        has_more,
+        limit: page.limit,
+        max_limit: page.max_limit,
+        warnings: page.warnings(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fees_round_half_up_to_the_minor_unit() {
+        let policy = BalancePolicy {
+            fee_bps: 290,
+            ..Default::default()
+        };
+        assert_eq!(policy.fee(1000), 29);
+        // 0.29 rounds down, 0.58 up and 1.45 down
+        assert_eq!(policy.fee(10), 0);
+        assert_eq!(policy.fee(20), 1);
+        assert_eq!(policy.fee(50), 1);
+        let half = BalancePolicy {
+            fee_bps: 1_000,
+            ..Default::default()
+        };
+        assert_eq!(half.fee(5), 1);
+        assert_eq!(BalancePolicy::default().fee(1000), 0);
+        let all = BalancePolicy {
+            fee_bps: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(all.fee(i64::MAX), i64::MAX);
+    }
+}
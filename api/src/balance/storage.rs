@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use mini_stripe_types::balance::BalanceTransactionType;
+use uuid::Uuid;
+
+// One field per balance_transactions column, read with `SELECT *`/`RETURNING *` like PaymentIntent
+#[derive(Debug, Clone)]
+pub struct BalanceTransaction {
+    pub id: Uuid,
+    pub r#type: String,
+    pub source_id: Uuid,
+    pub amount: i64,
+    pub fee: i64,
+    pub net: i64,
+    pub currency: String,
+    pub available_on: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BalanceTransaction {
+    // The column's CHECK keeps it to the known types
+    pub fn kind(&self) -> BalanceTransactionType {
+        BalanceTransactionType::parse(&self.r#type).unwrap_or_else(|| {
+            panic!(
+                "balance transaction {} has unknown type {}",
+                self.id, self.r#type
+            )
+        })
+    }
+}
+
+pub struct NewBalanceTransaction<'a> {
+    pub kind: BalanceTransactionType,
+    pub source_id: Uuid,
+    pub amount: i64,
+    pub fee: i64,
+    pub currency: &'a str,
+    // From now
+    pub available_after: Duration,
+}
+
+// Per currency, in currency order
+pub struct BalanceTotals {
+    pub currency: String,
+    pub available: i64,
+    pub pending: i64,
+}
+
+// In the transaction that wrote the charge, refund or payout
+pub async fn insert_balance_transaction(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    new: NewBalanceTransaction<'_>,
+) -> Result<BalanceTransaction, sqlx::Error> {
+    sqlx::query_as!(
+        BalanceTransaction,
+        r#"
+        INSERT INTO balance_transactions
+          (id, type, source_id, amount, fee, net, currency, available_on)
+        VALUES ($1, $2, $3, $4::bigint, $5::bigint, $4 - $5, $6, now() + make_interval(secs => $7))
+        RETURNING *
+        "#,
+        Uuid::new_v4(),
+        new.kind.as_str(),
+        new.source_id,
+        new.amount,
+        new.fee,
+        new.currency,
+        new.available_after.as_secs_f64()
+    )
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn fetch_balance_transaction(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<BalanceTransaction>, sqlx::Error> {
+    sqlx::query_as!(
+        BalanceTransaction,
+        r#"
+        SELECT *
+        FROM balance_transactions
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Newest first, after the `starting_after` transaction when given
+pub async fn list_balance_transactions(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    starting_after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<BalanceTransaction>, sqlx::Error> {
+    sqlx::query_as!(
+        BalanceTransaction,
+        r#"
+        SELECT *
+        FROM balance_transactions
+        WHERE $1::uuid IS NULL
+           OR (created_at, id) < (SELECT created_at, id FROM balance_transactions WHERE id = $1)
+        ORDER BY created_at DESC, id DESC
+        LIMIT $2
+        "#,
+        starting_after,
+        limit
+    )
+    .fetch_all(executor)
+    .await
+}
+
+pub async fn balance_totals(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+) -> Result<Vec<BalanceTotals>, sqlx::Error> {
+    sqlx::query_as!(
+        BalanceTotals,
+        r#"
+        SELECT
+          currency,
+          COALESCE(SUM(net) FILTER (WHERE available_on <= now()), 0)::bigint AS "available!",
+          COALESCE(SUM(net) FILTER (WHERE available_on > now()), 0)::bigint AS "pending!"
+        FROM balance_transactions
+        GROUP BY currency
+        ORDER BY currency
+        "#
+    )
+    .fetch_all(executor)
+    .await
+}
+
+// Held until the transaction ends by everything that takes money out of a currency's available
+// balance (payouts and refunds), so a payout's balance check can't race another withdrawal
+pub async fn lock_available_balance(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    currency: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock(hashtext('payouts:' || $1))",
+        currency
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// What can be paid out in one currency right now
+pub async fn available_balance(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    currency: &str,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(SUM(net), 0)::bigint AS "available!"
+        FROM balance_transactions
+        WHERE currency = $1 AND available_on <= now()
+        "#,
+        currency
+    )
+    .fetch_one(executor)
+    .await
+}
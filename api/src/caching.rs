@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderValue, Method, header},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::routes;
+use crate::state::AppState;
+
+// Defaults for config.cache_stats_max_age and config.cache_list_max_age
+const DEFAULT_STATS_MAX_AGE: Duration = Duration::from_secs(5);
+const DEFAULT_LIST_MAX_AGE: Duration = Duration::ZERO;
+
+const NO_STORE: HeaderValue = HeaderValue::from_static("no-store");
+
+// How long a successful GET of a route may be reused by the client that made it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheClass {
+    // Aggregates that are expensive and fine to be a few seconds old
+    Stats,
+    // Collections that change as resources are created
+    List,
+    // Admin and sandbox routes: captured requests, secrets, internal state. Never stored.
+    Sensitive,
+}
+
+pub fn class(route: &str) -> Option<CacheClass> {
+    match route {
+        routes::WEBHOOK_ENDPOINT_STATS | routes::ADMIN_IDEMPOTENCY_STATS => Some(CacheClass::Stats),
+        routes::PAYMENT_INTENTS
+        | routes::PAYMENT_INTENT_TRANSITIONS
+        | routes::CHARGES
+        | routes::REFUNDS
+        | routes::CUSTOMERS
+        | routes::PAYMENT_METHODS
+        | routes::BALANCE_TRANSACTIONS
+        | routes::PAYOUTS
+        | routes::WEBHOOK_ENDPOINTS
+        | routes::WEBHOOK_ENDPOINT_DELIVERIES => Some(CacheClass::List),
+        _ if route.starts_with(routes::ADMIN_PREFIX)
+            || route.starts_with(routes::SANDBOX_PREFIX) =>
+        {
+            Some(CacheClass::Sensitive)
+        }
+        _ => None,
+    }
+}
+
+fn cache_control(state: &AppState, class: CacheClass) -> HeaderValue {
+    let max_age = match class {
+        CacheClass::Stats => state
+            .config
+            .cache_stats_max_age
+            .unwrap_or(DEFAULT_STATS_MAX_AGE),
+        CacheClass::List => state
+            .config
+            .cache_list_max_age
+            .unwrap_or(DEFAULT_LIST_MAX_AGE),
+        CacheClass::Sensitive => return NO_STORE,
+    };
+
+    let mut value = format!("private, max-age={}", max_age.as_secs());
+    if let Some(swr) = state.config.cache_stale_while_revalidate {
+        value.push_str(&format!(", stale-while-revalidate={}", swr.as_secs()));
+    }
+    HeaderValue::from_str(&value).expect("cache-control is ASCII")
+}
+
+// Middleware. Mutations and failed requests are always `no-store`; successful GETs get their
+// route class's policy, and routes without a class are left to the handler. Cacheable responses
+// vary on Authorization, so a shared cache never mixes callers once there are API keys.
+pub async fn cache_headers(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let reading = matches!(*req.method(), Method::GET | Method::HEAD);
+    let class = req
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|p| class(p.as_str()));
+
+    let mut response = next.run(req).await;
+    if response.headers().contains_key(header::CACHE_CONTROL) {
+        return response;
+    }
+
+    let value = match class {
+        _ if !reading || !response.status().is_success() => NO_STORE,
+        Some(class) => cache_control(&state, class),
+        None => return response,
+    };
+    let headers = response.headers_mut();
+    if value != NO_STORE {
+        headers.insert(header::VARY, HeaderValue::from_static("Authorization"));
+    }
+    headers.insert(header::CACHE_CONTROL, value);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_route_with_a_class_is_classified_as_expected() {
+        assert_eq!(
+            class(routes::WEBHOOK_ENDPOINT_STATS),
+            Some(CacheClass::Stats)
+        );
+        assert_eq!(class(routes::PAYMENT_INTENTS), Some(CacheClass::List));
+        assert_eq!(
+            class(routes::ADMIN_REQUEST_CAPTURES),
+            Some(CacheClass::Sensitive)
+        );
+        assert_eq!(
+            class(routes::SANDBOX_ECHO_DELIVERIES),
+            Some(CacheClass::Sensitive)
+        );
+        assert_eq!(class(routes::PAYMENT_INTENT), None);
+    }
+}
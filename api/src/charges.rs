@@ -0,0 +1,85 @@
+pub mod storage;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::pagination::PageLimit;
+use crate::services::DomainError;
+use crate::state::AppState;
+use storage::{Charge, fetch_charge, list_charges as list_charge_rows};
+
+pub use mini_stripe_types::charges::{ChargeList, ChargeResponse, ChargeStatus};
+
+#[derive(Deserialize)]
+pub struct ListChargesQuery {
+    // Only this intent's charge
+    payment_intent: Option<Uuid>,
+    // The last id of the previous page
+    starting_after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+impl From<Charge> for ChargeResponse {
+    fn from(charge: Charge) -> Self {
+        Self {
+            status: charge.status(),
+            id: charge.id,
+            payment_intent: charge.payment_intent_id,
+            amount: charge.amount,
+            currency: charge.currency,
+            created_at: charge.created_at,
+            amount_refunded: charge.amount_refunded,
+            refunded: charge.amount_refunded == charge.amount,
+        }
+    }
+}
+
+pub async fn get_charge(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ChargeResponse>, ApiError> {
+    let charge = fetch_charge(&state.db, id)
+        .await?
+        .ok_or(DomainError::NotFound("charge"))?;
+    Ok(Json(ChargeResponse::from(charge)))
+}
+
+// From the read replica like the intent list, so a charge written a moment ago may be missing
+pub async fn list_charges(
+    State(state): State<AppState>,
+    Query(query): Query<ListChargesQuery>,
+) -> Result<Json<ChargeList>, ApiError> {
+    let page = PageLimit::resolve(&state.config, query.limit)?;
+    if let Some(after) = query.starting_after
+        && fetch_charge(&state.read_db, after).await?.is_none()
+    {
+        return Err(DomainError::InvalidParameter(format!(
+            "starting_after: no such charge: {after}"
+        ))
+        .into());
+    }
+    // One extra row says whether there are more
+    let mut charges = list_charge_rows(
+        &state.read_db,
+        query.payment_intent,
+        query.starting_after,
+        page.limit + 1,
+    )
+    .await?;
+    let has_more = charges.len() as i64 > page.limit;
+    charges.truncate(page.limit as usize);
+
+    Ok(Json(ChargeList {
+        object: "list".to_string(),
+        data: charges.into_iter().map(ChargeResponse::from).collect(),
+        has_more,
+        limit: page.limit,
+        max_limit: page.max_limit,
+        warnings: page.warnings(),
+    }))
+}
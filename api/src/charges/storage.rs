@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use mini_stripe_types::charges::ChargeStatus;
+use uuid::Uuid;
+
+// One field per charges column, read with `SELECT *`/`RETURNING *` like PaymentIntent
+#[derive(Debug, Clone)]
+pub struct Charge {
+    pub id: Uuid,
+    pub payment_intent_id: Uuid,
+    pub amount: i64,
+    pub currency: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub amount_refunded: i64,
+}
+
+impl Charge {
+    // The column's CHECK keeps it to the known statuses
+    pub fn status(&self) -> ChargeStatus {
+        ChargeStatus::parse(&self.status)
+            .unwrap_or_else(|| panic!("charge {} has unknown status {}", self.id, self.status))
+    }
+}
+
+// In the transaction that moved the intent to succeeded, for what it received
+pub async fn insert_charge(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    payment_intent_id: Uuid,
+    amount: i64,
+    currency: &str,
+) -> Result<Charge, sqlx::Error> {
+    sqlx::query_as!(
+        Charge,
+        r#"
+        INSERT INTO charges (id, payment_intent_id, amount, currency, status)
+        VALUES ($1, $2, $3, $4, 'succeeded')
+        RETURNING *
+        "#,
+        Uuid::new_v4(),
+        payment_intent_id,
+        amount,
+        currency
+    )
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn fetch_charge(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<Charge>, sqlx::Error> {
+    sqlx::query_as!(
+        Charge,
+        r#"
+        SELECT *
+        FROM charges
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Row lock for a refund, so concurrent refunds of one charge see each other's amount_refunded.
+// Like lock_payment_intent, at most one charge is locked per transaction.
+pub async fn lock_charge(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<Charge>, sqlx::Error> {
+    sqlx::query_as!(
+        Charge,
+        r#"
+        SELECT *
+        FROM charges
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+pub async fn lock_charge_for_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    payment_intent_id: Uuid,
+) -> Result<Option<Charge>, sqlx::Error> {
+    sqlx::query_as!(
+        Charge,
+        r#"
+        SELECT *
+        FROM charges
+        WHERE payment_intent_id = $1
+        FOR UPDATE
+        "#,
+        payment_intent_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Under the row lock, after the caller checked the refund fits; charges_amount_refunded_check
+// refuses it anyway if it doesn't
+pub async fn add_amount_refunded(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    amount: i64,
+) -> Result<Charge, sqlx::Error> {
+    sqlx::query_as!(
+        Charge,
+        r#"
+        UPDATE charges
+        SET amount_refunded = amount_refunded + $2
+        WHERE id = $1
+        RETURNING *
+        "#,
+        id,
+        amount
+    )
+    .fetch_one(executor)
+    .await
+}
+
+// Newest first, after the `starting_after` charge when given, only the intent's when
+// `payment_intent_id` is given
+pub async fn list_charges(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    payment_intent_id: Option<Uuid>,
+    starting_after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<Charge>, sqlx::Error> {
+    sqlx::query_as!(
+        Charge,
+        r#"
+        SELECT *
+        FROM charges
+        WHERE ($1::uuid IS NULL OR payment_intent_id = $1)
+          AND ($2::uuid IS NULL
+            OR (created_at, id) < (SELECT created_at, id FROM charges WHERE id = $2))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $3
+        "#,
+        payment_intent_id,
+        starting_after,
+        limit
+    )
+    .fetch_all(executor)
+    .await
+}
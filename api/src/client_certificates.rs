@@ -0,0 +1,210 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::{DateTime, Duration, Utc};
+use mini_stripe_types::crypto::WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE;
+use mini_stripe_types::error::codes;
+use mini_stripe_types::events::CERTIFICATE_EXPIRY_WARNING_DAYS;
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::sign::CertifiedKey;
+use rustls::{Error as TlsError, InconsistentKeys};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::audit_log::insert_audit_entry;
+use crate::error::ApiError;
+use crate::state::AppState;
+use crate::webhook_endpoints::{WebhookEndpointListItem, fetch_webhook_endpoint};
+
+// PUT /v1/webhook_endpoints/{id}/client_certificate
+#[derive(Deserialize)]
+pub struct UploadClientCertificateRequest {
+    // PEM, leaf first; intermediates may follow
+    pub certificate: String,
+    // PEM (PKCS#8, PKCS#1 or SEC1)
+    pub private_key: String,
+}
+
+// What GET /v1/webhook_endpoints/{id} shows of an uploaded certificate; never the key
+#[derive(Debug, Serialize)]
+pub struct ClientCertificateInfo {
+    pub fingerprint: String,
+    pub subject: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    // Within CERTIFICATE_EXPIRY_WARNING_DAYS of expiring (or already expired)
+    pub expiring: bool,
+}
+
+impl ClientCertificateInfo {
+    // From the endpoint's columns; None when no certificate is uploaded
+    pub fn from_columns(
+        fingerprint: Option<String>,
+        subject: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Option<Self> {
+        let (fingerprint, expires_at) = (fingerprint?, expires_at?);
+        Some(Self {
+            fingerprint,
+            subject,
+            expires_at,
+            expiring: expires_at < Utc::now() + Duration::days(CERTIFICATE_EXPIRY_WARNING_DAYS),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ParsedClientCertificate {
+    // Certificate chain and key as one PEM bundle, the form the worker's TLS client loads
+    pub bundle: String,
+    pub fingerprint: String,
+    pub subject: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Checks an upload the way the worker will use it: PEM that parses, a key rustls can sign with,
+// and a key that belongs to the leaf certificate. Expired certificates are refused too.
+pub fn parse_client_certificate(
+    certificate: &str,
+    private_key: &str,
+) -> Result<ParsedClientCertificate, String> {
+    let chain = CertificateDer::pem_slice_iter(certificate.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("certificate is not valid PEM: {e}"))?;
+    let Some(leaf) = chain.first() else {
+        return Err("certificate has no CERTIFICATE block".to_string());
+    };
+    let (_, parsed) = x509_parser::parse_x509_certificate(leaf)
+        .map_err(|e| format!("certificate is not a valid X.509 certificate: {e}"))?;
+    let expires_at = DateTime::from_timestamp(parsed.validity().not_after.timestamp(), 0)
+        .ok_or("certificate has an out of range expiry")?;
+    if expires_at <= Utc::now() {
+        return Err(format!("certificate expired at {expires_at}"));
+    }
+
+    let key = PrivateKeyDer::from_pem_slice(private_key.as_bytes())
+        .map_err(|e| format!("private_key is not a valid PEM private key: {e}"))?;
+    let signing_key = any_supported_type(&key)
+        .map_err(|e| format!("private_key is not a supported key type: {e}"))?;
+    // A key type that can't report its public key can't be checked here; the handshake will
+    match CertifiedKey::new(chain.clone(), signing_key).keys_match() {
+        Ok(()) | Err(TlsError::InconsistentKeys(InconsistentKeys::Unknown)) => {}
+        Err(_) => return Err("private_key does not match the certificate".to_string()),
+    }
+
+    Ok(ParsedClientCertificate {
+        bundle: format!("{}\n{}\n", certificate.trim(), private_key.trim()),
+        fingerprint: hex::encode(Sha256::digest(leaf)),
+        subject: parsed.subject().to_string(),
+        expires_at,
+    })
+}
+
+// Replaces the endpoint's certificate, if it had one. Deliveries after this present the new one.
+pub async fn upload_client_certificate(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UploadClientCertificateRequest>,
+) -> Result<Json<WebhookEndpointListItem>, ApiError> {
+    let parsed = parse_client_certificate(&req.certificate, &req.private_key)
+        .map_err(|msg| ApiError::bad_request(codes::CLIENT_CERTIFICATE_INVALID, msg))?;
+
+    let mut tx = state.db.begin().await?;
+    let url = sqlx::query_scalar!(
+        "SELECT url FROM webhook_endpoints WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::not_found("webhook_endpoint not found"))?;
+    if !url.starts_with("https://") {
+        return Err(ApiError::bad_request(
+            codes::CLIENT_CERTIFICATE_INVALID,
+            "client certificates are only presented to https urls",
+        ));
+    }
+
+    let stored =
+        WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE.encrypt(&state.config.encryption_keys, &parsed.bundle);
+    sqlx::query!(
+        r#"
+        UPDATE webhook_endpoints
+        SET client_certificate = $2,
+            client_certificate_fingerprint = $3,
+            client_certificate_subject = $4,
+            client_certificate_expires_at = $5,
+            client_certificate_expiry_notified_at = NULL
+        WHERE id = $1
+        "#,
+        id,
+        stored,
+        parsed.fingerprint,
+        parsed.subject,
+        parsed.expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    insert_audit_entry(
+        &mut *tx,
+        "webhook_endpoint.client_certificate_uploaded",
+        id,
+        serde_json::json!({
+            "fingerprint": parsed.fingerprint,
+            "expires_at": parsed.expires_at,
+        }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    let endpoint = fetch_webhook_endpoint(&state.db, id).await?;
+    Ok(Json(endpoint.ok_or_else(|| {
+        ApiError::not_found("webhook_endpoint not found")
+    })?))
+}
+
+// Deliveries go back to plain TLS
+pub async fn delete_client_certificate(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<WebhookEndpointListItem>, ApiError> {
+    let mut tx = state.db.begin().await?;
+    let current = sqlx::query_scalar!(
+        "SELECT client_certificate_fingerprint FROM webhook_endpoints WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::not_found("webhook_endpoint not found"))?;
+    if let Some(fingerprint) = current {
+        sqlx::query!(
+            r#"
+            UPDATE webhook_endpoints
+            SET client_certificate = NULL,
+                client_certificate_fingerprint = NULL,
+                client_certificate_subject = NULL,
+                client_certificate_expires_at = NULL,
+                client_certificate_expiry_notified_at = NULL
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+        insert_audit_entry(
+            &mut *tx,
+            "webhook_endpoint.client_certificate_removed",
+            id,
+            serde_json::json!({ "fingerprint": fingerprint }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    let endpoint = fetch_webhook_endpoint(&state.db, id).await?;
+    Ok(Json(endpoint.ok_or_else(|| {
+        ApiError::not_found("webhook_endpoint not found")
+    })?))
+}
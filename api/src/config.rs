@@ -0,0 +1,531 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use mini_stripe_types::crypto::KeyRing;
+
+use crate::currency::Currency;
+use crate::latency::SimulatedLatency;
+use crate::latency_alerts::LatencyAlerting;
+use crate::permissions::ApiKeys;
+
+// Minimums when MINIMUM_AMOUNTS is unset, in minor units (Stripe's for these currencies)
+pub const DEFAULT_MINIMUM_AMOUNTS: [(&str, i64); 3] = [("eur", 50), ("gbp", 30), ("usd", 50)];
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    // Enables test-only behaviour (per-request overrides etc.)
+    pub sandbox_mode: bool,
+    // Simulated acquirer latency applied on confirm
+    pub simulated_latency: Option<SimulatedLatency>,
+    // Intents older than this are canceled instead of confirmed
+    pub max_confirmable_age: Option<Duration>,
+    // Allows POST /v1/webhook_endpoints/{id}/reveal_secret
+    pub webhook_secret_reveal_enabled: bool,
+    // Skips the extra summary lookups on GET /v1/payment_intents/{id}
+    pub disable_payment_intent_summaries: bool,
+    // Pool floor; also how many connections the startup warm-up primes
+    pub db_min_connections: u32,
+    // Prime connections/statements before /readyz reports ready
+    pub warmup_enabled: bool,
+    pub warmup_timeout: Duration,
+    // Used when a create omits `currency`
+    pub default_currency: Option<String>,
+    // When set, creates in any other currency are rejected
+    pub allowed_currencies: Option<Vec<String>>,
+    // Smallest amount a create or amount change may have, per currency in minor units; None is
+    // DEFAULT_MINIMUM_AMOUNTS. Currencies not listed have no minimum beyond 1.
+    pub minimum_amounts: Option<BTreeMap<String, i64>>,
+    // Largest amount in any currency, in minor units; None is 99999999
+    pub maximum_amount: Option<i64>,
+    // How long shutdown waits for in-flight payment mutations before giving up on them
+    pub shutdown_timeout: Duration,
+    // How long webhook delivery rows are kept; longer stats windows are flagged partial
+    pub webhook_delivery_retention: Option<Duration>,
+    // Store a redacted copy of every mutating request (see request_capture.rs)
+    pub request_capture_enabled: bool,
+    pub request_capture_ttl: Duration,
+    // Dot paths into JSON bodies (e.g. `card.number`) replaced before a capture is stored
+    pub request_capture_redact_paths: Vec<String>,
+    // Statements slower than this are logged with their route (see slow_queries.rs); None is off
+    pub slow_query_threshold: Option<Duration>,
+    // Most intents one POST /v1/payment_intents/bulk_cancel call works through; None is 1000
+    pub bulk_cancel_max_per_call: Option<usize>,
+    // Signs mutating responses with X-Response-Signature (see response_signing.rs); None is off
+    pub response_signing_secret: Option<String>,
+    // Mutations get 503 event_backlog while more outbox events than this are pending delivery
+    // (see backpressure.rs); None is off
+    pub event_backlog_high_water: Option<i64>,
+    // They are accepted again once the backlog is down to this; None is half the high-water mark
+    pub event_backlog_low_water: Option<i64>,
+    // How often the backlog is sampled; also the Retry-After on a 503
+    pub event_backlog_sample_interval: Duration,
+    // Per-route p95 alerts written to the outbox (see latency_alerts.rs); None is off
+    pub latency_alerting: Option<LatencyAlerting>,
+    // Metadata key (from NATURAL_IDEMPOTENCY_KEY=metadata.<key>) allowing one intent that isn't
+    // canceled per value; None is off
+    pub natural_idempotency_key: Option<String>,
+    // Webhook workers without a heartbeat for this long are flagged stale; None is 60s
+    pub worker_stale_after: Option<Duration>,
+    // Cache-Control max-age on successful GETs of stats routes (see caching.rs); None is 5s
+    pub cache_stats_max_age: Option<Duration>,
+    // Same for list routes; None is 0, i.e. revalidate every time
+    pub cache_list_max_age: Option<Duration>,
+    // Adds stale-while-revalidate to those; None leaves it out
+    pub cache_stale_while_revalidate: Option<Duration>,
+    // Page size of list endpoints when the request has no `limit` (see pagination.rs); None is 20
+    pub list_default_limit: Option<i64>,
+    // Larger limits are clamped to this; None is 100
+    pub list_max_limit: Option<i64>,
+    // Open GET /v1/payment_intents/{id}/wait requests per process; None is 100
+    pub max_payment_intent_waiters: Option<usize>,
+    // Cap on X-Request-Deadline-Ms (see deadline.rs); None is 30s
+    pub max_request_deadline: Option<Duration>,
+    // Keys for encrypted columns (ENCRYPTION_KEYS / ENCRYPTION_KEY_ID); empty stores plaintext
+    pub encryption_keys: KeyRing,
+    // Keys callers must send as `Authorization: Bearer` (API_KEYS, see permissions.rs); empty
+    // leaves every route open
+    pub api_keys: ApiKeys,
+    // Idempotency keys expire this long after they are reserved and are then deleted in the
+    // background (see idempotency_cleanup.rs); None keeps them forever
+    pub idempotency_key_ttl: Option<Duration>,
+    // Keys the cleanup deletes per transaction; None is 1000
+    pub idempotency_cleanup_batch_size: Option<i64>,
+    // How long it sleeps between batches; None is 100ms
+    pub idempotency_cleanup_pause: Option<Duration>,
+    // Fee taken from each charge in basis points (PROCESSING_FEE_BPS, 0 to 10000); None is 0
+    pub processing_fee_bps: Option<i64>,
+    // How long a charge's money stays pending in the balance; None makes it available at once
+    pub balance_available_after: Option<Duration>,
+    // How long after creation a payout is settled (see payout_settlement.rs); None is at once
+    pub payout_settle_after: Option<Duration>,
+}
+
+impl Config {
+    // The configured minimum for a lowercase currency code, if it has one
+    pub fn minimum_amount(&self, currency: &str) -> Option<i64> {
+        match &self.minimum_amounts {
+            Some(minimums) => minimums.get(currency).copied(),
+            None => DEFAULT_MINIMUM_AMOUNTS
+                .iter()
+                .find(|(code, _)| *code == currency)
+                .map(|(_, minimum)| *minimum),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let sandbox_mode = env_flag("SANDBOX_MODE");
+
+        let simulated_latency = std::env::var("SIMULATED_PROCESSING_LATENCY_MS")
+            .ok()
+            .map(|v| {
+                SimulatedLatency::parse(&v)
+                    .expect("SIMULATED_PROCESSING_LATENCY_MS must be `N` or `min..max`")
+            })
+            .filter(|l| !l.is_zero());
+
+        let max_confirmable_age = std::env::var("MAX_CONFIRMABLE_AGE").ok().map(|v| {
+            parse_duration(&v)
+                .expect("MAX_CONFIRMABLE_AGE must be a duration like `90d` or `3600s`")
+        });
+
+        let db_min_connections = std::env::var("DB_MIN_CONNECTIONS")
+            .ok()
+            .map(|v| v.parse().expect("DB_MIN_CONNECTIONS must be a number"))
+            .unwrap_or(0);
+
+        let warmup_timeout = std::env::var("WARMUP_TIMEOUT")
+            .ok()
+            .map(|v| parse_duration(&v).expect("WARMUP_TIMEOUT must be a duration like `10s`"))
+            .unwrap_or(Duration::from_secs(10));
+
+        let default_currency = std::env::var("DEFAULT_CURRENCY").ok().map(|v| {
+            parse_currency(&v).expect("DEFAULT_CURRENCY must be a supported ISO 4217 code")
+        });
+
+        let allowed_currencies = std::env::var("ALLOWED_CURRENCIES").ok().map(|v| {
+            v.split(',')
+                .map(|c| {
+                    parse_currency(c)
+                        .expect("ALLOWED_CURRENCIES must be comma-separated ISO 4217 codes")
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let minimum_amounts = std::env::var("MINIMUM_AMOUNTS").ok().map(|v| {
+            parse_minimum_amounts(&v)
+                .expect("MINIMUM_AMOUNTS must be `<currency>:<minor units>,...`, e.g. `gbp:30`")
+        });
+        let maximum_amount = std::env::var("MAXIMUM_AMOUNT").ok().map(|v| {
+            v.trim()
+                .parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .expect("MAXIMUM_AMOUNT must be a positive number of minor units")
+        });
+
+        let shutdown_timeout = std::env::var("SHUTDOWN_TIMEOUT")
+            .ok()
+            .map(|v| parse_duration(&v).expect("SHUTDOWN_TIMEOUT must be a duration like `30s`"))
+            .unwrap_or(Duration::from_secs(30));
+
+        let webhook_delivery_retention =
+            std::env::var("WEBHOOK_DELIVERY_RETENTION").ok().map(|v| {
+                parse_duration(&v)
+                    .expect("WEBHOOK_DELIVERY_RETENTION must be a duration like `14d`")
+            });
+
+        let request_capture_ttl = std::env::var("REQUEST_CAPTURE_TTL")
+            .ok()
+            .map(|v| parse_duration(&v).expect("REQUEST_CAPTURE_TTL must be a duration like `24h`"))
+            .unwrap_or(Duration::from_secs(24 * 3600));
+
+        let request_capture_redact_paths = std::env::var("REQUEST_CAPTURE_REDACT_PATHS")
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // 0 turns slow query logging off
+        let slow_query_threshold_ms: u64 = std::env::var("SLOW_QUERY_THRESHOLD_MS")
+            .ok()
+            .map(|v| v.parse().expect("SLOW_QUERY_THRESHOLD_MS must be a number"))
+            .unwrap_or(200);
+        let slow_query_threshold =
+            (slow_query_threshold_ms > 0).then(|| Duration::from_millis(slow_query_threshold_ms));
+
+        let bulk_cancel_max_per_call = std::env::var("BULK_CANCEL_MAX_PER_CALL").ok().map(|v| {
+            v.parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .expect("BULK_CANCEL_MAX_PER_CALL must be a positive number")
+        });
+
+        let response_signing_secret = std::env::var("RESPONSE_SIGNING_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty());
+
+        let event_backlog_high_water = std::env::var("EVENT_BACKLOG_HIGH_WATER").ok().map(|v| {
+            v.parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .expect("EVENT_BACKLOG_HIGH_WATER must be a positive number")
+        });
+
+        let event_backlog_low_water = std::env::var("EVENT_BACKLOG_LOW_WATER").ok().map(|v| {
+            v.parse()
+                .ok()
+                .filter(|n| *n >= 0)
+                .expect("EVENT_BACKLOG_LOW_WATER must be a number")
+        });
+
+        let event_backlog_sample_interval = std::env::var("EVENT_BACKLOG_SAMPLE_INTERVAL")
+            .ok()
+            .map(|v| {
+                parse_duration(&v)
+                    .expect("EVENT_BACKLOG_SAMPLE_INTERVAL must be a duration like `5s`")
+            })
+            .filter(|d| !d.is_zero())
+            .unwrap_or(Duration::from_secs(5));
+
+        let latency_alerting = std::env::var("LATENCY_ALERT_P95_MS").ok().map(|v| {
+            let p95_threshold = v
+                .parse()
+                .ok()
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis)
+                .expect("LATENCY_ALERT_P95_MS must be a positive number");
+            let window = std::env::var("LATENCY_ALERT_WINDOW")
+                .ok()
+                .map(|v| {
+                    parse_duration(&v).expect("LATENCY_ALERT_WINDOW must be a duration like `60s`")
+                })
+                .filter(|d| !d.is_zero())
+                .unwrap_or(Duration::from_secs(60));
+            let consecutive_windows = std::env::var("LATENCY_ALERT_CONSECUTIVE_WINDOWS")
+                .ok()
+                .map(|v| {
+                    v.parse()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .expect("LATENCY_ALERT_CONSECUTIVE_WINDOWS must be a positive number")
+                })
+                .unwrap_or(3);
+            let cooldown = std::env::var("LATENCY_ALERT_COOLDOWN")
+                .ok()
+                .map(|v| {
+                    parse_duration(&v)
+                        .expect("LATENCY_ALERT_COOLDOWN must be a duration like `10m`")
+                })
+                .unwrap_or(Duration::from_secs(600));
+            LatencyAlerting {
+                window,
+                p95_threshold,
+                consecutive_windows,
+                cooldown,
+            }
+        });
+
+        let natural_idempotency_key = std::env::var("NATURAL_IDEMPOTENCY_KEY").ok().map(|v| {
+            v.trim()
+                .strip_prefix("metadata.")
+                .filter(|key| !key.is_empty())
+                .map(str::to_string)
+                .expect("NATURAL_IDEMPOTENCY_KEY must be a metadata path like `metadata.order_id`")
+        });
+
+        let worker_stale_after = std::env::var("WORKER_STALE_AFTER")
+            .ok()
+            .map(|v| parse_duration(&v).expect("WORKER_STALE_AFTER must be a duration like `60s`"));
+
+        let cache_stats_max_age = std::env::var("CACHE_MAX_AGE_STATS")
+            .ok()
+            .map(|v| parse_duration(&v).expect("CACHE_MAX_AGE_STATS must be a duration like `5s`"));
+        let cache_list_max_age = std::env::var("CACHE_MAX_AGE_LISTS")
+            .ok()
+            .map(|v| parse_duration(&v).expect("CACHE_MAX_AGE_LISTS must be a duration like `0s`"));
+        let cache_stale_while_revalidate =
+            std::env::var("CACHE_STALE_WHILE_REVALIDATE").ok().map(|v| {
+                parse_duration(&v)
+                    .expect("CACHE_STALE_WHILE_REVALIDATE must be a duration like `30s`")
+            });
+
+        let list_default_limit = std::env::var("LIST_DEFAULT_LIMIT").ok().map(|v| {
+            v.trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .expect("LIST_DEFAULT_LIMIT must be a positive integer")
+        });
+        let list_max_limit = std::env::var("LIST_MAX_LIMIT").ok().map(|v| {
+            v.trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .expect("LIST_MAX_LIMIT must be a positive integer")
+        });
+        let max_payment_intent_waiters =
+            std::env::var("MAX_PAYMENT_INTENT_WAITERS").ok().map(|v| {
+                v.trim()
+                    .parse::<usize>()
+                    .expect("MAX_PAYMENT_INTENT_WAITERS must be a non-negative integer")
+            });
+        let max_request_deadline = std::env::var("MAX_REQUEST_DEADLINE").ok().map(|v| {
+            parse_duration(&v).expect("MAX_REQUEST_DEADLINE must be a duration like `30s`")
+        });
+        let idempotency_key_ttl = std::env::var("IDEMPOTENCY_KEY_TTL")
+            .ok()
+            .map(|v| parse_duration(&v).expect("IDEMPOTENCY_KEY_TTL must be a duration like `24h`"))
+            .filter(|d| !d.is_zero());
+        let idempotency_cleanup_batch_size = std::env::var("IDEMPOTENCY_CLEANUP_BATCH_SIZE")
+            .ok()
+            .map(|v| {
+                v.trim()
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .expect("IDEMPOTENCY_CLEANUP_BATCH_SIZE must be a positive integer")
+            });
+        let idempotency_cleanup_pause =
+            std::env::var("IDEMPOTENCY_CLEANUP_PAUSE_MS").ok().map(|v| {
+                v.trim()
+                    .parse()
+                    .map(Duration::from_millis)
+                    .expect("IDEMPOTENCY_CLEANUP_PAUSE_MS must be a number")
+            });
+        let processing_fee_bps = std::env::var("PROCESSING_FEE_BPS").ok().map(|v| {
+            v.trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|bps| (0..=10_000).contains(bps))
+                .expect("PROCESSING_FEE_BPS must be a number from 0 to 10000")
+        });
+        let balance_available_after = std::env::var("BALANCE_AVAILABLE_AFTER").ok().map(|v| {
+            parse_duration(&v).expect("BALANCE_AVAILABLE_AFTER must be a duration like `2d`")
+        });
+        let payout_settle_after = std::env::var("PAYOUT_SETTLE_AFTER")
+            .ok()
+            .map(|v| parse_duration(&v).expect("PAYOUT_SETTLE_AFTER must be a duration like `1d`"));
+        if let (Some(default), Some(max)) = (list_default_limit, list_max_limit) {
+            assert!(
+                default <= max,
+                "LIST_DEFAULT_LIMIT must not be above LIST_MAX_LIMIT"
+            );
+        }
+
+        if let (Some(high), Some(low)) = (event_backlog_high_water, event_backlog_low_water) {
+            assert!(
+                low < high,
+                "EVENT_BACKLOG_LOW_WATER must be below EVENT_BACKLOG_HIGH_WATER"
+            );
+        }
+
+        if let (Some(default), Some(allowed)) = (&default_currency, &allowed_currencies) {
+            assert!(
+                allowed.contains(default),
+                "DEFAULT_CURRENCY must be one of ALLOWED_CURRENCIES"
+            );
+        }
+
+        if let Some(max) = maximum_amount {
+            let minimums: Vec<i64> = match &minimum_amounts {
+                Some(minimums) => minimums.values().copied().collect(),
+                None => DEFAULT_MINIMUM_AMOUNTS
+                    .iter()
+                    .map(|(_, min)| *min)
+                    .collect(),
+            };
+            assert!(
+                minimums.iter().all(|min| *min <= max),
+                "MINIMUM_AMOUNTS (gbp:30,usd:50,eur:50 when unset) must not be above MAXIMUM_AMOUNT"
+            );
+        }
+
+        Self {
+            sandbox_mode,
+            simulated_latency,
+            max_confirmable_age,
+            webhook_secret_reveal_enabled: env_flag("WEBHOOK_SECRET_REVEAL_ENABLED"),
+            disable_payment_intent_summaries: env_flag("DISABLE_PAYMENT_INTENT_SUMMARIES"),
+            db_min_connections,
+            warmup_enabled: env_flag("WARMUP_ENABLED"),
+            warmup_timeout,
+            default_currency,
+            allowed_currencies,
+            minimum_amounts,
+            maximum_amount,
+            shutdown_timeout,
+            webhook_delivery_retention,
+            request_capture_enabled: env_flag("REQUEST_CAPTURE_ENABLED"),
+            request_capture_ttl,
+            request_capture_redact_paths,
+            slow_query_threshold,
+            bulk_cancel_max_per_call,
+            response_signing_secret,
+            event_backlog_high_water,
+            event_backlog_low_water,
+            event_backlog_sample_interval,
+            latency_alerting,
+            natural_idempotency_key,
+            worker_stale_after,
+            cache_stats_max_age,
+            cache_list_max_age,
+            cache_stale_while_revalidate,
+            list_default_limit,
+            list_max_limit,
+            max_payment_intent_waiters,
+            max_request_deadline,
+            encryption_keys: KeyRing::from_env().expect(
+                "ENCRYPTION_KEYS must be `id:<64 hex chars>,...` and ENCRYPTION_KEY_ID one of the ids",
+            ),
+            api_keys: ApiKeys::from_env()
+                .expect("API_KEYS must be `<scope>:<key>,...` with scope full, read_only or admin"),
+            idempotency_key_ttl,
+            idempotency_cleanup_batch_size,
+            idempotency_cleanup_pause,
+            processing_fee_bps,
+            balance_available_after,
+            payout_settle_after,
+        }
+    }
+}
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|v| matches!(v.trim(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+// Currency settings are stored lowercase, like the fingerprint compares them
+fn parse_currency(s: &str) -> Result<String, &'static str> {
+    Currency::parse(s).map(|c| c.code.to_string())
+}
+
+// `gbp:30,usd:50`; each currency at most once
+fn parse_minimum_amounts(s: &str) -> Result<BTreeMap<String, i64>, &'static str> {
+    let mut minimums = BTreeMap::new();
+    for entry in s.split(',').filter(|e| !e.trim().is_empty()) {
+        let (currency, amount) = entry
+            .split_once(':')
+            .ok_or("expected <currency>:<amount>")?;
+        let amount: i64 = amount.trim().parse().map_err(|_| "invalid amount")?;
+        if amount <= 0 {
+            return Err("minimum must be > 0");
+        }
+        if minimums.insert(parse_currency(currency)?, amount).is_some() {
+            return Err("currency listed twice");
+        }
+    }
+    Ok(minimums)
+}
+
+// Accepts a number with an optional s/m/h/d suffix (plain numbers are seconds)
+pub fn parse_duration(s: &str) -> Result<Duration, &'static str> {
+    let s = s.trim();
+    let (num, unit_secs) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        _ => (s, 1),
+    };
+
+    let n: u64 = num.parse().map_err(|_| "invalid duration")?;
+    Ok(Duration::from_secs(n * unit_secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(
+            parse_duration("90d").unwrap(),
+            Duration::from_secs(90 * 86400)
+        );
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("1w").is_err());
+    }
+
+    #[test]
+    fn parse_currency_normalizes_and_rejects_junk() {
+        assert_eq!(parse_currency(" GBP ").unwrap(), "gbp");
+        assert!(parse_currency("pounds").is_err());
+        assert!(parse_currency("g1p").is_err());
+        assert!(parse_currency("zzz").is_err());
+    }
+
+    #[test]
+    fn minimum_amounts_default_until_configured() {
+        let mut config = Config::default();
+        assert_eq!(config.minimum_amount("gbp"), Some(30));
+        assert_eq!(config.minimum_amount("usd"), Some(50));
+        assert_eq!(config.minimum_amount("jpy"), None);
+
+        config.minimum_amounts = Some(parse_minimum_amounts("usd:100").unwrap());
+        assert_eq!(config.minimum_amount("usd"), Some(100));
+        assert_eq!(config.minimum_amount("gbp"), None);
+    }
+
+    #[test]
+    fn parse_minimum_amounts_per_currency() {
+        let minimums = parse_minimum_amounts("GBP:30, usd:50").unwrap();
+        assert_eq!(minimums["gbp"], 30);
+        assert_eq!(minimums["usd"], 50);
+        assert!(parse_minimum_amounts("").unwrap().is_empty());
+
+        assert!(parse_minimum_amounts("gbp").is_err());
+        assert!(parse_minimum_amounts("gbp:0").is_err());
+        assert!(parse_minimum_amounts("gbp:30p").is_err());
+        assert!(parse_minimum_amounts("zzz:30").is_err());
+        assert!(parse_minimum_amounts("gbp:30,GBP:50").is_err());
+    }
+}
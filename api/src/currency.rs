@@ -0,0 +1,135 @@
+// The ISO 4217 currencies the API accepts. Amounts are always in minor units, so a currency's
+// minor unit says what `amount: 100` means: £1.00 in gbp (2 decimals), ¥100 in jpy (none) and
+// 0.100 KWD in kwd (3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Currency {
+    // Lowercase, as stored and returned
+    pub code: &'static str,
+    // Decimal places between the major and the minor unit
+    pub minor_unit: u32,
+}
+
+const fn currency(code: &'static str, minor_unit: u32) -> Currency {
+    Currency { code, minor_unit }
+}
+
+// Sorted by code. Add to it rather than accepting any three letters: an unknown code has no
+// minor unit, so nothing could check its amounts.
+const SUPPORTED: &[Currency] = &[
+    currency("aed", 2),
+    currency("aud", 2),
+    currency("bgn", 2),
+    currency("bhd", 3),
+    currency("brl", 2),
+    currency("cad", 2),
+    currency("chf", 2),
+    currency("clp", 0),
+    currency("cny", 2),
+    currency("czk", 2),
+    currency("dkk", 2),
+    currency("eur", 2),
+    currency("gbp", 2),
+    currency("hkd", 2),
+    currency("huf", 2),
+    currency("idr", 2),
+    currency("ils", 2),
+    currency("inr", 2),
+    currency("isk", 0),
+    currency("jod", 3),
+    currency("jpy", 0),
+    currency("krw", 0),
+    currency("kwd", 3),
+    currency("mxn", 2),
+    currency("myr", 2),
+    currency("nok", 2),
+    currency("nzd", 2),
+    currency("omr", 3),
+    currency("php", 2),
+    currency("pln", 2),
+    currency("ron", 2),
+    currency("sek", 2),
+    currency("sgd", 2),
+    currency("thb", 2),
+    currency("tnd", 3),
+    currency("try", 2),
+    currency("twd", 2),
+    currency("usd", 2),
+    currency("vnd", 0),
+    currency("zar", 2),
+];
+
+impl Currency {
+    // Trims and lowercases the code. Anything that isn't three ASCII letters gets the same error
+    // as before this list existed; a well-formed code that isn't on it gets its own.
+    pub fn parse(s: &str) -> Result<Self, &'static str> {
+        let s = s.trim();
+        if s.len() != 3 || !s.bytes().all(|b| b.is_ascii_alphabetic()) {
+            return Err("currency must be a 3-letter ISO code");
+        }
+        let code = s.to_ascii_lowercase();
+        SUPPORTED
+            .binary_search_by(|c| c.code.cmp(code.as_str()))
+            .map(|i| SUPPORTED[i])
+            .map_err(|_| "currency must be a supported ISO 4217 code")
+    }
+
+    // How many minor units make one major unit: 100 for gbp, 1 for jpy
+    pub fn minor_units_per_major(self) -> i64 {
+        10_i64.pow(self.minor_unit)
+    }
+
+    // A minor-unit amount written in major units for messages: 30 gbp is "0.30", 500 jpy "500"
+    pub fn format_major(self, amount: i64) -> String {
+        let sign = if amount < 0 { "-" } else { "" };
+        let (amount, per_major) = (amount.unsigned_abs(), self.minor_units_per_major() as u64);
+        let (major, minor) = (amount / per_major, amount % per_major);
+        if self.minor_unit == 0 {
+            return format!("{sign}{major}");
+        }
+        format!(
+            "{sign}{major}.{minor:0width$}",
+            width = self.minor_unit as usize
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_list_is_sorted_lowercase_and_unique() {
+        for pair in SUPPORTED.windows(2) {
+            assert!(pair[0].code < pair[1].code, "{pair:?}");
+        }
+        for c in SUPPORTED {
+            assert!(c.code.bytes().all(|b| b.is_ascii_lowercase()), "{c:?}");
+            assert!(c.minor_unit <= 3, "{c:?}");
+        }
+    }
+
+    #[test]
+    fn parse_normalizes_and_knows_minor_units() {
+        assert_eq!(Currency::parse(" GBP ").unwrap().code, "gbp");
+        assert_eq!(Currency::parse("jpy").unwrap().minor_unit, 0);
+        assert_eq!(
+            Currency::parse("kwd").unwrap().minor_units_per_major(),
+            1000
+        );
+        assert_eq!(Currency::parse("usd").unwrap().minor_units_per_major(), 100);
+
+        assert_eq!(Currency::parse("gbp").unwrap().format_major(30), "0.30");
+        assert_eq!(Currency::parse("jpy").unwrap().format_major(500), "500");
+        assert_eq!(Currency::parse("kwd").unwrap().format_major(1005), "1.005");
+        assert_eq!(Currency::parse("usd").unwrap().format_major(-150), "-1.50");
+
+        assert_eq!(
+            Currency::parse("zzz").unwrap_err(),
+            "currency must be a supported ISO 4217 code"
+        );
+        assert_eq!(
+            Currency::parse("GBPP").unwrap_err(),
+            "currency must be a 3-letter ISO code"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+pub mod storage;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::pagination::PageLimit;
+use crate::services::DomainError;
+use crate::services::customers::{CreateCustomerParams, CustomerService, UpdateCustomerParams};
+use crate::state::AppState;
+use storage::{Customer, customer_exists, fetch_customer, list_customers as list_customer_rows};
+
+pub use mini_stripe_types::customers::{
+    CreateCustomerRequest, CustomerList, CustomerResponse, DeletedCustomerResponse,
+    UpdateCustomerRequest,
+};
+
+#[derive(Deserialize)]
+pub struct ListCustomersQuery {
+    // Exact match, as stored
+    email: Option<String>,
+    // The last id of the previous page
+    starting_after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+impl From<Customer> for CustomerResponse {
+    fn from(customer: Customer) -> Self {
+        Self {
+            id: customer.id,
+            email: customer.email,
+            name: customer.name,
+            description: customer.description,
+            metadata: serde_json::from_value(customer.metadata).unwrap_or_default(),
+            created_at: customer.created_at,
+        }
+    }
+}
+
+pub async fn create_customer(
+    State(state): State<AppState>,
+    Json(req): Json<CreateCustomerRequest>,
+) -> Result<(StatusCode, Json<CustomerResponse>), ApiError> {
+    let customer = CustomerService::new(&state.db, &state.in_flight)
+        .create(CreateCustomerParams {
+            email: req.email,
+            name: req.name,
+            description: req.description,
+            metadata: req.metadata,
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(customer)))
+}
+
+pub async fn get_customer(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<CustomerResponse>, ApiError> {
+    let customer = fetch_customer(&state.db, id)
+        .await?
+        .ok_or(DomainError::NotFound("customer"))?;
+    Ok(Json(CustomerResponse::from(customer)))
+}
+
+pub async fn update_customer(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateCustomerRequest>,
+) -> Result<Json<CustomerResponse>, ApiError> {
+    let customer = CustomerService::new(&state.db, &state.in_flight)
+        .update(
+            id,
+            UpdateCustomerParams {
+                email: req.email,
+                name: req.name,
+                description: req.description,
+                metadata: req.metadata,
+            },
+        )
+        .await?;
+    Ok(Json(customer))
+}
+
+pub async fn delete_customer(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DeletedCustomerResponse>, ApiError> {
+    CustomerService::new(&state.db, &state.in_flight)
+        .delete(id)
+        .await?;
+    Ok(Json(DeletedCustomerResponse { id, deleted: true }))
+}
+
+// From the read replica like the other lists
+pub async fn list_customers(
+    State(state): State<AppState>,
+    Query(query): Query<ListCustomersQuery>,
+) -> Result<Json<CustomerList>, ApiError> {
+    let page = PageLimit::resolve(&state.config, query.limit)?;
+    if let Some(after) = query.starting_after
+        && !customer_exists(&state.read_db, after).await?
+    {
+        return Err(DomainError::InvalidParameter(format!(
+            "starting_after: no such customer: {after}"
+        ))
+        .into());
+    }
+    let mut customers = list_customer_rows(
+        &state.read_db,
+        query.email.as_deref(),
+        query.starting_after,
+        page.limit + 1,
+    )
+    .await?;
+    let has_more = customers.len() as i64 > page.limit;
+    customers.truncate(page.limit as usize);
+
+    Ok(Json(CustomerList {
+        object: "list".to_string(),
+        data: customers.into_iter().map(CustomerResponse::from).collect(),
+        has_more,
+        limit: page.limit,
+        max_limit: page.max_limit,
+        warnings: page.warnings(),
+    }))
+}
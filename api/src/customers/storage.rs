@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+// One field per customers column, read with `SELECT *`/`RETURNING *` like PaymentIntent
+#[derive(Debug, Clone)]
+pub struct Customer {
+    pub id: Uuid,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub metadata: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+pub struct NewCustomer {
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub metadata: serde_json::Value,
+}
+
+// The whole new state of the editable fields; the service merges the request into the old one
+pub struct CustomerChanges {
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub metadata: serde_json::Value,
+}
+
+pub async fn insert_customer(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    new: NewCustomer,
+) -> Result<Customer, sqlx::Error> {
+    sqlx::query_as!(
+        Customer,
+        r#"
+        INSERT INTO customers (id, email, name, description, metadata)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#,
+        Uuid::new_v4(),
+        new.email,
+        new.name,
+        new.description,
+        new.metadata
+    )
+    .fetch_one(executor)
+    .await
+}
+
+// A deleted customer reads as missing
+pub async fn fetch_customer(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<Customer>, sqlx::Error> {
+    sqlx::query_as!(
+        Customer,
+        r#"
+        SELECT *
+        FROM customers
+        WHERE id = $1 AND deleted_at IS NULL
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Row lock for an update, so two concurrent metadata merges don't lose each other's keys
+pub async fn lock_customer(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<Customer>, sqlx::Error> {
+    sqlx::query_as!(
+        Customer,
+        r#"
+        SELECT *
+        FROM customers
+        WHERE id = $1 AND deleted_at IS NULL
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+pub async fn update_customer(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    changes: CustomerChanges,
+) -> Result<Customer, sqlx::Error> {
+    sqlx::query_as!(
+        Customer,
+        r#"
+        UPDATE customers
+        SET email = $2, name = $3, description = $4, metadata = $5
+        WHERE id = $1
+        RETURNING *
+        "#,
+        id,
+        changes.email,
+        changes.name,
+        changes.description,
+        changes.metadata
+    )
+    .fetch_one(executor)
+    .await
+}
+
+// Only sets deleted_at, as intents keep referencing the row. False when there was nothing to
+// delete (missing or already deleted).
+pub async fn delete_customer(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE customers
+        SET deleted_at = now()
+        WHERE id = $1 AND deleted_at IS NULL
+        "#,
+        id
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Deleted or not: a list cursor may name a customer deleted after its page was read
+pub async fn customer_exists(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM customers WHERE id = $1) AS "exists!""#,
+        id
+    )
+    .fetch_one(executor)
+    .await
+}
+
+// Newest first, after the `starting_after` customer when given, leaving out deleted customers
+pub async fn list_customers(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    email: Option<&str>,
+    starting_after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<Customer>, sqlx::Error> {
+    sqlx::query_as!(
+        Customer,
+        r#"
+        SELECT *
+        FROM customers
+        WHERE deleted_at IS NULL
+          AND ($1::text IS NULL OR email = $1)
+          AND ($2::uuid IS NULL
+            OR (created_at, id) < (SELECT created_at, id FROM customers WHERE id = $2))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $3
+        "#,
+        email,
+        starting_after,
+        limit
+    )
+    .fetch_all(executor)
+    .await
+}
@@ -0,0 +1,121 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::error::ApiError;
+use crate::metrics::{Metrics, TX_RETRIES, TX_RETRIES_EXHAUSTED};
+
+// SQLSTATEs that mean "run the whole transaction again": serialization_failure, deadlock_detected
+const RETRYABLE_SQLSTATES: &[&str] = &["40001", "40P01"];
+
+const QUERY_CANCELED_SQLSTATE: &str = "57014";
+
+// Backoff before retry n is a random point in [0, min(BASE * 2^(n-1), MAX)]
+const BACKOFF_BASE: Duration = Duration::from_millis(10);
+const BACKOFF_MAX: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Isolation {
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl Isolation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ReadCommitted => "read_committed",
+            Self::RepeatableRead => "repeatable_read",
+            Self::Serializable => "serializable",
+        }
+    }
+
+    fn set_statement(self) -> &'static str {
+        match self {
+            Self::ReadCommitted => "SET TRANSACTION ISOLATION LEVEL READ COMMITTED",
+            Self::RepeatableRead => "SET TRANSACTION ISOLATION LEVEL REPEATABLE READ",
+            Self::Serializable => "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE",
+        }
+    }
+}
+
+pub type TxFuture<'c, T> = Pin<Box<dyn Future<Output = Result<T, sqlx::Error>> + Send + 'c>>;
+
+pub fn is_retryable(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|db_err| db_err.code())
+        .is_some_and(|code| RETRYABLE_SQLSTATES.contains(&code.as_ref()))
+}
+
+// query_canceled, which is also what statement_timeout raises
+pub fn is_statement_timeout(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|db_err| db_err.code())
+        .is_some_and(|code| code == QUERY_CANCELED_SQLSTATE)
+}
+
+fn backoff(retry: u32) -> Duration {
+    let cap = BACKOFF_BASE
+        .saturating_mul(1 << retry.saturating_sub(1).min(16))
+        .min(BACKOFF_MAX);
+    Duration::from_millis(rand::random_range(0..=cap.as_millis() as u64))
+}
+
+// Runs `f` in a transaction at `isolation` and commits it. A serialization failure or deadlock,
+// from `f` or from the commit, rolls back and runs `f` again in a fresh transaction, up to
+// `max_retries` times; after that the caller gets a 503 transaction_conflict with Retry-After.
+// `f` runs once per attempt, so it must not have effects outside the transaction.
+pub async fn run_tx_with_retry<T, F>(
+    pool: &PgPool,
+    metrics: &Metrics,
+    isolation: Isolation,
+    max_retries: u32,
+    mut f: F,
+) -> Result<T, ApiError>
+where
+    F: for<'c> FnMut(&'c mut Transaction<'static, Postgres>) -> TxFuture<'c, T>,
+{
+    let labels = [("isolation", isolation.as_str())];
+    let mut retries = 0;
+    loop {
+        let attempt = async {
+            let mut tx = pool.begin().await?;
+            sqlx::query(isolation.set_statement())
+                .execute(&mut *tx)
+                .await?;
+            let value = f(&mut tx).await?;
+            tx.commit().await?;
+            Ok(value)
+        }
+        .await;
+
+        match attempt {
+            Err(e) if is_retryable(&e) && retries < max_retries => {
+                retries += 1;
+                metrics.inc(TX_RETRIES, &labels);
+                tokio::time::sleep(backoff(retries)).await;
+            }
+            Err(e) if is_retryable(&e) => {
+                metrics.inc(TX_RETRIES_EXHAUSTED, &labels);
+                return Err(e.into());
+            }
+            result => return result.map_err(ApiError::from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_to_the_cap() {
+        for _ in 0..100 {
+            assert!(backoff(1) <= BACKOFF_BASE);
+            assert!(backoff(3) <= BACKOFF_BASE * 4);
+            assert!(backoff(40) <= BACKOFF_MAX);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::future::Future;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use mini_stripe_types::error::codes;
+use sqlx::{PgConnection, Postgres, Transaction};
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::headers::require_ascii_header;
+use crate::services::DomainError;
+
+pub const HEADER: &str = "X-Request-Deadline-Ms";
+
+// Default for config.max_request_deadline
+const DEFAULT_MAX_DEADLINE: Duration = Duration::from_secs(30);
+
+// When the client stops waiting for a mutation. Work before the commit gives up at this point;
+// once COMMIT is sent the deadline is ignored, so a 504 always means nothing was written.
+// The default has no deadline.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self(Some(Instant::now() + timeout))
+    }
+
+    pub fn check(self) -> Result<(), DomainError> {
+        match self.0 {
+            Some(at) if Instant::now() >= at => Err(DomainError::DeadlineExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    // Runs `work` (simulated latency, waiting for a connection) unless the deadline passes first
+    pub async fn run<F: Future>(self, work: F) -> Result<F::Output, DomainError> {
+        let Some(at) = self.0 else {
+            return Ok(work.await);
+        };
+        tokio::time::timeout_at(at, work)
+            .await
+            .map_err(|_| DomainError::DeadlineExceeded)
+    }
+
+    // SET LOCAL statement_timeout to what is left, so Postgres cancels a statement still running
+    // at the deadline. The cancel surfaces as query_canceled (see db::is_statement_timeout).
+    pub async fn limit_statements(self, conn: &mut PgConnection) -> Result<(), DomainError> {
+        let Some(at) = self.0 else {
+            return Ok(());
+        };
+        self.check()?;
+        // statement_timeout = 0 would mean no limit
+        let remaining_ms = at.duration_since(Instant::now()).as_millis().max(1);
+        sqlx::query("SELECT set_config('statement_timeout', $1, true)")
+            .bind(remaining_ms.to_string())
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    // The last chance to give up, then a commit the deadline no longer applies to: a commit given
+    // up on halfway would leave the client unable to tell what happened. Postgres doesn't apply
+    // statement_timeout to COMMIT (deferred triggers included), so only this side could race it.
+    pub async fn commit(self, tx: Transaction<'_, Postgres>) -> Result<(), DomainError> {
+        self.check()?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+// X-Request-Deadline-Ms is relative to when the request arrived, and capped by config
+pub fn resolve(config: &Config, headers: &HeaderMap) -> Result<Deadline, ApiError> {
+    let Some(value) = require_ascii_header(headers, HEADER, codes::PARAMETER_INVALID)? else {
+        return Ok(Deadline::default());
+    };
+
+    let ms = value
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|ms| *ms > 0)
+        .ok_or_else(|| {
+            ApiError::bad_request(
+                codes::PARAMETER_INVALID,
+                format!("{HEADER} must be a positive number of milliseconds"),
+            )
+        })?;
+
+    let max = config.max_request_deadline.unwrap_or(DEFAULT_MAX_DEADLINE);
+    Ok(Deadline::after(Duration::from_millis(ms).min(max)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER, value.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn header_is_parsed_and_capped() {
+        let config = Config {
+            max_request_deadline: Some(Duration::from_secs(5)),
+            ..Config::default()
+        };
+        let now = Instant::now();
+
+        assert_eq!(
+            resolve(&config, &HeaderMap::new()).unwrap(),
+            Deadline::default()
+        );
+        assert_eq!(
+            resolve(&config, &headers("2000")).unwrap(),
+            Deadline(Some(now + Duration::from_secs(2)))
+        );
+        assert_eq!(
+            resolve(&config, &headers("600000")).unwrap(),
+            Deadline(Some(now + Duration::from_secs(5)))
+        );
+        for bad in ["0", "-1", "2s", ""] {
+            let err = resolve(&config, &headers(bad)).unwrap_err();
+            assert_eq!(err.code, codes::PARAMETER_INVALID, "{bad:?}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn work_past_the_deadline_is_abandoned() {
+        let deadline = Deadline::after(Duration::from_millis(200));
+        let started = Instant::now();
+
+        let result = deadline
+            .run(tokio::time::sleep(Duration::from_secs(1)))
+            .await;
+        assert!(matches!(result, Err(DomainError::DeadlineExceeded)));
+        assert_eq!(started.elapsed(), Duration::from_millis(200));
+        assert!(matches!(
+            deadline.check(),
+            Err(DomainError::DeadlineExceeded)
+        ));
+
+        // No deadline never gives up
+        Deadline::default()
+            .run(tokio::time::sleep(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        Deadline::default().check().unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+use axum::{
+    Json,
+    extract::Request,
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use mini_stripe_types::error::{ErrorDetail, ErrorResponse, codes};
+
+use crate::events_outbox::EventError;
+use crate::services::DomainError;
+
+// Shared error type: every error response is `{"error": {"code", "message"}}`, except 4xx
+// bodies for legacy plain-text clients (see `negotiate_errors`)
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    // Sent as Retry-After (seconds) when set
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, codes::RESOURCE_MISSING, message)
+    }
+
+    pub fn conflict(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, code, message)
+    }
+
+    pub fn deadline_exceeded(message: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            codes::DEADLINE_EXCEEDED,
+            message,
+        )
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            codes::INTERNAL_ERROR,
+            message,
+        )
+    }
+}
+
+tokio::task_local! {
+    // Set for the request being handled by `negotiate_errors`
+    static PLAIN_TEXT_ERRORS: bool;
+}
+
+// Legacy clients asked for plain-text errors with `Accept: text/plain`. One that also accepts
+// JSON gets the envelope.
+fn wants_plain_text(req: &Request) -> bool {
+    let accept = req
+        .headers()
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|media| {
+            media
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_ascii_lowercase()
+        })
+        .collect::<Vec<_>>();
+    accept.iter().any(|m| m == "text/plain") && !accept.iter().any(|m| m == "application/json")
+}
+
+// Middleware. Lets `ApiError::into_response` see how the caller wants 4xx bodies rendered.
+pub async fn negotiate_errors(req: Request, next: Next) -> Response {
+    let plain_text = wants_plain_text(&req);
+    PLAIN_TEXT_ERRORS.scope(plain_text, next.run(req)).await
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        // The legacy body is only the message; the status is the same either way
+        let plain_text =
+            self.status.is_client_error() && PLAIN_TEXT_ERRORS.try_with(|p| *p).unwrap_or(false);
+        let mut response = if plain_text {
+            (self.status, self.message).into_response()
+        } else {
+            let body = ErrorResponse {
+                error: ErrorDetail {
+                    code: self.code.to_string(),
+                    message: self.message,
+                },
+            };
+            (self.status, Json(body)).into_response()
+        };
+
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+// Constraint raised by the payment_intents trigger guarding amount/currency
+const MONEY_IMMUTABLE_CONSTRAINT: &str = "payment_intents_money_immutable";
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        let constraint = e.as_database_error().and_then(|db_err| db_err.constraint());
+
+        if constraint == Some(MONEY_IMMUTABLE_CONSTRAINT) {
+            return Self::conflict(
+                codes::PAYMENT_INTENT_IMMUTABLE,
+                "amount and currency cannot change once a payment_intent is processing or final",
+            );
+        }
+
+        // Raised outside db::run_tx_with_retry, or after it gave up: the client can retry
+        if crate::db::is_retryable(&e) {
+            return Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                codes::TRANSACTION_CONFLICT,
+                "the request conflicted with a concurrent one; retry it",
+            )
+            .with_retry_after(1);
+        }
+
+        // Only a request deadline sets statement_timeout, so a canceled statement means it passed
+        if crate::db::is_statement_timeout(&e) {
+            return Self::deadline_exceeded(
+                "the request deadline passed before the change was committed; nothing was written",
+            );
+        }
+
+        Self::internal(format!("db error: {e}"))
+    }
+}
+
+impl From<EventError> for ApiError {
+    fn from(e: EventError) -> Self {
+        match e {
+            EventError::Db(e) => e.into(),
+            e @ EventError::PayloadTooLarge { .. } => Self::internal(e.to_string()),
+        }
+    }
+}
+
+impl From<DomainError> for ApiError {
+    fn from(e: DomainError) -> Self {
+        let message = e.to_string();
+        match e {
+            DomainError::InvalidParameter(msg) => Self::bad_request(codes::PARAMETER_INVALID, msg),
+            DomainError::CurrencyNotAllowed(_) => {
+                Self::bad_request(codes::CURRENCY_NOT_ALLOWED, message)
+            }
+            DomainError::AmountTooSmall { .. } => {
+                Self::bad_request(codes::AMOUNT_TOO_SMALL, message)
+            }
+            DomainError::AmountTooLarge { .. } => {
+                Self::bad_request(codes::AMOUNT_TOO_LARGE, message)
+            }
+            DomainError::NotFound(_) => Self::not_found(message),
+            DomainError::IdempotencyKeyReused => {
+                Self::conflict(codes::IDEMPOTENCY_KEY_REUSED, message)
+            }
+            DomainError::UnexpectedState(_)
+            | DomainError::NotCapturable(_)
+            | DomainError::NotUpdatable(_)
+            | DomainError::NotArchivable(_)
+            | DomainError::NotRefundable(_) => {
+                Self::conflict(codes::PAYMENT_INTENT_UNEXPECTED_STATE, message)
+            }
+            DomainError::ExpiredForConfirmation => {
+                Self::conflict(codes::PAYMENT_INTENT_EXPIRED_FOR_CONFIRMATION, message)
+            }
+            DomainError::ChargeAlreadyRefunded => {
+                Self::bad_request(codes::CHARGE_ALREADY_REFUNDED, message)
+            }
+            DomainError::RefundTooLarge { .. } => {
+                Self::bad_request(codes::AMOUNT_TOO_LARGE, message)
+            }
+            DomainError::InsufficientBalance { .. } => {
+                Self::bad_request(codes::BALANCE_INSUFFICIENT, message)
+            }
+            DomainError::CardDeclined => {
+                Self::new(StatusCode::PAYMENT_REQUIRED, codes::CARD_DECLINED, message)
+            }
+            DomainError::PaymentFailed(error) => {
+                Self::new(StatusCode::PAYMENT_REQUIRED, error.code, message)
+            }
+            DomainError::AcquirerTimeout => Self::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                codes::ACQUIRER_TIMEOUT,
+                message,
+            ),
+            DomainError::TooManyWaiters => Self::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                codes::TOO_MANY_WAITERS,
+                message,
+            )
+            .with_retry_after(1),
+            DomainError::DeadlineExceeded => Self::deadline_exceeded(message),
+            DomainError::FaultInjected(_) => Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                codes::SERVICE_UNAVAILABLE,
+                message,
+            )
+            .with_retry_after(1),
+            DomainError::Internal(msg) => Self::internal(msg),
+            DomainError::Db(e) => e.into(),
+            DomainError::Event(e) => e.into(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::internal(format!("json error: {e}"))
+    }
+}
+
+// The envelope's body on its own, e.g. for one entry of a report that carries on past failures
+impl From<ApiError> for ErrorDetail {
+    fn from(e: ApiError) -> Self {
+        Self {
+            code: e.code.to_string(),
+            message: e.message,
+        }
+    }
+}
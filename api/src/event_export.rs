@@ -0,0 +1,85 @@
+use axum::{Json, extract::State};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::state::AppState;
+
+// Partitions listed in the status, newest first
+const RECENT_PARTITIONS: i64 = 31;
+
+// Where the worker's event exporter (worker/src/export.rs) has got to. It is worked out from
+// the watermarks alone, so it reads the same whether or not an exporter is running.
+#[derive(Serialize)]
+pub struct EventExportStatus {
+    // Outbox events past the exporter's cursor
+    pub pending_events: i64,
+    pub oldest_pending_created_at: Option<DateTime<Utc>>,
+    // Age of the oldest pending event; 0 when the export is caught up
+    pub lag_seconds: f64,
+    pub partitions: Vec<EventExportPartition>,
+}
+
+// A row of event_export_watermarks: one output partition (UTC day of created_at)
+#[derive(Serialize)]
+pub struct EventExportPartition {
+    pub partition_date: NaiveDate,
+    pub last_event_id: Uuid,
+    pub exported_count: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+pub async fn fetch_status(state: &AppState) -> Result<EventExportStatus, sqlx::Error> {
+    let cursor = sqlx::query!(
+        r#"
+        SELECT last_txid, last_sequence
+        FROM event_export_watermarks
+        ORDER BY last_txid DESC, last_sequence DESC
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&state.db)
+    .await?;
+    let (txid, sequence) = cursor.map_or((0, 0), |c| (c.last_txid, c.last_sequence));
+
+    let pending = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!",
+               MIN(created_at) AS oldest,
+               COALESCE(EXTRACT(EPOCH FROM now() - MIN(created_at))::float8, 0) AS "lag_seconds!"
+        FROM events_outbox
+        WHERE (txid, sequence) > ($1, $2)
+        "#,
+        txid,
+        sequence
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    let partitions = sqlx::query_as!(
+        EventExportPartition,
+        r#"
+        SELECT partition_date, last_event_id, exported_count, updated_at
+        FROM event_export_watermarks
+        ORDER BY partition_date DESC
+        LIMIT $1
+        "#,
+        RECENT_PARTITIONS
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(EventExportStatus {
+        pending_events: pending.count,
+        oldest_pending_created_at: pending.oldest,
+        lag_seconds: pending.lag_seconds.max(0.0),
+        partitions,
+    })
+}
+
+pub async fn get_event_export_status(
+    State(state): State<AppState>,
+) -> Result<Json<EventExportStatus>, ApiError> {
+    Ok(Json(fetch_status(&state).await?))
+}
@@ -1,15 +1,55 @@
-use chrono::Utc;
+use std::sync::LazyLock;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use mini_stripe_types::delivery::MAX_DELIVERY_ATTEMPTS;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::error;
 use uuid::Uuid;
 
+use crate::error::ApiError;
+use crate::state::AppState;
+
+// Hard cap on stored event payloads. Hitting it means a handler built a bad payload.
+static MAX_EVENT_PAYLOAD_BYTES: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MAX_EVENT_PAYLOAD_BYTES")
+        .ok()
+        .map(|v| v.parse().expect("MAX_EVENT_PAYLOAD_BYTES must be a number"))
+        .unwrap_or(256 * 1024)
+});
+
+#[derive(Debug, thiserror::Error)]
+pub enum EventError {
+    #[error("event payload is {size} bytes, over the {max} byte limit")]
+    PayloadTooLarge { size: usize, max: usize },
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
 pub async fn insert_event(
     executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
     event_type: &str,
     payload: serde_json::Value,
-) -> Result<(), sqlx::Error> {
+) -> Result<(), EventError> {
+    let size = payload.to_string().len();
+    let max = *MAX_EVENT_PAYLOAD_BYTES;
+    if size > max {
+        error!(
+            event_type,
+            size, max, "refusing to write event: payload is too large"
+        );
+        return Err(EventError::PayloadTooLarge { size, max });
+    }
+
     let event_id = Uuid::new_v4();
 
     sqlx::query!(
-        r#" 
+        r#"
         INSERT INTO events_outbox (id, event_type, payload)
         VALUES ($1, $2, $3)
         "#,
@@ -22,3 +62,253 @@ pub async fn insert_event(
 
     Ok(())
 }
+
+#[derive(Serialize)]
+pub struct LatestEventSummary {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Most recent event whose payload is about this payment intent
+pub async fn latest_payment_intent_event(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    payment_intent_id: Uuid,
+) -> Result<Option<LatestEventSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        LatestEventSummary,
+        r#"
+        SELECT event_type, created_at
+        FROM events_outbox
+        WHERE payload -> 'payment_intent' ->> 'id' = $1::uuid::text
+        ORDER BY created_at DESC, sequence DESC
+        LIMIT 1
+        "#,
+        payment_intent_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[derive(Serialize)]
+pub struct OutboxDrainStatus {
+    pub draining: bool,
+    pub draining_since: Option<DateTime<Utc>>,
+    // Events created before the drain marker that still have deliveries outstanding
+    pub pending: i64,
+    pub drained: bool,
+}
+
+async fn drain_status(db: &PgPool) -> Result<OutboxDrainStatus, sqlx::Error> {
+    let draining_since = sqlx::query_scalar!(
+        r#"
+        SELECT draining_since
+        FROM outbox_state
+        "#
+    )
+    .fetch_one(db)
+    .await?;
+
+    let pending = pending_event_count(db, draining_since).await?;
+
+    Ok(OutboxDrainStatus {
+        draining: draining_since.is_some(),
+        draining_since,
+        pending,
+        drained: draining_since.is_some() && pending == 0,
+    })
+}
+
+// Events (created up to `created_up_to`, if given) that still have deliveries outstanding. An event
+// still has work if a delivery is in flight, or an enabled endpoint has no delivery yet.
+pub async fn pending_event_count(
+    db: &PgPool,
+    created_up_to: Option<DateTime<Utc>>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*)::bigint AS "count!"
+        FROM events_outbox e
+        WHERE e.delivered_at IS NULL
+          AND e.superseded_at IS NULL
+          AND ($1::timestamptz IS NULL OR e.created_at <= $1)
+          AND (
+            EXISTS (
+              SELECT 1
+              FROM webhook_deliveries d
+              WHERE d.event_id = e.id
+                AND d.status IN ('pending', 'in_progress')
+            )
+            OR EXISTS (
+              SELECT 1
+              FROM webhook_endpoints w
+              WHERE w.is_enabled = true
+                AND NOT EXISTS (
+                  SELECT 1
+                  FROM webhook_deliveries d
+                  WHERE d.event_id = e.id AND d.webhook_endpoint_id = w.id
+                )
+            )
+          )
+        "#,
+        created_up_to
+    )
+    .fetch_one(db)
+    .await
+}
+
+// Workers only enqueue events created up to the marker while draining
+pub async fn start_outbox_drain(
+    State(state): State<AppState>,
+) -> Result<Json<OutboxDrainStatus>, ApiError> {
+    sqlx::query!(
+        r#"
+        UPDATE outbox_state
+        SET draining_since = COALESCE(draining_since, now()),
+            updated_at = now()
+        "#
+    )
+    .execute(&state.db)
+    .await?;
+
+    let status = drain_status(&state.db).await?;
+
+    Ok(Json(status))
+}
+
+pub async fn get_outbox_drain_status(
+    State(state): State<AppState>,
+) -> Result<Json<OutboxDrainStatus>, ApiError> {
+    let status = drain_status(&state.db).await?;
+
+    Ok(Json(status))
+}
+
+pub async fn resume_outbox(
+    State(state): State<AppState>,
+) -> Result<Json<OutboxDrainStatus>, ApiError> {
+    sqlx::query!(
+        r#"
+        UPDATE outbox_state
+        SET draining_since = NULL,
+            updated_at = now()
+        "#
+    )
+    .execute(&state.db)
+    .await?;
+
+    let status = drain_status(&state.db).await?;
+
+    Ok(Json(status))
+}
+
+// Only one delivery per (event, endpoint) may be pending or in progress at a time
+const ACTIVE_DELIVERY_INDEX: &str = "webhook_deliveries_one_active_idx";
+const DELIVERY_GENERATION_CONSTRAINT: &str = "webhook_deliveries_generation_unique";
+
+#[derive(Deserialize)]
+pub struct RedeliverEventRequest {
+    pub webhook_endpoint_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct RedeliveryResponse {
+    pub id: Uuid,
+    pub event_id: Uuid,
+    pub webhook_endpoint_id: Uuid,
+    pub generation: i32,
+    pub status: String,
+}
+
+// Queues a new generation of the event's delivery to one endpoint. Refused with 409 while an
+// earlier generation is still pending or in progress, so the worker never sends it twice at once.
+pub async fn redeliver_event(
+    State(state): State<AppState>,
+    Path(event_id): Path<Uuid>,
+    Json(req): Json<RedeliverEventRequest>,
+) -> Result<(StatusCode, Json<RedeliveryResponse>), ApiError> {
+    let endpoint_id = req.webhook_endpoint_id;
+
+    let event_exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM events_outbox WHERE id = $1) AS "exists!""#,
+        event_id
+    )
+    .fetch_one(&state.db)
+    .await?;
+    if !event_exists {
+        return Err(ApiError::not_found("event not found"));
+    }
+
+    let endpoint_exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM webhook_endpoints WHERE id = $1) AS "exists!""#,
+        endpoint_id
+    )
+    .fetch_one(&state.db)
+    .await?;
+    if !endpoint_exists {
+        return Err(ApiError::not_found("webhook_endpoint not found"));
+    }
+
+    let inserted = sqlx::query_as!(
+        RedeliveryResponse,
+        r#"
+        INSERT INTO webhook_deliveries (
+          id, event_id, webhook_endpoint_id, generation, status, next_attempt_at, max_attempts
+        )
+        -- With the attempt limit of the generations before it
+        SELECT gen_random_uuid(), $1, $2, COALESCE(MAX(generation), 0) + 1, 'pending', now(),
+               COALESCE(MAX(max_attempts), $3)
+        FROM webhook_deliveries
+        WHERE event_id = $1 AND webhook_endpoint_id = $2
+        RETURNING id, event_id, webhook_endpoint_id, generation, status
+        "#,
+        event_id,
+        endpoint_id,
+        MAX_DELIVERY_ATTEMPTS
+    )
+    .fetch_one(&state.db)
+    .await;
+
+    match inserted {
+        Ok(delivery) => Ok((StatusCode::CREATED, Json(delivery))),
+        // A concurrent redelivery took the same generation; it is the active one now
+        Err(e)
+            if matches!(
+                e.as_database_error().and_then(|db_err| db_err.constraint()),
+                Some(ACTIVE_DELIVERY_INDEX | DELIVERY_GENERATION_CONSTRAINT)
+            ) =>
+        {
+            Err(active_delivery_conflict(&state.db, event_id, endpoint_id).await)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn active_delivery_conflict(db: &PgPool, event_id: Uuid, endpoint_id: Uuid) -> ApiError {
+    let active = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM webhook_deliveries
+        WHERE event_id = $1
+          AND webhook_endpoint_id = $2
+          AND status IN ('pending', 'in_progress')
+        "#,
+        event_id,
+        endpoint_id
+    )
+    .fetch_optional(db)
+    .await;
+
+    match active {
+        Ok(Some(id)) => ApiError::conflict(
+            "delivery_already_active",
+            format!("delivery {id} for this event and endpoint is still pending or in progress"),
+        ),
+        // It finished in the meantime: the caller can simply retry
+        Ok(None) => ApiError::conflict(
+            "delivery_already_active",
+            "a delivery for this event and endpoint was just queued, retry",
+        ),
+        Err(e) => e.into(),
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    Json,
+    extract::Path,
+    http::{HeaderMap, Method, StatusCode, header},
+};
+use chrono::DateTime;
+use mini_stripe_types::delivery::{MAX_DELIVERY_ATTEMPTS, RetryPolicy, retry_schedule};
+use mini_stripe_types::error::{ErrorDetail, ErrorResponse, codes};
+use mini_stripe_types::payment_intents::{
+    CreatePaymentIntentRequest, PaymentIntentResponse, PaymentIntentStatus,
+};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::refunds::{CreateRefundRequest, RefundList, RefundResponse, RefundStatus};
+use crate::routes;
+use crate::webhook_endpoints::{
+    CreateWebhookEndpointRequest, WebhookDeliveryItem, WebhookEndpointCreatedResponse,
+};
+
+// Every flow GET /v1/examples/{flow} documents, in the order the 404 lists them
+pub const FLOWS: &[&str] = &[
+    "create_and_confirm",
+    "idempotent_retry",
+    "webhook_setup",
+    "refund",
+];
+
+// Used when the request has no usable Host header
+const DEFAULT_HOST: &str = "localhost:3000";
+
+#[derive(Serialize)]
+pub struct Example {
+    pub flow: &'static str,
+    pub description: &'static str,
+    pub steps: Vec<ExampleStep>,
+}
+
+// One request in a flow. Requests and responses are built from the handlers' own types and paths
+// from the route constants, so renaming either changes the example too.
+#[derive(Serialize)]
+pub struct ExampleStep {
+    pub description: &'static str,
+    pub method: String,
+    // The route template, e.g. `/v1/payment_intents/{id}/confirm`
+    pub route: &'static str,
+    // `$NAME`, here or in the body, is a shell variable saved by an earlier step
+    pub path: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<&'static str, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saves: Option<SavedField>,
+    pub curl: String,
+    pub expected_status: u16,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub expected_headers: BTreeMap<&'static str, &'static str>,
+    // Values are illustrative; the field names are the real ones
+    pub response: Value,
+}
+
+// A response field the step stores in a shell variable for later steps
+#[derive(Serialize)]
+pub struct SavedField {
+    pub variable: &'static str,
+    pub field: &'static str,
+}
+
+impl ExampleStep {
+    fn new(
+        description: &'static str,
+        method: Method,
+        route: &'static str,
+        path: String,
+        expected_status: StatusCode,
+        response: impl Serialize,
+    ) -> Self {
+        Self {
+            description,
+            method: method.to_string(),
+            route,
+            path,
+            headers: BTreeMap::new(),
+            body: None,
+            saves: None,
+            curl: String::new(),
+            expected_status: expected_status.as_u16(),
+            expected_headers: BTreeMap::new(),
+            response: to_value(response),
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value.into());
+        self
+    }
+
+    fn body(mut self, body: impl Serialize) -> Self {
+        self.body = Some(to_value(body));
+        self.header("Content-Type", "application/json")
+    }
+
+    fn saves(mut self, variable: &'static str, field: &'static str) -> Self {
+        self.saves = Some(SavedField { variable, field });
+        self
+    }
+
+    fn expect_header(mut self, name: &'static str, value: &'static str) -> Self {
+        self.expected_headers.insert(name, value);
+        self
+    }
+
+    // The URL is double-quoted so `$NAME` expands; headers are single-quoted, and so is the
+    // body unless it has a `$NAME` in it too
+    fn with_curl(mut self, base_url: &str) -> Self {
+        let mut curl = format!("curl -sS -X {} \"{base_url}{}\"", self.method, self.path);
+        for (name, value) in &self.headers {
+            curl.push_str(&format!(" -H '{name}: {value}'"));
+        }
+        if let Some(body) = &self.body {
+            let body = body.to_string();
+            if body.contains('$') {
+                let escaped = body
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('`', "\\`");
+                curl.push_str(&format!(" -d \"{escaped}\""));
+            } else {
+                curl.push_str(&format!(" -d '{body}'"));
+            }
+        }
+        if let Some(saved) = &self.saves {
+            curl = format!("{}=$({curl} | jq -r '.{}')", saved.variable, saved.field);
+        }
+        self.curl = curl;
+        self
+    }
+}
+
+fn to_value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).expect("example values serialize")
+}
+
+fn sample_intent(status: PaymentIntentStatus) -> PaymentIntentResponse {
+    PaymentIntentResponse {
+        id: Uuid::nil(),
+        amount: 1000,
+        currency: "usd".to_string(),
+        status,
+        cancellation_reason: None,
+        last_payment_error: None,
+        metadata: Default::default(),
+        capture_method: "automatic".to_string(),
+        amount_received: if status == PaymentIntentStatus::Succeeded {
+            1000
+        } else {
+            0
+        },
+        description: None,
+        expires_at: Some(DateTime::UNIX_EPOCH + chrono::Duration::hours(24)),
+        payment_method: None,
+        customer: None,
+    }
+}
+
+fn create_request(amount: i64, confirm: bool) -> CreatePaymentIntentRequest {
+    CreatePaymentIntentRequest {
+        amount,
+        currency: Some("usd".to_string()),
+        confirm,
+        payment_method: None,
+        metadata: Default::default(),
+        capture_method: None,
+        description: None,
+        customer: None,
+    }
+}
+
+fn create_and_confirm() -> Example {
+    Example {
+        flow: "create_and_confirm",
+        description: "Create a payment intent, then confirm it. Send `confirm: true` on the \
+                      create to do both in one request.",
+        steps: vec![
+            ExampleStep::new(
+                "Create the intent",
+                Method::POST,
+                routes::PAYMENT_INTENTS,
+                routes::PAYMENT_INTENTS.to_string(),
+                StatusCode::CREATED,
+                sample_intent(PaymentIntentStatus::RequiresConfirmation),
+            )
+            .body(create_request(1000, false))
+            .saves("PAYMENT_INTENT_ID", "id"),
+            ExampleStep::new(
+                "Confirm it",
+                Method::POST,
+                routes::PAYMENT_INTENT_CONFIRM,
+                routes::payment_intent_confirm("$PAYMENT_INTENT_ID"),
+                StatusCode::OK,
+                sample_intent(PaymentIntentStatus::Succeeded),
+            ),
+        ],
+    }
+}
+
+fn idempotent_retry() -> Example {
+    const KEY: &str = "order-1234";
+    let reused = ErrorResponse {
+        error: ErrorDetail {
+            code: codes::IDEMPOTENCY_KEY_REUSED.to_string(),
+            message: "Idempotency-Key was already used with a different request".to_string(),
+        },
+    };
+
+    Example {
+        flow: "idempotent_retry",
+        description: "Retry a create safely. A retry with the same Idempotency-Key and body \
+                      returns the original intent instead of creating another.",
+        steps: vec![
+            ExampleStep::new(
+                "Create with an Idempotency-Key",
+                Method::POST,
+                routes::PAYMENT_INTENTS,
+                routes::PAYMENT_INTENTS.to_string(),
+                StatusCode::CREATED,
+                sample_intent(PaymentIntentStatus::RequiresConfirmation),
+            )
+            .header("Idempotency-Key", KEY)
+            .body(create_request(1000, false)),
+            ExampleStep::new(
+                "Retry the same request: the original intent comes back, marked as a replay",
+                Method::POST,
+                routes::PAYMENT_INTENTS,
+                routes::PAYMENT_INTENTS.to_string(),
+                StatusCode::CREATED,
+                sample_intent(PaymentIntentStatus::RequiresConfirmation),
+            )
+            .header("Idempotency-Key", KEY)
+            .body(create_request(1000, false))
+            .expect_header("idempotent-replayed", "true"),
+            ExampleStep::new(
+                "Reusing the key for a different request is refused",
+                Method::POST,
+                routes::PAYMENT_INTENTS,
+                routes::PAYMENT_INTENTS.to_string(),
+                StatusCode::CONFLICT,
+                reused,
+            )
+            .header("Idempotency-Key", KEY)
+            .body(create_request(2500, false)),
+        ],
+    }
+}
+
+fn webhook_setup() -> Example {
+    let endpoint = WebhookEndpointCreatedResponse {
+        id: Uuid::nil(),
+        url: "https://example.com/webhooks".to_string(),
+        secret: "shown only in this response".to_string(),
+        signature_scheme: "v1".to_string(),
+        payload_template: None,
+        is_enabled: true,
+        created_at: DateTime::UNIX_EPOCH,
+    };
+    let delivery = WebhookDeliveryItem {
+        id: Uuid::nil(),
+        event_id: Uuid::nil(),
+        generation: 1,
+        status: "succeeded".to_string(),
+        attempt_count: 1,
+        last_attempt_at: Some(DateTime::UNIX_EPOCH),
+        next_attempt_at: None,
+        last_error: None,
+        response_status: Some(200),
+        response_snippet: Some("ok".to_string()),
+        response_content_length: Some(2),
+        response_truncated: false,
+        retry: retry_schedule(
+            RetryPolicy {
+                delivery_id: Uuid::nil(),
+                max_attempts: MAX_DELIVERY_ATTEMPTS,
+            },
+            "succeeded",
+            1,
+            None,
+            DateTime::UNIX_EPOCH,
+        ),
+    };
+
+    Example {
+        flow: "webhook_setup",
+        description: "Register a webhook endpoint and watch events reach it. Each delivery \
+                      carries an HMAC-SHA256 of the body under the endpoint secret in the \
+                      signature header (or, with `signature_scheme: v2`, a timestamped \
+                      HMAC-SHA512); verify it before trusting the payload.",
+        steps: vec![
+            ExampleStep::new(
+                "Register the endpoint and keep the secret: it is only returned here",
+                Method::POST,
+                routes::WEBHOOK_ENDPOINTS,
+                routes::WEBHOOK_ENDPOINTS.to_string(),
+                StatusCode::CREATED,
+                endpoint,
+            )
+            .body(CreateWebhookEndpointRequest {
+                url: "https://example.com/webhooks".to_string(),
+                signature_scheme: None,
+                payload_template: None,
+            })
+            .saves("WEBHOOK_ENDPOINT_ID", "id"),
+            ExampleStep::new(
+                "Create and confirm an intent, which emits payment_intent events",
+                Method::POST,
+                routes::PAYMENT_INTENTS,
+                routes::PAYMENT_INTENTS.to_string(),
+                StatusCode::CREATED,
+                sample_intent(PaymentIntentStatus::Succeeded),
+            )
+            .body(create_request(1000, true)),
+            ExampleStep::new(
+                "List the endpoint's deliveries once the worker has sent them",
+                Method::GET,
+                routes::WEBHOOK_ENDPOINT_DELIVERIES,
+                routes::webhook_endpoint_deliveries("$WEBHOOK_ENDPOINT_ID"),
+                StatusCode::OK,
+                vec![delivery],
+            ),
+        ],
+    }
+}
+
+fn sample_refund(amount: i64) -> RefundResponse {
+    RefundResponse {
+        id: Uuid::nil(),
+        charge: Uuid::nil(),
+        payment_intent: Uuid::nil(),
+        amount,
+        currency: "usd".to_string(),
+        status: RefundStatus::Succeeded,
+        created_at: DateTime::UNIX_EPOCH,
+    }
+}
+
+// A refund of the intent saved in $PAYMENT_INTENT_ID, which the typed request can't hold
+fn refund_request(amount: Option<i64>) -> Value {
+    let mut body = to_value(CreateRefundRequest {
+        payment_intent: Some(Uuid::nil()),
+        charge: None,
+        amount,
+    });
+    body["payment_intent"] = "$PAYMENT_INTENT_ID".into();
+    body
+}
+
+fn refund() -> Example {
+    let refunded = ErrorResponse {
+        error: ErrorDetail {
+            code: codes::CHARGE_ALREADY_REFUNDED.to_string(),
+            message: "charge has already been refunded in full".to_string(),
+        },
+    };
+    let list = RefundList {
+        object: "list".to_string(),
+        data: vec![sample_refund(600), sample_refund(400)],
+        has_more: false,
+        limit: 20,
+        max_limit: 100,
+        warnings: Vec::new(),
+    };
+
+    Example {
+        flow: "refund",
+        description: "Refund a paid intent, in part and then in full. Refunds add up to at most \
+                      what was captured; one without an amount refunds whatever is left.",
+        steps: vec![
+            ExampleStep::new(
+                "Create and confirm an intent in one request",
+                Method::POST,
+                routes::PAYMENT_INTENTS,
+                routes::PAYMENT_INTENTS.to_string(),
+                StatusCode::CREATED,
+                sample_intent(PaymentIntentStatus::Succeeded),
+            )
+            .body(create_request(1000, true))
+            .saves("PAYMENT_INTENT_ID", "id"),
+            ExampleStep::new(
+                "Refund part of it",
+                Method::POST,
+                routes::REFUNDS,
+                routes::REFUNDS.to_string(),
+                StatusCode::CREATED,
+                sample_refund(400),
+            )
+            .body(refund_request(Some(400))),
+            ExampleStep::new(
+                "Refund the rest by leaving out the amount",
+                Method::POST,
+                routes::REFUNDS,
+                routes::REFUNDS.to_string(),
+                StatusCode::CREATED,
+                sample_refund(600),
+            )
+            .body(refund_request(None)),
+            ExampleStep::new(
+                "Nothing is left, so another refund is refused",
+                Method::POST,
+                routes::REFUNDS,
+                routes::REFUNDS.to_string(),
+                StatusCode::BAD_REQUEST,
+                refunded,
+            )
+            .body(refund_request(None)),
+            ExampleStep::new(
+                "List the intent's refunds, newest first",
+                Method::GET,
+                routes::REFUNDS,
+                format!("{}?payment_intent=$PAYMENT_INTENT_ID", routes::REFUNDS),
+                StatusCode::OK,
+                list,
+            ),
+        ],
+    }
+}
+
+pub fn example(flow: &str, base_url: &str) -> Option<Example> {
+    let mut example = match flow {
+        "create_and_confirm" => create_and_confirm(),
+        "idempotent_retry" => idempotent_retry(),
+        "webhook_setup" => webhook_setup(),
+        "refund" => refund(),
+        _ => return None,
+    };
+    example.steps = example
+        .steps
+        .into_iter()
+        .map(|step| step.with_curl(base_url))
+        .collect();
+    Some(example)
+}
+
+// The Host the caller used, so the commands work as pasted. Anything that isn't a plain
+// host[:port] falls back to the default rather than ending up inside a shell command.
+fn base_url(headers: &HeaderMap) -> String {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .filter(|host| {
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+        })
+        .unwrap_or(DEFAULT_HOST);
+    format!("http://{host}")
+}
+
+pub async fn get_example(
+    Path(flow): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Example>, ApiError> {
+    example(&flow, &base_url(&headers))
+        .map(Json)
+        .ok_or_else(|| {
+            ApiError::not_found(format!(
+                "no example flow named '{flow}'; available flows: {}",
+                FLOWS.join(", ")
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_flow_is_listed() {
+        for flow in FLOWS {
+            assert_eq!(example(flow, "http://h").unwrap().flow, *flow);
+        }
+    }
+
+    #[test]
+    fn bodies_with_variables_are_double_quoted() {
+        let refund = example("refund", "http://h").unwrap();
+        assert_eq!(
+            refund.steps[1].curl,
+            "curl -sS -X POST \"http://h/v1/refunds\" -H 'Content-Type: application/json' \
+             -d \"{\\\"amount\\\":400,\\\"payment_intent\\\":\\\"$PAYMENT_INTENT_ID\\\"}\""
+        );
+    }
+
+    #[test]
+    fn base_url_ignores_hosts_that_are_not_plain() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(base_url(&headers), "http://localhost:3000");
+
+        headers.insert(header::HOST, "api.example.test:8080".parse().unwrap());
+        assert_eq!(base_url(&headers), "http://api.example.test:8080");
+
+        headers.insert(header::HOST, "x$(rm -rf ~)".parse().unwrap());
+        assert_eq!(base_url(&headers), "http://localhost:3000");
+    }
+}
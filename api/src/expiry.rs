@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::services::DomainError;
+use crate::services::payment_intents::PaymentIntentService;
+use crate::state::AppState;
+
+// How often the background loop looks for expired intents, and how many one transaction cancels
+const RUN_INTERVAL: Duration = Duration::from_secs(60);
+pub const BATCH_SIZE: i64 = 100;
+
+// Cancels every intent still unpaid (requires_confirmation, requires_payment_method or
+// requires_action) past its expires_at, a batch per transaction, and returns how many. A confirm
+// that gets to one first cancels it the same way.
+pub async fn run(state: &AppState, batch_size: i64) -> Result<u64, DomainError> {
+    let service = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
+    );
+    let mut expired = 0;
+    loop {
+        let batch = service.expire_batch(batch_size).await?;
+        expired += batch as u64;
+        if (batch as i64) < batch_size {
+            return Ok(expired);
+        }
+    }
+}
+
+// Runs every RUN_INTERVAL until the process exits
+pub async fn run_forever(state: AppState) {
+    let mut interval = tokio::time::interval(RUN_INTERVAL);
+    loop {
+        interval.tick().await;
+        match run(&state, BATCH_SIZE).await {
+            Ok(0) => {}
+            Ok(expired) => info!("expired {expired} payment intents"),
+            Err(e) => warn!(error = %e, "payment intent expiry failed"),
+        }
+    }
+}
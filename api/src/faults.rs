@@ -0,0 +1,236 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use mini_stripe_types::error::codes;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::metrics::{self, Metrics};
+use crate::services::DomainError;
+use crate::state::AppState;
+
+// How long a rule lasts when the request doesn't say, and the longest it may ask for
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+const MAX_TTL: Duration = Duration::from_secs(3600);
+const MAX_READ_LATENCY_MS: u64 = 10_000;
+
+// What a rule breaks
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Fault {
+    // This share (0 to 1) of confirms fail with a 503 before the intent is touched
+    ConfirmUnavailable { rate: f64 },
+    // Reads of payment intents (retrieve, list, transitions) take this much longer
+    ReadLatency { latency_ms: u64 },
+    // The worker fails deliveries to this endpoint as timed out, without sending them
+    WebhookTimeout { webhook_endpoint_id: Uuid },
+}
+
+impl Fault {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fault::ConfirmUnavailable { .. } => "confirm_unavailable",
+            Fault::ReadLatency { .. } => "read_latency",
+            Fault::WebhookTimeout { .. } => "webhook_timeout",
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match *self {
+            Fault::ConfirmUnavailable { rate } if !(0.0..=1.0).contains(&rate) => {
+                Err("rate must be between 0 and 1".to_string())
+            }
+            Fault::ReadLatency { latency_ms } if latency_ms > MAX_READ_LATENCY_MS => {
+                Err(format!("latency_ms must be at most {MAX_READ_LATENCY_MS}"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FaultRule {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub fault: Fault,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // Requests this rule failed or slowed; for webhook_timeout, deliveries the worker failed
+    pub activations: u64,
+}
+
+impl FaultRule {
+    pub fn new(fault: Fault, ttl: Duration) -> Self {
+        let created_at = Utc::now();
+        Self {
+            id: Uuid::new_v4(),
+            fault,
+            created_at,
+            expires_at: created_at + ttl,
+            activations: 0,
+        }
+    }
+}
+
+// The fault rules of this API process. Every hook is a no-op outside sandbox mode, whatever
+// the registry holds.
+#[derive(Clone, Default)]
+pub struct Faults {
+    rules: Arc<Mutex<Vec<FaultRule>>>,
+}
+
+impl Faults {
+    pub fn insert(&self, rule: FaultRule) -> FaultRule {
+        self.rules.lock().unwrap().push(rule.clone());
+        rule
+    }
+
+    // Unexpired rules, oldest first. Expired ones are dropped on the way.
+    pub fn active(&self) -> Vec<FaultRule> {
+        let mut rules = self.rules.lock().unwrap();
+        let now = Utc::now();
+        rules.retain(|rule| rule.expires_at > now);
+        rules.clone()
+    }
+
+    pub fn clear(&self) {
+        self.rules.lock().unwrap().clear();
+    }
+
+    // The first unexpired rule `applies` picks, with its activation counted and logged
+    fn activate(
+        &self,
+        config: &Config,
+        metrics: &Metrics,
+        applies: impl Fn(&Fault) -> bool,
+    ) -> Option<FaultRule> {
+        if !config.sandbox_mode {
+            return None;
+        }
+        let mut rules = self.rules.lock().unwrap();
+        let now = Utc::now();
+        rules.retain(|rule| rule.expires_at > now);
+        let rule = rules.iter_mut().find(|rule| applies(&rule.fault))?;
+        rule.activations += 1;
+
+        let name = rule.fault.name();
+        metrics.inc(metrics::FAULTS_INJECTED, &[("fault", name)]);
+        warn!("injected fault {} ({name})", rule.id);
+        Some(rule.clone())
+    }
+
+    // Hook at the start of a confirm
+    pub fn check_confirm(&self, config: &Config, metrics: &Metrics) -> Result<(), DomainError> {
+        let rule = self.activate(config, metrics, |fault| match *fault {
+            Fault::ConfirmUnavailable { rate } => rand::random_bool(rate),
+            _ => false,
+        });
+        match rule {
+            Some(rule) => Err(DomainError::FaultInjected(rule.id)),
+            None => Ok(()),
+        }
+    }
+
+    // Hook before a payment intent read
+    pub async fn delay_read(&self, config: &Config, metrics: &Metrics) {
+        let rule = self.activate(config, metrics, |fault| {
+            matches!(fault, Fault::ReadLatency { .. })
+        });
+        if let Some(FaultRule {
+            fault: Fault::ReadLatency { latency_ms },
+            ..
+        }) = rule
+        {
+            tokio::time::sleep(Duration::from_millis(latency_ms)).await;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateFaultRequest {
+    #[serde(flatten)]
+    pub fault: Fault,
+    // Defaults to 300, at most 3600
+    pub ttl_secs: Option<u64>,
+}
+
+pub async fn create_fault(
+    State(state): State<AppState>,
+    Json(req): Json<CreateFaultRequest>,
+) -> Result<(StatusCode, Json<FaultRule>), ApiError> {
+    req.fault
+        .validate()
+        .map_err(|msg| ApiError::bad_request(codes::PARAMETER_INVALID, msg))?;
+    let ttl = req.ttl_secs.map_or(DEFAULT_TTL, Duration::from_secs);
+    if ttl.is_zero() || ttl > MAX_TTL {
+        return Err(ApiError::bad_request(
+            codes::PARAMETER_INVALID,
+            format!("ttl_secs must be between 1 and {}", MAX_TTL.as_secs()),
+        ));
+    }
+
+    // The worker is another process: it only sees webhook_timeout rules through the database
+    let rule = FaultRule::new(req.fault, ttl);
+    if let Fault::WebhookTimeout {
+        webhook_endpoint_id,
+    } = rule.fault
+    {
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO webhook_delivery_faults (id, webhook_endpoint_id, expires_at)
+            SELECT $1, id, $3 FROM webhook_endpoints WHERE id = $2
+            "#,
+            rule.id,
+            webhook_endpoint_id,
+            rule.expires_at
+        )
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+        if inserted == 0 {
+            return Err(ApiError::not_found("webhook_endpoint not found"));
+        }
+    }
+    let rule = state.faults.insert(rule);
+    info!(
+        "fault {} ({}) active until {}",
+        rule.id,
+        rule.fault.name(),
+        rule.expires_at
+    );
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub async fn list_faults(State(state): State<AppState>) -> Result<Json<Vec<FaultRule>>, ApiError> {
+    let mut rules = state.faults.active();
+
+    // The worker counts webhook_timeout activations in the table
+    let ids: Vec<Uuid> = rules.iter().map(|rule| rule.id).collect();
+    let counted = sqlx::query!(
+        "SELECT id, activations FROM webhook_delivery_faults WHERE id = ANY($1)",
+        &ids
+    )
+    .fetch_all(&state.db)
+    .await?;
+    for row in counted {
+        if let Some(rule) = rules.iter_mut().find(|rule| rule.id == row.id) {
+            rule.activations = row.activations as u64;
+        }
+    }
+    Ok(Json(rules))
+}
+
+// Ends every rule at once, the worker's included
+pub async fn clear_faults(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.faults.clear();
+    sqlx::query!("DELETE FROM webhook_delivery_faults")
+        .execute(&state.db)
+        .await?;
+    info!("cleared all faults");
+    Ok(StatusCode::NO_CONTENT)
+}
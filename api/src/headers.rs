@@ -0,0 +1,46 @@
+use axum::http::HeaderMap;
+
+use crate::error::ApiError;
+
+// A header that is absent is None; one that is present but not visible ASCII is a 400 with `code`.
+// `.to_str().ok()` would treat the latter as absent, e.g. silently dropping an Idempotency-Key.
+pub fn require_ascii_header<'a>(
+    headers: &'a HeaderMap,
+    name: &str,
+    code: &'static str,
+) -> Result<Option<&'a str>, ApiError> {
+    let Some(value) = headers.get(name) else {
+        return Ok(None);
+    };
+
+    value
+        .to_str()
+        .map(Some)
+        .map_err(|_| ApiError::bad_request(code, format!("{name} header must be ASCII")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn absent_and_invalid_headers_differ() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            require_ascii_header(&headers, "x-key", "bad").unwrap(),
+            None
+        );
+
+        headers.insert("x-key", HeaderValue::from_static("abc"));
+        assert_eq!(
+            require_ascii_header(&headers, "x-key", "bad").unwrap(),
+            Some("abc")
+        );
+
+        headers.insert("x-key", HeaderValue::from_bytes(b"ab\xff").unwrap());
+        let err = require_ascii_header(&headers, "x-key", "bad").unwrap_err();
+        assert_eq!(err.code, "bad");
+        assert_eq!(err.message, "x-key header must be ASCII");
+    }
+}
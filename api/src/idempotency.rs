@@ -0,0 +1,111 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+use crate::state::AppState;
+
+const TOP_REPLAYED_KEYS: i64 = 10;
+
+#[derive(Deserialize)]
+pub struct IdempotencyStatsQuery {
+    // Keys created at or after this; defaults to the last 24h
+    since: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct EndpointIdempotencyStats {
+    pub endpoint: String,
+    pub keys: i64,
+    pub replays: i64,
+    pub conflicts: i64,
+    pub reconstructions: i64,
+    // Per key created
+    pub replay_rate: f64,
+    pub conflict_rate: f64,
+}
+
+#[derive(Serialize)]
+pub struct ReplayedKey {
+    pub key: String,
+    pub endpoint: String,
+    pub replay_count: i32,
+}
+
+#[derive(Serialize)]
+pub struct IdempotencyStats {
+    pub since: DateTime<Utc>,
+    pub endpoints: Vec<EndpointIdempotencyStats>,
+    // Keys replayed the most; a high count usually means a client stuck in a retry loop
+    pub top_replayed_keys: Vec<ReplayedKey>,
+}
+
+fn rate(count: i64, keys: i64) -> f64 {
+    if keys == 0 {
+        return 0.0;
+    }
+    count as f64 / keys as f64
+}
+
+pub async fn get_idempotency_stats(
+    State(state): State<AppState>,
+    Query(query): Query<IdempotencyStatsQuery>,
+) -> Result<Json<IdempotencyStats>, ApiError> {
+    let since = query
+        .since
+        .unwrap_or_else(|| Utc::now() - Duration::days(1));
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT endpoint,
+               COUNT(*)::bigint AS "keys!",
+               COALESCE(SUM(replay_count), 0)::bigint AS "replays!",
+               COALESCE(SUM(conflict_count), 0)::bigint AS "conflicts!",
+               COALESCE(SUM(reconstructed_count), 0)::bigint AS "reconstructions!"
+        FROM idempotency_keys
+        WHERE created_at >= $1
+        GROUP BY endpoint
+        ORDER BY endpoint
+        "#,
+        since
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let endpoints = rows
+        .into_iter()
+        .map(|r| EndpointIdempotencyStats {
+            replay_rate: rate(r.replays, r.keys),
+            conflict_rate: rate(r.conflicts, r.keys),
+            endpoint: r.endpoint,
+            keys: r.keys,
+            replays: r.replays,
+            conflicts: r.conflicts,
+            reconstructions: r.reconstructions,
+        })
+        .collect();
+
+    let top_replayed_keys = sqlx::query_as!(
+        ReplayedKey,
+        r#"
+        SELECT key, endpoint, replay_count
+        FROM idempotency_keys
+        WHERE created_at >= $1 AND replay_count > 0
+        ORDER BY replay_count DESC, created_at
+        LIMIT $2
+        "#,
+        since,
+        TOP_REPLAYED_KEYS
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(IdempotencyStats {
+        since,
+        endpoints,
+        top_replayed_keys,
+    }))
+}
@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use chrono::{DateTime, Utc};
+use mini_stripe_types::error::codes;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::error::ApiError;
+use crate::metrics::{self, Metrics};
+use crate::state::AppState;
+
+// Defaults for config.idempotency_cleanup_batch_size and config.idempotency_cleanup_pause
+pub const DEFAULT_BATCH_SIZE: i64 = 1000;
+pub const DEFAULT_PAUSE: Duration = Duration::from_millis(100);
+// How often the background loop starts a run, or carries on with an unfinished one
+const RUN_INTERVAL: Duration = Duration::from_secs(300);
+// Batches a manual run does when the request doesn't say, and the most it may ask for
+const DEFAULT_MANUAL_BATCHES: u64 = 100;
+const MAX_MANUAL_BATCHES: u64 = 10_000;
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CleanupReport {
+    // Batches that deleted something
+    pub batches: u64,
+    pub deleted: u64,
+    // The run reached the end of the expired keys; otherwise the next call picks up from here
+    pub finished: bool,
+}
+
+enum Batch {
+    Deleted(u64),
+    Finished { run_deleted: i64 },
+}
+
+// One transaction: starts a run if none is in progress, then deletes the next `batch_size`
+// expired keys after the cursor and moves it on. The state row stays locked until commit, so
+// two cleaners never work on the same run at once.
+async fn run_batch(db: &PgPool, batch_size: i64) -> Result<Batch, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let run = sqlx::query!(
+        r#"
+        UPDATE idempotency_cleanup_state
+        SET run_cutoff = COALESCE(run_cutoff, now()),
+            run_started_at = COALESCE(run_started_at, now()),
+            updated_at = now()
+        RETURNING run_cutoff AS "run_cutoff!", cursor_key, cursor_endpoint
+        "#
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // Keys still locked by a request are skipped; the next run gets them
+    let last = sqlx::query!(
+        r#"
+        WITH batch AS (
+          SELECT key, endpoint
+          FROM idempotency_keys
+          WHERE expires_at < $1 AND (key, endpoint) > ($2, $3)
+          ORDER BY key, endpoint
+          LIMIT $4
+          FOR UPDATE SKIP LOCKED
+        ),
+        deleted AS (
+          DELETE FROM idempotency_keys k
+          USING batch b
+          WHERE k.key = b.key AND k.endpoint = b.endpoint
+          RETURNING k.key, k.endpoint
+        )
+        SELECT key, endpoint, count(*) OVER () AS "deleted!"
+        FROM deleted
+        ORDER BY key DESC, endpoint DESC
+        LIMIT 1
+        "#,
+        run.run_cutoff,
+        run.cursor_key,
+        run.cursor_endpoint,
+        batch_size
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let batch = match last {
+        Some(last) => {
+            sqlx::query!(
+                r#"
+                UPDATE idempotency_cleanup_state
+                SET cursor_key = $1, cursor_endpoint = $2, run_deleted = run_deleted + $3
+                "#,
+                last.key,
+                last.endpoint,
+                last.deleted
+            )
+            .execute(&mut *tx)
+            .await?;
+            Batch::Deleted(last.deleted as u64)
+        }
+        None => {
+            let run_deleted = sqlx::query_scalar!(
+                r#"
+                UPDATE idempotency_cleanup_state
+                SET run_cutoff = NULL, cursor_key = '', cursor_endpoint = '',
+                    run_started_at = NULL, run_deleted = 0,
+                    last_run_finished_at = now(), last_run_deleted = run_deleted
+                RETURNING last_run_deleted AS "last_run_deleted!"
+                "#
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            Batch::Finished { run_deleted }
+        }
+    };
+    tx.commit().await?;
+    Ok(batch)
+}
+
+// Deletes expired idempotency keys in (key, endpoint) order, `batch_size` per transaction with
+// `pause` between them, so neither long locks nor a burst of WAL get in the way of traffic.
+// Progress is kept in idempotency_cleanup_state: a run cut short by `max_batches`, an error or
+// a restart carries on from its cursor next time.
+pub async fn run(
+    db: &PgPool,
+    metrics: &Metrics,
+    batch_size: i64,
+    pause: Duration,
+    max_batches: Option<u64>,
+) -> Result<CleanupReport, sqlx::Error> {
+    let mut report = CleanupReport::default();
+    while max_batches.is_none_or(|max| report.batches < max) {
+        if report.batches > 0 {
+            tokio::time::sleep(pause).await;
+        }
+        match run_batch(db, batch_size).await? {
+            Batch::Deleted(deleted) => {
+                metrics.add(metrics::IDEMPOTENCY_KEYS_DELETED, &[], deleted);
+                report.batches += 1;
+                report.deleted += deleted;
+            }
+            Batch::Finished { run_deleted } => {
+                metrics.inc(metrics::IDEMPOTENCY_CLEANUP_RUNS, &[]);
+                info!("idempotency key cleanup run finished, {run_deleted} keys deleted");
+                report.finished = true;
+                break;
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn batch_size(state: &AppState) -> i64 {
+    state
+        .config
+        .idempotency_cleanup_batch_size
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+}
+
+fn pause(state: &AppState) -> Duration {
+    state
+        .config
+        .idempotency_cleanup_pause
+        .unwrap_or(DEFAULT_PAUSE)
+}
+
+// Runs every RUN_INTERVAL until the process exits; only spawned when config.idempotency_key_ttl
+// is set
+pub async fn run_forever(state: AppState) {
+    let mut interval = tokio::time::interval(RUN_INTERVAL);
+    loop {
+        interval.tick().await;
+        let report = run(
+            &state.db,
+            &state.metrics,
+            batch_size(&state),
+            pause(&state),
+            None,
+        )
+        .await;
+        if let Err(e) = report {
+            warn!(error = %e, "idempotency key cleanup failed");
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CleanupState {
+    // Set while a run is in progress: it deletes keys that expired before this
+    pub run_cutoff: Option<DateTime<Utc>>,
+    pub run_started_at: Option<DateTime<Utc>>,
+    pub run_deleted: i64,
+    pub last_run_finished_at: Option<DateTime<Utc>>,
+    pub last_run_deleted: Option<i64>,
+    // Expired keys not yet deleted
+    pub expired_keys: i64,
+}
+
+pub async fn get_cleanup_state(
+    State(state): State<AppState>,
+) -> Result<Json<CleanupState>, ApiError> {
+    let cleanup = sqlx::query_as!(
+        CleanupState,
+        r#"
+        SELECT run_cutoff, run_started_at, run_deleted, last_run_finished_at, last_run_deleted,
+               (SELECT count(*) FROM idempotency_keys WHERE expires_at < now()) AS "expired_keys!"
+        FROM idempotency_cleanup_state
+        "#
+    )
+    .fetch_one(&state.db)
+    .await?;
+    Ok(Json(cleanup))
+}
+
+#[derive(Deserialize)]
+pub struct ManualCleanupQuery {
+    // Defaults to 100, at most 10000
+    max_batches: Option<u64>,
+}
+
+// A bounded run with the configured batch size and pause, e.g. to catch up after an outage.
+// Works whether or not IDEMPOTENCY_KEY_TTL is set.
+pub async fn run_cleanup(
+    State(state): State<AppState>,
+    Query(query): Query<ManualCleanupQuery>,
+) -> Result<Json<CleanupReport>, ApiError> {
+    let max_batches = query.max_batches.unwrap_or(DEFAULT_MANUAL_BATCHES);
+    if !(1..=MAX_MANUAL_BATCHES).contains(&max_batches) {
+        return Err(ApiError::bad_request(
+            codes::PARAMETER_INVALID,
+            format!("max_batches must be between 1 and {MAX_MANUAL_BATCHES}"),
+        ));
+    }
+    let report = run(
+        &state.db,
+        &state.metrics,
+        batch_size(&state),
+        pause(&state),
+        Some(max_batches),
+    )
+    .await?;
+    Ok(Json(report))
+}
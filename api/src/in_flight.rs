@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::slow_queries::REQUEST_ID_HEADER;
+use crate::state::AppState;
+use crate::workers::fetch_workers;
+
+tokio::task_local! {
+    // Set for the request being handled by `scope_request_id`
+    static REQUEST_ID: String;
+}
+
+// Middleware. Gives every request an X-Request-Id (the caller's, or a new one written into the
+// request so later layers see the same) that mutations tracked while handling it carry.
+pub async fn scope_request_id(mut req: Request, next: Next) -> Response {
+    let request_id = match req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(id) => id.to_string(),
+        None => {
+            let id = Uuid::new_v4().to_string();
+            req.headers_mut().insert(
+                REQUEST_ID_HEADER,
+                HeaderValue::from_str(&id).expect("uuids are valid header values"),
+            );
+            id
+        }
+    };
+    REQUEST_ID.scope(request_id, next.run(req)).await
+}
+
+// Payment mutations currently running; shutdown waits for this to reach zero
+#[derive(Clone, Default)]
+pub struct InFlight {
+    count: Arc<AtomicUsize>,
+    next_id: Arc<AtomicU64>,
+    active: Arc<Mutex<HashMap<u64, InFlightOperation>>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InFlightOperation {
+    pub operation: String,
+    // None when tracked outside a request
+    pub request_id: Option<String>,
+}
+
+// Counts one mutation until dropped (including on error/early return)
+pub struct InFlightGuard {
+    in_flight: InFlight,
+    id: u64,
+}
+
+impl InFlight {
+    pub fn track(&self, operation: impl Into<String>) -> InFlightGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let operation = InFlightOperation {
+            operation: operation.into(),
+            request_id: REQUEST_ID.try_with(Clone::clone).ok(),
+        };
+        self.active.lock().unwrap().insert(id, operation);
+        self.count.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard {
+            in_flight: self.clone(),
+            id,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    // What is running right now, oldest first
+    pub fn operations(&self) -> Vec<String> {
+        self.active_operations()
+            .into_iter()
+            .map(|op| op.operation)
+            .collect()
+    }
+
+    fn active_operations(&self) -> Vec<InFlightOperation> {
+        let active = self.active.lock().unwrap();
+        let mut ops: Vec<_> = active.iter().collect();
+        ops.sort_by_key(|(id, _)| **id);
+        ops.into_iter().map(|(_, op)| op.clone()).collect()
+    }
+
+    // Ok once nothing is in flight; Err with the stragglers if `deadline` passes first
+    pub async fn wait_idle(&self, deadline: Duration) -> Result<(), Vec<InFlightOperation>> {
+        let until = Instant::now() + deadline;
+        while self.count() > 0 {
+            if Instant::now() >= until {
+                return Err(self.active_operations());
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.active.lock().unwrap().remove(&self.id);
+        self.in_flight.count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Serialize)]
+pub struct Diagnostics {
+    in_flight_mutations: usize,
+    in_flight: Vec<String>,
+    ready: bool,
+    slow_queries_total: HashMap<String, u64>,
+    // Pending outbox events as last sampled (0 unless backpressure is configured)
+    event_backlog: i64,
+    event_backlog_engaged: bool,
+    // Webhook workers that stopped sending heartbeats (see GET /v1/admin/workers)
+    workers: usize,
+    stale_workers: Vec<Uuid>,
+}
+
+pub async fn get_diagnostics(State(state): State<AppState>) -> Result<Json<Diagnostics>, ApiError> {
+    let workers = fetch_workers(&state).await?;
+    Ok(Json(Diagnostics {
+        in_flight_mutations: state.in_flight.count(),
+        in_flight: state.in_flight.operations(),
+        ready: state.readiness.is_ready(),
+        slow_queries_total: state.slow_queries.totals(),
+        event_backlog: state.backpressure.depth(),
+        event_backlog_engaged: state.backpressure.is_engaged(),
+        workers: workers.len(),
+        stale_workers: workers
+            .iter()
+            .filter(|w| w.stale)
+            .map(|w| w.instance_id)
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_idle_reports_stragglers_after_deadline() {
+        let in_flight = InFlight::default();
+        assert!(in_flight.wait_idle(Duration::from_secs(1)).await.is_ok());
+
+        let guard = REQUEST_ID.sync_scope("req_1".to_string(), || {
+            in_flight.track("confirm payment_intent pi_1")
+        });
+        let _background = in_flight.track("expire payment_intents");
+        assert_eq!(in_flight.count(), 2);
+        assert_eq!(
+            in_flight.wait_idle(Duration::from_secs(1)).await,
+            Err(vec![
+                InFlightOperation {
+                    operation: "confirm payment_intent pi_1".to_string(),
+                    request_id: Some("req_1".to_string()),
+                },
+                InFlightOperation {
+                    operation: "expire payment_intents".to_string(),
+                    request_id: None,
+                },
+            ])
+        );
+
+        drop(guard);
+        assert_eq!(in_flight.operations(), ["expire payment_intents"]);
+    }
+}
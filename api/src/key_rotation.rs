@@ -0,0 +1,133 @@
+use mini_stripe_types::crypto::{
+    EncryptedColumn, KeyRing, WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE, WEBHOOK_ENDPOINT_SECRET,
+};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+// Rows re-encrypted per statement, so rotation never holds many row locks at once
+pub const BATCH_SIZE: i64 = 500;
+
+// A column `rotate` can re-encrypt. Identifiers are spliced into SQL, so only these are accepted.
+struct Target {
+    table: &'static str,
+    column: &'static str,
+    encrypted: EncryptedColumn,
+}
+
+const TARGETS: &[Target] = &[
+    Target {
+        table: "webhook_endpoints",
+        column: "secret",
+        encrypted: WEBHOOK_ENDPOINT_SECRET,
+    },
+    Target {
+        table: "webhook_endpoints",
+        column: "client_certificate",
+        encrypted: WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE,
+    },
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum RotateError {
+    #[error(
+        "unknown encrypted column `{column}`; expected one of: {}",
+        column_names()
+    )]
+    UnknownColumn { column: String },
+    #[error("ENCRYPTION_KEYS is empty: there is no key to rotate to")]
+    NoEncryptionKey,
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+fn column_names() -> String {
+    TARGETS
+        .iter()
+        .map(|t| t.encrypted.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RotationReport {
+    // Re-encrypted under the encryption key (including plaintext from before encryption)
+    pub rotated: u64,
+    // Written by someone else between our read and update; whoever wrote it used their own ring
+    pub changed_meanwhile: u64,
+    // Under a key this ring doesn't have, or corrupt; left as they are
+    pub undecryptable: Vec<Uuid>,
+}
+
+// Re-encrypts every value of `column` (`table.column`) that isn't under the ring's encryption
+// key, in batches of `batch_size` rows in id order. Each row is updated only if it still holds
+// the value that was read, so a concurrent write is never overwritten. Safe to run again.
+pub async fn run(
+    db: &PgPool,
+    ring: &KeyRing,
+    column: &str,
+    batch_size: i64,
+) -> Result<RotationReport, RotateError> {
+    let target = TARGETS
+        .iter()
+        .find(|t| t.encrypted.name() == column)
+        .ok_or_else(|| RotateError::UnknownColumn {
+            column: column.to_string(),
+        })?;
+    let key_id = ring
+        .encryption_key_id()
+        .ok_or(RotateError::NoEncryptionKey)?;
+
+    let (table, col) = (target.table, target.column);
+    let select = format!(
+        "SELECT id, {col} AS value FROM {table}
+         WHERE id > $1 AND NOT starts_with({col}, $2)
+         ORDER BY id
+         LIMIT $3"
+    );
+    let update = format!(
+        "UPDATE {table} AS t SET {col} = v.new
+         FROM unnest($1::uuid[], $2::text[], $3::text[]) AS v(id, old, new)
+         WHERE t.id = v.id AND t.{col} = v.old"
+    );
+    let current = format!("enc:{key_id}:");
+
+    let mut report = RotationReport::default();
+    let mut after = Uuid::nil();
+    loop {
+        let rows = sqlx::query(&select)
+            .bind(after)
+            .bind(&current)
+            .bind(batch_size)
+            .fetch_all(db)
+            .await?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after = last.try_get("id")?;
+
+        let (mut ids, mut olds, mut news) = (Vec::new(), Vec::new(), Vec::new());
+        for row in &rows {
+            let id: Uuid = row.try_get("id")?;
+            let old: String = row.try_get("value")?;
+            match target.encrypted.decrypt(ring, &old) {
+                Ok(plaintext) => {
+                    news.push(target.encrypted.encrypt(ring, &plaintext));
+                    ids.push(id);
+                    olds.push(old);
+                }
+                Err(_) => report.undecryptable.push(id),
+            }
+        }
+
+        let updated = sqlx::query(&update)
+            .bind(&ids)
+            .bind(&olds)
+            .bind(&news)
+            .execute(db)
+            .await?
+            .rows_affected();
+        report.rotated += updated;
+        report.changed_meanwhile += ids.len() as u64 - updated;
+    }
+    Ok(report)
+}
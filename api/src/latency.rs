@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::headers::require_ascii_header;
+
+pub const OVERRIDE_HEADER: &str = "X-Simulated-Latency-Ms";
+
+// Simulated acquirer latency: either fixed (min == max) or sampled per request from min..=max
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimulatedLatency {
+    min_ms: u64,
+    max_ms: u64,
+}
+
+impl SimulatedLatency {
+    pub fn fixed(ms: u64) -> Self {
+        Self {
+            min_ms: ms,
+            max_ms: ms,
+        }
+    }
+
+    // Accepts "250" or "100..500"
+    pub fn parse(s: &str) -> Result<Self, &'static str> {
+        let s = s.trim();
+
+        let Some((min, max)) = s.split_once("..") else {
+            let ms = s.parse().map_err(|_| "latency must be a number of ms")?;
+            return Ok(Self::fixed(ms));
+        };
+
+        let min_ms: u64 = min.trim().parse().map_err(|_| "invalid latency range")?;
+        let max_ms: u64 = max.trim().parse().map_err(|_| "invalid latency range")?;
+        if min_ms > max_ms {
+            return Err("latency range min must be <= max");
+        }
+
+        Ok(Self { min_ms, max_ms })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.max_ms == 0
+    }
+
+    pub fn sample(&self) -> Duration {
+        if self.min_ms == self.max_ms {
+            return Duration::from_millis(self.min_ms);
+        }
+        Duration::from_millis(rand::random_range(self.min_ms..=self.max_ms))
+    }
+}
+
+// Per-request override header wins over config, but is only honoured in sandbox mode
+pub fn resolve(config: &Config, headers: &HeaderMap) -> Result<Option<SimulatedLatency>, ApiError> {
+    let Some(value) = require_ascii_header(headers, OVERRIDE_HEADER, "parameter_invalid")? else {
+        return Ok(config.simulated_latency);
+    };
+
+    if !config.sandbox_mode {
+        return Err(ApiError::bad_request(
+            "sandbox_only",
+            format!("{OVERRIDE_HEADER} is only allowed in sandbox mode"),
+        ));
+    }
+
+    let latency = SimulatedLatency::parse(value)
+        .map_err(|msg| ApiError::bad_request("parameter_invalid", msg))?;
+
+    Ok(Some(latency).filter(|l| !l.is_zero()))
+}
+
+// Sleeps without blocking the runtime and returns how much latency was injected
+pub async fn inject(latency: Option<SimulatedLatency>) -> Duration {
+    let Some(latency) = latency else {
+        return Duration::ZERO;
+    };
+
+    let delay = latency.sample();
+    tokio::time::sleep(delay).await;
+    delay
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[test]
+    fn parse_fixed_and_range() {
+        assert_eq!(
+            SimulatedLatency::parse("250").unwrap(),
+            SimulatedLatency::fixed(250)
+        );
+        assert_eq!(
+            SimulatedLatency::parse("100..500").unwrap(),
+            SimulatedLatency {
+                min_ms: 100,
+                max_ms: 500
+            }
+        );
+        assert!(SimulatedLatency::parse("500..100").is_err());
+        assert!(SimulatedLatency::parse("abc").is_err());
+    }
+
+    #[test]
+    fn sample_stays_in_range() {
+        let latency = SimulatedLatency::parse("10..20").unwrap();
+        for _ in 0..100 {
+            let d = latency.sample();
+            assert!(d >= Duration::from_millis(10) && d <= Duration::from_millis(20));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inject_sleeps_for_configured_latency() {
+        let start = Instant::now();
+        let injected = inject(Some(SimulatedLatency::fixed(250))).await;
+
+        assert_eq!(injected, Duration::from_millis(250));
+        assert_eq!(start.elapsed(), Duration::from_millis(250));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inject_adds_nothing_when_unset() {
+        let start = Instant::now();
+        let injected = inject(None).await;
+
+        assert_eq!(injected, Duration::ZERO);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn override_header_rejected_outside_sandbox() {
+        let mut headers = HeaderMap::new();
+        headers.insert(OVERRIDE_HEADER, "100".parse().unwrap());
+
+        let err = resolve(&Config::default(), &headers).unwrap_err();
+        assert_eq!(err.code, "sandbox_only");
+
+        let config = Config {
+            sandbox_mode: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            resolve(&config, &headers).unwrap(),
+            Some(SimulatedLatency::fixed(100))
+        );
+
+        headers.insert(OVERRIDE_HEADER, "0".parse().unwrap());
+        assert_eq!(resolve(&config, &headers).unwrap(), None);
+    }
+}
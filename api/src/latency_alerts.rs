@@ -0,0 +1,364 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use mini_stripe_types::events::{SERVICE_LATENCY_DEGRADED, SERVICE_LATENCY_RECOVERED};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::events_outbox::{EventError, insert_event};
+use crate::routes;
+use crate::state::AppState;
+
+// Durations kept per route per window. A busier window keeps its most recent requests.
+const RING_CAPACITY: usize = 2048;
+
+// When a route's p95 counts as degraded. Windows are tumbling: each one is closed by the
+// background task (or a test) and starts empty.
+#[derive(Clone, Debug)]
+pub struct LatencyAlerting {
+    pub window: Duration,
+    pub p95_threshold: Duration,
+    // Degraded after this many breaching windows in a row, recovered after as many healthy ones
+    pub consecutive_windows: u32,
+    // Least time between a recovery and the next degraded event for the same route
+    pub cooldown: Duration,
+}
+
+// Data of service.latency_degraded and service.latency_recovered events
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LatencyAlert {
+    #[serde(skip)]
+    pub event_type: &'static str,
+    pub route: String,
+    pub threshold_ms: u64,
+    // Stats of the window that tipped the route over (or back)
+    pub p95_ms: u64,
+    pub max_ms: u64,
+    pub requests: usize,
+    pub window_started_at: DateTime<Utc>,
+    pub window_ended_at: DateTime<Utc>,
+    pub degraded_since: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct RouteLatency {
+    samples: VecDeque<Duration>,
+    // Consecutive breaching windows while healthy, consecutive healthy ones while degraded
+    streak: u32,
+    degraded_since: Option<DateTime<Utc>>,
+    recovered_at: Option<DateTime<Utc>>,
+    last_p95: Option<Duration>,
+}
+
+struct WindowStats {
+    p95: Duration,
+    max: Duration,
+    requests: usize,
+}
+
+impl RouteLatency {
+    fn push(&mut self, elapsed: Duration) {
+        if self.samples.len() == RING_CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(elapsed);
+    }
+
+    // Empties the ring; None for a window without traffic
+    fn take_stats(&mut self) -> Option<WindowStats> {
+        let mut samples: Vec<Duration> = self.samples.drain(..).collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let rank = (samples.len() * 95).div_ceil(100);
+        Some(WindowStats {
+            p95: samples[rank - 1],
+            max: samples[samples.len() - 1],
+            requests: samples.len(),
+        })
+    }
+}
+
+// Per-route request durations. The request path only locks its own route's ring; the map lock
+// is held just long enough to find it.
+#[derive(Clone, Default)]
+pub struct LatencyWatch {
+    routes: Arc<Mutex<HashMap<String, Arc<Mutex<RouteLatency>>>>>,
+    window_started_at: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+impl LatencyWatch {
+    pub fn record(&self, route: &str, elapsed: Duration) {
+        let ring = {
+            let mut routes = self.routes.lock().unwrap();
+            match routes.get(route) {
+                Some(ring) => ring.clone(),
+                None => routes.entry(route.to_string()).or_default().clone(),
+            }
+        };
+        ring.lock().unwrap().push(elapsed);
+    }
+
+    // Ends the current window at `now` and returns the alerts it raised. A window without
+    // traffic neither breaches nor recovers.
+    pub fn close_window(
+        &self,
+        now: DateTime<Utc>,
+        alerting: &LatencyAlerting,
+    ) -> Vec<LatencyAlert> {
+        let started_at = self
+            .window_started_at
+            .lock()
+            .unwrap()
+            .replace(now)
+            .unwrap_or_else(|| now - alerting.window);
+        let routes: Vec<_> = {
+            let routes = self.routes.lock().unwrap();
+            routes
+                .iter()
+                .map(|(r, ring)| (r.clone(), ring.clone()))
+                .collect()
+        };
+
+        let mut alerts = Vec::new();
+        for (route, ring) in routes {
+            let mut ring = ring.lock().unwrap();
+            let Some(stats) = ring.take_stats() else {
+                continue;
+            };
+            ring.last_p95 = Some(stats.p95);
+
+            let breached = stats.p95 > alerting.p95_threshold;
+            let degraded = ring.degraded_since.is_some();
+            ring.streak = if breached != degraded {
+                ring.streak + 1
+            } else {
+                0
+            };
+            if ring.streak < alerting.consecutive_windows {
+                continue;
+            }
+
+            let alert = |event_type, degraded_since, recovered_at| LatencyAlert {
+                event_type,
+                route: route.clone(),
+                threshold_ms: alerting.p95_threshold.as_millis() as u64,
+                p95_ms: stats.p95.as_millis() as u64,
+                max_ms: stats.max.as_millis() as u64,
+                requests: stats.requests,
+                window_started_at: started_at,
+                window_ended_at: now,
+                degraded_since,
+                recovered_at,
+            };
+            match ring.degraded_since {
+                Some(since) => {
+                    alerts.push(alert(SERVICE_LATENCY_RECOVERED, since, Some(now)));
+                    ring.degraded_since = None;
+                    ring.recovered_at = Some(now);
+                    ring.streak = 0;
+                }
+                None => {
+                    let cooling = ring.recovered_at.is_some_and(|at| {
+                        now - at < chrono::Duration::from_std(alerting.cooldown).unwrap_or_default()
+                    });
+                    // Keeps the streak, so a degradation that outlasts the cooldown still alerts
+                    if cooling {
+                        continue;
+                    }
+                    alerts.push(alert(SERVICE_LATENCY_DEGRADED, now, None));
+                    ring.degraded_since = Some(now);
+                    ring.streak = 0;
+                }
+            }
+        }
+        alerts
+    }
+
+    // route -> (degraded, p95 of its last window with traffic), for metrics
+    pub fn snapshot(&self) -> BTreeMap<String, (bool, Option<Duration>)> {
+        let routes = self.routes.lock().unwrap();
+        routes
+            .iter()
+            .map(|(route, ring)| {
+                let ring = ring.lock().unwrap();
+                (
+                    route.clone(),
+                    (ring.degraded_since.is_some(), ring.last_p95),
+                )
+            })
+            .collect()
+    }
+}
+
+// Closes the window and writes its alerts to the outbox. No-op when alerting is off.
+pub async fn flush(state: &AppState, now: DateTime<Utc>) -> Result<usize, EventError> {
+    let Some(alerting) = &state.config.latency_alerting else {
+        return Ok(0);
+    };
+
+    let alerts = state.latency_watch.close_window(now, alerting);
+    for alert in &alerts {
+        let payload = serde_json::to_value(alert).expect("latency alerts serialize");
+        insert_event(&state.db, alert.event_type, payload).await?;
+        warn!(
+            event_type = alert.event_type,
+            route = %alert.route,
+            p95_ms = alert.p95_ms,
+            threshold_ms = alert.threshold_ms,
+            "route latency over threshold"
+        );
+    }
+    Ok(alerts.len())
+}
+
+// Closes a window every config.latency_alerting.window until the process exits
+pub async fn run(state: AppState) {
+    let Some(window) = state.config.latency_alerting.as_ref().map(|a| a.window) else {
+        return;
+    };
+    let mut interval = tokio::time::interval(window);
+    // The first tick is immediate; the first window should be a full one
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = flush(&state, Utc::now()).await {
+            warn!(error = %e, "latency alert flush failed");
+        }
+    }
+}
+
+// Middleware, only layered when config.latency_alerting is set. Unmatched paths aren't routes,
+// and long-polls are slow on purpose.
+pub async fn observe(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(route) = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .filter(|route| route != routes::PAYMENT_INTENT_WAIT)
+    else {
+        return next.run(req).await;
+    };
+
+    let started = Instant::now();
+    let res = next.run(req).await;
+    state.latency_watch.record(&route, started.elapsed());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = "/v1/payment_intents/{id}/confirm";
+
+    fn alerting() -> LatencyAlerting {
+        LatencyAlerting {
+            window: Duration::from_secs(60),
+            p95_threshold: Duration::from_millis(500),
+            consecutive_windows: 3,
+            cooldown: Duration::from_secs(600),
+        }
+    }
+
+    // One window of 20 requests at `ms` each, closed `minute` minutes after the epoch
+    fn window(watch: &LatencyWatch, minute: i64, ms: u64) -> Vec<LatencyAlert> {
+        for _ in 0..20 {
+            watch.record(ROUTE, Duration::from_millis(ms));
+        }
+        watch.close_window(
+            DateTime::UNIX_EPOCH + chrono::Duration::minutes(minute),
+            &alerting(),
+        )
+    }
+
+    #[test]
+    fn p95_ignores_the_slowest_five_percent() {
+        let mut ring = RouteLatency::default();
+        for ms in 1..=100 {
+            ring.push(Duration::from_millis(ms));
+        }
+        let stats = ring.take_stats().unwrap();
+        assert_eq!(stats.p95, Duration::from_millis(95));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        assert!(ring.take_stats().is_none());
+    }
+
+    #[test]
+    fn an_incident_raises_one_degraded_and_one_recovered() {
+        let watch = LatencyWatch::default();
+        let mut alerts = Vec::new();
+        let mut minute = 0;
+        // Healthy, a blip too short to alert, a long incident, then recovery
+        for ms in [
+            100, 100, 900, 900, 100, 900, 900, 900, 900, 900, 100, 100, 100, 100, 100,
+        ] {
+            minute += 1;
+            alerts.extend(window(&watch, minute, ms));
+        }
+
+        let types: Vec<_> = alerts.iter().map(|a| a.event_type).collect();
+        assert_eq!(types, [SERVICE_LATENCY_DEGRADED, SERVICE_LATENCY_RECOVERED]);
+
+        let degraded = &alerts[0];
+        assert_eq!(degraded.route, ROUTE);
+        assert_eq!(degraded.p95_ms, 900);
+        assert_eq!(degraded.requests, 20);
+        assert_eq!(
+            degraded.window_ended_at,
+            DateTime::UNIX_EPOCH + chrono::Duration::minutes(8)
+        );
+        let recovered = &alerts[1];
+        assert_eq!(recovered.degraded_since, degraded.window_ended_at);
+        assert_eq!(
+            recovered.recovered_at,
+            Some(DateTime::UNIX_EPOCH + chrono::Duration::minutes(13))
+        );
+        assert_eq!(
+            watch.snapshot()[ROUTE],
+            (false, Some(Duration::from_millis(100)))
+        );
+    }
+
+    #[test]
+    fn a_relapse_inside_the_cooldown_waits_for_it_to_pass() {
+        let watch = LatencyWatch::default();
+        let mut minute = 0;
+        let mut run = |ms: u64, windows: usize| {
+            let mut raised = Vec::new();
+            for _ in 0..windows {
+                minute += 1;
+                for alert in window(&watch, minute, ms) {
+                    raised.push((minute, alert.event_type));
+                }
+            }
+            raised
+        };
+
+        assert_eq!(run(900, 3), [(3, SERVICE_LATENCY_DEGRADED)]);
+        assert_eq!(run(100, 3), [(6, SERVICE_LATENCY_RECOVERED)]);
+        // Recovered at minute 6 with a 10 minute cooldown: nothing until minute 16
+        assert!(run(900, 9).is_empty());
+        assert_eq!(run(900, 1), [(16, SERVICE_LATENCY_DEGRADED)]);
+    }
+
+    #[test]
+    fn quiet_windows_change_nothing() {
+        let watch = LatencyWatch::default();
+        window(&watch, 1, 900);
+        window(&watch, 2, 900);
+        let quiet = DateTime::UNIX_EPOCH + chrono::Duration::minutes(3);
+        assert!(watch.close_window(quiet, &alerting()).is_empty());
+        assert_eq!(window(&watch, 4, 900).len(), 1);
+    }
+}
@@ -1,5 +1,49 @@
+pub mod anonymize;
 pub mod app;
+pub mod audit_log;
+pub mod backpressure;
+pub mod balance;
+pub mod caching;
+pub mod charges;
+pub mod client_certificates;
+pub mod config;
+pub mod currency;
+pub mod customers;
+pub mod db;
+pub mod deadline;
+pub mod error;
+pub mod event_export;
 pub mod events_outbox;
+pub mod examples;
+pub mod expiry;
+pub mod faults;
+pub mod headers;
+pub mod idempotency;
+pub mod idempotency_cleanup;
+pub mod in_flight;
+pub mod key_rotation;
+pub mod latency;
+pub mod latency_alerts;
+pub mod metrics;
+pub mod pagination;
 pub mod payment_intents;
+pub mod payment_methods;
+pub mod payout_settlement;
+pub mod payouts;
+pub mod permissions;
+pub mod processor;
+pub mod refunds;
+pub mod request_capture;
+pub mod response_signing;
+pub mod routes;
+pub mod sandbox;
+pub mod seed;
+pub mod services;
+pub mod simulation;
+pub mod slow_queries;
 pub mod state;
+pub mod status_changes;
+pub mod warmup;
 pub mod webhook_endpoints;
+pub mod webhook_sync;
+pub mod workers;
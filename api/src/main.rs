@@ -1,26 +1,216 @@
-use sqlx::PgPool;
+use sqlx::postgres::PgPoolOptions;
+use tracing::{error, warn};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::LevelFilter;
 
-use api::state::AppState;
+use api::{
+    anonymize,
+    backpressure::{self, Backpressure},
+    config::Config,
+    expiry,
+    faults::Faults,
+    idempotency_cleanup,
+    in_flight::InFlight,
+    key_rotation,
+    latency_alerts::{self, LatencyWatch},
+    metrics::Metrics,
+    payout_settlement, request_capture,
+    sandbox::EchoReceiver,
+    slow_queries::SlowQueries,
+    state::AppState,
+    status_changes::StatusChanges,
+    warmup,
+};
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
 
+    // Warnings by default: that is where background failures and slow queries are logged
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::WARN.into())
+                .from_env_lossy(),
+        )
+        .init();
+
     let database_url =
         std::env::var("DATABASE_URL").expect("DATABASE_URL must be set (check .env)");
 
-    let db = PgPool::connect(&database_url)
+    // `mini-stripe-api anonymize <original domain>...`: rewrite PII in a staging copy and exit
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("anonymize") {
+        let db = PgPoolOptions::new()
+            .connect(&database_url)
+            .await
+            .expect("failed to connect to Postgres");
+        let confirm = std::env::var("CONFIRM_ANONYMIZE").ok();
+        match anonymize::run(&db, confirm.as_deref(), &args[1..]).await {
+            Ok(report) => {
+                for (table, rows) in report.tables {
+                    println!("{table}: {rows} rows");
+                }
+                println!("anonymized; no original domains found");
+            }
+            Err(e) => {
+                error!(error = %e, "anonymize failed");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // `mini-stripe-api rotate <table.column>`: re-encrypt a secret column under ENCRYPTION_KEY_ID
+    if args.first().map(String::as_str) == Some("rotate") {
+        let db = PgPoolOptions::new()
+            .connect(&database_url)
+            .await
+            .expect("failed to connect to Postgres");
+        let config = Config::from_env();
+        let column = args.get(1).map(String::as_str).unwrap_or_default();
+        match key_rotation::run(
+            &db,
+            &config.encryption_keys,
+            column,
+            key_rotation::BATCH_SIZE,
+        )
+        .await
+        {
+            Ok(report) => {
+                println!(
+                    "{column}: {} rotated, {} changed meanwhile",
+                    report.rotated, report.changed_meanwhile
+                );
+                if !report.undecryptable.is_empty() {
+                    error!(
+                        column,
+                        rows = report.undecryptable.len(),
+                        ids = ?report.undecryptable,
+                        "rows could not be decrypted with ENCRYPTION_KEYS"
+                    );
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                error!(column, error = %e, "rotate failed");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let config = Config::from_env();
+
+    let db = PgPoolOptions::new()
+        .min_connections(config.db_min_connections)
+        .connect(&database_url)
         .await
         .expect("failed to connect to Postgres");
 
-    let state = AppState { db };
+    // Connected lazily so a replica that is down doesn't stop the API starting
+    let read_db = match std::env::var("READ_DATABASE_URL") {
+        Ok(url) => PgPoolOptions::new()
+            .connect_lazy(&url)
+            .expect("READ_DATABASE_URL must be a Postgres URL"),
+        Err(_) => db.clone(),
+    };
+
+    let readiness = if config.warmup_enabled {
+        warmup::Readiness::not_ready()
+    } else {
+        warmup::Readiness::ready()
+    };
+
+    let state = AppState {
+        db,
+        read_db,
+        config,
+        echo: EchoReceiver::default(),
+        readiness,
+        in_flight: InFlight::default(),
+        slow_queries: SlowQueries::default(),
+        metrics: Metrics::default(),
+        backpressure: Backpressure::default(),
+        latency_watch: LatencyWatch::default(),
+        status_changes: StatusChanges::default(),
+        faults: Faults::default(),
+    };
+
+    // Serve straight away; /readyz stays 503 until the warm-up finishes or times out
+    if state.config.warmup_enabled {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let connections = state.config.db_min_connections.max(1);
+            warmup::run(&state, connections, state.config.warmup_timeout).await;
+            println!("warm-up done, ready");
+        });
+    }
+
+    // Captures are short-lived debugging aids: drop expired ones every few minutes
+    if state.config.request_capture_enabled {
+        let db = state.db.clone();
+        let ttl = state.config.request_capture_ttl;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(300));
+            loop {
+                interval.tick().await;
+                if let Err(e) = request_capture::prune_request_captures(&db, ttl).await {
+                    warn!(error = %e, "request capture pruning failed");
+                }
+            }
+        });
+    }
 
-    let app = api::app::build_app(state);
+    // The backlog depth is sampled in the background, never per request
+    if state.config.event_backlog_high_water.is_some() {
+        tokio::spawn(backpressure::run(state.clone()));
+    }
+
+    // Expired idempotency keys are deleted a batch at a time, resuming wherever the last run
+    // stopped
+    if state.config.idempotency_key_ttl.is_some() {
+        tokio::spawn(idempotency_cleanup::run_forever(state.clone()));
+    }
+
+    // Intents left requires_confirmation past their expires_at are canceled as `expired`
+    tokio::spawn(expiry::run_forever(state.clone()));
+
+    // Pending payouts past their arrival_date are marked paid or failed
+    tokio::spawn(payout_settlement::run_forever(state.clone()));
+
+    if state.config.latency_alerting.is_some() {
+        tokio::spawn(latency_alerts::run(state.clone()));
+    }
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
         .await
         .expect("failed to bind to port 3000");
 
     println!("API listening on http://localhost:3000");
-    axum::serve(listener, app).await.expect("server error");
+    api::app::serve(listener, state, shutdown_signal())
+        .await
+        .expect("server error");
+    println!("shut down");
+}
+
+// Ctrl-C locally, SIGTERM from an orchestrator
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    println!("shutting down, waiting for in-flight payment mutations");
 }
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+
+use crate::state::AppState;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Business counters, bumped by the services after their transaction commits. Labels are limited
+// to currency (already validated to 3 letters) and status, never ids, so cardinality stays small.
+pub const PAYMENTS_CREATED: &str = "payments_created";
+pub const PAYMENTS_SUCCEEDED: &str = "payments_succeeded";
+pub const PAYMENTS_FAILED: &str = "payments_failed";
+pub const GROSS_VOLUME: &str = "payments_gross_volume_minor";
+// Bumped by db::run_tx_with_retry, labelled by isolation level
+pub const TX_RETRIES: &str = "db_transaction_retries";
+pub const TX_RETRIES_EXHAUSTED: &str = "db_transaction_retries_exhausted";
+// Sandbox fault rules that fired, labelled by fault type (see faults.rs)
+pub const FAULTS_INJECTED: &str = "faults_injected";
+// Expired idempotency keys deleted, and cleanup runs finished (see idempotency_cleanup.rs); their
+// ratio is the rows deleted per run
+pub const IDEMPOTENCY_KEYS_DELETED: &str = "idempotency_keys_deleted";
+pub const IDEMPOTENCY_CLEANUP_RUNS: &str = "idempotency_cleanup_runs";
+// Latency slept by latency::inject, labelled by the operation that slept (a summary, not a counter)
+pub const SIMULATED_LATENCY: &str = "simulated_latency_seconds";
+
+const HELP: &[(&str, &str)] = &[
+    (PAYMENTS_CREATED, "Payment intents created"),
+    (PAYMENTS_SUCCEEDED, "Payment intents confirmed successfully"),
+    (
+        PAYMENTS_FAILED,
+        "Confirms that failed, by the intent's resulting status",
+    ),
+    (
+        GROSS_VOLUME,
+        "Amount of succeeded payment intents, in minor units",
+    ),
+    (
+        TX_RETRIES,
+        "Transactions retried after a serialization failure or deadlock",
+    ),
+    (
+        TX_RETRIES_EXHAUSTED,
+        "Transactions that still conflicted after every retry",
+    ),
+    (
+        FAULTS_INJECTED,
+        "Requests failed or slowed by a sandbox fault rule",
+    ),
+    (
+        IDEMPOTENCY_KEYS_DELETED,
+        "Expired idempotency keys deleted by the cleanup",
+    ),
+    (
+        IDEMPOTENCY_CLEANUP_RUNS,
+        "Idempotency key cleanup runs that reached the end of the expired keys",
+    ),
+    (
+        SIMULATED_LATENCY,
+        "Simulated acquirer latency injected into payment mutations",
+    ),
+];
+
+type Labels = Vec<(&'static str, String)>;
+type Series<T> = BTreeMap<&'static str, BTreeMap<Labels, T>>;
+
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Mutex<Series<u64>>>,
+    // Count and sum of each observed series
+    summaries: Arc<Mutex<Series<(u64, Duration)>>>,
+}
+
+impl Metrics {
+    pub fn add(&self, name: &'static str, labels: &[(&'static str, &str)], value: u64) {
+        let labels = labels.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .entry(labels)
+            .or_default() += value;
+    }
+
+    pub fn inc(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    pub fn observe(&self, name: &'static str, labels: &[(&'static str, &str)], value: Duration) {
+        let labels = labels.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        let mut summaries = self.summaries.lock().unwrap();
+        let (count, sum) = summaries
+            .entry(name)
+            .or_default()
+            .entry(labels)
+            .or_default();
+        *count += 1;
+        *sum += value;
+    }
+
+    // Current value of one series (0 if it was never touched)
+    pub fn get(&self, name: &str, labels: &[(&'static str, &str)]) -> u64 {
+        let labels: Labels = labels.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        self.counters
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|series| series.get(&labels))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn render_counters(&self, out: &mut String) {
+        for (name, series) in self.counters.lock().unwrap().iter() {
+            if let Some((_, help)) = HELP.iter().find(|(n, _)| n == name) {
+                writeln!(out, "# HELP {name} {help}").unwrap();
+            }
+            writeln!(out, "# TYPE {name} counter").unwrap();
+            for (labels, value) in series {
+                writeln!(out, "{name}_total{} {value}", render_labels(labels)).unwrap();
+            }
+        }
+    }
+
+    fn render_summaries(&self, out: &mut String) {
+        for (name, series) in self.summaries.lock().unwrap().iter() {
+            if let Some((_, help)) = HELP.iter().find(|(n, _)| n == name) {
+                writeln!(out, "# HELP {name} {help}").unwrap();
+            }
+            writeln!(out, "# TYPE {name} summary").unwrap();
+            for (labels, (count, sum)) in series {
+                let labels = render_labels(labels);
+                writeln!(out, "{name}_count{labels} {count}").unwrap();
+                writeln!(out, "{name}_sum{labels} {}", sum.as_secs_f64()).unwrap();
+            }
+        }
+    }
+}
+
+fn render_labels(labels: &[(&str, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+// OpenMetrics text: the business counters plus process-level ones
+pub async fn get_metrics(State(state): State<AppState>) -> Response {
+    let mut out = String::new();
+    state.metrics.render_counters(&mut out);
+    state.metrics.render_summaries(&mut out);
+
+    writeln!(
+        out,
+        "# HELP slow_queries Statements over the slow query threshold"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slow_queries counter").unwrap();
+    let mut slow: Vec<_> = state.slow_queries.totals().into_iter().collect();
+    slow.sort();
+    for (route, count) in slow {
+        let labels = render_labels(&[("route", route)]);
+        writeln!(out, "slow_queries_total{labels} {count}").unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP in_flight_mutations Payment mutations running now"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE in_flight_mutations gauge").unwrap();
+    writeln!(out, "in_flight_mutations {}", state.in_flight.count()).unwrap();
+
+    writeln!(
+        out,
+        "# HELP event_backlog Pending outbox events as last sampled"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE event_backlog gauge").unwrap();
+    writeln!(out, "event_backlog {}", state.backpressure.depth()).unwrap();
+    writeln!(
+        out,
+        "# HELP event_backlog_engaged 1 while mutations are refused for the event backlog"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE event_backlog_engaged gauge").unwrap();
+    writeln!(
+        out,
+        "event_backlog_engaged {}",
+        u8::from(state.backpressure.is_engaged())
+    )
+    .unwrap();
+
+    let latency = state.latency_watch.snapshot();
+    if !latency.is_empty() {
+        writeln!(
+            out,
+            "# HELP route_latency_degraded 1 while a route's p95 is over the latency alert threshold"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE route_latency_degraded gauge").unwrap();
+        for (route, (degraded, _)) in &latency {
+            let labels = render_labels(&[("route", route.clone())]);
+            writeln!(
+                out,
+                "route_latency_degraded{labels} {}",
+                u8::from(*degraded)
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "# HELP route_latency_p95_ms p95 of the route's last latency window with traffic"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE route_latency_p95_ms gauge").unwrap();
+        for (route, (_, p95)) in &latency {
+            if let Some(p95) = p95 {
+                let labels = render_labels(&[("route", route.clone())]);
+                writeln!(out, "route_latency_p95_ms{labels} {}", p95.as_millis()).unwrap();
+            }
+        }
+    }
+
+    out.push_str("# EOF\n");
+
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], out).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_render_as_openmetrics() {
+        let metrics = Metrics::default();
+        metrics.inc(PAYMENTS_CREATED, &[("currency", "gbp")]);
+        metrics.inc(PAYMENTS_CREATED, &[("currency", "gbp")]);
+        metrics.add(GROSS_VOLUME, &[("currency", "eu\"r")], 1500);
+
+        let mut out = String::new();
+        metrics.render_counters(&mut out);
+
+        assert_eq!(
+            out,
+            "# HELP payments_created Payment intents created\n\
+             # TYPE payments_created counter\n\
+             payments_created_total{currency=\"gbp\"} 2\n\
+             # HELP payments_gross_volume_minor Amount of succeeded payment intents, in minor units\n\
+             # TYPE payments_gross_volume_minor counter\n\
+             payments_gross_volume_minor_total{currency=\"eu\\\"r\"} 1500\n"
+        );
+        assert_eq!(metrics.get(PAYMENTS_CREATED, &[("currency", "gbp")]), 2);
+        assert_eq!(metrics.get(PAYMENTS_CREATED, &[("currency", "usd")]), 0);
+    }
+
+    #[test]
+    fn summaries_render_count_and_sum_in_seconds() {
+        let metrics = Metrics::default();
+        let labels = [("operation", "confirm")];
+        metrics.observe(SIMULATED_LATENCY, &labels, Duration::from_millis(250));
+        metrics.observe(SIMULATED_LATENCY, &labels, Duration::from_millis(1500));
+
+        let mut out = String::new();
+        metrics.render_summaries(&mut out);
+
+        assert_eq!(
+            out,
+            "# HELP simulated_latency_seconds Simulated acquirer latency injected into payment mutations\n\
+             # TYPE simulated_latency_seconds summary\n\
+             simulated_latency_seconds_count{operation=\"confirm\"} 2\n\
+             simulated_latency_seconds_sum{operation=\"confirm\"} 1.75\n"
+        );
+    }
+}
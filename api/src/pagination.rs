@@ -0,0 +1,83 @@
+use crate::config::Config;
+use crate::services::DomainError;
+
+// Defaults for config.list_default_limit and config.list_max_limit
+const DEFAULT_LIMIT: i64 = 20;
+const DEFAULT_MAX_LIMIT: i64 = 100;
+
+// The page size a list request gets, echoed back in the list envelope
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageLimit {
+    pub limit: i64,
+    pub max_limit: i64,
+    // What the client asked for when it was over max_limit
+    pub clamped_from: Option<i64>,
+}
+
+impl PageLimit {
+    // A missing limit is the deployment default. One over the cap is clamped to it rather than
+    // refused, so clients can ask for "as many as possible"; zero or negative is still a 400.
+    pub fn resolve(config: &Config, requested: Option<i64>) -> Result<Self, DomainError> {
+        let max_limit = config.list_max_limit.unwrap_or(DEFAULT_MAX_LIMIT);
+        let default = config
+            .list_default_limit
+            .unwrap_or(DEFAULT_LIMIT)
+            .min(max_limit);
+
+        match requested {
+            None => Ok(Self {
+                limit: default,
+                max_limit,
+                clamped_from: None,
+            }),
+            Some(limit) if limit < 1 => Err(DomainError::InvalidParameter(
+                "limit must be at least 1".to_string(),
+            )),
+            Some(limit) => Ok(Self {
+                limit: limit.min(max_limit),
+                max_limit,
+                clamped_from: (limit > max_limit).then_some(limit),
+            }),
+        }
+    }
+
+    // For the envelope's `warnings`
+    pub fn warnings(&self) -> Vec<String> {
+        self.clamped_from
+            .map(|requested| {
+                format!(
+                    "limit {requested} is over the maximum of {}; returned at most {}",
+                    self.max_limit, self.limit
+                )
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_defaults_clamps_and_rejects() {
+        let config = Config::default();
+        let page = PageLimit::resolve(&config, None).unwrap();
+        assert_eq!((page.limit, page.max_limit), (20, 100));
+        assert!(page.warnings().is_empty());
+
+        let page = PageLimit::resolve(&config, Some(500)).unwrap();
+        assert_eq!((page.limit, page.clamped_from), (100, Some(500)));
+        assert_eq!(page.warnings().len(), 1);
+
+        assert!(PageLimit::resolve(&config, Some(0)).is_err());
+        assert!(PageLimit::resolve(&config, Some(-3)).is_err());
+
+        let config = Config {
+            list_default_limit: Some(50),
+            list_max_limit: Some(10),
+            ..Config::default()
+        };
+        assert_eq!(PageLimit::resolve(&config, None).unwrap().limit, 10);
+    }
+}
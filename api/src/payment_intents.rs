@@ -1,423 +1,537 @@
+pub mod storage;
+
 use axum::{
     Json,
-    extract::{Path, State},
-    http::HeaderMap,
-    http::StatusCode,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
 };
-use chrono::Utc;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use mini_stripe_types::error::codes;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
-use crate::events_outbox::insert_event;
+use crate::config::parse_duration;
+use crate::error::ApiError;
+use crate::events_outbox::LatestEventSummary;
+use crate::headers::require_ascii_header;
+use crate::services::payment_intents::{
+    BulkCancelParams, CapturePaymentIntentParams, ConfirmPaymentIntentParams, CreateOutcome,
+    CreatePaymentIntentParams, IdempotencyKey, ListPaymentIntentsParams, PaymentIntentService,
+    UpdatePaymentIntentParams, WaitParams,
+};
 use crate::state::AppState;
+use crate::{deadline, latency, simulation};
+use storage::{PaymentIntent, PaymentIntentTransition};
+
+// `confirm: true` is open to every caller that may create (a `full` key when keys are configured)
+pub use mini_stripe_types::payment_intents::{
+    CapturePaymentIntentRequest, ConfirmPaymentIntentRequest, CreatePaymentIntentRequest,
+    PaymentIntentResponse, PaymentIntentStatus, UpdatePaymentIntentRequest,
+};
+
+// Set on creates (and webhook endpoint syncs) answered from a stored idempotent response
+pub const IDEMPOTENT_REPLAYED_HEADER: HeaderName = HeaderName::from_static("idempotent-replayed");
+// With it on creates: `stored`, or `reconstructed` when the response was rebuilt from the intent
+const IDEMPOTENT_REPLAY_SOURCE_HEADER: HeaderName =
+    HeaderName::from_static("idempotent-replay-source");
+
+// A create answered with the intent that already holds its natural idempotency key
+#[derive(Serialize)]
+pub struct DeduplicatedPaymentIntentResponse {
+    #[serde(flatten)]
+    payment_intent: PaymentIntentResponse,
+    deduplicated: bool,
+}
 
-const IDEMPOTENCY_ENDPOINT: &str = "POST /v1/payment_intents";
+// GET adds a few related-resource summaries so dashboards need one call per intent
+#[derive(Serialize)]
+pub struct PaymentIntentDetailsResponse {
+    #[serde(flatten)]
+    payment_intent: PaymentIntentResponse,
+    latest_event: Option<LatestEventSummary>,
+    // When a payment_intent.succeeded webhook first got a 2xx from any endpoint
+    webhook_acknowledged_at: Option<DateTime<Utc>>,
+    archived: bool,
+}
 
 #[derive(Deserialize)]
-pub struct CreatePaymentIntentRequest {
-    amount: i64,
-    currency: String,
+pub struct ListPaymentIntentsQuery {
+    #[serde(default)]
+    include_archived: bool,
+    // Only this customer's intents
+    customer: Option<Uuid>,
+    limit: Option<i64>,
+    // `next_cursor` from the previous page
+    starting_after: Option<String>,
+    // `prev_cursor` from the next page, to page back towards newer intents
+    ending_before: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct PaymentIntentResponse {
-    id: Uuid,
-    amount: i64,
-    currency: String,
-    status: String,
+// Stripe's list object, plus the cursors and page size
+#[derive(Serialize)]
+pub struct PaymentIntentList {
+    // Always `list`
+    object: &'static str,
+    data: Vec<PaymentIntentListItem>,
+    // More intents in the direction of paging (older ones, or newer ones with ending_before)
+    has_more: bool,
+    next_cursor: Option<String>,
+    prev_cursor: Option<String>,
+    // The page size used and the most a request can get (config.list_default_limit/max_limit)
+    limit: i64,
+    max_limit: i64,
+    // E.g. that the requested limit was clamped
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
 }
 
-fn request_fingerprint(req: &CreatePaymentIntentRequest) -> String {
-    format!(
-        "amount={}&currency={}",
-        req.amount,
-        req.currency.trim().to_lowercase()
-    )
+#[derive(Serialize)]
+pub struct PaymentIntentListItem {
+    #[serde(flatten)]
+    payment_intent: PaymentIntentResponse,
+    created_at: DateTime<Utc>,
+    archived: bool,
+}
+
+#[derive(Deserialize)]
+pub struct WaitForPaymentIntentQuery {
+    // A duration like `30s`, at most 60s; 30s when omitted
+    timeout: Option<String>,
+    // `terminal` (succeeded or canceled) or comma-separated statuses
+    until: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PaymentIntentWaitResponse {
+    #[serde(flatten)]
+    payment_intent: PaymentIntentResponse,
+    // The timeout passed with no status change; the intent is its current state
+    timed_out: bool,
 }
 
-fn validate_create_payment_intent(req: &CreatePaymentIntentRequest) -> Result<(), &'static str> {
-    if req.amount <= 0 {
-        return Err("amount must be > 0");
+#[derive(Deserialize)]
+pub struct BulkCancelRequest {
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    // Intents whose metadata has every one of these pairs
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+    customer: Option<Uuid>,
+    // Resume an earlier call's job instead of starting a new one
+    job_id: Option<Uuid>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct BulkCancelResponse {
+    job_id: Option<Uuid>,
+    dry_run: bool,
+    matched: i64,
+    canceled: i64,
+    skipped: i64,
+    // False until every matched intent has been canceled or skipped; call again with job_id
+    done: bool,
+}
+
+impl From<PaymentIntent> for PaymentIntentListItem {
+    fn from(pi: PaymentIntent) -> Self {
+        Self {
+            created_at: pi.created_at,
+            archived: pi.archived_at.is_some(),
+            payment_intent: PaymentIntentResponse::from(pi),
+        }
     }
-    if req.currency.trim().is_empty() {
-        return Err("currency is required");
+}
+
+impl From<PaymentIntent> for PaymentIntentResponse {
+    fn from(pi: PaymentIntent) -> Self {
+        Self {
+            status: pi.status,
+            id: pi.id,
+            amount: pi.amount,
+            currency: pi.currency,
+            cancellation_reason: pi.cancellation_reason,
+            last_payment_error: pi.last_payment_error,
+            // Only ever written from a string map
+            metadata: serde_json::from_value(pi.metadata).unwrap_or_default(),
+            capture_method: pi.capture_method,
+            amount_received: pi.amount_received,
+            description: pi.description,
+            expires_at: Some(pi.expires_at),
+            payment_method: pi.payment_method,
+            customer: pi.customer_id,
+        }
     }
-    Ok(())
 }
 
 pub async fn create_payment_intent(
     State(state): State<AppState>,
     headers: HeaderMap,
     Json(req): Json<CreatePaymentIntentRequest>,
-) -> Result<(StatusCode, Json<PaymentIntentResponse>), (StatusCode, String)> {
-    if let Err(msg) = validate_create_payment_intent(&req) {
-        return Err((StatusCode::BAD_REQUEST, msg.to_string()));
-    }
+) -> Result<Response, ApiError> {
+    let deadline = deadline::resolve(&state.config, &headers)?;
+    let idempotency_key = idempotency_key(&headers)?;
+
+    let confirm = if req.confirm {
+        Some(ConfirmPaymentIntentParams {
+            latency: latency::resolve(&state.config, &headers)?,
+            simulated_outcome: simulation::resolve(&state.config, &headers)?,
+            payment_method: req.payment_method,
+            deadline: Default::default(),
+        })
+    } else if req.payment_method.is_some() {
+        return Err(ApiError::bad_request(
+            codes::PARAMETER_INVALID,
+            "payment_method needs confirm: true",
+        ));
+    } else {
+        None
+    };
+    let params = CreatePaymentIntentParams {
+        amount: req.amount,
+        currency: req.currency,
+        confirm,
+        metadata: req.metadata,
+        capture_method: req.capture_method,
+        description: req.description,
+        customer: req.customer,
+        deadline,
+    };
+    let outcome = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
+    )
+    .create(params, idempotency_key)
+    .await?;
+
+    let mut response_headers = HeaderMap::new();
+    let response = match outcome {
+        CreateOutcome::Created(response) => (StatusCode::CREATED, Json(response)).into_response(),
+        CreateOutcome::Replayed(response, source) => {
+            response_headers.insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+            response_headers.insert(
+                IDEMPOTENT_REPLAY_SOURCE_HEADER,
+                HeaderValue::from_static(source.as_str()),
+            );
+            (StatusCode::CREATED, Json(response)).into_response()
+        }
+        CreateOutcome::Deduplicated(payment_intent) => Json(DeduplicatedPaymentIntentResponse {
+            payment_intent,
+            deduplicated: true,
+        })
+        .into_response(),
+    };
+
+    Ok((response_headers, response).into_response())
+}
 
-    // Read header
-    let idempotency_key = headers
-        .get("Idempotency-Key")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
-
-    // If no idempotency key keep current behavior
-    if idempotency_key.is_none() {
-        let id = Uuid::new_v4();
-        let status = "requires_confirmation";
-
-        let mut tx = state
-            .db
-            .begin()
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-        sqlx::query!(
-            r#"
-            INSERT INTO payment_intents (id, amount, currency, status)
-            VALUES ($1, $2, $3, $4)
-            "#,
-            id,
-            req.amount,
-            req.currency,
-            status
-        )
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        let response = PaymentIntentResponse {
-            id,
-            amount: req.amount,
-            currency: req.currency,
-            status: status.to_string(),
-        };
+// A key that is sent but can't be read is an error, never a request without idempotency
+pub fn idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, ApiError> {
+    let key = require_ascii_header(
+        headers,
+        "Idempotency-Key",
+        codes::IDEMPOTENCY_KEY_INVALID_ENCODING,
+    )?;
+    Ok(key.map(|s| IdempotencyKey(s.to_string())))
+}
+
+// Default wait when the request has no `timeout`
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
 
-        let payload = serde_json::json!({
-            "payment_intent": {
-                "id": response.id,
-                "amount": response.amount,
-                "currency": response.currency.clone(),
-                "status": response.status.clone()
-            }
-        });
+// Strong validator over the exact response body; HEAD gets the same one since it runs this handler
+fn etag(body: &impl Serialize) -> Result<HeaderValue, ApiError> {
+    let digest = Sha256::digest(serde_json::to_vec(body)?);
+    HeaderValue::from_str(&format!("\"{}\"", hex::encode(&digest[..16])))
+        .map_err(|e| ApiError::internal(format!("bad etag: {e}")))
+}
 
-        insert_event(&mut *tx, "payment_intent.created", payload)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+pub async fn get_payment_intent(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<(HeaderMap, Json<PaymentIntentDetailsResponse>), ApiError> {
+    let (pi, latest_event) = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
+    )
+    .retrieve(id)
+    .await?;
 
-        tx.commit()
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    let response = PaymentIntentDetailsResponse {
+        webhook_acknowledged_at: pi.webhook_acknowledged_at,
+        archived: pi.archived_at.is_some(),
+        payment_intent: PaymentIntentResponse::from(pi),
+        latest_event,
+    };
 
-        return Ok((StatusCode::CREATED, Json(response)));
-    }
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ETAG, etag(&response)?);
 
-    // Idempotent path
-    let key = idempotency_key.unwrap();
-    let req_hash = request_fingerprint(&req);
-
-    let mut tx = state
-        .db
-        .begin()
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-    // Reserve the key if its new
-    // If already used this returns 0 rows
-    let reserved = sqlx::query!(
-        r#"
-        INSERT INTO idempotency_keys (key, endpoint, request_hash, response_body)
-        VALUES ($1, $2, $3, '{}'::jsonb)
-        ON CONFLICT (key, endpoint) DO NOTHING
-        RETURNING key
-        "#,
-        key,
-        IDEMPOTENCY_ENDPOINT,
-        req_hash
+    Ok((headers, Json(response)))
+}
+
+pub async fn list_payment_intent_transitions(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<PaymentIntentTransition>>, ApiError> {
+    let transitions = PaymentIntentService::new(
+        &state.read_db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
     )
-    .fetch_optional(&mut *tx)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-    if reserved.is_some() {
-        // Successfully reserved the key -> create payment intent
-        let id = Uuid::new_v4();
-        let status = "requires_confirmation";
-
-        sqlx::query!(
-            r#"
-            INSERT INTO payment_intents (id, amount, currency, status)
-            VALUES ($1, $2, $3, $4)
-            "#,
-            id,
-            req.amount,
-            req.currency,
-            status
-        )
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        let response = PaymentIntentResponse {
-            id,
-            amount: req.amount,
-            currency: req.currency,
-            status: status.to_string(),
-        };
+    .transitions(id)
+    .await?;
 
-        // Store the response JSON so retries can return the same thing
-        let response_json = serde_json::to_value(&response).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("json error: {e}"),
+    Ok(Json(transitions))
+}
+
+// For clients that can't receive webhooks: one held request instead of polling GET
+pub async fn wait_for_payment_intent(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<WaitForPaymentIntentQuery>,
+) -> Result<(HeaderMap, Json<PaymentIntentWaitResponse>), ApiError> {
+    let timeout = match query.timeout.as_deref() {
+        Some(timeout) => parse_duration(timeout).map_err(|_| {
+            ApiError::bad_request(
+                codes::PARAMETER_INVALID,
+                "timeout must be a duration like `30s`",
             )
-        })?;
-
-        // Server Crash Edge Case: we store payment_intent_id as well as response_body.
-        // If the server crashes after reserving the idempotency key but before writing
-        // the final response_body: retries can reconstruct the response from payment_intents.
-        sqlx::query!(
-            r#"
-            UPDATE idempotency_keys
-            SET response_body = $1, payment_intent_id = $2
-            WHERE key = $3 AND endpoint = $4
-            "#,
-            response_json,
-            id,
-            key,
-            IDEMPOTENCY_ENDPOINT
-        )
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        // Outbox event to record that a new payment intent was created
-        let payload = serde_json::json!({
-            "payment_intent": {
-                "id": response.id,
-                "amount": response.amount,
-                "currency": response.currency.clone(),
-                "status": response.status.clone()
-            }
-        });
-
-        insert_event(&mut *tx, "payment_intent.created", payload)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        tx.commit()
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        return Ok((StatusCode::CREATED, Json(response)));
-    }
+        })?,
+        None => DEFAULT_WAIT_TIMEOUT,
+    };
+    let params = WaitParams {
+        until: query.until,
+        timeout,
+    };
+
+    let outcome = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
+    )
+    .wait(id, params)
+    .await?;
+
+    // Only true at the moment it was sent
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Ok((
+        headers,
+        Json(PaymentIntentWaitResponse {
+            payment_intent: PaymentIntentResponse::from(outcome.payment_intent),
+            timed_out: outcome.timed_out,
+        }),
+    ))
+}
 
-    // Key already exists = fetch stored record
-    let row = sqlx::query!(
-        r#"
-    SELECT request_hash, response_body, payment_intent_id
-    FROM idempotency_keys
-    WHERE key = $1 AND endpoint = $2
-    "#,
-        key,
-        IDEMPOTENCY_ENDPOINT
+pub async fn list_payment_intents(
+    State(state): State<AppState>,
+    Query(query): Query<ListPaymentIntentsQuery>,
+) -> Result<Json<PaymentIntentList>, ApiError> {
+    let params = ListPaymentIntentsParams {
+        include_archived: query.include_archived,
+        customer: query.customer,
+        limit: query.limit,
+        cursor: query.starting_after,
+        ending_before: query.ending_before,
+    };
+    let page = PaymentIntentService::new(
+        &state.read_db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
     )
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-    // If request differs its a conflict
-    if row.request_hash != req_hash {
-        tx.rollback().await.ok();
-        return Err((
-            StatusCode::CONFLICT,
-            "idempotency key reused with different request".to_string(),
-        ));
-    }
+    .list(params)
+    .await?;
+
+    Ok(Json(PaymentIntentList {
+        object: "list",
+        data: page.intents.into_iter().map(Into::into).collect(),
+        has_more: page.has_more,
+        next_cursor: page.next_cursor,
+        prev_cursor: page.prev_cursor,
+        limit: page.limit.limit,
+        max_limit: page.limit.max_limit,
+        warnings: page.limit.warnings(),
+    }))
+}
 
-    // If response_body looks complete return it
-    let looks_complete = row
-        .response_body
-        .get("id")
-        .and_then(|v| v.as_str())
-        .is_some();
-
-    if looks_complete {
-        let response: PaymentIntentResponse =
-            serde_json::from_value(row.response_body).map_err(|e| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("json error: {e}"),
-                )
-            })?;
-
-        tx.commit().await.ok();
-        return Ok((StatusCode::CREATED, Json(response)));
-    }
+// Sandbox only (see permissions::TABLE), like the other clean-up/fixture endpoints
+pub async fn archive_payment_intent(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PaymentIntentListItem>, ApiError> {
+    let pi = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
+    )
+    .archive(id)
+    .await?;
 
-    // Crash fallback: response_body is incomplete: reconstruct using payment_intent_id
-    if let Some(pi_id) = row.payment_intent_id {
-        let pi = sqlx::query!(
-            r#"
-        SELECT id, amount, currency, status
-        FROM payment_intents
-        WHERE id = $1
-        "#,
-            pi_id
-        )
-        .fetch_one(&mut *tx)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        let response = PaymentIntentResponse {
-            id: pi.id,
-            amount: pi.amount,
-            currency: pi.currency,
-            status: pi.status,
-        };
+    Ok(Json(pi.into()))
+}
 
-        // fill response_body so future retries are fast
-        let response_json = serde_json::to_value(&response).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("json error: {e}"),
-            )
-        })?;
-
-        sqlx::query!(
-            r#"
-        UPDATE idempotency_keys
-        SET response_body = $1
-        WHERE key = $2 AND endpoint = $3
-        "#,
-            response_json,
-            key,
-            IDEMPOTENCY_ENDPOINT
-        )
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        tx.commit().await.ok();
-        return Ok((StatusCode::CREATED, Json(response)));
+// Admin clean-up (an `admin` key when keys are configured). Only requires_confirmation intents
+// are ever touched.
+pub async fn bulk_cancel_payment_intents(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<BulkCancelRequest>,
+) -> Result<(HeaderMap, Json<BulkCancelResponse>), ApiError> {
+    let params = BulkCancelParams {
+        created_after: req.created_after,
+        created_before: req.created_before,
+        metadata: req.metadata,
+        customer: req.customer,
+        job_id: req.job_id,
+        dry_run: req.dry_run,
+    };
+    let idempotency_key = idempotency_key(&headers)?;
+    let job = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
+    )
+    .bulk_cancel(params, idempotency_key)
+    .await?;
+
+    let mut response_headers = HeaderMap::new();
+    if job.replayed {
+        response_headers.insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
     }
 
-    // Idempotency record exists but is incomplete in a way we cant recover from
-    tx.rollback().await.ok();
-    return Err((
-        StatusCode::INTERNAL_SERVER_ERROR,
-        "idempotency record exists but has no stored response or payment_intent_id".to_string(),
-    ));
+    Ok((
+        response_headers,
+        Json(BulkCancelResponse {
+            job_id: job.id,
+            dry_run: req.dry_run,
+            matched: job.matched,
+            canceled: job.canceled,
+            skipped: job.skipped,
+            done: job.done,
+        }),
+    ))
 }
 
-pub async fn get_payment_intent(
+pub async fn update_payment_intent(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
-) -> Result<Json<PaymentIntentResponse>, (StatusCode, String)> {
-    let row = sqlx::query!(
-        r#"
-        SELECT id, amount, currency, status
-        FROM payment_intents
-        WHERE id = $1
-        "#,
-        id
+    Json(req): Json<UpdatePaymentIntentRequest>,
+) -> Result<Json<PaymentIntentResponse>, ApiError> {
+    let response = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
     )
-    .fetch_optional(&state.db)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    .update(
+        id,
+        UpdatePaymentIntentParams {
+            amount: req.amount,
+            currency: req.currency,
+            description: req.description,
+            metadata: req.metadata,
+        },
+    )
+    .await?;
 
-    match row {
-        Some(pi) => Ok(Json(PaymentIntentResponse {
-            id: pi.id,
-            amount: pi.amount,
-            currency: pi.currency,
-            status: pi.status,
-        })),
-        None => Err((
-            StatusCode::NOT_FOUND,
-            "payment_intent not found".to_string(),
-        )),
-    }
+    Ok(Json(response))
 }
 
+// The body is optional, so a bare POST confirms without a payment method. Clients have always
+// sent an empty body here, some with a JSON content-type, so empty means none whatever the headers.
 pub async fn confirm_payment_intent(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
-) -> Result<Json<PaymentIntentResponse>, (StatusCode, String)> {
-    let mut tx = state
-        .db
-        .begin()
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-    // Try to update only if in the correct state
-    let updated = sqlx::query!(
-        r#"
-        UPDATE payment_intents
-        SET status = 'succeeded'
-        WHERE id = $1 AND status = 'requires_confirmation'
-        RETURNING id, amount, currency, status
-        "#,
-        id
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<PaymentIntentResponse>, ApiError> {
+    let req: ConfirmPaymentIntentRequest = if body.is_empty() {
+        Default::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| {
+            ApiError::bad_request(codes::PARAMETER_INVALID, format!("invalid body: {e}"))
+        })?
+    };
+    let params = ConfirmPaymentIntentParams {
+        deadline: deadline::resolve(&state.config, &headers)?,
+        latency: latency::resolve(&state.config, &headers)?,
+        simulated_outcome: simulation::resolve(&state.config, &headers)?,
+        payment_method: req.payment_method,
+    };
+
+    let response = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
     )
-    .fetch_optional(&mut *tx)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    .confirm(id, params)
+    .await?;
 
-    if let Some(pi) = updated {
-        let response = PaymentIntentResponse {
-            id: pi.id,
-            amount: pi.amount,
-            currency: pi.currency,
-            status: pi.status,
-        };
-
-        // Outbox event records successful confirmation
-        let payload = serde_json::json!({
-            "payment_intent": {
-                "id": response.id,
-                "amount": response.amount,
-                "currency": response.currency.clone(),
-                "status": response.status.clone()
-            }
-        });
-
-        insert_event(&mut *tx, "payment_intent.succeeded", payload)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        tx.commit()
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-        return Ok(Json(response));
-    }
+    Ok(Json(response))
+}
 
-    // Not updated = not found/invalid state
-    let exists = sqlx::query!(
-        r#"
-        SELECT status
-        FROM payment_intents
-        WHERE id = $1
-        "#,
-        id
+// Completes a manual-capture intent that confirm left at requires_capture. The body is optional
+// (no content-type means none), so a bare POST captures the whole amount.
+pub async fn capture_payment_intent(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    req: Option<Json<CapturePaymentIntentRequest>>,
+) -> Result<Json<PaymentIntentResponse>, ApiError> {
+    let params = CapturePaymentIntentParams {
+        amount_to_capture: req.and_then(|Json(req)| req.amount_to_capture),
+        latency: latency::resolve(&state.config, &headers)?,
+        deadline: deadline::resolve(&state.config, &headers)?,
+    };
+    let response = PaymentIntentService::new(
+        &state.db,
+        &state.config,
+        &state.in_flight,
+        &state.metrics,
+        &state.status_changes,
+        &state.faults,
     )
-    .fetch_optional(&mut *tx)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
-
-    // No state change happened safe to rollback
-    tx.rollback().await.ok();
-
-    match exists {
-        None => Err((
-            StatusCode::NOT_FOUND,
-            "payment_intent not found".to_string(),
-        )),
-        Some(row) => Err((
-            StatusCode::CONFLICT,
-            format!("cannot confirm payment_intent in status '{}'", row.status),
-        )),
-    }
+    .capture(id, params)
+    .await?;
+
+    Ok(Json(response))
 }
 
 #[cfg(test)]
@@ -425,34 +539,39 @@ mod tests {
     use super::*;
 
     #[test]
-    fn validate_rejects_non_positive_amount() {
-        let req = CreatePaymentIntentRequest {
-            amount: 0,
-            currency: "gbp".to_string(),
-        };
-
-        let err = validate_create_payment_intent(&req).unwrap_err();
-        assert_eq!(err, "amount must be > 0");
-    }
-
-    #[test]
-    fn validate_rejects_empty_currency() {
-        let req = CreatePaymentIntentRequest {
-            amount: 2500,
-            currency: "   ".to_string(),
-        };
-
-        let err = validate_create_payment_intent(&req).unwrap_err();
-        assert_eq!(err, "currency is required");
-    }
-
-    #[test]
-    fn validate_accepts_good_input() {
-        let req = CreatePaymentIntentRequest {
-            amount: 2500,
+    fn response_maps_every_field() {
+        let pi = PaymentIntent {
+            id: Uuid::new_v4(),
+            amount: 1234,
             currency: "gbp".to_string(),
+            status: PaymentIntentStatus::Canceled,
+            created_at: chrono::Utc::now(),
+            cancellation_reason: Some("abandoned".to_string()),
+            last_payment_error: None,
+            webhook_acknowledged_at: None,
+            archived_at: None,
+            metadata: serde_json::json!({ "order_id": "o_1" }),
+            natural_key: Some("o_1".to_string()),
+            capture_method: "manual".to_string(),
+            amount_received: 0,
+            description: Some("Room 12".to_string()),
+            expires_at: chrono::Utc::now(),
+            payment_method: Some("pm_card_visa".to_string()),
+            customer_id: Some(Uuid::nil()),
         };
 
-        assert!(validate_create_payment_intent(&req).is_ok());
+        let response = PaymentIntentResponse::from(pi.clone());
+        assert_eq!(response.id, pi.id);
+        assert_eq!(response.amount, 1234);
+        assert_eq!(response.currency, "gbp");
+        assert_eq!(response.status, PaymentIntentStatus::Canceled);
+        assert_eq!(response.cancellation_reason.as_deref(), Some("abandoned"));
+        assert_eq!(response.last_payment_error, None);
+        assert_eq!(response.metadata["order_id"], "o_1");
+        assert_eq!(response.capture_method, "manual");
+        assert_eq!(response.description.as_deref(), Some("Room 12"));
+        assert_eq!(response.expires_at, Some(pi.expires_at));
+        assert_eq!(response.payment_method.as_deref(), Some("pm_card_visa"));
+        assert_eq!(response.customer, Some(Uuid::nil()));
     }
 }
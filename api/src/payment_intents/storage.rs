@@ -0,0 +1,631 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use mini_stripe_types::payment_intents::PaymentIntentStatus;
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct PaymentIntent {
+    pub id: Uuid,
+    pub amount: i64,
+    pub currency: String,
+    pub status: PaymentIntentStatus,
+    pub created_at: DateTime<Utc>,
+    pub cancellation_reason: Option<String>,
+    pub last_payment_error: Option<serde_json::Value>,
+    pub webhook_acknowledged_at: Option<DateTime<Utc>>,
+    pub archived_at: Option<DateTime<Utc>>,
+    pub metadata: serde_json::Value,
+    pub natural_key: Option<String>,
+    pub capture_method: String,
+    pub amount_received: i64,
+    pub description: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub payment_method: Option<String>,
+    pub customer_id: Option<Uuid>,
+}
+
+// One field per payment_intents column. Queries use `SELECT *`/`RETURNING *` with
+// `query_as!`, so adding a column without adding it here (and below) is a compile error.
+struct PaymentIntentRow {
+    id: Uuid,
+    amount: i64,
+    currency: String,
+    status: String,
+    created_at: DateTime<Utc>,
+    cancellation_reason: Option<String>,
+    last_payment_error: Option<serde_json::Value>,
+    webhook_acknowledged_at: Option<DateTime<Utc>>,
+    archived_at: Option<DateTime<Utc>>,
+    metadata: serde_json::Value,
+    natural_key: Option<String>,
+    capture_method: String,
+    amount_received: i64,
+    description: Option<String>,
+    expires_at: DateTime<Utc>,
+    payment_method: Option<String>,
+    customer_id: Option<Uuid>,
+}
+
+// The column is text for sqlx; payment_intents_status_check keeps it to the known statuses, so
+// an unknown one is a decode error for the query that read it rather than a panic later
+impl TryFrom<PaymentIntentRow> for PaymentIntent {
+    type Error = sqlx::Error;
+
+    fn try_from(row: PaymentIntentRow) -> Result<Self, Self::Error> {
+        let PaymentIntentRow {
+            id,
+            amount,
+            currency,
+            status,
+            created_at,
+            cancellation_reason,
+            last_payment_error,
+            webhook_acknowledged_at,
+            archived_at,
+            metadata,
+            natural_key,
+            capture_method,
+            amount_received,
+            description,
+            expires_at,
+            payment_method,
+            customer_id,
+        } = row;
+        let status = PaymentIntentStatus::parse(&status).ok_or_else(|| {
+            sqlx::Error::Decode(format!("payment_intent {id} has unknown status {status}").into())
+        })?;
+        Ok(Self {
+            id,
+            amount,
+            currency,
+            status,
+            created_at,
+            cancellation_reason,
+            last_payment_error,
+            webhook_acknowledged_at,
+            archived_at,
+            metadata,
+            natural_key,
+            capture_method,
+            amount_received,
+            description,
+            expires_at,
+            payment_method,
+            customer_id,
+        })
+    }
+}
+
+pub struct NewPaymentIntent {
+    pub amount: i64,
+    pub currency: String,
+    pub metadata: BTreeMap<String, String>,
+    // Value of the configured natural idempotency key, if the request has one
+    pub natural_key: Option<String>,
+    // `automatic` or `manual`
+    pub capture_method: String,
+    pub description: Option<String>,
+    // Checked to exist (and not be deleted) before the insert
+    pub customer_id: Option<Uuid>,
+}
+
+pub async fn insert_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    new: NewPaymentIntent,
+) -> Result<PaymentIntent, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        INSERT INTO payment_intents (
+          id, amount, currency, status, metadata, natural_key, capture_method, description,
+          customer_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING *
+        "#,
+        Uuid::new_v4(),
+        new.amount,
+        new.currency,
+        PaymentIntentStatus::INITIAL.as_str(),
+        serde_json::json!(new.metadata),
+        new.natural_key,
+        new.capture_method,
+        new.description,
+        new.customer_id
+    )
+    .fetch_one(executor)
+    .await?
+    .try_into()
+}
+
+pub async fn fetch_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        SELECT *
+        FROM payment_intents
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// Row lock taken first thing by every transaction that changes a single intent (confirm, capture,
+// amount update), so they run one after the other and each sees the status the other left behind.
+// Lock order: at most one intent row is locked this way per transaction. Anything that has to
+// touch several (bulk_cancel) uses SKIP LOCKED instead of waiting, so the two never deadlock. A
+// future multi-row operation that must wait should lock in id order.
+pub async fn lock_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        SELECT *
+        FROM payment_intents
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// The fields PATCH can change, as they should be after it
+pub struct PaymentIntentChanges {
+    pub amount: i64,
+    pub currency: String,
+    pub description: Option<String>,
+    pub metadata: serde_json::Value,
+}
+
+// A different amount or currency only while requires_confirmation; None otherwise (the money
+// trigger refuses it later anyway)
+pub async fn update_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    changes: PaymentIntentChanges,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        UPDATE payment_intents
+        SET amount = $2, currency = $3, description = $4, metadata = $5
+        WHERE id = $1
+          AND ((amount = $2 AND currency = $3) OR status = $6)
+        RETURNING *
+        "#,
+        id,
+        changes.amount,
+        changes.currency,
+        changes.description,
+        changes.metadata,
+        PaymentIntentStatus::RequiresConfirmation.as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// The intent that isn't canceled holding this natural key, if any
+pub async fn fetch_payment_intent_by_natural_key(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    natural_key: &str,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        SELECT *
+        FROM payment_intents
+        WHERE natural_key = $1 AND status <> $2
+        "#,
+        natural_key,
+        PaymentIntentStatus::Canceled.as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// requires_confirmation -> succeeded, or requires_capture for a manual-capture intent. A declined
+// intent (requires_payment_method) can take the same step with a new payment method. None when
+// the intent is missing or in another status.
+pub async fn mark_payment_intent_confirmed(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    payment_method: Option<&str>,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        UPDATE payment_intents
+        SET status = CASE capture_method WHEN 'manual' THEN $3 ELSE $4 END,
+            amount_received = CASE capture_method WHEN 'manual' THEN 0 ELSE amount END,
+            last_payment_error = NULL,
+            payment_method = COALESCE($2, payment_method)
+        WHERE id = $1
+          AND (status = $5 OR (status = $6 AND $2::text IS NOT NULL))
+        RETURNING *
+        "#,
+        id,
+        payment_method,
+        PaymentIntentStatus::RequiresCapture.as_str(),
+        PaymentIntentStatus::Succeeded.as_str(),
+        PaymentIntentStatus::RequiresConfirmation.as_str(),
+        PaymentIntentStatus::RequiresPaymentMethod.as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// requires_confirmation -> `status` for a confirm that didn't go through (declined, needs action,
+// or timed out and left where it was). As above, requires_payment_method too with a new payment
+// method. None when the intent is missing or in another status.
+pub async fn mark_payment_intent_unconfirmed(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    status: PaymentIntentStatus,
+    last_payment_error: Option<serde_json::Value>,
+    payment_method: Option<&str>,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        UPDATE payment_intents
+        SET status = $2, last_payment_error = $3, payment_method = COALESCE($4, payment_method)
+        WHERE id = $1
+          AND (status = $5 OR (status = $6 AND $4::text IS NOT NULL))
+        RETURNING *
+        "#,
+        id,
+        status.as_str(),
+        last_payment_error,
+        payment_method,
+        PaymentIntentStatus::RequiresConfirmation.as_str(),
+        PaymentIntentStatus::RequiresPaymentMethod.as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// requires_capture -> succeeded, receiving `amount_received` (the caller keeps it within the
+// authorized amount); None when the intent is missing or in another status
+pub async fn capture_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    amount_received: i64,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        UPDATE payment_intents
+        SET status = $3, amount_received = $2
+        WHERE id = $1 AND status = $4
+        RETURNING *
+        "#,
+        id,
+        amount_received,
+        PaymentIntentStatus::Succeeded.as_str(),
+        PaymentIntentStatus::RequiresCapture.as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// requires_confirmation -> canceled; None when the intent is missing or in another status
+pub async fn cancel_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    reason: &str,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        UPDATE payment_intents
+        SET status = $3, cancellation_reason = $2
+        WHERE id = $1 AND status = $4
+        RETURNING *
+        "#,
+        id,
+        reason,
+        PaymentIntentStatus::Canceled.as_str(),
+        PaymentIntentStatus::RequiresConfirmation.as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// Still unpaid, so they expire; payment_intents_expiring_idx is over the same statuses
+const EXPIRABLE: [PaymentIntentStatus; 3] = [
+    PaymentIntentStatus::RequiresConfirmation,
+    PaymentIntentStatus::RequiresPaymentMethod,
+    PaymentIntentStatus::RequiresAction,
+];
+
+// -> canceled with reason `expired`, when the intent is past its expires_at and still unpaid
+// (one of EXPIRABLE)
+pub async fn expire_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        UPDATE payment_intents
+        SET status = $2, cancellation_reason = 'expired'
+        WHERE id = $1 AND status IN ($3, $4, $5) AND expires_at <= now()
+        RETURNING *
+        "#,
+        id,
+        PaymentIntentStatus::Canceled.as_str(),
+        EXPIRABLE[0].as_str(),
+        EXPIRABLE[1].as_str(),
+        EXPIRABLE[2].as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// Up to `limit` intents due to expire, oldest expiry first, locked for expire_payment_intent. Rows
+// locked by a confirm or update are skipped; that request or the next batch deals with them.
+pub async fn lock_expired_payment_intents(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    limit: i64,
+) -> Result<Vec<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        SELECT *
+        FROM payment_intents
+        WHERE status IN ($2, $3, $4) AND expires_at <= now()
+        ORDER BY expires_at
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED
+        "#,
+        limit,
+        EXPIRABLE[0].as_str(),
+        EXPIRABLE[1].as_str(),
+        EXPIRABLE[2].as_str()
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(PaymentIntent::try_from)
+    .collect()
+}
+
+// Newest first by (created_at, id), strictly after `after` when given, so concurrent inserts
+// (always newer) never shift later pages. Archived intents only when asked for, and only the
+// customer's when `customer_id` is given.
+pub async fn list_payment_intents(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    include_archived: bool,
+    customer_id: Option<Uuid>,
+    after: Option<(DateTime<Utc>, Uuid)>,
+    limit: i64,
+) -> Result<Vec<PaymentIntent>, sqlx::Error> {
+    let (after_created_at, after_id) = after.unzip();
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        SELECT *
+        FROM payment_intents
+        WHERE ($1 OR archived_at IS NULL)
+          AND ($2::uuid IS NULL OR customer_id = $2)
+          AND ($3::timestamptz IS NULL OR (created_at, id) < ($3, $4))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $5
+        "#,
+        include_archived,
+        customer_id,
+        after_created_at,
+        after_id,
+        limit
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(PaymentIntent::try_from)
+    .collect()
+}
+
+// The page before a cursor: oldest first by (created_at, id), strictly newer than `before`. The
+// caller reverses it into list order.
+pub async fn list_payment_intents_before(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    include_archived: bool,
+    customer_id: Option<Uuid>,
+    before: (DateTime<Utc>, Uuid),
+    limit: i64,
+) -> Result<Vec<PaymentIntent>, sqlx::Error> {
+    let (before_created_at, before_id) = before;
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        SELECT *
+        FROM payment_intents
+        WHERE ($1 OR archived_at IS NULL)
+          AND ($2::uuid IS NULL OR customer_id = $2)
+          AND (created_at, id) > ($3::timestamptz, $4::uuid)
+        ORDER BY created_at ASC, id ASC
+        LIMIT $5
+        "#,
+        include_archived,
+        customer_id,
+        before_created_at,
+        before_id,
+        limit
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(PaymentIntent::try_from)
+    .collect()
+}
+
+// Terminal intents only; archiving twice keeps the first archived_at. None when the intent is
+// missing or not terminal.
+pub async fn archive_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        UPDATE payment_intents
+        SET archived_at = COALESCE(archived_at, now())
+        WHERE id = $1 AND status IN ($2, $3)
+        RETURNING *
+        "#,
+        id,
+        PaymentIntentStatus::Succeeded.as_str(),
+        PaymentIntentStatus::Canceled.as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+// -> canceled with reason `abandoned`, when the intent was created at least `max_age` ago and
+// is still requires_confirmation or requires_payment_method
+pub async fn abandon_payment_intent(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    max_age: Duration,
+) -> Result<Option<PaymentIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentRow,
+        r#"
+        UPDATE payment_intents
+        SET status = $3, cancellation_reason = 'abandoned'
+        WHERE id = $1
+          AND status IN ($4, $5)
+          AND created_at <= now() - make_interval(secs => $2)
+        RETURNING *
+        "#,
+        id,
+        max_age.as_secs_f64(),
+        PaymentIntentStatus::Canceled.as_str(),
+        PaymentIntentStatus::RequiresConfirmation.as_str(),
+        PaymentIntentStatus::RequiresPaymentMethod.as_str()
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(PaymentIntent::try_from)
+    .transpose()
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaymentIntentTransition {
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub cause: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Call in the same transaction as the status change it records. Doesn't check the change is
+// allowed; services go through record_transition, which does.
+pub async fn insert_transition(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    payment_intent_id: Uuid,
+    from_status: Option<PaymentIntentStatus>,
+    to_status: PaymentIntentStatus,
+    cause: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO payment_intent_transitions (id, payment_intent_id, from_status, to_status, cause)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        payment_intent_id,
+        from_status.map(PaymentIntentStatus::as_str),
+        to_status.as_str(),
+        cause
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// Oldest first
+pub async fn list_transitions(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    payment_intent_id: Uuid,
+) -> Result<Vec<PaymentIntentTransition>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentIntentTransition,
+        r#"
+        SELECT from_status, to_status, cause, created_at
+        FROM payment_intent_transitions
+        WHERE payment_intent_id = $1
+        ORDER BY created_at, sequence
+        "#,
+        payment_intent_id
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_status_is_a_decode_error() {
+        let row = PaymentIntentRow {
+            id: Uuid::new_v4(),
+            amount: 1234,
+            currency: "gbp".to_string(),
+            status: "refunded".to_string(),
+            created_at: Utc::now(),
+            cancellation_reason: None,
+            last_payment_error: None,
+            webhook_acknowledged_at: None,
+            archived_at: None,
+            metadata: serde_json::json!({}),
+            natural_key: None,
+            capture_method: "automatic".to_string(),
+            amount_received: 0,
+            description: None,
+            expires_at: Utc::now(),
+            payment_method: None,
+            customer_id: None,
+        };
+
+        let err = PaymentIntent::try_from(row).unwrap_err();
+
+        assert!(
+            matches!(&err, sqlx::Error::Decode(e) if e.to_string().contains("unknown status refunded")),
+            "{err:?}"
+        );
+    }
+}
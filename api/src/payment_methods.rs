@@ -0,0 +1,136 @@
+pub mod storage;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::customers::storage::fetch_customer;
+use crate::error::ApiError;
+use crate::pagination::PageLimit;
+use crate::services::DomainError;
+use crate::services::payment_methods::{CreatePaymentMethodParams, PaymentMethodService};
+use crate::state::AppState;
+use storage::{
+    PaymentMethod, fetch_payment_method, list_payment_methods as list_payment_method_rows,
+};
+
+pub use mini_stripe_types::payment_methods::{
+    AttachPaymentMethodRequest, Card, CardDetails, CreatePaymentMethodRequest, PaymentMethodList,
+    PaymentMethodResponse,
+};
+
+#[derive(Deserialize)]
+pub struct ListPaymentMethodsQuery {
+    // Required, as on Stripe: payment methods are only listed per customer
+    customer: Uuid,
+    // The last id of the previous page
+    starting_after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+impl From<PaymentMethod> for PaymentMethodResponse {
+    fn from(payment_method: PaymentMethod) -> Self {
+        Self {
+            id: payment_method.id,
+            card: Card {
+                brand: payment_method.brand,
+                last4: payment_method.last4,
+                exp_month: payment_method.exp_month,
+                exp_year: payment_method.exp_year,
+            },
+            customer: payment_method.customer_id,
+            created_at: payment_method.created_at,
+        }
+    }
+}
+
+pub async fn create_payment_method(
+    State(state): State<AppState>,
+    Json(req): Json<CreatePaymentMethodRequest>,
+) -> Result<(StatusCode, Json<PaymentMethodResponse>), ApiError> {
+    let payment_method = PaymentMethodService::new(&state.db, &state.in_flight)
+        .create(CreatePaymentMethodParams {
+            number: req.card.number,
+            exp_month: req.card.exp_month,
+            exp_year: req.card.exp_year,
+            cvc: req.card.cvc,
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(payment_method)))
+}
+
+pub async fn get_payment_method(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PaymentMethodResponse>, ApiError> {
+    let payment_method = fetch_payment_method(&state.db, id)
+        .await?
+        .ok_or(DomainError::NotFound("payment_method"))?;
+    Ok(Json(PaymentMethodResponse::from(payment_method)))
+}
+
+pub async fn attach_payment_method(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<AttachPaymentMethodRequest>,
+) -> Result<Json<PaymentMethodResponse>, ApiError> {
+    let payment_method = PaymentMethodService::new(&state.db, &state.in_flight)
+        .attach(id, req.customer)
+        .await?;
+    Ok(Json(payment_method))
+}
+
+pub async fn detach_payment_method(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PaymentMethodResponse>, ApiError> {
+    let payment_method = PaymentMethodService::new(&state.db, &state.in_flight)
+        .detach(id)
+        .await?;
+    Ok(Json(payment_method))
+}
+
+// From the read replica like the other lists. A deleted customer is a 404 like on GET.
+pub async fn list_payment_methods(
+    State(state): State<AppState>,
+    Query(query): Query<ListPaymentMethodsQuery>,
+) -> Result<Json<PaymentMethodList>, ApiError> {
+    let page = PageLimit::resolve(&state.config, query.limit)?;
+    fetch_customer(&state.read_db, query.customer)
+        .await?
+        .ok_or(DomainError::NotFound("customer"))?;
+    // May have been detached since its page was read; it still marks a place in the order
+    if let Some(after) = query.starting_after
+        && fetch_payment_method(&state.read_db, after).await?.is_none()
+    {
+        return Err(DomainError::InvalidParameter(format!(
+            "starting_after: no such payment_method: {after}"
+        ))
+        .into());
+    }
+    let mut payment_methods = list_payment_method_rows(
+        &state.read_db,
+        query.customer,
+        query.starting_after,
+        page.limit + 1,
+    )
+    .await?;
+    let has_more = payment_methods.len() as i64 > page.limit;
+    payment_methods.truncate(page.limit as usize);
+
+    Ok(Json(PaymentMethodList {
+        object: "list".to_string(),
+        data: payment_methods
+            .into_iter()
+            .map(PaymentMethodResponse::from)
+            .collect(),
+        has_more,
+        limit: page.limit,
+        max_limit: page.max_limit,
+        warnings: page.warnings(),
+    }))
+}
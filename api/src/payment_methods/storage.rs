@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+// One field per payment_methods column, read with `SELECT *`/`RETURNING *` like PaymentIntent
+#[derive(Debug, Clone)]
+pub struct PaymentMethod {
+    pub id: Uuid,
+    pub customer_id: Option<Uuid>,
+    pub brand: String,
+    pub last4: String,
+    pub exp_month: i32,
+    pub exp_year: i32,
+    pub processor_token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct NewPaymentMethod {
+    pub brand: String,
+    pub last4: String,
+    pub exp_month: i32,
+    pub exp_year: i32,
+    pub processor_token: String,
+}
+
+pub async fn insert_payment_method(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    new: NewPaymentMethod,
+) -> Result<PaymentMethod, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentMethod,
+        r#"
+        INSERT INTO payment_methods (id, brand, last4, exp_month, exp_year, processor_token)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+        "#,
+        Uuid::new_v4(),
+        new.brand,
+        new.last4,
+        new.exp_month,
+        new.exp_year,
+        new.processor_token
+    )
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn fetch_payment_method(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<PaymentMethod>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentMethod,
+        r#"
+        SELECT *
+        FROM payment_methods
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Row lock for attach/detach, so two of them can't both see it unattached
+pub async fn lock_payment_method(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<PaymentMethod>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentMethod,
+        r#"
+        SELECT *
+        FROM payment_methods
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Attaches to `customer_id`, or detaches with None
+pub async fn set_payment_method_customer(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    customer_id: Option<Uuid>,
+) -> Result<PaymentMethod, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentMethod,
+        r#"
+        UPDATE payment_methods
+        SET customer_id = $2
+        WHERE id = $1
+        RETURNING *
+        "#,
+        id,
+        customer_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
+// When the customer is deleted, as Stripe does
+pub async fn detach_customer_payment_methods(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    customer_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE payment_methods
+        SET customer_id = NULL
+        WHERE customer_id = $1
+        "#,
+        customer_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// Newest first, after the `starting_after` payment method when given
+pub async fn list_payment_methods(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    customer_id: Uuid,
+    starting_after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<PaymentMethod>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentMethod,
+        r#"
+        SELECT *
+        FROM payment_methods
+        WHERE customer_id = $1
+          AND ($2::uuid IS NULL
+            OR (created_at, id) < (SELECT created_at, id FROM payment_methods WHERE id = $2))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $3
+        "#,
+        customer_id,
+        starting_after,
+        limit
+    )
+    .fetch_all(executor)
+    .await
+}
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::services::DomainError;
+use crate::services::payouts::PayoutService;
+use crate::state::AppState;
+
+// How often the background loop looks for due payouts, and how many one transaction settles
+const RUN_INTERVAL: Duration = Duration::from_secs(10);
+pub const BATCH_SIZE: i64 = 100;
+
+// Settles every pending payout past its arrival_date, a batch per transaction, and returns how
+// many. PAYOUT_SETTLE_AFTER sets how far ahead of creation that is.
+pub async fn run(state: &AppState, batch_size: i64) -> Result<u64, DomainError> {
+    let service = PayoutService::new(&state.db, &state.config, &state.in_flight);
+    let mut settled = 0;
+    loop {
+        let batch = service.settle_batch(batch_size).await?;
+        settled += batch as u64;
+        if (batch as i64) < batch_size {
+            return Ok(settled);
+        }
+    }
+}
+
+// Runs every RUN_INTERVAL until the process exits
+pub async fn run_forever(state: AppState) {
+    let mut interval = tokio::time::interval(RUN_INTERVAL);
+    loop {
+        interval.tick().await;
+        match run(&state, BATCH_SIZE).await {
+            Ok(0) => {}
+            Ok(settled) => info!("settled {settled} payouts"),
+            Err(e) => warn!(error = %e, "payout settlement failed"),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+pub mod storage;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::pagination::PageLimit;
+use crate::services::DomainError;
+use crate::services::payouts::{CreatePayoutParams, PayoutService};
+use crate::state::AppState;
+use storage::{Payout, fetch_payout, list_payouts as list_payout_rows};
+
+pub use mini_stripe_types::payouts::{
+    CreatePayoutRequest, PayoutList, PayoutResponse, PayoutStatus,
+};
+
+#[derive(Deserialize)]
+pub struct ListPayoutsQuery {
+    status: Option<String>,
+    // The last id of the previous page
+    starting_after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+impl From<Payout> for PayoutResponse {
+    fn from(payout: Payout) -> Self {
+        Self {
+            status: payout.status(),
+            id: payout.id,
+            amount: payout.amount,
+            currency: payout.currency,
+            destination: payout.destination,
+            description: payout.description,
+            arrival_date: payout.arrival_date,
+            failure_code: payout.failure_code,
+            failure_message: payout.failure_message,
+            created_at: payout.created_at,
+        }
+    }
+}
+
+pub async fn create_payout(
+    State(state): State<AppState>,
+    Json(req): Json<CreatePayoutRequest>,
+) -> Result<(StatusCode, Json<PayoutResponse>), ApiError> {
+    let payout = PayoutService::new(&state.db, &state.config, &state.in_flight)
+        .create(CreatePayoutParams {
+            amount: req.amount,
+            currency: req.currency,
+            destination: req.destination,
+            description: req.description,
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(payout)))
+}
+
+pub async fn get_payout(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PayoutResponse>, ApiError> {
+    let payout = fetch_payout(&state.db, id)
+        .await?
+        .ok_or(DomainError::NotFound("payout"))?;
+    Ok(Json(PayoutResponse::from(payout)))
+}
+
+// From the read replica like the other lists
+pub async fn list_payouts(
+    State(state): State<AppState>,
+    Query(query): Query<ListPayoutsQuery>,
+) -> Result<Json<PayoutList>, ApiError> {
+    let status = query
+        .status
+        .map(|s| {
+            PayoutStatus::parse(&s)
+                .ok_or_else(|| DomainError::InvalidParameter(format!("unknown status: {s}")))
+        })
+        .transpose()?;
+    let page = PageLimit::resolve(&state.config, query.limit)?;
+    if let Some(after) = query.starting_after
+        && fetch_payout(&state.read_db, after).await?.is_none()
+    {
+        return Err(DomainError::InvalidParameter(format!(
+            "starting_after: no such payout: {after}"
+        ))
+        .into());
+    }
+    let mut payouts =
+        list_payout_rows(&state.read_db, status, query.starting_after, page.limit + 1).await?;
+    let has_more = payouts.len() as i64 > page.limit;
+    payouts.truncate(page.limit as usize);
+
+    Ok(Json(PayoutList {
+        object: "list".to_string(),
+        data: payouts.into_iter().map(PayoutResponse::from).collect(),
+        has_more,
+        limit: page.limit,
+        max_limit: page.max_limit,
+        warnings: page.warnings(),
+    }))
+}
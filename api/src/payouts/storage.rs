@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use mini_stripe_types::payouts::PayoutStatus;
+use uuid::Uuid;
+
+// One field per payouts column, read with `SELECT *`/`RETURNING *` like Refund
+#[derive(Debug, Clone)]
+pub struct Payout {
+    pub id: Uuid,
+    pub amount: i64,
+    pub currency: String,
+    pub status: String,
+    pub destination: String,
+    pub description: Option<String>,
+    pub arrival_date: DateTime<Utc>,
+    pub failure_code: Option<String>,
+    pub failure_message: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Payout {
+    // The column's CHECK keeps it to the known statuses
+    pub fn status(&self) -> PayoutStatus {
+        PayoutStatus::parse(&self.status)
+            .unwrap_or_else(|| panic!("payout {} has unknown status {}", self.id, self.status))
+    }
+}
+
+pub struct NewPayout<'a> {
+    pub amount: i64,
+    pub currency: &'a str,
+    pub destination: &'a str,
+    pub description: Option<&'a str>,
+    // From now until arrival_date
+    pub settle_after: Duration,
+}
+
+pub async fn insert_payout(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    new: NewPayout<'_>,
+) -> Result<Payout, sqlx::Error> {
+    sqlx::query_as!(
+        Payout,
+        r#"
+        INSERT INTO payouts (id, amount, currency, status, destination, description, arrival_date)
+        VALUES ($1, $2, $3, 'pending', $4, $5, now() + make_interval(secs => $6))
+        RETURNING *
+        "#,
+        Uuid::new_v4(),
+        new.amount,
+        new.currency,
+        new.destination,
+        new.description,
+        new.settle_after.as_secs_f64()
+    )
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn fetch_payout(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<Payout>, sqlx::Error> {
+    sqlx::query_as!(
+        Payout,
+        r#"
+        SELECT *
+        FROM payouts
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Pending payouts past their arrival_date, oldest first. SKIP LOCKED lets two settlement loops
+// share the work instead of waiting on each other.
+pub async fn lock_due_payouts(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    limit: i64,
+) -> Result<Vec<Payout>, sqlx::Error> {
+    sqlx::query_as!(
+        Payout,
+        r#"
+        SELECT *
+        FROM payouts
+        WHERE status = 'pending' AND arrival_date <= now()
+        ORDER BY arrival_date, id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED
+        "#,
+        limit
+    )
+    .fetch_all(executor)
+    .await
+}
+
+// Only a pending payout is settled; the failure columns are None when it was paid
+pub async fn settle_payout(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+    status: PayoutStatus,
+    failure: Option<(&str, &str)>,
+) -> Result<Payout, sqlx::Error> {
+    let (failure_code, failure_message) = failure.unzip();
+    sqlx::query_as!(
+        Payout,
+        r#"
+        UPDATE payouts
+        SET status = $2, failure_code = $3, failure_message = $4
+        WHERE id = $1 AND status = 'pending'
+        RETURNING *
+        "#,
+        id,
+        status.as_str(),
+        failure_code,
+        failure_message
+    )
+    .fetch_one(executor)
+    .await
+}
+
+// Newest first, after the `starting_after` payout when given, narrowed to one status when given
+pub async fn list_payouts(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    status: Option<PayoutStatus>,
+    starting_after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<Payout>, sqlx::Error> {
+    sqlx::query_as!(
+        Payout,
+        r#"
+        SELECT *
+        FROM payouts
+        WHERE ($1::text IS NULL OR status = $1)
+          AND ($2::uuid IS NULL
+            OR (created_at, id) < (SELECT created_at, id FROM payouts WHERE id = $2))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $3
+        "#,
+        status.map(PayoutStatus::as_str),
+        starting_after,
+        limit
+    )
+    .fetch_all(executor)
+    .await
+}
@@ -0,0 +1,344 @@
+use std::fmt;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use mini_stripe_types::error::codes;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::routes;
+use crate::state::AppState;
+
+// What an API key may do. Each scope includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    // Reads of payment intents, charges, refunds, customers, payment methods, the balance,
+    // payouts, webhook endpoints and their deliveries
+    ReadOnly,
+    // Everything a merchant integration does
+    Full,
+    // Plus /v1/admin and /metrics
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::ReadOnly => "read_only",
+            Scope::Full => "full",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [Scope::ReadOnly, Scope::Full, Scope::Admin]
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    // Needed for GET, HEAD and OPTIONS
+    pub read: Scope,
+    // Needed for every other method
+    pub write: Scope,
+    // 404 unless the API runs with SANDBOX_MODE, whatever the key
+    pub sandbox_only: bool,
+    // Served without a key (probes, and the worker posting to the echo receiver)
+    pub unauthenticated: bool,
+}
+
+const API: Access = Access {
+    read: Scope::ReadOnly,
+    write: Scope::Full,
+    sandbox_only: false,
+    unauthenticated: false,
+};
+const ADMIN: Access = Access {
+    read: Scope::Admin,
+    write: Scope::Admin,
+    ..API
+};
+const OPEN: Access = Access {
+    unauthenticated: true,
+    ..API
+};
+
+// Every route in routes::ALL with who may call it. `authorize` turns away routes missing from
+// here, and a unit test fails when the two lists disagree.
+pub const TABLE: &[(&str, Access)] = &[
+    (routes::HEALTH, OPEN),
+    (routes::READYZ, OPEN),
+    (routes::METRICS, ADMIN),
+    (routes::PAYMENT_INTENTS, API),
+    (routes::PAYMENT_INTENTS_BULK_CANCEL, ADMIN),
+    (routes::PAYMENT_INTENT, API),
+    (
+        routes::PAYMENT_INTENT_ARCHIVE,
+        Access {
+            sandbox_only: true,
+            ..API
+        },
+    ),
+    (routes::PAYMENT_INTENT_TRANSITIONS, API),
+    (routes::PAYMENT_INTENT_CONFIRM, API),
+    (routes::PAYMENT_INTENT_CAPTURE, API),
+    (routes::PAYMENT_INTENT_WAIT, API),
+    (routes::CHARGES, API),
+    (routes::CHARGE, API),
+    (routes::REFUNDS, API),
+    (routes::REFUND, API),
+    (routes::CUSTOMERS, API),
+    (routes::CUSTOMER, API),
+    (routes::PAYMENT_METHODS, API),
+    (routes::PAYMENT_METHOD, API),
+    (routes::PAYMENT_METHOD_ATTACH, API),
+    (routes::PAYMENT_METHOD_DETACH, API),
+    (routes::BALANCE, API),
+    (routes::BALANCE_TRANSACTIONS, API),
+    (routes::BALANCE_TRANSACTION, API),
+    (routes::PAYOUTS, API),
+    (routes::PAYOUT, API),
+    (routes::WEBHOOK_ENDPOINTS, API),
+    (routes::WEBHOOK_ENDPOINTS_SYNC, API),
+    (routes::WEBHOOK_ENDPOINT, API),
+    (routes::WEBHOOK_ENDPOINT_DELIVERIES, API),
+    (routes::WEBHOOK_ENDPOINT_STATS, API),
+    (routes::WEBHOOK_ENDPOINT_REVEAL_SECRET, API),
+    (routes::WEBHOOK_ENDPOINT_PREVIEW, API),
+    (routes::WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE, API),
+    (routes::EVENT_REDELIVER, API),
+    (routes::EXAMPLE, API),
+    (routes::ADMIN_OUTBOX_DRAIN, ADMIN),
+    (routes::ADMIN_OUTBOX_DRAIN_STATUS, ADMIN),
+    (routes::ADMIN_OUTBOX_RESUME, ADMIN),
+    (routes::ADMIN_IDEMPOTENCY_STATS, ADMIN),
+    (routes::ADMIN_IDEMPOTENCY_CLEANUP, ADMIN),
+    (
+        routes::ADMIN_SEED,
+        Access {
+            sandbox_only: true,
+            ..ADMIN
+        },
+    ),
+    (routes::ADMIN_DIAGNOSTICS, ADMIN),
+    (routes::ADMIN_WORKERS, ADMIN),
+    (routes::ADMIN_EVENT_EXPORT, ADMIN),
+    (
+        routes::ADMIN_FAULTS,
+        Access {
+            sandbox_only: true,
+            ..ADMIN
+        },
+    ),
+    (routes::ADMIN_REQUEST_CAPTURES, ADMIN),
+    (
+        routes::ADMIN_REQUEST_CAPTURE_REPLAY,
+        Access {
+            sandbox_only: true,
+            ..ADMIN
+        },
+    ),
+    // Checked by webhook signature instead
+    (
+        routes::SANDBOX_ECHO,
+        Access {
+            sandbox_only: true,
+            ..OPEN
+        },
+    ),
+    (
+        routes::SANDBOX_ECHO_DELIVERIES,
+        Access {
+            sandbox_only: true,
+            ..API
+        },
+    ),
+];
+
+pub fn access(route: &str) -> Option<Access> {
+    TABLE
+        .iter()
+        .find(|(template, _)| *template == route)
+        .map(|(_, access)| *access)
+}
+
+// The keys callers authenticate with. Only SHA-256 digests are kept and compared, so how long
+// a comparison takes says nothing about a configured key.
+#[derive(Clone, Default)]
+pub struct ApiKeys(Vec<([u8; 32], Scope)>);
+
+impl fmt::Debug for ApiKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKeys({} keys)", self.0.len())
+    }
+}
+
+fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+impl ApiKeys {
+    // `<scope>:<key>,...`, e.g. `full:sk_1,read_only:rk_2,admin:ak_3`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut keys = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (scope, key) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("`{entry}` is not `<scope>:<key>`"))?;
+            let scope = Scope::parse(scope.trim()).ok_or_else(|| {
+                format!("unknown scope `{scope}`; expected full, read_only or admin")
+            })?;
+            let key = key.trim();
+            if key.is_empty() {
+                return Err(format!("empty key for scope `{scope}`"));
+            }
+            let digest = digest(key);
+            if keys.iter().any(|(d, _)| *d == digest) {
+                return Err("the same key is listed twice".to_string());
+            }
+            keys.push((digest, scope));
+        }
+        Ok(Self(keys))
+    }
+
+    // API_KEYS; unset means no keys
+    pub fn from_env() -> Result<Self, String> {
+        std::env::var("API_KEYS").map_or_else(|_| Ok(Self::default()), |v| Self::parse(&v))
+    }
+
+    // No keys: authentication is off and every route is open
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn scope(&self, key: &str) -> Option<Scope> {
+        let digest = digest(key);
+        self.0
+            .iter()
+            .find(|(d, _)| *d == digest)
+            .map(|(_, scope)| *scope)
+    }
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+// Whether a request for `route` may go ahead
+pub fn check(
+    config: &Config,
+    route: &str,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Result<(), ApiError> {
+    let Some(access) = access(route) else {
+        tracing::error!("route {route} has no entry in permissions::TABLE");
+        return Err(ApiError::internal("internal error"));
+    };
+    if access.sandbox_only && !config.sandbox_mode {
+        return Err(ApiError::not_found("not found"));
+    }
+    if access.unauthenticated || config.api_keys.is_empty() {
+        return Ok(());
+    }
+
+    let needed = match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => access.read,
+        _ => access.write,
+    };
+    let Some(key) = bearer(headers) else {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            codes::AUTHENTICATION_REQUIRED,
+            "send an API key as `Authorization: Bearer <key>`",
+        ));
+    };
+    let Some(scope) = config.api_keys.scope(key) else {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            codes::AUTHENTICATION_REQUIRED,
+            "invalid API key",
+        ));
+    };
+    if scope < needed {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            codes::INSUFFICIENT_SCOPE,
+            format!("{method} {route} needs a `{needed}` key; this key is `{scope}`"),
+        ));
+    }
+    Ok(())
+}
+
+// Middleware. Unknown paths have no matched route and fall through to the JSON 404.
+pub async fn authorize(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(route) = req.extensions().get::<MatchedPath>() else {
+        return next.run(req).await;
+    };
+    match check(&state.config, route.as_str(), req.method(), req.headers()) {
+        Ok(()) => next.run(req).await,
+        Err(err) => err.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_route_has_exactly_one_entry() {
+        for route in routes::ALL {
+            let entries = TABLE.iter().filter(|(r, _)| r == route).count();
+            assert_eq!(entries, 1, "{route} needs one entry in permissions::TABLE");
+        }
+        for (route, _) in TABLE {
+            assert!(routes::ALL.contains(route), "{route} is not in routes::ALL");
+        }
+    }
+
+    #[test]
+    fn admin_and_sandbox_routes_are_never_open_to_api_keys() {
+        for (route, access) in TABLE {
+            if route.starts_with(routes::ADMIN_PREFIX) {
+                assert_eq!(access.read, Scope::Admin, "{route}");
+                assert_eq!(access.write, Scope::Admin, "{route}");
+            }
+            if route.starts_with(routes::SANDBOX_PREFIX) {
+                assert!(access.sandbox_only, "{route}");
+            }
+        }
+    }
+
+    #[test]
+    fn api_keys_are_parsed_with_their_scopes() {
+        let keys = ApiKeys::parse("full:sk_1, read_only:rk_2,admin:ak_3").unwrap();
+        assert_eq!(keys.scope("sk_1"), Some(Scope::Full));
+        assert_eq!(keys.scope("rk_2"), Some(Scope::ReadOnly));
+        assert_eq!(keys.scope("ak_3"), Some(Scope::Admin));
+        assert_eq!(keys.scope("sk_2"), None);
+        assert!(!format!("{keys:?}").contains("sk_1"));
+        assert!(ApiKeys::parse("").unwrap().is_empty());
+
+        for bad in ["sk_1", "owner:sk_1", "full:", "full:sk_1,admin:sk_1"] {
+            assert!(ApiKeys::parse(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use mini_stripe_types::error::codes;
+
+// Why a payment failed, as Stripe reports it: a `code`, and for `card_declined` a `decline_code`
+// saying why the issuer said no
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaymentError {
+    pub code: &'static str,
+    pub decline_code: Option<&'static str>,
+    pub message: &'static str,
+}
+
+impl PaymentError {
+    const fn declined(decline_code: &'static str, message: &'static str) -> Self {
+        Self {
+            code: codes::CARD_DECLINED,
+            decline_code: Some(decline_code),
+            message,
+        }
+    }
+
+    // The intent's last_payment_error
+    pub fn to_json(self, payment_method: Option<&str>) -> serde_json::Value {
+        let mut error = serde_json::json!({
+            "code": self.code,
+            "message": self.message,
+            "payment_method": payment_method,
+        });
+        if let Some(decline_code) = self.decline_code {
+            error["decline_code"] = decline_code.into();
+        }
+        error
+    }
+}
+
+// What processing a confirm came to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessorOutcome {
+    Succeeded,
+    // Moves to requires_payment_method with the error as last_payment_error
+    Failed(PaymentError),
+    // Moves to requires_action, as if 3DS were needed
+    RequiresAction,
+}
+
+// Every confirm of a requires_confirmation intent is sent here, with its `payment_method` if it
+// named one. There is no real acquirer behind it: TestProcessor decides from the token and the
+// amount alone, like Stripe's test mode.
+pub trait PaymentProcessor: Send + Sync {
+    // Err is a 400 message for a payment method this processor doesn't know
+    fn process(
+        &self,
+        payment_method: Option<&str>,
+        amount: i64,
+    ) -> Result<ProcessorOutcome, &'static str>;
+}
+
+const DECLINED: PaymentError = PaymentError::declined("generic_decline", "Your card was declined.");
+const INSUFFICIENT_FUNDS: PaymentError =
+    PaymentError::declined("insufficient_funds", "Your card has insufficient funds.");
+const LOST_CARD: PaymentError = PaymentError::declined("lost_card", "Your card was declined.");
+const STOLEN_CARD: PaymentError = PaymentError::declined("stolen_card", "Your card was declined.");
+const EXPIRED_CARD: PaymentError = PaymentError {
+    code: codes::EXPIRED_CARD,
+    decline_code: None,
+    message: "Your card has expired.",
+};
+const INCORRECT_CVC: PaymentError = PaymentError {
+    code: codes::INCORRECT_CVC,
+    decline_code: None,
+    message: "Your card's security code is incorrect.",
+};
+const PROCESSING_ERROR: PaymentError = PaymentError {
+    code: codes::PROCESSING_ERROR,
+    decline_code: None,
+    message: "An error occurred while processing your card. Try again in a little bit.",
+};
+
+// Stripe's test tokens, or the closest thing to them
+const TEST_PAYMENT_METHODS: &[(&str, ProcessorOutcome)] = &[
+    ("pm_card_visa", ProcessorOutcome::Succeeded),
+    ("pm_card_mastercard", ProcessorOutcome::Succeeded),
+    ("pm_card_amex", ProcessorOutcome::Succeeded),
+    ("pm_card_declined", ProcessorOutcome::Failed(DECLINED)),
+    (
+        "pm_card_insufficient_funds",
+        ProcessorOutcome::Failed(INSUFFICIENT_FUNDS),
+    ),
+    ("pm_card_lost", ProcessorOutcome::Failed(LOST_CARD)),
+    ("pm_card_stolen", ProcessorOutcome::Failed(STOLEN_CARD)),
+    ("pm_card_expired", ProcessorOutcome::Failed(EXPIRED_CARD)),
+    (
+        "pm_card_incorrect_cvc",
+        ProcessorOutcome::Failed(INCORRECT_CVC),
+    ),
+    (
+        "pm_card_processing_error",
+        ProcessorOutcome::Failed(PROCESSING_ERROR),
+    ),
+    (
+        "pm_card_authentication_required",
+        ProcessorOutcome::RequiresAction,
+    ),
+];
+
+// Stripe's test card numbers for the failing tokens. Any other valid number saved as a payment
+// method succeeds like its brand's token.
+const TEST_CARD_NUMBERS: &[(&str, &str)] = &[
+    ("4000000000000002", "pm_card_declined"),
+    ("4000000000009995", "pm_card_insufficient_funds"),
+    ("4000000000009987", "pm_card_lost"),
+    ("4000000000009979", "pm_card_stolen"),
+    ("4000000000000069", "pm_card_expired"),
+    ("4000000000000127", "pm_card_incorrect_cvc"),
+    ("4000000000000119", "pm_card_processing_error"),
+    ("4000002760003184", "pm_card_authentication_required"),
+];
+
+// The test token a saved card is processed as, from its digits and brand. Kept instead of the
+// number, which is never stored.
+pub fn test_token_for_card(number: &str, brand: &str) -> &'static str {
+    if let Some(&(_, token)) = TEST_CARD_NUMBERS.iter().find(|(n, _)| *n == number) {
+        return token;
+    }
+    match brand {
+        "mastercard" => "pm_card_mastercard",
+        "amex" => "pm_card_amex",
+        _ => "pm_card_visa",
+    }
+}
+
+// Amounts that fail whatever succeeding payment method they come with, in sandbox mode only, so
+// clients that can't choose a token can still reach each error
+const MAGIC_AMOUNTS: &[(i64, PaymentError)] = &[
+    (4020, DECLINED),
+    (4030, LOST_CARD),
+    (4040, STOLEN_CARD),
+    (4050, EXPIRED_CARD),
+    (4060, INCORRECT_CVC),
+    (4070, PROCESSING_ERROR),
+    (4090, INSUFFICIENT_FUNDS),
+];
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TestProcessor {
+    // Whether MAGIC_AMOUNTS apply (sandbox mode)
+    pub magic_amounts: bool,
+}
+
+impl TestProcessor {
+    pub const SANDBOX: Self = Self {
+        magic_amounts: true,
+    };
+    pub const LIVE: Self = Self {
+        magic_amounts: false,
+    };
+}
+
+impl PaymentProcessor for TestProcessor {
+    // A failing or 3DS token decides on its own; otherwise a magic amount can still fail it
+    fn process(
+        &self,
+        payment_method: Option<&str>,
+        amount: i64,
+    ) -> Result<ProcessorOutcome, &'static str> {
+        let by_token = match payment_method {
+            Some(payment_method) => TEST_PAYMENT_METHODS
+                .iter()
+                .find(|(token, _)| *token == payment_method)
+                .map(|&(_, outcome)| outcome)
+                .ok_or("payment_method must be a test payment method such as pm_card_visa")?,
+            None => ProcessorOutcome::Succeeded,
+        };
+        if by_token != ProcessorOutcome::Succeeded || !self.magic_amounts {
+            return Ok(by_token);
+        }
+        Ok(MAGIC_AMOUNTS
+            .iter()
+            .find(|(magic, _)| *magic == amount)
+            .map_or(ProcessorOutcome::Succeeded, |&(_, error)| {
+                ProcessorOutcome::Failed(error)
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_decide_the_outcome() {
+        let processor = TestProcessor::LIVE;
+        assert_eq!(
+            processor.process(Some("pm_card_visa"), 1000),
+            Ok(ProcessorOutcome::Succeeded)
+        );
+        assert_eq!(
+            processor.process(Some("pm_card_insufficient_funds"), 1000),
+            Ok(ProcessorOutcome::Failed(INSUFFICIENT_FUNDS))
+        );
+        assert_eq!(
+            processor.process(Some("pm_card_authentication_required"), 1000),
+            Ok(ProcessorOutcome::RequiresAction)
+        );
+        assert!(processor.process(Some("pm_card_unknown"), 1000).is_err());
+        assert!(processor.process(Some("PM_CARD_VISA"), 1000).is_err());
+    }
+
+    #[test]
+    fn test_card_numbers_map_to_known_tokens() {
+        for &(_, token) in TEST_CARD_NUMBERS {
+            assert!(
+                TEST_PAYMENT_METHODS.iter().any(|(t, _)| *t == token),
+                "{token}"
+            );
+        }
+        assert_eq!(
+            test_token_for_card("4000000000009995", "visa"),
+            "pm_card_insufficient_funds"
+        );
+        assert_eq!(
+            test_token_for_card("5555555555554444", "mastercard"),
+            "pm_card_mastercard"
+        );
+        assert_eq!(
+            test_token_for_card("6011111111111117", "discover"),
+            "pm_card_visa"
+        );
+    }
+
+    #[test]
+    fn magic_amounts_fail_only_in_sandbox() {
+        assert_eq!(
+            TestProcessor::SANDBOX.process(None, 4020),
+            Ok(ProcessorOutcome::Failed(DECLINED))
+        );
+        assert_eq!(
+            TestProcessor::SANDBOX.process(Some("pm_card_visa"), 4090),
+            Ok(ProcessorOutcome::Failed(INSUFFICIENT_FUNDS))
+        );
+        // The token's own failure wins
+        assert_eq!(
+            TestProcessor::SANDBOX.process(Some("pm_card_expired"), 4090),
+            Ok(ProcessorOutcome::Failed(EXPIRED_CARD))
+        );
+        assert_eq!(
+            TestProcessor::SANDBOX.process(None, 4021),
+            Ok(ProcessorOutcome::Succeeded)
+        );
+        assert_eq!(
+            TestProcessor::LIVE.process(None, 4020),
+            Ok(ProcessorOutcome::Succeeded)
+        );
+    }
+
+    #[test]
+    fn errors_carry_a_decline_code_only_when_declined() {
+        let declined = INSUFFICIENT_FUNDS.to_json(Some("pm_card_insufficient_funds"));
+        assert_eq!(declined["code"], "card_declined");
+        assert_eq!(declined["decline_code"], "insufficient_funds");
+
+        let expired = EXPIRED_CARD.to_json(None);
+        assert_eq!(expired["code"], "expired_card");
+        assert!(expired.get("decline_code").is_none());
+        assert!(expired["payment_method"].is_null());
+    }
+}
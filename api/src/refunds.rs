@@ -0,0 +1,102 @@
+pub mod storage;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::pagination::PageLimit;
+use crate::services::DomainError;
+use crate::services::refunds::{CreateRefundParams, RefundService};
+use crate::state::AppState;
+use storage::{Refund, fetch_refund, list_refunds as list_refund_rows};
+
+pub use mini_stripe_types::refunds::{
+    CreateRefundRequest, RefundList, RefundResponse, RefundStatus,
+};
+
+#[derive(Deserialize)]
+pub struct ListRefundsQuery {
+    payment_intent: Option<Uuid>,
+    charge: Option<Uuid>,
+    // The last id of the previous page
+    starting_after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+impl From<Refund> for RefundResponse {
+    fn from(refund: Refund) -> Self {
+        Self {
+            status: refund.status(),
+            id: refund.id,
+            charge: refund.charge_id,
+            payment_intent: refund.payment_intent_id,
+            amount: refund.amount,
+            currency: refund.currency,
+            created_at: refund.created_at,
+        }
+    }
+}
+
+pub async fn create_refund(
+    State(state): State<AppState>,
+    Json(req): Json<CreateRefundRequest>,
+) -> Result<(StatusCode, Json<RefundResponse>), ApiError> {
+    let refund = RefundService::new(&state.db, &state.in_flight)
+        .create(CreateRefundParams {
+            payment_intent: req.payment_intent,
+            charge: req.charge,
+            amount: req.amount,
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(refund)))
+}
+
+pub async fn get_refund(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<RefundResponse>, ApiError> {
+    let refund = fetch_refund(&state.db, id)
+        .await?
+        .ok_or(DomainError::NotFound("refund"))?;
+    Ok(Json(RefundResponse::from(refund)))
+}
+
+// From the read replica like the other lists
+pub async fn list_refunds(
+    State(state): State<AppState>,
+    Query(query): Query<ListRefundsQuery>,
+) -> Result<Json<RefundList>, ApiError> {
+    let page = PageLimit::resolve(&state.config, query.limit)?;
+    if let Some(after) = query.starting_after
+        && fetch_refund(&state.read_db, after).await?.is_none()
+    {
+        return Err(DomainError::InvalidParameter(format!(
+            "starting_after: no such refund: {after}"
+        ))
+        .into());
+    }
+    let mut refunds = list_refund_rows(
+        &state.read_db,
+        query.payment_intent,
+        query.charge,
+        query.starting_after,
+        page.limit + 1,
+    )
+    .await?;
+    let has_more = refunds.len() as i64 > page.limit;
+    refunds.truncate(page.limit as usize);
+
+    Ok(Json(RefundList {
+        object: "list".to_string(),
+        data: refunds.into_iter().map(RefundResponse::from).collect(),
+        has_more,
+        limit: page.limit,
+        max_limit: page.max_limit,
+        warnings: page.warnings(),
+    }))
+}
@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use mini_stripe_types::refunds::RefundStatus;
+use uuid::Uuid;
+
+// One field per refunds column, read with `SELECT *`/`RETURNING *` like Charge
+#[derive(Debug, Clone)]
+pub struct Refund {
+    pub id: Uuid,
+    pub charge_id: Uuid,
+    pub payment_intent_id: Uuid,
+    pub amount: i64,
+    pub currency: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Refund {
+    // The column's CHECK keeps it to the known statuses
+    pub fn status(&self) -> RefundStatus {
+        RefundStatus::parse(&self.status)
+            .unwrap_or_else(|| panic!("refund {} has unknown status {}", self.id, self.status))
+    }
+}
+
+pub async fn insert_refund(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    charge_id: Uuid,
+    payment_intent_id: Uuid,
+    amount: i64,
+    currency: &str,
+) -> Result<Refund, sqlx::Error> {
+    sqlx::query_as!(
+        Refund,
+        r#"
+        INSERT INTO refunds (id, charge_id, payment_intent_id, amount, currency, status)
+        VALUES ($1, $2, $3, $4, $5, 'succeeded')
+        RETURNING *
+        "#,
+        Uuid::new_v4(),
+        charge_id,
+        payment_intent_id,
+        amount,
+        currency
+    )
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn fetch_refund(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    id: Uuid,
+) -> Result<Option<Refund>, sqlx::Error> {
+    sqlx::query_as!(
+        Refund,
+        r#"
+        SELECT *
+        FROM refunds
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Newest first, after the `starting_after` refund when given, narrowed to one intent and/or one
+// charge when given
+pub async fn list_refunds(
+    executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    payment_intent_id: Option<Uuid>,
+    charge_id: Option<Uuid>,
+    starting_after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<Refund>, sqlx::Error> {
+    sqlx::query_as!(
+        Refund,
+        r#"
+        SELECT *
+        FROM refunds
+        WHERE ($1::uuid IS NULL OR payment_intent_id = $1)
+          AND ($2::uuid IS NULL OR charge_id = $2)
+          AND ($3::uuid IS NULL
+            OR (created_at, id) < (SELECT created_at, id FROM refunds WHERE id = $3))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $4
+        "#,
+        payment_intent_id,
+        charge_id,
+        starting_after,
+        limit
+    )
+    .fetch_all(executor)
+    .await
+}
@@ -0,0 +1,322 @@
+use axum::{
+    Json,
+    body::{Body, HttpBody},
+    extract::{Path, Request, State},
+    http::{HeaderMap, HeaderName, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tower::ServiceExt;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::app::build_app;
+use crate::error::ApiError;
+use crate::routes;
+use crate::state::AppState;
+
+// Bodies are stored up to this many bytes
+const MAX_CAPTURED_BODY_BYTES: usize = 16 * 1024;
+// Bigger requests are passed through uncaptured rather than buffered
+const MAX_BUFFERED_BODY_BYTES: u64 = 2 * 1024 * 1024;
+const CAPTURE_LIST_LIMIT: i64 = 50;
+const REDACTED: &str = "[REDACTED]";
+// Redacted whatever REQUEST_CAPTURE_REDACT_PATHS says, so a card number is never stored
+const CARD_PATHS: [&str; 2] = ["card.number", "card.cvc"];
+
+// Only these headers are kept; everything else (auth, cookies, ...) is dropped
+const CAPTURED_HEADERS: [HeaderName; 3] = [
+    header::CONTENT_TYPE,
+    header::USER_AGENT,
+    HeaderName::from_static("idempotency-key"),
+];
+
+// Set on replayed requests so the replay itself is not captured again
+const REPLAY_HEADER: HeaderName = HeaderName::from_static("x-ministripe-replay");
+
+#[derive(Serialize)]
+pub struct RequestCapture {
+    pub id: Uuid,
+    pub method: String,
+    pub path: String,
+    pub headers: serde_json::Value,
+    pub body: String,
+    pub body_truncated: bool,
+    pub response_status: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ReplayResponse {
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+fn is_captured(req: &Request) -> bool {
+    let path = req.uri().path();
+    req.method() == Method::POST
+        && path.starts_with(routes::API_PREFIX)
+        && !path.starts_with(routes::ADMIN_PREFIX)
+        && !path.starts_with(routes::SANDBOX_PREFIX)
+        && !req.headers().contains_key(REPLAY_HEADER)
+}
+
+// Replaces each dot path (e.g. `card.number`) that exists in the JSON body
+fn redact(body: &mut serde_json::Value, paths: &[impl AsRef<str>]) {
+    for path in paths {
+        let mut segments = path.as_ref().split('.').peekable();
+        let mut node = &mut *body;
+        while let Some(segment) = segments.next() {
+            let Some(child) = node.get_mut(segment) else {
+                break;
+            };
+            if segments.peek().is_none() {
+                *child = serde_json::json!(REDACTED);
+                break;
+            }
+            node = child;
+        }
+    }
+}
+
+// Redacted (when JSON), lossily decoded and capped. A body that may hold card details but isn't
+// JSON can't be redacted, so it is dropped.
+fn captured_body(bytes: &[u8], redact_paths: &[String], holds_cards: bool) -> (String, bool) {
+    let text = match serde_json::from_slice::<serde_json::Value>(bytes) {
+        Ok(mut json) if !redact_paths.is_empty() || json.get("card").is_some() => {
+            redact(&mut json, &CARD_PATHS);
+            redact(&mut json, redact_paths);
+            json.to_string()
+        }
+        Err(_) if holds_cards => String::new(),
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    };
+
+    if text.len() <= MAX_CAPTURED_BODY_BYTES {
+        return (text, false);
+    }
+    let mut end = MAX_CAPTURED_BODY_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    (text[..end].to_string(), true)
+}
+
+fn captured_headers(headers: &HeaderMap) -> serde_json::Value {
+    let kept: serde_json::Map<_, _> = CAPTURED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = headers.get(name)?.to_str().ok()?;
+            Some((name.to_string(), serde_json::json!(value)))
+        })
+        .collect();
+    serde_json::Value::Object(kept)
+}
+
+// Middleware. The capture is written after the response, on its own task: a failure there is
+// logged and never affects the request.
+pub async fn capture(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if !state.config.request_capture_enabled || !is_captured(&req) {
+        return next.run(req).await;
+    }
+
+    // Known-size bodies only (Content-Length); streamed ones are never buffered here
+    let fits = req
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|len| len <= MAX_BUFFERED_BODY_BYTES);
+    if !fits {
+        return next.run(req).await;
+    }
+
+    let (parts, body) = req.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_BUFFERED_BODY_BYTES as usize).await else {
+        return (StatusCode::BAD_REQUEST, "could not read request body").into_response();
+    };
+
+    let method = parts.method.to_string();
+    let path = parts.uri.path().to_string();
+    let headers = captured_headers(&parts.headers);
+    let holds_cards = path.starts_with(routes::PAYMENT_METHODS);
+    let (body, body_truncated) = captured_body(
+        &bytes,
+        &state.config.request_capture_redact_paths,
+        holds_cards,
+    );
+
+    let response = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+    let response_status = i32::from(response.status().as_u16());
+
+    let db = state.db.clone();
+    tokio::spawn(async move {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO request_captures (id, method, path, headers, body, body_truncated, response_status)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            Uuid::new_v4(),
+            method,
+            path,
+            headers,
+            body,
+            body_truncated,
+            response_status
+        )
+        .execute(&db)
+        .await;
+
+        if let Err(e) = result {
+            warn!(%method, %path, error = %e, "request capture failed");
+        }
+    });
+
+    response
+}
+
+// Deletes captures older than `ttl`; returns how many went
+pub async fn prune_request_captures(
+    db: &PgPool,
+    ttl: std::time::Duration,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM request_captures
+        WHERE created_at < now() - make_interval(secs => $1)
+        "#,
+        ttl.as_secs_f64()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn list_request_captures(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<RequestCapture>>, ApiError> {
+    let captures = sqlx::query_as!(
+        RequestCapture,
+        r#"
+        SELECT id, method, path, headers, body, body_truncated, response_status, created_at
+        FROM request_captures
+        ORDER BY created_at DESC
+        LIMIT $1
+        "#,
+        CAPTURE_LIST_LIMIT
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(captures))
+}
+
+// Re-sends a captured request through the app (sandbox mode only), with the caller's own
+// Authorization since captures never store it. Redacted fields are sent as the redaction
+// marker, so replays of those requests will usually be rejected.
+pub async fn replay_request_capture(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    caller: HeaderMap,
+) -> Result<Json<ReplayResponse>, ApiError> {
+    let capture = sqlx::query!(
+        r#"
+        SELECT method, path, headers, body
+        FROM request_captures
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("request_capture not found"))?;
+
+    let mut builder = Request::builder()
+        .method(capture.method.as_str())
+        .uri(capture.path.as_str())
+        .header(REPLAY_HEADER, id.to_string());
+    if let Some(headers) = capture.headers.as_object() {
+        for (name, value) in headers {
+            if let Some(value) = value.as_str() {
+                builder = builder.header(name.as_str(), value);
+            }
+        }
+    }
+    if let Some(authorization) = caller.get(header::AUTHORIZATION) {
+        builder = builder.header(header::AUTHORIZATION, authorization);
+    }
+    let req = builder
+        .body(Body::from(capture.body))
+        .map_err(|e| ApiError::internal(format!("captured request is not replayable: {e}")))?;
+
+    let response = build_app(state)
+        .oneshot(req)
+        .await
+        .map_err(|e| ApiError::internal(format!("replay failed: {e}")))?;
+    let status = response.status().as_u16();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .map_err(|e| ApiError::internal(format!("replay failed: {e}")))?;
+    let body = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| serde_json::json!(String::from_utf8_lossy(&bytes)));
+
+    Ok(Json(ReplayResponse { status, body }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_replaces_only_existing_paths() {
+        let mut body = serde_json::json!({
+            "amount": 1000,
+            "card": { "number": "4242424242424242", "exp": "12/30" }
+        });
+        redact(
+            &mut body,
+            &["card.number".to_string(), "missing.path".to_string()],
+        );
+
+        assert_eq!(body["card"]["number"], REDACTED);
+        assert_eq!(body["card"]["exp"], "12/30");
+        assert_eq!(body["amount"], 1000);
+        assert!(body.get("missing").is_none());
+    }
+
+    #[test]
+    fn card_details_are_redacted_without_configured_paths() {
+        let body = br#"{"card":{"number":"4242424242424242","cvc":"123","exp_month":12}}"#;
+        let (body, _) = captured_body(body, &[], true);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(body["card"]["number"], REDACTED);
+        assert_eq!(body["card"]["cvc"], REDACTED);
+        assert_eq!(body["card"]["exp_month"], 12);
+    }
+
+    #[test]
+    fn unparseable_card_bodies_are_dropped() {
+        let body = br#"{"card":{"number":"4242424242424242","#;
+        assert_eq!(captured_body(body, &[], true), (String::new(), false));
+
+        // Elsewhere the raw body is still kept for debugging
+        let (kept, _) = captured_body(body, &[], false);
+        assert_eq!(kept.as_bytes(), body);
+    }
+
+    #[test]
+    fn captured_body_is_capped_on_a_char_boundary() {
+        let big = "€".repeat(MAX_CAPTURED_BODY_BYTES);
+        let (body, truncated) = captured_body(big.as_bytes(), &[], false);
+
+        assert!(truncated);
+        assert!(body.len() <= MAX_CAPTURED_BODY_BYTES);
+        assert!(body.chars().all(|c| c == '€'));
+    }
+}
@@ -0,0 +1,50 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::{HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use mini_stripe_types::signature::{RESPONSE_SIGNATURE_HEADER, sign_response};
+
+use crate::state::AppState;
+
+// Same bound request capture buffers requests to; bigger or streamed responses go out unsigned
+const MAX_SIGNED_BODY_BYTES: u64 = 2 * 1024 * 1024;
+
+// Middleware, only layered when config.response_signing_secret is set. Signs the exact bytes of
+// every mutating response so a consumer can store it as tamper-evident.
+pub async fn sign(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let mutating = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let response = next.run(req).await;
+    let Some(secret) = state.config.response_signing_secret.as_deref() else {
+        return response;
+    };
+
+    let fits = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|len| len <= MAX_SIGNED_BODY_BYTES);
+    if !mutating || !fits {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_SIGNED_BODY_BYTES as usize).await else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "could not read response body",
+        )
+            .into_response();
+    };
+
+    let signature = sign_response(secret, Utc::now().timestamp(), &bytes);
+    // Hex and digits only, so always a valid header value
+    parts.headers.insert(
+        RESPONSE_SIGNATURE_HEADER,
+        HeaderValue::from_str(&signature).unwrap(),
+    );
+    Response::from_parts(parts, Body::from(bytes))
+}
@@ -0,0 +1,284 @@
+// Every route path, in axum 0.8 `{param}` syntax. The router registers these constants and the
+// tests build request paths with the functions below, so neither can drift from the other.
+use std::fmt::Display;
+
+pub const HEALTH: &str = "/health";
+pub const READYZ: &str = "/readyz";
+pub const METRICS: &str = "/metrics";
+
+pub const PAYMENT_INTENTS: &str = "/v1/payment_intents";
+pub const PAYMENT_INTENTS_BULK_CANCEL: &str = "/v1/payment_intents/bulk_cancel";
+pub const PAYMENT_INTENT: &str = "/v1/payment_intents/{id}";
+pub const PAYMENT_INTENT_ARCHIVE: &str = "/v1/payment_intents/{id}/archive";
+pub const PAYMENT_INTENT_TRANSITIONS: &str = "/v1/payment_intents/{id}/transitions";
+pub const PAYMENT_INTENT_CONFIRM: &str = "/v1/payment_intents/{id}/confirm";
+pub const PAYMENT_INTENT_CAPTURE: &str = "/v1/payment_intents/{id}/capture";
+pub const PAYMENT_INTENT_WAIT: &str = "/v1/payment_intents/{id}/wait";
+
+pub const CHARGES: &str = "/v1/charges";
+pub const CHARGE: &str = "/v1/charges/{id}";
+pub const REFUNDS: &str = "/v1/refunds";
+pub const REFUND: &str = "/v1/refunds/{id}";
+pub const CUSTOMERS: &str = "/v1/customers";
+pub const CUSTOMER: &str = "/v1/customers/{id}";
+pub const PAYMENT_METHODS: &str = "/v1/payment_methods";
+pub const PAYMENT_METHOD: &str = "/v1/payment_methods/{id}";
+pub const PAYMENT_METHOD_ATTACH: &str = "/v1/payment_methods/{id}/attach";
+pub const PAYMENT_METHOD_DETACH: &str = "/v1/payment_methods/{id}/detach";
+pub const BALANCE: &str = "/v1/balance";
+pub const BALANCE_TRANSACTIONS: &str = "/v1/balance_transactions";
+pub const BALANCE_TRANSACTION: &str = "/v1/balance_transactions/{id}";
+pub const PAYOUTS: &str = "/v1/payouts";
+pub const PAYOUT: &str = "/v1/payouts/{id}";
+
+pub const WEBHOOK_ENDPOINTS: &str = "/v1/webhook_endpoints";
+pub const WEBHOOK_ENDPOINTS_SYNC: &str = "/v1/webhook_endpoints/sync";
+pub const WEBHOOK_ENDPOINT: &str = "/v1/webhook_endpoints/{id}";
+pub const WEBHOOK_ENDPOINT_DELIVERIES: &str = "/v1/webhook_endpoints/{id}/deliveries";
+pub const WEBHOOK_ENDPOINT_STATS: &str = "/v1/webhook_endpoints/{id}/stats";
+pub const WEBHOOK_ENDPOINT_REVEAL_SECRET: &str = "/v1/webhook_endpoints/{id}/reveal_secret";
+pub const WEBHOOK_ENDPOINT_PREVIEW: &str = "/v1/webhook_endpoints/{id}/preview";
+pub const WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE: &str =
+    "/v1/webhook_endpoints/{id}/client_certificate";
+
+pub const EVENT_REDELIVER: &str = "/v1/events/{id}/redeliver";
+
+pub const EXAMPLE: &str = "/v1/examples/{flow}";
+
+pub const ADMIN_OUTBOX_DRAIN: &str = "/v1/admin/outbox/drain";
+pub const ADMIN_OUTBOX_DRAIN_STATUS: &str = "/v1/admin/outbox/drain_status";
+pub const ADMIN_OUTBOX_RESUME: &str = "/v1/admin/outbox/resume";
+pub const ADMIN_IDEMPOTENCY_STATS: &str = "/v1/admin/idempotency/stats";
+pub const ADMIN_IDEMPOTENCY_CLEANUP: &str = "/v1/admin/idempotency/cleanup";
+pub const ADMIN_SEED: &str = "/v1/admin/seed";
+pub const ADMIN_DIAGNOSTICS: &str = "/v1/admin/diagnostics";
+pub const ADMIN_WORKERS: &str = "/v1/admin/workers";
+pub const ADMIN_EVENT_EXPORT: &str = "/v1/admin/event_export";
+pub const ADMIN_FAULTS: &str = "/v1/admin/faults";
+pub const ADMIN_REQUEST_CAPTURES: &str = "/v1/admin/request_captures";
+pub const ADMIN_REQUEST_CAPTURE_REPLAY: &str = "/v1/admin/request_captures/{id}/replay";
+
+// The worker's internal://echo target (worker/src/deliver.rs builds the same path)
+pub const SANDBOX_ECHO: &str = "/v1/sandbox/echo/{endpoint_id}";
+pub const SANDBOX_ECHO_DELIVERIES: &str = "/v1/sandbox/echo_deliveries";
+
+// Prefixes the request capture middleware tells API, admin and sandbox traffic apart by
+pub const API_PREFIX: &str = "/v1/";
+pub const ADMIN_PREFIX: &str = "/v1/admin/";
+pub const SANDBOX_PREFIX: &str = "/v1/sandbox/";
+
+// Every template above, for the check that each one reaches a registered route
+pub const ALL: &[&str] = &[
+    HEALTH,
+    READYZ,
+    METRICS,
+    PAYMENT_INTENTS,
+    PAYMENT_INTENTS_BULK_CANCEL,
+    PAYMENT_INTENT,
+    PAYMENT_INTENT_ARCHIVE,
+    PAYMENT_INTENT_TRANSITIONS,
+    PAYMENT_INTENT_CONFIRM,
+    PAYMENT_INTENT_CAPTURE,
+    PAYMENT_INTENT_WAIT,
+    CHARGES,
+    CHARGE,
+    REFUNDS,
+    REFUND,
+    CUSTOMERS,
+    CUSTOMER,
+    PAYMENT_METHODS,
+    PAYMENT_METHOD,
+    PAYMENT_METHOD_ATTACH,
+    PAYMENT_METHOD_DETACH,
+    BALANCE,
+    BALANCE_TRANSACTIONS,
+    BALANCE_TRANSACTION,
+    PAYOUTS,
+    PAYOUT,
+    WEBHOOK_ENDPOINTS,
+    WEBHOOK_ENDPOINTS_SYNC,
+    WEBHOOK_ENDPOINT,
+    WEBHOOK_ENDPOINT_DELIVERIES,
+    WEBHOOK_ENDPOINT_STATS,
+    WEBHOOK_ENDPOINT_REVEAL_SECRET,
+    WEBHOOK_ENDPOINT_PREVIEW,
+    WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE,
+    EVENT_REDELIVER,
+    EXAMPLE,
+    ADMIN_OUTBOX_DRAIN,
+    ADMIN_OUTBOX_DRAIN_STATUS,
+    ADMIN_OUTBOX_RESUME,
+    ADMIN_IDEMPOTENCY_STATS,
+    ADMIN_IDEMPOTENCY_CLEANUP,
+    ADMIN_SEED,
+    ADMIN_DIAGNOSTICS,
+    ADMIN_WORKERS,
+    ADMIN_EVENT_EXPORT,
+    ADMIN_FAULTS,
+    ADMIN_REQUEST_CAPTURES,
+    ADMIN_REQUEST_CAPTURE_REPLAY,
+    SANDBOX_ECHO,
+    SANDBOX_ECHO_DELIVERIES,
+];
+
+// Fills a template's single path parameter, whatever it is called
+fn fill(template: &str, param: impl Display) -> String {
+    let start = template.find('{').expect("route template has a parameter");
+    let end = template[start..]
+        .find('}')
+        .expect("unclosed route parameter")
+        + start;
+    format!("{}{param}{}", &template[..start], &template[end + 1..])
+}
+
+pub fn payment_intent(id: impl Display) -> String {
+    fill(PAYMENT_INTENT, id)
+}
+
+pub fn payment_intent_archive(id: impl Display) -> String {
+    fill(PAYMENT_INTENT_ARCHIVE, id)
+}
+
+pub fn payment_intent_transitions(id: impl Display) -> String {
+    fill(PAYMENT_INTENT_TRANSITIONS, id)
+}
+
+pub fn payment_intent_confirm(id: impl Display) -> String {
+    fill(PAYMENT_INTENT_CONFIRM, id)
+}
+
+pub fn payment_intent_capture(id: impl Display) -> String {
+    fill(PAYMENT_INTENT_CAPTURE, id)
+}
+
+pub fn payment_intent_wait(id: impl Display) -> String {
+    fill(PAYMENT_INTENT_WAIT, id)
+}
+
+pub fn charge(id: impl Display) -> String {
+    fill(CHARGE, id)
+}
+
+pub fn refund(id: impl Display) -> String {
+    fill(REFUND, id)
+}
+
+pub fn customer(id: impl Display) -> String {
+    fill(CUSTOMER, id)
+}
+
+pub fn payment_method(id: impl Display) -> String {
+    fill(PAYMENT_METHOD, id)
+}
+
+pub fn payment_method_attach(id: impl Display) -> String {
+    fill(PAYMENT_METHOD_ATTACH, id)
+}
+
+pub fn payment_method_detach(id: impl Display) -> String {
+    fill(PAYMENT_METHOD_DETACH, id)
+}
+
+pub fn balance_transaction(id: impl Display) -> String {
+    fill(BALANCE_TRANSACTION, id)
+}
+
+pub fn payout(id: impl Display) -> String {
+    fill(PAYOUT, id)
+}
+
+pub fn webhook_endpoint(id: impl Display) -> String {
+    fill(WEBHOOK_ENDPOINT, id)
+}
+
+pub fn webhook_endpoint_deliveries(id: impl Display) -> String {
+    fill(WEBHOOK_ENDPOINT_DELIVERIES, id)
+}
+
+pub fn webhook_endpoint_stats(id: impl Display) -> String {
+    fill(WEBHOOK_ENDPOINT_STATS, id)
+}
+
+pub fn webhook_endpoint_preview(id: impl Display) -> String {
+    fill(WEBHOOK_ENDPOINT_PREVIEW, id)
+}
+
+pub fn webhook_endpoint_reveal_secret(id: impl Display) -> String {
+    fill(WEBHOOK_ENDPOINT_REVEAL_SECRET, id)
+}
+
+pub fn webhook_endpoint_client_certificate(id: impl Display) -> String {
+    fill(WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE, id)
+}
+
+pub fn event_redeliver(id: impl Display) -> String {
+    fill(EVENT_REDELIVER, id)
+}
+
+pub fn example(flow: impl Display) -> String {
+    fill(EXAMPLE, flow)
+}
+
+pub fn admin_request_capture_replay(id: impl Display) -> String {
+    fill(ADMIN_REQUEST_CAPTURE_REPLAY, id)
+}
+
+pub fn sandbox_echo(endpoint_id: impl Display) -> String {
+    fill(SANDBOX_ECHO, endpoint_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{app::build_app, state::AppState};
+
+    #[test]
+    fn fill_replaces_the_parameter() {
+        assert_eq!(
+            payment_intent_confirm("pi_1"),
+            "/v1/payment_intents/pi_1/confirm"
+        );
+        assert_eq!(sandbox_echo(7), "/v1/sandbox/echo/7");
+    }
+
+    // OPTIONS is answered by the router itself (204 with Allow) without running a handler, so
+    // this needs no database; an unregistered path would be the JSON 404 instead
+    #[tokio::test]
+    async fn every_route_resolves() {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        // Sandbox-only routes are a 404 outside sandbox mode (see permissions::TABLE)
+        let mut state = AppState::new(pool);
+        state.config.sandbox_mode = true;
+        let app = build_app(state);
+
+        for template in ALL {
+            let path = if template.contains('{') {
+                fill(template, Uuid::new_v4())
+            } else {
+                template.to_string()
+            };
+            let res = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(Method::OPTIONS)
+                        .uri(&path)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT, "{template}");
+        }
+    }
+}
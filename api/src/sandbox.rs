@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use mini_stripe_types::error::codes;
+use mini_stripe_types::signature::{self, SIGNATURE_HEADER, SignatureScheme};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::headers::require_ascii_header;
+use crate::state::AppState;
+use crate::webhook_endpoints::decrypt_secret;
+
+// Webhook endpoints with this URL are delivered to the in-process receiver below
+pub const ECHO_URL: &str = "internal://echo";
+
+const ECHO_CAPACITY: usize = 20;
+
+#[derive(Clone, Serialize)]
+pub struct EchoDelivery {
+    pub webhook_endpoint_id: Uuid,
+    pub received_at: DateTime<Utc>,
+    pub event: serde_json::Value,
+}
+
+// Remembers the last few deliveries in memory (most recent first)
+#[derive(Clone, Default)]
+pub struct EchoReceiver {
+    deliveries: Arc<Mutex<VecDeque<EchoDelivery>>>,
+}
+
+impl EchoReceiver {
+    pub fn record(&self, delivery: EchoDelivery) {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        deliveries.push_front(delivery);
+        deliveries.truncate(ECHO_CAPACITY);
+    }
+
+    pub fn recent(&self) -> Vec<EchoDelivery> {
+        self.deliveries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// The worker posts `internal://echo` deliveries here; we check the signature like a real receiver would
+pub async fn receive_echo_delivery(
+    State(state): State<AppState>,
+    Path(endpoint_id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let endpoint = sqlx::query!(
+        r#"
+        SELECT secret, signature_scheme
+        FROM webhook_endpoints
+        WHERE id = $1 AND url = $2
+        "#,
+        endpoint_id,
+        ECHO_URL
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("echo webhook_endpoint not found"))?;
+
+    let signature = require_ascii_header(&headers, SIGNATURE_HEADER, codes::SIGNATURE_INVALID)?
+        .unwrap_or_default();
+
+    let scheme = SignatureScheme::parse(&endpoint.signature_scheme).unwrap_or_default();
+    let secret = decrypt_secret(&state, &endpoint.secret)?;
+    if !signature::verify_signature(scheme, &secret, signature, &body) {
+        return Err(ApiError::bad_request(
+            codes::SIGNATURE_INVALID,
+            "webhook signature verification failed",
+        ));
+    }
+
+    let event = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::bad_request("parameter_invalid", format!("invalid event: {e}")))?;
+
+    state.echo.record(EchoDelivery {
+        webhook_endpoint_id: endpoint_id,
+        received_at: Utc::now(),
+        event,
+    });
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_echo_deliveries(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<EchoDelivery>>, ApiError> {
+    Ok(Json(state.echo.recent()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_receiver_keeps_most_recent() {
+        let echo = EchoReceiver::default();
+        for i in 0..(ECHO_CAPACITY + 5) {
+            echo.record(EchoDelivery {
+                webhook_endpoint_id: Uuid::nil(),
+                received_at: Utc::now(),
+                event: serde_json::json!({ "n": i }),
+            });
+        }
+
+        let recent = echo.recent();
+        assert_eq!(recent.len(), ECHO_CAPACITY);
+        assert_eq!(recent[0].event["n"], ECHO_CAPACITY + 4);
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use axum::{Json, extract::State};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::payment_intents::PaymentIntentStatus;
+use crate::payment_intents::storage::fetch_payment_intent;
+use crate::sandbox::ECHO_URL;
+use crate::services::DomainError;
+use crate::services::payment_intents::{
+    CreatePaymentIntentParams, IdempotencyKey, PaymentIntentService,
+};
+use crate::state::AppState;
+use crate::webhook_endpoints::{self, CreateWebhookEndpointRequest};
+
+// What the fixture intent should end up as after seeding
+#[derive(Clone, Copy)]
+enum Target {
+    RequiresConfirmation,
+    Succeeded,
+    Abandoned,
+}
+
+// Keyed by idempotency key so re-running the seeder finds the same intents
+const PAYMENT_INTENT_FIXTURES: &[(&str, i64, &str, Target)] = &[
+    (
+        "seed_pi_requires_confirmation",
+        1000,
+        "gbp",
+        Target::RequiresConfirmation,
+    ),
+    ("seed_pi_succeeded", 2500, "gbp", Target::Succeeded),
+    ("seed_pi_abandoned", 500, "usd", Target::Abandoned),
+];
+
+#[derive(Serialize)]
+pub struct SeededPaymentIntent {
+    pub key: &'static str,
+    pub id: Uuid,
+    pub status: PaymentIntentStatus,
+}
+
+#[derive(Serialize)]
+pub struct SeedSummary {
+    pub payment_intents: Vec<SeededPaymentIntent>,
+    pub webhook_endpoint_id: Uuid,
+}
+
+// Builds the demo fixture set through the real services, so events exist exactly as in production.
+// Safe to run repeatedly: nothing is created twice.
+pub async fn seed(State(state): State<AppState>) -> Result<Json<SeedSummary>, ApiError> {
+    let webhook_endpoint_id = seed_echo_endpoint(&state).await?;
+
+    let mut payment_intents = Vec::new();
+    for &(key, amount, currency, target) in PAYMENT_INTENT_FIXTURES {
+        let created = PaymentIntentService::new(
+            &state.db,
+            &state.config,
+            &state.in_flight,
+            &state.metrics,
+            &state.status_changes,
+            &state.faults,
+        )
+        .create(
+            CreatePaymentIntentParams {
+                amount,
+                currency: Some(currency.to_string()),
+                confirm: None,
+                metadata: Default::default(),
+                capture_method: None,
+                description: None,
+                customer: None,
+                deadline: Default::default(),
+            },
+            Some(IdempotencyKey(key.to_string())),
+        )
+        .await?
+        .into_inner();
+        let id = serde_json::from_value(serde_json::to_value(created)?["id"].take())?;
+
+        // A replayed create returns the original body, so check where the intent really is
+        let current = fetch_payment_intent(&state.db, id)
+            .await?
+            .ok_or_else(|| ApiError::internal("seeded payment_intent disappeared"))?;
+
+        if current.status == PaymentIntentStatus::RequiresConfirmation {
+            match target {
+                Target::RequiresConfirmation => {}
+                Target::Succeeded => {
+                    PaymentIntentService::new(
+                        &state.db,
+                        &state.config,
+                        &state.in_flight,
+                        &state.metrics,
+                        &state.status_changes,
+                        &state.faults,
+                    )
+                    .confirm(id, Default::default())
+                    .await?;
+                }
+                // Confirming with a zero max age goes through the real abandonment path
+                Target::Abandoned => {
+                    let mut config = state.config.clone();
+                    config.max_confirmable_age = Some(Duration::ZERO);
+                    match PaymentIntentService::new(
+                        &state.db,
+                        &config,
+                        &state.in_flight,
+                        &state.metrics,
+                        &state.status_changes,
+                        &state.faults,
+                    )
+                    .confirm(id, Default::default())
+                    .await
+                    {
+                        Err(DomainError::ExpiredForConfirmation) => {}
+                        Err(e) => return Err(e.into()),
+                        Ok(_) => return Err(ApiError::internal("seeded intent was not abandoned")),
+                    }
+                }
+            }
+        }
+
+        let pi = fetch_payment_intent(&state.db, id)
+            .await?
+            .ok_or_else(|| ApiError::internal("seeded payment_intent disappeared"))?;
+
+        payment_intents.push(SeededPaymentIntent {
+            key,
+            id,
+            status: pi.status,
+        });
+    }
+
+    Ok(Json(SeedSummary {
+        payment_intents,
+        webhook_endpoint_id,
+    }))
+}
+
+async fn seed_echo_endpoint(state: &AppState) -> Result<Uuid, ApiError> {
+    let existing = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM webhook_endpoints
+        WHERE url = $1
+        ORDER BY created_at
+        LIMIT 1
+        "#,
+        ECHO_URL
+    )
+    .fetch_optional(&state.db)
+    .await?;
+
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+
+    let (_, Json(created)) = webhook_endpoints::create_webhook_endpoint(
+        State(state.clone()),
+        Json(CreateWebhookEndpointRequest {
+            url: ECHO_URL.to_string(),
+            signature_scheme: None,
+            payload_template: None,
+        }),
+    )
+    .await?;
+
+    Ok(created.id)
+}
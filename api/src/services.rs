@@ -0,0 +1,87 @@
+pub mod customers;
+pub mod idempotency;
+pub mod payment_intents;
+pub mod payment_methods;
+pub mod payouts;
+pub mod refunds;
+
+use mini_stripe_types::payment_intents::PaymentIntentStatus;
+use uuid::Uuid;
+
+use crate::currency::Currency;
+use crate::events_outbox::EventError;
+use crate::processor::PaymentError;
+
+// Business failures independent of any transport; error.rs maps them to HTTP in one place
+#[derive(Debug, thiserror::Error)]
+pub enum DomainError {
+    #[error("{0}")]
+    InvalidParameter(String),
+    #[error("currency must be one of: {}", .0.join(", "))]
+    CurrencyNotAllowed(Vec<String>),
+    #[error(
+        "amount must be at least {minimum} {} ({} {}), the minimum for that currency",
+        .currency.code, .currency.format_major(*.minimum), .currency.code
+    )]
+    AmountTooSmall { currency: Currency, minimum: i64 },
+    #[error(
+        "amount must be at most {maximum} {} ({} {}), the maximum for any currency",
+        .currency.code, .currency.format_major(*.maximum), .currency.code
+    )]
+    AmountTooLarge { currency: Currency, maximum: i64 },
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("idempotency key reused with different request")]
+    IdempotencyKeyReused,
+    #[error("cannot confirm payment_intent in status '{0}'")]
+    UnexpectedState(PaymentIntentStatus),
+    #[error("cannot capture payment_intent in status '{0}'")]
+    NotCapturable(PaymentIntentStatus),
+    // Amount and currency only change before the intent is confirmed
+    #[error("cannot change the amount or currency of payment_intent in status '{0}'")]
+    NotUpdatable(PaymentIntentStatus),
+    // Only succeeded and canceled intents are finished enough to hide from lists
+    #[error("cannot archive payment_intent in status '{0}'")]
+    NotArchivable(PaymentIntentStatus),
+    // Only a succeeded intent has a charge to refund
+    #[error("cannot refund payment_intent in status '{0}'")]
+    NotRefundable(PaymentIntentStatus),
+    #[error("charge has already been refunded in full")]
+    ChargeAlreadyRefunded,
+    #[error("refund amount {amount} is more than the {remaining} left unrefunded on the charge")]
+    RefundTooLarge { amount: i64, remaining: i64 },
+    // Only what is already available can be paid out, not what is still pending
+    #[error("payout amount {amount} is more than the {available} {currency} available")]
+    InsufficientBalance {
+        amount: i64,
+        available: i64,
+        currency: &'static str,
+    },
+    #[error("payment_intent is too old to confirm and has been canceled")]
+    ExpiredForConfirmation,
+    #[error("your card was declined")]
+    CardDeclined,
+    // From the processor: its code (card_declined, expired_card, ...) and message
+    #[error("{}", .0.message)]
+    PaymentFailed(PaymentError),
+    #[error("the acquirer did not respond in time")]
+    AcquirerTimeout,
+    #[error("too many open waits; retry shortly or poll instead")]
+    TooManyWaiters,
+    #[error("the request deadline passed before the change was committed; nothing was written")]
+    DeadlineExceeded,
+    #[error("temporarily unavailable (sandbox fault {0})")]
+    FaultInjected(Uuid),
+    #[error("{0}")]
+    Internal(String),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+    #[error(transparent)]
+    Event(#[from] EventError),
+}
+
+impl From<serde_json::Error> for DomainError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Internal(format!("json error: {e}"))
+    }
+}
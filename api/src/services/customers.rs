@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::customers::CustomerResponse;
+use crate::customers::storage::{
+    CustomerChanges, NewCustomer, delete_customer, insert_customer, lock_customer, update_customer,
+};
+use crate::in_flight::InFlight;
+use crate::payment_methods::storage::detach_customer_payment_methods;
+use crate::services::DomainError;
+use crate::services::payment_intents::{validate_description, validate_metadata};
+
+const MAX_EMAIL_LEN: usize = 512;
+const MAX_NAME_LEN: usize = 256;
+
+pub struct CreateCustomerParams {
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+// Fields left None keep their value; an empty string clears one
+pub struct UpdateCustomerParams {
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    // Merged into the existing keys; an empty value removes the key
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+pub struct CustomerService<'a> {
+    db: &'a PgPool,
+    in_flight: &'a InFlight,
+}
+
+impl<'a> CustomerService<'a> {
+    pub fn new(db: &'a PgPool, in_flight: &'a InFlight) -> Self {
+        Self { db, in_flight }
+    }
+
+    pub async fn create(
+        &self,
+        params: CreateCustomerParams,
+    ) -> Result<CustomerResponse, DomainError> {
+        let _in_flight = self.in_flight.track("create customer");
+        validate_fields(
+            params.email.as_deref(),
+            params.name.as_deref(),
+            params.description.as_deref(),
+        )?;
+        validate_metadata(&params.metadata)
+            .map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+
+        let customer = insert_customer(
+            self.db,
+            NewCustomer {
+                email: params.email.filter(|s| !s.is_empty()),
+                name: params.name.filter(|s| !s.is_empty()),
+                description: params.description.filter(|s| !s.is_empty()),
+                metadata: serde_json::json!(params.metadata),
+            },
+        )
+        .await?;
+        Ok(CustomerResponse::from(customer))
+    }
+
+    pub async fn update(
+        &self,
+        id: Uuid,
+        params: UpdateCustomerParams,
+    ) -> Result<CustomerResponse, DomainError> {
+        let _in_flight = self.in_flight.track(format!("update customer {id}"));
+        validate_fields(
+            params.email.as_deref(),
+            params.name.as_deref(),
+            params.description.as_deref(),
+        )?;
+
+        let mut tx = self.db.begin().await?;
+        let before = lock_customer(&mut *tx, id)
+            .await?
+            .ok_or(DomainError::NotFound("customer"))?;
+
+        let mut metadata: BTreeMap<String, String> =
+            serde_json::from_value(before.metadata.clone()).unwrap_or_default();
+        for (key, value) in params.metadata.unwrap_or_default() {
+            if value.is_empty() {
+                metadata.remove(&key);
+            } else {
+                metadata.insert(key, value);
+            }
+        }
+        validate_metadata(&metadata).map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+
+        let changes = CustomerChanges {
+            email: merge(params.email, before.email),
+            name: merge(params.name, before.name),
+            description: merge(params.description, before.description),
+            metadata: serde_json::json!(metadata),
+        };
+        let after = update_customer(&mut *tx, id, changes).await?;
+        tx.commit().await?;
+
+        Ok(CustomerResponse::from(after))
+    }
+
+    // The customer's intents keep pointing at it, so this only hides it. Its payment methods
+    // are detached, as on Stripe.
+    pub async fn delete(&self, id: Uuid) -> Result<(), DomainError> {
+        let _in_flight = self.in_flight.track(format!("delete customer {id}"));
+        let mut tx = self.db.begin().await?;
+        if !delete_customer(&mut *tx, id).await? {
+            return Err(DomainError::NotFound("customer"));
+        }
+        detach_customer_payment_methods(&mut *tx, id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+fn merge(requested: Option<String>, current: Option<String>) -> Option<String> {
+    match requested {
+        Some(value) if value.is_empty() => None,
+        Some(value) => Some(value),
+        None => current,
+    }
+}
+
+// Before any DB call, like validate_new_payment_intent. Empty strings pass: they mean "unset".
+fn validate_fields(
+    email: Option<&str>,
+    name: Option<&str>,
+    description: Option<&str>,
+) -> Result<(), DomainError> {
+    validate_email_and_name(email, name)
+        .map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+    if let Some(description) = description {
+        validate_description(description)
+            .map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+    }
+    Ok(())
+}
+
+fn validate_email_and_name(email: Option<&str>, name: Option<&str>) -> Result<(), &'static str> {
+    if let Some(email) = email.filter(|e| !e.is_empty()) {
+        if email.len() > MAX_EMAIL_LEN {
+            return Err("email must be at most 512 bytes");
+        }
+        // Not a full address check; only enough to catch a value in the wrong field
+        if !email.contains('@') || email.chars().any(char::is_whitespace) {
+            return Err("email must be an email address");
+        }
+        if email.contains('\0') {
+            return Err("email cannot contain NUL characters");
+        }
+    }
+    if let Some(name) = name {
+        if name.len() > MAX_NAME_LEN {
+            return Err("name must be at most 256 bytes");
+        }
+        if name.contains('\0') {
+            return Err("name cannot contain NUL characters");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_checks_email_and_name() {
+        assert!(validate_email_and_name(Some("jo@example.com"), Some("Jo")).is_ok());
+        // Empty clears the field on update
+        assert!(validate_email_and_name(Some(""), Some("")).is_ok());
+        assert_eq!(
+            validate_email_and_name(Some("jo"), None).unwrap_err(),
+            "email must be an email address"
+        );
+        assert_eq!(
+            validate_email_and_name(Some("jo @example.com"), None).unwrap_err(),
+            "email must be an email address"
+        );
+        assert_eq!(
+            validate_email_and_name(None, Some("a\0b")).unwrap_err(),
+            "name cannot contain NUL characters"
+        );
+        assert_eq!(
+            validate_email_and_name(None, Some(&"x".repeat(257))).unwrap_err(),
+            "name must be at most 256 bytes"
+        );
+    }
+
+    #[test]
+    fn merge_clears_on_empty_and_keeps_on_none() {
+        assert_eq!(merge(Some(String::new()), Some("a".into())), None);
+        assert_eq!(merge(None, Some("a".into())), Some("a".into()));
+        assert_eq!(merge(Some("b".into()), Some("a".into())), Some("b".into()));
+    }
+}
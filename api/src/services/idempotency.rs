@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::services::DomainError;
+
+// Idempotency for job-style endpoints, whose work spans several calls. The stored "response" is
+// just the job id: a replay reports that job's current state instead of starting another one.
+pub enum JobKey {
+    // New key, reserved in the caller's transaction; call record_job_id before committing
+    Reserved,
+    // Same key and request as an earlier call
+    Replayed(Uuid),
+    // Same key, different request. Commit anyway so the conflict is counted
+    Conflict,
+}
+
+// Same idempotency_keys table, and the same replay/conflict counters, as the create endpoint.
+// A concurrent call with the same key blocks on the insert until the first one commits.
+pub async fn reserve_job_key(
+    tx: &mut Transaction<'_, Postgres>,
+    key: &str,
+    endpoint: &str,
+    request_hash: &str,
+    ttl: Option<Duration>,
+) -> Result<JobKey, DomainError> {
+    let reserved = sqlx::query!(
+        r#"
+        INSERT INTO idempotency_keys (key, endpoint, request_hash, response_body, expires_at)
+        VALUES ($1, $2, $3, '{}'::jsonb, now() + make_interval(secs => $4))
+        ON CONFLICT (key, endpoint) DO NOTHING
+        RETURNING key
+        "#,
+        key,
+        endpoint,
+        request_hash,
+        ttl.map(|ttl| ttl.as_secs_f64())
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    if reserved.is_some() {
+        return Ok(JobKey::Reserved);
+    }
+
+    let row = sqlx::query!(
+        r#"
+        UPDATE idempotency_keys
+        SET replay_count = replay_count + (request_hash = $3)::int,
+            conflict_count = conflict_count + (request_hash <> $3)::int
+        WHERE key = $1 AND endpoint = $2
+        RETURNING request_hash, job_id
+        "#,
+        key,
+        endpoint,
+        request_hash
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    if row.request_hash != request_hash {
+        return Ok(JobKey::Conflict);
+    }
+
+    // The job is created in the same transaction that reserves the key, so this is always set
+    row.job_id.map(JobKey::Replayed).ok_or_else(|| {
+        DomainError::Internal("idempotency record exists but has no job_id".to_string())
+    })
+}
+
+pub async fn record_job_id(
+    tx: &mut Transaction<'_, Postgres>,
+    key: &str,
+    endpoint: &str,
+    job_id: Uuid,
+) -> Result<(), DomainError> {
+    sqlx::query!(
+        r#"
+        UPDATE idempotency_keys
+        SET job_id = $3, response_body = jsonb_build_object('job_id', $3::uuid)
+        WHERE key = $1 AND endpoint = $2
+        "#,
+        key,
+        endpoint,
+        job_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Whether the key was already used on the endpoint. Only a shortcut: the insert in the caller's
+// transaction still decides who owns a key two requests race for.
+pub async fn idempotency_key_exists(
+    db: &PgPool,
+    key: &str,
+    endpoint: &str,
+) -> Result<bool, DomainError> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM idempotency_keys WHERE key = $1 AND endpoint = $2) AS "exists!"
+        "#,
+        key,
+        endpoint
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(exists)
+}
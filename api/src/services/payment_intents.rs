@@ -0,0 +1,2254 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use mini_stripe_types::events::{self, ChargeEventData, PaymentIntentEventData};
+use mini_stripe_types::payment_intents::PaymentIntentStatus;
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::audit_log::insert_audit_entry;
+use crate::balance::storage::{NewBalanceTransaction, insert_balance_transaction};
+use crate::balance::{BalancePolicy, BalanceTransactionType};
+use crate::charges::ChargeResponse;
+use crate::charges::storage::insert_charge;
+use crate::config::Config;
+use crate::currency::Currency;
+use crate::customers::storage::fetch_customer;
+use crate::deadline::Deadline;
+use crate::events_outbox::{LatestEventSummary, insert_event, latest_payment_intent_event};
+use crate::faults::Faults;
+use crate::in_flight::InFlight;
+use crate::latency::{self, SimulatedLatency};
+use crate::metrics::{self, Metrics};
+use crate::pagination::PageLimit;
+use crate::payment_intents::PaymentIntentResponse;
+use crate::payment_intents::storage::{
+    NewPaymentIntent, PaymentIntent, PaymentIntentChanges, PaymentIntentTransition,
+    abandon_payment_intent, archive_payment_intent, cancel_payment_intent, capture_payment_intent,
+    expire_payment_intent, fetch_payment_intent, fetch_payment_intent_by_natural_key,
+    insert_payment_intent, insert_transition, list_payment_intents, list_payment_intents_before,
+    list_transitions, lock_expired_payment_intents, lock_payment_intent,
+    mark_payment_intent_confirmed, mark_payment_intent_unconfirmed, update_payment_intent,
+};
+use crate::payment_methods::storage::fetch_payment_method;
+use crate::processor::{PaymentProcessor, ProcessorOutcome, TestProcessor};
+use crate::services::DomainError;
+use crate::services::idempotency::{
+    JobKey, idempotency_key_exists, record_job_id, reserve_job_key,
+};
+use crate::simulation::SimulatedOutcome;
+use crate::status_changes::{self, StatusChanges};
+
+const IDEMPOTENCY_ENDPOINT: &str = "POST /v1/payment_intents";
+const BULK_CANCEL_IDEMPOTENCY_ENDPOINT: &str = "POST /v1/payment_intents/bulk_cancel";
+
+// Version of the PaymentIntentResponse shape stored in idempotency_keys.response_body.
+// Bump it whenever the response gains a field and add the matching step to upgrade_response_body
+const RESPONSE_BODY_VERSION: i32 = 10;
+
+// Keys are part of a btree primary key, so they need a bound well under Postgres' entry limit
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+// Stripe's metadata limits
+const MAX_METADATA_KEYS: usize = 50;
+const MAX_METADATA_KEY_LEN: usize = 40;
+const MAX_METADATA_VALUE_LEN: usize = 500;
+const MAX_DESCRIPTION_LEN: usize = 1000;
+
+const CAPTURE_METHODS: &[&str] = &["automatic", "manual"];
+
+// Waits: open waits per process unless configured, and the longest a wait can be
+const DEFAULT_MAX_WAITERS: usize = 100;
+pub const MAX_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Partial unique index: one intent that isn't canceled per natural key
+const NATURAL_KEY_CONSTRAINT: &str = "payment_intents_natural_key_unique";
+
+// Largest amount unless MAXIMUM_AMOUNT says otherwise; Stripe's eight digits of minor units
+const DEFAULT_MAXIMUM_AMOUNT: i64 = 99_999_999;
+
+// Bulk cancel: per-call cap unless configured, and how many intents share one transaction
+const DEFAULT_BULK_CANCEL_MAX_PER_CALL: usize = 1000;
+const BULK_CANCEL_BATCH_SIZE: usize = 100;
+
+// Transition causes. Which API key made a change isn't recorded, so every API-driven one is `api`
+const CAUSE_API: &str = "api";
+const CAUSE_MAX_CONFIRMABLE_AGE: &str = "max_confirmable_age";
+const CAUSE_SIMULATED: &str = "simulated";
+const CAUSE_BULK_CANCEL: &str = "bulk_cancel";
+const CAUSE_EXPIRED: &str = "expired";
+
+pub struct IdempotencyKey(pub String);
+
+pub struct WaitParams {
+    // `until`: comma-separated statuses or `terminal`; None is `terminal`
+    pub until: Option<String>,
+    pub timeout: Duration,
+}
+
+pub struct WaitOutcome {
+    pub payment_intent: PaymentIntent,
+    // True when the timeout passed with the status unchanged
+    pub timed_out: bool,
+}
+
+pub struct CreatePaymentIntentParams {
+    pub amount: i64,
+    // Falls back to the configured default currency when omitted
+    pub currency: Option<String>,
+    // Confirm in the same transaction as the create (`confirm: true`)
+    pub confirm: Option<ConfirmPaymentIntentParams>,
+    pub metadata: BTreeMap<String, String>,
+    // `automatic` when omitted
+    pub capture_method: Option<String>,
+    pub description: Option<String>,
+    // Must be an existing customer that hasn't been deleted
+    pub customer: Option<Uuid>,
+    // Covers the whole create, including a `confirm: true` (whose own deadline is ignored)
+    pub deadline: Deadline,
+}
+
+// Fields left None keep their value
+#[derive(Default)]
+pub struct UpdatePaymentIntentParams {
+    pub amount: Option<i64>,
+    pub currency: Option<String>,
+    // An empty description clears it
+    pub description: Option<String>,
+    // Merged into the intent's metadata; an empty value removes the key
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Default)]
+pub struct ListPaymentIntentsParams {
+    pub include_archived: bool,
+    // Only this customer's intents
+    pub customer: Option<Uuid>,
+    pub limit: Option<i64>,
+    // next_cursor of the previous page
+    pub cursor: Option<String>,
+    // prev_cursor of the next page, to page back towards newer intents. Not with `cursor`.
+    pub ending_before: Option<String>,
+}
+
+pub struct PaymentIntentPage {
+    pub intents: Vec<PaymentIntent>,
+    pub limit: PageLimit,
+    // More rows in the direction of paging: older ones, or newer ones with `ending_before`
+    pub has_more: bool,
+    // None when there is nothing older
+    pub next_cursor: Option<String>,
+    // None when there is nothing newer
+    pub prev_cursor: Option<String>,
+}
+
+// Either a filter (starts a new job) or the job_id of an earlier call (resumes it)
+#[derive(Default)]
+pub struct BulkCancelParams {
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    // Pairs the intent's metadata must all contain
+    pub metadata: BTreeMap<String, String>,
+    // Only this customer's intents
+    pub customer: Option<Uuid>,
+    pub job_id: Option<Uuid>,
+    pub dry_run: bool,
+}
+
+impl BulkCancelParams {
+    fn has_filter(&self) -> bool {
+        self.created_after.is_some()
+            || self.created_before.is_some()
+            || !self.metadata.is_empty()
+            || self.customer.is_some()
+    }
+}
+
+pub struct BulkCancelJob {
+    // None on a dry run, which creates no job
+    pub id: Option<Uuid>,
+    pub matched: i64,
+    pub canceled: i64,
+    // No longer requires_confirmation by the time the job reached them
+    pub skipped: i64,
+    pub done: bool,
+    // Answered from the job an earlier call with the same Idempotency-Key started
+    pub replayed: bool,
+}
+
+#[derive(Default)]
+pub struct ConfirmPaymentIntentParams {
+    // Simulated acquirer call; counts as in flight like the DB work after it
+    pub latency: Option<SimulatedLatency>,
+    // Sandbox X-Simulate override for this one confirm; wins over the payment method
+    pub simulated_outcome: Option<SimulatedOutcome>,
+    // Sent through the processor with the amount, whose outcome decides the new status
+    pub payment_method: Option<String>,
+    pub deadline: Deadline,
+}
+
+pub struct CapturePaymentIntentParams {
+    // The whole authorized amount when omitted
+    pub amount_to_capture: Option<i64>,
+    // Simulated acquirer call, like on confirm
+    pub latency: Option<SimulatedLatency>,
+    pub deadline: Deadline,
+}
+
+pub enum CreateOutcome {
+    Created(PaymentIntentResponse),
+    // Same key and request as an earlier create: the original response
+    Replayed(PaymentIntentResponse, ReplaySource),
+    // An intent with the same natural key already exists: that intent as it is now
+    Deduplicated(PaymentIntentResponse),
+}
+
+impl CreateOutcome {
+    pub fn into_inner(self) -> PaymentIntentResponse {
+        match self {
+            Self::Created(response)
+            | Self::Replayed(response, _)
+            | Self::Deduplicated(response) => response,
+        }
+    }
+}
+
+// Where a replayed response came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySource {
+    // The body stored with the key (upgraded to the current shape if it was older)
+    Stored,
+    // Rebuilt from the intent's row, after a crash left no usable body
+    Reconstructed,
+}
+
+impl ReplaySource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stored => "stored",
+            Self::Reconstructed => "reconstructed",
+        }
+    }
+}
+
+enum JobStart {
+    Started(Uuid),
+    // An earlier call with the same Idempotency-Key already started this one
+    Replayed(Uuid),
+}
+
+pub struct PaymentIntentService<'a> {
+    db: &'a PgPool,
+    config: &'a Config,
+    in_flight: &'a InFlight,
+    metrics: &'a Metrics,
+    status_changes: &'a StatusChanges,
+    faults: &'a Faults,
+    // Decides every confirm that isn't simulated; TestProcessor unless replaced, with magic
+    // amounts in sandbox mode
+    processor: &'a dyn PaymentProcessor,
+}
+
+// Outbox payloads carry the same shape the API returns
+fn event_payload(
+    response: &PaymentIntentResponse,
+    simulated_outcome: Option<SimulatedOutcome>,
+) -> serde_json::Value {
+    serde_json::json!(PaymentIntentEventData {
+        payment_intent: response.clone(),
+        simulated_outcome: simulated_outcome.map(|outcome| outcome.as_str().to_string()),
+        previous_attributes: None,
+    })
+}
+
+// Every change to an intent other than its status goes through here, in the transaction that
+// made it. It writes payment_intent.updated with the old value of each field that changed, so
+// consumers holding an earlier event can reconcile. Nothing is written when nothing changed.
+async fn record_update(
+    tx: &mut Transaction<'_, Postgres>,
+    before: PaymentIntent,
+    after: PaymentIntent,
+) -> Result<PaymentIntentResponse, DomainError> {
+    let before = PaymentIntentResponse::from(before);
+    let after = PaymentIntentResponse::from(after);
+    if let Some(previous) = events::previous_attributes(&before, &after) {
+        let payload = serde_json::json!(PaymentIntentEventData {
+            payment_intent: after.clone(),
+            simulated_outcome: None,
+            previous_attributes: Some(previous),
+        });
+        insert_event(&mut **tx, events::PAYMENT_INTENT_UPDATED, payload).await?;
+    }
+    Ok(after)
+}
+
+// Brings a stored response body up to the current shape, re-reading new fields from the row.
+// Returns None when there is no upgrade path from `version` (caller rebuilds from the row)
+fn upgrade_response_body(
+    mut body: serde_json::Value,
+    version: i32,
+    pi: &PaymentIntent,
+) -> Option<serde_json::Value> {
+    for from in version..RESPONSE_BODY_VERSION {
+        match from {
+            // v1 -> v2: cancellation_reason
+            1 => body["cancellation_reason"] = serde_json::json!(pi.cancellation_reason),
+            // v2 -> v3: last_payment_error
+            2 => body["last_payment_error"] = serde_json::json!(pi.last_payment_error),
+            // v3 -> v4: metadata
+            3 => body["metadata"] = pi.metadata.clone(),
+            // v4 -> v5: capture_method
+            4 => body["capture_method"] = serde_json::json!(pi.capture_method),
+            // v5 -> v6: amount_received
+            5 => body["amount_received"] = serde_json::json!(pi.amount_received),
+            // v6 -> v7: description
+            6 => body["description"] = serde_json::json!(pi.description),
+            // v7 -> v8: expires_at
+            7 => body["expires_at"] = serde_json::json!(pi.expires_at),
+            // v8 -> v9: payment_method
+            8 => body["payment_method"] = serde_json::json!(pi.payment_method),
+            // v9 -> v10: customer
+            9 => body["customer"] = serde_json::json!(pi.customer_id),
+            _ => return None,
+        }
+    }
+    (version <= RESPONSE_BODY_VERSION).then_some(body)
+}
+
+// A create with confirm is a different request from a plain one (and so is each simulated outcome)
+fn request_fingerprint(
+    new: &NewPaymentIntent,
+    confirm: Option<&ConfirmPaymentIntentParams>,
+) -> String {
+    let mut fingerprint = format!(
+        "amount={}&currency={}",
+        new.amount,
+        new.currency.trim().to_lowercase()
+    );
+    // Only when present, so requests without metadata keep their earlier fingerprints
+    if !new.metadata.is_empty() {
+        fingerprint.push_str(&format!("&metadata={}", serde_json::json!(new.metadata)));
+    }
+    // Likewise only when it isn't the default
+    if new.capture_method != "automatic" {
+        fingerprint.push_str(&format!("&capture_method={}", new.capture_method));
+    }
+    if let Some(description) = &new.description {
+        fingerprint.push_str(&format!("&description={}", serde_json::json!(description)));
+    }
+    if let Some(customer) = new.customer_id {
+        fingerprint.push_str(&format!("&customer={customer}"));
+    }
+    if let Some(confirm) = confirm {
+        fingerprint.push_str("&confirm=true");
+        if let Some(payment_method) = &confirm.payment_method {
+            fingerprint.push_str(&format!("&payment_method={payment_method}"));
+        }
+        if let Some(outcome) = confirm.simulated_outcome {
+            fingerprint.push_str(&format!("&simulate={}", outcome.as_str()));
+        }
+    }
+    fingerprint
+}
+
+// Runs before any DB call, so junk input (huge, NUL-containing, non-ASCII) is a 400 rather than
+// a Postgres error
+fn validate_new_payment_intent(new: &NewPaymentIntent) -> Result<(), &'static str> {
+    if new.amount <= 0 {
+        return Err("amount must be > 0");
+    }
+    let currency = new.currency.trim();
+    if currency.is_empty() {
+        return Err("currency is required");
+    }
+    Currency::parse(currency)?;
+    if !CAPTURE_METHODS.contains(&new.capture_method.as_str()) {
+        return Err("capture_method must be `automatic` or `manual`");
+    }
+    if let Some(description) = &new.description {
+        validate_description(description)?;
+    }
+    validate_metadata(&new.metadata)
+}
+
+pub(crate) fn validate_description(description: &str) -> Result<(), &'static str> {
+    if description.len() > MAX_DESCRIPTION_LEN {
+        return Err("description must be at most 1000 bytes");
+    }
+    if description.contains('\0') {
+        return Err("description cannot contain NUL characters");
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_metadata(metadata: &BTreeMap<String, String>) -> Result<(), &'static str> {
+    if metadata.len() > MAX_METADATA_KEYS {
+        return Err("metadata can have at most 50 keys");
+    }
+    for (key, value) in metadata {
+        if key.is_empty() || key.len() > MAX_METADATA_KEY_LEN {
+            return Err("metadata keys must be 1 to 40 bytes");
+        }
+        if value.len() > MAX_METADATA_VALUE_LEN {
+            return Err("metadata values must be at most 500 bytes");
+        }
+        if key.contains('\0') || value.contains('\0') {
+            return Err("metadata cannot contain NUL characters");
+        }
+    }
+    Ok(())
+}
+
+// An intent waiting for its first confirm, or a declined one given another payment method to try
+fn is_confirmable(status: PaymentIntentStatus, payment_method: Option<&str>) -> bool {
+    match status {
+        PaymentIntentStatus::RequiresConfirmation => true,
+        PaymentIntentStatus::RequiresPaymentMethod => payment_method.is_some(),
+        _ => false,
+    }
+}
+
+fn is_natural_key_conflict(e: &DomainError) -> bool {
+    let DomainError::Db(e) = e else {
+        return false;
+    };
+    e.as_database_error()
+        .and_then(|db_err| db_err.constraint())
+        .is_some_and(|constraint| constraint == NATURAL_KEY_CONSTRAINT)
+}
+
+// Opaque to clients: the (created_at, id) of the last row of a page, created_at to the microsecond
+fn encode_cursor(pi: &PaymentIntent) -> String {
+    hex::encode(format!("{}:{}", pi.created_at.timestamp_micros(), pi.id))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(DateTime<Utc>, Uuid)> {
+    let raw = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+    let (micros, id) = raw.split_once(':')?;
+    let created_at = DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+    Some((created_at, id.parse().ok()?))
+}
+
+fn parse_until(until: Option<&str>) -> Result<Vec<PaymentIntentStatus>, DomainError> {
+    let until = until.unwrap_or("terminal");
+    if until == "terminal" {
+        return Ok(PaymentIntentStatus::ALL
+            .iter()
+            .copied()
+            .filter(|status| status.is_terminal())
+            .collect());
+    }
+    until
+        .split(',')
+        .map(|status| {
+            PaymentIntentStatus::parse(status.trim()).ok_or_else(|| {
+                let known: Vec<_> = PaymentIntentStatus::ALL
+                    .iter()
+                    .map(|s| s.as_str())
+                    .collect();
+                DomainError::InvalidParameter(format!(
+                    "until must be `terminal` or a comma-separated list of: {}",
+                    known.join(", ")
+                ))
+            })
+        })
+        .collect()
+}
+
+pub fn validate_idempotency_key(key: &str) -> Result<(), &'static str> {
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err("Idempotency-Key must be 1 to 255 bytes");
+    }
+    Ok(())
+}
+
+impl<'a> PaymentIntentService<'a> {
+    pub fn new(
+        db: &'a PgPool,
+        config: &'a Config,
+        in_flight: &'a InFlight,
+        metrics: &'a Metrics,
+        status_changes: &'a StatusChanges,
+        faults: &'a Faults,
+    ) -> Self {
+        Self {
+            db,
+            config,
+            in_flight,
+            metrics,
+            status_changes,
+            faults,
+            processor: if config.sandbox_mode {
+                &TestProcessor::SANDBOX
+            } else {
+                &TestProcessor::LIVE
+            },
+        }
+    }
+
+    pub fn with_processor(mut self, processor: &'a dyn PaymentProcessor) -> Self {
+        self.processor = processor;
+        self
+    }
+
+    // An unknown payment method is the caller's mistake, so a 400 rather than a failed payment.
+    // A saved one is named by its id and processed as the test card it was saved from; only
+    // then is there a statement to run. One attached to a customer is only for that customer's
+    // intents.
+    async fn process(
+        &self,
+        executor: impl sqlx::Executor<'_, Database = Postgres>,
+        payment_method: Option<&str>,
+        amount: i64,
+        customer: Option<Uuid>,
+    ) -> Result<ProcessorOutcome, DomainError> {
+        let saved_token = match payment_method.map(Uuid::parse_str) {
+            Some(Ok(id)) => {
+                let saved = fetch_payment_method(executor, id).await?.ok_or_else(|| {
+                    DomainError::InvalidParameter(format!("no such payment_method: {id}"))
+                })?;
+                if saved.customer_id.is_some() && saved.customer_id != customer {
+                    return Err(DomainError::InvalidParameter(format!(
+                        "payment_method {id} is attached to a different customer"
+                    )));
+                }
+                Some(saved.processor_token)
+            }
+            _ => None,
+        };
+        self.processor
+            .process(saved_token.as_deref().or(payment_method), amount)
+            .map_err(|msg| DomainError::InvalidParameter(msg.into()))
+    }
+
+    // Fills in the default currency and enforces the allowlist. The fingerprint is taken from the
+    // result, so omitting `currency` and sending the default are the same idempotent request.
+    fn resolve(&self, params: CreatePaymentIntentParams) -> Result<NewPaymentIntent, DomainError> {
+        let natural_key = self
+            .config
+            .natural_idempotency_key
+            .as_ref()
+            .and_then(|field| params.metadata.get(field))
+            .cloned();
+        let mut new = NewPaymentIntent {
+            amount: params.amount,
+            currency: params
+                .currency
+                .or_else(|| self.config.default_currency.clone())
+                .unwrap_or_default(),
+            metadata: params.metadata,
+            natural_key,
+            capture_method: params
+                .capture_method
+                .unwrap_or_else(|| "automatic".to_string()),
+            description: params.description,
+            customer_id: params.customer,
+        };
+
+        validate_new_payment_intent(&new)
+            .map_err(|msg| DomainError::InvalidParameter(msg.to_string()))?;
+        // Already parsed by the validation above; stored lowercase like Stripe returns it
+        let currency = Currency::parse(&new.currency)
+            .map_err(|msg| DomainError::InvalidParameter(msg.to_string()))?;
+        new.currency = currency.code.to_string();
+        self.check_currency_allowed(&new.currency)?;
+        self.check_amount_limits(new.amount, currency)?;
+
+        Ok(new)
+    }
+
+    // MINIMUM_AMOUNTS for the currency and MAXIMUM_AMOUNT, both in its minor units
+    fn check_amount_limits(&self, amount: i64, currency: Currency) -> Result<(), DomainError> {
+        if let Some(minimum) = self.config.minimum_amount(currency.code)
+            && amount < minimum
+        {
+            return Err(DomainError::AmountTooSmall { currency, minimum });
+        }
+        let maximum = self.config.maximum_amount.unwrap_or(DEFAULT_MAXIMUM_AMOUNT);
+        if amount > maximum {
+            return Err(DomainError::AmountTooLarge { currency, maximum });
+        }
+        Ok(())
+    }
+
+    fn check_currency_allowed(&self, currency: &str) -> Result<(), DomainError> {
+        if let Some(allowed) = &self.config.allowed_currencies
+            && !allowed.contains(&currency.trim().to_lowercase())
+        {
+            return Err(DomainError::CurrencyNotAllowed(allowed.clone()));
+        }
+        Ok(())
+    }
+
+    pub async fn create(
+        &self,
+        mut params: CreatePaymentIntentParams,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Result<CreateOutcome, DomainError> {
+        let _in_flight = self.in_flight.track("create payment_intent");
+        let confirm = params.confirm.take();
+        let deadline = params.deadline;
+        let new = self.resolve(params)?;
+        if let Some(IdempotencyKey(key)) = &idempotency_key {
+            validate_idempotency_key(key)
+                .map_err(|msg| DomainError::InvalidParameter(msg.to_string()))?;
+        }
+        // A key used before is answered from its record alone: a replay looks nothing up and
+        // doesn't call the processor again. Only checked when there is such work to skip; a
+        // create racing this one for the key is settled by the reservation below.
+        let has_lookups = new.customer_id.is_some() || confirm.is_some();
+        if let Some(IdempotencyKey(key)) = &idempotency_key
+            && has_lookups
+            && idempotency_key_exists(self.db, key, IDEMPOTENCY_ENDPOINT).await?
+        {
+            let mut tx = deadline.run(self.db.begin()).await??;
+            deadline.limit_statements(&mut tx).await?;
+            let req_hash = request_fingerprint(&new, confirm.as_ref());
+            return self.replay(tx, key, &req_hash).await;
+        }
+
+        // Only a create naming a customer pays for the lookup
+        if let Some(customer) = new.customer_id
+            && fetch_customer(self.db, customer).await?.is_none()
+        {
+            return Err(DomainError::InvalidParameter(format!(
+                "no such customer: {customer}"
+            )));
+        }
+
+        // Simulated acquirer latency of the confirm, before any transaction like on confirm
+        if let Some(confirm) = &confirm {
+            let slept = deadline.run(latency::inject(confirm.latency)).await?;
+            self.record_latency("create", slept);
+        }
+        let processed = match &confirm {
+            Some(confirm) if confirm.simulated_outcome.is_none() => {
+                let payment_method = confirm.payment_method.as_deref();
+                Some(
+                    self.process(self.db, payment_method, new.amount, new.customer_id)
+                        .await?,
+                )
+            }
+            _ => None,
+        };
+
+        // If no idempotency key keep current behavior
+        let natural_key = new.natural_key.clone();
+        let policy = BalancePolicy::from_config(self.config);
+        let Some(IdempotencyKey(key)) = idempotency_key else {
+            let mut tx = deadline.run(self.db.begin()).await??;
+            deadline.limit_statements(&mut tx).await?;
+            let (_, response) =
+                match insert_new(&mut tx, new, confirm.as_ref(), processed, policy).await {
+                    Err(e) if is_natural_key_conflict(&e) => {
+                        return self.deduplicate(tx, natural_key).await;
+                    }
+                    result => result?,
+                };
+
+            deadline.commit(tx).await?;
+            self.record_create_metrics(&response, confirm.as_ref());
+
+            return Ok(CreateOutcome::Created(response));
+        };
+
+        // Idempotent path
+        let req_hash = request_fingerprint(&new, confirm.as_ref());
+
+        // A create given up on rolls the key's reservation back too, so a retry starts afresh
+        let mut tx = deadline.run(self.db.begin()).await??;
+        deadline.limit_statements(&mut tx).await?;
+
+        // Reserve the key if its new
+        // If already used this returns 0 rows
+        let reserved = sqlx::query!(
+            r#"
+            INSERT INTO idempotency_keys (key, endpoint, request_hash, response_body, expires_at)
+            VALUES ($1, $2, $3, '{}'::jsonb, now() + make_interval(secs => $4))
+            ON CONFLICT (key, endpoint) DO NOTHING
+            RETURNING key
+            "#,
+            key,
+            IDEMPOTENCY_ENDPOINT,
+            req_hash,
+            self.config.idempotency_key_ttl.map(|ttl| ttl.as_secs_f64())
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if reserved.is_some() {
+            // Successfully reserved the key -> create payment intent. A natural-key duplicate
+            // rolls the reservation back with everything else.
+            let (id, response) =
+                match insert_new(&mut tx, new, confirm.as_ref(), processed, policy).await {
+                    Err(e) if is_natural_key_conflict(&e) => {
+                        return self.deduplicate(tx, natural_key).await;
+                    }
+                    result => result?,
+                };
+
+            // Store the response JSON so retries can return the same thing
+            let response_json = serde_json::to_value(&response)?;
+
+            // Server Crash Edge Case: we store payment_intent_id as well as response_body.
+            // If the server crashes after reserving the idempotency key but before writing
+            // the final response_body: retries can reconstruct the response from payment_intents.
+            sqlx::query!(
+                r#"
+                UPDATE idempotency_keys
+                SET response_body = $1, payment_intent_id = $2, body_version = $3
+                WHERE key = $4 AND endpoint = $5
+                "#,
+                response_json,
+                id,
+                RESPONSE_BODY_VERSION,
+                key,
+                IDEMPOTENCY_ENDPOINT
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            deadline.commit(tx).await?;
+            self.record_create_metrics(&response, confirm.as_ref());
+
+            return Ok(CreateOutcome::Created(response));
+        }
+
+        // Lost the key to a concurrent create with it
+        self.replay(tx, &key, &req_hash).await
+    }
+
+    // Answers a create whose Idempotency-Key is already taken
+    async fn replay(
+        &self,
+        mut tx: Transaction<'_, Postgres>,
+        key: &str,
+        req_hash: &str,
+    ) -> Result<CreateOutcome, DomainError> {
+        // Fetch the stored record, counting the replay/conflict for usage stats
+        let row = sqlx::query!(
+            r#"
+            UPDATE idempotency_keys
+            SET replay_count = replay_count + (request_hash = $3)::int,
+                conflict_count = conflict_count + (request_hash <> $3)::int
+            WHERE key = $1 AND endpoint = $2
+            RETURNING request_hash, response_body, body_version, payment_intent_id
+            "#,
+            key,
+            IDEMPOTENCY_ENDPOINT,
+            req_hash
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // If request differs its a conflict (commit so the conflict is counted)
+        if row.request_hash != req_hash {
+            tx.commit().await.ok();
+            return Err(DomainError::IdempotencyKeyReused);
+        }
+
+        // If response_body looks complete return it
+        let looks_complete = row
+            .response_body
+            .get("id")
+            .and_then(|v| v.as_str())
+            .is_some();
+
+        if looks_complete && row.body_version == RESPONSE_BODY_VERSION {
+            let response: PaymentIntentResponse = serde_json::from_value(row.response_body)?;
+
+            tx.commit().await.ok();
+            return Ok(CreateOutcome::Replayed(response, ReplaySource::Stored));
+        }
+
+        // Crash fallback (response_body incomplete) or an old-format body:
+        // re-read the payment intent and upgrade/reconstruct the response from it. The read goes
+        // through this transaction on the primary, never state.read_db: a lagging replica's
+        // snapshot would be stored below as the canonical response for good.
+        if let Some(pi_id) = row.payment_intent_id {
+            let pi = fetch_payment_intent(&mut *tx, pi_id)
+                .await?
+                .ok_or_else(|| {
+                    DomainError::Internal(
+                        "idempotency record points at a missing payment_intent".to_string(),
+                    )
+                })?;
+
+            let upgraded = looks_complete
+                .then(|| upgrade_response_body(row.response_body, row.body_version, &pi))
+                .flatten();
+            let (response, source) = match upgraded {
+                Some(body) => (serde_json::from_value(body)?, ReplaySource::Stored),
+                None => (PaymentIntentResponse::from(pi), ReplaySource::Reconstructed),
+            };
+
+            // fill response_body so future retries are fast
+            let response_json = serde_json::to_value(&response)?;
+
+            sqlx::query!(
+                r#"
+                UPDATE idempotency_keys
+                SET response_body = $1,
+                    body_version = $2,
+                    reconstructed_count = reconstructed_count + ($5::bool)::int
+                WHERE key = $3 AND endpoint = $4
+                "#,
+                response_json,
+                RESPONSE_BODY_VERSION,
+                key,
+                IDEMPOTENCY_ENDPOINT,
+                !looks_complete
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await.ok();
+            return Ok(CreateOutcome::Replayed(response, source));
+        }
+
+        // Old-format body with nothing to re-read: missing optional fields deserialize as null
+        if looks_complete {
+            let response: PaymentIntentResponse = serde_json::from_value(row.response_body)?;
+
+            tx.commit().await.ok();
+            return Ok(CreateOutcome::Replayed(response, ReplaySource::Stored));
+        }
+
+        // Idempotency record exists but is incomplete in a way we cant recover from
+        tx.rollback().await.ok();
+        Err(DomainError::Internal(
+            "idempotency record exists but has no stored response or payment_intent_id".to_string(),
+        ))
+    }
+
+    // The insert lost to an intent with the same natural key, maybe one committed a moment ago by
+    // a concurrent create. The transaction is dead after the violation, so the winner is read
+    // outside it.
+    async fn deduplicate(
+        &self,
+        tx: Transaction<'_, Postgres>,
+        natural_key: Option<String>,
+    ) -> Result<CreateOutcome, DomainError> {
+        tx.rollback().await.ok();
+        let natural_key = natural_key.ok_or_else(|| {
+            DomainError::Internal("natural key conflict without a natural key".to_string())
+        })?;
+
+        let existing = fetch_payment_intent_by_natural_key(self.db, &natural_key)
+            .await?
+            .ok_or_else(|| {
+                DomainError::Internal(
+                    "the intent holding this natural key was canceled meanwhile; retry".to_string(),
+                )
+            })?;
+        Ok(CreateOutcome::Deduplicated(existing.into()))
+    }
+
+    fn record_create_metrics(
+        &self,
+        response: &PaymentIntentResponse,
+        confirm: Option<&ConfirmPaymentIntentParams>,
+    ) {
+        record_metric(self.metrics, metrics::PAYMENTS_CREATED, response);
+        if let Some(confirm) = confirm {
+            record_confirm_metrics(self.metrics, response, confirm.simulated_outcome);
+        }
+    }
+
+    // Only requests that actually slept are observed, so the count is the simulated calls
+    fn record_latency(&self, operation: &str, slept: Duration) {
+        if !slept.is_zero() {
+            self.metrics.observe(
+                metrics::SIMULATED_LATENCY,
+                &[("operation", operation)],
+                slept,
+            );
+        }
+    }
+
+    // The intent plus its latest event (unless summaries are disabled)
+    pub async fn retrieve(
+        &self,
+        id: Uuid,
+    ) -> Result<(PaymentIntent, Option<LatestEventSummary>), DomainError> {
+        self.faults.delay_read(self.config, self.metrics).await;
+        let (pi, latest_event) = if self.config.disable_payment_intent_summaries {
+            (fetch_payment_intent(self.db, id).await?, None)
+        } else {
+            tokio::try_join!(
+                fetch_payment_intent(self.db, id),
+                latest_payment_intent_event(self.db, id)
+            )?
+        };
+
+        let pi = pi.ok_or(DomainError::NotFound("payment_intent"))?;
+        Ok((pi, latest_event))
+    }
+
+    // Long-poll: returns at once if the intent is already in one of the `until` statuses, else at
+    // its next status change or when the timeout passes, whichever is first
+    pub async fn wait(&self, id: Uuid, params: WaitParams) -> Result<WaitOutcome, DomainError> {
+        let until = parse_until(params.until.as_deref())?;
+        if params.timeout > MAX_WAIT_TIMEOUT {
+            return Err(DomainError::InvalidParameter(format!(
+                "timeout must be at most {}s",
+                MAX_WAIT_TIMEOUT.as_secs()
+            )));
+        }
+        let deadline = Instant::now() + params.timeout;
+
+        // Subscribed before the read, so a change committed in between isn't missed
+        let max_waiters = self
+            .config
+            .max_payment_intent_waiters
+            .unwrap_or(DEFAULT_MAX_WAITERS);
+        let mut subscription = self
+            .status_changes
+            .subscribe(max_waiters)
+            .ok_or(DomainError::TooManyWaiters)?;
+
+        let pi = fetch_payment_intent(self.db, id)
+            .await?
+            .ok_or(DomainError::NotFound("payment_intent"))?;
+        if until.contains(&pi.status) {
+            return Ok(WaitOutcome {
+                payment_intent: pi,
+                timed_out: false,
+            });
+        }
+
+        let from = pi.status;
+        let change = subscription.next_change(id, from.as_str(), deadline).await;
+        drop(subscription);
+
+        // Read again either way: the response is the committed row, not the broadcast status
+        let pi = fetch_payment_intent(self.db, id)
+            .await?
+            .ok_or(DomainError::NotFound("payment_intent"))?;
+        let timed_out = change == status_changes::WaitOutcome::TimedOut && pi.status == from;
+        Ok(WaitOutcome {
+            payment_intent: pi,
+            timed_out,
+        })
+    }
+
+    // Most recent intents first, one page at a time, forwards from `cursor` or backwards from
+    // `ending_before`
+    pub async fn list(
+        &self,
+        params: ListPaymentIntentsParams,
+    ) -> Result<PaymentIntentPage, DomainError> {
+        self.faults.delay_read(self.config, self.metrics).await;
+        let page_limit = PageLimit::resolve(self.config, params.limit)?;
+        let limit = page_limit.limit;
+        if params.cursor.is_some() && params.ending_before.is_some() {
+            return Err(DomainError::InvalidParameter(
+                "starting_after and ending_before cannot be used together".into(),
+            ));
+        }
+        let decode = |c: String| {
+            decode_cursor(&c).ok_or(DomainError::InvalidParameter("invalid cursor".into()))
+        };
+        let after = params.cursor.map(decode).transpose()?;
+        let before = params.ending_before.map(decode).transpose()?;
+
+        // One extra row tells us whether there is another page in that direction. The row the
+        // cursor came from is on the other side, so that direction has more whenever a cursor
+        // was given.
+        if let Some(before) = before {
+            let mut intents = list_payment_intents_before(
+                self.db,
+                params.include_archived,
+                params.customer,
+                before,
+                limit + 1,
+            )
+            .await?;
+            let has_more = intents.len() as i64 > limit;
+            intents.truncate(limit as usize);
+            intents.reverse();
+            return Ok(PaymentIntentPage {
+                next_cursor: intents.last().map(encode_cursor),
+                prev_cursor: intents.first().filter(|_| has_more).map(encode_cursor),
+                intents,
+                limit: page_limit,
+                has_more,
+            });
+        }
+
+        let mut intents = list_payment_intents(
+            self.db,
+            params.include_archived,
+            params.customer,
+            after,
+            limit + 1,
+        )
+        .await?;
+        let has_more = intents.len() as i64 > limit;
+        intents.truncate(limit as usize);
+        Ok(PaymentIntentPage {
+            next_cursor: intents.last().filter(|_| has_more).map(encode_cursor),
+            prev_cursor: intents
+                .first()
+                .filter(|_| after.is_some())
+                .map(encode_cursor),
+            intents,
+            limit: page_limit,
+            has_more,
+        })
+    }
+
+    // Sandbox clean-up: hides a finished intent from lists. Not a status change, so no transition
+    // or event is written.
+    pub async fn archive(&self, id: Uuid) -> Result<PaymentIntent, DomainError> {
+        if let Some(pi) = archive_payment_intent(self.db, id).await? {
+            return Ok(pi);
+        }
+
+        match fetch_payment_intent(self.db, id).await? {
+            None => Err(DomainError::NotFound("payment_intent")),
+            Some(pi) => Err(DomainError::NotArchivable(pi.status)),
+        }
+    }
+
+    // Cancels the requires_confirmation intents matching the filter, at most the configured
+    // number per call. The matching ids are snapshotted when the job starts; each batch of them
+    // commits on its own, so a resumed (or concurrent) call carries on where the last one stopped.
+    //
+    // An Idempotency-Key only matters on the call that starts a job: a replay returns that job's
+    // current state without processing anything. Resumes and dry runs are safe to repeat anyway.
+    pub async fn bulk_cancel(
+        &self,
+        params: BulkCancelParams,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Result<BulkCancelJob, DomainError> {
+        if let Some(IdempotencyKey(key)) = &idempotency_key {
+            validate_idempotency_key(key)
+                .map_err(|msg| DomainError::InvalidParameter(msg.to_string()))?;
+        }
+
+        let job_id = match params.job_id {
+            Some(job_id) => {
+                if params.has_filter() {
+                    return Err(DomainError::InvalidParameter(
+                        "job_id cannot be combined with filters".into(),
+                    ));
+                }
+                if params.dry_run {
+                    return Err(DomainError::InvalidParameter(
+                        "dry_run cannot be combined with job_id".into(),
+                    ));
+                }
+                job_id
+            }
+            None => {
+                // Cancelling every open intent is never what an incident clean-up means
+                if !params.has_filter() {
+                    return Err(DomainError::InvalidParameter(
+                        "created_after, created_before, metadata or customer is required".into(),
+                    ));
+                }
+                validate_metadata(&params.metadata)
+                    .map_err(|msg| DomainError::InvalidParameter(msg.to_string()))?;
+                if params.dry_run {
+                    return self.count_bulk_cancel_matches(&params).await;
+                }
+                match self.start_bulk_cancel_job(&params, idempotency_key).await? {
+                    JobStart::Started(job_id) => job_id,
+                    JobStart::Replayed(job_id) => {
+                        let mut job = self.bulk_cancel_status(job_id).await?;
+                        job.replayed = true;
+                        return Ok(job);
+                    }
+                }
+            }
+        };
+
+        let _in_flight = self.in_flight.track(format!("bulk_cancel job {job_id}"));
+
+        let max_per_call = self
+            .config
+            .bulk_cancel_max_per_call
+            .unwrap_or(DEFAULT_BULK_CANCEL_MAX_PER_CALL);
+        let mut processed = 0;
+        while processed < max_per_call {
+            let batch = BULK_CANCEL_BATCH_SIZE.min(max_per_call - processed);
+            let n = self.bulk_cancel_batch(job_id, batch).await?;
+            if n == 0 {
+                break;
+            }
+            processed += n;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE bulk_cancel_jobs
+            SET completed_at = now()
+            WHERE id = $1
+              AND completed_at IS NULL
+              AND NOT EXISTS (
+                SELECT 1 FROM bulk_cancel_job_items WHERE job_id = $1 AND outcome IS NULL
+              )
+            "#,
+            job_id
+        )
+        .execute(self.db)
+        .await?;
+
+        self.bulk_cancel_status(job_id).await
+    }
+
+    async fn bulk_cancel_status(&self, job_id: Uuid) -> Result<BulkCancelJob, DomainError> {
+        let job = sqlx::query!(
+            r#"
+            SELECT matched, canceled, skipped, completed_at
+            FROM bulk_cancel_jobs
+            WHERE id = $1
+            "#,
+            job_id
+        )
+        .fetch_optional(self.db)
+        .await?
+        .ok_or(DomainError::NotFound("bulk_cancel_job"))?;
+
+        Ok(BulkCancelJob {
+            id: Some(job_id),
+            matched: job.matched.into(),
+            canceled: job.canceled.into(),
+            skipped: job.skipped.into(),
+            done: job.completed_at.is_some(),
+            replayed: false,
+        })
+    }
+
+    async fn count_bulk_cancel_matches(
+        &self,
+        params: &BulkCancelParams,
+    ) -> Result<BulkCancelJob, DomainError> {
+        let matched = sqlx::query_scalar!(
+            r#"
+            SELECT count(*) AS "count!"
+            FROM payment_intents
+            WHERE status = $3
+              AND ($1::timestamptz IS NULL OR created_at >= $1)
+              AND ($2::timestamptz IS NULL OR created_at < $2)
+              AND metadata @> $4
+              AND ($5::uuid IS NULL OR customer_id = $5)
+            "#,
+            params.created_after,
+            params.created_before,
+            PaymentIntentStatus::RequiresConfirmation.as_str(),
+            serde_json::json!(params.metadata),
+            params.customer
+        )
+        .fetch_one(self.db)
+        .await?;
+
+        Ok(BulkCancelJob {
+            id: None,
+            matched,
+            canceled: 0,
+            skipped: 0,
+            done: true,
+            replayed: false,
+        })
+    }
+
+    async fn start_bulk_cancel_job(
+        &self,
+        params: &BulkCancelParams,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Result<JobStart, DomainError> {
+        let job_id = Uuid::new_v4();
+        let mut tx = self.db.begin().await?;
+
+        let key = idempotency_key.map(|IdempotencyKey(key)| key);
+        if let Some(key) = &key {
+            let mut fingerprint = format!(
+                "created_after={}&created_before={}",
+                params
+                    .created_after
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_default(),
+                params
+                    .created_before
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_default()
+            );
+            // Only when present, so jobs started without them keep their earlier fingerprints
+            if !params.metadata.is_empty() {
+                fingerprint.push_str(&format!("&metadata={}", serde_json::json!(params.metadata)));
+            }
+            if let Some(customer) = params.customer {
+                fingerprint.push_str(&format!("&customer={customer}"));
+            }
+            match reserve_job_key(
+                &mut tx,
+                key,
+                BULK_CANCEL_IDEMPOTENCY_ENDPOINT,
+                &fingerprint,
+                self.config.idempotency_key_ttl,
+            )
+            .await?
+            {
+                JobKey::Reserved => {}
+                JobKey::Replayed(job_id) => {
+                    tx.commit().await?;
+                    return Ok(JobStart::Replayed(job_id));
+                }
+                JobKey::Conflict => {
+                    tx.commit().await.ok();
+                    return Err(DomainError::IdempotencyKeyReused);
+                }
+            }
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO bulk_cancel_jobs (
+              id, created_after, created_before, metadata, customer_id, matched
+            )
+            VALUES ($1, $2, $3, $4, $5, 0)
+            "#,
+            job_id,
+            params.created_after,
+            params.created_before,
+            serde_json::json!(params.metadata),
+            params.customer
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let matched = sqlx::query!(
+            r#"
+            INSERT INTO bulk_cancel_job_items (job_id, payment_intent_id)
+            SELECT $1, id
+            FROM payment_intents
+            WHERE status = $4
+              AND ($2::timestamptz IS NULL OR created_at >= $2)
+              AND ($3::timestamptz IS NULL OR created_at < $3)
+              AND metadata @> $5
+              AND ($6::uuid IS NULL OR customer_id = $6)
+            "#,
+            job_id,
+            params.created_after,
+            params.created_before,
+            PaymentIntentStatus::RequiresConfirmation.as_str(),
+            serde_json::json!(params.metadata),
+            params.customer
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        sqlx::query!(
+            r#"
+            UPDATE bulk_cancel_jobs
+            SET matched = $2
+            WHERE id = $1
+            "#,
+            job_id,
+            matched as i32
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        insert_audit_entry(
+            &mut *tx,
+            "payment_intent.bulk_cancel",
+            job_id,
+            serde_json::json!({
+                "created_after": params.created_after,
+                "created_before": params.created_before,
+                "matched": matched,
+            }),
+        )
+        .await?;
+
+        if let Some(key) = &key {
+            record_job_id(&mut tx, key, BULK_CANCEL_IDEMPOTENCY_ENDPOINT, job_id).await?;
+        }
+
+        tx.commit().await?;
+        Ok(JobStart::Started(job_id))
+    }
+
+    // One transaction: up to `limit` unprocessed items of the job, each canceled through the
+    // same transition/event path as any other cancel, or skipped if it already moved on.
+    // Returns how many items were processed (0 once the job is exhausted).
+    async fn bulk_cancel_batch(&self, job_id: Uuid, limit: usize) -> Result<usize, DomainError> {
+        let mut tx = self.db.begin().await?;
+
+        let ids = sqlx::query_scalar!(
+            r#"
+            SELECT payment_intent_id
+            FROM bulk_cancel_job_items
+            WHERE job_id = $1 AND outcome IS NULL
+            ORDER BY payment_intent_id
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+            "#,
+            job_id,
+            limit as i64
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let (mut canceled, mut skipped) = (Vec::new(), 0);
+        for &id in &ids {
+            let outcome = match cancel_payment_intent(&mut *tx, id, CAUSE_BULK_CANCEL).await? {
+                Some(pi) => {
+                    record_transition(
+                        &mut *tx,
+                        pi.id,
+                        Some(PaymentIntentStatus::RequiresConfirmation),
+                        pi.status,
+                        CAUSE_BULK_CANCEL,
+                    )
+                    .await?;
+
+                    let response = PaymentIntentResponse::from(pi);
+                    insert_event(
+                        &mut *tx,
+                        events::PAYMENT_INTENT_CANCELED,
+                        event_payload(&response, None),
+                    )
+                    .await?;
+
+                    canceled.push(id);
+                    "canceled"
+                }
+                None => {
+                    skipped += 1;
+                    "skipped"
+                }
+            };
+
+            sqlx::query!(
+                r#"
+                UPDATE bulk_cancel_job_items
+                SET outcome = $3
+                WHERE job_id = $1 AND payment_intent_id = $2
+                "#,
+                job_id,
+                id,
+                outcome
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE bulk_cancel_jobs
+            SET canceled = canceled + $2, skipped = skipped + $3, updated_at = now()
+            WHERE id = $1
+            "#,
+            job_id,
+            canceled.len() as i64,
+            skipped
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        for id in canceled {
+            self.status_changes
+                .publish(id, PaymentIntentStatus::Canceled.as_str());
+        }
+        Ok(ids.len())
+    }
+
+    // Every status change the intent went through, oldest first
+    pub async fn transitions(&self, id: Uuid) -> Result<Vec<PaymentIntentTransition>, DomainError> {
+        self.faults.delay_read(self.config, self.metrics).await;
+        let mut tx = self.db.begin().await?;
+        if fetch_payment_intent(&mut *tx, id).await?.is_none() {
+            return Err(DomainError::NotFound("payment_intent"));
+        }
+        let transitions = list_transitions(&mut *tx, id).await?;
+        tx.commit().await?;
+
+        Ok(transitions)
+    }
+
+    // Changes the amount and currency (only while requires_confirmation), the description and/or
+    // the metadata. Holds the row lock from the status check to the commit, so a confirm either
+    // sees the new amount or makes this a 409. Not a status change, so no transition is written,
+    // but a change that isn't a no-op writes payment_intent.updated (see record_update).
+    pub async fn update(
+        &self,
+        id: Uuid,
+        params: UpdatePaymentIntentParams,
+    ) -> Result<PaymentIntentResponse, DomainError> {
+        let _in_flight = self.in_flight.track(format!("update payment_intent {id}"));
+        if params.amount.is_some_and(|amount| amount <= 0) {
+            return Err(DomainError::InvalidParameter("amount must be > 0".into()));
+        }
+        let currency = params
+            .currency
+            .map(|c| Currency::parse(&c).map(|c| c.code.to_string()))
+            .transpose()
+            .map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+        if let Some(currency) = &currency {
+            self.check_currency_allowed(currency)?;
+        }
+        if let Some(description) = &params.description {
+            validate_description(description)
+                .map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+        }
+
+        let mut tx = self.db.begin().await?;
+        let Some(before) = lock_payment_intent(&mut *tx, id).await? else {
+            return Err(DomainError::NotFound("payment_intent"));
+        };
+        if (params.amount.is_some() || currency.is_some())
+            && before.status != PaymentIntentStatus::RequiresConfirmation
+        {
+            return Err(DomainError::NotUpdatable(before.status));
+        }
+        let amount = params.amount.unwrap_or(before.amount);
+        let currency = currency
+            .filter(|c| !c.eq_ignore_ascii_case(&before.currency))
+            .unwrap_or_else(|| before.currency.clone());
+        // A new amount, or the old one in a new currency, must fit that currency's limits. Rows
+        // from before the supported list can't be checked and keep their amount as it is.
+        if (amount != before.amount || currency != before.currency)
+            && let Ok(parsed) = Currency::parse(&currency)
+        {
+            self.check_amount_limits(amount, parsed)?;
+        }
+        let description = match params.description {
+            Some(description) if description.is_empty() => None,
+            Some(description) => Some(description),
+            None => before.description.clone(),
+        };
+
+        let current: BTreeMap<String, String> =
+            serde_json::from_value(before.metadata.clone()).unwrap_or_default();
+        let mut metadata = current.clone();
+        for (key, value) in params.metadata.unwrap_or_default() {
+            // The natural key was taken from this field at creation; it can't move now
+            if self.config.natural_idempotency_key.as_ref() == Some(&key)
+                && current.get(&key) != Some(&value)
+            {
+                return Err(DomainError::InvalidParameter(format!(
+                    "metadata.{key} is the natural idempotency key and can't change"
+                )));
+            }
+            if value.is_empty() {
+                metadata.remove(&key);
+            } else {
+                metadata.insert(key, value);
+            }
+        }
+        validate_metadata(&metadata).map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+
+        if amount == before.amount
+            && currency == before.currency
+            && description == before.description
+            && metadata == current
+        {
+            tx.commit().await?;
+            return Ok(PaymentIntentResponse::from(before));
+        }
+        let changes = PaymentIntentChanges {
+            amount,
+            currency,
+            description,
+            metadata: serde_json::json!(metadata),
+        };
+        let after = update_payment_intent(&mut *tx, id, changes)
+            .await?
+            .ok_or_else(|| DomainError::Internal("locked payment_intent changed status".into()))?;
+        let response = record_update(&mut tx, before, after).await?;
+        tx.commit().await?;
+
+        Ok(response)
+    }
+
+    pub async fn confirm(
+        &self,
+        id: Uuid,
+        params: ConfirmPaymentIntentParams,
+    ) -> Result<PaymentIntentResponse, DomainError> {
+        let _in_flight = self.in_flight.track(format!("confirm payment_intent {id}"));
+        // A sandbox outage refuses the confirm before anything is read or written
+        self.faults.check_confirm(self.config, self.metrics)?;
+
+        // Simulated acquirer latency happens before we take a connection/transaction
+        let deadline = params.deadline;
+        let slept = deadline.run(latency::inject(params.latency)).await?;
+        self.record_latency("confirm", slept);
+
+        let mut tx = deadline.run(self.db.begin()).await??;
+        deadline.limit_statements(&mut tx).await?;
+        // Waits for an amount update in flight, so the event below carries the final amount
+        let locked = lock_payment_intent(&mut *tx, id).await?;
+
+        // Confirming a long-stale intent is almost always a client replaying old state:
+        // cancel it (same tx) so a follow-up GET reflects reality, then refuse the confirm.
+        // Past expires_at it is `expired`; older than MAX_CONFIRMABLE_AGE, `abandoned`.
+        // The locked row says whether to bother, so a live intent costs no extra statement
+        let mut stale = None;
+        if locked
+            .as_ref()
+            .is_some_and(|pi| pi.expires_at <= Utc::now())
+        {
+            stale = expire_payment_intent(&mut *tx, id)
+                .await?
+                .map(|pi| (pi, CAUSE_EXPIRED));
+        }
+        if stale.is_none()
+            && let Some(max_age) = self.config.max_confirmable_age
+        {
+            stale = abandon_payment_intent(&mut *tx, id, max_age)
+                .await?
+                .map(|pi| (pi, CAUSE_MAX_CONFIRMABLE_AGE));
+        }
+
+        if let Some((pi, cause)) = stale {
+            let from = locked
+                .as_ref()
+                .map_or(PaymentIntentStatus::INITIAL, |pi| pi.status);
+            let response = record_stale_cancel(&mut tx, from, pi, cause).await?;
+            deadline.commit(tx).await?;
+            self.status_changes.publish(id, response.status.as_str());
+            record_metric(self.metrics, metrics::PAYMENTS_FAILED, &response);
+
+            return Err(DomainError::ExpiredForConfirmation);
+        }
+
+        // Only an intent that can be confirmed gets as far as the processor
+        let payment_method = params.payment_method.as_deref();
+        let Some(pi) = locked.filter(|pi| is_confirmable(pi.status, payment_method)) else {
+            return Err(refuse_confirm(tx, id).await?);
+        };
+        let outcome = match params.simulated_outcome {
+            None => Some(
+                self.process(&mut *tx, payment_method, pi.amount, pi.customer_id)
+                    .await?,
+            ),
+            Some(_) => None,
+        };
+
+        let Some(response) = apply_confirm(
+            &mut tx,
+            id,
+            pi.status,
+            params.simulated_outcome,
+            payment_method,
+            outcome,
+            BalancePolicy::from_config(self.config),
+        )
+        .await?
+        else {
+            return Err(refuse_confirm(tx, id).await?);
+        };
+
+        deadline.commit(tx).await?;
+        // Declines and timeouts change the status too, so waiters hear about those as well
+        self.status_changes.publish(id, response.status.as_str());
+        record_confirm_metrics(self.metrics, &response, params.simulated_outcome);
+
+        match (params.simulated_outcome, outcome) {
+            (Some(SimulatedOutcome::CardDeclined), _) => Err(DomainError::CardDeclined),
+            (None, Some(ProcessorOutcome::Failed(error))) => Err(DomainError::PaymentFailed(error)),
+            (Some(SimulatedOutcome::Timeout), _) => Err(DomainError::AcquirerTimeout),
+            _ => Ok(response),
+        }
+    }
+
+    // requires_capture -> succeeded for an intent created with `capture_method: manual`, written
+    // with payment_intent.captured. `amount_to_capture` (the whole amount when omitted) becomes
+    // amount_received; the rest of the authorization is released. Holds the row lock like
+    // confirm, so a capture racing the confirm either sees requires_capture or is a 409.
+    pub async fn capture(
+        &self,
+        id: Uuid,
+        params: CapturePaymentIntentParams,
+    ) -> Result<PaymentIntentResponse, DomainError> {
+        let _in_flight = self.in_flight.track(format!("capture payment_intent {id}"));
+        let amount_to_capture = params.amount_to_capture;
+
+        if amount_to_capture.is_some_and(|amount| amount <= 0) {
+            return Err(DomainError::InvalidParameter(
+                "amount_to_capture must be > 0".into(),
+            ));
+        }
+        // Capture goes to the same acquirer as confirm, so the same outage refuses it
+        self.faults.check_confirm(self.config, self.metrics)?;
+
+        let deadline = params.deadline;
+        let slept = deadline.run(latency::inject(params.latency)).await?;
+        self.record_latency("capture", slept);
+
+        let mut tx = deadline.run(self.db.begin()).await??;
+        deadline.limit_statements(&mut tx).await?;
+        let Some(before) = lock_payment_intent(&mut *tx, id).await? else {
+            return Err(DomainError::NotFound("payment_intent"));
+        };
+        if before.status != PaymentIntentStatus::RequiresCapture {
+            return Err(DomainError::NotCapturable(before.status));
+        }
+        let amount_received = amount_to_capture.unwrap_or(before.amount);
+        if amount_received > before.amount {
+            return Err(DomainError::InvalidParameter(format!(
+                "amount_to_capture must be at most the authorized amount ({})",
+                before.amount
+            )));
+        }
+        let Some(pi) = capture_payment_intent(&mut *tx, id, amount_received).await? else {
+            return Err(DomainError::NotCapturable(before.status));
+        };
+        record_transition(
+            &mut *tx,
+            id,
+            Some(PaymentIntentStatus::RequiresCapture),
+            pi.status,
+            CAUSE_API,
+        )
+        .await?;
+
+        let response = PaymentIntentResponse::from(pi);
+        record_charge(&mut tx, &response, BalancePolicy::from_config(self.config)).await?;
+        insert_event(
+            &mut *tx,
+            events::PAYMENT_INTENT_CAPTURED,
+            event_payload(&response, None),
+        )
+        .await?;
+        deadline.commit(tx).await?;
+
+        self.status_changes.publish(id, response.status.as_str());
+        record_succeeded_metrics(self.metrics, &response);
+
+        Ok(response)
+    }
+
+    // One transaction: cancels up to `limit` intents past their expires_at with reason
+    // `expired`, each with its transition and payment_intent.canceled event. Returns how many
+    // (fewer than `limit` once none are left).
+    pub async fn expire_batch(&self, limit: i64) -> Result<usize, DomainError> {
+        let mut tx = self.db.begin().await?;
+        let mut expired = Vec::new();
+        for due in lock_expired_payment_intents(&mut *tx, limit).await? {
+            // Locked above, so it is still due and in the status read
+            if let Some(pi) = expire_payment_intent(&mut *tx, due.id).await? {
+                let response = record_stale_cancel(&mut tx, due.status, pi, CAUSE_EXPIRED).await?;
+                expired.push(response);
+            }
+        }
+        tx.commit().await?;
+
+        for response in &expired {
+            self.status_changes
+                .publish(response.id, response.status.as_str());
+            record_metric(self.metrics, metrics::PAYMENTS_FAILED, response);
+        }
+        Ok(expired.len())
+    }
+}
+
+// The transition and payment_intent.canceled event of an intent canceled for being stale, in
+// the transaction that canceled it
+async fn record_stale_cancel(
+    tx: &mut Transaction<'_, Postgres>,
+    from: PaymentIntentStatus,
+    pi: PaymentIntent,
+    cause: &str,
+) -> Result<PaymentIntentResponse, DomainError> {
+    record_transition(&mut **tx, pi.id, Some(from), pi.status, cause).await?;
+
+    let response = PaymentIntentResponse::from(pi);
+    insert_event(
+        &mut **tx,
+        events::PAYMENT_INTENT_CANCELED,
+        event_payload(&response, None),
+    )
+    .await?;
+    Ok(response)
+}
+
+// Every status change is written through here, in the transaction that made it. A change the
+// transition table (PaymentIntentStatus::can_transition_to) doesn't allow is a bug in the caller,
+// so it fails that transaction rather than being recorded.
+async fn record_transition(
+    executor: impl sqlx::Executor<'_, Database = Postgres>,
+    id: Uuid,
+    from: Option<PaymentIntentStatus>,
+    to: PaymentIntentStatus,
+    cause: &str,
+) -> Result<(), DomainError> {
+    let allowed = match from {
+        None => to == PaymentIntentStatus::INITIAL,
+        Some(from) => from.can_transition_to(to),
+    };
+    if !allowed {
+        return Err(DomainError::Internal(format!(
+            "payment_intent {id} cannot go from {} to {to}",
+            from.map_or("nothing", PaymentIntentStatus::as_str)
+        )));
+    }
+    insert_transition(executor, id, from, to, cause).await?;
+    Ok(())
+}
+
+// A create's writes inside the caller's transaction: the intent, its first transition and the
+// created event. With `confirm` the confirm's writes follow in the same transaction, so the
+// intermediate state is never visible. `processed` is what the processor made of that confirm.
+// Returns the intent's id and its final state.
+async fn insert_new(
+    tx: &mut Transaction<'_, Postgres>,
+    new: NewPaymentIntent,
+    confirm: Option<&ConfirmPaymentIntentParams>,
+    processed: Option<ProcessorOutcome>,
+    policy: BalancePolicy,
+) -> Result<(Uuid, PaymentIntentResponse), DomainError> {
+    let pi = insert_payment_intent(&mut **tx, new).await?;
+    let id = pi.id;
+    record_transition(&mut **tx, id, None, pi.status, CAUSE_API).await?;
+
+    let response = PaymentIntentResponse::from(pi);
+
+    // Outbox event to record that a new payment intent was created
+    insert_event(
+        &mut **tx,
+        events::PAYMENT_INTENT_CREATED,
+        event_payload(&response, None),
+    )
+    .await?;
+
+    let Some(confirm) = confirm else {
+        return Ok((id, response));
+    };
+    let confirmed = apply_confirm(
+        tx,
+        id,
+        PaymentIntentStatus::INITIAL,
+        confirm.simulated_outcome,
+        confirm.payment_method.as_deref(),
+        processed,
+        policy,
+    )
+    .await?
+    .ok_or_else(|| DomainError::Internal("new payment_intent was not confirmable".into()))?;
+
+    Ok((id, confirmed))
+}
+
+// A failure (simulated or from the processor) or a simulated timeout counts as failed, and
+// requires_action as neither (yet). A manual-capture intent only counts once it is captured.
+fn record_confirm_metrics(
+    metrics: &Metrics,
+    response: &PaymentIntentResponse,
+    simulated_outcome: Option<SimulatedOutcome>,
+) {
+    match simulated_outcome {
+        None => match response.status {
+            PaymentIntentStatus::Succeeded => record_succeeded_metrics(metrics, response),
+            PaymentIntentStatus::RequiresPaymentMethod => {
+                record_metric(metrics, metrics::PAYMENTS_FAILED, response)
+            }
+            _ => {}
+        },
+        Some(SimulatedOutcome::CardDeclined | SimulatedOutcome::Timeout) => {
+            record_metric(metrics, metrics::PAYMENTS_FAILED, response)
+        }
+        Some(SimulatedOutcome::RequiresAction) => {}
+    }
+}
+
+fn record_succeeded_metrics(metrics: &Metrics, response: &PaymentIntentResponse) {
+    record_metric(metrics, metrics::PAYMENTS_SUCCEEDED, response);
+    metrics.add(
+        metrics::GROSS_VOLUME,
+        &[("currency", &response.currency.to_ascii_lowercase())],
+        response.amount_received.unsigned_abs(),
+    );
+}
+
+// Labelled by currency and the intent's status after the change
+fn record_metric(metrics: &Metrics, name: &'static str, response: &PaymentIntentResponse) {
+    let currency = response.currency.to_ascii_lowercase();
+    if name == metrics::PAYMENTS_FAILED {
+        metrics.inc(
+            name,
+            &[
+                ("currency", &currency),
+                ("status", response.status.as_str()),
+            ],
+        );
+    } else {
+        metrics.inc(name, &[("currency", &currency)]);
+    }
+}
+
+// Not updated = not found/invalid state. No state change happened, so this rolls back
+async fn refuse_confirm(
+    mut tx: Transaction<'_, Postgres>,
+    id: Uuid,
+) -> Result<DomainError, DomainError> {
+    let exists = sqlx::query!(
+        r#"
+        SELECT status
+        FROM payment_intents
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    tx.rollback().await.ok();
+
+    Ok(match exists {
+        None => DomainError::NotFound("payment_intent"),
+        Some(row) => DomainError::UnexpectedState(
+            PaymentIntentStatus::parse(&row.status)
+                .ok_or_else(|| DomainError::Internal(format!("unknown status {}", row.status)))?,
+        ),
+    })
+}
+
+// A confirm's writes inside the caller's transaction: the status change, its transition and its
+// event. A manual-capture intent moves to requires_capture and gets no event until it is captured.
+// `from` is the status the confirm found, and `outcome` what the processor made of the payment
+// method. None when the intent is missing or no longer confirmable; nothing is committed here.
+async fn apply_confirm(
+    tx: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    from: PaymentIntentStatus,
+    simulated_outcome: Option<SimulatedOutcome>,
+    payment_method: Option<&str>,
+    outcome: Option<ProcessorOutcome>,
+    policy: BalancePolicy,
+) -> Result<Option<PaymentIntentResponse>, DomainError> {
+    if let Some(outcome) = simulated_outcome {
+        return apply_simulated_outcome(tx, id, from, outcome, payment_method).await;
+    }
+    match outcome {
+        None | Some(ProcessorOutcome::Succeeded) => {}
+        Some(ProcessorOutcome::Failed(error)) => {
+            return apply_unconfirmed(
+                tx,
+                id,
+                from,
+                PaymentIntentStatus::RequiresPaymentMethod,
+                Some(error.to_json(payment_method)),
+                payment_method,
+                events::PAYMENT_INTENT_PAYMENT_FAILED,
+            )
+            .await;
+        }
+        Some(ProcessorOutcome::RequiresAction) => {
+            return apply_unconfirmed(
+                tx,
+                id,
+                from,
+                PaymentIntentStatus::RequiresAction,
+                None,
+                payment_method,
+                events::PAYMENT_INTENT_REQUIRES_ACTION,
+            )
+            .await;
+        }
+    }
+
+    // Try to update only if in the correct state
+    let Some(pi) = mark_payment_intent_confirmed(&mut **tx, id, payment_method).await? else {
+        return Ok(None);
+    };
+
+    record_transition(&mut **tx, pi.id, Some(from), pi.status, CAUSE_API).await?;
+
+    let response = PaymentIntentResponse::from(pi);
+
+    // Outbox event records successful confirmation
+    if response.status == PaymentIntentStatus::Succeeded {
+        record_charge(tx, &response, policy).await?;
+        insert_event(
+            &mut **tx,
+            events::PAYMENT_INTENT_SUCCEEDED,
+            event_payload(&response, None),
+        )
+        .await?;
+    }
+
+    Ok(Some(response))
+}
+
+// The charge for an intent that just succeeded, its balance transaction and its charge.succeeded
+// event, written before the intent's own event as Stripe orders them
+async fn record_charge(
+    tx: &mut Transaction<'_, Postgres>,
+    response: &PaymentIntentResponse,
+    policy: BalancePolicy,
+) -> Result<(), DomainError> {
+    let charge = insert_charge(
+        &mut **tx,
+        response.id,
+        response.amount_received,
+        &response.currency,
+    )
+    .await?;
+    insert_balance_transaction(
+        &mut **tx,
+        NewBalanceTransaction {
+            kind: BalanceTransactionType::Charge,
+            source_id: charge.id,
+            amount: charge.amount,
+            fee: policy.fee(charge.amount),
+            currency: &charge.currency,
+            available_after: policy.available_after,
+        },
+    )
+    .await?;
+    insert_event(
+        &mut **tx,
+        events::CHARGE_SUCCEEDED,
+        serde_json::json!(ChargeEventData {
+            charge: ChargeResponse::from(charge),
+        }),
+    )
+    .await?;
+    Ok(())
+}
+
+// A processed confirm that didn't succeed: the new status with its transition and event. A retry
+// that is declined again stays requires_payment_method, so it has no transition, only the event.
+async fn apply_unconfirmed(
+    tx: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    from: PaymentIntentStatus,
+    status: PaymentIntentStatus,
+    last_payment_error: Option<serde_json::Value>,
+    payment_method: Option<&str>,
+    event_type: &str,
+) -> Result<Option<PaymentIntentResponse>, DomainError> {
+    let updated =
+        mark_payment_intent_unconfirmed(&mut **tx, id, status, last_payment_error, payment_method)
+            .await?;
+    let Some(pi) = updated else {
+        return Ok(None);
+    };
+
+    if pi.status != from {
+        record_transition(&mut **tx, id, Some(from), pi.status, CAUSE_API).await?;
+    }
+
+    let response = PaymentIntentResponse::from(pi);
+    insert_event(&mut **tx, event_type, event_payload(&response, None)).await?;
+    Ok(Some(response))
+}
+
+// Applies a forced X-Simulate outcome instead of the normal confirm. The override is written to
+// the audit log, and to last_payment_error/the event payload, so it is never mistaken for real.
+async fn apply_simulated_outcome(
+    tx: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    from: PaymentIntentStatus,
+    outcome: SimulatedOutcome,
+    payment_method: Option<&str>,
+) -> Result<Option<PaymentIntentResponse>, DomainError> {
+    let (status, last_payment_error, event_type) = match outcome {
+        SimulatedOutcome::CardDeclined => (
+            PaymentIntentStatus::RequiresPaymentMethod,
+            Some(serde_json::json!({
+                "code": "card_declined",
+                "message": "Your card was declined.",
+                "simulated": true,
+            })),
+            Some(events::PAYMENT_INTENT_PAYMENT_FAILED),
+        ),
+        SimulatedOutcome::Timeout => (
+            PaymentIntentStatus::RequiresConfirmation,
+            Some(serde_json::json!({
+                "code": "processing_timeout",
+                "message": "The acquirer did not respond in time.",
+                "simulated": true,
+            })),
+            None,
+        ),
+        SimulatedOutcome::RequiresAction => (
+            PaymentIntentStatus::RequiresAction,
+            None,
+            Some(events::PAYMENT_INTENT_REQUIRES_ACTION),
+        ),
+    };
+
+    let updated =
+        mark_payment_intent_unconfirmed(&mut **tx, id, status, last_payment_error, payment_method)
+            .await?;
+
+    let Some(pi) = updated else {
+        return Ok(None);
+    };
+
+    insert_audit_entry(
+        &mut **tx,
+        "payment_intent.simulated_outcome",
+        id,
+        serde_json::json!({ "outcome": outcome.as_str() }),
+    )
+    .await?;
+
+    if pi.status != from {
+        record_transition(&mut **tx, id, Some(from), pi.status, CAUSE_SIMULATED).await?;
+    }
+
+    let response = PaymentIntentResponse::from(pi);
+
+    if let Some(event_type) = event_type {
+        let payload = event_payload(&response, Some(outcome));
+        insert_event(&mut **tx, event_type, payload).await?;
+    }
+
+    Ok(Some(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_intent(amount: i64, currency: &str) -> NewPaymentIntent {
+        NewPaymentIntent {
+            amount,
+            currency: currency.to_string(),
+            metadata: BTreeMap::new(),
+            natural_key: None,
+            capture_method: "automatic".to_string(),
+            description: None,
+            customer_id: None,
+        }
+    }
+
+    // What PaymentIntentService borrows, owned by the test
+    #[derive(Default)]
+    struct ServiceDeps {
+        config: Config,
+        in_flight: InFlight,
+        metrics: Metrics,
+        status_changes: StatusChanges,
+        faults: Faults,
+    }
+
+    impl ServiceDeps {
+        fn service<'a>(&'a self, pool: &'a PgPool) -> PaymentIntentService<'a> {
+            PaymentIntentService::new(
+                pool,
+                &self.config,
+                &self.in_flight,
+                &self.metrics,
+                &self.status_changes,
+                &self.faults,
+            )
+        }
+    }
+
+    fn params(amount: i64, currency: Option<&str>) -> CreatePaymentIntentParams {
+        CreatePaymentIntentParams {
+            amount,
+            currency: currency.map(str::to_string),
+            confirm: None,
+            metadata: BTreeMap::new(),
+            capture_method: None,
+            description: None,
+            customer: None,
+            deadline: Deadline::default(),
+        }
+    }
+
+    #[test]
+    fn validate_rejects_non_positive_amount() {
+        let err = validate_new_payment_intent(&new_intent(0, "gbp")).unwrap_err();
+        assert_eq!(err, "amount must be > 0");
+    }
+
+    #[test]
+    fn validate_rejects_empty_currency() {
+        let err = validate_new_payment_intent(&new_intent(2500, "   ")).unwrap_err();
+        assert_eq!(err, "currency is required");
+    }
+
+    #[test]
+    fn validate_accepts_good_input() {
+        assert!(validate_new_payment_intent(&new_intent(2500, "gbp")).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_malformed_currencies() {
+        let huge = "a".repeat(1024 * 1024);
+        let cases = [
+            huge.as_str(),
+            "gb",
+            "gbpx",
+            "g\0p",
+            "gb\0",
+            "gb1",
+            // Multibyte: three chars but more than three bytes, and cut at a char boundary
+            "gb€",
+            "ğbp",
+            "€",
+            "\u{1F4B7}",
+        ];
+        for currency in cases {
+            let err = validate_new_payment_intent(&new_intent(2500, currency)).unwrap_err();
+            assert_eq!(err, "currency must be a 3-letter ISO code", "{currency:?}");
+        }
+
+        assert!(validate_new_payment_intent(&new_intent(2500, " GBP ")).is_ok());
+
+        let err = validate_new_payment_intent(&new_intent(2500, "zzz")).unwrap_err();
+        assert_eq!(err, "currency must be a supported ISO 4217 code");
+    }
+
+    #[test]
+    fn validate_bounds_idempotency_keys() {
+        assert!(validate_idempotency_key("k").is_ok());
+        assert!(validate_idempotency_key(&"k".repeat(MAX_IDEMPOTENCY_KEY_LEN)).is_ok());
+        assert!(validate_idempotency_key(&"k".repeat(MAX_IDEMPOTENCY_KEY_LEN + 1)).is_err());
+        assert!(validate_idempotency_key("").is_err());
+    }
+
+    #[test]
+    fn upgrade_fills_fields_missing_from_old_bodies() {
+        let pi = PaymentIntent {
+            id: Uuid::new_v4(),
+            amount: 1234,
+            currency: "gbp".to_string(),
+            status: PaymentIntentStatus::Canceled,
+            created_at: chrono::Utc::now(),
+            cancellation_reason: Some("abandoned".to_string()),
+            last_payment_error: None,
+            webhook_acknowledged_at: None,
+            archived_at: None,
+            metadata: serde_json::json!({ "order_id": "o_1" }),
+            natural_key: None,
+            capture_method: "manual".to_string(),
+            amount_received: 0,
+            description: None,
+            expires_at: chrono::Utc::now(),
+            payment_method: None,
+            customer_id: None,
+        };
+        let v1 = serde_json::json!({ "id": pi.id, "amount": 1234, "currency": "gbp", "status": "canceled" });
+
+        let upgraded = upgrade_response_body(v1, 1, &pi).unwrap();
+        assert_eq!(upgraded["cancellation_reason"], "abandoned");
+        assert_eq!(upgraded["metadata"]["order_id"], "o_1");
+        assert_eq!(upgraded["capture_method"], "manual");
+        assert_eq!(upgraded["amount_received"], 0);
+        assert!(upgraded["description"].is_null());
+
+        let current = serde_json::to_value(PaymentIntentResponse::from(pi.clone())).unwrap();
+        assert_eq!(upgraded, current);
+        assert!(upgrade_response_body(current, RESPONSE_BODY_VERSION + 1, &pi).is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn create_distinguishes_created_from_replayed(pool: PgPool) {
+        let deps = ServiceDeps::default();
+        let service = deps.service(&pool);
+        let key = || Some(IdempotencyKey("svc".to_string()));
+
+        let first = service
+            .create(params(1000, Some("gbp")), key())
+            .await
+            .unwrap();
+        let CreateOutcome::Created(created) = first else {
+            panic!("first create should not be a replay");
+        };
+
+        let second = service
+            .create(params(1000, Some("gbp")), key())
+            .await
+            .unwrap();
+        let CreateOutcome::Replayed(replayed, ReplaySource::Stored) = second else {
+            panic!("second create should be a replay");
+        };
+        assert_eq!(
+            serde_json::to_value(&replayed).unwrap(),
+            serde_json::to_value(&created).unwrap()
+        );
+
+        let err = service
+            .create(params(2000, Some("gbp")), key())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, DomainError::IdempotencyKeyReused));
+
+        let err = service
+            .create(params(0, Some("gbp")), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, DomainError::InvalidParameter(_)));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn resolve_applies_default_currency_and_allowlist(pool: PgPool) {
+        let deps = ServiceDeps {
+            config: Config {
+                default_currency: Some("gbp".to_string()),
+                allowed_currencies: Some(vec!["gbp".to_string(), "eur".to_string()]),
+                ..Config::default()
+            },
+            ..ServiceDeps::default()
+        };
+        let service = deps.service(&pool);
+
+        let omitted = service.resolve(params(1000, None)).unwrap();
+        let explicit = service.resolve(params(1000, Some("GBP"))).unwrap();
+        assert_eq!(omitted.currency, "gbp");
+        assert_eq!(
+            request_fingerprint(&omitted, None),
+            request_fingerprint(&explicit, None)
+        );
+
+        let err = service.resolve(params(1000, Some("usd"))).err().unwrap();
+        assert_eq!(err.to_string(), "currency must be one of: gbp, eur");
+
+        let deps = ServiceDeps::default();
+        let service = deps.service(&pool);
+        let err = service.resolve(params(1000, None)).err().unwrap();
+        assert_eq!(err.to_string(), "currency is required");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn confirm_moves_to_succeeded_once(pool: PgPool) {
+        let deps = ServiceDeps::default();
+        let service = deps.service(&pool);
+
+        let created = service
+            .create(params(1000, Some("gbp")), None)
+            .await
+            .unwrap()
+            .into_inner();
+        let id = serde_json::to_value(&created).unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        service.confirm(id, Default::default()).await.unwrap();
+        let (pi, latest_event) = service.retrieve(id).await.unwrap();
+        assert_eq!(pi.status, PaymentIntentStatus::Succeeded);
+        assert_eq!(latest_event.unwrap().event_type, "payment_intent.succeeded");
+
+        let err = service.confirm(id, Default::default()).await.unwrap_err();
+        assert!(matches!(
+            err,
+            DomainError::UnexpectedState(PaymentIntentStatus::Succeeded)
+        ));
+
+        let err = service
+            .confirm(Uuid::new_v4(), Default::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DomainError::NotFound("payment_intent")));
+    }
+}
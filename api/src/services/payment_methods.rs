@@ -0,0 +1,206 @@
+use chrono::{Datelike, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::customers::storage::lock_customer;
+use crate::in_flight::InFlight;
+use crate::payment_methods::PaymentMethodResponse;
+use crate::payment_methods::storage::{
+    NewPaymentMethod, insert_payment_method, lock_payment_method, set_payment_method_customer,
+};
+use crate::processor::test_token_for_card;
+use crate::services::DomainError;
+
+pub struct CreatePaymentMethodParams {
+    pub number: String,
+    pub exp_month: i32,
+    pub exp_year: i32,
+    pub cvc: Option<String>,
+}
+
+pub struct PaymentMethodService<'a> {
+    db: &'a PgPool,
+    in_flight: &'a InFlight,
+}
+
+impl<'a> PaymentMethodService<'a> {
+    pub fn new(db: &'a PgPool, in_flight: &'a InFlight) -> Self {
+        Self { db, in_flight }
+    }
+
+    // Keeps brand, last4, expiry and the test token the number behaves as; the number and cvc
+    // go no further than this function
+    pub async fn create(
+        &self,
+        params: CreatePaymentMethodParams,
+    ) -> Result<PaymentMethodResponse, DomainError> {
+        let _in_flight = self.in_flight.track("create payment_method");
+        let number: String = params.number.chars().filter(|c| *c != ' ').collect();
+        validate_card(
+            &number,
+            params.exp_month,
+            params.exp_year,
+            params.cvc.as_deref(),
+        )
+        .map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+
+        let brand = card_brand(&number);
+        let payment_method = insert_payment_method(
+            self.db,
+            NewPaymentMethod {
+                brand: brand.to_string(),
+                last4: number[number.len() - 4..].to_string(),
+                exp_month: params.exp_month,
+                exp_year: params.exp_year,
+                processor_token: test_token_for_card(&number, brand).to_string(),
+            },
+        )
+        .await?;
+        Ok(PaymentMethodResponse::from(payment_method))
+    }
+
+    // Attaching again to the same customer is a no-op. The customer's row is locked too, so a
+    // concurrent delete (which detaches its payment methods) can't miss this one.
+    pub async fn attach(
+        &self,
+        id: Uuid,
+        customer: Uuid,
+    ) -> Result<PaymentMethodResponse, DomainError> {
+        let _in_flight = self.in_flight.track(format!("attach payment_method {id}"));
+        let mut tx = self.db.begin().await?;
+        let payment_method = lock_payment_method(&mut *tx, id)
+            .await?
+            .ok_or(DomainError::NotFound("payment_method"))?;
+        lock_customer(&mut *tx, customer)
+            .await?
+            .ok_or(DomainError::NotFound("customer"))?;
+
+        let attached = match payment_method.customer_id {
+            Some(current) if current == customer => payment_method,
+            Some(_) => {
+                return Err(DomainError::InvalidParameter(
+                    "payment_method is already attached to another customer".into(),
+                ));
+            }
+            None => set_payment_method_customer(&mut *tx, id, Some(customer)).await?,
+        };
+        tx.commit().await?;
+
+        Ok(PaymentMethodResponse::from(attached))
+    }
+
+    pub async fn detach(&self, id: Uuid) -> Result<PaymentMethodResponse, DomainError> {
+        let _in_flight = self.in_flight.track(format!("detach payment_method {id}"));
+        let mut tx = self.db.begin().await?;
+        let payment_method = lock_payment_method(&mut *tx, id)
+            .await?
+            .ok_or(DomainError::NotFound("payment_method"))?;
+        if payment_method.customer_id.is_none() {
+            return Err(DomainError::InvalidParameter(
+                "payment_method is not attached to a customer".into(),
+            ));
+        }
+        let detached = set_payment_method_customer(&mut *tx, id, None).await?;
+        tx.commit().await?;
+
+        Ok(PaymentMethodResponse::from(detached))
+    }
+}
+
+// By the leading digits; only decides the brand shown and which success token it behaves as
+fn card_brand(number: &str) -> &'static str {
+    let prefix = |n: usize| number[..n].parse::<u32>().unwrap_or(0);
+    match () {
+        _ if number.starts_with('4') => "visa",
+        _ if (51..=55).contains(&prefix(2)) || (2221..=2720).contains(&prefix(4)) => "mastercard",
+        _ if matches!(prefix(2), 34 | 37) => "amex",
+        _ if prefix(4) == 6011 || prefix(2) == 65 => "discover",
+        _ => "unknown",
+    }
+}
+
+// Runs before any DB call. `number` has had its spaces taken out.
+fn validate_card(
+    number: &str,
+    exp_month: i32,
+    exp_year: i32,
+    cvc: Option<&str>,
+) -> Result<(), &'static str> {
+    if !(12..=19).contains(&number.len())
+        || !number.bytes().all(|b| b.is_ascii_digit())
+        || !luhn_valid(number)
+    {
+        return Err("card number is invalid");
+    }
+    if !(1..=12).contains(&exp_month) {
+        return Err("card exp_month must be 1 to 12");
+    }
+    let today = Utc::now();
+    if (exp_year, exp_month) < (today.year(), today.month() as i32) || exp_year > 9999 {
+        return Err("card has expired or exp_year is invalid");
+    }
+    if let Some(cvc) = cvc
+        && (!(3..=4).contains(&cvc.len()) || !cvc.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return Err("card cvc must be 3 or 4 digits");
+    }
+    Ok(())
+}
+
+fn luhn_valid(number: &str) -> bool {
+    let sum: u32 = number
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, b)| {
+            let digit = u32::from(b - b'0');
+            match i % 2 {
+                0 => digit,
+                _ if digit > 4 => digit * 2 - 9,
+                _ => digit * 2,
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_checks_number_expiry_and_cvc() {
+        let year = Utc::now().year() + 1;
+        assert!(validate_card("4242424242424242", 12, year, Some("123")).is_ok());
+        assert_eq!(
+            validate_card("4242424242424241", 12, year, None).unwrap_err(),
+            "card number is invalid"
+        );
+        assert_eq!(
+            validate_card("42424242", 12, year, None).unwrap_err(),
+            "card number is invalid"
+        );
+        assert_eq!(
+            validate_card("4242424242424242", 13, year, None).unwrap_err(),
+            "card exp_month must be 1 to 12"
+        );
+        assert_eq!(
+            validate_card("4242424242424242", 12, 2020, None).unwrap_err(),
+            "card has expired or exp_year is invalid"
+        );
+        assert_eq!(
+            validate_card("4242424242424242", 12, year, Some("12a")).unwrap_err(),
+            "card cvc must be 3 or 4 digits"
+        );
+    }
+
+    #[test]
+    fn brands_come_from_the_leading_digits() {
+        assert_eq!(card_brand("4242424242424242"), "visa");
+        assert_eq!(card_brand("5555555555554444"), "mastercard");
+        assert_eq!(card_brand("2223003122003222"), "mastercard");
+        assert_eq!(card_brand("378282246310005"), "amex");
+        assert_eq!(card_brand("6011111111111117"), "discover");
+        assert_eq!(card_brand("3056930009020004"), "unknown");
+    }
+}
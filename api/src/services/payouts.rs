@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use mini_stripe_types::events::{self, PayoutEventData};
+use mini_stripe_types::payouts::PayoutStatus;
+use sqlx::PgPool;
+
+use crate::balance::BalanceTransactionType;
+use crate::balance::storage::{
+    NewBalanceTransaction, available_balance, insert_balance_transaction, lock_available_balance,
+};
+use crate::config::Config;
+use crate::currency::Currency;
+use crate::events_outbox::insert_event;
+use crate::in_flight::InFlight;
+use crate::payouts::PayoutResponse;
+use crate::payouts::storage::{NewPayout, insert_payout, lock_due_payouts, settle_payout};
+use crate::services::DomainError;
+use crate::services::payment_intents::validate_description;
+
+pub const DEFAULT_DESTINATION: &str = "ba_test";
+
+// The test bank accounts a payout can go to, and the failure each one settles with
+const DESTINATIONS: &[(&str, Option<(&str, &str)>)] = &[
+    (DEFAULT_DESTINATION, None),
+    (
+        "ba_test_no_account",
+        Some((
+            "no_account",
+            "The bank account details on file don't match an account.",
+        )),
+    ),
+    (
+        "ba_test_account_closed",
+        Some(("account_closed", "The bank account has been closed.")),
+    ),
+];
+
+pub struct CreatePayoutParams {
+    pub amount: i64,
+    pub currency: String,
+    pub destination: Option<String>,
+    pub description: Option<String>,
+}
+
+pub struct PayoutService<'a> {
+    db: &'a PgPool,
+    config: &'a Config,
+    in_flight: &'a InFlight,
+}
+
+impl<'a> PayoutService<'a> {
+    pub fn new(db: &'a PgPool, config: &'a Config, in_flight: &'a InFlight) -> Self {
+        Self {
+            db,
+            config,
+            in_flight,
+        }
+    }
+
+    // One transaction: the pending payout and the balance transaction taking its amount out of
+    // the available balance. A per-currency advisory lock makes concurrent payouts take turns,
+    // so together they can never draw more than was available.
+    pub async fn create(&self, params: CreatePayoutParams) -> Result<PayoutResponse, DomainError> {
+        let _in_flight = self.in_flight.track("create payout");
+
+        if params.amount <= 0 {
+            return Err(DomainError::InvalidParameter("amount must be > 0".into()));
+        }
+        let currency = Currency::parse(&params.currency)
+            .map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+        let destination = params
+            .destination
+            .unwrap_or_else(|| DEFAULT_DESTINATION.to_string());
+        if failure_for(&destination).is_none() {
+            return Err(DomainError::InvalidParameter(format!(
+                "no such destination: {destination}"
+            )));
+        }
+        if let Some(description) = &params.description {
+            validate_description(description)
+                .map_err(|msg| DomainError::InvalidParameter(msg.into()))?;
+        }
+
+        let mut tx = self.db.begin().await?;
+        lock_available_balance(&mut *tx, currency.code).await?;
+        let available = available_balance(&mut *tx, currency.code).await?;
+        if params.amount > available {
+            return Err(DomainError::InsufficientBalance {
+                amount: params.amount,
+                available,
+                currency: currency.code,
+            });
+        }
+
+        let payout = insert_payout(
+            &mut *tx,
+            NewPayout {
+                amount: params.amount,
+                currency: currency.code,
+                destination: &destination,
+                description: params.description.as_deref(),
+                settle_after: self.config.payout_settle_after.unwrap_or_default(),
+            },
+        )
+        .await?;
+        insert_balance_transaction(
+            &mut *tx,
+            NewBalanceTransaction {
+                kind: BalanceTransactionType::Payout,
+                source_id: payout.id,
+                amount: -payout.amount,
+                fee: 0,
+                currency: currency.code,
+                available_after: Duration::ZERO,
+            },
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(PayoutResponse::from(payout))
+    }
+
+    // Settles up to `limit` due payouts in one transaction and returns how many. A failed one
+    // gets its amount back in the balance; each writes payout.paid or payout.failed.
+    pub async fn settle_batch(&self, limit: i64) -> Result<usize, DomainError> {
+        let mut tx = self.db.begin().await?;
+        let due = lock_due_payouts(&mut *tx, limit).await?;
+        for payout in &due {
+            // Destinations were checked on create
+            let failure = failure_for(&payout.destination).flatten();
+            let (status, event_type) = match failure {
+                None => (PayoutStatus::Paid, events::PAYOUT_PAID),
+                Some(_) => (PayoutStatus::Failed, events::PAYOUT_FAILED),
+            };
+            let settled = settle_payout(&mut *tx, payout.id, status, failure).await?;
+            if failure.is_some() {
+                insert_balance_transaction(
+                    &mut *tx,
+                    NewBalanceTransaction {
+                        kind: BalanceTransactionType::PayoutFailure,
+                        source_id: settled.id,
+                        amount: settled.amount,
+                        fee: 0,
+                        currency: &settled.currency,
+                        available_after: Duration::ZERO,
+                    },
+                )
+                .await?;
+            }
+            insert_event(
+                &mut *tx,
+                event_type,
+                serde_json::json!(PayoutEventData {
+                    payout: PayoutResponse::from(settled),
+                }),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(due.len())
+    }
+}
+
+// None for an unknown destination, Some(None) for one that is paid
+fn failure_for(destination: &str) -> Option<Option<(&'static str, &'static str)>> {
+    DESTINATIONS
+        .iter()
+        .find(|(id, _)| *id == destination)
+        .map(|(_, failure)| *failure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destinations_settle_as_documented() {
+        assert_eq!(failure_for(DEFAULT_DESTINATION), Some(None));
+        assert_eq!(
+            failure_for("ba_test_account_closed").flatten().unwrap().0,
+            "account_closed"
+        );
+        assert_eq!(
+            failure_for("ba_test_no_account").flatten().unwrap().0,
+            "no_account"
+        );
+        assert_eq!(failure_for("ba_live_123"), None);
+    }
+}
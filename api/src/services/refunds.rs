@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use mini_stripe_types::events::{self, RefundEventData};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::balance::BalanceTransactionType;
+use crate::balance::storage::{
+    NewBalanceTransaction, insert_balance_transaction, lock_available_balance,
+};
+use crate::charges::storage::{add_amount_refunded, lock_charge, lock_charge_for_payment_intent};
+use crate::events_outbox::insert_event;
+use crate::in_flight::InFlight;
+use crate::payment_intents::storage::fetch_payment_intent;
+use crate::refunds::RefundResponse;
+use crate::refunds::storage::insert_refund;
+use crate::services::DomainError;
+
+pub struct CreateRefundParams {
+    pub payment_intent: Option<Uuid>,
+    pub charge: Option<Uuid>,
+    pub amount: Option<i64>,
+}
+
+pub struct RefundService<'a> {
+    db: &'a PgPool,
+    in_flight: &'a InFlight,
+}
+
+impl<'a> RefundService<'a> {
+    pub fn new(db: &'a PgPool, in_flight: &'a InFlight) -> Self {
+        Self { db, in_flight }
+    }
+
+    // One transaction: the refund, the charge's new amount_refunded and refund.created. The
+    // charge's row lock makes concurrent refunds of it take turns, so together they can never go
+    // over its amount.
+    pub async fn create(&self, params: CreateRefundParams) -> Result<RefundResponse, DomainError> {
+        let _in_flight = self.in_flight.track("create refund");
+
+        if params.amount.is_some_and(|amount| amount <= 0) {
+            return Err(DomainError::InvalidParameter("amount must be > 0".into()));
+        }
+
+        let mut tx = self.db.begin().await?;
+        let charge = match (params.payment_intent, params.charge) {
+            (Some(payment_intent), None) => {
+                match lock_charge_for_payment_intent(&mut *tx, payment_intent).await? {
+                    Some(charge) => charge,
+                    None => {
+                        let pi = fetch_payment_intent(&mut *tx, payment_intent)
+                            .await?
+                            .ok_or(DomainError::NotFound("payment_intent"))?;
+                        return Err(DomainError::NotRefundable(pi.status));
+                    }
+                }
+            }
+            (None, Some(charge)) => lock_charge(&mut *tx, charge)
+                .await?
+                .ok_or(DomainError::NotFound("charge"))?,
+            _ => {
+                return Err(DomainError::InvalidParameter(
+                    "pass exactly one of payment_intent or charge".into(),
+                ));
+            }
+        };
+
+        let remaining = charge.amount - charge.amount_refunded;
+        if remaining == 0 {
+            return Err(DomainError::ChargeAlreadyRefunded);
+        }
+        let amount = params.amount.unwrap_or(remaining);
+        if amount > remaining {
+            return Err(DomainError::RefundTooLarge { amount, remaining });
+        }
+
+        let refund = insert_refund(
+            &mut *tx,
+            charge.id,
+            charge.payment_intent_id,
+            amount,
+            &charge.currency,
+        )
+        .await?;
+        add_amount_refunded(&mut *tx, charge.id, amount).await?;
+        // The refund draws on the available balance, so it waits for a payout checking it
+        lock_available_balance(&mut *tx, &charge.currency).await?;
+        // Available at once, and the charge's fee isn't given back
+        insert_balance_transaction(
+            &mut *tx,
+            NewBalanceTransaction {
+                kind: BalanceTransactionType::Refund,
+                source_id: refund.id,
+                amount: -amount,
+                fee: 0,
+                currency: &charge.currency,
+                available_after: Duration::ZERO,
+            },
+        )
+        .await?;
+
+        let response = RefundResponse::from(refund);
+        insert_event(
+            &mut *tx,
+            events::REFUND_CREATED,
+            serde_json::json!(RefundEventData {
+                refund: response.clone(),
+            }),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(response)
+    }
+}
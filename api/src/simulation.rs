@@ -0,0 +1,79 @@
+use axum::http::HeaderMap;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::headers::require_ascii_header;
+
+pub const SIMULATE_HEADER: &str = "X-Simulate";
+
+// Forces the result of a single confirm regardless of the intent's data (sandbox only)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulatedOutcome {
+    // Moves to requires_payment_method with a card_declined last_payment_error
+    CardDeclined,
+    // The acquirer never answers: nothing changes except last_payment_error
+    Timeout,
+    // Moves to requires_action, as if 3DS were needed
+    RequiresAction,
+}
+
+impl SimulatedOutcome {
+    // Accepts "outcome=card_declined" (the only key for now)
+    pub fn parse(s: &str) -> Result<Self, &'static str> {
+        let Some(("outcome", outcome)) = s.split_once('=').map(|(key, v)| (key.trim(), v)) else {
+            return Err("X-Simulate must look like `outcome=card_declined`");
+        };
+
+        match outcome.trim() {
+            "card_declined" => Ok(Self::CardDeclined),
+            "timeout" => Ok(Self::Timeout),
+            "requires_action" => Ok(Self::RequiresAction),
+            _ => Err("outcome must be one of: card_declined, timeout, requires_action"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CardDeclined => "card_declined",
+            Self::Timeout => "timeout",
+            Self::RequiresAction => "requires_action",
+        }
+    }
+}
+
+// Like latency::resolve: only honoured in sandbox mode, a 400 anywhere else
+pub fn resolve(config: &Config, headers: &HeaderMap) -> Result<Option<SimulatedOutcome>, ApiError> {
+    let Some(value) = require_ascii_header(headers, SIMULATE_HEADER, "parameter_invalid")? else {
+        return Ok(None);
+    };
+
+    if !config.sandbox_mode {
+        return Err(ApiError::bad_request(
+            "sandbox_only",
+            format!("{SIMULATE_HEADER} is only allowed in sandbox mode"),
+        ));
+    }
+
+    SimulatedOutcome::parse(value)
+        .map(Some)
+        .map_err(|msg| ApiError::bad_request("parameter_invalid", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_known_outcomes() {
+        assert_eq!(
+            SimulatedOutcome::parse("outcome=card_declined").unwrap(),
+            SimulatedOutcome::CardDeclined
+        );
+        assert_eq!(
+            SimulatedOutcome::parse(" outcome = timeout ").unwrap(),
+            SimulatedOutcome::Timeout
+        );
+        assert!(SimulatedOutcome::parse("outcome=explode").is_err());
+        assert!(SimulatedOutcome::parse("card_declined").is_err());
+    }
+}
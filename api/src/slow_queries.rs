@@ -0,0 +1,290 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::field::{Field, Visit};
+use tracing::instrument::WithSubscriber;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Metadata, Subscriber, warn};
+use uuid::Uuid;
+
+use crate::state::AppState;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// sqlx reports every statement it runs as an event under this target, with its duration
+const SQLX_TARGET: &str = "sqlx::query";
+// How many logged entries are kept in memory for diagnostics
+const RECENT_LIMIT: usize = 20;
+
+// One slow statement, logged as a WARN event with these fields
+#[derive(Clone, Debug, Serialize)]
+pub struct SlowQuery {
+    pub level: &'static str,
+    pub message: &'static str,
+    pub duration_ms: u64,
+    pub threshold_ms: u64,
+    // Leading words of the statement with literals masked, plus a fingerprint of the whole of it
+    pub statement: String,
+    pub route: String,
+    pub request_id: String,
+}
+
+// slow_queries_total{route}, plus the most recent entries
+#[derive(Clone, Default)]
+pub struct SlowQueries {
+    totals: Arc<Mutex<HashMap<String, u64>>>,
+    recent: Arc<Mutex<VecDeque<SlowQuery>>>,
+}
+
+impl SlowQueries {
+    fn record(&self, entry: SlowQuery) {
+        warn!(
+            duration_ms = entry.duration_ms,
+            threshold_ms = entry.threshold_ms,
+            statement = %entry.statement,
+            route = %entry.route,
+            request_id = %entry.request_id,
+            "slow query"
+        );
+
+        *self
+            .totals
+            .lock()
+            .unwrap()
+            .entry(entry.route.clone())
+            .or_default() += 1;
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_LIMIT {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+
+    pub fn totals(&self) -> HashMap<String, u64> {
+        self.totals.lock().unwrap().clone()
+    }
+
+    pub fn total(&self, route: &str) -> u64 {
+        self.totals.lock().unwrap().get(route).copied().unwrap_or(0)
+    }
+
+    // Oldest first
+    pub fn recent(&self) -> Vec<SlowQuery> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// Middleware. Watches the statements run while the request is handled (not ones on tasks it
+// spawns) and logs those over config.slow_query_threshold once the response is ready. An event
+// emitted from inside the watcher would reach no subscriber at all.
+pub async fn instrument(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(threshold) = state.config.slow_query_threshold else {
+        return next.run(req).await;
+    };
+
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    // Whatever subscriber is already in scope keeps seeing everything
+    let inner = tracing::dispatcher::get_default(Dispatch::clone);
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let watcher = SlowQueryWatcher {
+        inner,
+        threshold,
+        route,
+        request_id,
+        found: found.clone(),
+    };
+
+    let response = next.run(req).with_subscriber(watcher).await;
+    for entry in std::mem::take(&mut *found.lock().unwrap()) {
+        state.slow_queries.record(entry);
+    }
+    response
+}
+
+struct SlowQueryWatcher {
+    inner: Dispatch,
+    threshold: Duration,
+    route: String,
+    request_id: String,
+    // This request's slow statements so far
+    found: Arc<Mutex<Vec<SlowQuery>>>,
+}
+
+impl SlowQueryWatcher {
+    fn check(&self, event: &Event<'_>) {
+        let mut fields = StatementFields::default();
+        event.record(&mut fields);
+
+        let (Some(elapsed), Some(sql)) = (fields.elapsed_secs, fields.statement) else {
+            return;
+        };
+        let elapsed = Duration::from_secs_f64(elapsed);
+        if elapsed < self.threshold {
+            return;
+        }
+
+        self.found.lock().unwrap().push(SlowQuery {
+            level: "WARN",
+            message: "slow query",
+            duration_ms: elapsed.as_millis() as u64,
+            threshold_ms: self.threshold.as_millis() as u64,
+            statement: statement_id(&sql),
+            route: self.route.clone(),
+            request_id: self.request_id.clone(),
+        });
+    }
+}
+
+impl Subscriber for SlowQueryWatcher {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata);
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == SQLX_TARGET || self.inner.enabled(metadata)
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        self.inner.new_span(span)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        self.inner.record(span, values)
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        self.inner.record_follows_from(span, follows)
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        if event.metadata().target() == SQLX_TARGET {
+            self.check(event);
+        }
+        if self.inner.enabled(event.metadata()) {
+            self.inner.event(event);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        self.inner.enter(span)
+    }
+
+    fn exit(&self, span: &Id) {
+        self.inner.exit(span)
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        self.inner.clone_span(id)
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        self.inner.try_close(id)
+    }
+}
+
+#[derive(Default)]
+struct StatementFields {
+    elapsed_secs: Option<f64>,
+    statement: Option<String>,
+}
+
+impl Visit for StatementFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.elapsed_secs = Some(value);
+        }
+    }
+
+    // sqlx leaves db.statement empty when the summary already is the whole statement
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "db.statement" if !value.is_empty() => self.statement = Some(value.to_string()),
+            "summary" if self.statement.is_none() => self.statement = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+// Never includes values: string and numeric literals are masked before anything is kept, and
+// `query!` statements only carry `$n` placeholders anyway
+fn statement_id(sql: &str) -> String {
+    let mut masked = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut prev_is_word = false;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // '' is an escaped quote inside the literal
+            while let Some(c) = chars.next() {
+                if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                    break;
+                }
+            }
+            masked.push('?');
+            prev_is_word = false;
+        } else if c.is_ascii_digit() && !prev_is_word {
+            while chars.next_if(|c| c.is_ascii_digit() || *c == '.').is_some() {}
+            masked.push('?');
+            prev_is_word = false;
+        } else {
+            masked.push(c);
+            prev_is_word = c.is_alphanumeric() || c == '_' || c == '$';
+        }
+    }
+
+    let words: Vec<&str> = masked.split_whitespace().collect();
+    let digest = Sha256::digest(words.join(" ").as_bytes());
+    let head = words.iter().take(6).copied().collect::<Vec<_>>().join(" ");
+    let ellipsis = if words.len() > 6 { " …" } else { "" };
+
+    format!("{head}{ellipsis} [{}]", hex::encode(&digest[..4]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statement_id_masks_literals_and_keeps_placeholders() {
+        let id = statement_id("SELECT pg_sleep(0.25), 'card 4242'  FROM t WHERE id = $1");
+        assert!(
+            id.starts_with("SELECT pg_sleep(?), ? FROM t WHERE …"),
+            "{id}"
+        );
+        assert!(!id.contains("4242"));
+
+        let escaped = statement_id("SELECT 'it''s secret' AS x");
+        assert!(escaped.starts_with("SELECT ? AS x ["), "{escaped}");
+
+        // Same shape, different values: same fingerprint
+        assert_eq!(
+            statement_id("SELECT pg_sleep(1)"),
+            statement_id("SELECT  pg_sleep(2)")
+        );
+        assert_ne!(statement_id("SELECT 1"), statement_id("SELECT $1"));
+    }
+}
@@ -1,6 +1,51 @@
 use sqlx::{Pool, Postgres};
 
+use crate::backpressure::Backpressure;
+use crate::config::Config;
+use crate::faults::Faults;
+use crate::in_flight::InFlight;
+use crate::latency_alerts::LatencyWatch;
+use crate::metrics::Metrics;
+use crate::sandbox::EchoReceiver;
+use crate::slow_queries::SlowQueries;
+use crate::status_changes::StatusChanges;
+use crate::warmup::Readiness;
+
 #[derive(Clone)]
 pub struct AppState {
+    // The primary. Writes, and any read a write or an idempotent replay depends on, use this.
     pub db: Pool<Postgres>,
+    // Reads that can tolerate replica lag (list endpoints); the primary unless
+    // READ_DATABASE_URL is set
+    pub read_db: Pool<Postgres>,
+    pub config: Config,
+    pub echo: EchoReceiver,
+    pub readiness: Readiness,
+    pub in_flight: InFlight,
+    pub slow_queries: SlowQueries,
+    pub metrics: Metrics,
+    pub backpressure: Backpressure,
+    pub latency_watch: LatencyWatch,
+    pub status_changes: StatusChanges,
+    // Sandbox fault rules (POST /v1/admin/faults)
+    pub faults: Faults,
+}
+
+impl AppState {
+    pub fn new(db: Pool<Postgres>) -> Self {
+        Self {
+            read_db: db.clone(),
+            db,
+            config: Config::default(),
+            echo: EchoReceiver::default(),
+            readiness: Readiness::ready(),
+            in_flight: InFlight::default(),
+            slow_queries: SlowQueries::default(),
+            metrics: Metrics::default(),
+            backpressure: Backpressure::default(),
+            latency_watch: LatencyWatch::default(),
+            status_changes: StatusChanges::default(),
+            faults: Faults::default(),
+        }
+    }
 }
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+// Changes buffered per waiter; one that falls further behind re-reads the intent instead
+const CHANNEL_CAPACITY: usize = 1024;
+
+// A payment intent status change committed by this process
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusChange {
+    pub id: Uuid,
+    pub status: String,
+}
+
+// Fed by the payment intent service after each commit that changes a status, for the waits
+// on GET /v1/payment_intents/{id}/wait. In-process only: a change committed by another API
+// instance is seen when the wait times out and re-reads the intent.
+#[derive(Clone)]
+pub struct StatusChanges {
+    sender: broadcast::Sender<StatusChange>,
+    waiters: Arc<AtomicUsize>,
+}
+
+// One wait's subscription; counts towards the waiter limit until dropped
+pub struct Subscription {
+    receiver: broadcast::Receiver<StatusChange>,
+    waiters: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WaitOutcome {
+    Changed(String),
+    // Changes were dropped before this waiter saw them; the caller has to re-read the intent
+    Lagged,
+    TimedOut,
+}
+
+impl Default for StatusChanges {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            waiters: Arc::default(),
+        }
+    }
+}
+
+impl StatusChanges {
+    pub fn publish(&self, id: Uuid, status: impl Into<String>) {
+        // Err only means nobody is waiting
+        self.sender
+            .send(StatusChange {
+                id,
+                status: status.into(),
+            })
+            .ok();
+    }
+
+    // None when `max_waiters` waits are already open
+    pub fn subscribe(&self, max_waiters: usize) -> Option<Subscription> {
+        let claimed = self
+            .waiters
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max_waiters).then_some(n + 1)
+            });
+        claimed.ok().map(|_| Subscription {
+            receiver: self.sender.subscribe(),
+            waiters: self.waiters.clone(),
+        })
+    }
+
+    pub fn waiters(&self) -> usize {
+        self.waiters.load(Ordering::Acquire)
+    }
+}
+
+impl Subscription {
+    // The next status of `id` other than `from`, unless `deadline` passes first
+    pub async fn next_change(&mut self, id: Uuid, from: &str, deadline: Instant) -> WaitOutcome {
+        loop {
+            match tokio::time::timeout_at(deadline, self.receiver.recv()).await {
+                Err(_) => return WaitOutcome::TimedOut,
+                Ok(Ok(change)) if change.id == id && change.status != from => {
+                    return WaitOutcome::Changed(change.status);
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(RecvError::Lagged(_))) => return WaitOutcome::Lagged,
+                // The sender lives as long as the app state
+                Ok(Err(RecvError::Closed)) => return WaitOutcome::TimedOut,
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.waiters.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_a_change_of_its_own_intent() {
+        let changes = StatusChanges::default();
+        let (id, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut sub = changes.subscribe(10).unwrap();
+
+        let publisher = changes.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            publisher.publish(other, "succeeded");
+            publisher.publish(id, "requires_confirmation");
+            publisher.publish(id, "succeeded");
+        });
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(30);
+        let outcome = sub.next_change(id, "requires_confirmation", deadline).await;
+        assert_eq!(outcome, WaitOutcome::Changed("succeeded".to_string()));
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_at_the_deadline() {
+        let changes = StatusChanges::default();
+        let mut sub = changes.subscribe(10).unwrap();
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(30);
+        let outcome = sub
+            .next_change(Uuid::new_v4(), "requires_confirmation", deadline)
+            .await;
+        assert_eq!(outcome, WaitOutcome::TimedOut);
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn subscriptions_are_limited_until_dropped() {
+        let changes = StatusChanges::default();
+        let first = changes.subscribe(2).unwrap();
+        let _second = changes.subscribe(2).unwrap();
+        assert!(changes.subscribe(2).is_none());
+
+        drop(first);
+        assert!(changes.subscribe(2).is_some());
+        assert_eq!(changes.waiters(), 1);
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode};
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::events_outbox::{EventError, insert_event};
+use crate::payment_intents::storage::{
+    NewPaymentIntent, fetch_payment_intent, insert_payment_intent, insert_transition,
+    mark_payment_intent_confirmed,
+};
+use crate::state::AppState;
+
+// Flipped once startup work is done; /readyz reports it
+#[derive(Clone)]
+pub struct Readiness {
+    ready: Arc<AtomicBool>,
+    // Connections the warm-up has primed (mostly for tests and logs)
+    pub warmed_connections: Arc<AtomicUsize>,
+}
+
+impl Readiness {
+    pub fn ready() -> Self {
+        Self {
+            ready: Arc::new(AtomicBool::new(true)),
+            warmed_connections: Arc::default(),
+        }
+    }
+
+    pub fn not_ready() -> Self {
+        Self {
+            ready: Arc::new(AtomicBool::new(false)),
+            warmed_connections: Arc::default(),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+}
+
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    if state.readiness.is_ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "warming up")
+    }
+}
+
+// Runs the hot-path statements once on a connection so they are prepared, then rolls back
+async fn warm_connection(db: &PgPool) -> Result<(), EventError> {
+    let mut tx = db.begin().await?;
+
+    let pi = insert_payment_intent(
+        &mut *tx,
+        NewPaymentIntent {
+            amount: 1,
+            currency: "gbp".to_string(),
+            metadata: Default::default(),
+            natural_key: None,
+            capture_method: "automatic".to_string(),
+            description: None,
+            customer_id: None,
+        },
+    )
+    .await?;
+    fetch_payment_intent(&mut *tx, pi.id).await?;
+    insert_transition(&mut *tx, pi.id, None, pi.status, "warmup").await?;
+    mark_payment_intent_confirmed(&mut *tx, pi.id, None).await?;
+    insert_event(&mut *tx, "warmup", serde_json::json!({})).await?;
+
+    Ok(tx.rollback().await?)
+}
+
+// Opens `connections` connections at once (so each is distinct), warms every one, then marks the
+// app ready. Failures and timeouts are logged and never keep the app unready.
+pub async fn run(state: &AppState, connections: u32, timeout: Duration) {
+    let warm_all = warm_connections(state, connections);
+
+    match tokio::time::timeout(timeout, warm_all).await {
+        Ok(errors) if errors.is_empty() => {}
+        Ok(errors) => {
+            for e in errors {
+                warn!(error = %e, "warm-up failed on a connection");
+            }
+        }
+        Err(_) => warn!(?timeout, "warm-up timed out, continuing"),
+    }
+
+    state.readiness.mark_ready();
+}
+
+async fn warm_connections(state: &AppState, connections: u32) -> Vec<EventError> {
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..connections {
+        let db = state.db.clone();
+        let warmed = state.readiness.warmed_connections.clone();
+        tasks.spawn(async move {
+            warm_connection(&db).await?;
+            warmed.fetch_add(1, Ordering::Relaxed);
+            Ok::<_, EventError>(())
+        });
+    }
+
+    let mut errors = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Err(e)) = result {
+            errors.push(e);
+        }
+    }
+    errors
+}
@@ -1,14 +1,64 @@
-use axum::{Json, extract::State, http::StatusCode};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
 use chrono::{DateTime, Utc};
+use mini_stripe_types::crypto::WEBHOOK_ENDPOINT_SECRET;
+use mini_stripe_types::delivery::{RetryPolicy, RetrySchedule, retry_schedule};
+use mini_stripe_types::error::codes;
+use mini_stripe_types::events::{
+    EventType, PAYMENT_INTENT_SUCCEEDED, WEBHOOK_ENDPOINT_SECRET_REVEALED,
+};
+use mini_stripe_types::payload_template::{self, PayloadTemplate};
+use mini_stripe_types::signature::SignatureScheme;
 use rand::distr::{Alphanumeric, SampleString};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use url::Url;
 use uuid::Uuid;
 
+use crate::audit_log::insert_audit_entry;
+use crate::client_certificates::ClientCertificateInfo;
+use crate::config::parse_duration;
+use crate::error::ApiError;
+use crate::events_outbox::insert_event;
+use crate::sandbox::ECHO_URL;
 use crate::state::AppState;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct CreateWebhookEndpointRequest {
     pub url: String,
+    // `v1` (the default) or `v2`; see mini_stripe_types::signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_scheme: Option<String>,
+    // Reshapes payment_intent.* webhook bodies; see mini_stripe_types::payload_template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_template: Option<Value>,
+}
+
+// PATCH /v1/webhook_endpoints/{id}. Only the payload template can change; null removes it.
+#[derive(Deserialize)]
+pub struct UpdateWebhookEndpointRequest {
+    pub payload_template: Value,
+}
+
+#[derive(Deserialize)]
+pub struct PreviewRequest {
+    // Defaults to payment_intent.succeeded
+    pub event_type: Option<String>,
+    // A draft to try; the endpoint's stored template when omitted
+    pub payload_template: Option<Value>,
+}
+
+#[derive(Serialize)]
+pub struct PreviewResponse {
+    pub event_type: String,
+    // The sample event as the standard envelope
+    pub event: Value,
+    // What the worker would post for it (the envelope itself when there is no template)
+    pub body: Value,
 }
 
 #[derive(Serialize)]
@@ -16,6 +66,8 @@ pub struct WebhookEndpointCreatedResponse {
     pub id: Uuid,
     pub url: String,
     pub secret: String, // returns only on creation
+    pub signature_scheme: String,
+    pub payload_template: Option<Value>,
     pub is_enabled: bool,
     pub created_at: DateTime<Utc>,
 }
@@ -24,45 +76,194 @@ pub struct WebhookEndpointCreatedResponse {
 pub struct WebhookEndpointListItem {
     pub id: Uuid,
     pub url: String,
+    pub signature_scheme: String,
+    pub payload_template: Option<Value>,
     pub is_enabled: bool,
     pub created_at: DateTime<Utc>,
+    // Set by PUT /v1/webhook_endpoints/sync, which manages only endpoints that have one
+    pub external_id: Option<String>,
+    pub description: Option<String>,
+    // Event types delivered to this endpoint; ["*"] is all of them
+    pub enabled_events: Vec<String>,
+    // Delivery circuit breaker: closed, open (receiver down, no attempts) or half_open (probing)
+    pub circuit_state: String,
+    // Presented to the receiver for mutual TLS (see client_certificates.rs); null when none
+    pub client_certificate: Option<ClientCertificateInfo>,
+}
+
+#[derive(Serialize)]
+pub struct WebhookEndpointSecretResponse {
+    pub id: Uuid,
+    pub secret: String,
+}
+
+#[derive(Serialize)]
+pub struct WebhookDeliveryItem {
+    pub id: Uuid,
+    pub event_id: Uuid,
+    // 1 for the original delivery, +1 for each redelivery
+    pub generation: i32,
+    pub status: String,
+    pub attempt_count: i32,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub response_status: Option<i32>,
+    // Bounded, sanitized start of the receiver's response body (see worker::deliver)
+    pub response_snippet: Option<String>,
+    pub response_content_length: Option<i64>,
+    pub response_truncated: bool,
+    // attempts_remaining, remaining_schedule and will_not_retry, worked out from the worker's
+    // backoff (mini_stripe_types::delivery)
+    #[serde(flatten)]
+    pub retry: RetrySchedule,
+}
+
+const DELIVERY_HISTORY_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct WebhookEndpointStatsQuery {
+    // e.g. `7d` or `30d`
+    pub window: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WebhookEndpointStats {
+    pub window: String,
+    // Deliveries created in the window
+    pub attempted: i64,
+    pub succeeded: i64,
+    pub failed: i64,
+    // succeeded / (succeeded + failed); null until something has finished
+    pub success_rate: Option<f64>,
+    pub p50_latency_ms: Option<f64>,
+    pub p95_latency_ms: Option<f64>,
+    // Attempted deliveries whose latest attempt failed since the last success (any window)
+    pub consecutive_failures: i64,
+    pub last_successful_delivery_at: Option<DateTime<Utc>>,
+    // The window reaches further back than delivery rows are kept
+    pub partial_data: bool,
+}
+
+const DEFAULT_STATS_WINDOW: &str = "30d";
+
+// Break-glass reveals allowed per endpoint in a rolling 24h window
+const MAX_SECRET_REVEALS_PER_DAY: i64 = 3;
+
+pub const URL_UNIQUE_CONSTRAINT: &str = "webhook_endpoints_url_unique";
+
+// One canonical spelling per receiver, so duplicates are caught however the URL was typed:
+// lowercase scheme/host, no default port, dot segments resolved, no trailing slash or fragment.
+// The query string is kept as-is.
+pub fn normalize_url(raw: &str, sandbox_mode: bool) -> Result<String, String> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return Err("url is required".to_string());
+    }
+
+    // internal:// targets the in-process echo receiver, which only exists in sandbox mode
+    if raw.starts_with("internal://") {
+        if raw != ECHO_URL || !sandbox_mode {
+            return Err(format!(
+                "only {ECHO_URL} is supported, and only in sandbox mode"
+            ));
+        }
+        return Ok(raw.to_string());
+    }
+
+    let mut url = Url::parse(raw).map_err(|e| format!("url is invalid: {e}"))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err("url must be an absolute http or https URL".to_string());
+    }
+
+    url.set_fragment(None);
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let trimmed = url.path().trim_end_matches('/').to_string();
+        url.set_path(&trimmed);
+    }
+
+    Ok(url.into())
 }
 
-fn generate_secret() -> String {
+// Refused with the template location and problem, e.g. a path no payment_intent event has
+fn validate_payload_template(spec: &Value) -> Result<(), ApiError> {
+    PayloadTemplate::parse(spec)
+        .map(|_| ())
+        .map_err(|e| ApiError::bad_request(codes::PAYLOAD_TEMPLATE_INVALID, e.to_string()))
+}
+
+pub fn generate_secret() -> String {
     Alphanumeric.sample_string(&mut rand::rng(), 32)
 }
 
+// The stored secret is encrypted when keys are configured (see mini_stripe_types::crypto)
+pub fn decrypt_secret(state: &AppState, stored: &str) -> Result<String, ApiError> {
+    WEBHOOK_ENDPOINT_SECRET
+        .decrypt(&state.config.encryption_keys, stored)
+        .map_err(|e| {
+            ApiError::internal(format!(
+                "webhook_endpoint secret: {e}; is its key in ENCRYPTION_KEYS?"
+            ))
+        })
+}
+
 pub async fn create_webhook_endpoint(
     State(state): State<AppState>,
     Json(req): Json<CreateWebhookEndpointRequest>,
-) -> Result<(StatusCode, Json<WebhookEndpointCreatedResponse>), (StatusCode, String)> {
-    if req.url.trim().is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "url is required".to_string()));
+) -> Result<(StatusCode, Json<WebhookEndpointCreatedResponse>), ApiError> {
+    let url = normalize_url(&req.url, state.config.sandbox_mode)
+        .map_err(|msg| ApiError::bad_request("url_invalid", msg))?;
+    let scheme = match req.signature_scheme.as_deref() {
+        None => SignatureScheme::default(),
+        Some(s) => SignatureScheme::parse(s).ok_or_else(|| {
+            ApiError::bad_request(
+                codes::PARAMETER_INVALID,
+                "signature_scheme must be one of: v1, v2",
+            )
+        })?,
+    };
+
+    if let Some(spec) = &req.payload_template {
+        validate_payload_template(spec)?;
     }
 
     let id = Uuid::new_v4();
     let secret = generate_secret();
+    let stored_secret = WEBHOOK_ENDPOINT_SECRET.encrypt(&state.config.encryption_keys, &secret);
 
     let row = sqlx::query!(
         r#"
-        INSERT INTO webhook_endpoints (id, url, secret)
-        VALUES ($1, $2, $3)
-        RETURNING id, url, secret, is_enabled, created_at
+        INSERT INTO webhook_endpoints (id, url, secret, signature_scheme, payload_template)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, url, signature_scheme, payload_template, is_enabled, created_at
         "#,
         id,
-        req.url,
-        secret
+        url,
+        stored_secret,
+        scheme.as_str(),
+        req.payload_template
     )
     .fetch_one(&state.db)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    .map_err(|e| {
+        let constraint = e.as_database_error().and_then(|db_err| db_err.constraint());
+        if constraint == Some(URL_UNIQUE_CONSTRAINT) {
+            return ApiError::conflict(
+                "webhook_endpoint_url_taken",
+                format!("a webhook_endpoint for {url} already exists"),
+            );
+        }
+        e.into()
+    })?;
 
     Ok((
         StatusCode::CREATED,
         Json(WebhookEndpointCreatedResponse {
             id: row.id,
             url: row.url,
-            secret: row.secret,
+            secret,
+            signature_scheme: row.signature_scheme,
+            payload_template: row.payload_template,
             is_enabled: row.is_enabled,
             created_at: row.created_at,
         }),
@@ -71,27 +272,443 @@ pub async fn create_webhook_endpoint(
 
 pub async fn list_webhook_endpoints(
     State(state): State<AppState>,
-) -> Result<Json<Vec<WebhookEndpointListItem>>, (StatusCode, String)> {
+) -> Result<Json<Vec<WebhookEndpointListItem>>, ApiError> {
     let rows = sqlx::query!(
         r#"
-        SELECT id, url, is_enabled, created_at
+        SELECT id, url, external_id, description, enabled_events, signature_scheme,
+               payload_template, is_enabled, created_at,
+               CASE
+                 WHEN circuit_open_until IS NULL THEN 'closed'
+                 WHEN circuit_open_until > now() THEN 'open'
+                 ELSE 'half_open'
+               END AS "circuit_state!",
+               client_certificate_fingerprint, client_certificate_subject,
+               client_certificate_expires_at
         FROM webhook_endpoints
         ORDER BY created_at DESC
         "#
     )
     .fetch_all(&state.db)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    .await?;
 
     let items = rows
         .into_iter()
         .map(|r| WebhookEndpointListItem {
             id: r.id,
             url: r.url,
+            signature_scheme: r.signature_scheme,
+            payload_template: r.payload_template,
             is_enabled: r.is_enabled,
             created_at: r.created_at,
+            external_id: r.external_id,
+            description: r.description,
+            enabled_events: r.enabled_events,
+            circuit_state: r.circuit_state,
+            client_certificate: ClientCertificateInfo::from_columns(
+                r.client_certificate_fingerprint,
+                r.client_certificate_subject,
+                r.client_certificate_expires_at,
+            ),
         })
         .collect();
 
     Ok(Json(items))
 }
+
+pub async fn fetch_webhook_endpoint(
+    db: &PgPool,
+    id: Uuid,
+) -> Result<Option<WebhookEndpointListItem>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, url, external_id, description, enabled_events, signature_scheme,
+               payload_template, is_enabled, created_at,
+               CASE
+                 WHEN circuit_open_until IS NULL THEN 'closed'
+                 WHEN circuit_open_until > now() THEN 'open'
+                 ELSE 'half_open'
+               END AS "circuit_state!",
+               client_certificate_fingerprint, client_certificate_subject,
+               client_certificate_expires_at
+        FROM webhook_endpoints
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|row| WebhookEndpointListItem {
+        id: row.id,
+        url: row.url,
+        signature_scheme: row.signature_scheme,
+        payload_template: row.payload_template,
+        is_enabled: row.is_enabled,
+        created_at: row.created_at,
+        external_id: row.external_id,
+        description: row.description,
+        enabled_events: row.enabled_events,
+        circuit_state: row.circuit_state,
+        client_certificate: ClientCertificateInfo::from_columns(
+            row.client_certificate_fingerprint,
+            row.client_certificate_subject,
+            row.client_certificate_expires_at,
+        ),
+    }))
+}
+
+pub async fn get_webhook_endpoint(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<WebhookEndpointListItem>, ApiError> {
+    let endpoint = fetch_webhook_endpoint(&state.db, id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("webhook_endpoint not found"))?;
+    Ok(Json(endpoint))
+}
+
+pub async fn update_webhook_endpoint(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateWebhookEndpointRequest>,
+) -> Result<Json<WebhookEndpointListItem>, ApiError> {
+    let template = match req.payload_template {
+        Value::Null => None,
+        spec => {
+            validate_payload_template(&spec)?;
+            Some(spec)
+        }
+    };
+
+    // Deliveries already in flight keep the body they were sent with; retries use the new one
+    let row = sqlx::query!(
+        r#"
+        UPDATE webhook_endpoints
+        SET payload_template = $2
+        WHERE id = $1
+        RETURNING id, url, external_id, description, enabled_events, signature_scheme,
+                  payload_template, is_enabled, created_at,
+                  CASE
+                    WHEN circuit_open_until IS NULL THEN 'closed'
+                    WHEN circuit_open_until > now() THEN 'open'
+                    ELSE 'half_open'
+                  END AS "circuit_state!",
+                  client_certificate_fingerprint, client_certificate_subject,
+                  client_certificate_expires_at
+        "#,
+        id,
+        template
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("webhook_endpoint not found"))?;
+
+    Ok(Json(WebhookEndpointListItem {
+        id: row.id,
+        url: row.url,
+        signature_scheme: row.signature_scheme,
+        payload_template: row.payload_template,
+        is_enabled: row.is_enabled,
+        created_at: row.created_at,
+        external_id: row.external_id,
+        description: row.description,
+        enabled_events: row.enabled_events,
+        circuit_state: row.circuit_state,
+        client_certificate: ClientCertificateInfo::from_columns(
+            row.client_certificate_fingerprint,
+            row.client_certificate_subject,
+            row.client_certificate_expires_at,
+        ),
+    }))
+}
+
+// Renders a sample event through a template without storing or sending anything, so a
+// template can be tried out before it is saved
+pub async fn preview_webhook_endpoint_payload(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<PreviewRequest>,
+) -> Result<Json<PreviewResponse>, ApiError> {
+    let stored = sqlx::query_scalar!(
+        r#"
+        SELECT payload_template
+        FROM webhook_endpoints
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("webhook_endpoint not found"))?;
+
+    let event_type = req
+        .event_type
+        .unwrap_or_else(|| PAYMENT_INTENT_SUCCEEDED.to_string());
+    let event = EventType::parse(&event_type)
+        .and_then(payload_template::sample_event)
+        .ok_or_else(|| {
+            ApiError::bad_request(
+                codes::PARAMETER_INVALID,
+                "event_type must be a payment_intent event type",
+            )
+        })?;
+
+    let body = match req.payload_template.or(stored) {
+        Some(spec) => PayloadTemplate::parse(&spec)
+            .and_then(|template| template.render(&event))
+            .map_err(|e| ApiError::bad_request(codes::PAYLOAD_TEMPLATE_INVALID, e.to_string()))?,
+        None => event.clone(),
+    };
+
+    Ok(Json(PreviewResponse {
+        event_type,
+        event,
+        body,
+    }))
+}
+
+pub async fn list_webhook_endpoint_deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<WebhookDeliveryItem>>, ApiError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, event_id, generation, status, attempt_count, max_attempts, last_attempt_at,
+               next_attempt_at, last_error,
+               response_status, response_snippet, response_content_length, response_truncated,
+               now() AS "now!"
+        FROM webhook_deliveries
+        WHERE webhook_endpoint_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+        id,
+        DELIVERY_HISTORY_LIMIT
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    // On the database clock, which is what the worker schedules against
+    let items = rows
+        .into_iter()
+        .map(|row| WebhookDeliveryItem {
+            retry: retry_schedule(
+                RetryPolicy {
+                    delivery_id: row.id,
+                    max_attempts: row.max_attempts,
+                },
+                &row.status,
+                row.attempt_count,
+                row.next_attempt_at,
+                row.now,
+            ),
+            id: row.id,
+            event_id: row.event_id,
+            generation: row.generation,
+            status: row.status,
+            attempt_count: row.attempt_count,
+            last_attempt_at: row.last_attempt_at,
+            next_attempt_at: row.next_attempt_at,
+            last_error: row.last_error,
+            response_status: row.response_status,
+            response_snippet: row.response_snippet,
+            response_content_length: row.response_content_length,
+            response_truncated: row.response_truncated,
+        })
+        .collect();
+
+    Ok(Json(items))
+}
+
+pub async fn get_webhook_endpoint_stats(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<WebhookEndpointStatsQuery>,
+) -> Result<Json<WebhookEndpointStats>, ApiError> {
+    let window = query
+        .window
+        .unwrap_or_else(|| DEFAULT_STATS_WINDOW.to_string());
+    let window_len = parse_duration(&window).map_err(|_| {
+        ApiError::bad_request(
+            "parameter_invalid",
+            "window must be a duration like 7d or 30d",
+        )
+    })?;
+
+    let row = sqlx::query!(
+        r#"
+        WITH last_success AS (
+          SELECT max(last_attempt_at) AS at
+          FROM webhook_deliveries
+          WHERE webhook_endpoint_id = $1 AND status = 'succeeded'
+        ),
+        windowed AS (
+          SELECT status, attempt_count, response_time_ms
+          FROM webhook_deliveries
+          WHERE webhook_endpoint_id = $1
+            AND created_at >= now() - make_interval(secs => $2)
+        )
+        SELECT
+          e.id,
+          (SELECT COUNT(*) FROM windowed WHERE attempt_count > 0) AS "attempted!",
+          (SELECT COUNT(*) FROM windowed WHERE status = 'succeeded') AS "succeeded!",
+          (SELECT COUNT(*) FROM windowed WHERE status = 'failed') AS "failed!",
+          (SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY response_time_ms) FROM windowed)
+            AS p50_latency_ms,
+          (SELECT percentile_cont(0.95) WITHIN GROUP (ORDER BY response_time_ms) FROM windowed)
+            AS p95_latency_ms,
+          (
+            SELECT COUNT(*)
+            FROM webhook_deliveries d
+            WHERE d.webhook_endpoint_id = $1
+              AND d.status <> 'succeeded'
+              AND d.last_error IS NOT NULL
+              AND d.last_attempt_at > COALESCE((SELECT at FROM last_success), '-infinity')
+          ) AS "consecutive_failures!",
+          (SELECT at FROM last_success) AS last_successful_delivery_at
+        FROM webhook_endpoints e
+        WHERE e.id = $1
+        "#,
+        id,
+        window_len.as_secs_f64()
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::not_found("webhook_endpoint not found"))?;
+
+    let finished = row.succeeded + row.failed;
+
+    Ok(Json(WebhookEndpointStats {
+        window,
+        attempted: row.attempted,
+        succeeded: row.succeeded,
+        failed: row.failed,
+        success_rate: (finished > 0).then(|| row.succeeded as f64 / finished as f64),
+        p50_latency_ms: row.p50_latency_ms,
+        p95_latency_ms: row.p95_latency_ms,
+        consecutive_failures: row.consecutive_failures,
+        last_successful_delivery_at: row.last_successful_delivery_at,
+        partial_data: state
+            .config
+            .webhook_delivery_retention
+            .is_some_and(|retention| window_len > retention),
+    }))
+}
+
+pub async fn reveal_webhook_endpoint_secret(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<WebhookEndpointSecretResponse>, ApiError> {
+    if !state.config.webhook_secret_reveal_enabled {
+        return Err(ApiError::not_found("not found"));
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    // Lock the endpoint so concurrent reveals can't both slip under the daily cap
+    let endpoint = sqlx::query!(
+        r#"
+        SELECT id, url, secret
+        FROM webhook_endpoints
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::not_found("webhook_endpoint not found"))?;
+
+    let reveals_today: i64 = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*)::bigint AS "count!"
+        FROM audit_log
+        WHERE resource_id = $1
+          AND action = 'webhook_endpoint.secret_revealed'
+          AND created_at > now() - interval '1 day'
+        "#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if reveals_today >= MAX_SECRET_REVEALS_PER_DAY {
+        tx.rollback().await.ok();
+        return Err(ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "secret reveal limit reached for this webhook_endpoint, try again tomorrow",
+        ));
+    }
+
+    insert_audit_entry(
+        &mut *tx,
+        "webhook_endpoint.secret_revealed",
+        id,
+        serde_json::json!({}),
+    )
+    .await?;
+
+    // The event never carries the secret itself
+    let payload = serde_json::json!({
+        "webhook_endpoint": {
+            "id": endpoint.id,
+            "url": endpoint.url
+        }
+    });
+
+    insert_event(&mut *tx, WEBHOOK_ENDPOINT_SECRET_REVEALED, payload).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(WebhookEndpointSecretResponse {
+        id: endpoint.id,
+        secret: decrypt_secret(&state, &endpoint.secret)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_url_collapses_equivalent_spellings() {
+        let canonical = "https://example.com/hook";
+        for raw in [
+            "https://example.com/hook",
+            "https://example.com/hook/",
+            "HTTPS://Example.COM/hook",
+            "https://example.com:443/hook",
+            "https://example.com/a/../hook",
+            "https://example.com/./hook#frag",
+            "  https://example.com/hook  ",
+        ] {
+            assert_eq!(normalize_url(raw, false).unwrap(), canonical, "{raw}");
+        }
+
+        assert_eq!(
+            normalize_url("http://example.com:80/hook?b=2&a=1", false).unwrap(),
+            "http://example.com/hook?b=2&a=1"
+        );
+        assert_eq!(
+            normalize_url("http://example.com:8080/Hook", false).unwrap(),
+            "http://example.com:8080/Hook"
+        );
+        assert_eq!(
+            normalize_url("https://example.com", false).unwrap(),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn normalize_url_rejects_non_http_targets() {
+        assert!(normalize_url("", false).is_err());
+        assert!(normalize_url("not a url", false).is_err());
+        assert!(normalize_url("ftp://example.com/hook", false).is_err());
+        assert!(normalize_url("internal://echo", false).is_err());
+        assert_eq!(
+            normalize_url("internal://echo", true).unwrap(),
+            "internal://echo"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::HashSet;
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use mini_stripe_types::crypto::WEBHOOK_ENDPOINT_SECRET;
+use mini_stripe_types::error::{ErrorDetail, codes};
+use mini_stripe_types::events::EventType;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::payment_intents::{IDEMPOTENT_REPLAYED_HEADER, idempotency_key};
+use crate::services::payment_intents::validate_idempotency_key;
+use crate::state::AppState;
+use crate::webhook_endpoints::{URL_UNIQUE_CONSTRAINT, generate_secret, normalize_url};
+
+const IDEMPOTENCY_ENDPOINT: &str = "PUT /v1/webhook_endpoints/sync";
+// Largest declaration one call accepts
+const MAX_DECLARED_ENDPOINTS: usize = 500;
+const MAX_EXTERNAL_ID_LEN: usize = 255;
+// enabled_events entry meaning every event type
+const ALL_EVENTS: &str = "*";
+
+// PUT /v1/webhook_endpoints/sync: every endpoint the caller manages, as it should be
+#[derive(Serialize, Deserialize)]
+pub struct SyncRequest {
+    pub endpoints: Vec<DeclaredEndpoint>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeclaredEndpoint {
+    // The caller's stable name for the endpoint; the URL can change under it
+    pub external_id: String,
+    pub url: String,
+    // Omitted means ["*"]
+    #[serde(default)]
+    pub enabled_events: Option<Vec<String>>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncReport {
+    pub created: Vec<CreatedEndpoint>,
+    pub updated: Vec<UpdatedEndpoint>,
+    // Managed endpoints missing from the declaration; kept, with their history, but not delivered to
+    pub disabled: Vec<SyncedEndpoint>,
+    pub unchanged: Vec<SyncedEndpoint>,
+    // Declared endpoints that couldn't be converged; the rest of the sync still went ahead
+    pub failed: Vec<FailedEndpoint>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedEndpoint {
+    pub id: Uuid,
+    pub external_id: String,
+    pub url: String,
+    // Only in the response to the call that created the endpoint, never in a replay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdatedEndpoint {
+    pub id: Uuid,
+    pub external_id: String,
+    // Which of url, description, enabled_events and is_enabled changed
+    pub changed: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncedEndpoint {
+    pub id: Uuid,
+    pub external_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailedEndpoint {
+    pub external_id: String,
+    pub error: ErrorDetail,
+}
+
+// A declared endpoint with its url normalized and enabled_events sorted, so it compares equal
+// to what is stored however it was written
+struct Desired {
+    external_id: String,
+    url: String,
+    enabled_events: Vec<String>,
+    description: Option<String>,
+}
+
+enum Outcome {
+    Created(CreatedEndpoint),
+    Updated(UpdatedEndpoint),
+    Unchanged(SyncedEndpoint),
+}
+
+// Refuses declarations that can't be read as one set of endpoints. Problems with a single entry
+// are reported against it instead (see desired).
+fn validate_declaration(req: &SyncRequest) -> Result<(), ApiError> {
+    if req.endpoints.len() > MAX_DECLARED_ENDPOINTS {
+        return Err(ApiError::bad_request(
+            codes::PARAMETER_INVALID,
+            format!("at most {MAX_DECLARED_ENDPOINTS} endpoints can be declared"),
+        ));
+    }
+    let mut seen = HashSet::new();
+    for endpoint in &req.endpoints {
+        let id = &endpoint.external_id;
+        if id.trim().is_empty() || id.len() > MAX_EXTERNAL_ID_LEN {
+            return Err(ApiError::bad_request(
+                codes::PARAMETER_INVALID,
+                format!("external_id must be 1 to {MAX_EXTERNAL_ID_LEN} bytes"),
+            ));
+        }
+        if !seen.insert(id) {
+            return Err(ApiError::bad_request(
+                codes::PARAMETER_INVALID,
+                format!("external_id {id} is declared more than once"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn desired(declared: &DeclaredEndpoint, sandbox_mode: bool) -> Result<Desired, ApiError> {
+    let url = normalize_url(&declared.url, sandbox_mode)
+        .map_err(|msg| ApiError::bad_request("url_invalid", msg))?;
+
+    let mut events = declared
+        .enabled_events
+        .clone()
+        .unwrap_or_else(|| vec![ALL_EVENTS.to_string()]);
+    events.sort();
+    events.dedup();
+    if events.is_empty() {
+        return Err(ApiError::bad_request(
+            codes::PARAMETER_INVALID,
+            "enabled_events must list at least one event type, or \"*\"",
+        ));
+    }
+    if events.len() > 1 && events.iter().any(|e| e == ALL_EVENTS) {
+        return Err(ApiError::bad_request(
+            codes::PARAMETER_INVALID,
+            "enabled_events can't combine \"*\" with other event types",
+        ));
+    }
+    if let Some(unknown) = events
+        .iter()
+        .find(|e| *e != ALL_EVENTS && EventType::parse(e).is_none())
+    {
+        return Err(ApiError::bad_request(
+            codes::PARAMETER_INVALID,
+            format!("enabled_events has unknown event type {unknown}"),
+        ));
+    }
+
+    Ok(Desired {
+        external_id: declared.external_id.clone(),
+        url,
+        enabled_events: events,
+        description: declared.description.clone(),
+    })
+}
+
+fn url_taken(e: sqlx::Error, url: &str) -> ApiError {
+    let constraint = e.as_database_error().and_then(|db_err| db_err.constraint());
+    if constraint == Some(URL_UNIQUE_CONSTRAINT) {
+        return ApiError::conflict(
+            "webhook_endpoint_url_taken",
+            format!("a webhook_endpoint for {url} already exists"),
+        );
+    }
+    e.into()
+}
+
+// Converges one endpoint in its own transaction, holding its row while comparing
+async fn sync_one(state: &AppState, desired: Desired) -> Result<Outcome, ApiError> {
+    let mut tx = state.db.begin().await?;
+    let current = sqlx::query!(
+        r#"
+        SELECT id, url, description, enabled_events, is_enabled
+        FROM webhook_endpoints
+        WHERE external_id = $1
+        FOR UPDATE
+        "#,
+        desired.external_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(current) = current else {
+        let id = Uuid::new_v4();
+        let secret = generate_secret();
+        let stored_secret = WEBHOOK_ENDPOINT_SECRET.encrypt(&state.config.encryption_keys, &secret);
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (
+              id, url, secret, external_id, description, enabled_events
+            )
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            id,
+            desired.url,
+            stored_secret,
+            desired.external_id,
+            desired.description,
+            &desired.enabled_events
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| url_taken(e, &desired.url))?;
+        tx.commit().await?;
+        return Ok(Outcome::Created(CreatedEndpoint {
+            id,
+            external_id: desired.external_id,
+            url: desired.url,
+            secret: Some(secret),
+        }));
+    };
+
+    let mut changed = Vec::new();
+    if current.url != desired.url {
+        changed.push("url".to_string());
+    }
+    if current.description != desired.description {
+        changed.push("description".to_string());
+    }
+    let mut stored_events = current.enabled_events;
+    stored_events.sort();
+    if stored_events != desired.enabled_events {
+        changed.push("enabled_events".to_string());
+    }
+    if !current.is_enabled {
+        changed.push("is_enabled".to_string());
+    }
+    if changed.is_empty() {
+        return Ok(Outcome::Unchanged(SyncedEndpoint {
+            id: current.id,
+            external_id: desired.external_id,
+        }));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE webhook_endpoints
+        SET url = $2, description = $3, enabled_events = $4, is_enabled = true
+        WHERE id = $1
+        "#,
+        current.id,
+        desired.url,
+        desired.description,
+        &desired.enabled_events
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| url_taken(e, &desired.url))?;
+    tx.commit().await?;
+    Ok(Outcome::Updated(UpdatedEndpoint {
+        id: current.id,
+        external_id: desired.external_id,
+        changed,
+    }))
+}
+
+// Disables one managed endpoint that is no longer declared; false if it was already disabled
+// or has been removed from sync meanwhile
+async fn disable_one(state: &AppState, id: Uuid) -> Result<bool, sqlx::Error> {
+    let disabled = sqlx::query!(
+        r#"
+        UPDATE webhook_endpoints
+        SET is_enabled = false
+        WHERE id = $1 AND is_enabled AND external_id IS NOT NULL
+        "#,
+        id
+    )
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+    Ok(disabled == 1)
+}
+
+// Converges the endpoints that have an external_id to the declaration: creates the missing ones,
+// updates the ones that drifted and disables the ones that aren't declared. Endpoints created
+// without an external_id are never touched. Each endpoint is changed in its own transaction, and
+// one that fails is reported without stopping the others.
+//
+// Syncs run one at a time, under an advisory lock held by a transaction that also reserves the
+// Idempotency-Key. The report is stored with the key when the sync finishes, so a retry with the
+// same key and declaration gets it back (without secrets). A sync that dies halfway stores
+// nothing and leaves the key free; running it again converges from wherever it got to.
+pub async fn sync_webhook_endpoints(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<SyncRequest>,
+) -> Result<Response, ApiError> {
+    let key = idempotency_key(&headers)?.map(|key| key.0);
+    if let Some(key) = &key {
+        validate_idempotency_key(key)
+            .map_err(|msg| ApiError::bad_request(codes::PARAMETER_INVALID, msg))?;
+    }
+    validate_declaration(&req)?;
+
+    let mut guard = state.db.begin().await?;
+    sqlx::query!("SELECT pg_advisory_xact_lock(hashtext('webhook_endpoints_sync'))")
+        .execute(&mut *guard)
+        .await?;
+
+    let request_hash = hex::encode(Sha256::digest(serde_json::to_vec(&req)?));
+    if let Some(key) = &key {
+        let reserved = sqlx::query_scalar!(
+            r#"
+            INSERT INTO idempotency_keys (key, endpoint, request_hash, response_body, expires_at)
+            VALUES ($1, $2, $3, '{}'::jsonb, now() + make_interval(secs => $4))
+            ON CONFLICT (key, endpoint) DO NOTHING
+            RETURNING key
+            "#,
+            key,
+            IDEMPOTENCY_ENDPOINT,
+            request_hash,
+            state
+                .config
+                .idempotency_key_ttl
+                .map(|ttl| ttl.as_secs_f64())
+        )
+        .fetch_optional(&mut *guard)
+        .await?;
+
+        if reserved.is_none() {
+            // Reserved keys are only ever committed together with their report
+            let row = sqlx::query!(
+                r#"
+                UPDATE idempotency_keys
+                SET replay_count = replay_count + (request_hash = $3)::int,
+                    conflict_count = conflict_count + (request_hash <> $3)::int
+                WHERE key = $1 AND endpoint = $2
+                RETURNING request_hash, response_body
+                "#,
+                key,
+                IDEMPOTENCY_ENDPOINT,
+                request_hash
+            )
+            .fetch_one(&mut *guard)
+            .await?;
+            guard.commit().await?;
+            if row.request_hash != request_hash {
+                return Err(ApiError::conflict(
+                    codes::IDEMPOTENCY_KEY_REUSED,
+                    "Idempotency-Key was already used for a different sync",
+                ));
+            }
+            let report: SyncReport = serde_json::from_value(row.response_body)?;
+            let mut response = Json(report).into_response();
+            response
+                .headers_mut()
+                .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+            return Ok(response);
+        }
+    }
+
+    let mut report = SyncReport::default();
+    for declared in &req.endpoints {
+        let outcome = match desired(declared, state.config.sandbox_mode) {
+            Ok(desired) => sync_one(&state, desired).await,
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(Outcome::Created(created)) => report.created.push(created),
+            Ok(Outcome::Updated(updated)) => report.updated.push(updated),
+            Ok(Outcome::Unchanged(unchanged)) => report.unchanged.push(unchanged),
+            Err(e) => report.failed.push(FailedEndpoint {
+                external_id: declared.external_id.clone(),
+                error: e.into(),
+            }),
+        }
+    }
+
+    // Entries that failed are still declared, so they are never disabled for it
+    let declared: Vec<String> = req
+        .endpoints
+        .iter()
+        .map(|e| e.external_id.clone())
+        .collect();
+    let absent = sqlx::query!(
+        r#"
+        SELECT id, external_id AS "external_id!"
+        FROM webhook_endpoints
+        WHERE external_id IS NOT NULL AND is_enabled AND NOT (external_id = ANY($1))
+        ORDER BY external_id
+        "#,
+        &declared
+    )
+    .fetch_all(&state.db)
+    .await?;
+    for endpoint in absent {
+        match disable_one(&state, endpoint.id).await {
+            Ok(true) => report.disabled.push(SyncedEndpoint {
+                id: endpoint.id,
+                external_id: endpoint.external_id,
+            }),
+            Ok(false) => {}
+            Err(e) => report.failed.push(FailedEndpoint {
+                external_id: endpoint.external_id,
+                error: ApiError::from(e).into(),
+            }),
+        }
+    }
+
+    if let Some(key) = &key {
+        // Secrets are shown once and never stored
+        let mut stored = serde_json::to_value(&report)?;
+        for created in stored["created"].as_array_mut().into_iter().flatten() {
+            if let Some(created) = created.as_object_mut() {
+                created.remove("secret");
+            }
+        }
+        sqlx::query!(
+            "UPDATE idempotency_keys SET response_body = $3 WHERE key = $1 AND endpoint = $2",
+            key,
+            IDEMPOTENCY_ENDPOINT,
+            stored
+        )
+        .execute(&mut *guard)
+        .await?;
+    }
+    guard.commit().await?;
+
+    Ok(Json(report).into_response())
+}
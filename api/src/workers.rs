@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use axum::{Json, extract::State};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::state::AppState;
+
+// A worker counts as stale once its heartbeat is older than this (config.worker_stale_after)
+const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(60);
+
+// A row of worker_heartbeats, which each webhook worker process keeps up to date
+#[derive(Serialize)]
+pub struct WorkerItem {
+    pub instance_id: Uuid,
+    pub hostname: String,
+    pub pid: i32,
+    pub started_at: DateTime<Utc>,
+    pub last_heartbeat_at: DateTime<Utc>,
+    // No heartbeat for longer than the stale threshold: the worker died or its loop is stuck
+    pub stale: bool,
+    pub deliveries_claimed: i64,
+    pub deliveries_succeeded: i64,
+    pub deliveries_failed: i64,
+    pub claim_contention: i64,
+    pub last_loop_ms: i64,
+    pub max_loop_ms: i64,
+}
+
+fn stale_after(state: &AppState) -> f64 {
+    state
+        .config
+        .worker_stale_after
+        .unwrap_or(DEFAULT_STALE_AFTER)
+        .as_secs_f64()
+}
+
+// Workers that reported recently come first
+pub async fn fetch_workers(state: &AppState) -> Result<Vec<WorkerItem>, sqlx::Error> {
+    sqlx::query_as!(
+        WorkerItem,
+        r#"
+        SELECT instance_id, hostname, pid, started_at, last_heartbeat_at,
+               last_heartbeat_at < now() - make_interval(secs => $1) AS "stale!",
+               deliveries_claimed, deliveries_succeeded, deliveries_failed, claim_contention,
+               last_loop_ms, max_loop_ms
+        FROM worker_heartbeats
+        ORDER BY last_heartbeat_at DESC, instance_id
+        "#,
+        stale_after(state)
+    )
+    .fetch_all(&state.db)
+    .await
+}
+
+pub async fn list_workers(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<WorkerItem>>, ApiError> {
+    Ok(Json(fetch_workers(&state).await?))
+}
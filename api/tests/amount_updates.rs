@@ -0,0 +1,266 @@
+mod common;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{Router, http::StatusCode};
+use common::send;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+
+async fn create(app: &Router, amount: i64) -> String {
+    let (status, pi) = send(
+        app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        json!({ "amount": amount, "currency": "gbp" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    pi["id"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn amount_changes_only_before_confirmation(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let id = create(&app, 1000).await;
+    let uri = routes::payment_intent(&id);
+
+    let (status, err) = send(&app, "PATCH", &uri, json!({ "amount": 0 })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+
+    let (status, pi) = send(&app, "PATCH", &uri, json!({ "amount": 2500 })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(pi["amount"], 2500);
+    assert_eq!(pi["status"], "requires_confirmation");
+
+    let (status, pi) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&id),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(pi["amount"], 2500);
+
+    let (status, err) = send(&app, "PATCH", &uri, json!({ "amount": 3000 })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "payment_intent_unexpected_state");
+    assert_eq!(
+        err["error"]["message"],
+        "cannot change the amount or currency of payment_intent in status 'succeeded'"
+    );
+
+    let missing = routes::payment_intent(uuid::Uuid::new_v4());
+    let (status, _) = send(&app, "PATCH", &missing, json!({ "amount": 3000 })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+// Whichever of the two wins, the confirm response, the stored row and the succeeded event agree
+#[sqlx::test(migrations = "./migrations")]
+async fn a_patch_racing_a_confirm_never_splits_the_amount(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    for _ in 0..20 {
+        let id = create(&app, 1000).await;
+        let patch = {
+            let app = app.clone();
+            let uri = routes::payment_intent(&id);
+            tokio::spawn(async move { send(&app, "PATCH", &uri, json!({ "amount": 2000 })).await })
+        };
+        let confirm = {
+            let app = app.clone();
+            let uri = routes::payment_intent_confirm(&id);
+            tokio::spawn(async move { send(&app, "POST", &uri, json!({})).await })
+        };
+        let (patch_status, _) = patch.await.unwrap();
+        let (confirm_status, confirmed) = confirm.await.unwrap();
+
+        assert_eq!(confirm_status, StatusCode::OK);
+        let expected = match patch_status {
+            StatusCode::OK => 2000,
+            StatusCode::CONFLICT => 1000,
+            other => panic!("PATCH returned {other}"),
+        };
+        assert_eq!(confirmed["amount"], expected);
+
+        let row = sqlx::query!(
+            r#"
+            SELECT pi.amount, e.payload
+            FROM payment_intents pi
+            JOIN events_outbox e
+              ON e.event_type = 'payment_intent.succeeded'
+             AND e.payload->'payment_intent'->>'id' = pi.id::text
+            WHERE pi.id = $1
+            "#,
+            uuid::Uuid::parse_str(&id).unwrap()
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(row.amount, expected);
+        assert_eq!(row.payload["payment_intent"]["amount"], expected);
+    }
+}
+
+async fn updated_events(pool: &PgPool) -> Vec<Value> {
+    sqlx::query_scalar!(
+        "SELECT payload FROM events_outbox WHERE event_type = 'payment_intent.updated' ORDER BY sequence"
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap()
+}
+
+// Consumers that saw payment_intent.created can reconcile every later change from the events
+#[sqlx::test(migrations = "./migrations")]
+async fn patches_emit_updated_events_with_previous_attributes(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let (status, created) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        json!({ "amount": 1000, "currency": "gbp", "metadata": { "order_id": "o_1", "note": "gift" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = created["id"].as_str().unwrap();
+    let uri = routes::payment_intent(id);
+
+    let (status, _) = send(&app, "PATCH", &uri, json!({ "amount": 2500 })).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, pi) = send(
+        &app,
+        "PATCH",
+        &uri,
+        json!({ "metadata": { "note": "", "order_id": "o_2", "channel": "web" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        pi["metadata"],
+        json!({ "order_id": "o_2", "channel": "web" })
+    );
+
+    let events = updated_events(&pool).await;
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["payment_intent"]["amount"], 2500);
+    assert_eq!(events[0]["previous_attributes"], json!({ "amount": 1000 }));
+    assert_eq!(events[1]["payment_intent"], pi);
+    assert_eq!(
+        events[1]["previous_attributes"],
+        json!({ "metadata": { "order_id": "o_1", "note": "gift", "channel": null } })
+    );
+
+    // No-ops write nothing
+    for body in [
+        json!({}),
+        json!({ "amount": 2500 }),
+        json!({ "metadata": { "order_id": "o_2", "missing": "" } }),
+    ] {
+        let (status, unchanged) = send(&app, "PATCH", &uri, body.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(unchanged, pi, "{body}");
+    }
+    assert_eq!(updated_events(&pool).await.len(), 2);
+
+    // After confirmation only the metadata can still change
+    let (status, _) = send(&app, "POST", &routes::payment_intent_confirm(id), json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, "PATCH", &uri, json!({ "amount": 3000 })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = send(
+        &app,
+        "PATCH",
+        &uri,
+        json!({ "metadata": { "channel": "store" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let events = updated_events(&pool).await;
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[2]["payment_intent"]["status"], "succeeded");
+    assert_eq!(
+        events[2]["previous_attributes"],
+        json!({ "metadata": { "channel": "web" } })
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn the_natural_key_field_cannot_be_patched(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.natural_idempotency_key = Some("order_id".to_string());
+    let app = build_app(state);
+    let (status, created) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        json!({ "amount": 1000, "currency": "gbp", "metadata": { "order_id": "o_1" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let uri = routes::payment_intent(created["id"].as_str().unwrap());
+
+    for metadata in [json!({ "order_id": "o_2" }), json!({ "order_id": "" })] {
+        let (status, err) = send(&app, "PATCH", &uri, json!({ "metadata": metadata })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{metadata}");
+        assert_eq!(err["error"]["code"], "parameter_invalid");
+    }
+    let (status, _) = send(
+        &app,
+        "PATCH",
+        &uri,
+        json!({ "metadata": { "order_id": "o_1", "note": "gift" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated_events(&pool).await.len(), 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn currency_and_description_can_be_patched(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let id = create(&app, 1000).await;
+    let uri = routes::payment_intent(&id);
+
+    let (status, err) = send(&app, "PATCH", &uri, json!({ "currency": "pounds" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+
+    let (status, pi) = send(
+        &app,
+        "PATCH",
+        &uri,
+        json!({ "currency": "eur", "description": "Room 12, two nights" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(pi["currency"], "eur");
+    assert_eq!(pi["description"], "Room 12, two nights");
+    let events = updated_events(&pool).await;
+    assert_eq!(
+        events[0]["previous_attributes"],
+        json!({ "currency": "gbp", "description": null })
+    );
+
+    // The currency freezes with the amount; the description can still change, or be cleared
+    let (status, _) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&id),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, err) = send(&app, "PATCH", &uri, json!({ "currency": "usd" })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "payment_intent_unexpected_state");
+    assert_eq!(
+        err["error"]["message"],
+        "cannot change the amount or currency of payment_intent in status 'succeeded'"
+    );
+    let (status, pi) = send(&app, "PATCH", &uri, json!({ "description": "" })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(pi["description"].is_null());
+    assert_eq!(updated_events(&pool).await.len(), 2);
+}
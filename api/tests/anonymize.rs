@@ -0,0 +1,253 @@
+use api::anonymize::{self, AnonymizeError, DomainHit};
+use api::{app::build_app, routes, state::AppState};
+use axum::{Router, body::Body, http::Request};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+const DOMAIN: &str = "acme-corp.com";
+
+async fn post(app: &Router, uri: &str, key: Option<&str>, body: Value) -> Value {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(key) = key {
+        builder = builder.header("Idempotency-Key", key);
+    }
+    let res = app
+        .clone()
+        .oneshot(builder.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    assert!(res.status().is_success(), "{uri}: {}", res.status());
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn database(pool: &PgPool) -> String {
+    sqlx::query_scalar("SELECT current_database()")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+async fn count(pool: &PgPool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+// Production-shaped data: receivers and customers at acme-corp.com
+async fn seed(pool: &PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.natural_idempotency_key = Some("order_id".to_string());
+    state.config.webhook_secret_reveal_enabled = true;
+    let app = build_app(state);
+
+    let mut endpoints = Vec::new();
+    for path in ["hooks", "billing/hooks"] {
+        let url = format!("https://{DOMAIN}/{path}");
+        let endpoint = post(&app, routes::WEBHOOK_ENDPOINTS, None, json!({ "url": url })).await;
+        endpoints.push(endpoint["id"].as_str().unwrap().to_string());
+    }
+    post(
+        &app,
+        &format!("/v1/webhook_endpoints/{}/reveal_secret", endpoints[0]),
+        None,
+        json!({}),
+    )
+    .await;
+
+    // Two orders from one customer
+    for (order, key) in [("ord_1", Some("checkout-1")), ("ord_2", None)] {
+        let metadata = json!({ "order_id": order, "email": format!("jane@{DOMAIN}") });
+        let created = post(
+            &app,
+            routes::PAYMENT_INTENTS,
+            key,
+            json!({ "amount": 1000, "currency": "gbp", "metadata": metadata }),
+        )
+        .await;
+        let id = created["id"].as_str().unwrap();
+        post(&app, &routes::payment_intent_confirm(id), None, json!({})).await;
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_deliveries (id, event_id, webhook_endpoint_id, status, last_error, response_snippet)
+        SELECT gen_random_uuid(), e.id, $1::uuid, 'failed',
+               'error sending request for url (https://acme-corp.com/hooks)', 'hello jane@acme-corp.com'
+        FROM events_outbox e
+        "#,
+    )
+    .bind(&endpoints[0])
+    .execute(pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"
+        INSERT INTO request_captures (id, method, path, body, response_status)
+        VALUES ($1, 'POST', '/v1/payment_intents', '{"metadata":{"email":"jane@acme-corp.com"}}', 201)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn refuses_unless_the_connected_database_is_confirmed(pool: PgPool) {
+    seed(&pool).await;
+    let domains = [DOMAIN.to_string()];
+
+    for confirm in [None, Some("production")] {
+        let err = anonymize::run(&pool, confirm, &domains).await.unwrap_err();
+        assert!(matches!(err, AnonymizeError::NotConfirmed { .. }), "{err}");
+    }
+    assert_eq!(count(&pool, "request_captures").await, 1);
+    let urls: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_endpoints WHERE url LIKE $1")
+        .bind(format!("%{DOMAIN}%"))
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(urls, 2);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn removes_pii_and_keeps_uniqueness_and_relations(pool: PgPool) {
+    seed(&pool).await;
+    let related = [
+        "payment_intents",
+        "payment_intent_transitions",
+        "events_outbox",
+        "webhook_deliveries",
+    ];
+    let mut before = Vec::new();
+    for table in related {
+        before.push(count(&pool, table).await);
+    }
+    let old_secrets: Vec<String> =
+        sqlx::query_scalar("SELECT secret FROM webhook_endpoints ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+
+    let confirm = database(&pool).await;
+    let report = anonymize::run(&pool, Some(&confirm), &[DOMAIN.to_string()])
+        .await
+        .unwrap();
+    assert!(report.tables.contains(&("webhook_endpoints", 2)));
+    assert!(report.tables.contains(&("request_captures", 1)));
+
+    // Same rows, still related to each other
+    for (table, before) in related.into_iter().zip(before) {
+        assert_eq!(count(&pool, table).await, before, "{table}");
+    }
+    assert_eq!(count(&pool, "request_captures").await, 0);
+
+    let endpoints: Vec<(Uuid, String, String)> =
+        sqlx::query_as("SELECT id, url, secret FROM webhook_endpoints ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    for ((id, url, secret), old_secret) in endpoints.iter().zip(&old_secrets) {
+        assert_eq!(url, &format!("https://{id}.example.org/webhooks"));
+        assert_eq!(secret.len(), 32);
+        assert_ne!(secret, old_secret);
+    }
+
+    // One customer is still one (anonymized) customer, and each order keeps its own natural key
+    let intents: Vec<(Value, Option<String>)> =
+        sqlx::query_as("SELECT metadata, natural_key FROM payment_intents")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    let (first, second) = (&intents[0], &intents[1]);
+    assert_eq!(first.0["email"], second.0["email"]);
+    assert!(first.0["email"].as_str().unwrap().starts_with("anon_"));
+    assert_ne!(first.0["order_id"], second.0["order_id"]);
+    for (metadata, natural_key) in &intents {
+        assert_eq!(metadata["order_id"].as_str(), natural_key.as_deref());
+    }
+
+    // Event payloads agree with the rows they describe
+    let revealed_url: String = sqlx::query_scalar(
+        r#"
+        SELECT e.payload->'webhook_endpoint'->>'url'
+        FROM events_outbox e
+        WHERE e.event_type = 'webhook_endpoint.secret_revealed'
+        "#,
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(endpoints.iter().any(|(_, url, _)| *url == revealed_url));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn verification_fails_on_pii_outside_the_map(pool: PgPool) {
+    seed(&pool).await;
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log (id, action, resource_id, details)
+        VALUES ($1, 'note', $1, '{"contact": "ops@acme-corp.com"}')
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let confirm = database(&pool).await;
+    let err = anonymize::run(&pool, Some(&confirm), &[DOMAIN.to_string()])
+        .await
+        .unwrap_err();
+    let AnonymizeError::VerificationFailed(hits) = err else {
+        panic!("expected a verification failure, got {err}");
+    };
+    assert_eq!(
+        hits,
+        [DomainHit {
+            domain: DOMAIN.to_string(),
+            table: "audit_log".to_string(),
+            rows: 1,
+        }]
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn rewrites_tables_larger_than_one_batch(pool: PgPool) {
+    sqlx::query(
+        r#"
+        INSERT INTO idempotency_keys (key, endpoint, request_hash, response_body)
+        SELECT 'key-' || n, 'POST /v1/payment_intents',
+               'amount=1&currency=gbp&metadata={"email":"u' || n || '@acme-corp.com"}',
+               jsonb_build_object('metadata', jsonb_build_object('email', 'u' || n || '@acme-corp.com'))
+        FROM generate_series(1, 1234) AS n
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let confirm = database(&pool).await;
+    let report = anonymize::run(&pool, Some(&confirm), &[DOMAIN.to_string()])
+        .await
+        .unwrap();
+    assert!(report.tables.contains(&("idempotency_keys", 1234)));
+
+    // Distinct customers stay distinct
+    let distinct: i64 = sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT response_body->'metadata'->>'email') FROM idempotency_keys",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(distinct, 1234);
+}
@@ -0,0 +1,140 @@
+use api::routes;
+use api::{app::build_app, backpressure, state::AppState};
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request, StatusCode, header},
+};
+use http_body_util::BodyExt;
+use serde_json::json;
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+async fn send(
+    app: &axum::Router,
+    method: &str,
+    uri: &str,
+) -> (StatusCode, HeaderMap, serde_json::Value) {
+    let mut builder = Request::builder().method(method).uri(uri);
+    let body = if method == "POST" {
+        builder = builder.header("content-type", "application/json");
+        Body::from(json!({ "amount": 1000, "currency": "gbp" }).to_string())
+    } else {
+        Body::empty()
+    };
+
+    let res = app
+        .clone()
+        .oneshot(builder.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let headers = res.headers().clone();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        headers,
+        serde_json::from_slice(&bytes).unwrap_or_default(),
+    )
+}
+
+// `count` events that an enabled endpoint has not received yet
+async fn seed_backlog(pool: &PgPool, count: i64) {
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_endpoints (id, url, secret)
+        VALUES ($1, 'https://receiver.invalid/hook', 'whsec_backlog')
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO events_outbox (id, event_type, payload)
+        SELECT gen_random_uuid(), 'payment_intent.created', '{}'::jsonb
+        FROM generate_series(1, $1::bigint)
+        "#,
+        count
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+// Marks all but `remaining` of the pending events delivered, as a recovering worker would
+async fn drain_to(pool: &PgPool, remaining: i64) {
+    sqlx::query!(
+        r#"
+        UPDATE events_outbox
+        SET delivered_at = now()
+        WHERE id IN (
+          SELECT id FROM events_outbox
+          WHERE delivered_at IS NULL
+          ORDER BY sequence
+          OFFSET $1
+        )
+        "#,
+        remaining
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn mutations_are_refused_until_the_backlog_drains(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.event_backlog_high_water = Some(50);
+    state.config.event_backlog_low_water = Some(10);
+    state.config.event_backlog_sample_interval = std::time::Duration::from_secs(5);
+    let app = build_app(state.clone());
+
+    seed_backlog(&pool, 60).await;
+
+    // Nothing changes until the next sample
+    let (status, _, _) = send(&app, "POST", routes::PAYMENT_INTENTS).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    backpressure::sample(&state).await.unwrap();
+    let (status, headers, err) = send(&app, "POST", routes::PAYMENT_INTENTS).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(err["error"]["code"], "event_backlog");
+    assert_eq!(headers[header::RETRY_AFTER], "5");
+
+    // Reads and the admin endpoints keep working
+    let (status, _, _) = send(&app, "GET", routes::PAYMENT_INTENTS).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, _, diagnostics) = send(&app, "GET", routes::ADMIN_DIAGNOSTICS).await;
+    assert_eq!(diagnostics["event_backlog"], 61);
+    assert_eq!(diagnostics["event_backlog_engaged"], true);
+
+    // Below the high-water mark but above the low one: still refused
+    drain_to(&pool, 30).await;
+    backpressure::sample(&state).await.unwrap();
+    let (status, _, _) = send(&app, "POST", routes::PAYMENT_INTENTS).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    drain_to(&pool, 10).await;
+    backpressure::sample(&state).await.unwrap();
+    let (status, _, _) = send(&app, "POST", routes::PAYMENT_INTENTS).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(routes::METRICS)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let text =
+        String::from_utf8(res.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap();
+    assert!(text.contains("event_backlog 10\n"), "{text}");
+    assert!(text.contains("event_backlog_engaged 0\n"), "{text}");
+}
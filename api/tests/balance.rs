@@ -0,0 +1,119 @@
+mod common;
+
+use std::time::Duration;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::http::StatusCode;
+use common::{paid, send};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+
+#[sqlx::test(migrations = "./migrations")]
+async fn charges_and_refunds_are_in_the_ledger(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.processing_fee_bps = Some(290);
+    let app = build_app(state);
+
+    let pi = paid(&app, 1000, "gbp").await;
+    let (status, refund) = send(
+        &app,
+        "POST",
+        routes::REFUNDS,
+        json!({ "payment_intent": pi, "amount": 400 }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, list) = send(&app, "GET", routes::BALANCE_TRANSACTIONS, Value::Null).await;
+    let data = list["data"].as_array().unwrap();
+    assert_eq!(data.len(), 2);
+    // Newest first
+    assert_eq!(data[0]["type"], "refund");
+    assert_eq!(data[0]["source"], refund["id"]);
+    assert_eq!(data[0]["amount"], -400);
+    assert_eq!(data[0]["fee"], 0);
+    assert_eq!(data[0]["net"], -400);
+    assert_eq!(data[1]["type"], "charge");
+    assert_eq!(data[1]["amount"], 1000);
+    assert_eq!(data[1]["fee"], 29);
+    assert_eq!(data[1]["net"], 971);
+    assert_eq!(data[1]["status"], "available");
+
+    let id = data[1]["id"].as_str().unwrap();
+    let (_, fetched) = send(&app, "GET", &routes::balance_transaction(id), Value::Null).await;
+    assert_eq!(&fetched, &data[1]);
+
+    let (_, balance) = send(&app, "GET", routes::BALANCE, Value::Null).await;
+    assert_eq!(
+        balance,
+        json!({
+            "available": [{ "amount": 571, "currency": "gbp" }],
+            "pending": [{ "amount": 0, "currency": "gbp" }]
+        })
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn charges_stay_pending_until_available_on(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.balance_available_after = Some(Duration::from_secs(2 * 86400));
+    let app = build_app(state);
+
+    paid(&app, 1000, "gbp").await;
+    paid(&app, 500, "usd").await;
+
+    let (_, balance) = send(&app, "GET", routes::BALANCE, Value::Null).await;
+    assert_eq!(
+        balance["pending"],
+        json!([
+            { "amount": 1000, "currency": "gbp" },
+            { "amount": 500, "currency": "usd" }
+        ])
+    );
+    assert_eq!(balance["available"][0]["amount"], 0);
+
+    let (_, list) = send(&app, "GET", routes::BALANCE_TRANSACTIONS, Value::Null).await;
+    assert_eq!(list["data"][0]["status"], "pending");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn balance_transactions_page_with_starting_after(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    for amount in [100, 200, 300] {
+        paid(&app, amount, "gbp").await;
+    }
+
+    let uri = format!("{}?limit=2", routes::BALANCE_TRANSACTIONS);
+    let (_, first) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(first["has_more"], true);
+    assert_eq!(
+        (first["limit"].clone(), first["max_limit"].clone()),
+        (json!(2), json!(100))
+    );
+    assert!(first.get("warnings").is_none());
+    let amounts: Vec<_> = first["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["amount"].clone())
+        .collect();
+    assert_eq!(amounts, [300, 200]);
+
+    let last = first["data"][1]["id"].as_str().unwrap();
+    let uri = format!(
+        "{}?limit=2&starting_after={last}",
+        routes::BALANCE_TRANSACTIONS
+    );
+    let (_, second) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(second["has_more"], false);
+    assert_eq!(second["data"].as_array().unwrap().len(), 1);
+    assert_eq!(second["data"][0]["amount"], 100);
+
+    let uri = format!(
+        "{}?starting_after={}",
+        routes::BALANCE_TRANSACTIONS,
+        uuid::Uuid::new_v4()
+    );
+    let (status, _) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{HeaderMap, Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn send(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, HeaderMap, Value) {
+    let mut builder = Request::builder().method(method).uri(uri);
+    let body = match body {
+        Some(body) => {
+            builder = builder.header("content-type", "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    let res = app
+        .clone()
+        .oneshot(builder.body(body).unwrap())
+        .await
+        .unwrap();
+    let (status, headers) = (res.status(), res.headers().clone());
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        headers,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn cache_control(headers: &HeaderMap) -> &str {
+    headers["cache-control"].to_str().unwrap()
+}
+
+async fn create_endpoint(app: &Router) -> String {
+    let (status, headers, body) = send(
+        app,
+        "POST",
+        routes::WEBHOOK_ENDPOINTS,
+        Some(json!({ "url": "https://example.com/webhooks" })),
+    )
+    .await;
+    assert!(status.is_success());
+    assert_eq!(cache_control(&headers), "no-store");
+    assert!(!headers.contains_key("vary"));
+    body["id"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn reads_get_their_class_policy_and_mutations_are_never_stored(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let id = create_endpoint(&app).await;
+
+    let (status, headers, _) = send(
+        &app,
+        "GET",
+        &format!("/v1/webhook_endpoints/{id}/stats"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cache_control(&headers), "private, max-age=5");
+    assert_eq!(headers["vary"], "Authorization");
+
+    let (_, headers, _) = send(&app, "GET", routes::WEBHOOK_ENDPOINTS, None).await;
+    assert_eq!(cache_control(&headers), "private, max-age=0");
+    assert_eq!(headers["vary"], "Authorization");
+
+    let (_, headers, _) = send(&app, "GET", routes::ADMIN_WORKERS, None).await;
+    assert_eq!(cache_control(&headers), "no-store");
+
+    // Single resources are left to their handler
+    let (_, headers, _) = send(&app, "GET", &format!("/v1/webhook_endpoints/{id}"), None).await;
+    assert!(!headers.contains_key("cache-control"));
+
+    let (status, headers, _) =
+        send(&app, "GET", "/v1/webhook_endpoints/we_missing/stats", None).await;
+    assert!(!status.is_success());
+    assert_eq!(cache_control(&headers), "no-store");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn max_ages_and_stale_while_revalidate_come_from_config(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.cache_stats_max_age = Some(Duration::from_secs(30));
+    state.config.cache_list_max_age = Some(Duration::from_secs(2));
+    state.config.cache_stale_while_revalidate = Some(Duration::from_secs(60));
+    let app = build_app(state);
+    let id = create_endpoint(&app).await;
+
+    let (_, headers, _) = send(
+        &app,
+        "GET",
+        &format!("/v1/webhook_endpoints/{id}/stats"),
+        None,
+    )
+    .await;
+    assert_eq!(
+        cache_control(&headers),
+        "private, max-age=30, stale-while-revalidate=60"
+    );
+
+    let (_, headers, _) = send(&app, "GET", routes::PAYMENT_INTENTS, None).await;
+    assert_eq!(
+        cache_control(&headers),
+        "private, max-age=2, stale-while-revalidate=60"
+    );
+}
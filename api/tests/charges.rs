@@ -0,0 +1,169 @@
+mod common;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{Router, http::StatusCode};
+use common::{paid, send};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+async fn create(app: &Router, body: Value) -> String {
+    let (status, pi) = send(app, "POST", routes::PAYMENT_INTENTS, body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    pi["id"].as_str().unwrap().to_string()
+}
+
+async fn charges_of(app: &Router, payment_intent: &str) -> Vec<Value> {
+    let uri = format!("{}?payment_intent={payment_intent}", routes::CHARGES);
+    let (status, list) = send(app, "GET", &uri, Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(list["object"], "list");
+    list["data"].as_array().unwrap().clone()
+}
+
+async fn charge_events(pool: &PgPool) -> Vec<Value> {
+    sqlx::query_scalar("SELECT payload FROM events_outbox WHERE event_type = 'charge.succeeded'")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_successful_confirm_creates_one_charge(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let id = create(&app, json!({ "amount": 1000, "currency": "gbp" })).await;
+    assert!(charges_of(&app, &id).await.is_empty());
+
+    let (status, _) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&id),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let charges = charges_of(&app, &id).await;
+    assert_eq!(charges.len(), 1);
+    let charge = &charges[0];
+    assert_eq!(charge["payment_intent"], id.as_str());
+    assert_eq!(charge["amount"], 1000);
+    assert_eq!(charge["currency"], "gbp");
+    assert_eq!(charge["status"], "succeeded");
+
+    let charge_id = charge["id"].as_str().unwrap();
+    let (status, fetched) = send(&app, "GET", &routes::charge(charge_id), Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&fetched, charge);
+
+    // Its event carries the same charge
+    assert_eq!(
+        charge_events(&pool).await,
+        vec![json!({ "charge": charge })]
+    );
+
+    // Confirming again is a 409 and charges nothing more
+    let (status, _) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&id),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(charges_of(&app, &id).await.len(), 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn only_money_that_moved_is_charged(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    // A decline and an authorization charge nothing
+    let declined = create(&app, json!({ "amount": 1000, "currency": "gbp" })).await;
+    let (status, _) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&declined),
+        json!({ "payment_method": "pm_card_declined" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert!(charges_of(&app, &declined).await.is_empty());
+
+    let manual = create(
+        &app,
+        json!({ "amount": 1000, "currency": "gbp", "capture_method": "manual" }),
+    )
+    .await;
+    let (status, _) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&manual),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(charges_of(&app, &manual).await.is_empty());
+
+    // The capture is charged for what it captured
+    let (status, _) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_capture(&manual),
+        json!({ "amount_to_capture": 600 }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let charges = charges_of(&app, &manual).await;
+    assert_eq!(charges.len(), 1);
+    assert_eq!(charges[0]["amount"], 600);
+    assert_eq!(charge_events(&pool).await.len(), 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn charges_list_newest_first_and_page(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        ids.push(paid(&app, 1000, "gbp").await);
+    }
+
+    let (_, list) = send(&app, "GET", routes::CHARGES, Value::Null).await;
+    let intents: Vec<_> = list["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|charge| charge["payment_intent"].as_str().unwrap())
+        .collect();
+    assert_eq!(intents, [ids[2].as_str(), ids[1].as_str(), ids[0].as_str()]);
+    assert_eq!(list["has_more"], false);
+
+    let uri = format!("{}?limit=2", routes::CHARGES);
+    let (_, list) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(list["data"].as_array().unwrap().len(), 2);
+    assert_eq!(list["has_more"], true);
+    assert_eq!(
+        (list["limit"].clone(), list["max_limit"].clone()),
+        (json!(2), json!(100))
+    );
+
+    let last = list["data"][1]["id"].as_str().unwrap();
+    let uri = format!("{}?limit=2&starting_after={last}", routes::CHARGES);
+    let (_, next) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(next["data"].as_array().unwrap().len(), 1);
+    assert_eq!(next["data"][0]["payment_intent"], ids[0].as_str());
+    assert_eq!(next["has_more"], false);
+
+    let uri = format!("{}?limit=500", routes::CHARGES);
+    let (_, list) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(list["limit"], 100);
+    assert_eq!(list["warnings"].as_array().unwrap().len(), 1);
+
+    let uri = format!("{}?starting_after={}", routes::CHARGES, Uuid::new_v4());
+    let (status, _) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, err) = send(&app, "GET", &routes::charge(Uuid::new_v4()), Value::Null).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(err["error"]["code"], "resource_missing");
+}
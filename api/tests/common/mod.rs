@@ -0,0 +1,93 @@
+// Shared helpers for integration tests.
+// Each test binary uses only some of them.
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use api::routes;
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use tower::ServiceExt;
+use tracing::instrument::WithSubscriber;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Metadata, Subscriber};
+
+// Counts the statements sqlx executes (it logs every one under the `sqlx::query` target)
+#[derive(Clone, Default)]
+struct QueryCounter {
+    count: Arc<AtomicUsize>,
+}
+
+impl Subscriber for QueryCounter {
+    fn register_callsite(&self, _: &'static Metadata<'static>) -> Interest {
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == "sqlx::query"
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        if event.metadata().target() == "sqlx::query" {
+            self.count.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+// Runs `fut` and returns its output plus the number of SQL statements it executed.
+// Only statements polled inside `fut` are counted, so parallel tests don't interfere.
+pub async fn count_queries<F: Future>(fut: F) -> (F::Output, usize) {
+    let counter = QueryCounter::default();
+    let count = counter.count.clone();
+
+    let output = fut.with_subscriber(counter).await;
+
+    (output, count.load(Ordering::SeqCst))
+}
+
+// One JSON request through the router; the response body must be JSON too
+pub async fn send(app: &Router, method: &str, uri: &str, body: Value) -> (StatusCode, Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+// A succeeded intent's id, created with `confirm: true`
+pub async fn paid(app: &Router, amount: i64, currency: &str) -> String {
+    let body = json!({ "amount": amount, "currency": currency, "confirm": true });
+    let (status, pi) = send(app, "POST", routes::PAYMENT_INTENTS, body).await;
+    assert_eq!(status, StatusCode::CREATED, "{pi}");
+    pi["id"].as_str().unwrap().to_string()
+}
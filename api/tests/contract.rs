@@ -0,0 +1,333 @@
+// Contract tests: each file in tests/fixtures/contract is a sequence of requests and the responses
+// we promise for them. Dynamic values are placeholders: in a response, "{{name}}" matches anything
+// the first time and must repeat exactly after that; in a later request, "{{name}}" is replaced by
+// the captured value. "{{*}}" matches anything without capturing.
+//
+// After an intentional wire change, regenerate and review the fixture diff:
+//
+//     UPDATE_FIXTURES=1 cargo test -p mini-stripe-api --test contract
+//
+// Placeholders are kept where the new response still has the field; new fields come in literally.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use api::{app::build_app, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use mini_stripe_types::signature::{self, SIGNATURE_HEADER};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/contract");
+const WILDCARD: &str = "*";
+
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    description: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    sandbox: bool,
+    steps: Vec<Step>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Step {
+    request: FixtureRequest,
+    response: FixtureResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FixtureRequest {
+    method: String,
+    path: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<Value>,
+    // Webhook secret to sign the body with, sent as the signature header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sign_with: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FixtureResponse {
+    status: u16,
+    // Only the headers listed here are compared
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    // Null for an empty body
+    body: Value,
+}
+
+fn placeholder(s: &str) -> Option<&str> {
+    s.strip_prefix("{{")?.strip_suffix("}}")
+}
+
+fn substitute_str(s: &str, captures: &HashMap<String, Value>) -> String {
+    captures.iter().fold(s.to_string(), |s, (name, value)| {
+        let text = value
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| value.to_string());
+        s.replace(&format!("{{{{{name}}}}}"), &text)
+    })
+}
+
+fn substitute(value: &Value, captures: &HashMap<String, Value>) -> Value {
+    match value {
+        Value::String(s) => match placeholder(s).and_then(|name| captures.get(name)) {
+            Some(captured) => captured.clone(),
+            None => Value::String(substitute_str(s, captures)),
+        },
+        Value::Array(items) => items.iter().map(|v| substitute(v, captures)).collect(),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(k, v)| (k.clone(), substitute(v, captures)))
+            .collect(),
+        other => other.clone(),
+    }
+}
+
+// Collects every difference rather than stopping at the first, so a rename shows both sides
+fn compare(
+    expected: &Value,
+    actual: &Value,
+    at: &str,
+    captures: &mut HashMap<String, Value>,
+    diffs: &mut Vec<String>,
+) {
+    if let Some(name) = expected.as_str().and_then(placeholder) {
+        if name == WILDCARD {
+            return;
+        }
+        match captures.get(name) {
+            Some(captured) if captured != actual => diffs.push(format!(
+                "{at}: expected {{{{{name}}}}} = {captured}, got {actual}"
+            )),
+            Some(_) => {}
+            None => {
+                captures.insert(name.to_string(), actual.clone());
+            }
+        }
+        return;
+    }
+
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected) in expected {
+                match actual.get(key) {
+                    Some(actual) => {
+                        compare(expected, actual, &format!("{at}.{key}"), captures, diffs)
+                    }
+                    None => diffs.push(format!("{at}.{key}: missing from the response")),
+                }
+            }
+            for key in actual.keys().filter(|key| !expected.contains_key(*key)) {
+                diffs.push(format!("{at}.{key}: not in the fixture"));
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => {
+            for (i, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                compare(expected, actual, &format!("{at}[{i}]"), captures, diffs);
+            }
+        }
+        _ if expected != actual => diffs.push(format!("{at}: expected {expected}, got {actual}")),
+        _ => {}
+    }
+}
+
+// The actual response, with the fixture's placeholders kept wherever the field still exists
+fn regenerate(expected: &Value, actual: &Value) -> Value {
+    if expected.as_str().and_then(placeholder).is_some() {
+        return expected.clone();
+    }
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => actual
+            .iter()
+            .map(|(key, actual)| {
+                let value = match expected.get(key) {
+                    Some(expected) => regenerate(expected, actual),
+                    None => actual.clone(),
+                };
+                (key.clone(), value)
+            })
+            .collect(),
+        (Value::Array(expected), Value::Array(actual)) => actual
+            .iter()
+            .enumerate()
+            .map(|(i, actual)| match expected.get(i) {
+                Some(expected) => regenerate(expected, actual),
+                None => actual.clone(),
+            })
+            .collect(),
+        _ => actual.clone(),
+    }
+}
+
+async fn send(
+    app: &Router,
+    request: &FixtureRequest,
+    captures: &HashMap<String, Value>,
+) -> (StatusCode, axum::http::HeaderMap, Value) {
+    let mut builder = Request::builder()
+        .method(request.method.as_str())
+        .uri(substitute_str(&request.path, captures));
+    for (name, value) in &request.headers {
+        builder = builder.header(name, substitute_str(value, captures));
+    }
+
+    let body = match &request.body {
+        Some(body) => {
+            let bytes = serde_json::to_vec(&substitute(body, captures)).unwrap();
+            builder = builder.header("content-type", "application/json");
+            if let Some(secret) = &request.sign_with {
+                let secret = substitute_str(secret, captures);
+                builder = builder.header(SIGNATURE_HEADER, signature::sign(&secret, &bytes));
+            }
+            Body::from(bytes)
+        }
+        None => Body::empty(),
+    };
+
+    let res = app
+        .clone()
+        .oneshot(builder.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let headers = res.headers().clone();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let body = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes).unwrap()
+    };
+    (status, headers, body)
+}
+
+async fn run_fixture(pool: PgPool, name: &str) {
+    let path = PathBuf::from(FIXTURES_DIR).join(format!("{name}.json"));
+    let raw = std::fs::read_to_string(&path).unwrap();
+    let mut fixture: Fixture = serde_json::from_str(&raw).unwrap();
+    let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+
+    let mut state = AppState::new(pool);
+    state.config.sandbox_mode = fixture.sandbox;
+    let app = build_app(state);
+
+    let mut captures = HashMap::new();
+    let mut diffs = Vec::new();
+    for (i, step) in fixture.steps.iter_mut().enumerate() {
+        let (status, headers, body) = send(&app, &step.request, &captures).await;
+        let at = format!("step {i} ({} {})", step.request.method, step.request.path);
+
+        if status.as_u16() != step.response.status {
+            diffs.push(format!(
+                "{at}: expected status {}, got {status}",
+                step.response.status
+            ));
+        }
+        for (header, expected) in &step.response.headers {
+            let actual = headers
+                .get(header)
+                .and_then(|v| v.to_str().ok())
+                .map_or(Value::Null, Value::from);
+            let expected = Value::from(expected.as_str());
+            let at = format!("{at} header {header}");
+            compare(&expected, &actual, &at, &mut captures, &mut diffs);
+        }
+        compare(&step.response.body, &body, &at, &mut captures, &mut diffs);
+
+        if update {
+            step.response.status = status.as_u16();
+            for (header, value) in step.response.headers.iter_mut() {
+                let actual = headers.get(header.as_str()).and_then(|v| v.to_str().ok());
+                if let Some(actual) = actual.filter(|_| placeholder(value).is_none()) {
+                    *value = actual.to_string();
+                }
+            }
+            step.response.body = regenerate(&step.response.body, &body);
+        }
+    }
+
+    if update {
+        let json = serde_json::to_string_pretty(&fixture).unwrap();
+        std::fs::write(&path, format!("{json}\n")).unwrap();
+        return;
+    }
+    assert!(
+        diffs.is_empty(),
+        "{name}: response no longer matches the fixture \
+         (UPDATE_FIXTURES=1 regenerates it if the change is intended):\n{}",
+        diffs.join("\n")
+    );
+}
+
+// One test per fixture, so a failure names the flow that changed
+macro_rules! contract_tests {
+    ($($name:ident),* $(,)?) => {
+        const FIXTURES: &[&str] = &[$(stringify!($name)),*];
+
+        $(
+            #[sqlx::test(migrations = "./migrations")]
+            async fn $name(pool: PgPool) {
+                run_fixture(pool, stringify!($name)).await;
+            }
+        )*
+    };
+}
+
+contract_tests!(
+    create_payment_intent,
+    create_rejects_non_positive_amount,
+    create_requires_currency,
+    create_rejects_malformed_currency,
+    create_rejects_unsupported_currency,
+    create_with_confirm,
+    retrieve_payment_intent,
+    retrieve_unknown_payment_intent,
+    confirm_payment_intent,
+    confirm_twice_conflicts,
+    idempotent_replay,
+    idempotency_key_reused,
+    payment_intent_transitions,
+    webhook_signature_verified,
+    webhook_signature_rejected,
+);
+
+#[test]
+fn every_fixture_has_a_test() {
+    let mut on_disk: Vec<String> = std::fs::read_dir(FIXTURES_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+        .collect();
+    on_disk.sort();
+
+    let mut registered: Vec<String> = FIXTURES.iter().map(|name| name.to_string()).collect();
+    registered.sort();
+    assert_eq!(on_disk, registered);
+}
+
+#[test]
+fn a_renamed_field_is_reported() {
+    let expected = serde_json::json!({ "id": "{{pi}}", "amount": 1000 });
+    let actual = serde_json::json!({ "id": "pi_1", "amount_minor": 1000 });
+
+    let mut diffs = Vec::new();
+    compare(&expected, &actual, "body", &mut HashMap::new(), &mut diffs);
+    assert_eq!(
+        diffs,
+        vec![
+            "body.amount: missing from the response",
+            "body.amount_minor: not in the fixture",
+        ]
+    );
+}
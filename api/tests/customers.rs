@@ -0,0 +1,146 @@
+mod common;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{Router, http::StatusCode};
+use common::send;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+
+async fn create_customer(app: &Router, body: Value) -> String {
+    let (status, customer) = send(app, "POST", routes::CUSTOMERS, body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    customer["id"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn customers_can_be_created_updated_and_listed(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let body = json!({
+        "email": "jo@example.com",
+        "name": "Jo",
+        "metadata": { "crm_id": "c_1", "tier": "gold" }
+    });
+    let id = create_customer(&app, body).await;
+    create_customer(&app, json!({ "email": "sam@example.com" })).await;
+
+    let (status, fetched) = send(&app, "GET", &routes::customer(&id), Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched["email"], "jo@example.com");
+    assert_eq!(fetched["metadata"]["tier"], "gold");
+
+    // Metadata merges, an empty string clears, and untouched fields stay
+    let changes = json!({ "name": "", "description": "VIP", "metadata": { "tier": "" } });
+    let (status, updated) = send(&app, "PATCH", &routes::customer(&id), changes).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(updated["name"].is_null());
+    assert_eq!(updated["description"], "VIP");
+    assert_eq!(updated["email"], "jo@example.com");
+    assert_eq!(updated["metadata"], json!({ "crm_id": "c_1" }));
+
+    let (_, all) = send(&app, "GET", routes::CUSTOMERS, Value::Null).await;
+    assert_eq!(all["object"], "list");
+    assert_eq!(all["data"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        (all["limit"].clone(), all["max_limit"].clone()),
+        (json!(20), json!(100))
+    );
+
+    // Paging on from a customer deleted since its page was read still works
+    let uri = format!("{}?limit=1", routes::CUSTOMERS);
+    let (_, first) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(first["has_more"], true);
+    let sam = first["data"][0]["id"].as_str().unwrap().to_string();
+    send(&app, "DELETE", &routes::customer(&sam), Value::Null).await;
+    let uri = format!("{}?limit=1&starting_after={sam}", routes::CUSTOMERS);
+    let (_, second) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(second["data"][0]["id"], id.as_str());
+    assert_eq!(second["has_more"], false);
+    let uri = format!("{}?email=jo@example.com", routes::CUSTOMERS);
+    let (_, jo) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(jo["data"].as_array().unwrap().len(), 1);
+    assert_eq!(jo["data"][0]["id"], id.as_str());
+
+    let (status, err) = send(&app, "POST", routes::CUSTOMERS, json!({ "email": "jo" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn deleted_customers_are_gone_but_their_intents_stay(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let id = create_customer(&app, json!({ "email": "jo@example.com" })).await;
+
+    let body = json!({ "amount": 1000, "currency": "gbp", "customer": id });
+    let (status, pi) = send(&app, "POST", routes::PAYMENT_INTENTS, body.clone()).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, deleted) = send(&app, "DELETE", &routes::customer(&id), Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(deleted, json!({ "id": id, "deleted": true }));
+
+    for (method, body) in [
+        ("GET", Value::Null),
+        ("PATCH", json!({ "name": "Jo" })),
+        ("DELETE", Value::Null),
+    ] {
+        let (status, err) = send(&app, method, &routes::customer(&id), body).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{method}");
+        assert_eq!(err["error"]["code"], "resource_missing");
+    }
+    let (_, all) = send(&app, "GET", routes::CUSTOMERS, Value::Null).await;
+    assert_eq!(all["data"], json!([]));
+
+    // The existing intent still names it; a new one can't
+    let pi_id = pi["id"].as_str().unwrap();
+    let (_, fetched) = send(&app, "GET", &routes::payment_intent(pi_id), Value::Null).await;
+    assert_eq!(fetched["customer"], id.as_str());
+    let (status, err) = send(&app, "POST", routes::PAYMENT_INTENTS, body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn intents_carry_their_customer(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let id = create_customer(&app, json!({ "email": "jo@example.com" })).await;
+
+    let body = json!({ "amount": 1000, "currency": "gbp", "customer": id });
+    let (status, pi) = send(&app, "POST", routes::PAYMENT_INTENTS, body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(pi["customer"], id.as_str());
+    let (_, other) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        json!({ "amount": 500, "currency": "gbp" }),
+    )
+    .await;
+    assert!(other["customer"].is_null());
+
+    let uri = format!("{}?customer={id}", routes::PAYMENT_INTENTS);
+    let (_, listed) = send(&app, "GET", &uri, Value::Null).await;
+    let data = listed["data"].as_array().unwrap();
+    assert_eq!(data.len(), 1);
+    assert_eq!(data[0]["id"], pi["id"]);
+
+    let payload: Value = sqlx::query_scalar(
+        "SELECT payload FROM events_outbox WHERE event_type = 'payment_intent.created' \
+         AND payload->'payment_intent'->>'id' = $1",
+    )
+    .bind(pi["id"].as_str().unwrap())
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(payload["payment_intent"]["customer"], id.as_str());
+
+    let missing = json!({ "amount": 1000, "currency": "gbp", "customer": uuid::Uuid::new_v4() });
+    let (status, err) = send(&app, "POST", routes::PAYMENT_INTENTS, missing).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        err["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("no such customer")
+    );
+}
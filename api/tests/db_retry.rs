@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use api::db::{Isolation, run_tx_with_retry};
+use api::metrics::{Metrics, TX_RETRIES, TX_RETRIES_EXHAUSTED};
+use axum::{http::StatusCode, response::IntoResponse};
+use sqlx::PgPool;
+
+async fn counter_table(pool: &PgPool) {
+    sqlx::query("CREATE TABLE counters (id INT PRIMARY KEY, value INT NOT NULL)")
+        .execute(pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO counters VALUES (1, 0)")
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+// Increments the counter after reading it. On the attempts listed in `conflict_on` another
+// transaction bumps it in between, so the update fails with a serialization failure.
+async fn increment(
+    pool: &PgPool,
+    metrics: &Metrics,
+    max_retries: u32,
+    conflict_on: &'static [u32],
+) -> (Result<i32, api::error::ApiError>, u32) {
+    let attempts = Arc::new(AtomicU32::new(0));
+    let result = run_tx_with_retry(
+        pool,
+        metrics,
+        Isolation::RepeatableRead,
+        max_retries,
+        |tx| {
+            let attempts = attempts.clone();
+            let pool = pool.clone();
+            Box::pin(async move {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                let value: i32 = sqlx::query_scalar("SELECT value FROM counters WHERE id = 1")
+                    .fetch_one(&mut **tx)
+                    .await?;
+                if conflict_on.contains(&attempt) {
+                    sqlx::query("UPDATE counters SET value = value + 100 WHERE id = 1")
+                        .execute(&pool)
+                        .await?;
+                }
+                sqlx::query("UPDATE counters SET value = $1 WHERE id = 1")
+                    .bind(value + 1)
+                    .execute(&mut **tx)
+                    .await?;
+                Ok(value + 1)
+            })
+        },
+    )
+    .await;
+    (result, attempts.load(Ordering::SeqCst))
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_serialization_failure_is_retried(pool: PgPool) {
+    counter_table(&pool).await;
+    let metrics = Metrics::default();
+
+    let (result, attempts) = increment(&pool, &metrics, 3, &[1]).await;
+    // The retry read the concurrent write, so neither update is lost
+    assert_eq!(result.unwrap(), 101);
+    assert_eq!(attempts, 2);
+    let labels = [("isolation", "repeatable_read")];
+    assert_eq!(metrics.get(TX_RETRIES, &labels), 1);
+    assert_eq!(metrics.get(TX_RETRIES_EXHAUSTED, &labels), 0);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn exhausted_retries_are_a_503_with_retry_after(pool: PgPool) {
+    counter_table(&pool).await;
+    let metrics = Metrics::default();
+
+    let (result, attempts) = increment(&pool, &metrics, 2, &[1, 2, 3]).await;
+    let err = result.unwrap_err();
+    assert_eq!(attempts, 3);
+    assert_eq!(err.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(err.code, "transaction_conflict");
+    let labels = [("isolation", "repeatable_read")];
+    assert_eq!(metrics.get(TX_RETRIES, &labels), 2);
+    assert_eq!(metrics.get(TX_RETRIES_EXHAUSTED, &labels), 1);
+
+    let response = err.into_response();
+    assert_eq!(response.headers()["retry-after"], "1");
+}
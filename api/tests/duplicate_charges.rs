@@ -0,0 +1,281 @@
+// Scenarios where a misbehaving client tries to pay for one order twice. Each test is one
+// logical payment (one order_id) and checks it ends up with at most one succeeded intent and
+// exactly the events that payment should have produced.
+
+use std::time::Duration;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use mini_stripe_types::events::{PAYMENT_INTENT_CREATED, PAYMENT_INTENT_SUCCEEDED};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+
+const ORDER: &str = "ord_42";
+
+fn app(pool: &PgPool) -> Router {
+    let mut state = AppState::new(pool.clone());
+    state.config.natural_idempotency_key = Some("order_id".to_string());
+    build_app(state)
+}
+
+fn checkout(amount: i64, confirm: bool) -> Value {
+    json!({
+        "amount": amount,
+        "currency": "gbp",
+        "confirm": confirm,
+        "metadata": { "order_id": ORDER },
+    })
+}
+
+fn create_request(key: Option<&str>, body: &Value) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(routes::PAYMENT_INTENTS)
+        .header("content-type", "application/json");
+    if let Some(key) = key {
+        builder = builder.header("Idempotency-Key", key);
+    }
+    builder.body(Body::from(body.to_string())).unwrap()
+}
+
+async fn create(app: &Router, key: Option<&str>, body: &Value) -> (StatusCode, Value) {
+    let res = app
+        .clone()
+        .oneshot(create_request(key, body))
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+async fn confirm(app: &Router, id: &str) -> StatusCode {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+// What the order ended up with. There is no charge ledger: a succeeded intent, and its
+// payment_intent.succeeded event, is the charge.
+#[derive(Debug, PartialEq)]
+struct Payment {
+    intents: i64,
+    succeeded: i64,
+    // Sorted event types for the order's intents
+    events: Vec<String>,
+}
+
+async fn payment(pool: &PgPool) -> Payment {
+    let counts = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "intents!",
+               COUNT(*) FILTER (WHERE status = 'succeeded') AS "succeeded!"
+        FROM payment_intents
+        WHERE metadata->>'order_id' = $1
+        "#,
+        ORDER
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap();
+
+    let events = sqlx::query_scalar!(
+        r#"
+        SELECT e.event_type
+        FROM events_outbox e
+        JOIN payment_intents p ON e.payload->'payment_intent'->>'id' = p.id::text
+        WHERE p.metadata->>'order_id' = $1
+        ORDER BY e.event_type
+        "#,
+        ORDER
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap();
+
+    Payment {
+        intents: counts.intents,
+        succeeded: counts.succeeded,
+        events,
+    }
+}
+
+fn paid_once() -> Payment {
+    Payment {
+        intents: 1,
+        succeeded: 1,
+        events: vec![
+            PAYMENT_INTENT_CREATED.to_string(),
+            PAYMENT_INTENT_SUCCEEDED.to_string(),
+        ],
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_retry_after_a_lost_response_replays_the_charge(pool: PgPool) {
+    let app = app(&pool);
+
+    // The client times out waiting, but the server finished
+    let (status, first) = create(&app, Some("checkout-1"), &checkout(1000, true)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // A replay answers exactly like the original
+    let (status, retried) = create(&app, Some("checkout-1"), &checkout(1000, true)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(retried["id"], first["id"]);
+    assert_eq!(retried["status"], "succeeded");
+    assert_eq!(payment(&pool).await, paid_once());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_request_cut_off_at_any_point_can_be_retried(pool: PgPool) {
+    let app = app(&pool);
+
+    // The client gives up (and drops the connection) at various points in the request.
+    // Whatever was or wasn't committed, the retry settles on one charge.
+    for (i, micros) in [0, 50, 200, 1000, 5000].into_iter().enumerate() {
+        sqlx::query("TRUNCATE payment_intents, idempotency_keys, events_outbox CASCADE")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let key = format!("checkout-cut-{i}");
+        let cut_off = app
+            .clone()
+            .oneshot(create_request(Some(&key), &checkout(1000, true)));
+        let _ = tokio::time::timeout(Duration::from_micros(micros), cut_off).await;
+
+        let (status, retried) = create(&app, Some(&key), &checkout(1000, true)).await;
+        assert!(status.is_success(), "{status} after a {micros}us cut-off");
+        assert_eq!(retried["status"], "succeeded");
+        assert_eq!(payment(&pool).await, paid_once(), "{micros}us cut-off");
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_retry_with_a_mutated_body_is_refused(pool: PgPool) {
+    let app = app(&pool);
+
+    let (status, _) = create(&app, Some("checkout-1"), &checkout(1000, true)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Same key, but the client "fixed" the amount before retrying
+    let (status, body) = create(&app, Some("checkout-1"), &checkout(1200, true)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"]["code"], "idempotency_key_reused");
+    assert_eq!(payment(&pool).await, paid_once());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_double_click_creates_one_intent_for_the_order(pool: PgPool) {
+    let app = app(&pool);
+
+    // Each click generates its own idempotency key, so only the order_id ties them together
+    let mut clicks = JoinSet::new();
+    for i in 0..2 {
+        let app = app.clone();
+        clicks.spawn(async move {
+            let key = format!("click-{i}");
+            create(&app, Some(&key), &checkout(1000, false)).await
+        });
+    }
+    let mut ids = Vec::new();
+    while let Some(result) = clicks.join_next().await {
+        let (status, body) = result.unwrap();
+        assert!(status.is_success());
+        ids.push(body["id"].clone());
+    }
+    assert_eq!(ids[0], ids[1]);
+
+    assert_eq!(
+        confirm(&app, ids[0].as_str().unwrap()).await,
+        StatusCode::OK
+    );
+    assert_eq!(payment(&pool).await, paid_once());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_confirm_retried_concurrently_charges_once(pool: PgPool) {
+    let app = app(&pool);
+    let (_, created) = create(&app, Some("checkout-1"), &checkout(1000, false)).await;
+    let id = created["id"].as_str().unwrap().to_string();
+
+    let mut confirms = JoinSet::new();
+    for _ in 0..3 {
+        let (app, id) = (app.clone(), id.clone());
+        confirms.spawn(async move { confirm(&app, &id).await });
+    }
+    let mut statuses = confirms.join_all().await;
+    statuses.sort();
+    assert_eq!(
+        statuses,
+        [StatusCode::OK, StatusCode::CONFLICT, StatusCode::CONFLICT]
+    );
+    assert_eq!(payment(&pool).await, paid_once());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_crash_before_the_response_is_stored_leaves_nothing_behind(pool: PgPool) {
+    let app = app(&pool);
+
+    // Fault injection: fail the write of the stored response, after the key was reserved and
+    // the intent inserted in the same transaction
+    sqlx::query(
+        r#"
+        CREATE FUNCTION crash_before_response() RETURNS trigger AS $$
+        BEGIN
+            RAISE EXCEPTION 'injected crash';
+        END
+        $$ LANGUAGE plpgsql
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        r#"
+        CREATE TRIGGER crash_before_response
+        BEFORE UPDATE OF response_body ON idempotency_keys
+        FOR EACH ROW EXECUTE FUNCTION crash_before_response()
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let (status, _) = create(&app, Some("checkout-1"), &checkout(1000, true)).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        payment(&pool).await,
+        Payment {
+            intents: 0,
+            succeeded: 0,
+            events: vec![],
+        }
+    );
+
+    // The process comes back and the client retries
+    sqlx::query("DROP TRIGGER crash_before_response ON idempotency_keys")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (status, retried) = create(&app, Some("checkout-1"), &checkout(1000, true)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(retried["status"], "succeeded");
+    assert_eq!(payment(&pool).await, paid_once());
+}
@@ -0,0 +1,111 @@
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    http::{HeaderMap, Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+async fn send(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    accept: Option<&str>,
+    body: Option<Value>,
+) -> (StatusCode, HeaderMap, Bytes) {
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(accept) = accept {
+        builder = builder.header("accept", accept);
+    }
+    let body = match body {
+        Some(body) => {
+            builder = builder.header("content-type", "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    let res = app
+        .clone()
+        .oneshot(builder.body(body).unwrap())
+        .await
+        .unwrap();
+    let (status, headers) = (res.status(), res.headers().clone());
+    (
+        status,
+        headers,
+        res.into_body().collect().await.unwrap().to_bytes(),
+    )
+}
+
+// The same error, rendered for a JSON client and a legacy plain-text one
+async fn both_ways(app: &Router, method: &str, uri: &str, body: Option<Value>) -> (Value, String) {
+    let (json_status, json_headers, json_body) = send(app, method, uri, None, body.clone()).await;
+    let (text_status, text_headers, text_body) =
+        send(app, method, uri, Some("text/plain"), body).await;
+
+    assert_eq!(json_status, text_status);
+    assert!(
+        json_headers["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("application/json")
+    );
+    assert!(
+        text_headers["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain")
+    );
+    (
+        serde_json::from_slice(&json_body).unwrap(),
+        String::from_utf8(text_body.to_vec()).unwrap(),
+    )
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn plain_text_clients_get_the_message_with_the_same_status(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let missing = format!("/v1/payment_intents/{}", Uuid::new_v4());
+    let (envelope, text) = both_ways(&app, "GET", &missing, None).await;
+    assert_eq!(envelope["error"]["code"], "resource_missing");
+    assert_eq!(text, envelope["error"]["message"]);
+
+    let (envelope, text) = both_ways(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        Some(json!({ "amount": -5, "currency": "usd" })),
+    )
+    .await;
+    assert_eq!(envelope["error"]["code"], "parameter_invalid");
+    assert_eq!(text, envelope["error"]["message"]);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn json_stays_the_default_when_both_are_accepted(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let missing = format!("/v1/payment_intents/{}", Uuid::new_v4());
+    let (status, headers, body) = send(
+        &app,
+        "GET",
+        &missing,
+        Some("text/plain, application/json;q=0.9"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(
+        headers["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("application/json")
+    );
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["code"], "resource_missing");
+}
@@ -0,0 +1,96 @@
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+async fn get(app: &Router, uri: &str) -> Value {
+    let res = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn insert_event(pool: &PgPool, age_secs: f64) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO events_outbox (id, event_type, payload, created_at)
+        VALUES ($1, 'payment_intent.created', '{}'::jsonb, now() - make_interval(secs => $2))
+        "#,
+        id,
+        age_secs
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+    id
+}
+
+// What the worker's exporter records once it has written everything up to `event_id`
+async fn export_through(pool: &PgPool, event_id: Uuid, exported_count: i64) {
+    sqlx::query!(
+        r#"
+        INSERT INTO event_export_watermarks
+          (partition_date, last_event_id, last_txid, last_sequence, exported_count)
+        SELECT (created_at AT TIME ZONE 'UTC')::date, id, txid, sequence, $2
+        FROM events_outbox
+        WHERE id = $1
+        ON CONFLICT (partition_date) DO UPDATE
+        SET last_event_id = EXCLUDED.last_event_id,
+            last_txid = EXCLUDED.last_txid,
+            last_sequence = EXCLUDED.last_sequence,
+            exported_count = EXCLUDED.exported_count
+        "#,
+        event_id,
+        exported_count
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn export_lag_follows_the_watermarks(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let first = insert_event(&pool, 90.0).await;
+    let second = insert_event(&pool, 60.0).await;
+    let third = insert_event(&pool, 30.0).await;
+
+    // Nothing exported yet: the lag is the age of the oldest event
+    let status = get(&app, routes::ADMIN_EVENT_EXPORT).await;
+    assert_eq!(status["pending_events"], 3);
+    let lag = status["lag_seconds"].as_f64().unwrap();
+    assert!((90.0..120.0).contains(&lag), "{lag}");
+    assert_eq!(status["partitions"], Value::Array(vec![]));
+
+    export_through(&pool, first, 1).await;
+    export_through(&pool, second, 2).await;
+    let status = get(&app, routes::ADMIN_EVENT_EXPORT).await;
+    assert_eq!(status["pending_events"], 1);
+    let lag = status["lag_seconds"].as_f64().unwrap();
+    assert!((30.0..60.0).contains(&lag), "{lag}");
+    let partitions = status["partitions"].as_array().unwrap();
+    assert!(!partitions.is_empty());
+    let exported: i64 = partitions
+        .iter()
+        .map(|p| p["exported_count"].as_i64().unwrap())
+        .sum();
+    assert_eq!(exported, 2);
+
+    export_through(&pool, third, 3).await;
+    let status = get(&app, routes::ADMIN_EVENT_EXPORT).await;
+    assert_eq!(status["pending_events"], 0);
+    assert_eq!(status["lag_seconds"], 0.0);
+    assert_eq!(status["oldest_pending_created_at"], Value::Null);
+}
@@ -0,0 +1,227 @@
+use api::events_outbox::{EventError, insert_event};
+use api::routes;
+use api::{app::build_app, state::AppState};
+use axum::{body::Body, http::Request};
+use http_body_util::BodyExt;
+use serde_json::json;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn send(
+    app: &axum::Router,
+    method: &str,
+    uri: &str,
+    body: Option<String>,
+) -> serde_json::Value {
+    let builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    let body = body.map(Body::from).unwrap_or_else(Body::empty);
+
+    let res = app
+        .clone()
+        .oneshot(builder.body(body).unwrap())
+        .await
+        .unwrap();
+    assert!(
+        res.status().is_success(),
+        "{method} {uri} -> {}",
+        res.status()
+    );
+
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn drain_reports_pending_until_existing_work_is_done(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let endpoint = send(
+        &app,
+        "POST",
+        routes::WEBHOOK_ENDPOINTS,
+        Some(json!({ "url": "https://example.com/webhooks" }).to_string()),
+    )
+    .await;
+    let endpoint_id: uuid::Uuid = endpoint["id"].as_str().unwrap().parse().unwrap();
+
+    let pi_body = json!({ "amount": 1000, "currency": "gbp" }).to_string();
+    send(&app, "POST", routes::PAYMENT_INTENTS, Some(pi_body.clone())).await;
+
+    let status = send(&app, "POST", routes::ADMIN_OUTBOX_DRAIN, None).await;
+    assert_eq!(status["draining"], true);
+    assert_eq!(status["pending"], 1);
+    assert_eq!(status["drained"], false);
+
+    // Events created after the marker are not part of the drain
+    sqlx::query!("UPDATE outbox_state SET draining_since = draining_since - interval '1 second'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    send(&app, "POST", routes::PAYMENT_INTENTS, Some(pi_body)).await;
+
+    let status = send(&app, "GET", routes::ADMIN_OUTBOX_DRAIN_STATUS, None).await;
+    assert_eq!(status["pending"], 0);
+
+    // Simulate the worker finishing the pre-marker event
+    let event_id = sqlx::query_scalar!("SELECT id FROM events_outbox ORDER BY created_at LIMIT 1")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_deliveries (id, event_id, webhook_endpoint_id, status)
+        VALUES (gen_random_uuid(), $1, $2, 'succeeded')
+        "#,
+        event_id,
+        endpoint_id
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let status = send(&app, "GET", routes::ADMIN_OUTBOX_DRAIN_STATUS, None).await;
+    assert_eq!(status["draining"], true);
+    assert_eq!(status["drained"], true);
+
+    let status = send(&app, "POST", routes::ADMIN_OUTBOX_RESUME, None).await;
+    assert_eq!(status["draining"], false);
+    assert_eq!(status["drained"], false);
+    assert_eq!(status["pending"], 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn oversized_event_payloads_are_rejected_at_insert(pool: PgPool) {
+    let payload = json!({ "payment_intent": { "id": "pi", "note": "x".repeat(300 * 1024) } });
+
+    let err = insert_event(&pool, "payment_intent.created", payload)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, EventError::PayloadTooLarge { .. }));
+
+    let count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM events_outbox"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+
+    insert_event(
+        &pool,
+        "payment_intent.created",
+        json!({ "payment_intent": { "id": "pi" } }),
+    )
+    .await
+    .unwrap();
+}
+
+async fn redeliver(
+    app: &axum::Router,
+    event_id: uuid::Uuid,
+    endpoint_id: uuid::Uuid,
+) -> (axum::http::StatusCode, serde_json::Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::event_redeliver(event_id))
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({ "webhook_endpoint_id": endpoint_id }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn redeliver_waits_for_the_active_delivery(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let endpoint = send(
+        &app,
+        "POST",
+        routes::WEBHOOK_ENDPOINTS,
+        Some(json!({ "url": "https://example.com/webhooks" }).to_string()),
+    )
+    .await;
+    let endpoint_id: uuid::Uuid = endpoint["id"].as_str().unwrap().parse().unwrap();
+    let pi_body = json!({ "amount": 1000, "currency": "gbp" }).to_string();
+    send(&app, "POST", routes::PAYMENT_INTENTS, Some(pi_body)).await;
+    let event_id = sqlx::query_scalar!("SELECT id FROM events_outbox")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+    // What the worker leaves behind while it is sending the original delivery
+    let in_flight = sqlx::query_scalar!(
+        r#"
+        INSERT INTO webhook_deliveries (id, event_id, webhook_endpoint_id, status, attempt_count)
+        VALUES (gen_random_uuid(), $1, $2, 'in_progress', 1)
+        RETURNING id
+        "#,
+        event_id,
+        endpoint_id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+
+    let (status, body) = redeliver(&app, event_id, endpoint_id).await;
+    assert_eq!(status, 409);
+    assert_eq!(body["error"]["code"], "delivery_already_active");
+    assert!(
+        body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains(&in_flight.to_string())
+    );
+
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET status = 'succeeded' WHERE id = $1",
+        in_flight
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let (status, body) = redeliver(&app, event_id, endpoint_id).await;
+    assert_eq!(status, 201);
+    assert_eq!(body["generation"], 2);
+    assert_eq!(body["status"], "pending");
+
+    // Two redeliveries racing once that one completes: exactly one gets through
+    sqlx::query!("UPDATE webhook_deliveries SET status = 'failed' WHERE generation = 2")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (a, b) = tokio::join!(
+        redeliver(&app, event_id, endpoint_id),
+        redeliver(&app, event_id, endpoint_id)
+    );
+    let mut statuses = vec![a.0.as_u16(), b.0.as_u16()];
+    statuses.sort();
+    assert_eq!(statuses, vec![201, 409]);
+
+    let generations: Vec<i32> =
+        sqlx::query_scalar!("SELECT generation FROM webhook_deliveries ORDER BY generation")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(generations, vec![1, 2, 3]);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn redeliver_unknown_event_is_not_found(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (status, body) = redeliver(&app, uuid::Uuid::new_v4(), uuid::Uuid::new_v4()).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "resource_missing");
+}
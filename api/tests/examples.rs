@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use api::{app::build_app, examples::FLOWS, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn get_example(app: &Router, flow: &str) -> (StatusCode, Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(routes::example(flow))
+                .header("host", "api.example.test")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+fn expand(s: &str, vars: &HashMap<String, String>) -> String {
+    vars.iter().fold(s.to_string(), |s, (name, value)| {
+        s.replace(&format!("${name}"), value)
+    })
+}
+
+// The documented response has to have exactly the fields the real one has (array responses
+// compare their first items, when both have one)
+fn assert_same_fields(documented: &Value, actual: &Value, at: &str) {
+    match (documented, actual) {
+        (Value::Object(documented), Value::Object(actual)) => {
+            let mut documented: Vec<_> = documented.keys().collect();
+            let mut actual: Vec<_> = actual.keys().collect();
+            documented.sort();
+            actual.sort();
+            assert_eq!(documented, actual, "{at}");
+        }
+        (Value::Array(documented), Value::Array(actual)) => {
+            if let (Some(documented), Some(actual)) = (documented.first(), actual.first()) {
+                assert_same_fields(documented, actual, at);
+            }
+        }
+        _ => panic!("{at}: documented {documented}, got {actual}"),
+    }
+}
+
+// Runs every step of every flow as documented and checks the status, headers and fields it
+// promises
+#[sqlx::test(migrations = "./migrations")]
+async fn every_example_runs_as_documented(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    for flow in FLOWS {
+        let (status, example) = get_example(&app, flow).await;
+        assert_eq!(status, StatusCode::OK, "{flow}");
+
+        let mut vars = HashMap::new();
+        for (i, step) in example["steps"].as_array().unwrap().iter().enumerate() {
+            let at = format!("{flow} step {i}");
+            let curl = step["curl"].as_str().unwrap();
+            assert!(
+                curl.contains("\"http://api.example.test/v1/"),
+                "{at}: {curl}"
+            );
+
+            let mut request = Request::builder()
+                .method(step["method"].as_str().unwrap())
+                .uri(expand(step["path"].as_str().unwrap(), &vars));
+            if let Some(headers) = step["headers"].as_object() {
+                for (name, value) in headers {
+                    request = request.header(name, value.as_str().unwrap());
+                }
+            }
+            let body = match &step["body"] {
+                Value::Null => Body::empty(),
+                body => Body::from(expand(&body.to_string(), &vars)),
+            };
+
+            let res = app
+                .clone()
+                .oneshot(request.body(body).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(
+                res.status().as_u16() as u64,
+                step["expected_status"].as_u64().unwrap(),
+                "{at}"
+            );
+            if let Some(expected) = step["expected_headers"].as_object() {
+                for (name, value) in expected {
+                    assert_eq!(
+                        res.headers().get(name).and_then(|v| v.to_str().ok()),
+                        value.as_str(),
+                        "{at}: {name}"
+                    );
+                }
+            }
+
+            let bytes = res.into_body().collect().await.unwrap().to_bytes();
+            let actual: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_same_fields(&step["response"], &actual, &at);
+
+            if let Some(saved) = step["saves"].as_object() {
+                let value = actual[saved["field"].as_str().unwrap()].as_str().unwrap();
+                vars.insert(
+                    saved["variable"].as_str().unwrap().to_string(),
+                    value.to_string(),
+                );
+            }
+        }
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn unknown_flow_lists_the_available_ones(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (status, body) = get_example(&app, "payout").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "resource_missing");
+    let message = body["error"]["message"].as_str().unwrap();
+    for flow in FLOWS {
+        assert!(message.contains(flow), "{message}");
+    }
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use api::{app::build_app, expiry, routes, state::AppState};
+use axum::{Router, http::StatusCode};
+use common::send;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+async fn create(app: &Router) -> String {
+    let (status, pi) = send(
+        app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        json!({ "amount": 1000, "currency": "gbp" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    pi["id"].as_str().unwrap().to_string()
+}
+
+async fn backdate_expiry(pool: &PgPool, id: &str) {
+    sqlx::query!(
+        "UPDATE payment_intents SET expires_at = now() - interval '1 second' WHERE id = $1",
+        Uuid::parse_str(id).unwrap()
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+async fn canceled_events(pool: &PgPool) -> Vec<Value> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT payload
+        FROM events_outbox
+        WHERE event_type = 'payment_intent.canceled'
+        ORDER BY id
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn new_intents_expire_a_day_after_creation(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let id = create(&app).await;
+
+    let (_, pi) = send(&app, "GET", &routes::payment_intent(&id), json!({})).await;
+    let expires_at: chrono::DateTime<chrono::Utc> =
+        serde_json::from_value(pi["expires_at"].clone()).unwrap();
+    let ttl = expires_at - chrono::Utc::now();
+    assert!(ttl > chrono::Duration::hours(23) && ttl <= chrono::Duration::hours(24));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn confirm_cancels_an_expired_intent_instead(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let id = create(&app).await;
+    backdate_expiry(&pool, &id).await;
+
+    let (status, err) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&id),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        err["error"]["code"],
+        "payment_intent_expired_for_confirmation"
+    );
+
+    let (_, pi) = send(&app, "GET", &routes::payment_intent(&id), json!({})).await;
+    assert_eq!(pi["status"], "canceled");
+    assert_eq!(pi["cancellation_reason"], "expired");
+    assert_eq!(canceled_events(&pool).await.len(), 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn expirer_cancels_only_expired_unconfirmed_intents(pool: PgPool) {
+    let state = AppState::new(pool.clone());
+    let app = build_app(state.clone());
+
+    let expired: Vec<String> = [create(&app).await, create(&app).await, create(&app).await].into();
+    for id in &expired {
+        backdate_expiry(&pool, id).await;
+    }
+    let live = create(&app).await;
+    let confirmed = create(&app).await;
+    let (status, _) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&confirmed),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    backdate_expiry(&pool, &confirmed).await;
+
+    // Two batches of two, the second one short
+    assert_eq!(expiry::run(&state, 2).await.unwrap(), 3);
+    assert_eq!(expiry::run(&state, 2).await.unwrap(), 0);
+
+    for id in &expired {
+        let (_, pi) = send(&app, "GET", &routes::payment_intent(id), json!({})).await;
+        assert_eq!(pi["status"], "canceled");
+        assert_eq!(pi["cancellation_reason"], "expired");
+
+        let (_, transitions) = send(
+            &app,
+            "GET",
+            &routes::payment_intent_transitions(id),
+            json!({}),
+        )
+        .await;
+        assert_eq!(transitions[1]["cause"], "expired");
+    }
+    for id in [&live, &confirmed] {
+        let (_, pi) = send(&app, "GET", &routes::payment_intent(id), json!({})).await;
+        assert_ne!(pi["status"], "canceled");
+    }
+
+    let events = canceled_events(&pool).await;
+    assert_eq!(events.len(), 3);
+    for event in events {
+        assert_eq!(event["payment_intent"]["cancellation_reason"], "expired");
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn expirer_cancels_declined_and_action_pending_intents(pool: PgPool) {
+    let state = AppState::new(pool.clone());
+    let app = build_app(state.clone());
+
+    let mut unpaid = Vec::new();
+    for (card, status) in [
+        ("pm_card_declined", "requires_payment_method"),
+        ("pm_card_authentication_required", "requires_action"),
+    ] {
+        let id = create(&app).await;
+        let confirm = routes::payment_intent_confirm(&id);
+        send(&app, "POST", &confirm, json!({ "payment_method": card })).await;
+        let (_, pi) = send(&app, "GET", &routes::payment_intent(&id), json!({})).await;
+        assert_eq!(pi["status"], status);
+        backdate_expiry(&pool, &id).await;
+        unpaid.push((id, status));
+    }
+
+    assert_eq!(expiry::run(&state, 10).await.unwrap(), 2);
+
+    for (id, status) in &unpaid {
+        let (_, pi) = send(&app, "GET", &routes::payment_intent(id), json!({})).await;
+        assert_eq!(pi["status"], "canceled");
+        assert_eq!(pi["cancellation_reason"], "expired");
+
+        let (_, transitions) = send(
+            &app,
+            "GET",
+            &routes::payment_intent_transitions(id),
+            json!({}),
+        )
+        .await;
+        assert_eq!(transitions[2]["from_status"], *status);
+        assert_eq!(transitions[2]["cause"], "expired");
+    }
+}
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use api::faults::{Fault, FaultRule};
+use api::metrics::FAULTS_INJECTED;
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{HeaderMap, Method, Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tokio::time::Instant;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+fn state(pool: &PgPool, sandbox: bool) -> AppState {
+    let mut state = AppState::new(pool.clone());
+    state.config.sandbox_mode = sandbox;
+    state
+}
+
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, HeaderMap, Value) {
+    let req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    let (parts, body) = res.into_parts();
+    let bytes = body.collect().await.unwrap().to_bytes();
+    (
+        parts.status,
+        parts.headers,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+async fn create_intent(app: &Router) -> String {
+    let body = json!({ "amount": 1000, "currency": "gbp" });
+    let (status, _, created) = send(app, Method::POST, routes::PAYMENT_INTENTS, Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    created["id"].as_str().unwrap().to_string()
+}
+
+async fn add_fault(app: &Router, rule: Value) -> Value {
+    let (status, _, created) = send(app, Method::POST, routes::ADMIN_FAULTS, Some(rule)).await;
+    assert_eq!(status, StatusCode::CREATED, "{created}");
+    created
+}
+
+async fn list_faults(app: &Router) -> Vec<Value> {
+    let (status, _, rules) = send(app, Method::GET, routes::ADMIN_FAULTS, None).await;
+    assert_eq!(status, StatusCode::OK);
+    rules.as_array().unwrap().clone()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn confirm_unavailable_fails_confirms_until_it_expires(pool: PgPool) {
+    let state = state(&pool, true);
+    let app = build_app(state.clone());
+    let id = create_intent(&app).await;
+
+    for bad in [
+        json!({ "type": "confirm_unavailable", "rate": 1.5 }),
+        json!({ "type": "confirm_unavailable", "rate": 1.0, "ttl_secs": 0 }),
+        json!({ "type": "confirm_unavailable", "rate": 1.0, "ttl_secs": 7200 }),
+    ] {
+        let (status, _, body) = send(&app, Method::POST, routes::ADMIN_FAULTS, Some(bad)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+    }
+    let rule = add_fault(
+        &app,
+        json!({ "type": "confirm_unavailable", "rate": 1.0, "ttl_secs": 1 }),
+    )
+    .await;
+    assert_eq!(rule["type"], "confirm_unavailable");
+    assert_eq!(rule["activations"], 0);
+
+    let confirm = routes::payment_intent_confirm(&id);
+    let (status, headers, body) = send(&app, Method::POST, &confirm, None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"]["code"], "service_unavailable");
+    assert_eq!(headers["retry-after"], "1");
+    let status = sqlx::query_scalar!(
+        "SELECT status FROM payment_intents WHERE id = $1",
+        id.parse::<Uuid>().unwrap()
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(status, "requires_confirmation");
+
+    let rules = list_faults(&app).await;
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0]["id"], rule["id"]);
+    assert_eq!(rules[0]["activations"], 1);
+    let injected = state
+        .metrics
+        .get(FAULTS_INJECTED, &[("fault", "confirm_unavailable")]);
+    assert_eq!(injected, 1);
+
+    // Expired: confirms go through again and the rule is gone
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let (status, _, body) = send(&app, Method::POST, &confirm, None).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert!(list_faults(&app).await.is_empty());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn confirm_unavailable_also_refuses_captures(pool: PgPool) {
+    let app = build_app(state(&pool, true));
+    let body = json!({ "amount": 1000, "currency": "gbp", "capture_method": "manual" });
+    let (status, _, created) = send(&app, Method::POST, routes::PAYMENT_INTENTS, Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = created["id"].as_str().unwrap();
+    let confirm = routes::payment_intent_confirm(id);
+    let (status, _, _) = send(&app, Method::POST, &confirm, None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    add_fault(&app, json!({ "type": "confirm_unavailable", "rate": 1.0 })).await;
+    let capture = routes::payment_intent_capture(id);
+    let (status, _, body) = send(&app, Method::POST, &capture, Some(json!({}))).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"]["code"], "service_unavailable");
+    let (_, _, pi) = send(&app, Method::GET, &routes::payment_intent(id), None).await;
+    assert_eq!(pi["status"], "requires_capture");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn read_latency_slows_reads_until_cleared(pool: PgPool) {
+    let app = build_app(state(&pool, true));
+    let id = create_intent(&app).await;
+    add_fault(&app, json!({ "type": "read_latency", "latency_ms": 300 })).await;
+
+    for uri in [
+        routes::payment_intent(&id),
+        routes::PAYMENT_INTENTS.to_string(),
+    ] {
+        let started = Instant::now();
+        let (status, _, _) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(started.elapsed() >= Duration::from_millis(300), "{uri}");
+    }
+    assert_eq!(list_faults(&app).await[0]["activations"], 2);
+
+    let (status, _, _) = send(&app, Method::DELETE, routes::ADMIN_FAULTS, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let started = Instant::now();
+    let (status, _, _) = send(&app, Method::GET, &routes::payment_intent(&id), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(started.elapsed() < Duration::from_millis(300));
+}
+
+// The worker is another process, so these rules are mirrored to webhook_delivery_faults; it
+// counts its activations there (see the worker's tests for the delivery side)
+#[sqlx::test(migrations = "./migrations")]
+async fn webhook_timeout_rules_reach_the_worker(pool: PgPool) {
+    let app = build_app(state(&pool, true));
+    let body = json!({ "url": "https://example.com/hooks" });
+    let (status, _, endpoint) =
+        send(&app, Method::POST, routes::WEBHOOK_ENDPOINTS, Some(body)).await;
+    assert!(status.is_success());
+
+    let unknown = json!({ "type": "webhook_timeout", "webhook_endpoint_id": Uuid::new_v4() });
+    let (status, _, _) = send(&app, Method::POST, routes::ADMIN_FAULTS, Some(unknown)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let rule = add_fault(
+        &app,
+        json!({ "type": "webhook_timeout", "webhook_endpoint_id": endpoint["id"] }),
+    )
+    .await;
+    let id: Uuid = rule["id"].as_str().unwrap().parse().unwrap();
+    let row = sqlx::query!(
+        r#"
+        SELECT webhook_endpoint_id, expires_at > now() + interval '299 seconds' AS "long_enough!"
+        FROM webhook_delivery_faults
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(row.webhook_endpoint_id.to_string(), endpoint["id"]);
+    assert!(row.long_enough, "the default TTL is 300s");
+
+    sqlx::query!("UPDATE webhook_delivery_faults SET activations = 3")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(list_faults(&app).await[0]["activations"], 3);
+
+    let (status, _, _) = send(&app, Method::DELETE, routes::ADMIN_FAULTS, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(list_faults(&app).await.is_empty());
+    let left = sqlx::query_scalar!(r#"SELECT count(*) AS "n!" FROM webhook_delivery_faults"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(left, 0);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn faults_are_hard_disabled_outside_sandbox_mode(pool: PgPool) {
+    let state = state(&pool, false);
+    let app = build_app(state.clone());
+    let rule = json!({ "type": "confirm_unavailable", "rate": 1.0 });
+    let (status, _, _) = send(&app, Method::POST, routes::ADMIN_FAULTS, Some(rule)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Even a rule that got into the registry is never consulted
+    state.faults.insert(FaultRule::new(
+        Fault::ConfirmUnavailable { rate: 1.0 },
+        Duration::from_secs(60),
+    ));
+    let id = create_intent(&app).await;
+    let (status, _, body) = send(
+        &app,
+        Method::POST,
+        &routes::payment_intent_confirm(&id),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(
+        state
+            .metrics
+            .get(FAULTS_INJECTED, &[("fault", "confirm_unavailable")]),
+        0
+    );
+}
@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use api::idempotency_cleanup::{self, CleanupReport};
+use api::metrics::{IDEMPOTENCY_CLEANUP_RUNS, IDEMPOTENCY_KEYS_DELETED, Metrics};
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Method, Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+// `total` keys whose order by key has nothing to do with their expiry: every 51st is live (half
+// of those with no expiry at all), the rest expired an hour ago
+async fn seed_keys(pool: &PgPool, total: i32) {
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency_keys (key, endpoint, request_hash, response_body, expires_at)
+        SELECT md5(i::text), 'create_payment_intent', 'hash', '{}'::jsonb,
+               CASE
+                 WHEN i % 102 = 0 THEN NULL
+                 WHEN i % 51 = 0 THEN now() + interval '1 hour'
+                 ELSE now() - interval '1 hour'
+               END
+        FROM generate_series(1, $1) AS i
+        "#,
+        total
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+// (expired, live) keys left
+async fn count_keys(pool: &PgPool) -> (i64, i64) {
+    let row = sqlx::query!(
+        r#"
+        SELECT count(*) FILTER (WHERE expires_at < now()) AS "expired!",
+               count(*) FILTER (WHERE expires_at IS NULL OR expires_at >= now()) AS "live!"
+        FROM idempotency_keys
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    (row.expired, row.live)
+}
+
+async fn send(app: &Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    if body.is_some() {
+        req = req.header("Idempotency-Key", "order-1");
+    }
+    let body = body.map_or_else(Body::empty, |b| Body::from(b.to_string()));
+    let res = app.clone().oneshot(req.body(body).unwrap()).await.unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn only_expired_keys_are_deleted_and_runs_resume(pool: PgPool) {
+    seed_keys(&pool, 51_000).await;
+    assert_eq!(count_keys(&pool).await, (50_000, 1_000));
+    let metrics = Metrics::default();
+
+    // Stopped after 40 batches: the cursor is kept for the next run
+    let report = idempotency_cleanup::run(&pool, &metrics, 250, Duration::ZERO, Some(40))
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        CleanupReport {
+            batches: 40,
+            deleted: 10_000,
+            finished: false,
+        }
+    );
+    assert_eq!(count_keys(&pool).await, (40_000, 1_000));
+    let state =
+        sqlx::query!("SELECT run_cutoff, cursor_key, run_deleted FROM idempotency_cleanup_state")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert!(state.run_cutoff.is_some());
+    assert_ne!(state.cursor_key, "");
+    assert_eq!(state.run_deleted, 10_000);
+
+    // Killed mid-run, wherever it happens to be: the batch in flight rolls back
+    let cleaner = {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            idempotency_cleanup::run(&pool, &Metrics::default(), 250, Duration::ZERO, None).await
+        })
+    };
+    loop {
+        let deleted = sqlx::query_scalar!("SELECT run_deleted FROM idempotency_cleanup_state")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        if deleted >= 20_000 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    cleaner.abort();
+    let _ = cleaner.await;
+    let (expired, live) = count_keys(&pool).await;
+    assert!(expired > 0 && expired <= 30_000, "{expired} expired left");
+    assert_eq!(live, 1_000);
+
+    let report = idempotency_cleanup::run(&pool, &metrics, 250, Duration::ZERO, None)
+        .await
+        .unwrap();
+    assert!(report.finished);
+    assert_eq!(report.deleted as i64, expired);
+    assert_eq!(count_keys(&pool).await, (0, 1_000));
+
+    // The run's total spans all three calls, and nothing was deleted twice
+    let state = sqlx::query!(
+        "SELECT run_cutoff, run_deleted, last_run_deleted FROM idempotency_cleanup_state"
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(state.run_cutoff.is_none());
+    assert_eq!(state.run_deleted, 0);
+    assert_eq!(state.last_run_deleted, Some(50_000));
+    assert_eq!(metrics.get(IDEMPOTENCY_CLEANUP_RUNS, &[]), 1);
+    assert_eq!(
+        metrics.get(IDEMPOTENCY_KEYS_DELETED, &[]),
+        10_000 + report.deleted
+    );
+
+    // The next run starts afresh and finds nothing
+    let report = idempotency_cleanup::run(&pool, &metrics, 250, Duration::ZERO, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        CleanupReport {
+            batches: 0,
+            deleted: 0,
+            finished: true,
+        }
+    );
+    assert_eq!(metrics.get(IDEMPOTENCY_CLEANUP_RUNS, &[]), 2);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn admins_can_run_a_bounded_cleanup(pool: PgPool) {
+    seed_keys(&pool, 51).await;
+    let mut state = AppState::new(pool.clone());
+    state.config.idempotency_cleanup_batch_size = Some(20);
+    state.config.idempotency_cleanup_pause = Some(Duration::ZERO);
+    let app = build_app(state);
+    let uri = |max_batches: u64| {
+        format!(
+            "{}?max_batches={max_batches}",
+            routes::ADMIN_IDEMPOTENCY_CLEANUP
+        )
+    };
+
+    for bad in [0, 10_001] {
+        let (status, _) = send(&app, Method::POST, &uri(bad), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    let (status, report) = send(&app, Method::POST, &uri(2), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        report,
+        json!({ "batches": 2, "deleted": 40, "finished": false })
+    );
+    let (status, cleanup) = send(&app, Method::GET, routes::ADMIN_IDEMPOTENCY_CLEANUP, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cleanup["run_deleted"], 40);
+    assert_eq!(cleanup["expired_keys"], 10);
+    assert!(cleanup["run_cutoff"].is_string());
+    assert!(cleanup["last_run_finished_at"].is_null());
+
+    let (_, report) = send(&app, Method::POST, &uri(2), None).await;
+    assert_eq!(
+        report,
+        json!({ "batches": 1, "deleted": 10, "finished": true })
+    );
+    let (_, cleanup) = send(&app, Method::GET, routes::ADMIN_IDEMPOTENCY_CLEANUP, None).await;
+    assert_eq!(cleanup["last_run_deleted"], 50);
+    assert_eq!(cleanup["expired_keys"], 0);
+    assert!(cleanup["run_cutoff"].is_null());
+    assert_eq!(count_keys(&pool).await, (0, 1));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn keys_expire_only_with_a_configured_ttl(pool: PgPool) {
+    let create = json!({ "amount": 1000, "currency": "gbp" });
+    let app = build_app(AppState::new(pool.clone()));
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        routes::PAYMENT_INTENTS,
+        Some(create.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let expires_at = sqlx::query_scalar!("SELECT expires_at FROM idempotency_keys")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert!(expires_at.is_none());
+
+    sqlx::query!("DELETE FROM idempotency_keys")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let mut state = AppState::new(pool.clone());
+    state.config.idempotency_key_ttl = Some(Duration::from_secs(3600));
+    let app = build_app(state);
+    let (status, _) = send(&app, Method::POST, routes::PAYMENT_INTENTS, Some(create)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let in_an_hour = sqlx::query_scalar!(
+        r#"
+        SELECT expires_at BETWEEN now() + interval '59 minutes' AND now() + interval '1 hour'
+          AS "in_an_hour!"
+        FROM idempotency_keys
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(in_an_hour);
+}
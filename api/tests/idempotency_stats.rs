@@ -0,0 +1,96 @@
+use api::routes;
+use api::{app::build_app, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::json;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn create(app: &Router, key: &str, amount: i64) -> StatusCode {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::PAYMENT_INTENTS)
+                .header("content-type", "application/json")
+                .header("Idempotency-Key", key)
+                .body(Body::from(
+                    json!({ "amount": amount, "currency": "gbp" }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+async fn get_stats(app: &Router, query: &str) -> serde_json::Value {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri(format!("{}{query}", routes::ADMIN_IDEMPOTENCY_STATS))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn stats_aggregate_replays_conflicts_and_reconstructions(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    // "loop": created once, replayed 3 times
+    assert_eq!(create(&app, "loop", 1000).await, StatusCode::CREATED);
+    for _ in 0..3 {
+        assert_eq!(create(&app, "loop", 1000).await, StatusCode::CREATED);
+    }
+    // "reused": created once, then reused with a different body
+    assert_eq!(create(&app, "reused", 1000).await, StatusCode::CREATED);
+    assert_eq!(create(&app, "reused", 2000).await, StatusCode::CONFLICT);
+    // "crash": response_body lost after the intent was written, then retried
+    assert_eq!(create(&app, "crash", 1000).await, StatusCode::CREATED);
+    sqlx::query!("UPDATE idempotency_keys SET response_body = '{}'::jsonb WHERE key = 'crash'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(create(&app, "crash", 1000).await, StatusCode::CREATED);
+    // "once": never retried
+    assert_eq!(create(&app, "once", 1000).await, StatusCode::CREATED);
+
+    let stats = get_stats(&app, "").await;
+    assert_eq!(
+        stats["endpoints"],
+        json!([{
+            "endpoint": "POST /v1/payment_intents",
+            "keys": 4,
+            "replays": 4,
+            "conflicts": 1,
+            "reconstructions": 1,
+            "replay_rate": 1.0,
+            "conflict_rate": 0.25
+        }])
+    );
+    assert_eq!(
+        stats["top_replayed_keys"],
+        json!([
+            { "key": "loop", "endpoint": "POST /v1/payment_intents", "replay_count": 3 },
+            { "key": "crash", "endpoint": "POST /v1/payment_intents", "replay_count": 1 }
+        ])
+    );
+
+    // Keys created before `since` are left out
+    let later = get_stats(&app, "?since=2999-01-01T00:00:00Z").await;
+    assert_eq!(later["endpoints"], json!([]));
+    assert_eq!(later["top_replayed_keys"], json!([]));
+}
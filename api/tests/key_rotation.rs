@@ -0,0 +1,144 @@
+use api::key_rotation::{self, RotateError};
+use api::{app::build_app, routes, state::AppState};
+use axum::{Router, body::Body, http::Request};
+use http_body_util::BodyExt;
+use mini_stripe_types::crypto::{KeyRing, WEBHOOK_ENDPOINT_SECRET, key_id};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+const COLUMN: &str = "webhook_endpoints.secret";
+
+fn key_ring(keys: &[(&str, &str)], encrypt_with: &str) -> KeyRing {
+    let keys = keys
+        .iter()
+        .map(|(id, byte)| format!("{id}:{}", byte.repeat(32)))
+        .collect::<Vec<_>>()
+        .join(",");
+    KeyRing::parse(&keys, Some(encrypt_with)).unwrap()
+}
+
+fn app(pool: &PgPool, ring: KeyRing) -> Router {
+    let mut state = AppState::new(pool.clone());
+    state.config.encryption_keys = ring;
+    state.config.webhook_secret_reveal_enabled = true;
+    build_app(state)
+}
+
+async fn post(app: &Router, uri: &str, body: Value) -> Value {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(res.status().is_success(), "{uri}: {}", res.status());
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn stored_secrets(pool: &PgPool) -> Vec<(Uuid, String)> {
+    sqlx::query_as("SELECT id, secret FROM webhook_endpoints ORDER BY id")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn rotate_moves_every_secret_to_the_new_key(pool: PgPool) {
+    // Three endpoints created under k1, and one from before encryption was configured
+    let old = app(&pool, key_ring(&[("k1", "11")], "k1"));
+    let mut secrets = Vec::new();
+    for n in 0..3 {
+        let url = format!("https://example.com/hooks/{n}");
+        let created = post(&old, routes::WEBHOOK_ENDPOINTS, json!({ "url": url })).await;
+        secrets.push((created["id"].clone(), created["secret"].clone()));
+    }
+    let plaintext = app(&pool, KeyRing::default());
+    let created = post(
+        &plaintext,
+        routes::WEBHOOK_ENDPOINTS,
+        json!({ "url": "https://example.com/legacy" }),
+    )
+    .await;
+    secrets.push((created["id"].clone(), created["secret"].clone()));
+
+    let stored = stored_secrets(&pool).await;
+    let encrypted = stored
+        .iter()
+        .filter(|(_, s)| key_id(s) == Some("k1"))
+        .count();
+    assert_eq!(encrypted, 3);
+    for (_, stored) in &stored {
+        let exposed = secrets[..3]
+            .iter()
+            .any(|(_, secret)| stored.contains(secret.as_str().unwrap()));
+        assert!(!exposed, "{stored}");
+    }
+
+    let rotating = key_ring(&[("k1", "11"), ("k2", "22")], "k2");
+    let report = key_rotation::run(&pool, &rotating, COLUMN, 2)
+        .await
+        .unwrap();
+    assert_eq!(report.rotated, 4);
+    assert_eq!(report.changed_meanwhile, 0);
+    assert!(report.undecryptable.is_empty());
+    for (_, stored) in stored_secrets(&pool).await {
+        assert_eq!(key_id(&stored), Some("k2"), "{stored}");
+    }
+
+    // k1 can now be dropped: every secret is still revealed as it was created
+    let new = app(&pool, key_ring(&[("k2", "22")], "k2"));
+    for (id, secret) in &secrets {
+        let uri = routes::webhook_endpoint_reveal_secret(id.as_str().unwrap());
+        let revealed = post(&new, &uri, json!({})).await;
+        assert_eq!(&revealed["secret"], secret);
+    }
+
+    // Running it again finds nothing to do
+    let report = key_rotation::run(&pool, &rotating, COLUMN, 2)
+        .await
+        .unwrap();
+    assert_eq!(report.rotated, 0);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn rotate_leaves_values_it_cannot_decrypt(pool: PgPool) {
+    let unknown = key_ring(&[("k9", "99")], "k9");
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO webhook_endpoints (id, url, secret) VALUES ($1, $2, $3)")
+        .bind(id)
+        .bind("https://example.com/hooks")
+        .bind(WEBHOOK_ENDPOINT_SECRET.encrypt(&unknown, "whsec_lost"))
+        .execute(&pool)
+        .await
+        .unwrap();
+    let before = stored_secrets(&pool).await;
+
+    let ring = key_ring(&[("k1", "11")], "k1");
+    let report = key_rotation::run(&pool, &ring, COLUMN, 10).await.unwrap();
+    assert_eq!(report.rotated, 0);
+    assert_eq!(report.undecryptable, [id]);
+    assert_eq!(stored_secrets(&pool).await, before);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn rotate_refuses_unknown_columns_and_an_empty_ring(pool: PgPool) {
+    let ring = key_ring(&[("k1", "11")], "k1");
+    let err = key_rotation::run(&pool, &ring, "payment_intents.metadata", 10)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RotateError::UnknownColumn { .. }), "{err}");
+
+    let err = key_rotation::run(&pool, &KeyRing::default(), COLUMN, 10)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RotateError::NoEncryptionKey), "{err}");
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use api::latency_alerts::{self, LatencyAlerting};
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use mini_stripe_types::events::{SERVICE_LATENCY_DEGRADED, SERVICE_LATENCY_RECOVERED};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+const CONFIRM: &str = routes::PAYMENT_INTENT_CONFIRM;
+
+fn alerting_state(pool: PgPool) -> AppState {
+    let mut state = AppState::new(pool);
+    state.config.latency_alerting = Some(LatencyAlerting {
+        window: Duration::from_secs(60),
+        p95_threshold: Duration::from_millis(500),
+        consecutive_windows: 2,
+        cooldown: Duration::from_secs(600),
+    });
+    state
+}
+
+fn minute(n: i64) -> DateTime<Utc> {
+    DateTime::UNIX_EPOCH + chrono::Duration::minutes(n)
+}
+
+// A simulated incident on confirm ends up as exactly one degraded and one recovered event
+#[sqlx::test(migrations = "./migrations")]
+async fn an_incident_is_written_to_the_outbox_once(pool: PgPool) {
+    let state = alerting_state(pool.clone());
+
+    let windows = [80, 900, 950, 1200, 900, 120, 90, 100, 100];
+    for (i, ms) in windows.into_iter().enumerate() {
+        for _ in 0..50 {
+            state
+                .latency_watch
+                .record(CONFIRM, Duration::from_millis(ms));
+        }
+        latency_alerts::flush(&state, minute(i as i64 + 1))
+            .await
+            .unwrap();
+    }
+
+    let events = sqlx::query!(
+        r#"
+        SELECT event_type, payload
+        FROM events_outbox
+        ORDER BY created_at, sequence
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    let types: Vec<_> = events.iter().map(|e| e.event_type.as_str()).collect();
+    assert_eq!(types, [SERVICE_LATENCY_DEGRADED, SERVICE_LATENCY_RECOVERED]);
+
+    let degraded = &events[0].payload;
+    assert_eq!(degraded["route"], CONFIRM);
+    assert_eq!(degraded["p95_ms"], 950);
+    assert_eq!(degraded["threshold_ms"], 500);
+    assert_eq!(degraded["requests"], 50);
+    assert_eq!(
+        degraded["window_started_at"],
+        serde_json::to_value(minute(2)).unwrap()
+    );
+    assert_eq!(
+        degraded["degraded_since"],
+        serde_json::to_value(minute(3)).unwrap()
+    );
+    let recovered = &events[1].payload;
+    assert_eq!(recovered["degraded_since"], degraded["degraded_since"]);
+    assert_eq!(
+        recovered["recovered_at"],
+        serde_json::to_value(minute(7)).unwrap()
+    );
+}
+
+// The middleware records under the route template, and the metrics show its state
+#[sqlx::test(migrations = "./migrations")]
+async fn requests_are_tracked_by_route(pool: PgPool) {
+    let state = alerting_state(pool);
+    let app = build_app(state.clone());
+
+    for _ in 0..3 {
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(routes::payment_intent(uuid::Uuid::new_v4()))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+    latency_alerts::flush(&state, Utc::now()).await.unwrap();
+
+    let snapshot = state.latency_watch.snapshot();
+    let (degraded, p95) = snapshot[routes::PAYMENT_INTENT];
+    assert!(!degraded);
+    assert!(p95.is_some());
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri(routes::METRICS)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = http_body_util::BodyExt::collect(res.into_body())
+        .await
+        .unwrap()
+        .to_bytes();
+    let metrics = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(
+        metrics.contains("route_latency_degraded{route=\"/v1/payment_intents/{id}\"} 0\n"),
+        "{metrics}"
+    );
+}
@@ -0,0 +1,123 @@
+use api::latency::SimulatedLatency;
+use api::metrics::{GROSS_VOLUME, PAYMENTS_CREATED, PAYMENTS_FAILED, PAYMENTS_SUCCEEDED};
+use api::routes;
+use api::{app::build_app, state::AppState};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::json;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn send(app: &axum::Router, req: Request<Body>) -> (StatusCode, String) {
+    let res = app.clone().oneshot(req).await.unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+async fn create(app: &axum::Router, amount: i64, currency: &str, key: Option<&str>) -> String {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(routes::PAYMENT_INTENTS)
+        .header("content-type", "application/json");
+    if let Some(key) = key {
+        builder = builder.header("Idempotency-Key", key);
+    }
+    let body = json!({ "amount": amount, "currency": currency }).to_string();
+    let (status, body) = send(app, builder.body(Body::from(body)).unwrap()).await;
+    assert_eq!(status, StatusCode::CREATED, "{body}");
+
+    let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+    json["id"].as_str().unwrap().to_string()
+}
+
+async fn confirm(app: &axum::Router, id: &str, simulate: Option<&str>) -> StatusCode {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(routes::payment_intent_confirm(id));
+    if let Some(simulate) = simulate {
+        builder = builder.header("X-Simulate", simulate);
+    }
+    send(app, builder.body(Body::empty()).unwrap()).await.0
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn payment_mutations_bump_business_counters(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.sandbox_mode = true;
+    let metrics = state.metrics.clone();
+    let app = build_app(state);
+
+    let paid = create(&app, 2500, "gbp", Some("metrics_key")).await;
+    assert_eq!(confirm(&app, &paid, None).await, StatusCode::OK);
+
+    // An idempotent replay and a refused second confirm change nothing
+    create(&app, 2500, "gbp", Some("metrics_key")).await;
+    assert_eq!(confirm(&app, &paid, None).await, StatusCode::CONFLICT);
+
+    let declined = create(&app, 700, "EUR", None).await;
+    assert_eq!(
+        confirm(&app, &declined, Some("outcome=card_declined")).await,
+        StatusCode::PAYMENT_REQUIRED
+    );
+
+    assert_eq!(metrics.get(PAYMENTS_CREATED, &[("currency", "gbp")]), 1);
+    assert_eq!(metrics.get(PAYMENTS_CREATED, &[("currency", "eur")]), 1);
+    assert_eq!(metrics.get(PAYMENTS_SUCCEEDED, &[("currency", "gbp")]), 1);
+    assert_eq!(metrics.get(GROSS_VOLUME, &[("currency", "gbp")]), 2500);
+    assert_eq!(metrics.get(GROSS_VOLUME, &[("currency", "eur")]), 0);
+    assert_eq!(
+        metrics.get(
+            PAYMENTS_FAILED,
+            &[("currency", "eur"), ("status", "requires_payment_method")]
+        ),
+        1
+    );
+
+    let (status, body) = send(
+        &app,
+        Request::builder()
+            .uri(routes::METRICS)
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("payments_created_total{currency=\"gbp\"} 1\n"));
+    assert!(body.contains("payments_succeeded_total{currency=\"gbp\"} 1\n"));
+    assert!(body.contains("payments_gross_volume_minor_total{currency=\"gbp\"} 2500\n"));
+    assert!(body.contains(
+        "payments_failed_total{currency=\"eur\",status=\"requires_payment_method\"} 1\n"
+    ));
+    assert!(body.contains("in_flight_mutations 0\n"));
+    assert!(body.ends_with("# EOF\n"));
+    // Never labelled per intent
+    assert!(!body.contains(&paid) && !body.contains(&declined));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn injected_latency_is_observed_per_operation(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.simulated_latency = Some(SimulatedLatency::fixed(20));
+    let app = build_app(state);
+
+    let id = create(&app, 2500, "gbp", None).await;
+    assert_eq!(confirm(&app, &id, None).await, StatusCode::OK);
+
+    let (_, body) = send(
+        &app,
+        Request::builder()
+            .uri(routes::METRICS)
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert!(body.contains("# TYPE simulated_latency_seconds summary\n"));
+    assert!(body.contains("simulated_latency_seconds_count{operation=\"confirm\"} 1\n"));
+    assert!(body.contains("simulated_latency_seconds_sum{operation=\"confirm\"} 0.02\n"));
+    // A create without confirm never sleeps
+    assert!(!body.contains("operation=\"create\""));
+}
@@ -0,0 +1,129 @@
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+
+fn app(pool: PgPool, natural_key: Option<&str>) -> Router {
+    let mut state = AppState::new(pool);
+    state.config.natural_idempotency_key = natural_key.map(str::to_string);
+    build_app(state)
+}
+
+async fn create(app: &Router, body: Value) -> (StatusCode, Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::PAYMENT_INTENTS)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+fn order(order_id: &str, amount: i64) -> Value {
+    json!({ "amount": amount, "currency": "gbp", "metadata": { "order_id": order_id } })
+}
+
+async fn intent_count(pool: &PgPool) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM payment_intents"#)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_repeated_order_returns_the_existing_intent(pool: PgPool) {
+    let app = app(pool.clone(), Some("order_id"));
+
+    let (status, first) = create(&app, order("ord_1", 1000)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(first["metadata"]["order_id"], "ord_1");
+    assert!(first.get("deduplicated").is_none());
+
+    // Even with a different amount: the order already has its intent
+    let (status, second) = create(&app, order("ord_1", 2500)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(second["deduplicated"], true);
+    assert_eq!(second["id"], first["id"]);
+    assert_eq!(second["amount"], 1000);
+    assert_eq!(intent_count(&pool).await, 1);
+
+    let (status, other) = create(&app, order("ord_2", 1000)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_ne!(other["id"], first["id"]);
+
+    // Requests without the key are never deduplicated
+    for _ in 0..2 {
+        let (status, _) = create(&app, json!({ "amount": 1000, "currency": "gbp" })).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+    assert_eq!(intent_count(&pool).await, 4);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn concurrent_duplicates_resolve_to_one_intent(pool: PgPool) {
+    let app = app(pool.clone(), Some("order_id"));
+
+    let mut tasks = JoinSet::new();
+    for _ in 0..10 {
+        let app = app.clone();
+        tasks.spawn(async move { create(&app, order("ord_race", 1000)).await });
+    }
+    let results = tasks.join_all().await;
+
+    let created: Vec<_> = results
+        .iter()
+        .filter(|(status, _)| *status == StatusCode::CREATED)
+        .collect();
+    assert_eq!(created.len(), 1, "{results:?}");
+    let id = &created[0].1["id"];
+    for (status, body) in &results {
+        assert!(
+            *status == StatusCode::CREATED || body["deduplicated"] == true,
+            "{status} {body}"
+        );
+        assert_eq!(&body["id"], id);
+    }
+    assert_eq!(intent_count(&pool).await, 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_canceled_intent_frees_its_order(pool: PgPool) {
+    let app = app(pool.clone(), Some("order_id"));
+
+    let (_, first) = create(&app, order("ord_1", 1000)).await;
+    sqlx::query!("UPDATE payment_intents SET status = 'canceled'")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (status, second) = create(&app, order("ord_1", 1000)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_ne!(second["id"], first["id"]);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn duplicates_are_created_when_the_setting_is_off(pool: PgPool) {
+    let app = app(pool.clone(), None);
+
+    for _ in 0..2 {
+        let (status, body) = create(&app, order("ord_1", 1000)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(body.get("deduplicated").is_none());
+    }
+    assert_eq!(intent_count(&pool).await, 2);
+}
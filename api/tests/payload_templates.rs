@@ -0,0 +1,140 @@
+mod common;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::http::StatusCode;
+use common::send;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+
+// The legacy shape from the request that prompted templates
+fn legacy_template() -> Value {
+    json!({ "fields": {
+        "transaction": { "fields": {
+            "ref": { "path": "$.data.payment_intent.id" },
+            "state": { "path": "$.data.payment_intent.status" },
+            "value_minor": { "path": "$.data.payment_intent.amount" },
+            "order": { "path": "$.data.payment_intent.metadata.order_id" }
+        }},
+        "notification_type": { "path": "$.type" },
+        "version": { "const": 2 }
+    }})
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn templates_are_stored_on_create_and_replaced_by_patch(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let body =
+        json!({ "url": "https://legacy.example.com/hooks", "payload_template": legacy_template() });
+    let (status, created) = send(&app, "POST", routes::WEBHOOK_ENDPOINTS, body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["payload_template"], legacy_template());
+    let id = created["id"].as_str().unwrap();
+
+    let simpler = json!({ "fields": { "ref": { "path": "$.data.payment_intent.id" } } });
+    let (status, updated) = send(
+        &app,
+        "PATCH",
+        &routes::webhook_endpoint(id),
+        json!({ "payload_template": simpler }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["payload_template"], simpler);
+
+    let (status, cleared) = send(
+        &app,
+        "PATCH",
+        &routes::webhook_endpoint(id),
+        json!({ "payload_template": null }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cleared["payload_template"], Value::Null);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn templates_referencing_unknown_paths_are_rejected(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let unknown = json!({ "fields": {
+        "customer": { "path": "$.data.payment_intent.customer.email" }
+    }});
+    let body = json!({ "url": "https://legacy.example.com/hooks", "payload_template": unknown });
+    let (status, err) = send(&app, "POST", routes::WEBHOOK_ENDPOINTS, body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "payload_template_invalid");
+    let message = err["error"]["message"].as_str().unwrap();
+    assert!(
+        message.contains("template.customer") && message.contains("$.data.payment_intent.customer"),
+        "{message}"
+    );
+
+    // Nothing was created, and a PATCH can't store one either
+    let body = json!({ "url": "https://legacy.example.com/hooks" });
+    let (status, created) = send(&app, "POST", routes::WEBHOOK_ENDPOINTS, body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = created["id"].as_str().unwrap();
+    let (status, err) = send(
+        &app,
+        "PATCH",
+        &routes::webhook_endpoint(id),
+        json!({ "payload_template": { "fields": { "x": { "script": "return 1" } } } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "payload_template_invalid");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn preview_renders_a_sample_event_through_the_template(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let body =
+        json!({ "url": "https://legacy.example.com/hooks", "payload_template": legacy_template() });
+    let (_, created) = send(&app, "POST", routes::WEBHOOK_ENDPOINTS, body).await;
+    let preview = routes::webhook_endpoint_preview(created["id"].as_str().unwrap());
+
+    // The stored template
+    let (status, stored) = send(&app, "POST", &preview, json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stored["event_type"], "payment_intent.succeeded");
+    let event = &stored["event"];
+    assert_eq!(
+        stored["body"],
+        json!({
+            "transaction": {
+                "ref": event["data"]["payment_intent"]["id"],
+                "state": "succeeded",
+                "value_minor": event["data"]["payment_intent"]["amount"],
+                "order": event["data"]["payment_intent"]["metadata"]["order_id"]
+            },
+            "notification_type": "payment_intent.succeeded",
+            "version": 2
+        })
+    );
+
+    // A draft, for another event type, without saving it
+    let draft =
+        json!({ "fields": { "reason": { "path": "$.data.payment_intent.cancellation_reason" } } });
+    let (status, drafted) = send(
+        &app,
+        "POST",
+        &preview,
+        json!({ "event_type": "payment_intent.canceled", "payload_template": draft }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(drafted["body"], json!({ "reason": "abandoned" }));
+    let (_, endpoint) = send(&app, "POST", &preview, json!({})).await;
+    assert_eq!(endpoint["body"], stored["body"]);
+
+    let (status, err) = send(
+        &app,
+        "POST",
+        &preview,
+        json!({ "event_type": "webhook_endpoint.secret_revealed" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tokio::time::Instant;
+use tower::ServiceExt;
+
+async fn send(app: &Router, method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+async fn create_intent(app: &Router) -> String {
+    let body = json!({ "amount": 1000, "currency": "gbp" });
+    let (status, created) = send(app, "POST", routes::PAYMENT_INTENTS, Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    created["id"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn wait_returns_when_the_intent_is_confirmed(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let id = create_intent(&app).await;
+
+    let waiting = {
+        let (app, uri) = (app.clone(), routes::payment_intent_wait(&id));
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let response = send(&app, "GET", &format!("{uri}?timeout=30s"), None).await;
+            (response, started.elapsed())
+        })
+    };
+
+    // Confirm from another task once the wait is underway
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (status, _) = send(&app, "POST", &routes::payment_intent_confirm(&id), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let ((status, waited), elapsed) = waiting.await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(waited["id"], id.as_str());
+    assert_eq!(waited["status"], "succeeded");
+    assert_eq!(waited["timed_out"], false);
+    assert!(elapsed < Duration::from_secs(5), "took {elapsed:?}");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn wait_returns_at_once_when_already_in_a_target_state(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let id = create_intent(&app).await;
+
+    let uri = format!(
+        "{}?until=requires_confirmation,succeeded",
+        routes::payment_intent_wait(&id)
+    );
+    let started = Instant::now();
+    let (status, waited) = send(&app, "GET", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(waited["status"], "requires_confirmation");
+    assert_eq!(waited["timed_out"], false);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn wait_times_out_with_the_current_state(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let id = create_intent(&app).await;
+
+    // A real (short) timeout: pausing tokio's clock would also fire the pool's timeouts
+    // mid-query. status_changes.rs covers the full timeout on a paused clock.
+    let started = Instant::now();
+    let uri = format!("{}?timeout=1s", routes::payment_intent_wait(&id));
+    let (status, waited) = send(&app, "GET", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(waited["status"], "requires_confirmation");
+    assert_eq!(waited["timed_out"], true);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn waits_over_the_limit_are_refused(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.max_payment_intent_waiters = Some(0);
+    let app = build_app(state);
+    let id = create_intent(&app).await;
+
+    let (status, body) = send(&app, "GET", &routes::payment_intent_wait(&id), None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["error"]["code"], "too_many_waiters");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn wait_rejects_bad_parameters(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let id = create_intent(&app).await;
+    let uri = routes::payment_intent_wait(&id);
+
+    for query in ["until=settled", "timeout=soon", "timeout=5m"] {
+        let (status, body) = send(&app, "GET", &format!("{uri}?{query}"), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+        assert_eq!(body["error"]["code"], "parameter_invalid", "{query}");
+    }
+
+    let missing = routes::payment_intent_wait(uuid::Uuid::new_v4());
+    let (status, _) = send(&app, "GET", &format!("{missing}?until=succeeded"), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
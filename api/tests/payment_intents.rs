@@ -1,7 +1,8 @@
+use api::routes;
 use api::{app::build_app, state::AppState};
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    http::{HeaderValue, Request, StatusCode},
 };
 use http_body_util::BodyExt;
 use serde_json::json;
@@ -12,7 +13,7 @@ use uuid::Uuid;
 #[sqlx::test(migrations = "./migrations")]
 async fn create_then_get_payment_intent(pool: PgPool) {
     // Build the router with real DB pool
-    let app = build_app(AppState { db: pool });
+    let app = build_app(AppState::new(pool));
 
     // POST /v1/payment_intents
     let body = json!({ "amount": 1000, "currency": "gbp" }).to_string();
@@ -22,7 +23,7 @@ async fn create_then_get_payment_intent(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .body(Body::from(body))
                 .unwrap(),
@@ -41,7 +42,7 @@ async fn create_then_get_payment_intent(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("GET")
-                .uri(format!("/v1/payment_intents/{id}"))
+                .uri(routes::payment_intent(id))
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -61,7 +62,7 @@ async fn create_then_get_payment_intent(pool: PgPool) {
 
 #[sqlx::test(migrations = "./migrations")]
 async fn get_unknown_payment_intent_returns_404(pool: PgPool) {
-    let app = build_app(AppState { db: pool });
+    let app = build_app(AppState::new(pool));
 
     let random_id = Uuid::new_v4();
 
@@ -69,7 +70,7 @@ async fn get_unknown_payment_intent_returns_404(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("GET")
-                .uri(format!("/v1/payment_intents/{random_id}"))
+                .uri(routes::payment_intent(random_id))
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -81,7 +82,7 @@ async fn get_unknown_payment_intent_returns_404(pool: PgPool) {
 
 #[sqlx::test(migrations = "./migrations")]
 async fn idempotency_same_key_same_body_returns_same_intent(pool: PgPool) {
-    let app = build_app(AppState { db: pool });
+    let app = build_app(AppState::new(pool));
 
     let body = json!({ "amount": 2500, "currency": "gbp" }).to_string();
 
@@ -90,7 +91,7 @@ async fn idempotency_same_key_same_body_returns_same_intent(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .header("Idempotency-Key", "abc123")
                 .body(Body::from(body.clone()))
@@ -108,7 +109,7 @@ async fn idempotency_same_key_same_body_returns_same_intent(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .header("Idempotency-Key", "abc123")
                 .body(Body::from(body))
@@ -127,7 +128,7 @@ async fn idempotency_same_key_same_body_returns_same_intent(pool: PgPool) {
 
 #[sqlx::test(migrations = "./migrations")]
 async fn idempotency_same_key_different_body_returns_409(pool: PgPool) {
-    let app = build_app(AppState { db: pool });
+    let app = build_app(AppState::new(pool));
 
     let body1 = json!({ "amount": 2500, "currency": "gbp" }).to_string();
     let body2 = json!({ "amount": 9999, "currency": "gbp" }).to_string();
@@ -137,7 +138,7 @@ async fn idempotency_same_key_different_body_returns_409(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .header("Idempotency-Key", "conflict-key")
                 .body(Body::from(body1))
@@ -151,7 +152,7 @@ async fn idempotency_same_key_different_body_returns_409(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .header("Idempotency-Key", "conflict-key")
                 .body(Body::from(body2))
@@ -164,7 +165,7 @@ async fn idempotency_same_key_different_body_returns_409(pool: PgPool) {
 
 #[sqlx::test(migrations = "./migrations")]
 async fn idempotency_reconstructs_response_if_response_body_missing(pool: PgPool) {
-    let app = build_app(AppState { db: pool.clone() });
+    let app = build_app(AppState::new(pool.clone()));
 
     let pi_id = Uuid::new_v4();
     sqlx::query!(
@@ -205,7 +206,7 @@ async fn idempotency_reconstructs_response_if_response_body_missing(pool: PgPool
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .header("Idempotency-Key", "crash-window-key")
                 .body(Body::from(body))
@@ -225,9 +226,60 @@ async fn idempotency_reconstructs_response_if_response_body_missing(pool: PgPool
     assert_eq!(v["status"], "requires_confirmation");
 }
 
+#[sqlx::test(migrations = "./migrations")]
+async fn idempotency_replay_upgrades_old_format_response_body(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let create = || {
+        Request::builder()
+            .method("POST")
+            .uri(routes::PAYMENT_INTENTS)
+            .header("content-type", "application/json")
+            .header("Idempotency-Key", "old-format-key")
+            .body(Body::from(
+                json!({ "amount": 2500, "currency": "gbp" }).to_string(),
+            ))
+            .unwrap()
+    };
+
+    let res = app.clone().oneshot(create()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let fresh: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+    // Simulate a body stored before cancellation_reason existed
+    sqlx::query!(
+        r#"
+        UPDATE idempotency_keys
+        SET response_body = response_body - 'cancellation_reason', body_version = 1
+        WHERE key = $1
+        "#,
+        "old-format-key"
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let res = app.oneshot(create()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let replayed: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(replayed, fresh);
+
+    let stored = sqlx::query!(
+        "SELECT response_body, body_version FROM idempotency_keys WHERE key = $1",
+        "old-format-key"
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(stored.response_body, fresh);
+    assert_eq!(stored.body_version, 10);
+}
+
 #[sqlx::test(migrations = "./migrations")]
 async fn create_then_confirm_payment_intent_sets_succeeded(pool: PgPool) {
-    let app = build_app(AppState { db: pool });
+    let app = build_app(AppState::new(pool));
 
     let body = json!({ "amount": 1000, "currency": "gbp" }).to_string();
     let res = app
@@ -235,7 +287,7 @@ async fn create_then_confirm_payment_intent_sets_succeeded(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .body(Body::from(body))
                 .unwrap(),
@@ -253,7 +305,7 @@ async fn create_then_confirm_payment_intent_sets_succeeded(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri(format!("/v1/payment_intents/{id}/confirm"))
+                .uri(routes::payment_intent_confirm(&id))
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -273,7 +325,7 @@ async fn create_then_confirm_payment_intent_sets_succeeded(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("GET")
-                .uri(format!("/v1/payment_intents/{id}"))
+                .uri(routes::payment_intent(&id))
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -289,7 +341,7 @@ async fn create_then_confirm_payment_intent_sets_succeeded(pool: PgPool) {
 
 #[sqlx::test(migrations = "./migrations")]
 async fn confirming_twice_returns_409(pool: PgPool) {
-    let app = build_app(AppState { db: pool });
+    let app = build_app(AppState::new(pool));
 
     let body = json!({ "amount": 1500, "currency": "gbp" }).to_string();
     let res = app
@@ -297,7 +349,7 @@ async fn confirming_twice_returns_409(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .body(Body::from(body))
                 .unwrap(),
@@ -315,7 +367,7 @@ async fn confirming_twice_returns_409(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri(format!("/v1/payment_intents/{id}/confirm"))
+                .uri(routes::payment_intent_confirm(&id))
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -328,7 +380,7 @@ async fn confirming_twice_returns_409(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri(format!("/v1/payment_intents/{id}/confirm"))
+                .uri(routes::payment_intent_confirm(&id))
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -339,7 +391,7 @@ async fn confirming_twice_returns_409(pool: PgPool) {
 
 #[sqlx::test(migrations = "./migrations")]
 async fn confirm_unknown_payment_intent_returns_404(pool: PgPool) {
-    let app = build_app(AppState { db: pool });
+    let app = build_app(AppState::new(pool));
 
     let random_id = Uuid::new_v4();
 
@@ -347,7 +399,7 @@ async fn confirm_unknown_payment_intent_returns_404(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri(format!("/v1/payment_intents/{random_id}/confirm"))
+                .uri(routes::payment_intent_confirm(random_id))
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -359,7 +411,7 @@ async fn confirm_unknown_payment_intent_returns_404(pool: PgPool) {
 
 #[sqlx::test(migrations = "./migrations")]
 async fn create_payment_intent_writes_created_outbox_event(pool: PgPool) {
-    let app = build_app(AppState { db: pool.clone() });
+    let app = build_app(AppState::new(pool.clone()));
 
     // Create payment intent via API
     let body = json!({ "amount": 2000, "currency": "gbp" }).to_string();
@@ -368,7 +420,7 @@ async fn create_payment_intent_writes_created_outbox_event(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .body(Body::from(body))
                 .unwrap(),
@@ -407,7 +459,7 @@ async fn create_payment_intent_writes_created_outbox_event(pool: PgPool) {
 
 #[sqlx::test(migrations = "./migrations")]
 async fn confirm_payment_intent_writes_succeeded_outbox_event(pool: PgPool) {
-    let app = build_app(AppState { db: pool.clone() });
+    let app = build_app(AppState::new(pool.clone()));
 
     // Create via API
     let body = json!({ "amount": 3000, "currency": "gbp" }).to_string();
@@ -417,7 +469,7 @@ async fn confirm_payment_intent_writes_succeeded_outbox_event(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/payment_intents")
+                .uri(routes::PAYMENT_INTENTS)
                 .header("content-type", "application/json")
                 .body(Body::from(body))
                 .unwrap(),
@@ -436,7 +488,7 @@ async fn confirm_payment_intent_writes_succeeded_outbox_event(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri(format!("/v1/payment_intents/{pi_id}/confirm"))
+                .uri(routes::payment_intent_confirm(&pi_id))
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -472,3 +524,1839 @@ async fn confirm_payment_intent_writes_succeeded_outbox_event(pool: PgPool) {
         "succeeded"
     );
 }
+
+#[sqlx::test(migrations = "./migrations")]
+async fn simulated_latency_header_rejected_outside_sandbox(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let random_id = Uuid::new_v4();
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(random_id))
+                .header("X-Simulated-Latency-Ms", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+async fn insert_intent_aged(pool: &PgPool, age_secs: f64) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO payment_intents (id, amount, currency, status, created_at)
+        VALUES ($1, 1000, 'gbp', 'requires_confirmation', now() - make_interval(secs => $2))
+        "#,
+        id,
+        age_secs
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+    id
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn confirm_refuses_intents_older_than_max_age(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.max_confirmable_age = Some(std::time::Duration::from_secs(3600));
+    let app = build_app(state);
+
+    let fresh = insert_intent_aged(&pool, 3590.0).await;
+    let stale = insert_intent_aged(&pool, 3610.0).await;
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(fresh))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(stale))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let err: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(
+        err["error"]["code"],
+        "payment_intent_expired_for_confirmation"
+    );
+
+    // The cancel was committed alongside the refusal
+    let res = app
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri(routes::payment_intent(stale))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let fetched: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(fetched["status"], "canceled");
+    assert_eq!(fetched["cancellation_reason"], "abandoned");
+
+    let event_type = sqlx::query_scalar!(
+        r#"
+        SELECT event_type
+        FROM events_outbox
+        WHERE payload->'payment_intent'->>'id' = $1
+        "#,
+        stale.to_string()
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(event_type, "payment_intent.canceled");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn database_rejects_money_changes_on_final_intents(pool: PgPool) {
+    let open = insert_intent_aged(&pool, 0.0).await;
+    let done = insert_intent_aged(&pool, 0.0).await;
+    sqlx::query!(
+        "UPDATE payment_intents SET status = 'succeeded' WHERE id = $1",
+        done
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    // Still editable before confirmation
+    sqlx::query!(
+        "UPDATE payment_intents SET amount = 2000 WHERE id = $1",
+        open
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let err = sqlx::query!(
+        "UPDATE payment_intents SET amount = 2000 WHERE id = $1",
+        done
+    )
+    .execute(&pool)
+    .await
+    .unwrap_err();
+    let api_err = api::error::ApiError::from(err);
+    assert_eq!(api_err.status, StatusCode::CONFLICT);
+    assert_eq!(api_err.code, "payment_intent_immutable");
+
+    let err = sqlx::query!(
+        "UPDATE payment_intents SET currency = 'usd' WHERE id = $1",
+        done
+    )
+    .execute(&pool)
+    .await
+    .unwrap_err();
+    assert_eq!(
+        api::error::ApiError::from(err).code,
+        "payment_intent_immutable"
+    );
+
+    // Status-only updates are still allowed
+    sqlx::query!(
+        "UPDATE payment_intents SET status = 'canceled' WHERE id = $1",
+        done
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+}
+
+async fn get_json(app: &axum::Router, uri: &str) -> serde_json::Value {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn get_payment_intent_includes_latest_event(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::PAYMENT_INTENTS)
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({ "amount": 1000, "currency": "gbp" }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let created: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let id = created["id"].as_str().unwrap();
+
+    let fetched = get_json(&app, &routes::payment_intent(id)).await;
+    assert_eq!(fetched["latest_event"]["type"], "payment_intent.created");
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let fetched = get_json(&app, &routes::payment_intent(id)).await;
+    assert_eq!(fetched["latest_event"]["type"], "payment_intent.succeeded");
+    assert!(fetched["latest_event"]["created_at"].is_string());
+
+    // Summaries can be switched off
+    let mut state = AppState::new(pool);
+    state.config.disable_payment_intent_summaries = true;
+    let fetched = get_json(&build_app(state), &routes::payment_intent(id)).await;
+    assert!(fetched["latest_event"].is_null());
+    assert_eq!(fetched["status"], "succeeded");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn get_bare_payment_intent_has_null_summaries(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let id = insert_intent_aged(&pool, 0.0).await;
+
+    let fetched = get_json(&app, &routes::payment_intent(id)).await;
+    assert_eq!(fetched["status"], "requires_confirmation");
+    assert!(fetched["latest_event"].is_null());
+}
+
+async fn create_with(
+    app: &axum::Router,
+    key: Option<&str>,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(routes::PAYMENT_INTENTS)
+        .header("content-type", "application/json");
+    if let Some(key) = key {
+        builder = builder.header("Idempotency-Key", key);
+    }
+
+    let res = app
+        .clone()
+        .oneshot(builder.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn default_currency_and_allowlist_apply_to_create(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.default_currency = Some("gbp".to_string());
+    state.config.allowed_currencies = Some(vec!["gbp".to_string(), "eur".to_string()]);
+    let app = build_app(state);
+
+    let (status, created) = create_with(&app, None, json!({ "amount": 1000 })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["currency"], "gbp");
+
+    let (status, err) = create_with(&app, None, json!({ "amount": 1000, "currency": "usd" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "currency_not_allowed");
+    assert_eq!(err["error"]["message"], "currency must be one of: gbp, eur");
+
+    // Omitting the currency and sending the default are the same idempotent request
+    let (status, first) = create_with(&app, Some("default-ccy"), json!({ "amount": 500 })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, second) = create_with(
+        &app,
+        Some("default-ccy"),
+        json!({ "amount": 500, "currency": "GBP" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(second, first);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn amounts_must_fit_the_currency_limits(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.minimum_amounts = Some([("gbp".to_string(), 30)].into());
+    state.config.maximum_amount = Some(1_000_000);
+    let app = build_app(state);
+
+    let (status, err) = create_with(&app, None, json!({ "amount": 29, "currency": "gbp" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "amount_too_small");
+    assert_eq!(
+        err["error"]["message"],
+        "amount must be at least 30 gbp (0.30 gbp), the minimum for that currency"
+    );
+    let (status, _) = create_with(&app, None, json!({ "amount": 30, "currency": "gbp" })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    // No minimum configured for jpy
+    let (status, _) = create_with(&app, None, json!({ "amount": 1, "currency": "jpy" })).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, err) = create_with(
+        &app,
+        None,
+        json!({ "amount": 1_000_001, "currency": "jpy" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "amount_too_large");
+    assert_eq!(
+        err["error"]["message"],
+        "amount must be at most 1000000 jpy (1000000 jpy), the maximum for any currency"
+    );
+
+    // An update is held to the same limits
+    let (_, created) = create_with(&app, None, json!({ "amount": 500, "currency": "gbp" })).await;
+    let uri = routes::payment_intent(created["id"].as_str().unwrap());
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("PATCH")
+                .uri(&uri)
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "amount": 10 }).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let err: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(err["error"]["code"], "amount_too_small");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn currency_is_required_without_a_default(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (status, err) = create_with(&app, None, json!({ "amount": 1000 })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["message"], "currency is required");
+}
+
+async fn confirm(app: &axum::Router, id: &str) -> StatusCode {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+fn transition_pairs(transitions: &serde_json::Value) -> Vec<(Option<&str>, &str, &str)> {
+    transitions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| {
+            (
+                t["from_status"].as_str(),
+                t["to_status"].as_str().unwrap(),
+                t["cause"].as_str().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn transitions_record_every_status_change(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let (_, created) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    let confirmed_id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(confirm(&app, &confirmed_id).await, StatusCode::OK);
+
+    let transitions = get_json(&app, &routes::payment_intent_transitions(&confirmed_id)).await;
+    assert_eq!(
+        transition_pairs(&transitions),
+        vec![
+            (None, "requires_confirmation", "api"),
+            (Some("requires_confirmation"), "succeeded", "api"),
+        ]
+    );
+
+    // A confirm refused for age cancels the intent instead
+    let mut state = AppState::new(pool.clone());
+    state.config.max_confirmable_age = Some(std::time::Duration::ZERO);
+    let strict = build_app(state);
+    let (_, created) = create_with(
+        &strict,
+        Some("abandon-me"),
+        json!({ "amount": 500, "currency": "gbp" }),
+    )
+    .await;
+    let canceled_id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(confirm(&strict, &canceled_id).await, StatusCode::CONFLICT);
+
+    let transitions = get_json(&app, &routes::payment_intent_transitions(&canceled_id)).await;
+    assert_eq!(
+        transition_pairs(&transitions),
+        vec![
+            (None, "requires_confirmation", "api"),
+            (
+                Some("requires_confirmation"),
+                "canceled",
+                "max_confirmable_age"
+            ),
+        ]
+    );
+
+    // Refused confirms change nothing, so they record nothing
+    assert_eq!(confirm(&app, &confirmed_id).await, StatusCode::CONFLICT);
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_intent_transitions")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 4);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri(routes::payment_intent_transitions(Uuid::new_v4()))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn transitions_cannot_be_rewritten(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+
+    let err = sqlx::query("UPDATE payment_intent_transitions SET to_status = 'succeeded'")
+        .execute(&pool)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("append-only"), "{err}");
+
+    let err = sqlx::query("DELETE FROM payment_intent_transitions")
+        .execute(&pool)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("append-only"), "{err}");
+}
+
+async fn confirm_simulating(
+    app: &axum::Router,
+    id: &str,
+    simulate: &str,
+) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(id))
+                .header("X-Simulate", simulate)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+async fn confirm_paying(
+    app: &axum::Router,
+    id: &str,
+    payment_method: &str,
+) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(id))
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({ "payment_method": payment_method }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+async fn event_types_for(pool: &PgPool, id: &str) -> Vec<(String, Option<String>)> {
+    sqlx::query!(
+        r#"
+        SELECT event_type, payload->>'simulated_outcome' AS simulated_outcome
+        FROM events_outbox
+        WHERE payload->'payment_intent'->>'id' = $1
+        ORDER BY created_at, sequence
+        "#,
+        id
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|row| (row.event_type, row.simulated_outcome))
+    .collect()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn payment_method_decides_the_confirm_outcome(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let body = || json!({ "amount": 1000, "currency": "gbp" });
+
+    let (_, created) = create_with(&app, None, body()).await;
+    let paid = created["id"].as_str().unwrap().to_string();
+    let (status, confirmed) = confirm_paying(&app, &paid, "pm_card_visa").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(confirmed["status"], "succeeded");
+    assert_eq!(confirmed["payment_method"], "pm_card_visa");
+
+    // A decline: 402, requires_payment_method, and the reason on the intent and its event
+    let (_, created) = create_with(&app, None, body()).await;
+    let declined = created["id"].as_str().unwrap().to_string();
+    let (status, err) = confirm_paying(&app, &declined, "pm_card_insufficient_funds").await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(err["error"]["code"], "card_declined");
+
+    let fetched = get_json(&app, &routes::payment_intent(&declined)).await;
+    assert_eq!(fetched["status"], "requires_payment_method");
+    assert_eq!(fetched["payment_method"], "pm_card_insufficient_funds");
+    assert_eq!(
+        fetched["last_payment_error"],
+        json!({
+            "code": "card_declined",
+            "decline_code": "insufficient_funds",
+            "message": "Your card has insufficient funds.",
+            "payment_method": "pm_card_insufficient_funds",
+        })
+    );
+    assert_eq!(
+        event_types_for(&pool, &declined).await,
+        vec![
+            ("payment_intent.created".to_string(), None),
+            ("payment_intent.payment_failed".to_string(), None),
+        ]
+    );
+
+    let (_, created) = create_with(&app, None, body()).await;
+    let action = created["id"].as_str().unwrap().to_string();
+    let (status, confirmed) =
+        confirm_paying(&app, &action, "pm_card_authentication_required").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(confirmed["status"], "requires_action");
+    assert_eq!(
+        event_types_for(&pool, &action).await.last().unwrap().0,
+        "payment_intent.requires_action"
+    );
+
+    // An unknown payment method changes nothing
+    let (_, created) = create_with(&app, None, body()).await;
+    let unknown = created["id"].as_str().unwrap().to_string();
+    let (status, err) = confirm_paying(&app, &unknown, "pm_card_bogus").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+    let fetched = get_json(&app, &routes::payment_intent(&unknown)).await;
+    assert_eq!(fetched["status"], "requires_confirmation");
+    assert!(fetched["payment_method"].is_null());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_declined_intent_can_be_confirmed_with_another_card(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let (_, created) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    let id = created["id"].as_str().unwrap().to_string();
+
+    let (status, _) = confirm_paying(&app, &id, "pm_card_declined").await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    let (status, _) = confirm_paying(&app, &id, "pm_card_lost").await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+
+    // Without a new payment method there is nothing to try again
+    assert_eq!(confirm(&app, &id).await, StatusCode::CONFLICT);
+
+    let (status, confirmed) = confirm_paying(&app, &id, "pm_card_visa").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(confirmed["status"], "succeeded");
+    assert_eq!(confirmed["payment_method"], "pm_card_visa");
+    assert!(confirmed["last_payment_error"].is_null());
+
+    // The second decline left the status as it was, so it has an event but no transition
+    let transitions = get_json(&app, &routes::payment_intent_transitions(&id)).await;
+    assert_eq!(
+        transition_pairs(&transitions),
+        vec![
+            (None, "requires_confirmation", "api"),
+            (
+                Some("requires_confirmation"),
+                "requires_payment_method",
+                "api"
+            ),
+            (Some("requires_payment_method"), "succeeded", "api"),
+        ]
+    );
+    let events: Vec<_> = event_types_for(&pool, &id)
+        .await
+        .into_iter()
+        .map(|(event_type, _)| event_type)
+        .collect();
+    assert_eq!(
+        events,
+        [
+            "payment_intent.created",
+            "payment_intent.payment_failed",
+            "payment_intent.payment_failed",
+            "payment_intent.succeeded",
+        ]
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn magic_amounts_fail_confirms_in_sandbox(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.sandbox_mode = true;
+    let app = build_app(state);
+
+    // No payment method needed: the amount alone fails it
+    let (_, created) = create_with(&app, None, json!({ "amount": 4090, "currency": "gbp" })).await;
+    let id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(confirm(&app, &id).await, StatusCode::PAYMENT_REQUIRED);
+    let fetched = get_json(&app, &routes::payment_intent(&id)).await;
+    assert_eq!(fetched["status"], "requires_payment_method");
+    assert_eq!(
+        fetched["last_payment_error"],
+        json!({
+            "code": "card_declined",
+            "decline_code": "insufficient_funds",
+            "message": "Your card has insufficient funds.",
+            "payment_method": null,
+        })
+    );
+
+    // Errors other than declines keep their own code, and have no decline_code
+    let (_, created) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    let id = created["id"].as_str().unwrap().to_string();
+    let (status, err) = confirm_paying(&app, &id, "pm_card_expired").await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(err["error"]["code"], "expired_card");
+    let fetched = get_json(&app, &routes::payment_intent(&id)).await;
+    assert_eq!(fetched["last_payment_error"]["code"], "expired_card");
+    assert!(fetched["last_payment_error"].get("decline_code").is_none());
+
+    // Create-and-confirm goes through the processor too: a 201 with the failed state
+    let body = json!({ "amount": 4020, "currency": "gbp", "confirm": true });
+    let (status, created) = create_with(&app, None, body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["status"], "requires_payment_method");
+    assert_eq!(
+        created["last_payment_error"]["decline_code"],
+        "generic_decline"
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn magic_amounts_are_ordinary_outside_sandbox(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let (_, created) = create_with(&app, None, json!({ "amount": 4020, "currency": "gbp" })).await;
+    let id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(confirm(&app, &id).await, StatusCode::OK);
+    let fetched = get_json(&app, &routes::payment_intent(&id)).await;
+    assert_eq!(fetched["status"], "succeeded");
+    assert!(fetched["last_payment_error"].is_null());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn simulate_header_forces_confirm_outcomes(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.sandbox_mode = true;
+    let app = build_app(state);
+    let body = || json!({ "amount": 1000, "currency": "gbp" });
+
+    // card_declined: 402, requires_payment_method, payment_failed event
+    let (_, created) = create_with(&app, None, body()).await;
+    let declined = created["id"].as_str().unwrap().to_string();
+    let (status, err) = confirm_simulating(&app, &declined, "outcome=card_declined").await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(err["error"]["code"], "card_declined");
+
+    let fetched = get_json(&app, &routes::payment_intent(&declined)).await;
+    assert_eq!(fetched["status"], "requires_payment_method");
+    assert_eq!(fetched["last_payment_error"]["code"], "card_declined");
+    assert_eq!(fetched["last_payment_error"]["simulated"], true);
+    assert_eq!(
+        event_types_for(&pool, &declined).await,
+        vec![
+            ("payment_intent.created".to_string(), None),
+            (
+                "payment_intent.payment_failed".to_string(),
+                Some("card_declined".to_string())
+            ),
+        ]
+    );
+
+    // timeout: 504, nothing moves, a normal confirm afterwards succeeds and clears the error
+    let (_, created) = create_with(&app, None, body()).await;
+    let timed_out = created["id"].as_str().unwrap().to_string();
+    let (status, err) = confirm_simulating(&app, &timed_out, "outcome=timeout").await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(err["error"]["code"], "acquirer_timeout");
+
+    let fetched = get_json(&app, &routes::payment_intent(&timed_out)).await;
+    assert_eq!(fetched["status"], "requires_confirmation");
+    assert_eq!(fetched["last_payment_error"]["code"], "processing_timeout");
+
+    assert_eq!(confirm(&app, &timed_out).await, StatusCode::OK);
+    let fetched = get_json(&app, &routes::payment_intent(&timed_out)).await;
+    assert_eq!(fetched["status"], "succeeded");
+    assert!(fetched["last_payment_error"].is_null());
+
+    // requires_action: 200 with the intent waiting on the customer
+    let (_, created) = create_with(&app, None, body()).await;
+    let action = created["id"].as_str().unwrap().to_string();
+    let (status, confirmed) = confirm_simulating(&app, &action, "outcome=requires_action").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(confirmed["status"], "requires_action");
+    assert_eq!(
+        event_types_for(&pool, &action).await.last().unwrap(),
+        &(
+            "payment_intent.requires_action".to_string(),
+            Some("requires_action".to_string())
+        )
+    );
+
+    // Every forced outcome is in the audit log
+    let audited: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT details->>'outcome'
+        FROM audit_log
+        WHERE action = 'payment_intent.simulated_outcome'
+        ORDER BY created_at
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(audited, vec!["card_declined", "timeout", "requires_action"]);
+
+    let (status, err) = confirm_simulating(&app, &action, "outcome=explode").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn simulate_header_rejected_outside_sandbox(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (_, created) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    let id = created["id"].as_str().unwrap();
+    let (status, err) = confirm_simulating(&app, id, "outcome=card_declined").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "sandbox_only");
+
+    let fetched = get_json(&app, &routes::payment_intent(id)).await;
+    assert_eq!(fetched["status"], "requires_confirmation");
+}
+
+async fn create_confirming(
+    app: &axum::Router,
+    key: &str,
+    simulate: Option<&str>,
+) -> (StatusCode, serde_json::Value) {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(routes::PAYMENT_INTENTS)
+        .header("content-type", "application/json")
+        .header("Idempotency-Key", key);
+    if let Some(simulate) = simulate {
+        builder = builder.header("X-Simulate", simulate);
+    }
+    let body = json!({ "amount": 1000, "currency": "gbp", "confirm": true });
+
+    let res = app
+        .clone()
+        .oneshot(builder.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn create_with_confirm_succeeds_in_one_step(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let (status, created) = create_confirming(&app, "one-step", None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["status"], "succeeded");
+    let id = created["id"].as_str().unwrap().to_string();
+
+    assert_eq!(
+        event_types_for(&pool, &id).await,
+        vec![
+            ("payment_intent.created".to_string(), None),
+            ("payment_intent.succeeded".to_string(), None),
+        ]
+    );
+    let transitions = get_json(&app, &routes::payment_intent_transitions(&id)).await;
+    assert_eq!(
+        transition_pairs(&transitions),
+        vec![
+            (None, "requires_confirmation", "api"),
+            (Some("requires_confirmation"), "succeeded", "api"),
+        ]
+    );
+
+    // The key covers the combined operation: a retry replays it, a plain create with it conflicts
+    let (status, replayed) = create_confirming(&app, "one-step", None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(replayed, created);
+    assert_eq!(event_types_for(&pool, &id).await.len(), 2);
+
+    let (status, _) = create_with(
+        &app,
+        Some("one-step"),
+        json!({ "amount": 1000, "currency": "gbp" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn create_with_confirm_fingerprints_the_payment_method(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let body = |payment_method: &str| {
+        json!({
+            "amount": 1000,
+            "currency": "gbp",
+            "confirm": true,
+            "payment_method": payment_method,
+        })
+    };
+
+    let (status, created) = create_with(&app, Some("pm-key"), body("pm_card_visa")).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["status"], "succeeded");
+
+    // Another card under the same key is another payment, not a retry of the first
+    let (status, err) = create_with(&app, Some("pm-key"), body("pm_card_declined")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "idempotency_key_reused");
+
+    let (status, replayed) = create_with(&app, Some("pm-key"), body("pm_card_visa")).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(replayed, created);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn replays_come_from_the_key_without_lookups(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let customer: Uuid =
+        sqlx::query_scalar("INSERT INTO customers (id) VALUES (gen_random_uuid()) RETURNING id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    let body = json!({ "amount": 1000, "currency": "gbp", "confirm": true, "customer": customer });
+
+    let (status, created) = create_with(&app, Some("lookup-key"), body.clone()).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // The customer is gone, but a retry is still answered with what the create returned
+    sqlx::query("UPDATE customers SET deleted_at = now()")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (status, replayed) = create_with(&app, Some("lookup-key"), body.clone()).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(replayed, created);
+
+    let (status, err) = create_with(&app, Some("fresh-key"), body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        err["error"]["message"],
+        format!("no such customer: {customer}")
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn create_with_confirm_applies_simulated_decline(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.sandbox_mode = true;
+    let app = build_app(state);
+
+    // Created but failed: still a 201, with the final state in the body
+    let (status, created) =
+        create_confirming(&app, "declined", Some("outcome=card_declined")).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["status"], "requires_payment_method");
+    assert_eq!(created["last_payment_error"]["code"], "card_declined");
+    let id = created["id"].as_str().unwrap().to_string();
+
+    assert_eq!(
+        event_types_for(&pool, &id).await,
+        vec![
+            ("payment_intent.created".to_string(), None),
+            (
+                "payment_intent.payment_failed".to_string(),
+                Some("card_declined".to_string())
+            ),
+        ]
+    );
+
+    // A different simulated outcome is a different request for the same key
+    let (status, _) = create_confirming(&app, "declined", None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Outside the sandbox X-Simulate is refused before anything is created
+    let app = build_app(AppState::new(pool.clone()));
+    let (status, err) = create_confirming(&app, "not-sandbox", Some("outcome=card_declined")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "sandbox_only");
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_intents")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn malformed_inputs_are_400s_not_500s(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let huge = "x".repeat(1024 * 1024);
+    for currency in [huge.as_str(), "g\u{0}p", "gb€", "gbpp"] {
+        let (status, err) =
+            create_with(&app, None, json!({ "amount": 1000, "currency": currency })).await;
+        assert_eq!(
+            status,
+            StatusCode::BAD_REQUEST,
+            "{:?}",
+            &currency[..currency.len().min(8)]
+        );
+        assert_eq!(err["error"]["code"], "parameter_invalid");
+    }
+
+    // Unpaired surrogates never reach us: the JSON extractor refuses them
+    for raw in [
+        r#"{"amount": 1000, "currency": "\ud800bp"}"#,
+        r#"{"amount": 1000, "currency": "gb\udc00"}"#,
+    ] {
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(routes::PAYMENT_INTENTS)
+                    .header("content-type", "application/json")
+                    .body(Body::from(raw))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{raw}");
+    }
+
+    let long_key = "k".repeat(256);
+    let (status, err) = create_with(
+        &app,
+        Some(&long_key),
+        json!({ "amount": 1000, "currency": "gbp" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        err["error"]["message"],
+        "Idempotency-Key must be 1 to 255 bytes"
+    );
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_intents")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+
+    // The database refuses what validation would have caught
+    let err = sqlx::query(
+        "INSERT INTO payment_intents (id, amount, currency, status) VALUES ($1, 1, 'gbpp', 'requires_confirmation')",
+    )
+    .bind(Uuid::new_v4())
+    .execute(&pool)
+    .await
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("payment_intents_currency_format"),
+        "{err}"
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn unreadable_idempotency_key_is_rejected_not_ignored(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let body = json!({ "amount": 1000, "currency": "gbp" }).to_string();
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::PAYMENT_INTENTS)
+                .header("content-type", "application/json")
+                .header(
+                    "Idempotency-Key",
+                    HeaderValue::from_bytes(b"caf\xe9-\xff").unwrap(),
+                )
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let err: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(err["error"]["code"], "idempotency_key_invalid_encoding");
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_intents")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+
+    // Without the header a create is still a plain, non-idempotent one
+    let (status, _) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let keys: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM idempotency_keys")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(keys, 0);
+}
+
+async fn archive(app: &axum::Router, id: &str) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_archive(id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+fn listed_ids(list: &serde_json::Value) -> Vec<&str> {
+    list["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|pi| pi["id"].as_str().unwrap())
+        .collect()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn archived_intents_leave_the_list_but_stay_retrievable(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.sandbox_mode = true;
+    let app = build_app(state);
+
+    let (_, done) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    let done = done["id"].as_str().unwrap().to_string();
+    assert_eq!(confirm(&app, &done).await, StatusCode::OK);
+    let (_, pending) = create_with(&app, None, json!({ "amount": 500, "currency": "gbp" })).await;
+    let pending = pending["id"].as_str().unwrap().to_string();
+
+    let (status, archived) = archive(&app, &done).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(archived["archived"], true);
+    assert_eq!(archived["status"], "succeeded");
+
+    // Archiving again is a no-op
+    let (status, _) = archive(&app, &done).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, err) = archive(&app, &pending).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "payment_intent_unexpected_state");
+    assert_eq!(
+        err["error"]["message"],
+        "cannot archive payment_intent in status 'requires_confirmation'"
+    );
+
+    let (status, _) = archive(&app, &Uuid::new_v4().to_string()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let list = get_json(&app, routes::PAYMENT_INTENTS).await;
+    assert_eq!(listed_ids(&list), vec![pending.as_str()]);
+    assert_eq!(list["data"][0]["archived"], false);
+
+    let list = get_json(
+        &app,
+        &format!("{}?include_archived=true", routes::PAYMENT_INTENTS),
+    )
+    .await;
+    assert_eq!(listed_ids(&list), vec![pending.as_str(), done.as_str()]);
+    assert_eq!(list["data"][1]["archived"], true);
+
+    let fetched = get_json(&app, &routes::payment_intent(&done)).await;
+    assert_eq!(fetched["archived"], true);
+    assert_eq!(fetched["status"], "succeeded");
+
+    // Not a status change: no transition or event for the archive. Two created events, and the
+    // confirm's charge.succeeded and payment_intent.succeeded.
+    let events: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM events_outbox")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(events, 4);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn archive_is_sandbox_only(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (_, created) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    let id = created["id"].as_str().unwrap();
+    assert_eq!(confirm(&app, id).await, StatusCode::OK);
+
+    let (status, _) = archive(&app, id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+async fn raw(app: &axum::Router, method: &str, uri: &str) -> axum::response::Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn head_matches_get_without_a_body(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let (_, created) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    let uri = routes::payment_intent(created["id"].as_str().unwrap());
+
+    let get = raw(&app, "GET", &uri).await;
+    assert_eq!(get.status(), StatusCode::OK);
+    let etag = get.headers()["etag"].clone();
+    let content_length = get.headers()["content-length"].clone();
+    let body = get.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(content_length.to_str().unwrap(), body.len().to_string());
+
+    let head = raw(&app, "HEAD", &uri).await;
+    assert_eq!(head.status(), StatusCode::OK);
+    assert_eq!(head.headers()["etag"], etag);
+    assert_eq!(head.headers()["content-length"], content_length);
+    assert!(
+        head.into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .is_empty()
+    );
+
+    // The ETag follows the representation
+    assert_eq!(
+        confirm(&app, created["id"].as_str().unwrap()).await,
+        StatusCode::OK
+    );
+    let after = raw(&app, "HEAD", &uri).await;
+    assert_ne!(after.headers()["etag"], etag);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn options_lists_allowed_methods(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let res = raw(&app, "OPTIONS", &routes::payment_intent(Uuid::new_v4())).await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers()["allow"], "GET,HEAD,PATCH,OPTIONS");
+
+    let res = raw(&app, "OPTIONS", routes::PAYMENT_INTENTS).await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let allow = res.headers()["allow"].to_str().unwrap();
+    for method in ["GET", "HEAD", "POST", "OPTIONS"] {
+        assert!(allow.split(',').any(|m| m == method), "{allow}");
+    }
+
+    // Methods a route really lacks are still refused
+    let res = raw(&app, "DELETE", routes::PAYMENT_INTENTS).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn unknown_paths_are_json_404s(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    for method in ["GET", "HEAD", "OPTIONS", "POST"] {
+        let res = raw(&app, method, "/v1/nope").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND, "{method}");
+    }
+
+    let res = raw(&app, "GET", "/v1/nope").await;
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let err: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(err["error"]["code"], "resource_missing");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn pagination_is_stable_across_concurrent_inserts(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let mut existing = Vec::new();
+    for amount in 101..=107 {
+        let (_, created) =
+            create_with(&app, None, json!({ "amount": amount, "currency": "gbp" })).await;
+        existing.push(created["id"].as_str().unwrap().to_string());
+    }
+    // Same created_at for several rows: only the id breaks the tie
+    sqlx::query(
+        "UPDATE payment_intents SET created_at = '2026-01-01T00:00:00.123456Z' WHERE amount <= 104",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let mut seen = Vec::new();
+    let mut uri = format!("{}?limit=2", routes::PAYMENT_INTENTS);
+    loop {
+        let page = get_json(&app, &uri).await;
+        seen.extend(listed_ids(&page).into_iter().map(str::to_string));
+
+        // New intents land between page fetches
+        create_with(&app, None, json!({ "amount": 999, "currency": "gbp" })).await;
+
+        if page["has_more"] == false {
+            assert!(page["next_cursor"].is_null());
+            break;
+        }
+        let cursor = page["next_cursor"].as_str().unwrap();
+        uri = format!(
+            "{}?limit=2&starting_after={cursor}",
+            routes::PAYMENT_INTENTS
+        );
+    }
+
+    // Rows created while paging sort before the cursor, so they never show up; every
+    // pre-existing row shows up exactly once
+    let mut sorted = seen.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted.len(), seen.len(), "{seen:?}");
+    existing.sort();
+    assert_eq!(sorted, existing);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn ending_before_pages_back_towards_newer_intents(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    for amount in 101..=105 {
+        create_with(&app, None, json!({ "amount": amount, "currency": "gbp" })).await;
+    }
+    let everything = get_json(&app, routes::PAYMENT_INTENTS).await;
+    assert_eq!(everything["object"], "list");
+    assert!(everything["prev_cursor"].is_null());
+    let all = listed_ids(&everything);
+    assert_eq!(all.len(), 5);
+
+    // Forwards to the last page, then back again
+    let first = get_json(&app, &format!("{}?limit=2", routes::PAYMENT_INTENTS)).await;
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let second = get_json(
+        &app,
+        &format!(
+            "{}?limit=2&starting_after={cursor}",
+            routes::PAYMENT_INTENTS
+        ),
+    )
+    .await;
+    assert_eq!(listed_ids(&second), &all[2..4]);
+    let cursor = second["next_cursor"].as_str().unwrap();
+    let last = get_json(
+        &app,
+        &format!(
+            "{}?limit=2&starting_after={cursor}",
+            routes::PAYMENT_INTENTS
+        ),
+    )
+    .await;
+    assert_eq!(listed_ids(&last), &all[4..]);
+    assert_eq!(last["has_more"], false);
+
+    let cursor = last["prev_cursor"].as_str().unwrap();
+    let back = get_json(
+        &app,
+        &format!("{}?limit=2&ending_before={cursor}", routes::PAYMENT_INTENTS),
+    )
+    .await;
+    assert_eq!(listed_ids(&back), &all[2..4]);
+    assert_eq!(back["has_more"], true);
+    let cursor = back["prev_cursor"].as_str().unwrap();
+    let front = get_json(
+        &app,
+        &format!("{}?limit=2&ending_before={cursor}", routes::PAYMENT_INTENTS),
+    )
+    .await;
+    assert_eq!(listed_ids(&front), &all[..2]);
+    assert_eq!(front["has_more"], false);
+    assert!(front["prev_cursor"].is_null());
+    assert!(front["next_cursor"].is_string());
+
+    let uri = format!(
+        "{}?starting_after={cursor}&ending_before={cursor}",
+        routes::PAYMENT_INTENTS
+    );
+    let res = raw(&app, "GET", &uri).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn list_rejects_bad_limits_and_cursors(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    for query in [
+        "limit=0",
+        "limit=-1",
+        "starting_after=zz",
+        "starting_after=3132",
+        "ending_before=zz",
+    ] {
+        let uri = format!("{}?{query}", routes::PAYMENT_INTENTS);
+        let res = raw(&app, "GET", &uri).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn list_echoes_limits_and_clamps_oversized_ones(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let page = get_json(&app, routes::PAYMENT_INTENTS).await;
+    assert_eq!(
+        (page["limit"].as_i64(), page["max_limit"].as_i64()),
+        (Some(20), Some(100))
+    );
+    assert!(page.get("warnings").is_none());
+
+    let page = get_json(&app, &format!("{}?limit=500", routes::PAYMENT_INTENTS)).await;
+    assert_eq!(page["limit"], 100);
+    let warnings = page["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(
+        warnings[0].as_str().unwrap().contains("500"),
+        "{warnings:?}"
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn list_page_sizes_come_from_config(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.list_default_limit = Some(2);
+    state.config.list_max_limit = Some(3);
+    let app = build_app(state);
+    for amount in 101..=104 {
+        create_with(&app, None, json!({ "amount": amount, "currency": "gbp" })).await;
+    }
+
+    let page = get_json(&app, routes::PAYMENT_INTENTS).await;
+    assert_eq!(listed_ids(&page).len(), 2);
+    assert_eq!(
+        (page["limit"].as_i64(), page["max_limit"].as_i64()),
+        (Some(2), Some(3))
+    );
+
+    let page = get_json(&app, &format!("{}?limit=50", routes::PAYMENT_INTENTS)).await;
+    assert_eq!(listed_ids(&page).len(), 3);
+    assert_eq!(page["has_more"], true);
+    assert_eq!(page["limit"], 3);
+}
+
+async fn bulk_cancel(
+    app: &axum::Router,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let (status, _, body) = bulk_cancel_with_key(app, None, body).await;
+    (status, body)
+}
+
+async fn bulk_cancel_with_key(
+    app: &axum::Router,
+    key: Option<&str>,
+    body: serde_json::Value,
+) -> (StatusCode, axum::http::HeaderMap, serde_json::Value) {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(routes::PAYMENT_INTENTS_BULK_CANCEL)
+        .header("content-type", "application/json");
+    if let Some(key) = key {
+        builder = builder.header("Idempotency-Key", key);
+    }
+
+    let res = app
+        .clone()
+        .oneshot(builder.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let headers = res.headers().clone();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, serde_json::from_slice(&bytes).unwrap())
+}
+
+// `count` requires_confirmation intents created at `created_at`
+async fn insert_intents_created_at(pool: &PgPool, count: usize, created_at: &str) -> Vec<Uuid> {
+    let mut ids = Vec::new();
+    for _ in 0..count {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO payment_intents (id, amount, currency, status, created_at)
+             VALUES ($1, 1000, 'gbp', 'requires_confirmation', $2::timestamptz)",
+        )
+        .bind(id)
+        .bind(created_at)
+        .execute(pool)
+        .await
+        .unwrap();
+        ids.push(id);
+    }
+    ids
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn bulk_cancel_dry_run_counts_without_touching_anything(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    insert_intents_created_at(&pool, 3, "2026-03-01T12:00:00Z").await;
+    insert_intents_created_at(&pool, 2, "2026-02-01T12:00:00Z").await;
+    let paid = insert_intents_created_at(&pool, 1, "2026-03-01T12:00:00Z").await[0];
+    assert_eq!(confirm(&app, &paid.to_string()).await, StatusCode::OK);
+
+    let (status, body) = bulk_cancel(
+        &app,
+        json!({
+            "created_after": "2026-03-01T00:00:00Z",
+            "created_before": "2026-03-02T00:00:00Z",
+            "dry_run": true,
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(body["matched"], 3);
+    assert_eq!(body["canceled"], 0);
+    assert!(body["job_id"].is_null());
+
+    let open: i64 = sqlx::query_scalar(
+        "SELECT count(*) FROM payment_intents WHERE status = 'requires_confirmation'",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(open, 5);
+
+    // Some filter is required, and a resumed job has no filter of its own
+    let (status, _) = bulk_cancel(&app, json!({ "dry_run": true })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = bulk_cancel(
+        &app,
+        json!({ "job_id": Uuid::new_v4(), "created_after": "2026-03-01T00:00:00Z" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = bulk_cancel(&app, json!({ "job_id": Uuid::new_v4() })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn bulk_cancel_runs_in_capped_resumable_calls(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.bulk_cancel_max_per_call = Some(2);
+    let app = build_app(state);
+
+    let stale = insert_intents_created_at(&pool, 5, "2026-03-01T12:00:00Z").await;
+    let paid = insert_intents_created_at(&pool, 1, "2026-03-01T12:00:00Z").await[0];
+    assert_eq!(confirm(&app, &paid.to_string()).await, StatusCode::OK);
+
+    let (status, first) =
+        bulk_cancel(&app, json!({ "created_before": "2026-04-01T00:00:00Z" })).await;
+    assert_eq!(status, StatusCode::OK, "{first}");
+    assert_eq!(first["matched"], 5);
+    assert_eq!(first["canceled"], 2);
+    assert_eq!(first["done"], false);
+    let job_id = first["job_id"].as_str().unwrap().to_string();
+
+    // One of the remaining intents gets confirmed between calls: it is skipped, not canceled
+    let pending: Uuid = sqlx::query_scalar(
+        "SELECT payment_intent_id FROM bulk_cancel_job_items WHERE outcome IS NULL LIMIT 1",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(confirm(&app, &pending.to_string()).await, StatusCode::OK);
+
+    let mut calls = 1;
+    let last = loop {
+        let (status, body) = bulk_cancel(&app, json!({ "job_id": job_id })).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        calls += 1;
+        if body["done"] == true {
+            break body;
+        }
+    };
+    assert_eq!(calls, 3);
+    assert_eq!(last["matched"], 5);
+    assert_eq!(last["canceled"], 4);
+    assert_eq!(last["skipped"], 1);
+
+    for id in stale.iter().filter(|id| **id != pending) {
+        let fetched = get_json(&app, &routes::payment_intent(id)).await;
+        assert_eq!(fetched["status"], "canceled");
+        assert_eq!(fetched["cancellation_reason"], "bulk_cancel");
+        assert_eq!(fetched["latest_event"]["type"], "payment_intent.canceled");
+
+        let transitions = get_json(&app, &routes::payment_intent_transitions(id)).await;
+        assert_eq!(
+            transition_pairs(&transitions).last(),
+            Some(&(Some("requires_confirmation"), "canceled", "bulk_cancel"))
+        );
+    }
+    for id in [paid, pending] {
+        let fetched = get_json(&app, &routes::payment_intent(id)).await;
+        assert_eq!(fetched["status"], "succeeded");
+    }
+
+    // Resuming a finished job is a no-op
+    let (_, again) = bulk_cancel(&app, json!({ "job_id": job_id })).await;
+    assert_eq!(again["canceled"], 4);
+    assert_eq!(again["done"], true);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn bulk_cancel_replays_return_the_original_job(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.bulk_cancel_max_per_call = Some(2);
+    let app = build_app(state);
+
+    insert_intents_created_at(&pool, 3, "2026-03-01T12:00:00Z").await;
+    let body = json!({ "created_before": "2026-04-01T00:00:00Z" });
+
+    let (status, headers, first) =
+        bulk_cancel_with_key(&app, Some("cleanup-1"), body.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{first}");
+    assert!(headers.get("idempotent-replayed").is_none());
+    assert_eq!(first["canceled"], 2);
+
+    // A retry reports the same job as it stands now; it does not start or advance one
+    let (status, headers, replay) =
+        bulk_cancel_with_key(&app, Some("cleanup-1"), body.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{replay}");
+    assert_eq!(headers["idempotent-replayed"], "true");
+    assert_eq!(replay["job_id"], first["job_id"]);
+    assert_eq!(replay["canceled"], 2);
+    assert_eq!(replay["done"], false);
+
+    let jobs: i64 = sqlx::query_scalar("SELECT count(*) FROM bulk_cancel_jobs")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(jobs, 1);
+
+    let (status, _, err) = bulk_cancel_with_key(
+        &app,
+        Some("cleanup-1"),
+        json!({ "created_before": "2026-05-01T00:00:00Z" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "idempotency_key_reused");
+
+    // Finishing the job is still a resume, and a later replay sees the final state
+    let (_, done) = bulk_cancel(&app, json!({ "job_id": first["job_id"] })).await;
+    assert_eq!(done["done"], true);
+    let (_, _, replay) = bulk_cancel_with_key(&app, Some("cleanup-1"), body).await;
+    assert_eq!(replay["canceled"], 3);
+    assert_eq!(replay["done"], true);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn bulk_cancel_filters_on_metadata_and_customer(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let customer: Uuid =
+        sqlx::query_scalar("INSERT INTO customers (id) VALUES (gen_random_uuid()) RETURNING id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+    let mut ids = Vec::new();
+    for body in [
+        json!({ "metadata": { "client": "runaway", "batch": "7" } }),
+        json!({ "metadata": { "client": "runaway" }, "customer": customer }),
+        json!({ "metadata": { "client": "other" }, "customer": customer }),
+        json!({}),
+    ] {
+        let mut body = body;
+        body["amount"] = json!(1000);
+        body["currency"] = json!("gbp");
+        let (status, created) = create_with(&app, None, body).await;
+        assert_eq!(status, StatusCode::CREATED, "{created}");
+        ids.push(created["id"].as_str().unwrap().to_string());
+    }
+
+    let dry_run = |filter: serde_json::Value| {
+        let mut body = filter;
+        body["dry_run"] = json!(true);
+        bulk_cancel(&app, body)
+    };
+    let (_, body) = dry_run(json!({ "metadata": { "client": "runaway" } })).await;
+    assert_eq!(body["matched"], 2);
+    let (_, body) = dry_run(json!({ "customer": customer })).await;
+    assert_eq!(body["matched"], 2);
+    let (_, body) =
+        dry_run(json!({ "metadata": { "client": "runaway" }, "customer": customer })).await;
+    assert_eq!(body["matched"], 1);
+    let (status, _) = dry_run(json!({ "metadata": { "": "runaway" } })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Every pair has to match, and the filter is kept with the job
+    let (status, body) = bulk_cancel(
+        &app,
+        json!({ "metadata": { "client": "runaway", "batch": "7" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(
+        (body["matched"].as_i64(), body["canceled"].as_i64()),
+        (Some(1), Some(1))
+    );
+    let (metadata, job_customer): (serde_json::Value, Option<Uuid>) =
+        sqlx::query_as("SELECT metadata, customer_id FROM bulk_cancel_jobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(metadata, json!({ "client": "runaway", "batch": "7" }));
+    assert_eq!(job_customer, None);
+
+    let (status, body) = bulk_cancel(&app, json!({ "customer": customer })).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(body["canceled"], 2);
+
+    let mut statuses = Vec::new();
+    for id in &ids {
+        let fetched = get_json(&app, &routes::payment_intent(id)).await;
+        statuses.push(fetched["status"].as_str().unwrap().to_string());
+    }
+    assert_eq!(
+        statuses,
+        ["canceled", "canceled", "canceled", "requires_confirmation"]
+    );
+}
+
+async fn capture(app: &axum::Router, id: &str) -> (StatusCode, serde_json::Value) {
+    capture_with(app, id, None).await
+}
+
+async fn capture_with(
+    app: &axum::Router,
+    id: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    let builder = Request::builder()
+        .method("POST")
+        .uri(routes::payment_intent_capture(id));
+    let req = match body {
+        Some(body) => builder
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => builder.body(Body::empty()),
+    };
+    let res = app.clone().oneshot(req.unwrap()).await.unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn manual_capture_holds_at_requires_capture_until_captured(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let (status, created) = create_with(
+        &app,
+        None,
+        json!({ "amount": 1000, "currency": "gbp", "capture_method": "manual" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["capture_method"], "manual");
+    let id = created["id"].as_str().unwrap().to_string();
+
+    // Nothing to capture before confirmation
+    let (status, err) = capture(&app, &id).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "payment_intent_unexpected_state");
+
+    assert_eq!(confirm(&app, &id).await, StatusCode::OK);
+    let fetched = get_json(&app, &routes::payment_intent(&id)).await;
+    assert_eq!(fetched["status"], "requires_capture");
+
+    let (status, captured) = capture(&app, &id).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(captured["status"], "succeeded");
+    assert_eq!(captured["amount_received"], 1000);
+
+    let (status, _) = capture(&app, &id).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let transitions = get_json(&app, &routes::payment_intent_transitions(&id)).await;
+    assert_eq!(
+        transition_pairs(&transitions),
+        vec![
+            (None, "requires_confirmation", "api"),
+            (Some("requires_confirmation"), "requires_capture", "api"),
+            (Some("requires_capture"), "succeeded", "api"),
+        ]
+    );
+    let events: Vec<String> = event_types_for(&pool, &id)
+        .await
+        .into_iter()
+        .map(|(event_type, _)| event_type)
+        .collect();
+    assert_eq!(
+        events,
+        vec!["payment_intent.created", "payment_intent.captured"]
+    );
+
+    // Automatic intents never stop at requires_capture
+    let (_, created) = create_with(&app, None, json!({ "amount": 1000, "currency": "gbp" })).await;
+    assert_eq!(created["capture_method"], "automatic");
+    let id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(confirm(&app, &id).await, StatusCode::OK);
+    let fetched = get_json(&app, &routes::payment_intent(&id)).await;
+    assert_eq!(fetched["amount_received"], 1000);
+    let (status, _) = capture(&app, &id).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = capture(&app, &Uuid::new_v4().to_string()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, err) = create_with(
+        &app,
+        None,
+        json!({ "amount": 1000, "currency": "gbp", "capture_method": "later" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        err["error"]["message"],
+        "capture_method must be `automatic` or `manual`"
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn partial_capture_records_amount_received(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let (_, created) = create_with(
+        &app,
+        None,
+        json!({ "amount": 1000, "currency": "gbp", "capture_method": "manual" }),
+    )
+    .await;
+    let id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(confirm(&app, &id).await, StatusCode::OK);
+
+    for (amount, message) in [
+        (0, "amount_to_capture must be > 0"),
+        (
+            1001,
+            "amount_to_capture must be at most the authorized amount (1000)",
+        ),
+    ] {
+        let (status, err) =
+            capture_with(&app, &id, Some(json!({ "amount_to_capture": amount }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(err["error"]["message"], message);
+    }
+    let fetched = get_json(&app, &routes::payment_intent(&id)).await;
+    assert_eq!(fetched["status"], "requires_capture");
+    assert_eq!(fetched["amount_received"], 0);
+
+    let (status, captured) =
+        capture_with(&app, &id, Some(json!({ "amount_to_capture": 750 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(captured["status"], "succeeded");
+    assert_eq!(captured["amount"], 1000);
+    assert_eq!(captured["amount_received"], 750);
+
+    let payload: serde_json::Value = sqlx::query_scalar(
+        "SELECT payload FROM events_outbox WHERE event_type = 'payment_intent.captured'",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(payload["payment_intent"]["amount"], 1000);
+    assert_eq!(payload["payment_intent"]["amount_received"], 750);
+}
@@ -0,0 +1,203 @@
+mod common;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{Router, http::StatusCode};
+use chrono::{Datelike, Utc};
+use common::send;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+
+fn card(number: &str) -> Value {
+    let exp_year = Utc::now().year() + 2;
+    json!({ "card": { "number": number, "exp_month": 12, "exp_year": exp_year, "cvc": "123" } })
+}
+
+async fn create(app: &Router, uri: &str, body: Value) -> String {
+    let (status, created) = send(app, "POST", uri, body).await;
+    assert_eq!(status, StatusCode::CREATED, "{created}");
+    created["id"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn cards_keep_only_brand_last4_and_expiry(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let (status, pm) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_METHODS,
+        card("4242 4242 4242 4242"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(pm["card"]["brand"], "visa");
+    assert_eq!(pm["card"]["last4"], "4242");
+    assert_eq!(pm["card"]["exp_month"], 12);
+    assert!(pm["customer"].is_null());
+    assert!(!pm.to_string().contains("4242424242424242"));
+
+    let id = pm["id"].as_str().unwrap();
+    let (_, fetched) = send(&app, "GET", &routes::payment_method(id), Value::Null).await;
+    assert_eq!(fetched, pm);
+
+    // Nothing in the row can give the number back
+    let row: String = sqlx::query_scalar("SELECT row_to_json(p)::text FROM payment_methods p")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert!(!row.contains("4242424242424242"));
+
+    let (status, err) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_METHODS,
+        card("4242424242424241"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn payment_methods_attach_to_one_customer_at_a_time(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let jo = create(
+        &app,
+        routes::CUSTOMERS,
+        json!({ "email": "jo@example.com" }),
+    )
+    .await;
+    let sam = create(
+        &app,
+        routes::CUSTOMERS,
+        json!({ "email": "sam@example.com" }),
+    )
+    .await;
+    let pm = create(&app, routes::PAYMENT_METHODS, card("5555555555554444")).await;
+
+    let attach = routes::payment_method_attach(&pm);
+    let (status, attached) = send(&app, "POST", &attach, json!({ "customer": jo })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(attached["customer"], jo.as_str());
+    // Again to the same customer is fine; to another isn't
+    let (status, _) = send(&app, "POST", &attach, json!({ "customer": jo })).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, err) = send(&app, "POST", &attach, json!({ "customer": sam })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+
+    let uri = format!("{}?customer={jo}", routes::PAYMENT_METHODS);
+    let (_, listed) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(listed["data"][0]["id"], pm.as_str());
+    assert_eq!(listed["data"][0]["card"]["brand"], "mastercard");
+    assert_eq!(listed["limit"], 20);
+
+    // A second card pages ahead of the first
+    let visa = create(&app, routes::PAYMENT_METHODS, card("4242424242424242")).await;
+    let attach_visa = routes::payment_method_attach(&visa);
+    send(&app, "POST", &attach_visa, json!({ "customer": jo })).await;
+    let (_, page) = send(&app, "GET", &format!("{uri}&limit=1"), Value::Null).await;
+    assert_eq!(page["data"][0]["id"], visa.as_str());
+    assert_eq!(page["has_more"], true);
+    let next = format!("{uri}&limit=1&starting_after={visa}");
+    let (_, page) = send(&app, "GET", &next, Value::Null).await;
+    assert_eq!(page["data"][0]["id"], pm.as_str());
+    assert_eq!(page["has_more"], false);
+    send(
+        &app,
+        "POST",
+        &routes::payment_method_detach(&visa),
+        Value::Null,
+    )
+    .await;
+
+    let detach = routes::payment_method_detach(&pm);
+    let (status, detached) = send(&app, "POST", &detach, Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(detached["customer"].is_null());
+    let (status, _) = send(&app, "POST", &detach, Value::Null).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (_, listed) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(listed["data"], json!([]));
+
+    // Deleting a customer detaches its payment methods
+    send(&app, "POST", &attach, json!({ "customer": sam })).await;
+    send(&app, "DELETE", &routes::customer(&sam), Value::Null).await;
+    let (_, fetched) = send(&app, "GET", &routes::payment_method(&pm), Value::Null).await;
+    assert!(fetched["customer"].is_null());
+    let uri = format!("{}?customer={sam}", routes::PAYMENT_METHODS);
+    let (status, _) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn confirm_uses_a_saved_payment_method(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let jo = create(
+        &app,
+        routes::CUSTOMERS,
+        json!({ "email": "jo@example.com" }),
+    )
+    .await;
+    let visa = create(&app, routes::PAYMENT_METHODS, card("4242424242424242")).await;
+    let declined = create(&app, routes::PAYMENT_METHODS, card("4000000000009995")).await;
+    send(
+        &app,
+        "POST",
+        &routes::payment_method_attach(&visa),
+        json!({ "customer": jo }),
+    )
+    .await;
+
+    let intent = json!({ "amount": 1000, "currency": "gbp", "customer": jo });
+    let pi = create(&app, routes::PAYMENT_INTENTS, intent.clone()).await;
+    let (status, confirmed) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&pi),
+        json!({ "payment_method": visa }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(confirmed["status"], "succeeded");
+    assert_eq!(confirmed["payment_method"], visa.as_str());
+
+    // An unattached card works for anyone, and fails like the test card it was saved from
+    let pi = create(&app, routes::PAYMENT_INTENTS, intent).await;
+    let (status, err) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&pi),
+        json!({ "payment_method": declined }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(err["error"]["code"], "card_declined");
+    let (_, failed) = send(&app, "GET", &routes::payment_intent(&pi), Value::Null).await;
+    assert_eq!(
+        failed["last_payment_error"]["decline_code"],
+        "insufficient_funds"
+    );
+    assert_eq!(
+        failed["last_payment_error"]["payment_method"],
+        declined.as_str()
+    );
+
+    // Jo's card isn't for an intent without Jo
+    let body = json!({ "amount": 1000, "currency": "gbp" });
+    let pi = create(&app, routes::PAYMENT_INTENTS, body).await;
+    let (status, err) = send(
+        &app,
+        "POST",
+        &routes::payment_intent_confirm(&pi),
+        json!({ "payment_method": visa }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        err["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("different customer")
+    );
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use std::time::Duration;
+
+use api::{app::build_app, payout_settlement, routes, state::AppState};
+use axum::{Router, http::StatusCode};
+use common::{paid, send};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+
+async fn available(app: &Router) -> Value {
+    let (_, balance) = send(app, "GET", routes::BALANCE, Value::Null).await;
+    balance["available"][0]["amount"].clone()
+}
+
+async fn events(pool: &PgPool, event_type: &str) -> Vec<Value> {
+    sqlx::query_scalar("SELECT payload FROM events_outbox WHERE event_type = $1 ORDER BY sequence")
+        .bind(event_type)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn payouts_only_draw_on_the_available_balance(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.balance_available_after = Some(Duration::from_secs(86400));
+    let app = build_app(state);
+
+    paid(&app, 1000, "gbp").await;
+    let (status, err) = send(
+        &app,
+        "POST",
+        routes::PAYOUTS,
+        json!({ "amount": 500, "currency": "gbp" }),
+    )
+    .await;
+    // Still pending, so there is nothing to pay out yet
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "balance_insufficient");
+
+    let (status, err) = send(
+        &app,
+        "POST",
+        routes::PAYOUTS,
+        json!({ "amount": 500, "currency": "gbp", "destination": "ba_live_1" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_paid_payout_leaves_the_balance_and_emits_payout_paid(pool: PgPool) {
+    let state = AppState::new(pool.clone());
+    let app = build_app(state.clone());
+
+    paid(&app, 1000, "gbp").await;
+    let (status, payout) = send(
+        &app,
+        "POST",
+        routes::PAYOUTS,
+        json!({ "amount": 600, "currency": "gbp", "description": "weekly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(payout["status"], "pending");
+    assert_eq!(payout["destination"], "ba_test");
+    assert_eq!(available(&app).await, 400);
+
+    let (status, err) = send(
+        &app,
+        "POST",
+        routes::PAYOUTS,
+        json!({ "amount": 401, "currency": "gbp" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "balance_insufficient");
+
+    assert_eq!(payout_settlement::run(&state, 100).await.unwrap(), 1);
+    assert_eq!(payout_settlement::run(&state, 100).await.unwrap(), 0);
+
+    let id = payout["id"].as_str().unwrap();
+    let (_, fetched) = send(&app, "GET", &routes::payout(id), Value::Null).await;
+    assert_eq!(fetched["status"], "paid");
+    assert_eq!(fetched["failure_code"], Value::Null);
+    assert_eq!(available(&app).await, 400);
+    assert_eq!(
+        events(&pool, "payout.paid").await,
+        [json!({ "payout": fetched })]
+    );
+
+    let uri = format!("{}?status=paid", routes::PAYOUTS);
+    let (_, list) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(list["data"].as_array().unwrap().len(), 1);
+    assert_eq!(list["limit"], 20);
+    let uri = format!("{}?status=paid&starting_after={id}", routes::PAYOUTS);
+    let (_, list) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(list["data"], json!([]));
+    let uri = format!("{}?status=pending", routes::PAYOUTS);
+    let (_, list) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(list["data"], json!([]));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_failed_payout_gives_the_amount_back(pool: PgPool) {
+    let state = AppState::new(pool.clone());
+    let app = build_app(state.clone());
+
+    paid(&app, 1000, "gbp").await;
+    let (_, payout) = send(
+        &app,
+        "POST",
+        routes::PAYOUTS,
+        json!({ "amount": 1000, "currency": "gbp", "destination": "ba_test_account_closed" }),
+    )
+    .await;
+    assert_eq!(available(&app).await, 0);
+
+    payout_settlement::run(&state, 100).await.unwrap();
+
+    let id = payout["id"].as_str().unwrap();
+    let (_, fetched) = send(&app, "GET", &routes::payout(id), Value::Null).await;
+    assert_eq!(fetched["status"], "failed");
+    assert_eq!(fetched["failure_code"], "account_closed");
+    assert_eq!(available(&app).await, 1000);
+    assert_eq!(
+        events(&pool, "payout.failed").await,
+        [json!({ "payout": fetched })]
+    );
+
+    let (_, list) = send(&app, "GET", routes::BALANCE_TRANSACTIONS, Value::Null).await;
+    assert_eq!(list["data"][0]["type"], "payout_failure");
+    assert_eq!(list["data"][0]["amount"], 1000);
+    assert_eq!(list["data"][1]["type"], "payout");
+    assert_eq!(list["data"][1]["amount"], -1000);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn payouts_wait_for_their_arrival_date(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.payout_settle_after = Some(Duration::from_secs(86400));
+    let app = build_app(state.clone());
+
+    paid(&app, 1000, "gbp").await;
+    let (_, payout) = send(
+        &app,
+        "POST",
+        routes::PAYOUTS,
+        json!({ "amount": 1000, "currency": "gbp" }),
+    )
+    .await;
+    assert_eq!(payout_settlement::run(&state, 100).await.unwrap(), 0);
+    let id = payout["id"].as_str().unwrap();
+    let (_, fetched) = send(&app, "GET", &routes::payout(id), Value::Null).await;
+    assert_eq!(fetched["status"], "pending");
+}
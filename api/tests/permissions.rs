@@ -0,0 +1,266 @@
+use std::time::Duration;
+
+use api::permissions::ApiKeys;
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Method, Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+const KEYS: &str = "read_only:rk_test,full:sk_test,admin:ak_test";
+
+// The callers every case is tried with, in the order of `Case::expected`
+const CALLERS: [(&str, Option<&str>); 5] = [
+    ("no key", None),
+    ("unknown key", Some("sk_unknown")),
+    ("read_only", Some("rk_test")),
+    ("full", Some("sk_test")),
+    ("admin", Some("ak_test")),
+];
+
+fn app(pool: &PgPool, keys: &str, sandbox: bool) -> Router {
+    let mut state = AppState::new(pool.clone());
+    state.config.api_keys = ApiKeys::parse(keys).unwrap();
+    state.config.sandbox_mode = sandbox;
+    state.config.request_capture_enabled = true;
+    build_app(state)
+}
+
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    key: Option<&str>,
+    body: Option<&Value>,
+) -> (StatusCode, Value) {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(key) = key {
+        req = req.header("authorization", format!("Bearer {key}"));
+    }
+    let body = body.map_or_else(Body::empty, |b| Body::from(b.to_string()));
+    let res = app.clone().oneshot(req.body(body).unwrap()).await.unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+struct Case {
+    method: Method,
+    uri: String,
+    body: Option<Value>,
+    // One status per entry of CALLERS
+    expected: [u16; 5],
+}
+
+fn case(method: Method, uri: impl Into<String>, body: Option<Value>, expected: [u16; 5]) -> Case {
+    Case {
+        method,
+        uri: uri.into(),
+        body,
+        expected,
+    }
+}
+
+async fn assert_matrix(app: &Router, cases: &[Case]) {
+    for case in cases {
+        for ((caller, key), expected) in CALLERS.iter().zip(case.expected) {
+            let (status, body) = send(
+                app,
+                case.method.clone(),
+                &case.uri,
+                *key,
+                case.body.as_ref(),
+            )
+            .await;
+            assert_eq!(
+                status.as_u16(),
+                expected,
+                "{} {} as {caller}: {body}",
+                case.method,
+                case.uri
+            );
+            match expected {
+                401 => assert_eq!(body["error"]["code"], "authentication_required"),
+                403 => assert_eq!(body["error"]["code"], "insufficient_scope"),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn each_scope_gets_exactly_its_routes(pool: PgPool) {
+    let app = app(&pool, KEYS, false);
+    let create = json!({ "amount": 1000, "currency": "gbp" });
+
+    assert_matrix(
+        &app,
+        &[
+            // Unauthenticated probes
+            case(Method::GET, routes::HEALTH, None, [200; 5]),
+            // API reads and writes
+            case(
+                Method::GET,
+                routes::PAYMENT_INTENTS,
+                None,
+                [401, 401, 200, 200, 200],
+            ),
+            case(
+                Method::POST,
+                routes::PAYMENT_INTENTS,
+                Some(create),
+                [401, 401, 403, 201, 201],
+            ),
+            // Past authorization, the handler decides
+            case(
+                Method::GET,
+                routes::payment_intent(Uuid::new_v4()),
+                None,
+                [401, 401, 404, 404, 404],
+            ),
+            // Admin reads and writes
+            case(
+                Method::GET,
+                routes::ADMIN_WORKERS,
+                None,
+                [401, 401, 403, 403, 200],
+            ),
+            case(
+                Method::POST,
+                routes::ADMIN_OUTBOX_RESUME,
+                None,
+                [401, 401, 403, 403, 200],
+            ),
+            case(
+                Method::GET,
+                routes::METRICS,
+                None,
+                [401, 401, 403, 403, 200],
+            ),
+            // Sandbox-only routes don't exist outside sandbox mode, for any key
+            case(Method::GET, routes::SANDBOX_ECHO_DELIVERIES, None, [404; 5]),
+            case(Method::POST, routes::ADMIN_SEED, None, [404; 5]),
+            case(
+                Method::POST,
+                routes::sandbox_echo(Uuid::new_v4()),
+                Some(json!({})),
+                [404; 5],
+            ),
+            // Unknown paths stay the plain 404
+            case(Method::GET, "/v1/unknown", None, [404; 5]),
+        ],
+    )
+    .await;
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn sandbox_routes_follow_their_scopes_in_sandbox_mode(pool: PgPool) {
+    let app = app(&pool, KEYS, true);
+
+    assert_matrix(
+        &app,
+        &[
+            case(
+                Method::GET,
+                routes::SANDBOX_ECHO_DELIVERIES,
+                None,
+                [401, 401, 200, 200, 200],
+            ),
+            case(
+                Method::POST,
+                routes::ADMIN_SEED,
+                None,
+                [401, 401, 403, 403, 200],
+            ),
+            case(
+                Method::POST,
+                routes::payment_intent_archive(Uuid::new_v4()),
+                None,
+                [401, 401, 403, 404, 404],
+            ),
+            // The worker posts here without a key; the handler checks the webhook signature
+            case(
+                Method::POST,
+                routes::sandbox_echo(Uuid::new_v4()),
+                Some(json!({})),
+                [404; 5],
+            ),
+        ],
+    )
+    .await;
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn without_keys_every_route_is_open(pool: PgPool) {
+    let app = app(&pool, "", false);
+
+    let (status, _) = send(&app, Method::GET, routes::ADMIN_WORKERS, None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json!({ "amount": 1000, "currency": "gbp" });
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        routes::PAYMENT_INTENTS,
+        Some("anything"),
+        Some(&body),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = send(
+        &app,
+        Method::GET,
+        routes::SANDBOX_ECHO_DELIVERIES,
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+// Captures never store Authorization, so a replay runs with the admin's own key
+#[sqlx::test(migrations = "./migrations")]
+async fn a_replay_runs_with_the_callers_key(pool: PgPool) {
+    let app = app(&pool, KEYS, true);
+    let body = json!({ "amount": 1000, "currency": "gbp" });
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        routes::PAYMENT_INTENTS,
+        Some("sk_test"),
+        Some(&body),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Captures are written after the response
+    let mut capture_id = None;
+    for _ in 0..50 {
+        capture_id = sqlx::query_scalar!("SELECT id FROM request_captures")
+            .fetch_optional(&pool)
+            .await
+            .unwrap();
+        if capture_id.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let uri = routes::admin_request_capture_replay(capture_id.expect("request was captured"));
+
+    let (status, _) = send(&app, Method::POST, &uri, Some("sk_test"), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, replayed) = send(&app, Method::POST, &uri, Some("ak_test"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(replayed["status"], 201, "{replayed}");
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use api::routes;
+use api::{app::build_app, state::AppState};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::count_queries;
+use http_body_util::BodyExt;
+use serde_json::json;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+// Statement budgets for the hot paths (baselined on the current handlers). Raise these deliberately.
+// Creates and confirms each include one payment_intent_transitions insert. Confirms also take the
+// intent's row lock first, and a successful one writes the charge, its balance transaction and
+// its event.
+const CREATE_BUDGET: usize = 4;
+const CREATE_IDEMPOTENT_BUDGET: usize = 6;
+const CONFIRM_BUDGET: usize = 8;
+
+fn create_request(idempotency_key: Option<&str>) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri(routes::PAYMENT_INTENTS)
+        .header("content-type", "application/json");
+    if let Some(key) = idempotency_key {
+        builder = builder.header("Idempotency-Key", key);
+    }
+
+    builder
+        .body(Body::from(
+            json!({ "amount": 1000, "currency": "gbp" }).to_string(),
+        ))
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn counter_sees_every_statement(pool: PgPool) {
+    let (_, count) = count_queries(async {
+        sqlx::query("SELECT 1").execute(&pool).await.unwrap();
+        sqlx::query("SELECT 2").execute(&pool).await.unwrap();
+    })
+    .await;
+
+    assert_eq!(count, 2);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn create_payment_intent_stays_within_budget(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (res, count) = count_queries(app.oneshot(create_request(None))).await;
+
+    assert_eq!(res.unwrap().status(), StatusCode::CREATED);
+    assert!(count <= CREATE_BUDGET, "create ran {count} statements");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn idempotent_create_stays_within_budget(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (res, count) = count_queries(app.clone().oneshot(create_request(Some("budget")))).await;
+    assert_eq!(res.unwrap().status(), StatusCode::CREATED);
+    assert!(
+        count <= CREATE_IDEMPOTENT_BUDGET,
+        "idempotent create ran {count} statements"
+    );
+
+    // Replays should be cheaper than the first execution
+    let (res, count) = count_queries(app.oneshot(create_request(Some("budget")))).await;
+    assert_eq!(res.unwrap().status(), StatusCode::CREATED);
+    assert!(
+        count <= CREATE_IDEMPOTENT_BUDGET,
+        "idempotent replay ran {count} statements"
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn confirm_payment_intent_stays_within_budget(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let res = app.clone().oneshot(create_request(None)).await.unwrap();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let created: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let id = created["id"].as_str().unwrap();
+
+    let (res, count) = count_queries(
+        app.oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::payment_intent_confirm(id))
+                .body(Body::empty())
+                .unwrap(),
+        ),
+    )
+    .await;
+
+    assert_eq!(res.unwrap().status(), StatusCode::OK);
+    assert!(count <= CONFIRM_BUDGET, "confirm ran {count} statements");
+}
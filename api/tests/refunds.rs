@@ -0,0 +1,169 @@
+mod common;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{Router, http::StatusCode};
+use common::{paid, send};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// A succeeded intent for `amount` gbp and its charge's id
+async fn charged(app: &Router, amount: i64) -> (String, String) {
+    let id = paid(app, amount, "gbp").await;
+    let uri = format!("{}?payment_intent={id}", routes::CHARGES);
+    let (_, charges) = send(app, "GET", &uri, Value::Null).await;
+    let charge = charges["data"][0]["id"].as_str().unwrap().to_string();
+    (id, charge)
+}
+
+async fn refund(app: &Router, body: Value) -> (StatusCode, Value) {
+    send(app, "POST", routes::REFUNDS, body).await
+}
+
+async fn refund_events(pool: &PgPool) -> Vec<Value> {
+    sqlx::query_scalar(
+        "SELECT payload FROM events_outbox WHERE event_type = 'refund.created' ORDER BY sequence",
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn partial_refunds_add_up_to_the_charge(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let (pi, charge) = charged(&app, 1000).await;
+
+    let (status, first) = refund(&app, json!({ "charge": charge, "amount": 300 })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(first["amount"], 300);
+    assert_eq!(first["charge"], charge.as_str());
+    assert_eq!(first["payment_intent"], pi.as_str());
+    assert_eq!(first["currency"], "gbp");
+    assert_eq!(first["status"], "succeeded");
+
+    // More than is left is refused and changes nothing
+    let (status, err) = refund(&app, json!({ "payment_intent": pi, "amount": 800 })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "amount_too_large");
+
+    // Without an amount, the rest
+    let (status, rest) = refund(&app, json!({ "payment_intent": pi })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(rest["amount"], 700);
+
+    let (_, fetched) = send(&app, "GET", &routes::charge(&charge), Value::Null).await;
+    assert_eq!(fetched["amount_refunded"], 1000);
+    assert_eq!(fetched["refunded"], true);
+
+    let (status, err) = refund(&app, json!({ "charge": charge, "amount": 1 })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "charge_already_refunded");
+
+    assert_eq!(
+        refund_events(&pool).await,
+        vec![json!({ "refund": first }), json!({ "refund": rest })]
+    );
+
+    let (status, fetched) = send(
+        &app,
+        "GET",
+        &routes::refund(first["id"].as_str().unwrap()),
+        Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched, first);
+
+    let uri = format!("{}?charge={charge}", routes::REFUNDS);
+    let (_, list) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(list["data"], json!([rest, first]));
+    assert_eq!(list["has_more"], false);
+    assert_eq!(list["limit"], 20);
+
+    let uri = format!("{}?charge={charge}&limit=1", routes::REFUNDS);
+    let (_, page) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(page["data"], json!([rest]));
+    assert_eq!(page["has_more"], true);
+    let uri = format!("{uri}&starting_after={}", rest["id"].as_str().unwrap());
+    let (_, page) = send(&app, "GET", &uri, Value::Null).await;
+    assert_eq!(page["data"], json!([first]));
+    assert_eq!(page["has_more"], false);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn only_charged_intents_can_be_refunded(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let body = json!({ "amount": 1000, "currency": "gbp" });
+    let (_, pending) = send(&app, "POST", routes::PAYMENT_INTENTS, body).await;
+    let (status, err) = refund(&app, json!({ "payment_intent": pending["id"] })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "payment_intent_unexpected_state");
+
+    let (status, err) = refund(&app, json!({ "payment_intent": Uuid::new_v4() })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(err["error"]["code"], "resource_missing");
+    let (status, _) = refund(&app, json!({ "charge": Uuid::new_v4() })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (pi, charge) = charged(&app, 1000).await;
+    for body in [
+        json!({}),
+        json!({ "payment_intent": pi, "charge": charge }),
+        json!({ "charge": charge, "amount": 0 }),
+    ] {
+        let (status, err) = refund(&app, body.clone()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(err["error"]["code"], "parameter_invalid", "{body}");
+    }
+    assert!(refund_events(&pool).await.is_empty());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn concurrent_refunds_never_exceed_the_charge(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let (_, charge) = charged(&app, 1000).await;
+
+    // Ten refunds of 300 race for 1000: exactly three fit
+    let attempts = (0..10).map(|_| {
+        let app = app.clone();
+        let body = json!({ "charge": charge, "amount": 300 });
+        tokio::spawn(async move { refund(&app, body).await.0 })
+    });
+    let mut created = 0;
+    for attempt in attempts.collect::<Vec<_>>() {
+        match attempt.await.unwrap() {
+            StatusCode::CREATED => created += 1,
+            status => assert_eq!(status, StatusCode::BAD_REQUEST),
+        }
+    }
+    assert_eq!(created, 3);
+
+    let (_, fetched) = send(&app, "GET", &routes::charge(&charge), Value::Null).await;
+    assert_eq!(fetched["amount_refunded"], 900);
+    assert_eq!(fetched["refunded"], false);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn refunds_wait_for_a_payout_checking_the_balance(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let (_, charge) = charged(&app, 1000).await;
+
+    // Stands in for a payout between its balance check and its commit
+    let mut payout = pool.begin().await.unwrap();
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('payouts:' || 'gbp'))")
+        .execute(&mut *payout)
+        .await
+        .unwrap();
+
+    let refunding = tokio::spawn({
+        let app = app.clone();
+        async move { refund(&app, json!({ "charge": charge })).await.0 }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert!(!refunding.is_finished());
+
+    payout.commit().await.unwrap();
+    assert_eq!(refunding.await.unwrap(), StatusCode::CREATED);
+}
@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use api::routes;
+use api::{app::build_app, request_capture::prune_request_captures, state::AppState};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::json;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+fn capturing_state(pool: PgPool) -> AppState {
+    let mut state = AppState::new(pool);
+    state.config.sandbox_mode = true;
+    state.config.request_capture_enabled = true;
+    state.config.request_capture_redact_paths = vec!["card.number".to_string()];
+    state
+}
+
+async fn send(
+    app: &axum::Router,
+    method: &str,
+    uri: &str,
+    key: Option<&str>,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(key) = key {
+        builder = builder.header("Idempotency-Key", key);
+    }
+    let body = body.map(|b| Body::from(b.to_string())).unwrap_or_default();
+
+    let res = app
+        .clone()
+        .oneshot(builder.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap_or_default())
+}
+
+// Captures are written off the request path, so wait for them to land
+async fn captures(app: &axum::Router, expected: usize) -> serde_json::Value {
+    for _ in 0..100 {
+        let (_, list) = send(app, "GET", routes::ADMIN_REQUEST_CAPTURES, None, None).await;
+        if list.as_array().unwrap().len() >= expected {
+            return list;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("expected {expected} request captures");
+}
+
+async fn capture_count(pool: &PgPool) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM request_captures")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn captures_are_off_by_default(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let (status, _) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        None,
+        Some(json!({ "amount": 1000, "currency": "gbp" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(capture_count(&pool).await, 0);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn mutating_requests_are_captured_redacted(pool: PgPool) {
+    let app = build_app(capturing_state(pool.clone()));
+
+    let (status, _) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        Some("capture-me"),
+        Some(json!({
+            "amount": 1000,
+            "currency": "gbp",
+            "card": { "number": "4242424242424242", "exp": "12/30" }
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Rejected requests are the interesting ones
+    let (status, _) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        None,
+        Some(json!({ "amount": 0, "currency": "gbp" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let list = captures(&app, 2).await;
+    let mut statuses: Vec<_> = list
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["response_status"].as_i64().unwrap())
+        .collect();
+    statuses.sort();
+    assert_eq!(statuses, vec![201, 400]);
+
+    let created = list
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["response_status"] == 201)
+        .unwrap();
+    assert_eq!(created["method"], "POST");
+    assert_eq!(created["path"], routes::PAYMENT_INTENTS);
+    assert_eq!(created["headers"]["idempotency-key"], "capture-me");
+    let body: serde_json::Value = serde_json::from_str(created["body"].as_str().unwrap()).unwrap();
+    assert_eq!(body["card"]["number"], "[REDACTED]");
+    assert_eq!(body["card"]["exp"], "12/30");
+    assert_eq!(body["amount"], 1000);
+
+    // GETs are never captured
+    send(&app, "GET", routes::WEBHOOK_ENDPOINTS, None, None).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(capture_count(&pool).await, 2);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn replay_reproduces_the_original_result(pool: PgPool) {
+    let app = build_app(capturing_state(pool.clone()));
+
+    let (_, original) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        Some("replay-me"),
+        Some(json!({ "amount": 1500, "currency": "gbp" })),
+    )
+    .await;
+
+    let list = captures(&app, 1).await;
+    let capture_id = list[0]["id"].as_str().unwrap();
+
+    let (status, replayed) = send(
+        &app,
+        "POST",
+        &routes::admin_request_capture_replay(capture_id),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(replayed["status"], 201);
+    assert_eq!(replayed["body"], original);
+
+    // The replay is not captured itself
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(capture_count(&pool).await, 1);
+
+    // Sandbox only
+    let app = build_app(AppState::new(pool));
+    let (status, _) = send(
+        &app,
+        "POST",
+        &routes::admin_request_capture_replay(capture_id),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn pruning_drops_expired_captures(pool: PgPool) {
+    for age_hours in [1, 48] {
+        sqlx::query(
+            r#"
+            INSERT INTO request_captures (id, method, path, body, response_status, created_at)
+            VALUES ($1, 'POST', '/v1/payment_intents', '{}', 201, now() - make_interval(hours => $2))
+            "#,
+        )
+        .bind(uuid::Uuid::new_v4())
+        .bind(age_hours)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    let pruned = prune_request_captures(&pool, Duration::from_secs(24 * 3600))
+        .await
+        .unwrap();
+    assert_eq!(pruned, 1);
+    assert_eq!(capture_count(&pool).await, 1);
+}
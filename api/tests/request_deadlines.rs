@@ -0,0 +1,236 @@
+use std::time::Duration;
+
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tokio::time::Instant;
+use tower::ServiceExt;
+
+async fn send(
+    app: &Router,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json");
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let res = app
+        .clone()
+        .oneshot(
+            req.body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+fn sandbox_app(pool: PgPool) -> Router {
+    let mut state = AppState::new(pool);
+    state.config.sandbox_mode = true;
+    build_app(state)
+}
+
+async fn create_intent(app: &Router) -> String {
+    let body = json!({ "amount": 1000, "currency": "gbp" });
+    let (status, created) = send(app, routes::PAYMENT_INTENTS, &[], Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    created["id"].as_str().unwrap().to_string()
+}
+
+async fn status_and_events(pool: &PgPool, id: &str) -> (String, Vec<String>) {
+    let id: uuid::Uuid = id.parse().unwrap();
+    let status = sqlx::query_scalar!("SELECT status FROM payment_intents WHERE id = $1", id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    let events = sqlx::query_scalar!(
+        r#"
+        SELECT event_type FROM events_outbox
+        WHERE payload->'payment_intent'->>'id' = $1::uuid::text
+        ORDER BY created_at
+        "#,
+        id
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap();
+    (status, events)
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn confirm_gives_up_when_the_latency_outlasts_the_deadline(pool: PgPool) {
+    let app = sandbox_app(pool.clone());
+    let id = create_intent(&app).await;
+
+    let started = Instant::now();
+    let headers = [
+        ("X-Simulated-Latency-Ms", "2000"),
+        ("X-Request-Deadline-Ms", "200"),
+    ];
+    let (status, body) = send(&app, &routes::payment_intent_confirm(&id), &headers, None).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body["error"]["code"], "deadline_exceeded");
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // Nothing was written, so the confirm can simply be retried
+    let (status, events) = status_and_events(&pool, &id).await;
+    assert_eq!(status, "requires_confirmation");
+    assert_eq!(events, ["payment_intent.created"]);
+    let (status, _) = send(&app, &routes::payment_intent_confirm(&id), &[], None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn capture_gives_up_when_the_latency_outlasts_the_deadline(pool: PgPool) {
+    let app = sandbox_app(pool.clone());
+    let body = json!({ "amount": 1000, "currency": "gbp", "capture_method": "manual" });
+    let (status, created) = send(&app, routes::PAYMENT_INTENTS, &[], Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = created["id"].as_str().unwrap().to_string();
+    let (status, _) = send(&app, &routes::payment_intent_confirm(&id), &[], None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let started = Instant::now();
+    let headers = [
+        ("X-Simulated-Latency-Ms", "2000"),
+        ("X-Request-Deadline-Ms", "200"),
+    ];
+    let (status, body) = send(
+        &app,
+        &routes::payment_intent_capture(&id),
+        &headers,
+        Some(json!({})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body["error"]["code"], "deadline_exceeded");
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let (status, events) = status_and_events(&pool, &id).await;
+    assert_eq!(status, "requires_capture");
+    assert!(!events.contains(&"payment_intent.captured".to_string()));
+    let (status, _) = send(
+        &app,
+        &routes::payment_intent_capture(&id),
+        &[],
+        Some(json!({})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_slow_statement_is_canceled_at_the_deadline(pool: PgPool) {
+    let app = sandbox_app(pool.clone());
+    let id = create_intent(&app).await;
+
+    // Stands in for a statement stuck behind something slow in Postgres
+    sqlx::raw_sql(
+        r#"
+        CREATE FUNCTION slow_update() RETURNS trigger AS $$
+        BEGIN PERFORM pg_sleep(2); RETURN NEW; END $$ LANGUAGE plpgsql;
+        CREATE TRIGGER slow_update BEFORE UPDATE ON payment_intents
+        FOR EACH ROW EXECUTE FUNCTION slow_update();
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let started = Instant::now();
+    let headers = [("X-Request-Deadline-Ms", "300")];
+    let (status, body) = send(&app, &routes::payment_intent_confirm(&id), &headers, None).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body["error"]["code"], "deadline_exceeded");
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let (status, events) = status_and_events(&pool, &id).await;
+    assert_eq!(status, "requires_confirmation");
+    assert_eq!(events, ["payment_intent.created"]);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_deadline_passing_during_commit_still_succeeds(pool: PgPool) {
+    let app = sandbox_app(pool.clone());
+    let id = create_intent(&app).await;
+
+    // A deferred trigger runs inside COMMIT, so this makes the commit itself take 1s
+    sqlx::raw_sql(
+        r#"
+        CREATE FUNCTION slow_commit() RETURNS trigger AS $$
+        BEGIN PERFORM pg_sleep(1); RETURN NULL; END $$ LANGUAGE plpgsql;
+        CREATE CONSTRAINT TRIGGER slow_commit AFTER UPDATE ON payment_intents
+        DEFERRABLE INITIALLY DEFERRED
+        FOR EACH ROW EXECUTE FUNCTION slow_commit();
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let started = Instant::now();
+    let headers = [("X-Request-Deadline-Ms", "500")];
+    let (status, body) = send(&app, &routes::payment_intent_confirm(&id), &headers, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "succeeded");
+    assert!(started.elapsed() >= Duration::from_secs(1));
+
+    let (status, events) = status_and_events(&pool, &id).await;
+    assert_eq!(status, "succeeded");
+    assert_eq!(
+        events,
+        ["payment_intent.created", "payment_intent.succeeded"]
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_create_given_up_on_can_be_retried_with_the_same_key(pool: PgPool) {
+    let app = sandbox_app(pool.clone());
+    let body = json!({ "amount": 1000, "currency": "gbp", "confirm": true });
+
+    let headers = [
+        ("Idempotency-Key", "deadline-retry"),
+        ("X-Simulated-Latency-Ms", "2000"),
+        ("X-Request-Deadline-Ms", "200"),
+    ];
+    let (status, err) = send(&app, routes::PAYMENT_INTENTS, &headers, Some(body.clone())).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(err["error"]["code"], "deadline_exceeded");
+    let intents = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM payment_intents"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(intents, 0);
+
+    let headers = [("Idempotency-Key", "deadline-retry")];
+    let (status, created) = send(&app, routes::PAYMENT_INTENTS, &headers, Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["status"], "succeeded");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn malformed_deadlines_are_rejected(pool: PgPool) {
+    let app = sandbox_app(pool);
+    let id = create_intent(&app).await;
+
+    for value in ["soon", "0", "1.5"] {
+        let headers = [("X-Request-Deadline-Ms", value)];
+        let (status, body) = send(&app, &routes::payment_intent_confirm(&id), &headers, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{value}");
+        assert_eq!(body["error"]["code"], "parameter_invalid", "{value}");
+    }
+}
@@ -0,0 +1,99 @@
+use api::routes;
+use api::{app::build_app, state::AppState};
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    http::{HeaderMap, Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use mini_stripe_types::signature::{RESPONSE_SIGNATURE_HEADER, verify_response};
+use serde_json::json;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn send(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, HeaderMap, Bytes) {
+    let mut builder = Request::builder().method(method).uri(uri);
+    let body = match body {
+        Some(body) => {
+            builder = builder.header("content-type", "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+
+    let res = app
+        .clone()
+        .oneshot(builder.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let headers = res.headers().clone();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, bytes)
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn mutating_responses_are_signed_when_enabled(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.response_signing_secret = Some("rs_test_secret".to_string());
+    let app = build_app(state);
+
+    let (status, headers, bytes) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        Some(json!({ "amount": 1000, "currency": "gbp" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let signature = headers[RESPONSE_SIGNATURE_HEADER].to_str().unwrap();
+    assert!(verify_response("rs_test_secret", signature, &bytes));
+
+    // Any change to the stored body, or the wrong secret, fails verification
+    let tampered = String::from_utf8(bytes.to_vec())
+        .unwrap()
+        .replace("1000", "9000");
+    assert!(!verify_response(
+        "rs_test_secret",
+        signature,
+        tampered.as_bytes()
+    ));
+    assert!(!verify_response("other_secret", signature, &bytes));
+
+    // Errors from mutating endpoints are signed too
+    let id = serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    send(&app, "POST", &routes::payment_intent_confirm(&id), None).await;
+    let (status, headers, bytes) =
+        send(&app, "POST", &routes::payment_intent_confirm(&id), None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let signature = headers[RESPONSE_SIGNATURE_HEADER].to_str().unwrap();
+    assert!(verify_response("rs_test_secret", signature, &bytes));
+
+    // Reads are not
+    let (status, headers, _) = send(&app, "GET", &routes::payment_intent(&id), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!headers.contains_key(RESPONSE_SIGNATURE_HEADER));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn responses_are_unsigned_by_default(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (status, headers, _) = send(
+        &app,
+        "POST",
+        routes::PAYMENT_INTENTS,
+        Some(json!({ "amount": 1000, "currency": "gbp" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(!headers.contains_key(RESPONSE_SIGNATURE_HEADER));
+}
@@ -0,0 +1,187 @@
+use api::routes;
+use api::{app::build_app, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use mini_stripe_types::signature::{self, SIGNATURE_HEADER, SignatureScheme};
+use serde_json::json;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn post_json(
+    app: &Router,
+    uri: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap_or_default())
+}
+
+fn sandbox_app(pool: PgPool) -> Router {
+    let mut state = AppState::new(pool);
+    state.config.sandbox_mode = true;
+    build_app(state)
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn echo_endpoint_rejected_outside_sandbox(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (status, _) = post_json(
+        &app,
+        routes::WEBHOOK_ENDPOINTS,
+        json!({ "url": "internal://echo" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn echo_receiver_records_signed_deliveries(pool: PgPool) {
+    let app = sandbox_app(pool.clone());
+
+    let (status, endpoint) = post_json(
+        &app,
+        routes::WEBHOOK_ENDPOINTS,
+        json!({ "url": "internal://echo" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let endpoint_id = endpoint["id"].as_str().unwrap().to_string();
+    let secret = endpoint["secret"].as_str().unwrap().to_string();
+
+    let (_, pi) = post_json(
+        &app,
+        routes::PAYMENT_INTENTS,
+        json!({ "amount": 1000, "currency": "gbp" }),
+    )
+    .await;
+    let pi_id = pi["id"].as_str().unwrap();
+    let (status, _) = post_json(&app, &routes::payment_intent_confirm(pi_id), json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Deliver the succeeded event the way the worker does
+    let row = sqlx::query!(
+        r#"
+        SELECT id, event_type, payload, created_at
+        FROM events_outbox
+        WHERE event_type = 'payment_intent.succeeded'
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let event = json!({
+        "id": row.id,
+        "type": row.event_type,
+        "created_at": row.created_at,
+        "data": row.payload
+    });
+    let bytes = serde_json::to_vec(&event).unwrap();
+    let sig = signature::sign(&secret, &bytes);
+
+    let deliver = |sig: String| {
+        Request::builder()
+            .method("POST")
+            .uri(routes::sandbox_echo(&endpoint_id))
+            .header("content-type", "application/json")
+            .header(SIGNATURE_HEADER, sig)
+            .body(Body::from(bytes.clone()))
+            .unwrap()
+    };
+
+    let res = app.clone().oneshot(deliver("00".repeat(32))).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = app.clone().oneshot(deliver(sig)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri(routes::SANDBOX_ECHO_DELIVERIES)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let deliveries: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(deliveries.as_array().unwrap().len(), 1);
+    assert_eq!(deliveries[0]["webhook_endpoint_id"], endpoint_id.as_str());
+    assert_eq!(deliveries[0]["event"]["type"], "payment_intent.succeeded");
+    assert_eq!(
+        deliveries[0]["event"]["data"]["payment_intent"]["id"],
+        pi_id
+    );
+}
+
+// A v2 endpoint accepts only v2 signatures, so a v1 header can't stand in for one
+#[sqlx::test(migrations = "./migrations")]
+async fn echo_receiver_verifies_with_the_endpoint_scheme(pool: PgPool) {
+    let app = sandbox_app(pool);
+
+    let (status, err) = post_json(
+        &app,
+        routes::WEBHOOK_ENDPOINTS,
+        json!({ "url": "internal://echo", "signature_scheme": "v3" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "parameter_invalid");
+
+    let (status, endpoint) = post_json(
+        &app,
+        routes::WEBHOOK_ENDPOINTS,
+        json!({ "url": "internal://echo", "signature_scheme": "v2" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(endpoint["signature_scheme"], "v2");
+    let endpoint_id = endpoint["id"].as_str().unwrap().to_string();
+    let secret = endpoint["secret"].as_str().unwrap();
+
+    let bytes = serde_json::to_vec(&json!({
+        "id": uuid::Uuid::new_v4(),
+        "type": "payment_intent.succeeded",
+        "data": {}
+    }))
+    .unwrap();
+    let deliver = |sig: String| {
+        Request::builder()
+            .method("POST")
+            .uri(routes::sandbox_echo(&endpoint_id))
+            .header("content-type", "application/json")
+            .header(SIGNATURE_HEADER, sig)
+            .body(Body::from(bytes.clone()))
+            .unwrap()
+    };
+
+    let v1 = signature::sign_webhook(SignatureScheme::V1, secret, 0, &bytes);
+    let res = app.clone().oneshot(deliver(v1)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let v2 = signature::sign_webhook(SignatureScheme::V2, secret, 1_700_000_000, &bytes);
+    let res = app.clone().oneshot(deliver(v2)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+}
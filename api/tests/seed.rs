@@ -0,0 +1,81 @@
+use api::routes;
+use api::{app::build_app, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn seed(app: &Router) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::ADMIN_SEED)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap_or_default())
+}
+
+async fn count(pool: &PgPool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn seed_is_404_outside_sandbox(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (status, _) = seed(&app).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn seeding_twice_creates_fixtures_once(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.sandbox_mode = true;
+    let app = build_app(state);
+
+    let (status, first) = seed(&app).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let statuses: Vec<_> = first["payment_intents"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|pi| pi["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, ["requires_confirmation", "succeeded", "canceled"]);
+
+    let counts = (
+        count(&pool, "payment_intents").await,
+        count(&pool, "webhook_endpoints").await,
+        count(&pool, "events_outbox").await,
+    );
+    // 3 created events + charge.succeeded and succeeded + canceled
+    assert_eq!(counts, (3, 1, 6));
+
+    let (status, second) = seed(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(second, first);
+    assert_eq!(
+        (
+            count(&pool, "payment_intents").await,
+            count(&pool, "webhook_endpoints").await,
+            count(&pool, "events_outbox").await,
+        ),
+        counts
+    );
+}
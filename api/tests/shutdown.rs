@@ -0,0 +1,184 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use api::routes;
+use api::{
+    app::{build_app, serve},
+    latency::SimulatedLatency,
+    payment_intents::storage::{NewPaymentIntent, insert_payment_intent},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use sqlx::PgPool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tower::ServiceExt;
+use tracing::instrument::WithSubscriber;
+use uuid::Uuid;
+
+// What a test subscriber wrote
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl std::io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+async fn insert_intent(pool: &PgPool) -> Uuid {
+    let new = NewPaymentIntent {
+        amount: 1000,
+        currency: "gbp".to_string(),
+        metadata: Default::default(),
+        natural_key: None,
+        capture_method: "automatic".to_string(),
+        description: None,
+        customer_id: None,
+    };
+    insert_payment_intent(pool, new).await.unwrap().id
+}
+
+// Plain HTTP/1.1 so the request really goes through the listener being shut down
+async fn raw_confirm(addr: std::net::SocketAddr, id: Uuid) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: req_{id}\r\n\
+         Content-Length: 0\r\nConnection: close\r\n\r\n",
+        routes::payment_intent_confirm(id)
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn shutdown_waits_for_in_flight_confirm(pool: PgPool) {
+    let id = insert_intent(&pool).await;
+
+    let mut state = AppState::new(pool.clone());
+    state.config.simulated_latency = Some(SimulatedLatency::fixed(500));
+    state.config.shutdown_timeout = Duration::from_secs(10);
+    let in_flight = state.in_flight.clone();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(serve(listener, state, async {
+        shutdown_rx.await.ok();
+    }));
+
+    let confirm = tokio::spawn(raw_confirm(addr, id));
+    while in_flight.count() == 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let started = Instant::now();
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert_eq!(in_flight.count(), 0);
+
+    let response = confirm.await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    let status: String = sqlx::query_scalar("SELECT status FROM payment_intents WHERE id = $1")
+        .bind(id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "succeeded");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn shutdown_gives_up_after_deadline(pool: PgPool) {
+    let id = insert_intent(&pool).await;
+
+    let mut state = AppState::new(pool);
+    state.config.simulated_latency = Some(SimulatedLatency::fixed(5_000));
+    state.config.shutdown_timeout = Duration::from_millis(100);
+    let in_flight = state.in_flight.clone();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let logs = Logs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .with_ansi(false)
+        .finish();
+    let server = tokio::spawn(
+        serve(listener, state, async {
+            shutdown_rx.await.ok();
+        })
+        .with_subscriber(subscriber),
+    );
+
+    tokio::spawn(raw_confirm(addr, id));
+    while in_flight.count() == 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let started = Instant::now();
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(
+        in_flight.operations(),
+        vec![format!("confirm payment_intent {id}")]
+    );
+
+    let logged = logs.contents();
+    assert!(
+        logged.contains("gave up waiting for in-flight operation")
+            && logged.contains(&format!("confirm payment_intent {id}"))
+            && logged.contains(&format!("request_id=\"req_{id}\"")),
+        "{logged}"
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn diagnostics_reports_in_flight_mutations(pool: PgPool) {
+    let state = AppState::new(pool);
+    let _guard = state.in_flight.track("create payment_intent");
+
+    let resp = build_app(state)
+        .oneshot(
+            Request::builder()
+                .uri(routes::ADMIN_DIAGNOSTICS)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json["in_flight_mutations"], 1);
+    assert_eq!(json["in_flight"][0], "create payment_intent");
+    assert_eq!(json["ready"], true);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use std::time::Duration;
+
+use api::{slow_queries, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode},
+    middleware,
+    routing::get,
+};
+use common::count_queries;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+// Test-only routes: one statement that sleeps for `ms`, behind the slow query middleware
+fn app(state: AppState) -> Router {
+    async fn sleep(
+        State(state): State<AppState>,
+        axum::extract::Path(ms): axum::extract::Path<i32>,
+    ) {
+        sqlx::query("SELECT pg_sleep($1::int / 1000.0)")
+            .bind(ms)
+            .execute(&state.db)
+            .await
+            .unwrap();
+    }
+
+    Router::new()
+        .route("/test/sleep/{ms}", get(sleep))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            slow_queries::instrument,
+        ))
+        .with_state(state)
+}
+
+async fn call(app: Router, uri: &str) {
+    let resp = app
+        .oneshot(
+            Request::builder()
+                .uri(uri)
+                .header(slow_queries::REQUEST_ID_HEADER, "req_123")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn statements_over_the_threshold_are_logged_per_route(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.slow_query_threshold = Some(Duration::from_millis(100));
+    let slow_queries = state.slow_queries.clone();
+
+    call(app(state.clone()), "/test/sleep/0").await;
+    assert_eq!(slow_queries.total("/test/sleep/{ms}"), 0);
+    assert!(slow_queries.recent().is_empty());
+
+    call(app(state), "/test/sleep/150").await;
+    assert_eq!(slow_queries.total("/test/sleep/{ms}"), 1);
+
+    let logged = slow_queries.recent();
+    assert_eq!(logged.len(), 1);
+    let entry = &logged[0];
+    assert_eq!(entry.level, "WARN");
+    assert_eq!(entry.route, "/test/sleep/{ms}");
+    assert_eq!(entry.request_id, "req_123");
+    assert_eq!(entry.threshold_ms, 100);
+    assert!(entry.duration_ms >= 150, "{entry:?}");
+    assert!(
+        entry
+            .statement
+            .starts_with("SELECT pg_sleep($1::int / ?) ["),
+        "{entry:?}"
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn disabled_threshold_logs_nothing_and_keeps_outer_subscribers(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.slow_query_threshold = None;
+    let slow_queries = state.slow_queries.clone();
+
+    call(app(state.clone()), "/test/sleep/150").await;
+    assert!(slow_queries.totals().is_empty());
+
+    // With the watcher installed, subscribers further out still see every statement
+    state.config.slow_query_threshold = Some(Duration::from_secs(5));
+    let (_, count) = count_queries(call(app(state), "/test/sleep/0")).await;
+    assert_eq!(count, 1);
+}
@@ -0,0 +1,71 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use api::routes;
+use api::{app::build_app, state::AppState, warmup};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+async fn readyz(app: &Router) -> StatusCode {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .uri(routes::READYZ)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn readyz_is_ready_without_warmup(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    assert_eq!(readyz(&app).await, StatusCode::OK);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn readyz_flips_after_warmup_and_leaves_no_data(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.readiness = warmup::Readiness::not_ready();
+    let app = build_app(state.clone());
+
+    assert_eq!(readyz(&app).await, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        state.readiness.warmed_connections.load(Ordering::Relaxed),
+        0
+    );
+
+    warmup::run(&state, 2, Duration::from_secs(10)).await;
+
+    assert_eq!(
+        state.readiness.warmed_connections.load(Ordering::Relaxed),
+        2
+    );
+    assert_eq!(readyz(&app).await, StatusCode::OK);
+
+    let rows: (i64, i64) = sqlx::query_as(
+        "SELECT (SELECT COUNT(*) FROM payment_intents), (SELECT COUNT(*) FROM events_outbox)",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(rows, (0, 0));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn warmup_timeout_still_marks_ready(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.readiness = warmup::Readiness::not_ready();
+
+    warmup::run(&state, 2, Duration::ZERO).await;
+
+    assert!(state.readiness.is_ready());
+}
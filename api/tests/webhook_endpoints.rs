@@ -1,3 +1,4 @@
+use api::routes;
 use api::{app::build_app, state::AppState};
 use axum::{
     body::Body,
@@ -10,7 +11,7 @@ use tower::ServiceExt;
 
 #[sqlx::test(migrations = "./migrations")]
 async fn create_and_list_webhook_endpoints(pool: PgPool) {
-    let app = build_app(AppState { db: pool });
+    let app = build_app(AppState::new(pool));
 
     let body = json!({ "url": "https://example.com/webhooks" }).to_string();
 
@@ -19,7 +20,7 @@ async fn create_and_list_webhook_endpoints(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/webhook_endpoints")
+                .uri(routes::WEBHOOK_ENDPOINTS)
                 .header("content-type", "application/json")
                 .body(Body::from(body))
                 .unwrap(),
@@ -41,7 +42,7 @@ async fn create_and_list_webhook_endpoints(pool: PgPool) {
         .oneshot(
             Request::builder()
                 .method("GET")
-                .uri("/v1/webhook_endpoints")
+                .uri(routes::WEBHOOK_ENDPOINTS)
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -59,3 +60,453 @@ async fn create_and_list_webhook_endpoints(pool: PgPool) {
     assert_eq!(first["url"], "https://example.com/webhooks");
     assert!(first.get("secret").is_none());
 }
+
+async fn create_endpoint(app: &axum::Router) -> serde_json::Value {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::WEBHOOK_ENDPOINTS)
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({ "url": "https://example.com/webhooks" }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn reveal(app: &axum::Router, id: &str) -> axum::response::Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::webhook_endpoint_reveal_secret(id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn reveal_secret_is_404_when_disabled(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let created = create_endpoint(&app).await;
+    let res = reveal(&app, created["id"].as_str().unwrap()).await;
+
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn reveal_secret_audits_and_caps_per_day(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.webhook_secret_reveal_enabled = true;
+    let app = build_app(state);
+
+    let created = create_endpoint(&app).await;
+    let id = created["id"].as_str().unwrap();
+
+    for _ in 0..3 {
+        let res = reveal(&app, id).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let bytes = res.into_body().collect().await.unwrap().to_bytes();
+        let revealed: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(revealed["secret"], created["secret"]);
+    }
+
+    let res = reveal(&app, id).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let audit_entries: i64 = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*)::bigint AS "count!"
+        FROM audit_log
+        WHERE action = 'webhook_endpoint.secret_revealed'
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(audit_entries, 3);
+
+    let event = sqlx::query!(
+        r#"
+        SELECT payload
+        FROM events_outbox
+        WHERE event_type = 'webhook_endpoint.secret_revealed'
+        LIMIT 1
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(event.payload["webhook_endpoint"]["id"], id);
+    assert!(event.payload["webhook_endpoint"].get("secret").is_none());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn get_webhook_endpoint_reports_circuit_state(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let created = create_endpoint(&app).await;
+    let id = created["id"].as_str().unwrap();
+
+    let circuit_state = |app: axum::Router| async move {
+        let res = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(routes::webhook_endpoint(id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let bytes = res.into_body().collect().await.unwrap().to_bytes();
+        let endpoint: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(endpoint.get("secret").is_none());
+        endpoint["circuit_state"].as_str().unwrap().to_string()
+    };
+
+    assert_eq!(circuit_state(app.clone()).await, "closed");
+
+    for (open_until, expected) in [
+        ("now() + interval '1 minute'", "open"),
+        ("now() - interval '1 second'", "half_open"),
+    ] {
+        sqlx::query(&format!(
+            "UPDATE webhook_endpoints SET circuit_open_until = {open_until}"
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(circuit_state(app.clone()).await, expected);
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn delivery_history_includes_response_snippets(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+
+    let created = create_endpoint(&app).await;
+    let id: uuid::Uuid = created["id"].as_str().unwrap().parse().unwrap();
+
+    let event_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO events_outbox (id, event_type, payload) VALUES ($1, 'payment_intent.created', '{}'::jsonb)",
+        event_id
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_deliveries (
+          id, event_id, webhook_endpoint_id, status, attempt_count,
+          response_status, response_snippet, response_content_length, response_truncated
+        )
+        VALUES ($1, $2, $3, 'succeeded', 1, 200, $4, 10485760, true)
+        "#,
+        uuid::Uuid::new_v4(),
+        event_id,
+        id,
+        "ok \"quoted\" ✅\n\t{\"nested\": true}"
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri(routes::webhook_endpoint_deliveries(id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let deliveries: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(deliveries[0]["event_id"], event_id.to_string());
+    assert_eq!(deliveries[0]["response_status"], 200);
+    assert_eq!(
+        deliveries[0]["response_snippet"],
+        "ok \"quoted\" ✅\n\t{\"nested\": true}"
+    );
+    assert_eq!(deliveries[0]["response_content_length"], 10485760);
+    assert_eq!(deliveries[0]["response_truncated"], true);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn delivery_history_shows_the_remaining_retry_schedule(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let created = create_endpoint(&app).await;
+    let id: uuid::Uuid = created["id"].as_str().unwrap().parse().unwrap();
+
+    // Attempt 3 just failed (retry in 8s), and one that used all 10 attempts
+    for (status, attempts, retry_in, created_at) in [
+        ("pending", 3, Some(8.0), "2026-01-02T00:00:00Z"),
+        ("failed", 10, None, "2026-01-01T00:00:00Z"),
+    ] {
+        let event_id = uuid::Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO events_outbox (id, event_type, payload) VALUES ($1, 'payment_intent.created', '{}'::jsonb)",
+            event_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_deliveries (
+              id, event_id, webhook_endpoint_id, status, attempt_count, next_attempt_at, created_at
+            )
+            VALUES ($1, $2, $3, $4, $5, now() + make_interval(secs => $6), $7::text::timestamptz)
+            "#,
+            uuid::Uuid::new_v4(),
+            event_id,
+            id,
+            status,
+            attempts,
+            retry_in,
+            created_at
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri(routes::webhook_endpoint_deliveries(id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let deliveries: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+    let pending = &deliveries[0];
+    assert_eq!(pending["attempts_remaining"], 7);
+    assert_eq!(pending["will_not_retry"], false);
+    let schedule: Vec<chrono::DateTime<chrono::Utc>> =
+        serde_json::from_value(pending["remaining_schedule"].clone()).unwrap();
+    let next: chrono::DateTime<chrono::Utc> =
+        serde_json::from_value(pending["next_attempt_at"].clone()).unwrap();
+    assert_eq!(schedule[0], next);
+    // Each backoff plus up to a quarter of it as jitter
+    let gaps: Vec<i64> = schedule
+        .windows(2)
+        .map(|w| (w[1] - w[0]).num_milliseconds())
+        .collect();
+    assert_eq!(gaps.len(), 6);
+    for (gap, base) in gaps.into_iter().zip([16, 32, 60, 60, 60, 60]) {
+        assert!((base * 1000..=base * 1250).contains(&gap), "{gap}ms");
+    }
+
+    let exhausted = &deliveries[1];
+    assert_eq!(exhausted["attempts_remaining"], 0);
+    assert_eq!(exhausted["remaining_schedule"], json!([]));
+    assert_eq!(exhausted["will_not_retry"], true);
+}
+
+async fn insert_delivery(
+    pool: &PgPool,
+    endpoint_id: uuid::Uuid,
+    status: &str,
+    age_days: i32,
+    response_time_ms: Option<i32>,
+    last_error: Option<&str>,
+) {
+    let event_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO events_outbox (id, event_type, payload) VALUES ($1, 'payment_intent.created', '{}'::jsonb)",
+        event_id
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_deliveries (
+          id, event_id, webhook_endpoint_id, status, attempt_count,
+          last_attempt_at, last_error, response_time_ms, created_at
+        )
+        VALUES (
+          $1, $2, $3, $4, 1,
+          now() - make_interval(days => $5), $6, $7,
+          now() - make_interval(days => $5)
+        )
+        "#,
+        uuid::Uuid::new_v4(),
+        event_id,
+        endpoint_id,
+        status,
+        age_days,
+        last_error,
+        response_time_ms
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+async fn stats(
+    app: &axum::Router,
+    id: uuid::Uuid,
+    window: &str,
+) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!(
+                    "{}?window={window}",
+                    routes::webhook_endpoint_stats(id)
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap_or_default())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn endpoint_stats_aggregate_delivery_history(pool: PgPool) {
+    let mut state = AppState::new(pool.clone());
+    state.config.webhook_delivery_retention = Some(std::time::Duration::from_secs(14 * 86400));
+    let app = build_app(state);
+
+    let created = create_endpoint(&app).await;
+    let id: uuid::Uuid = created["id"].as_str().unwrap().parse().unwrap();
+
+    // Outside the 7d window, inside 30d
+    insert_delivery(
+        &pool,
+        id,
+        "failed",
+        10,
+        Some(1000),
+        Some("non-2xx status: 500"),
+    )
+    .await;
+    // Inside 7d: three successes, then a failure and a retrying delivery
+    for (age, ms) in [(5, 10), (4, 20), (3, 30)] {
+        insert_delivery(&pool, id, "succeeded", age, Some(ms), None).await;
+    }
+    insert_delivery(
+        &pool,
+        id,
+        "failed",
+        2,
+        Some(40),
+        Some("non-2xx status: 500"),
+    )
+    .await;
+    insert_delivery(&pool, id, "pending", 1, None, Some("http error: timed out")).await;
+
+    let (status, week) = stats(&app, id, "7d").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(week["attempted"], 5);
+    assert_eq!(week["succeeded"], 3);
+    assert_eq!(week["failed"], 1);
+    assert_eq!(week["success_rate"], 0.75);
+    assert_eq!(week["p50_latency_ms"], 25.0);
+    assert_eq!(week["p95_latency_ms"], 38.5);
+    assert_eq!(week["consecutive_failures"], 2);
+    assert!(week["last_successful_delivery_at"].is_string());
+    assert_eq!(week["partial_data"], false);
+
+    // Longer than retention: counted from what is left, but flagged
+    let (_, month) = stats(&app, id, "30d").await;
+    assert_eq!(month["attempted"], 6);
+    assert_eq!(month["failed"], 2);
+    assert_eq!(month["partial_data"], true);
+
+    let (status, _) = stats(&app, id, "forever").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = stats(&app, uuid::Uuid::new_v4(), "7d").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+async fn post_endpoint(app: &axum::Router, url: &str) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(routes::WEBHOOK_ENDPOINTS)
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "url": url }).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn urls_are_normalized_before_the_duplicate_check(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (status, created) = post_endpoint(&app, "HTTPS://Example.com:443/a/../hooks/").await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["url"], "https://example.com/hooks");
+    assert_eq!(created["signature_scheme"], "v1");
+
+    let (status, err) = post_endpoint(&app, "https://example.com/hooks").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "webhook_endpoint_url_taken");
+
+    // A different query string is a different receiver
+    let (status, created) = post_endpoint(&app, "https://example.com/hooks?tenant=2").await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["url"], "https://example.com/hooks?tenant=2");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn errors_use_the_shared_error_shape(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+
+    let (status, err) = post_endpoint(&app, "not a url").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(err["error"]["code"], "url_invalid");
+    assert!(err["error"]["message"].is_string());
+
+    // Same shape and code as a missing payment_intent
+    for uri in [
+        routes::webhook_endpoint(uuid::Uuid::new_v4()),
+        routes::payment_intent(uuid::Uuid::new_v4()),
+    ] {
+        let res = app
+            .clone()
+            .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let bytes = res.into_body().collect().await.unwrap().to_bytes();
+        let err: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(err["error"]["code"], "resource_missing", "{uri}");
+    }
+}
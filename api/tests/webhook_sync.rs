@@ -0,0 +1,246 @@
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Method, Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+// (status, idempotent-replayed header, body)
+async fn sync(app: &Router, key: Option<&str>, endpoints: Value) -> (StatusCode, bool, Value) {
+    let mut req = Request::builder()
+        .method(Method::PUT)
+        .uri(routes::WEBHOOK_ENDPOINTS_SYNC)
+        .header("content-type", "application/json");
+    if let Some(key) = key {
+        req = req.header("Idempotency-Key", key);
+    }
+    let body = Body::from(json!({ "endpoints": endpoints }).to_string());
+    let res = app.clone().oneshot(req.body(body).unwrap()).await.unwrap();
+    let status = res.status();
+    let replayed = res.headers().contains_key("idempotent-replayed");
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        replayed,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+async fn send(app: &Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn external_ids(report: &Value, outcome: &str) -> Vec<String> {
+    report[outcome]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["external_id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_declaration_is_created_then_left_alone(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let declared = json!([
+        {
+            "external_id": "orders",
+            "url": "https://example.com/orders",
+            "enabled_events": ["payment_intent.succeeded", "payment_intent.created"],
+            "description": "order service"
+        },
+        { "external_id": "audit", "url": "https://example.com/audit" }
+    ]);
+
+    let (status, _, report) = sync(&app, None, declared.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{report}");
+    assert_eq!(external_ids(&report, "created"), ["orders", "audit"]);
+    for created in report["created"].as_array().unwrap() {
+        assert_eq!(created["secret"].as_str().unwrap().len(), 32);
+    }
+
+    let id = report["created"][0]["id"].as_str().unwrap();
+    let (_, endpoint) = send(&app, Method::GET, &routes::webhook_endpoint(id), None).await;
+    assert_eq!(endpoint["external_id"], "orders");
+    assert_eq!(endpoint["description"], "order service");
+    assert_eq!(
+        endpoint["enabled_events"],
+        json!(["payment_intent.created", "payment_intent.succeeded"])
+    );
+    let audit = report["created"][1]["id"].as_str().unwrap();
+    let (_, endpoint) = send(&app, Method::GET, &routes::webhook_endpoint(audit), None).await;
+    assert_eq!(endpoint["enabled_events"], json!(["*"]));
+
+    // Declaring the same thing again changes nothing
+    let (status, _, report) = sync(&app, None, declared).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(external_ids(&report, "unchanged"), ["orders", "audit"]);
+    for outcome in ["created", "updated", "disabled", "failed"] {
+        assert!(external_ids(&report, outcome).is_empty(), "{report}");
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn drift_is_corrected_and_absent_endpoints_are_disabled(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let (_, unmanaged) = send(
+        &app,
+        Method::POST,
+        routes::WEBHOOK_ENDPOINTS,
+        Some(json!({ "url": "https://example.com/manual" })),
+    )
+    .await;
+    let (_, _, report) = sync(
+        &app,
+        None,
+        json!([
+            { "external_id": "orders", "url": "https://example.com/orders" },
+            { "external_id": "audit", "url": "https://example.com/audit" }
+        ]),
+    )
+    .await;
+    let audit = report["created"][1]["id"].as_str().unwrap().to_string();
+
+    let (status, _, report) = sync(
+        &app,
+        None,
+        json!([{
+            "external_id": "orders",
+            "url": "https://example.com/orders/v2",
+            "enabled_events": ["payment_intent.canceled"]
+        }]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{report}");
+    assert_eq!(
+        report["updated"][0]["changed"],
+        json!(["url", "enabled_events"])
+    );
+    assert_eq!(external_ids(&report, "disabled"), ["audit"]);
+    let (_, endpoint) = send(&app, Method::GET, &routes::webhook_endpoint(&audit), None).await;
+    assert_eq!(endpoint["is_enabled"], false);
+
+    // Endpoints created without an external_id aren't the sync's to manage
+    let (_, endpoint) = send(
+        &app,
+        Method::GET,
+        &routes::webhook_endpoint(unmanaged["id"].as_str().unwrap()),
+        None,
+    )
+    .await;
+    assert_eq!(endpoint["is_enabled"], true);
+
+    // Declaring a disabled endpoint again brings it back
+    let (_, _, report) = sync(
+        &app,
+        None,
+        json!([
+            {
+                "external_id": "orders",
+                "url": "https://example.com/orders/v2",
+                "enabled_events": ["payment_intent.canceled"]
+            },
+            { "external_id": "audit", "url": "https://example.com/audit" }
+        ]),
+    )
+    .await;
+    assert_eq!(external_ids(&report, "unchanged"), ["orders"]);
+    assert_eq!(report["updated"][0]["id"], audit.as_str());
+    assert_eq!(report["updated"][0]["changed"], json!(["is_enabled"]));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn one_bad_entry_does_not_stop_the_rest(pool: PgPool) {
+    let app = build_app(AppState::new(pool));
+    let (status, _, report) = sync(
+        &app,
+        None,
+        json!([
+            { "external_id": "bad-url", "url": "not a url" },
+            {
+                "external_id": "bad-event",
+                "url": "https://example.com/a",
+                "enabled_events": ["payment_intent.exploded"]
+            },
+            {
+                "external_id": "star-and-more",
+                "url": "https://example.com/b",
+                "enabled_events": ["*", "payment_intent.created"]
+            },
+            { "external_id": "good", "url": "https://example.com/good" },
+            { "external_id": "same-url", "url": "https://example.com/good" }
+        ]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{report}");
+    assert_eq!(external_ids(&report, "created"), ["good"]);
+    assert_eq!(
+        external_ids(&report, "failed"),
+        ["bad-url", "bad-event", "star-and-more", "same-url"]
+    );
+    assert_eq!(report["failed"][0]["error"]["code"], "url_invalid");
+    assert_eq!(report["failed"][1]["error"]["code"], "parameter_invalid");
+    assert_eq!(
+        report["failed"][3]["error"]["code"],
+        "webhook_endpoint_url_taken"
+    );
+
+    // A declaration that can't be read as a whole is refused outright
+    let (status, _, error) = sync(
+        &app,
+        None,
+        json!([
+            { "external_id": "good", "url": "https://example.com/good" },
+            { "external_id": "good", "url": "https://example.com/other" }
+        ]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"]["code"], "parameter_invalid");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_retried_sync_replays_its_report_without_secrets(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let declared = json!([{ "external_id": "orders", "url": "https://example.com/orders" }]);
+
+    let (status, replayed, first) = sync(&app, Some("deploy-42"), declared.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!replayed);
+    let secret = first["created"][0]["secret"].as_str().unwrap();
+
+    let (status, replayed, again) = sync(&app, Some("deploy-42"), declared).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(replayed);
+    assert_eq!(again["created"][0]["id"], first["created"][0]["id"]);
+    assert!(again["created"][0].get("secret").is_none());
+
+    let (status, _, error) = sync(&app, Some("deploy-42"), json!([])).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error["error"]["code"], "idempotency_key_reused");
+
+    let stored = sqlx::query!(
+        "SELECT response_body, replay_count, conflict_count FROM idempotency_keys WHERE key = 'deploy-42'"
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(!stored.response_body.to_string().contains(secret));
+    assert_eq!((stored.replay_count, stored.conflict_count), (1, 1));
+}
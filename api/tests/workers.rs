@@ -0,0 +1,88 @@
+use api::{app::build_app, routes, state::AppState};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+async fn get(app: &Router, uri: &str) -> Value {
+    let res = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+// A worker's heartbeat, as the worker process writes it
+async fn heartbeat(pool: &PgPool, instance_id: Uuid, claimed: i64) {
+    sqlx::query!(
+        r#"
+        INSERT INTO worker_heartbeats (instance_id, hostname, pid, deliveries_claimed)
+        VALUES ($1, 'worker-host', 4242, $2)
+        "#,
+        instance_id,
+        claimed
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+// Mock clock: move one worker's heartbeat back instead of waiting
+async fn advance_clock(pool: &PgPool, instance_id: Uuid, secs: f64) {
+    sqlx::query!(
+        r#"
+        UPDATE worker_heartbeats
+        SET last_heartbeat_at = last_heartbeat_at - make_interval(secs => $2)
+        WHERE instance_id = $1
+        "#,
+        instance_id,
+        secs
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn a_worker_that_stops_beating_is_flagged_stale(pool: PgPool) {
+    let app = build_app(AppState::new(pool.clone()));
+    let (alive, silent) = (Uuid::new_v4(), Uuid::new_v4());
+    heartbeat(&pool, alive, 7).await;
+    heartbeat(&pool, silent, 3).await;
+
+    let workers = get(&app, routes::ADMIN_WORKERS).await;
+    assert_eq!(workers.as_array().unwrap().len(), 2);
+    assert!(
+        workers
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|w| w["stale"] == false)
+    );
+    let diagnostics = get(&app, routes::ADMIN_DIAGNOSTICS).await;
+    assert_eq!(diagnostics["workers"], 2);
+    assert_eq!(diagnostics["stale_workers"], serde_json::json!([]));
+
+    // Past the default 60s threshold
+    advance_clock(&pool, silent, 61.0).await;
+
+    let workers = get(&app, routes::ADMIN_WORKERS).await;
+    assert_eq!(workers[0]["instance_id"], alive.to_string());
+    assert_eq!(workers[0]["stale"], false);
+    assert_eq!(workers[0]["deliveries_claimed"], 7);
+    assert_eq!(workers[1]["instance_id"], silent.to_string());
+    assert_eq!(workers[1]["stale"], true);
+    assert_eq!(workers[1]["hostname"], "worker-host");
+
+    let diagnostics = get(&app, routes::ADMIN_DIAGNOSTICS).await;
+    assert_eq!(diagnostics["stale_workers"], serde_json::json!([silent]));
+}
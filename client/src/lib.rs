@@ -0,0 +1,385 @@
+use mini_stripe_types::balance::{
+    BalanceResponse, BalanceTransactionList, BalanceTransactionResponse,
+};
+use mini_stripe_types::charges::{ChargeList, ChargeResponse};
+use mini_stripe_types::customers::{
+    CreateCustomerRequest, CustomerList, CustomerResponse, DeletedCustomerResponse,
+    UpdateCustomerRequest,
+};
+use mini_stripe_types::error::ErrorResponse;
+use mini_stripe_types::payment_intents::{
+    CapturePaymentIntentRequest, ConfirmPaymentIntentRequest, CreatePaymentIntentRequest,
+    PaymentIntentResponse,
+};
+use mini_stripe_types::payment_methods::{
+    AttachPaymentMethodRequest, CreatePaymentMethodRequest, PaymentMethodList,
+    PaymentMethodResponse,
+};
+use mini_stripe_types::payouts::{CreatePayoutRequest, PayoutList, PayoutResponse, PayoutStatus};
+use mini_stripe_types::refunds::{CreateRefundRequest, RefundList, RefundResponse};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+pub mod webhooks;
+
+pub use mini_stripe_types as types;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // Any non-2xx with the API's error body; `code` is one of types::error::codes
+    #[error("{status}: {code}: {message}")]
+    Api {
+        status: StatusCode,
+        code: String,
+        message: String,
+    },
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+// Thin async client for the payment intents, charges, refunds, customers, payment methods,
+// balance and payouts APIs
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    pub fn with_http_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    // Sent as `Authorization: Bearer` on every request; needed once the API has API_KEYS
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    fn authorized(&self, builder: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(key) => builder.bearer_auth(key),
+            None => builder,
+        }
+    }
+
+    // Retries with the same key and request return the original intent
+    pub async fn create_payment_intent(
+        &self,
+        req: &CreatePaymentIntentRequest,
+        idempotency_key: Option<&str>,
+    ) -> Result<PaymentIntentResponse, Error> {
+        let mut builder = self
+            .http
+            .post(format!("{}/v1/payment_intents", self.base_url))
+            .json(req);
+        if let Some(key) = idempotency_key {
+            builder = builder.header("Idempotency-Key", key);
+        }
+        send(self.authorized(builder)).await
+    }
+
+    pub async fn retrieve_payment_intent(&self, id: Uuid) -> Result<PaymentIntentResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .get(format!("{}/v1/payment_intents/{id}", self.base_url)),
+            ),
+        )
+        .await
+    }
+
+    // `payment_method: None` still goes through the processor, which then decides from the amount
+    pub async fn confirm_payment_intent(
+        &self,
+        id: Uuid,
+        req: &ConfirmPaymentIntentRequest,
+    ) -> Result<PaymentIntentResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .post(format!("{}/v1/payment_intents/{id}/confirm", self.base_url))
+                    .json(req),
+            ),
+        )
+        .await
+    }
+
+    // Only for intents created with `capture_method: manual`, once they reach requires_capture.
+    // `amount_to_capture: None` captures the whole amount.
+    pub async fn capture_payment_intent(
+        &self,
+        id: Uuid,
+        req: &CapturePaymentIntentRequest,
+    ) -> Result<PaymentIntentResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .post(format!("{}/v1/payment_intents/{id}/capture", self.base_url))
+                    .json(req),
+            ),
+        )
+        .await
+    }
+
+    pub async fn retrieve_charge(&self, id: Uuid) -> Result<ChargeResponse, Error> {
+        send(self.authorized(self.http.get(format!("{}/v1/charges/{id}", self.base_url)))).await
+    }
+
+    // Newest first; an intent has at most one charge, from when it succeeded. Pass the last id
+    // of a page to get the next one.
+    pub async fn list_charges(
+        &self,
+        payment_intent: Option<Uuid>,
+        starting_after: Option<Uuid>,
+    ) -> Result<ChargeList, Error> {
+        let mut builder = self.http.get(format!("{}/v1/charges", self.base_url));
+        if let Some(payment_intent) = payment_intent {
+            builder = builder.query(&[("payment_intent", payment_intent)]);
+        }
+        if let Some(starting_after) = starting_after {
+            builder = builder.query(&[("starting_after", starting_after)]);
+        }
+        send(self.authorized(builder)).await
+    }
+
+    // Refunds `amount` of the charge, or whatever is left of it when None
+    pub async fn create_refund(&self, req: &CreateRefundRequest) -> Result<RefundResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .post(format!("{}/v1/refunds", self.base_url))
+                    .json(req),
+            ),
+        )
+        .await
+    }
+
+    pub async fn retrieve_refund(&self, id: Uuid) -> Result<RefundResponse, Error> {
+        send(self.authorized(self.http.get(format!("{}/v1/refunds/{id}", self.base_url)))).await
+    }
+
+    // Newest first; pass the last id of a page to get the next one
+    pub async fn list_refunds(
+        &self,
+        payment_intent: Option<Uuid>,
+        starting_after: Option<Uuid>,
+    ) -> Result<RefundList, Error> {
+        let mut builder = self.http.get(format!("{}/v1/refunds", self.base_url));
+        if let Some(payment_intent) = payment_intent {
+            builder = builder.query(&[("payment_intent", payment_intent)]);
+        }
+        if let Some(starting_after) = starting_after {
+            builder = builder.query(&[("starting_after", starting_after)]);
+        }
+        send(self.authorized(builder)).await
+    }
+
+    pub async fn create_customer(
+        &self,
+        req: &CreateCustomerRequest,
+    ) -> Result<CustomerResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .post(format!("{}/v1/customers", self.base_url))
+                    .json(req),
+            ),
+        )
+        .await
+    }
+
+    pub async fn retrieve_customer(&self, id: Uuid) -> Result<CustomerResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .get(format!("{}/v1/customers/{id}", self.base_url)),
+            ),
+        )
+        .await
+    }
+
+    pub async fn update_customer(
+        &self,
+        id: Uuid,
+        req: &UpdateCustomerRequest,
+    ) -> Result<CustomerResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .patch(format!("{}/v1/customers/{id}", self.base_url))
+                    .json(req),
+            ),
+        )
+        .await
+    }
+
+    pub async fn delete_customer(&self, id: Uuid) -> Result<DeletedCustomerResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .delete(format!("{}/v1/customers/{id}", self.base_url)),
+            ),
+        )
+        .await
+    }
+
+    // Newest first, without deleted customers; pass the last id of a page to get the next one
+    pub async fn list_customers(
+        &self,
+        email: Option<&str>,
+        starting_after: Option<Uuid>,
+    ) -> Result<CustomerList, Error> {
+        let mut builder = self.http.get(format!("{}/v1/customers", self.base_url));
+        if let Some(email) = email {
+            builder = builder.query(&[("email", email)]);
+        }
+        if let Some(starting_after) = starting_after {
+            builder = builder.query(&[("starting_after", starting_after)]);
+        }
+        send(self.authorized(builder)).await
+    }
+
+    pub async fn create_payment_method(
+        &self,
+        req: &CreatePaymentMethodRequest,
+    ) -> Result<PaymentMethodResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .post(format!("{}/v1/payment_methods", self.base_url))
+                    .json(req),
+            ),
+        )
+        .await
+    }
+
+    pub async fn retrieve_payment_method(&self, id: Uuid) -> Result<PaymentMethodResponse, Error> {
+        let url = format!("{}/v1/payment_methods/{id}", self.base_url);
+        send(self.authorized(self.http.get(url))).await
+    }
+
+    pub async fn attach_payment_method(
+        &self,
+        id: Uuid,
+        customer: Uuid,
+    ) -> Result<PaymentMethodResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .post(format!("{}/v1/payment_methods/{id}/attach", self.base_url))
+                    .json(&AttachPaymentMethodRequest { customer }),
+            ),
+        )
+        .await
+    }
+
+    pub async fn detach_payment_method(&self, id: Uuid) -> Result<PaymentMethodResponse, Error> {
+        let url = format!("{}/v1/payment_methods/{id}/detach", self.base_url);
+        send(self.authorized(self.http.post(url))).await
+    }
+
+    // The customer's saved payment methods, newest first; pass the last id of a page to get the
+    // next one
+    pub async fn list_payment_methods(
+        &self,
+        customer: Uuid,
+        starting_after: Option<Uuid>,
+    ) -> Result<PaymentMethodList, Error> {
+        let mut builder = self
+            .http
+            .get(format!("{}/v1/payment_methods", self.base_url))
+            .query(&[("customer", customer)]);
+        if let Some(starting_after) = starting_after {
+            builder = builder.query(&[("starting_after", starting_after)]);
+        }
+        send(self.authorized(builder)).await
+    }
+
+    pub async fn retrieve_balance(&self) -> Result<BalanceResponse, Error> {
+        send(self.authorized(self.http.get(format!("{}/v1/balance", self.base_url)))).await
+    }
+
+    pub async fn retrieve_balance_transaction(
+        &self,
+        id: Uuid,
+    ) -> Result<BalanceTransactionResponse, Error> {
+        let url = format!("{}/v1/balance_transactions/{id}", self.base_url);
+        send(self.authorized(self.http.get(url))).await
+    }
+
+    // Newest first; pass the last id of a page to get the next one
+    pub async fn list_balance_transactions(
+        &self,
+        starting_after: Option<Uuid>,
+    ) -> Result<BalanceTransactionList, Error> {
+        let mut builder = self
+            .http
+            .get(format!("{}/v1/balance_transactions", self.base_url));
+        if let Some(starting_after) = starting_after {
+            builder = builder.query(&[("starting_after", starting_after)]);
+        }
+        send(self.authorized(builder)).await
+    }
+
+    pub async fn create_payout(&self, req: &CreatePayoutRequest) -> Result<PayoutResponse, Error> {
+        send(
+            self.authorized(
+                self.http
+                    .post(format!("{}/v1/payouts", self.base_url))
+                    .json(req),
+            ),
+        )
+        .await
+    }
+
+    pub async fn retrieve_payout(&self, id: Uuid) -> Result<PayoutResponse, Error> {
+        send(self.authorized(self.http.get(format!("{}/v1/payouts/{id}", self.base_url)))).await
+    }
+
+    // Newest first; pass the last id of a page to get the next one
+    pub async fn list_payouts(
+        &self,
+        status: Option<PayoutStatus>,
+        starting_after: Option<Uuid>,
+    ) -> Result<PayoutList, Error> {
+        let mut builder = self.http.get(format!("{}/v1/payouts", self.base_url));
+        if let Some(status) = status {
+            builder = builder.query(&[("status", status.as_str())]);
+        }
+        if let Some(starting_after) = starting_after {
+            builder = builder.query(&[("starting_after", starting_after)]);
+        }
+        send(self.authorized(builder)).await
+    }
+}
+
+async fn send<T: DeserializeOwned>(builder: RequestBuilder) -> Result<T, Error> {
+    let res = builder.send().await?;
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res.json().await?);
+    }
+
+    // Anything that isn't our error shape (a proxy's 502 page, say) keeps just its status
+    let body = res.bytes().await?;
+    let (code, message) = match serde_json::from_slice::<ErrorResponse>(&body) {
+        Ok(ErrorResponse { error }) => (error.code, error.message),
+        Err(_) => (String::new(), String::from_utf8_lossy(&body).into_owned()),
+    };
+    Err(Error::Api {
+        status,
+        code,
+        message,
+    })
+}
@@ -0,0 +1,90 @@
+use mini_stripe_types::events::{
+    ChargeEventData, EventType, PaymentIntentEventData, PayoutEventData, RefundEventData,
+    WebhookEvent,
+};
+use mini_stripe_types::signature::{SignatureScheme, verify_signature};
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("webhook signature verification failed")]
+    SignatureInvalid,
+    #[error("invalid webhook body: {0}")]
+    Body(#[from] serde_json::Error),
+}
+
+// For a receiver: checks the X-Ministripe-Signature header against the raw body bytes (before
+// any re-encoding), then parses them. `scheme` is the endpoint's signature_scheme.
+pub fn construct_event(
+    body: &[u8],
+    signature: &str,
+    secret: &str,
+    scheme: SignatureScheme,
+) -> Result<WebhookEvent, WebhookError> {
+    if !verify_signature(scheme, secret, signature, body) {
+        return Err(WebhookError::SignatureInvalid);
+    }
+    Ok(serde_json::from_slice(body)?)
+}
+
+// `data` of a payment_intent.* event. None for other types, and for thin events
+// (`payload_truncated`), whose data only holds the object's id.
+pub fn payment_intent_data(
+    event: &WebhookEvent,
+) -> Result<Option<PaymentIntentEventData>, WebhookError> {
+    let is_payment_intent = match event.kind() {
+        Some(
+            EventType::PaymentIntentCreated
+            | EventType::PaymentIntentSucceeded
+            | EventType::PaymentIntentPaymentFailed
+            | EventType::PaymentIntentRequiresAction
+            | EventType::PaymentIntentCanceled
+            | EventType::PaymentIntentUpdated
+            | EventType::PaymentIntentCaptured,
+        ) => true,
+        Some(
+            EventType::ChargeSucceeded
+            | EventType::RefundCreated
+            | EventType::PayoutPaid
+            | EventType::PayoutFailed
+            | EventType::ServiceLatencyDegraded
+            | EventType::ServiceLatencyRecovered
+            | EventType::WebhookEndpointSecretRevealed
+            | EventType::WebhookEndpointCertificateExpiring
+            | EventType::WebhookDeliveriesDailyDigest,
+        )
+        | None => false,
+    };
+    if !is_payment_intent || event.payload_truncated {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(event.data.clone())?))
+}
+
+// `data` of a charge.* event. None for other types and for thin events, like
+// payment_intent_data.
+pub fn charge_data(event: &WebhookEvent) -> Result<Option<ChargeEventData>, WebhookError> {
+    if event.kind() != Some(EventType::ChargeSucceeded) || event.payload_truncated {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(event.data.clone())?))
+}
+
+// `data` of a refund.* event. None for other types and for thin events.
+pub fn refund_data(event: &WebhookEvent) -> Result<Option<RefundEventData>, WebhookError> {
+    if event.kind() != Some(EventType::RefundCreated) || event.payload_truncated {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(event.data.clone())?))
+}
+
+// `data` of a payout.* event. None for other types and for thin events.
+pub fn payout_data(event: &WebhookEvent) -> Result<Option<PayoutEventData>, WebhookError> {
+    if !matches!(
+        event.kind(),
+        Some(EventType::PayoutPaid | EventType::PayoutFailed)
+    ) || event.payload_truncated
+    {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(event.data.clone())?))
+}
@@ -0,0 +1,153 @@
+use api::permissions::ApiKeys;
+use api::{app::build_app, state::AppState};
+use mini_stripe_client::{Client, Error};
+use mini_stripe_types::error::codes;
+use mini_stripe_types::payment_intents::{
+    ConfirmPaymentIntentRequest, CreatePaymentIntentRequest, PaymentIntentStatus,
+};
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+// The real router on a local port, so requests go through reqwest and the wire types end to end
+async fn spawn_api(pool: PgPool) -> Client {
+    spawn_api_with(AppState::new(pool)).await
+}
+
+async fn spawn_api_with(state: AppState) -> Client {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, build_app(state)).into_future());
+    Client::new(format!("http://{addr}"))
+}
+
+fn gbp(amount: i64) -> CreatePaymentIntentRequest {
+    CreatePaymentIntentRequest {
+        amount,
+        currency: Some("gbp".to_string()),
+        confirm: false,
+        payment_method: None,
+        metadata: Default::default(),
+        capture_method: None,
+        description: None,
+        customer: None,
+    }
+}
+
+#[sqlx::test(migrations = "../api/migrations")]
+async fn create_retrieve_and_confirm(pool: PgPool) {
+    let client = spawn_api(pool).await;
+
+    let created = client
+        .create_payment_intent(&gbp(1000), Some("client-key"))
+        .await
+        .unwrap();
+    assert_eq!(created.amount, 1000);
+    assert_eq!(created.status, PaymentIntentStatus::RequiresConfirmation);
+
+    let replayed = client
+        .create_payment_intent(&gbp(1000), Some("client-key"))
+        .await
+        .unwrap();
+    assert_eq!(replayed, created);
+
+    // GET's extra summaries are ignored by the shared response type
+    let fetched = client.retrieve_payment_intent(created.id).await.unwrap();
+    assert_eq!(fetched, created);
+
+    let confirmed = client
+        .confirm_payment_intent(created.id, &ConfirmPaymentIntentRequest::default())
+        .await
+        .unwrap();
+    assert_eq!(confirmed.status, PaymentIntentStatus::Succeeded);
+}
+
+#[sqlx::test(migrations = "../api/migrations")]
+async fn api_errors_carry_status_and_code(pool: PgPool) {
+    let client = spawn_api(pool).await;
+
+    let err = client
+        .retrieve_payment_intent(Uuid::new_v4())
+        .await
+        .unwrap_err();
+    let Error::Api { status, code, .. } = err else {
+        panic!("expected an API error, got {err}");
+    };
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(code, codes::RESOURCE_MISSING);
+
+    let created = client.create_payment_intent(&gbp(500), None).await.unwrap();
+    client
+        .confirm_payment_intent(created.id, &ConfirmPaymentIntentRequest::default())
+        .await
+        .unwrap();
+    let err = client
+        .confirm_payment_intent(created.id, &ConfirmPaymentIntentRequest::default())
+        .await
+        .unwrap_err();
+    let Error::Api { status, code, .. } = err else {
+        panic!("expected an API error, got {err}");
+    };
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(code, codes::PAYMENT_INTENT_UNEXPECTED_STATE);
+}
+
+#[sqlx::test(migrations = "../api/migrations")]
+async fn declined_payment_methods_are_card_declined_errors(pool: PgPool) {
+    let client = spawn_api(pool).await;
+
+    let created = client.create_payment_intent(&gbp(500), None).await.unwrap();
+    let declined = ConfirmPaymentIntentRequest {
+        payment_method: Some("pm_card_declined".to_string()),
+    };
+    let err = client
+        .confirm_payment_intent(created.id, &declined)
+        .await
+        .unwrap_err();
+    let Error::Api { status, code, .. } = err else {
+        panic!("expected an API error, got {err}");
+    };
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(code, codes::CARD_DECLINED);
+
+    let fetched = client.retrieve_payment_intent(created.id).await.unwrap();
+    assert_eq!(fetched.status, PaymentIntentStatus::RequiresPaymentMethod);
+    assert_eq!(fetched.payment_method.as_deref(), Some("pm_card_declined"));
+}
+
+#[sqlx::test(migrations = "../api/migrations")]
+async fn requests_carry_the_api_key(pool: PgPool) {
+    let mut state = AppState::new(pool);
+    state.config.api_keys = ApiKeys::parse("full:sk_client,read_only:rk_client").unwrap();
+    let client = spawn_api_with(state).await;
+
+    let err = client
+        .create_payment_intent(&gbp(1000), None)
+        .await
+        .unwrap_err();
+    let Error::Api { status, code, .. } = err else {
+        panic!("expected an API error, got {err}");
+    };
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(code, codes::AUTHENTICATION_REQUIRED);
+
+    let created = client
+        .clone()
+        .with_api_key("sk_client")
+        .create_payment_intent(&gbp(1000), None)
+        .await
+        .unwrap();
+
+    let read_only = client.with_api_key("rk_client");
+    read_only.retrieve_payment_intent(created.id).await.unwrap();
+    let err = read_only
+        .confirm_payment_intent(created.id, &ConfirmPaymentIntentRequest::default())
+        .await
+        .unwrap_err();
+    let Error::Api { status, code, .. } = err else {
+        panic!("expected an API error, got {err}");
+    };
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(code, codes::INSUFFICIENT_SCOPE);
+}
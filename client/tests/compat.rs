@@ -0,0 +1,323 @@
+// Wire compatibility between what the server writes to the outbox, the envelope the worker posts
+// and what a receiver using this crate reads back, for every event type
+use std::time::Duration;
+
+use api::events_outbox::insert_event;
+use api::latency_alerts::LatencyAlert;
+use api::{app::build_app, state::AppState};
+use chrono::{DateTime, Utc};
+use mini_stripe_client::Client;
+use mini_stripe_client::webhooks::{
+    WebhookError, charge_data, construct_event, payment_intent_data, payout_data, refund_data,
+};
+use mini_stripe_types::charges::{ChargeResponse, ChargeStatus};
+use mini_stripe_types::events::{
+    ChargeEventData, EventType, PaymentIntentEventData, PayoutEventData, RefundEventData,
+    WebhookEvent,
+};
+use mini_stripe_types::payment_intents::{
+    ConfirmPaymentIntentRequest, CreatePaymentIntentRequest, PaymentIntentResponse,
+    PaymentIntentStatus,
+};
+use mini_stripe_types::payouts::{PayoutResponse, PayoutStatus};
+use mini_stripe_types::refunds::{CreateRefundRequest, RefundResponse, RefundStatus};
+use mini_stripe_types::signature::{SignatureScheme, sign_webhook};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+const SECRET: &str = "whsec_compat";
+
+fn intent(status: &str) -> PaymentIntentResponse {
+    PaymentIntentResponse {
+        id: Uuid::new_v4(),
+        amount: 1000,
+        currency: "gbp".to_string(),
+        status: PaymentIntentStatus::parse(status).unwrap(),
+        cancellation_reason: (status == "canceled").then(|| "abandoned".to_string()),
+        last_payment_error: (status == "requires_payment_method")
+            .then(|| json!({ "code": "card_declined", "message": "Your card was declined." })),
+        metadata: [("order_id".to_string(), "ord_1".to_string())].into(),
+        capture_method: "automatic".to_string(),
+        amount_received: if status == "succeeded" { 1000 } else { 0 },
+        description: Some("Order ord_1".to_string()),
+        expires_at: Some(Utc::now() + chrono::Duration::hours(24)),
+        payment_method: Some("pm_card_visa".to_string()),
+        customer: Some(Uuid::new_v4()),
+    }
+}
+
+fn latency_alert(event_type: &'static str, recovered: bool) -> LatencyAlert {
+    LatencyAlert {
+        event_type,
+        route: "/v1/payment_intents/{id}/confirm".to_string(),
+        threshold_ms: 500,
+        p95_ms: 900,
+        max_ms: 1200,
+        requests: 50,
+        window_started_at: DateTime::UNIX_EPOCH,
+        window_ended_at: DateTime::UNIX_EPOCH + Duration::from_secs(60),
+        degraded_since: DateTime::UNIX_EPOCH,
+        recovered_at: recovered.then(Utc::now),
+    }
+}
+
+// The payload the server writes for each type. No wildcard arm: a new EventType doesn't compile
+// until it has a sample here, and so until this test covers it.
+fn sample_payload(t: EventType) -> Value {
+    let pi = |status: &str, simulated_outcome: Option<&str>| {
+        serde_json::to_value(PaymentIntentEventData {
+            payment_intent: intent(status),
+            simulated_outcome: simulated_outcome.map(str::to_string),
+            previous_attributes: None,
+        })
+        .unwrap()
+    };
+    match t {
+        EventType::PaymentIntentCreated => pi("requires_confirmation", None),
+        EventType::PaymentIntentSucceeded => pi("succeeded", None),
+        EventType::PaymentIntentPaymentFailed => pi("requires_payment_method", Some("decline")),
+        EventType::PaymentIntentRequiresAction => pi("requires_action", Some("requires_action")),
+        EventType::PaymentIntentCanceled => pi("canceled", None),
+        EventType::PaymentIntentCaptured => pi("succeeded", None),
+        EventType::PaymentIntentUpdated => serde_json::to_value(PaymentIntentEventData {
+            payment_intent: intent("requires_confirmation"),
+            simulated_outcome: None,
+            previous_attributes: Some(json!({ "amount": 500, "metadata": { "order_id": null } })),
+        })
+        .unwrap(),
+        EventType::ChargeSucceeded => serde_json::to_value(ChargeEventData {
+            charge: ChargeResponse {
+                id: Uuid::new_v4(),
+                payment_intent: Uuid::new_v4(),
+                amount: 1000,
+                currency: "gbp".to_string(),
+                status: ChargeStatus::Succeeded,
+                created_at: Utc::now(),
+                amount_refunded: 0,
+                refunded: false,
+            },
+        })
+        .unwrap(),
+        EventType::RefundCreated => serde_json::to_value(RefundEventData {
+            refund: RefundResponse {
+                id: Uuid::new_v4(),
+                charge: Uuid::new_v4(),
+                payment_intent: Uuid::new_v4(),
+                amount: 400,
+                currency: "gbp".to_string(),
+                status: RefundStatus::Succeeded,
+                created_at: Utc::now(),
+            },
+        })
+        .unwrap(),
+        EventType::PayoutPaid | EventType::PayoutFailed => {
+            let failed = t == EventType::PayoutFailed;
+            serde_json::to_value(PayoutEventData {
+                payout: PayoutResponse {
+                    id: Uuid::new_v4(),
+                    amount: 2500,
+                    currency: "gbp".to_string(),
+                    status: if failed {
+                        PayoutStatus::Failed
+                    } else {
+                        PayoutStatus::Paid
+                    },
+                    destination: "ba_test".to_string(),
+                    description: None,
+                    arrival_date: Utc::now(),
+                    failure_code: failed.then(|| "no_account".to_string()),
+                    failure_message: failed
+                        .then(|| "The bank account details don't match an account.".to_string()),
+                    created_at: Utc::now(),
+                },
+            })
+            .unwrap()
+        }
+        EventType::ServiceLatencyDegraded => {
+            serde_json::to_value(latency_alert(t.as_str(), false)).unwrap()
+        }
+        EventType::ServiceLatencyRecovered => {
+            serde_json::to_value(latency_alert(t.as_str(), true)).unwrap()
+        }
+        // Built inline by reveal_webhook_endpoint_secret
+        EventType::WebhookEndpointSecretRevealed => json!({
+            "webhook_endpoint": { "id": Uuid::new_v4(), "url": "https://example.com/hooks" }
+        }),
+        // Built inline by the worker's emit_certificate_expiry_warnings
+        EventType::WebhookEndpointCertificateExpiring => json!({
+            "webhook_endpoint": { "id": Uuid::new_v4(), "url": "https://example.com/hooks" },
+            "client_certificate": {
+                "fingerprint": "3f".repeat(32),
+                "subject": "CN=mini-stripe",
+                "expires_at": "2026-11-15T00:00:00Z",
+            },
+        }),
+        // Built inline by the worker's emit_daily_digest
+        EventType::WebhookDeliveriesDailyDigest => json!({
+            "date": "2026-10-15",
+            "window_start": "2026-10-15T00:00:00Z",
+            "window_end": "2026-10-16T00:00:00Z",
+            "failed_deliveries": 2,
+            "endpoints": [{ "webhook_endpoint_id": Uuid::new_v4(), "failed": 2 }],
+            "top_event_types": [{ "event_type": "payment_intent.created", "failed": 2 }],
+            "failed_delivery_ids": [Uuid::new_v4(), Uuid::new_v4()],
+            "failed_delivery_ids_truncated": false,
+        }),
+    }
+}
+
+// The worker's request body for an outbox row: the WebhookEvent envelope, as JSON bytes
+async fn delivery_bodies(pool: &PgPool) -> Vec<(String, Vec<u8>)> {
+    let rows: Vec<(Uuid, String, Value, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT id, event_type, payload, created_at FROM events_outbox ORDER BY sequence",
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap();
+    rows.into_iter()
+        .map(|(id, event_type, payload, created_at)| {
+            let envelope = json!(WebhookEvent {
+                id,
+                event_type: event_type.clone(),
+                created_at: Some(created_at),
+                data: payload,
+                payload_truncated: false,
+            });
+            (event_type, serde_json::to_vec(&envelope).unwrap())
+        })
+        .collect()
+}
+
+// Signs like the worker, reads like a receiver, and checks nothing was lost on the way
+fn receive(body: &[u8], scheme: SignatureScheme) -> WebhookEvent {
+    let signature = sign_webhook(scheme, SECRET, Utc::now().timestamp(), body);
+    let event = construct_event(body, &signature, SECRET, scheme).unwrap();
+    let sent: Value = serde_json::from_slice(body).unwrap();
+    assert_eq!(serde_json::to_value(&event).unwrap(), sent);
+
+    let other = match scheme {
+        SignatureScheme::V1 => SignatureScheme::V2,
+        SignatureScheme::V2 => SignatureScheme::V1,
+    };
+    assert!(matches!(
+        construct_event(body, &signature, SECRET, other),
+        Err(WebhookError::SignatureInvalid)
+    ));
+    event
+}
+
+#[sqlx::test(migrations = "../api/migrations")]
+async fn every_event_type_round_trips(pool: PgPool) {
+    let mut samples = Vec::new();
+    for t in EventType::ALL {
+        let payload = sample_payload(*t);
+        insert_event(&pool, t.as_str(), payload.clone())
+            .await
+            .unwrap();
+        samples.push((*t, payload));
+    }
+
+    let bodies = delivery_bodies(&pool).await;
+    assert_eq!(bodies.len(), EventType::ALL.len());
+    for ((t, payload), (event_type, body)) in samples.into_iter().zip(bodies) {
+        assert_eq!(event_type, t.as_str());
+        for scheme in [SignatureScheme::V1, SignatureScheme::V2] {
+            let event = receive(&body, scheme);
+            assert_eq!(event.kind(), Some(t));
+            assert_eq!(event.data, payload, "{event_type}");
+
+            let typed = payment_intent_data(&event).unwrap();
+            match typed {
+                Some(data) => assert_eq!(serde_json::to_value(data).unwrap(), payload),
+                None => assert!(!event_type.starts_with("payment_intent."), "{event_type}"),
+            }
+            match charge_data(&event).unwrap() {
+                Some(data) => assert_eq!(serde_json::to_value(data).unwrap(), payload),
+                None => assert!(!event_type.starts_with("charge."), "{event_type}"),
+            }
+            match refund_data(&event).unwrap() {
+                Some(data) => assert_eq!(serde_json::to_value(data).unwrap(), payload),
+                None => assert!(!event_type.starts_with("refund."), "{event_type}"),
+            }
+            match payout_data(&event).unwrap() {
+                Some(data) => assert_eq!(serde_json::to_value(data).unwrap(), payload),
+                None => assert!(!event_type.starts_with("payout."), "{event_type}"),
+            }
+        }
+    }
+}
+
+// Events from the real create and confirm handlers decode to the intent the API returns
+#[sqlx::test(migrations = "../api/migrations")]
+async fn payment_intent_events_match_the_api(pool: PgPool) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, build_app(AppState::new(pool.clone()))).into_future());
+    let client = Client::new(format!("http://{addr}"));
+
+    let req = CreatePaymentIntentRequest {
+        amount: 1000,
+        currency: Some("gbp".to_string()),
+        confirm: false,
+        payment_method: None,
+        metadata: [("order_id".to_string(), "ord_1".to_string())].into(),
+        capture_method: None,
+        description: Some("Order ord_1".to_string()),
+        customer: None,
+    };
+    let created = client.create_payment_intent(&req, None).await.unwrap();
+    let confirmed = client
+        .confirm_payment_intent(created.id, &ConfirmPaymentIntentRequest::default())
+        .await
+        .unwrap();
+
+    let events: Vec<_> = delivery_bodies(&pool)
+        .await
+        .iter()
+        .map(|(_, body)| receive(body, SignatureScheme::V2))
+        .collect();
+    let kinds: Vec<_> = events.iter().map(|e| e.kind()).collect();
+    assert_eq!(
+        kinds,
+        [
+            Some(EventType::PaymentIntentCreated),
+            Some(EventType::ChargeSucceeded),
+            Some(EventType::PaymentIntentSucceeded)
+        ]
+    );
+    let data: Vec<_> = events
+        .iter()
+        .filter_map(|e| payment_intent_data(e).unwrap())
+        .map(|data| data.payment_intent)
+        .collect();
+    assert_eq!(data, [created, confirmed.clone()]);
+
+    // The charge event holds the charge the API returns
+    let charge = charge_data(&events[1]).unwrap().unwrap().charge;
+    assert_eq!(charge.payment_intent, confirmed.id);
+    assert_eq!(charge.amount, confirmed.amount_received);
+    assert_eq!(client.retrieve_charge(charge.id).await.unwrap(), charge);
+    let listed = client.list_charges(Some(confirmed.id), None).await.unwrap();
+    assert_eq!(listed.data, std::slice::from_ref(&charge));
+    assert!(!listed.has_more);
+
+    // So does the refund event
+    let refund = client
+        .create_refund(&CreateRefundRequest {
+            charge: Some(charge.id),
+            amount: Some(400),
+            ..CreateRefundRequest::default()
+        })
+        .await
+        .unwrap();
+    let (_, body) = delivery_bodies(&pool).await.pop().unwrap();
+    let event = receive(&body, SignatureScheme::V2);
+    assert_eq!(refund_data(&event).unwrap().unwrap().refund, refund);
+    assert_eq!(client.retrieve_refund(refund.id).await.unwrap(), refund);
+    let listed = client.list_refunds(Some(confirmed.id), None).await.unwrap();
+    assert_eq!(listed.data, [refund]);
+    let charge = client.retrieve_charge(charge.id).await.unwrap();
+    assert_eq!((charge.amount_refunded, charge.refunded), (400, false));
+}
@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// What moved the balance. `source` on the transaction is the charge, refund or payout's id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceTransactionType {
+    Charge,
+    Refund,
+    Payout,
+    // Gives a failed payout's amount back
+    PayoutFailure,
+}
+
+impl BalanceTransactionType {
+    pub const ALL: &[BalanceTransactionType] = &[
+        Self::Charge,
+        Self::Refund,
+        Self::Payout,
+        Self::PayoutFailure,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Charge => "charge",
+            Self::Refund => "refund",
+            Self::Payout => "payout",
+            Self::PayoutFailure => "payout_failure",
+        }
+    }
+
+    // None for a type this version doesn't know
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| t.as_str() == s)
+    }
+}
+
+impl std::fmt::Display for BalanceTransactionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Pending until available_on, then available. Not stored: it follows from the clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceTransactionStatus {
+    Available,
+    Pending,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceTransactionResponse {
+    pub id: Uuid,
+    // Gross, in minor units: positive for a charge or payout failure, negative for a refund or
+    // payout
+    pub amount: i64,
+    // What the processor kept; 0 for anything but a charge
+    pub fee: i64,
+    // amount - fee, what the balance actually moved by
+    pub net: i64,
+    pub currency: String,
+    #[serde(rename = "type")]
+    pub kind: BalanceTransactionType,
+    pub source: Uuid,
+    pub status: BalanceTransactionStatus,
+    pub available_on: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+// GET /v1/balance_transactions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceTransactionList {
+    // Always `list`
+    pub object: String,
+    pub data: Vec<BalanceTransactionResponse>,
+    pub has_more: bool,
+    // The page size used and the most a request can get
+    pub limit: i64,
+    pub max_limit: i64,
+    // E.g. that the requested limit was clamped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceAmount {
+    pub amount: i64,
+    pub currency: String,
+}
+
+// GET /v1/balance: the sum of net per currency, split by whether it is available yet. Every
+// currency with a transaction appears in both, with 0 where it has nothing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceResponse {
+    pub available: Vec<BalanceAmount>,
+    pub pending: Vec<BalanceAmount>,
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Only successful payments are recorded as charges for now; failed attempts stay on the intent's
+// last_payment_error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeStatus {
+    Succeeded,
+}
+
+impl ChargeStatus {
+    pub const ALL: &[ChargeStatus] = &[Self::Succeeded];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+        }
+    }
+
+    // None for a status this version doesn't know
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|status| status.as_str() == s)
+    }
+}
+
+impl std::fmt::Display for ChargeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// The money an intent moved when it succeeded: `amount` is its amount_received
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChargeResponse {
+    pub id: Uuid,
+    pub payment_intent: Uuid,
+    pub amount: i64,
+    pub currency: String,
+    pub status: ChargeStatus,
+    pub created_at: DateTime<Utc>,
+    // The sum of its refunds, never more than `amount`
+    #[serde(default)]
+    pub amount_refunded: i64,
+    // Whether all of `amount` has been refunded
+    #[serde(default)]
+    pub refunded: bool,
+}
+
+// GET /v1/charges
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChargeList {
+    // Always `list`
+    pub object: String,
+    pub data: Vec<ChargeResponse>,
+    pub has_more: bool,
+    // The page size used and the most a request can get
+    pub limit: i64,
+    pub max_limit: i64,
+    // E.g. that the requested limit was clamped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_round_trip_through_their_wire_names() {
+        for &status in ChargeStatus::ALL {
+            assert_eq!(ChargeStatus::parse(status.as_str()), Some(status));
+            assert_eq!(
+                serde_json::to_value(status).unwrap(),
+                serde_json::json!(status.as_str())
+            );
+        }
+        assert_eq!(ChargeStatus::parse("refunded"), None);
+    }
+}
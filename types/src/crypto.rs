@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+
+// Encryption at rest for secret columns, shared by the API and the worker so both read the same
+// format. A stored value is
+//
+//   enc:<key id>:<hex of 12-byte nonce || AES-256-GCM ciphertext and tag>
+//
+// with the column's name as associated data, so a value copied into another column won't
+// decrypt. Values without the `enc:` prefix were written before keys were configured and are
+// read as they are; `mini-stripe-api rotate` encrypts them.
+
+const PREFIX: &str = "enc:";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const MAX_KEY_ID_LEN: usize = 32;
+
+// Secret columns, named `table.column`
+pub const WEBHOOK_ENDPOINT_SECRET: EncryptedColumn =
+    EncryptedColumn::new("webhook_endpoints.secret");
+// Client certificate and private key presented to mTLS receivers, as one PEM bundle
+pub const WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE: EncryptedColumn =
+    EncryptedColumn::new("webhook_endpoints.client_certificate");
+
+// Every key that may have encrypted a stored value, plus the one new values are encrypted with.
+// Rotating: add the new key and make it the encryption key everywhere, run `rotate`, then drop
+// the old key. Empty (the default) stores new values as plaintext, like before keys existed.
+#[derive(Clone, Default)]
+pub struct KeyRing {
+    keys: BTreeMap<String, Aes256Gcm>,
+    encryption_key_id: Option<String>,
+}
+
+// Never prints key material
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyRing")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .field("encryption_key_id", &self.encryption_key_id)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRingError(pub String);
+
+impl fmt::Display for KeyRingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for KeyRingError {}
+
+impl KeyRing {
+    // `keys` is `id:<64 hex chars>,...`. `encryption_key_id` may be left out when there is only
+    // one key.
+    pub fn parse(keys: &str, encryption_key_id: Option<&str>) -> Result<Self, KeyRingError> {
+        let invalid = |msg: String| Err(KeyRingError(msg));
+        let mut ring = Self::default();
+
+        for entry in keys.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((id, hex_key)) = entry.split_once(':') else {
+                return invalid(format!("`{entry}` is not `id:<hex key>`"));
+            };
+            if !valid_key_id(id) {
+                return invalid(format!(
+                    "key id `{id}` must be 1-{MAX_KEY_ID_LEN} letters, digits, `_` or `-`"
+                ));
+            }
+            let key = hex::decode(hex_key.trim())
+                .ok()
+                .filter(|k| k.len() == KEY_LEN)
+                .ok_or_else(|| {
+                    KeyRingError(format!("key `{id}` must be {KEY_LEN} bytes of hex"))
+                })?;
+            let cipher = Aes256Gcm::new_from_slice(&key).expect("key length checked");
+            if ring.keys.insert(id.to_string(), cipher).is_some() {
+                return invalid(format!("key id `{id}` is listed twice"));
+            }
+        }
+
+        ring.encryption_key_id = match encryption_key_id.map(str::trim) {
+            Some(id) if ring.keys.contains_key(id) => Some(id.to_string()),
+            Some(id) => return invalid(format!("encryption key `{id}` is not in the key list")),
+            None if ring.keys.len() <= 1 => ring.keys.keys().next().cloned(),
+            None => return invalid("several keys need an encryption key id".to_string()),
+        };
+        Ok(ring)
+    }
+
+    // ENCRYPTION_KEYS and ENCRYPTION_KEY_ID; neither set is an empty ring
+    pub fn from_env() -> Result<Self, KeyRingError> {
+        let keys = std::env::var("ENCRYPTION_KEYS").unwrap_or_default();
+        let encryption_key_id = std::env::var("ENCRYPTION_KEY_ID").ok();
+        Self::parse(&keys, encryption_key_id.as_deref())
+    }
+
+    pub fn encryption_key_id(&self) -> Option<&str> {
+        self.encryption_key_id.as_deref()
+    }
+}
+
+fn valid_key_id(id: &str) -> bool {
+    (1..=MAX_KEY_ID_LEN).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+// Every way a stored value can fail to decrypt (malformed, unknown key id, wrong key, tampered)
+// is this one error, so a caller can't be used to tell them apart. The tag check itself is
+// constant-time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecryptError;
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("stored value could not be decrypted")
+    }
+}
+
+impl std::error::Error for DecryptError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncryptedColumn {
+    name: &'static str,
+}
+
+impl EncryptedColumn {
+    pub const fn new(name: &'static str) -> Self {
+        Self { name }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // Under the ring's encryption key with a fresh random nonce; plaintext when the ring is empty
+    pub fn encrypt(&self, ring: &KeyRing, plaintext: &str) -> String {
+        let Some(id) = ring.encryption_key_id() else {
+            return plaintext.to_string();
+        };
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: self.name.as_bytes(),
+        };
+        let ciphertext = ring.keys[id]
+            .encrypt(&nonce, payload)
+            .expect("AES-GCM encryption of a small value");
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        format!("{PREFIX}{id}:{}", hex::encode(sealed))
+    }
+
+    pub fn decrypt(&self, ring: &KeyRing, stored: &str) -> Result<String, DecryptError> {
+        let Some(rest) = stored.strip_prefix(PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let (id, sealed) = rest.split_once(':').ok_or(DecryptError)?;
+        let cipher = ring.keys.get(id).ok_or(DecryptError)?;
+        let sealed = hex::decode(sealed).map_err(|_| DecryptError)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(DecryptError);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: self.name.as_bytes(),
+        };
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| DecryptError)?;
+        String::from_utf8(plaintext).map_err(|_| DecryptError)
+    }
+
+    // Whether `rotate` would leave this value alone: it is under the encryption key, or is
+    // plaintext and there is no key to encrypt it with
+    pub fn is_current(&self, ring: &KeyRing, stored: &str) -> bool {
+        key_id(stored) == ring.encryption_key_id()
+    }
+}
+
+// The key a stored value is encrypted under; None for plaintext
+pub fn key_id(stored: &str) -> Option<&str> {
+    let rest = stored.strip_prefix(PREFIX)?;
+    rest.split_once(':').map(|(id, _)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLUMN: EncryptedColumn = EncryptedColumn::new("things.secret");
+
+    fn key(byte: u8) -> String {
+        hex::encode([byte; KEY_LEN])
+    }
+
+    fn ring(keys: &[(&str, u8)], encrypt_with: &str) -> KeyRing {
+        let keys = keys
+            .iter()
+            .map(|(id, byte)| format!("{id}:{}", key(*byte)))
+            .collect::<Vec<_>>()
+            .join(",");
+        KeyRing::parse(&keys, Some(encrypt_with)).unwrap()
+    }
+
+    #[test]
+    fn values_round_trip_with_a_fresh_nonce_each_time() {
+        let ring = ring(&[("k1", 1)], "k1");
+        let first = COLUMN.encrypt(&ring, "whsec_abc");
+        let second = COLUMN.encrypt(&ring, "whsec_abc");
+
+        assert!(first.starts_with("enc:k1:"), "{first}");
+        assert!(!first.contains("whsec_abc"));
+        assert_ne!(first, second);
+        assert_eq!(COLUMN.decrypt(&ring, &first).unwrap(), "whsec_abc");
+        assert_eq!(COLUMN.decrypt(&ring, &second).unwrap(), "whsec_abc");
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let ring = ring(&[("k1", 1), ("k2", 2)], "k1");
+        let stored = COLUMN.encrypt(&ring, "whsec_abc");
+        let sealed = stored.strip_prefix("enc:k1:").unwrap();
+
+        // Flip one bit in the nonce, the ciphertext and the tag in turn
+        for at in [0, NONCE_LEN * 2 + 1, sealed.len() - 1] {
+            let mut bytes = hex::decode(sealed).unwrap();
+            bytes[at / 2] ^= 1;
+            let tampered = format!("enc:k1:{}", hex::encode(bytes));
+            assert_eq!(COLUMN.decrypt(&ring, &tampered), Err(DecryptError), "{at}");
+        }
+
+        let relabelled = stored.replace("enc:k1:", "enc:k2:");
+        let truncated = &stored[..stored.len() - 2];
+        for bad in [
+            relabelled.as_str(),
+            truncated,
+            "enc:k1:zz",
+            "enc:k1:00",
+            "enc:k1",
+        ] {
+            assert_eq!(COLUMN.decrypt(&ring, bad), Err(DecryptError), "{bad}");
+        }
+
+        // The column name is bound in, so a value moved to another column is rejected too
+        let other = EncryptedColumn::new("others.secret");
+        assert_eq!(other.decrypt(&ring, &stored), Err(DecryptError));
+    }
+
+    #[test]
+    fn a_wrong_or_missing_key_fails_the_same_way() {
+        let stored = COLUMN.encrypt(&ring(&[("k1", 1)], "k1"), "whsec_abc");
+
+        let wrong = ring(&[("k1", 9)], "k1");
+        let missing = ring(&[("k2", 2)], "k2");
+        assert_eq!(COLUMN.decrypt(&wrong, &stored), Err(DecryptError));
+        assert_eq!(COLUMN.decrypt(&missing, &stored), Err(DecryptError));
+        assert_eq!(
+            COLUMN.decrypt(&KeyRing::default(), &stored),
+            Err(DecryptError)
+        );
+    }
+
+    #[test]
+    fn rotation_keeps_old_values_readable() {
+        let old = ring(&[("k1", 1)], "k1");
+        let before = COLUMN.encrypt(&old, "whsec_abc");
+
+        // Mid-rotation: both keys decrypt, new values use k2
+        let rotating = ring(&[("k1", 1), ("k2", 2)], "k2");
+        assert!(!COLUMN.is_current(&rotating, &before));
+        assert_eq!(COLUMN.decrypt(&rotating, &before).unwrap(), "whsec_abc");
+        let after = COLUMN.encrypt(&rotating, &COLUMN.decrypt(&rotating, &before).unwrap());
+        assert_eq!(key_id(&after), Some("k2"));
+        assert!(COLUMN.is_current(&rotating, &after));
+
+        // Once everything is rotated k1 can go
+        let new = ring(&[("k2", 2)], "k2");
+        assert_eq!(COLUMN.decrypt(&new, &after).unwrap(), "whsec_abc");
+        assert_eq!(COLUMN.decrypt(&new, &before), Err(DecryptError));
+    }
+
+    #[test]
+    fn plaintext_from_before_encryption_is_still_read() {
+        let ring = ring(&[("k1", 1)], "k1");
+        assert_eq!(COLUMN.decrypt(&ring, "whsec_abc").unwrap(), "whsec_abc");
+        assert!(!COLUMN.is_current(&ring, "whsec_abc"));
+
+        // An empty ring keeps writing plaintext, which is then current
+        let empty = KeyRing::default();
+        assert_eq!(COLUMN.encrypt(&empty, "whsec_abc"), "whsec_abc");
+        assert!(COLUMN.is_current(&empty, "whsec_abc"));
+    }
+
+    #[test]
+    fn key_rings_are_validated() {
+        let one = format!("k1:{}", key(1));
+        let two = format!("k1:{},k2:{}", key(1), key(2));
+
+        assert_eq!(
+            KeyRing::parse(&one, None).unwrap().encryption_key_id(),
+            Some("k1")
+        );
+        assert_eq!(
+            KeyRing::parse(&two, Some("k2"))
+                .unwrap()
+                .encryption_key_id(),
+            Some("k2")
+        );
+        assert_eq!(KeyRing::parse("", None).unwrap().encryption_key_id(), None);
+
+        let short = format!("k1:{}", hex::encode([1u8; 16]));
+        let bad_id = format!("k 1:{}", key(1));
+        let twice = format!("k1:{},k1:{}", key(1), key(2));
+        for (keys, encrypt_with) in [
+            (two.as_str(), None),
+            (two.as_str(), Some("k3")),
+            (short.as_str(), None),
+            ("k1:not-hex", None),
+            (bad_id.as_str(), None),
+            (twice.as_str(), Some("k1")),
+            ("k1", None),
+        ] {
+            assert!(
+                KeyRing::parse(keys, encrypt_with).is_err(),
+                "{keys} {encrypt_with:?}"
+            );
+        }
+
+        let debug = format!("{:?}", KeyRing::parse(&one, None).unwrap());
+        assert!(!debug.contains(&key(1)), "{debug}");
+    }
+}
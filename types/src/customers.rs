@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// POST /v1/customers. Every field is optional, as on Stripe.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CreateCustomerRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+// PATCH /v1/customers/{id}. Like a payment intent update: an empty string clears a field, and
+// metadata is merged into the existing keys, where an empty value removes a key.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UpdateCustomerRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomerResponse {
+    pub id: Uuid,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// DELETE /v1/customers/{id}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeletedCustomerResponse {
+    pub id: Uuid,
+    // Always true
+    pub deleted: bool,
+}
+
+// GET /v1/customers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomerList {
+    // Always `list`
+    pub object: String,
+    pub data: Vec<CustomerResponse>,
+    pub has_more: bool,
+    // The page size used and the most a request can get
+    pub limit: i64,
+    pub max_limit: i64,
+    // E.g. that the requested limit was clamped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
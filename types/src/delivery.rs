@@ -0,0 +1,171 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Attempts a webhook delivery gets before it is marked failed for good, unless the worker's
+// WEBHOOK_MAX_DELIVERY_ATTEMPTS says otherwise
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 10;
+
+// Backoff after a failed attempt: 2^attempts seconds, up to this, plus up to a quarter of that
+// again as jitter
+const MAX_RETRY_DELAY_SECS: i64 = 60;
+
+// What a delivery's retries depend on besides its attempt count, both from its row
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // Seeds the jitter
+    pub delivery_id: Uuid,
+    pub max_attempts: i32,
+}
+
+// How long the worker waits after the `attempt_count`th attempt failed. None when that was the
+// last attempt. The worker schedules with this, so the API's schedule can't disagree with it.
+pub fn retry_delay(policy: RetryPolicy, attempt_count: i32) -> Option<Duration> {
+    if attempt_count >= policy.max_attempts {
+        return None;
+    }
+    let base_ms = 1000
+        * 2_i64
+            .pow(attempt_count.clamp(0, 10) as u32)
+            .min(MAX_RETRY_DELAY_SECS);
+    let jitter_ms = jitter(policy.delivery_id, attempt_count, base_ms / 4);
+    Some(Duration::milliseconds(base_ms + jitter_ms))
+}
+
+// 0..=max, so the deliveries an endpoint outage failed together don't all come back at once.
+// Mixed from the delivery and attempt rather than random: the same inputs always give the same
+// delay, which is what lets the API show the worker's schedule.
+fn jitter(delivery_id: Uuid, attempt_count: i32, max: i64) -> i64 {
+    let (high, low) = delivery_id.as_u64_pair();
+    // splitmix64's finalizer
+    let mut x = high ^ low ^ (attempt_count as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    (x % (max as u64 + 1)) as i64
+}
+
+// What is left of a delivery's retries, shown on delivery objects
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetrySchedule {
+    pub attempts_remaining: i32,
+    // When each remaining attempt runs if every one before it fails, next one first
+    pub remaining_schedule: Vec<DateTime<Utc>>,
+    // Failed for good: retries are exhausted (a redelivery starts a new generation)
+    pub will_not_retry: bool,
+}
+
+// `status`, `attempt_count` and `next_attempt_at` as stored on webhook_deliveries. `now` stands
+// in for the next attempt of a delivery that is due (no next_attempt_at) or being attempted.
+pub fn retry_schedule(
+    policy: RetryPolicy,
+    status: &str,
+    attempt_count: i32,
+    next_attempt_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> RetrySchedule {
+    let first = match status {
+        "pending" if attempt_count < policy.max_attempts => Some(next_attempt_at.unwrap_or(now)),
+        // The running attempt could still fail: the next one follows its backoff
+        "in_progress" => retry_delay(policy, attempt_count).map(|delay| now + delay),
+        _ => None,
+    };
+
+    let mut remaining_schedule = Vec::new();
+    if let Some(mut at) = first {
+        // The attempt that runs at `at`
+        let mut attempt = attempt_count + 1;
+        loop {
+            remaining_schedule.push(at);
+            match retry_delay(policy, attempt) {
+                Some(delay) => at += delay,
+                None => break,
+            }
+            attempt += 1;
+        }
+    }
+
+    RetrySchedule {
+        attempts_remaining: remaining_schedule.len() as i32,
+        remaining_schedule,
+        will_not_retry: status == "failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        delivery_id: Uuid::from_u128(0x6f1c_0e6a_2b1d_4d3e_9a57_1f0c_8e4b_2a10),
+        max_attempts: MAX_DELIVERY_ATTEMPTS,
+    };
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH + Duration::seconds(secs)
+    }
+
+    #[test]
+    fn delays_double_up_to_the_cap_and_stop_at_the_last_attempt() {
+        let bases = [2, 4, 8, 16, 32, 60, 60, 60, 60];
+        for (n, base) in (1..).zip(bases) {
+            let delay = retry_delay(POLICY, n).unwrap().num_milliseconds();
+            assert!(
+                (base * 1000..=base * 1250).contains(&delay),
+                "attempt {n}: {delay}ms"
+            );
+            assert_eq!(retry_delay(POLICY, n), retry_delay(POLICY, n));
+        }
+        assert_eq!(retry_delay(POLICY, MAX_DELIVERY_ATTEMPTS), None);
+
+        let short = RetryPolicy {
+            max_attempts: 2,
+            ..POLICY
+        };
+        assert!(retry_delay(short, 1).is_some());
+        assert_eq!(retry_delay(short, 2), None);
+    }
+
+    #[test]
+    fn jitter_differs_between_deliveries() {
+        let delays: std::collections::BTreeSet<_> = (0..20)
+            .map(|i| {
+                let policy = RetryPolicy {
+                    delivery_id: Uuid::from_u128(i),
+                    ..POLICY
+                };
+                retry_delay(policy, 6).unwrap()
+            })
+            .collect();
+        assert!(delays.len() > 10, "{delays:?}");
+    }
+
+    #[test]
+    fn a_pending_delivery_lists_every_remaining_attempt() {
+        // Attempt 3 failed at t=0, so attempt 4 runs at t=8
+        let schedule = retry_schedule(POLICY, "pending", 3, Some(at(8)), at(1));
+        assert_eq!(schedule.attempts_remaining, 7);
+        assert_eq!(schedule.remaining_schedule[0], at(8));
+        // Each gap is the backoff after the attempt before it
+        for (attempt, pair) in (4..).zip(schedule.remaining_schedule.windows(2)) {
+            assert_eq!(pair[1] - pair[0], retry_delay(POLICY, attempt).unwrap());
+        }
+        assert!(!schedule.will_not_retry);
+    }
+
+    #[test]
+    fn finished_deliveries_have_no_schedule() {
+        let failed = retry_schedule(POLICY, "failed", MAX_DELIVERY_ATTEMPTS, None, at(0));
+        assert_eq!(failed.attempts_remaining, 0);
+        assert!(failed.remaining_schedule.is_empty());
+        assert!(failed.will_not_retry);
+
+        let succeeded = retry_schedule(POLICY, "succeeded", 1, None, at(0));
+        assert!(succeeded.remaining_schedule.is_empty());
+        assert!(!succeeded.will_not_retry);
+
+        // The running attempt is the last one
+        let last = retry_schedule(POLICY, "in_progress", MAX_DELIVERY_ATTEMPTS, None, at(0));
+        assert_eq!(last.attempts_remaining, 0);
+    }
+}
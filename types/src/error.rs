@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+// Every error response is `{"error": {"code", "message"}}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: ErrorDetail,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorDetail {
+    pub code: String,
+    pub message: String,
+}
+
+// `error.code` values clients are expected to branch on
+pub mod codes {
+    pub const PARAMETER_INVALID: &str = "parameter_invalid";
+    pub const CURRENCY_NOT_ALLOWED: &str = "currency_not_allowed";
+    pub const AMOUNT_TOO_SMALL: &str = "amount_too_small";
+    pub const AMOUNT_TOO_LARGE: &str = "amount_too_large";
+    pub const RESOURCE_MISSING: &str = "resource_missing";
+    pub const SANDBOX_ONLY: &str = "sandbox_only";
+    pub const AUTHENTICATION_REQUIRED: &str = "authentication_required";
+    pub const INSUFFICIENT_SCOPE: &str = "insufficient_scope";
+    pub const IDEMPOTENCY_KEY_REUSED: &str = "idempotency_key_reused";
+    pub const IDEMPOTENCY_KEY_INVALID_ENCODING: &str = "idempotency_key_invalid_encoding";
+    pub const PAYMENT_INTENT_UNEXPECTED_STATE: &str = "payment_intent_unexpected_state";
+    pub const PAYMENT_INTENT_EXPIRED_FOR_CONFIRMATION: &str =
+        "payment_intent_expired_for_confirmation";
+    pub const PAYMENT_INTENT_IMMUTABLE: &str = "payment_intent_immutable";
+    pub const CARD_DECLINED: &str = "card_declined";
+    pub const EXPIRED_CARD: &str = "expired_card";
+    pub const INCORRECT_CVC: &str = "incorrect_cvc";
+    pub const PROCESSING_ERROR: &str = "processing_error";
+    pub const ACQUIRER_TIMEOUT: &str = "acquirer_timeout";
+    pub const CHARGE_ALREADY_REFUNDED: &str = "charge_already_refunded";
+    pub const BALANCE_INSUFFICIENT: &str = "balance_insufficient";
+    pub const SIGNATURE_INVALID: &str = "signature_invalid";
+    pub const PAYLOAD_TEMPLATE_INVALID: &str = "payload_template_invalid";
+    pub const CLIENT_CERTIFICATE_INVALID: &str = "client_certificate_invalid";
+    pub const EVENT_BACKLOG: &str = "event_backlog";
+    pub const TRANSACTION_CONFLICT: &str = "transaction_conflict";
+    pub const TOO_MANY_WAITERS: &str = "too_many_waiters";
+    pub const DEADLINE_EXCEEDED: &str = "deadline_exceeded";
+    pub const SERVICE_UNAVAILABLE: &str = "service_unavailable";
+    pub const INTERNAL_ERROR: &str = "internal_error";
+}
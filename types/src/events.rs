@@ -0,0 +1,228 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::charges::ChargeResponse;
+use crate::payment_intents::PaymentIntentResponse;
+use crate::payouts::PayoutResponse;
+use crate::refunds::RefundResponse;
+
+pub const PAYMENT_INTENT_CREATED: &str = "payment_intent.created";
+pub const PAYMENT_INTENT_SUCCEEDED: &str = "payment_intent.succeeded";
+pub const PAYMENT_INTENT_PAYMENT_FAILED: &str = "payment_intent.payment_failed";
+pub const PAYMENT_INTENT_REQUIRES_ACTION: &str = "payment_intent.requires_action";
+pub const PAYMENT_INTENT_CANCELED: &str = "payment_intent.canceled";
+// A field other than the status changed (PATCH); carries `previous_attributes`
+pub const PAYMENT_INTENT_UPDATED: &str = "payment_intent.updated";
+// A manual-capture intent was captured (requires_capture -> succeeded)
+pub const PAYMENT_INTENT_CAPTURED: &str = "payment_intent.captured";
+
+// Written with the charge, in the transaction that moves its intent to succeeded
+pub const CHARGE_SUCCEEDED: &str = "charge.succeeded";
+// Written with the refund, which is applied to its charge in the same transaction
+pub const REFUND_CREATED: &str = "refund.created";
+// Written by the settlement worker as it settles a pending payout
+pub const PAYOUT_PAID: &str = "payout.paid";
+pub const PAYOUT_FAILED: &str = "payout.failed";
+
+// Raised by the API about itself (see api/src/latency_alerts.rs)
+pub const SERVICE_LATENCY_DEGRADED: &str = "service.latency_degraded";
+pub const SERVICE_LATENCY_RECOVERED: &str = "service.latency_recovered";
+
+pub const WEBHOOK_ENDPOINT_SECRET_REVEALED: &str = "webhook_endpoint.secret_revealed";
+// Written by the worker once per uploaded certificate, CERTIFICATE_EXPIRY_WARNING_DAYS before it
+// expires (see worker/src/db.rs emit_certificate_expiry_warnings)
+pub const WEBHOOK_ENDPOINT_CERTIFICATE_EXPIRING: &str = "webhook_endpoint.certificate_expiring";
+pub const CERTIFICATE_EXPIRY_WARNING_DAYS: i64 = 30;
+// Written by the worker (see worker/src/db.rs emit_daily_digest)
+pub const WEBHOOK_DELIVERIES_DAILY_DIGEST: &str = "webhook_deliveries.daily_digest";
+
+// Every event type the server emits. Code that handles events should match on this without a
+// wildcard arm, so a new type fails to compile until it is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    PaymentIntentCreated,
+    PaymentIntentSucceeded,
+    PaymentIntentPaymentFailed,
+    PaymentIntentRequiresAction,
+    PaymentIntentCanceled,
+    PaymentIntentUpdated,
+    PaymentIntentCaptured,
+    ChargeSucceeded,
+    RefundCreated,
+    PayoutPaid,
+    PayoutFailed,
+    ServiceLatencyDegraded,
+    ServiceLatencyRecovered,
+    WebhookEndpointSecretRevealed,
+    WebhookEndpointCertificateExpiring,
+    WebhookDeliveriesDailyDigest,
+}
+
+impl EventType {
+    pub const ALL: &[EventType] = &[
+        Self::PaymentIntentCreated,
+        Self::PaymentIntentSucceeded,
+        Self::PaymentIntentPaymentFailed,
+        Self::PaymentIntentRequiresAction,
+        Self::PaymentIntentCanceled,
+        Self::PaymentIntentUpdated,
+        Self::PaymentIntentCaptured,
+        Self::ChargeSucceeded,
+        Self::RefundCreated,
+        Self::PayoutPaid,
+        Self::PayoutFailed,
+        Self::ServiceLatencyDegraded,
+        Self::ServiceLatencyRecovered,
+        Self::WebhookEndpointSecretRevealed,
+        Self::WebhookEndpointCertificateExpiring,
+        Self::WebhookDeliveriesDailyDigest,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PaymentIntentCreated => PAYMENT_INTENT_CREATED,
+            Self::PaymentIntentSucceeded => PAYMENT_INTENT_SUCCEEDED,
+            Self::PaymentIntentPaymentFailed => PAYMENT_INTENT_PAYMENT_FAILED,
+            Self::PaymentIntentRequiresAction => PAYMENT_INTENT_REQUIRES_ACTION,
+            Self::PaymentIntentCanceled => PAYMENT_INTENT_CANCELED,
+            Self::PaymentIntentUpdated => PAYMENT_INTENT_UPDATED,
+            Self::PaymentIntentCaptured => PAYMENT_INTENT_CAPTURED,
+            Self::ChargeSucceeded => CHARGE_SUCCEEDED,
+            Self::RefundCreated => REFUND_CREATED,
+            Self::PayoutPaid => PAYOUT_PAID,
+            Self::PayoutFailed => PAYOUT_FAILED,
+            Self::ServiceLatencyDegraded => SERVICE_LATENCY_DEGRADED,
+            Self::ServiceLatencyRecovered => SERVICE_LATENCY_RECOVERED,
+            Self::WebhookEndpointSecretRevealed => WEBHOOK_ENDPOINT_SECRET_REVEALED,
+            Self::WebhookEndpointCertificateExpiring => WEBHOOK_ENDPOINT_CERTIFICATE_EXPIRING,
+            Self::WebhookDeliveriesDailyDigest => WEBHOOK_DELIVERIES_DAILY_DIGEST,
+        }
+    }
+
+    // None for a type this version doesn't know
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| t.as_str() == s)
+    }
+}
+
+// `data` of every payment_intent.* event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaymentIntentEventData {
+    pub payment_intent: PaymentIntentResponse,
+    // Set when a sandbox X-Simulate outcome produced the event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simulated_outcome: Option<String>,
+    // payment_intent.updated only: the old value of each field that changed (see
+    // previous_attributes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_attributes: Option<Value>,
+}
+
+// `data` of every charge.* event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChargeEventData {
+    pub charge: ChargeResponse,
+}
+
+// `data` of every refund.* event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefundEventData {
+    pub refund: RefundResponse,
+}
+
+// `data` of every payout.* event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayoutEventData {
+    pub payout: PayoutResponse,
+}
+
+// Stripe-style `previous_attributes` between two versions of an object, compared as they
+// serialize: the old value of each field that differs, recursing into nested objects. A field
+// the new version added is null. None when nothing changed.
+pub fn previous_attributes<T: Serialize>(before: &T, after: &T) -> Option<Value> {
+    let before = serde_json::to_value(before).ok()?;
+    let after = serde_json::to_value(after).ok()?;
+    diff(&before, &after)
+}
+
+fn diff(before: &Value, after: &Value) -> Option<Value> {
+    let (Value::Object(before), Value::Object(after)) = (before, after) else {
+        return (before != after).then(|| before.clone());
+    };
+    let mut previous = Map::new();
+    for (key, new) in after {
+        match before.get(key) {
+            Some(old) => {
+                if let Some(old) = diff(old, new) {
+                    previous.insert(key.clone(), old);
+                }
+            }
+            None => {
+                previous.insert(key.clone(), Value::Null);
+            }
+        }
+    }
+    for (key, old) in before {
+        if !after.contains_key(key) {
+            previous.insert(key.clone(), old.clone());
+        }
+    }
+    (!previous.is_empty()).then_some(Value::Object(previous))
+}
+
+// What the worker posts to a webhook endpoint. Oversized events arrive "thin": `data` is then
+// just `{"object": {"id": ...}}` and `payload_truncated` is true.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    pub data: Value,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub payload_truncated: bool,
+}
+
+impl WebhookEvent {
+    pub fn kind(&self) -> Option<EventType> {
+        EventType::parse(&self.event_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_types_round_trip_through_their_names() {
+        for t in EventType::ALL {
+            assert_eq!(EventType::parse(t.as_str()), Some(*t));
+        }
+        assert_eq!(EventType::parse("payment_intent.refunded"), None);
+    }
+
+    #[test]
+    fn previous_attributes_hold_the_old_value_of_each_change() {
+        let before = serde_json::json!({
+            "id": "pi_1",
+            "amount": 1000,
+            "metadata": { "order_id": "o_1", "note": "gift", "gone": "x" },
+        });
+        let after = serde_json::json!({
+            "id": "pi_1",
+            "amount": 2500,
+            "metadata": { "order_id": "o_1", "note": "rush", "added": "y" },
+        });
+        assert_eq!(
+            previous_attributes(&before, &after),
+            Some(serde_json::json!({
+                "amount": 1000,
+                "metadata": { "note": "gift", "gone": "x", "added": null },
+            }))
+        );
+        assert_eq!(previous_attributes(&before, &before), None);
+    }
+}
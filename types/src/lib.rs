@@ -0,0 +1,13 @@
+pub mod balance;
+pub mod charges;
+pub mod crypto;
+pub mod customers;
+pub mod delivery;
+pub mod error;
+pub mod events;
+pub mod payload_template;
+pub mod payment_intents;
+pub mod payment_methods;
+pub mod payouts;
+pub mod refunds;
+pub mod signature;
@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::DateTime;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::events::{EventType, PaymentIntentEventData, WebhookEvent};
+use crate::payment_intents::{PaymentIntentResponse, PaymentIntentStatus};
+
+// A per-endpoint reshaping of the webhook body, for receivers that need a fixed JSON shape.
+// Only field lookups and constants, never code:
+//
+//   {"fields": {"txn": {"path": "$.data.payment_intent.id"},
+//               "source": {"const": "mini-stripe"},
+//               "money": {"fields": {"cents": {"path": "$.data.payment_intent.amount"}}}}}
+//
+// Paths are `$` followed by `.name` segments into the event envelope the worker would post.
+// Templates apply to payment_intent.* events; every other event keeps the standard envelope.
+
+// Bounds so a stored template stays small and cheap to render
+const MAX_FIELDS: usize = 100;
+const MAX_DEPTH: usize = 5;
+
+// Envelope paths that can be missing or null on a real event. Anything at or under them renders
+// as null when absent; metadata, last_payment_error and previous_attributes have no fixed keys.
+const OPTIONAL_PATHS: &[&[&str]] = &[
+    &["data", "simulated_outcome"],
+    &["data", "previous_attributes"],
+    &["data", "payment_intent", "metadata"],
+    &["data", "payment_intent", "last_payment_error"],
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PayloadTemplate {
+    fields: Vec<(String, Field)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Path(Vec<String>),
+    Const(Value),
+    Fields(Vec<(String, Field)>),
+}
+
+// Where in the template (e.g. `template.money.cents`) and what is wrong
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TemplateError {
+    pub at: String,
+    pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.at, self.message)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+fn error(at: &str, message: impl Into<String>) -> TemplateError {
+    TemplateError {
+        at: at.to_string(),
+        message: message.into(),
+    }
+}
+
+// Whether events of this type go through an endpoint's template
+pub fn applies_to(event_type: &str) -> bool {
+    EventType::parse(event_type).is_some_and(|t| sample_data(t).is_some())
+}
+
+impl PayloadTemplate {
+    // Checks the template's structure and that every path exists on every payment_intent event,
+    // so a template that is accepted can always render
+    pub fn parse(spec: &Value) -> Result<Self, TemplateError> {
+        let mut count = 0;
+        let fields = parse_fields(spec, "template", 1, &mut count)?;
+        let template = Self { fields };
+
+        for t in EventType::ALL {
+            if let Some(sample) = sample_event(*t) {
+                template.render(&sample)?;
+            }
+        }
+        Ok(template)
+    }
+
+    // The body to post in place of `event` (the full envelope, never the thin one)
+    pub fn render(&self, event: &Value) -> Result<Value, TemplateError> {
+        render_fields(&self.fields, event, "template")
+    }
+}
+
+fn parse_fields(
+    spec: &Value,
+    at: &str,
+    depth: usize,
+    count: &mut usize,
+) -> Result<Vec<(String, Field)>, TemplateError> {
+    if depth > MAX_DEPTH {
+        return Err(error(
+            at,
+            format!("nesting is limited to {MAX_DEPTH} levels"),
+        ));
+    }
+    let Some(spec) = spec.as_object() else {
+        return Err(error(at, "must be an object with `fields`"));
+    };
+    if let Some(key) = spec.keys().find(|k| *k != "fields") {
+        return Err(error(at, format!("unknown key `{key}`")));
+    }
+    let Some(fields) = spec.get("fields").and_then(Value::as_object) else {
+        return Err(error(at, "`fields` must be an object"));
+    };
+
+    let mut parsed = Vec::new();
+    for (name, field) in fields {
+        let at = format!("{at}.{name}");
+        *count += 1;
+        if *count > MAX_FIELDS {
+            return Err(error(
+                &at,
+                format!("templates have at most {MAX_FIELDS} fields"),
+            ));
+        }
+        parsed.push((name.clone(), parse_field(field, &at, depth, count)?));
+    }
+    Ok(parsed)
+}
+
+fn parse_field(
+    spec: &Value,
+    at: &str,
+    depth: usize,
+    count: &mut usize,
+) -> Result<Field, TemplateError> {
+    let kind = spec
+        .as_object()
+        .filter(|o| o.len() == 1)
+        .and_then(|o| o.keys().next());
+    match kind.map(String::as_str) {
+        Some("path") => match &spec["path"] {
+            Value::String(path) => parse_path(path).map(Field::Path).map_err(|m| error(at, m)),
+            _ => Err(error(at, "`path` must be a string")),
+        },
+        Some("const") => Ok(Field::Const(spec["const"].clone())),
+        Some("fields") => parse_fields(spec, at, depth + 1, count).map(Field::Fields),
+        _ => Err(error(
+            at,
+            "must be exactly one of {\"path\": ...}, {\"const\": ...} or {\"fields\": ...}",
+        )),
+    }
+}
+
+fn parse_path(path: &str) -> Result<Vec<String>, String> {
+    let invalid = || format!("`{path}` is not a path like `$.data.payment_intent.id`");
+    let rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    if rest.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = rest.strip_prefix('.').ok_or_else(invalid)?;
+    rest.split('.')
+        .map(|segment| {
+            let valid = !segment.is_empty()
+                && segment
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+            valid.then(|| segment.to_string()).ok_or_else(invalid)
+        })
+        .collect()
+}
+
+fn render_fields(
+    fields: &[(String, Field)],
+    event: &Value,
+    at: &str,
+) -> Result<Value, TemplateError> {
+    let mut out = Map::new();
+    for (name, field) in fields {
+        let at = format!("{at}.{name}");
+        let value = match field {
+            Field::Path(path) => lookup(event, path).map_err(|m| error(&at, m))?,
+            Field::Const(value) => value.clone(),
+            Field::Fields(fields) => render_fields(fields, event, &at)?,
+        };
+        out.insert(name.clone(), value);
+    }
+    Ok(Value::Object(out))
+}
+
+fn lookup(event: &Value, path: &[String]) -> Result<Value, String> {
+    let mut current = event;
+    for (i, segment) in path.iter().enumerate() {
+        let optional = OPTIONAL_PATHS
+            .iter()
+            .any(|optional| optional.len() <= i + 1 && path[..optional.len()] == **optional);
+        current = match current.get(segment.as_str()) {
+            Some(value) => value,
+            None if optional => return Ok(Value::Null),
+            None => {
+                return Err(format!(
+                    "$.{} does not exist on {} events",
+                    path.join("."),
+                    event["type"].as_str().unwrap_or("these")
+                ));
+            }
+        };
+    }
+    Ok(current.clone())
+}
+
+// `data` of a representative event of this type, with every field present. None for types
+// templates don't apply to.
+fn sample_data(t: EventType) -> Option<PaymentIntentEventData> {
+    let (status, simulated_outcome, last_payment_error, cancellation_reason) = match t {
+        EventType::PaymentIntentCreated => {
+            (PaymentIntentStatus::RequiresConfirmation, None, None, None)
+        }
+        EventType::PaymentIntentSucceeded => (PaymentIntentStatus::Succeeded, None, None, None),
+        EventType::PaymentIntentPaymentFailed => (
+            PaymentIntentStatus::RequiresPaymentMethod,
+            Some("card_declined"),
+            Some(serde_json::json!({
+                "code": "card_declined",
+                "message": "Your card was declined.",
+                "simulated": true
+            })),
+            None,
+        ),
+        EventType::PaymentIntentRequiresAction => (
+            PaymentIntentStatus::RequiresAction,
+            Some("requires_action"),
+            None,
+            None,
+        ),
+        EventType::PaymentIntentCanceled => {
+            (PaymentIntentStatus::Canceled, None, None, Some("abandoned"))
+        }
+        EventType::PaymentIntentUpdated => {
+            (PaymentIntentStatus::RequiresConfirmation, None, None, None)
+        }
+        EventType::PaymentIntentCaptured => (PaymentIntentStatus::Succeeded, None, None, None),
+        EventType::ChargeSucceeded
+        | EventType::RefundCreated
+        | EventType::PayoutPaid
+        | EventType::PayoutFailed
+        | EventType::ServiceLatencyDegraded
+        | EventType::ServiceLatencyRecovered
+        | EventType::WebhookEndpointSecretRevealed
+        | EventType::WebhookEndpointCertificateExpiring
+        | EventType::WebhookDeliveriesDailyDigest => return None,
+    };
+
+    Some(PaymentIntentEventData {
+        payment_intent: PaymentIntentResponse {
+            id: Uuid::nil(),
+            amount: 1000,
+            currency: "gbp".to_string(),
+            status,
+            cancellation_reason: cancellation_reason.map(str::to_string),
+            last_payment_error,
+            metadata: BTreeMap::from([("order_id".to_string(), "ord_123".to_string())]),
+            capture_method: if t == EventType::PaymentIntentCaptured {
+                "manual".to_string()
+            } else {
+                "automatic".to_string()
+            },
+            // A captured sample shows a partial capture
+            amount_received: match t {
+                EventType::PaymentIntentSucceeded => 1000,
+                EventType::PaymentIntentCaptured => 800,
+                _ => 0,
+            },
+            description: Some("Order ord_123".to_string()),
+            expires_at: Some(DateTime::UNIX_EPOCH + chrono::Duration::hours(24)),
+            payment_method: Some("pm_card_visa".to_string()),
+            customer: None,
+        },
+        simulated_outcome: simulated_outcome.map(str::to_string),
+        previous_attributes: (t == EventType::PaymentIntentUpdated)
+            .then(|| serde_json::json!({ "amount": 500 })),
+    })
+}
+
+// The envelope the worker would post for an event of this type, for validation and previews
+pub fn sample_event(t: EventType) -> Option<Value> {
+    let data = sample_data(t)?;
+    Some(serde_json::json!(WebhookEvent {
+        id: Uuid::nil(),
+        event_type: t.as_str().to_string(),
+        created_at: Some(DateTime::UNIX_EPOCH),
+        data: serde_json::json!(data),
+        payload_truncated: false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn template() -> Value {
+        json!({ "fields": {
+            "txn": { "path": "$.data.payment_intent.id" },
+            "kind": { "path": "$.type" },
+            "source": { "const": "mini-stripe" },
+            "money": { "fields": {
+                "cents": { "path": "$.data.payment_intent.amount" },
+                "ccy": { "path": "$.data.payment_intent.currency" }
+            }},
+            "order": { "path": "$.data.payment_intent.metadata.order_id" },
+            "decline": { "path": "$.data.payment_intent.last_payment_error.code" }
+        }})
+    }
+
+    #[test]
+    fn renders_lookups_constants_and_nested_objects() {
+        let template = PayloadTemplate::parse(&template()).unwrap();
+        let event = sample_event(EventType::PaymentIntentSucceeded).unwrap();
+        assert_eq!(
+            template.render(&event).unwrap(),
+            json!({
+                "txn": Uuid::nil(),
+                "kind": "payment_intent.succeeded",
+                "source": "mini-stripe",
+                "money": { "cents": 1000, "ccy": "gbp" },
+                "order": "ord_123",
+                // No error on a succeeded intent
+                "decline": null
+            })
+        );
+    }
+
+    #[test]
+    fn optional_fields_render_as_null_when_missing() {
+        let template = PayloadTemplate::parse(&json!({ "fields": {
+            "outcome": { "path": "$.data.simulated_outcome" },
+            "customer": { "path": "$.data.payment_intent.metadata.customer" },
+            "old_amount": { "path": "$.data.previous_attributes.amount" }
+        }}))
+        .unwrap();
+        let event = json!({
+            "id": Uuid::nil(),
+            "type": "payment_intent.created",
+            "data": { "payment_intent": { "id": Uuid::nil(), "metadata": {} } }
+        });
+        assert_eq!(
+            template.render(&event).unwrap(),
+            json!({ "outcome": null, "customer": null, "old_amount": null })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_paths_and_malformed_specs() {
+        let cases = [
+            (
+                json!({ "fields": { "x": { "path": "$.data.payment_intent.shipping" } } }),
+                "template.x",
+            ),
+            (
+                json!({ "fields": { "x": { "path": "$.data.payment_intent.amount.value" } } }),
+                "template.x",
+            ),
+            (
+                json!({ "fields": { "x": { "path": "data.id" } } }),
+                "template.x",
+            ),
+            (
+                json!({ "fields": { "x": { "eval": "1 + 1" } } }),
+                "template.x",
+            ),
+            (
+                json!({ "fields": { "x": { "path": "$.id", "const": 1 } } }),
+                "template.x",
+            ),
+            (json!({ "fields": { "x": "$.id" } }), "template.x"),
+            (json!({ "map": {} }), "template"),
+            (json!([]), "template"),
+        ];
+        for (spec, at) in cases {
+            let err = PayloadTemplate::parse(&spec).unwrap_err();
+            assert!(err.to_string().starts_with(at), "{spec}: {err}");
+        }
+    }
+
+    #[test]
+    fn a_path_missing_at_render_time_is_an_error() {
+        let template = PayloadTemplate::parse(&json!({ "fields": {
+            "txn": { "path": "$.data.payment_intent.id" }
+        }}))
+        .unwrap();
+        let event = json!({ "id": Uuid::nil(), "type": "payment_intent.created", "data": {} });
+        let err = template.render(&event).unwrap_err();
+        assert_eq!(err.at, "template.txn");
+    }
+
+    #[test]
+    fn only_payment_intent_events_are_templated() {
+        assert!(applies_to("payment_intent.succeeded"));
+        assert!(!applies_to("webhook_endpoint.secret_revealed"));
+        assert!(applies_to("payment_intent.updated"));
+        assert!(!applies_to("payment_intent.refunded"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Where an intent is in its lifecycle. Stored as `as_str` in payment_intents.status; every change
+// of it must be allowed by `can_transition_to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentIntentStatus {
+    RequiresConfirmation,
+    RequiresPaymentMethod,
+    RequiresAction,
+    Processing,
+    RequiresCapture,
+    Succeeded,
+    Canceled,
+}
+
+impl PaymentIntentStatus {
+    pub const ALL: &[PaymentIntentStatus] = &[
+        Self::RequiresConfirmation,
+        Self::RequiresPaymentMethod,
+        Self::RequiresAction,
+        Self::Processing,
+        Self::RequiresCapture,
+        Self::Succeeded,
+        Self::Canceled,
+    ];
+
+    // Every intent starts here
+    pub const INITIAL: PaymentIntentStatus = Self::RequiresConfirmation;
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::RequiresConfirmation => "requires_confirmation",
+            Self::RequiresPaymentMethod => "requires_payment_method",
+            Self::RequiresAction => "requires_action",
+            Self::Processing => "processing",
+            Self::RequiresCapture => "requires_capture",
+            Self::Succeeded => "succeeded",
+            Self::Canceled => "canceled",
+        }
+    }
+
+    // None for a status this version doesn't know
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|status| status.as_str() == s)
+    }
+
+    // Nothing leaves these
+    pub fn is_terminal(self) -> bool {
+        matches!(self, Self::Succeeded | Self::Canceled)
+    }
+
+    // The transition table. Not every edge has an API path yet (nothing moves an intent to
+    // processing), but the ones that do must appear here. A declined intent is confirmed again
+    // with another payment method, straight from requires_payment_method.
+    pub fn can_transition_to(self, next: Self) -> bool {
+        use PaymentIntentStatus::*;
+        match self {
+            RequiresConfirmation => matches!(
+                next,
+                RequiresPaymentMethod
+                    | RequiresAction
+                    | Processing
+                    | RequiresCapture
+                    | Succeeded
+                    | Canceled
+            ),
+            RequiresPaymentMethod => matches!(
+                next,
+                RequiresConfirmation
+                    | RequiresAction
+                    | Processing
+                    | RequiresCapture
+                    | Succeeded
+                    | Canceled
+            ),
+            RequiresAction => matches!(
+                next,
+                RequiresPaymentMethod | Processing | RequiresCapture | Succeeded | Canceled
+            ),
+            Processing => matches!(next, RequiresPaymentMethod | RequiresCapture | Succeeded),
+            RequiresCapture => matches!(next, Succeeded | Canceled),
+            Succeeded | Canceled => false,
+        }
+    }
+}
+
+impl std::fmt::Display for PaymentIntentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatePaymentIntentRequest {
+    pub amount: i64,
+    // Falls back to the API's default currency when omitted
+    pub currency: Option<String>,
+    // Create and confirm in one step; X-Simulate and the latency headers apply as on confirm
+    #[serde(default)]
+    pub confirm: bool,
+    // The card to charge, as on confirm; only with `confirm: true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_method: Option<String>,
+    // Up to 50 string pairs, returned as given
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    // `automatic` (the default) or `manual`: a manual intent stops at requires_capture when
+    // confirmed, until POST /v1/payment_intents/{id}/capture
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_method: Option<String>,
+    // Up to 1000 bytes of free text, returned as given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // An existing customer the payment is for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer: Option<Uuid>,
+}
+
+// PATCH /v1/payment_intents/{id}. The amount and currency can only change before confirmation.
+// The description and metadata can change at any time; an empty description clears it, and
+// metadata is merged into the existing keys, where an empty value removes a key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdatePaymentIntentRequest {
+    pub amount: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+// POST /v1/payment_intents/{id}/confirm. The body is optional; without a payment method the
+// processor decides from the amount alone, which only fails for sandbox magic amounts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfirmPaymentIntentRequest {
+    // A test token such as `pm_card_visa` or `pm_card_declined`, which decides the outcome, or
+    // the id of a saved payment method. One attached to a customer only works for that
+    // customer's intents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_method: Option<String>,
+}
+
+// POST /v1/payment_intents/{id}/capture. The body is optional; without it the whole authorized
+// amount is captured.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CapturePaymentIntentRequest {
+    // 1 up to the intent's amount
+    pub amount_to_capture: Option<i64>,
+}
+
+// The intent as every endpoint and outbox event returns it. GET adds a few summaries alongside,
+// which deserializing into this ignores.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaymentIntentResponse {
+    pub id: Uuid,
+    pub amount: i64,
+    pub currency: String,
+    pub status: PaymentIntentStatus,
+    pub cancellation_reason: Option<String>,
+    pub last_payment_error: Option<serde_json::Value>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default = "automatic")]
+    pub capture_method: String,
+    // The captured amount once succeeded, which a manual capture may set below `amount`; 0 before
+    #[serde(default)]
+    pub amount_received: i64,
+    #[serde(default)]
+    pub description: Option<String>,
+    // When it is canceled with cancellation_reason `expired` if still requires_confirmation
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    // What the last confirm was processed with, if it named one
+    #[serde(default)]
+    pub payment_method: Option<String>,
+    #[serde(default)]
+    pub customer: Option<Uuid>,
+}
+
+// Intents from before capture_method existed were all captured on confirm
+fn automatic() -> String {
+    "automatic".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_round_trip_through_their_wire_names() {
+        for &status in PaymentIntentStatus::ALL {
+            assert_eq!(PaymentIntentStatus::parse(status.as_str()), Some(status));
+            assert_eq!(
+                serde_json::to_value(status).unwrap(),
+                serde_json::json!(status.as_str())
+            );
+        }
+        assert_eq!(PaymentIntentStatus::parse("refunded"), None);
+    }
+
+    #[test]
+    fn terminal_statuses_have_no_way_out() {
+        use PaymentIntentStatus::*;
+        for &from in PaymentIntentStatus::ALL {
+            for &to in PaymentIntentStatus::ALL {
+                if from.is_terminal() {
+                    assert!(!from.can_transition_to(to), "{from} -> {to}");
+                }
+            }
+            assert!(!from.can_transition_to(from), "{from} -> {from}");
+        }
+        assert!(RequiresConfirmation.can_transition_to(RequiresCapture));
+        assert!(RequiresCapture.can_transition_to(Succeeded));
+        assert!(RequiresPaymentMethod.can_transition_to(Succeeded));
+        assert!(!RequiresCapture.can_transition_to(RequiresConfirmation));
+    }
+}
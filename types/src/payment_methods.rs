@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// The card as sent on create. Only brand, last4 and expiry are kept from it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CardDetails {
+    // Digits, optionally with spaces. Stripe's test numbers (4242 4242 4242 4242,
+    // 4000 0000 0000 0002, ...) behave like the matching test tokens on confirm.
+    pub number: String,
+    pub exp_month: i32,
+    pub exp_year: i32,
+    // Checked for shape and then dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cvc: Option<String>,
+}
+
+// POST /v1/payment_methods
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatePaymentMethodRequest {
+    pub card: CardDetails,
+}
+
+// POST /v1/payment_methods/{id}/attach
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttachPaymentMethodRequest {
+    pub customer: Uuid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Card {
+    // visa, mastercard, amex, discover or unknown
+    pub brand: String,
+    pub last4: String,
+    pub exp_month: i32,
+    pub exp_year: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaymentMethodResponse {
+    pub id: Uuid,
+    pub card: Card,
+    // The customer it is attached to, if any
+    pub customer: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+// GET /v1/payment_methods?customer=...
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaymentMethodList {
+    // Always `list`
+    pub object: String,
+    pub data: Vec<PaymentMethodResponse>,
+    pub has_more: bool,
+    // The page size used and the most a request can get
+    pub limit: i64,
+    pub max_limit: i64,
+    // E.g. that the requested limit was clamped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// POST /v1/payouts
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CreatePayoutRequest {
+    pub amount: i64,
+    pub currency: String,
+    // A test bank account: `ba_test` (the default) is paid, `ba_test_no_account` and
+    // `ba_test_account_closed` fail with that failure_code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+// pending until the settlement worker gets to it after arrival_date, then paid or failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutStatus {
+    Pending,
+    Paid,
+    Failed,
+}
+
+impl PayoutStatus {
+    pub const ALL: &[PayoutStatus] = &[Self::Pending, Self::Paid, Self::Failed];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Paid => "paid",
+            Self::Failed => "failed",
+        }
+    }
+
+    // None for a status this version doesn't know
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|status| status.as_str() == s)
+    }
+}
+
+impl std::fmt::Display for PayoutStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayoutResponse {
+    pub id: Uuid,
+    pub amount: i64,
+    pub currency: String,
+    pub status: PayoutStatus,
+    pub destination: String,
+    pub description: Option<String>,
+    // When it is settled, at the earliest
+    pub arrival_date: DateTime<Utc>,
+    // Set once failed
+    pub failure_code: Option<String>,
+    pub failure_message: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// GET /v1/payouts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayoutList {
+    // Always `list`
+    pub object: String,
+    pub data: Vec<PayoutResponse>,
+    pub has_more: bool,
+    // The page size used and the most a request can get
+    pub limit: i64,
+    pub max_limit: i64,
+    // E.g. that the requested limit was clamped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
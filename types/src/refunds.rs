@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// POST /v1/refunds. Exactly one of `payment_intent` and `charge`; an intent is refunded through
+// its charge.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CreateRefundRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_intent: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charge: Option<Uuid>,
+    // What is left unrefunded on the charge when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<i64>,
+}
+
+// Refunds are applied as they are created, so succeeded is the only status for now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundStatus {
+    Succeeded,
+}
+
+impl RefundStatus {
+    pub const ALL: &[RefundStatus] = &[Self::Succeeded];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+        }
+    }
+
+    // None for a status this version doesn't know
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|status| status.as_str() == s)
+    }
+}
+
+impl std::fmt::Display for RefundStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefundResponse {
+    pub id: Uuid,
+    pub charge: Uuid,
+    pub payment_intent: Uuid,
+    pub amount: i64,
+    pub currency: String,
+    pub status: RefundStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+// GET /v1/refunds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefundList {
+    // Always `list`
+    pub object: String,
+    pub data: Vec<RefundResponse>,
+    pub has_more: bool,
+    // The page size used and the most a request can get
+    pub limit: i64,
+    pub max_limit: i64,
+    // E.g. that the requested limit was clamped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_round_trip_through_their_wire_names() {
+        for &status in RefundStatus::ALL {
+            assert_eq!(RefundStatus::parse(status.as_str()), Some(status));
+        }
+        assert_eq!(RefundStatus::parse("pending"), None);
+    }
+
+    #[test]
+    fn create_request_leaves_out_what_is_not_set() {
+        let req = CreateRefundRequest {
+            charge: Some(Uuid::nil()),
+            ..CreateRefundRequest::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&req).unwrap(),
+            serde_json::json!({ "charge": Uuid::nil() })
+        );
+    }
+}
@@ -1,8 +1,16 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveTime, Utc};
+use mini_stripe_types::delivery::{RetryPolicy, retry_delay};
+use mini_stripe_types::events::{
+    CERTIFICATE_EXPIRY_WARNING_DAYS, PAYMENT_INTENT_CAPTURED, PAYMENT_INTENT_SUCCEEDED,
+    WEBHOOK_ENDPOINT_CERTIFICATE_EXPIRING,
+};
+use mini_stripe_types::signature::SignatureScheme;
 use serde_json::Value;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::deliver::WebhookResponse;
+
 pub struct ClaimedDelivery {
     pub delivery_id: Uuid,
     pub event_id: Uuid,
@@ -12,35 +20,64 @@ pub struct ClaimedDelivery {
     pub endpoint_id: Uuid,
     pub endpoint_url: String,
     pub endpoint_secret: String,
+    pub endpoint_signature_scheme: SignatureScheme,
+    // Reshapes payment_intent events (see deliver::render_body)
+    pub endpoint_payload_template: Option<Value>,
+    // Encrypted PEM bundle presented for mutual TLS, and its fingerprint (the client cache key)
+    pub endpoint_client_certificate: Option<String>,
+    pub endpoint_client_certificate_fingerprint: Option<String>,
     pub attempt_count: i32,
+    // The attempt limit it was created with
+    pub max_attempts: i32,
+    // True when this delivery is the single probe sent to a half-open endpoint
+    pub is_probe: bool,
+}
+
+impl ClaimedDelivery {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            delivery_id: self.delivery_id,
+            max_attempts: self.max_attempts,
+        }
+    }
 }
 
 // Enqueue deliveries for any (event, endpoint) pairs that don't exist yet.
 // This makes sure the worker has something to deliver without adding more logic to the API.
 pub async fn enqueue_missing_deliveries(
     tx: &mut Transaction<'_, Postgres>,
+    max_attempts: i32,
 ) -> Result<(), sqlx::Error> {
-    // Insert a pending delivery row for each enabled endpoint per event (if missing).
-    // *BUT* This assumes events should be delivered to all enabled endpoints.
+    // Insert a pending delivery row for each enabled endpoint per event (if missing), for the
+    // endpoints whose enabled_events include the event's type ('*' is every type). Each keeps
+    // `max_attempts` for good, so a later change to it only affects new deliveries.
+    // While the outbox is draining only events created up to the drain marker are enqueued.
     sqlx::query!(
         r#"
         INSERT INTO webhook_deliveries (
-          id, event_id, webhook_endpoint_id, status, next_attempt_at
+          id, event_id, webhook_endpoint_id, status, next_attempt_at, max_attempts
         )
         SELECT
           gen_random_uuid(),
           e.id,
           w.id,
           'pending',
-          now()
+          now(),
+          $1
         FROM events_outbox e
-        JOIN webhook_endpoints w ON w.is_enabled = true
-        WHERE NOT EXISTS (
-          SELECT 1
-          FROM webhook_deliveries d
-          WHERE d.event_id = e.id AND d.webhook_endpoint_id = w.id
-        )
-        "#
+        JOIN webhook_endpoints w
+          ON w.is_enabled = true
+         AND ('*' = ANY (w.enabled_events) OR e.event_type = ANY (w.enabled_events))
+        CROSS JOIN outbox_state s
+        WHERE (s.draining_since IS NULL OR e.created_at <= s.draining_since)
+          AND e.superseded_at IS NULL
+          AND NOT EXISTS (
+            SELECT 1
+            FROM webhook_deliveries d
+            WHERE d.event_id = e.id AND d.webhook_endpoint_id = w.id
+          )
+        "#,
+        max_attempts
     )
     .execute(&mut **tx)
     .await?;
@@ -48,10 +85,58 @@ pub async fn enqueue_missing_deliveries(
     Ok(())
 }
 
+// Collapse runs of not-yet-enqueued compactable events (e.g. payment_intent.updated) for the same
+// resource into the latest one. A different event type for that resource in between breaks the run.
+// The resource is the payload object named by the type prefix: payment_intent.* -> payload.payment_intent.id
+pub async fn compact_events(
+    tx: &mut Transaction<'_, Postgres>,
+    compactable_types: &[String],
+) -> Result<u64, sqlx::Error> {
+    if compactable_types.is_empty() {
+        return Ok(0);
+    }
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE events_outbox e
+        SET superseded_at = now()
+        WHERE e.event_type = ANY($1)
+          AND e.superseded_at IS NULL
+          AND e.delivered_at IS NULL
+          AND NOT EXISTS (SELECT 1 FROM webhook_deliveries d WHERE d.event_id = e.id)
+          AND EXISTS (
+            SELECT 1
+            FROM events_outbox later
+            WHERE later.event_type = e.event_type
+              AND later.payload -> split_part(later.event_type, '.', 1) ->> 'id'
+                = e.payload -> split_part(e.event_type, '.', 1) ->> 'id'
+              AND (later.created_at, later.id) > (e.created_at, e.id)
+              AND NOT EXISTS (
+                SELECT 1
+                FROM events_outbox other
+                WHERE other.event_type <> e.event_type
+                  AND other.payload -> split_part(e.event_type, '.', 1) ->> 'id'
+                    = e.payload -> split_part(e.event_type, '.', 1) ->> 'id'
+                  AND other.created_at > e.created_at
+                  AND other.created_at < later.created_at
+              )
+          )
+        "#,
+        compactable_types
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
 // 2) Claim one due delivery (pending and due now) atomically bumping attempt_count.
 // We claim it inside the tx to avoid multiple workers doing the same row later.
+// Endpoints with an open circuit are skipped; once the cool-down has passed the claimed
+// delivery becomes the probe and the circuit is held open for `probe_timeout_secs` meanwhile.
 pub async fn claim_one_due_delivery(
     tx: &mut Transaction<'_, Postgres>,
+    probe_timeout_secs: f64,
 ) -> Result<Option<ClaimedDelivery>, sqlx::Error> {
     // Select one due pending delivery with row level lock.
     let row = sqlx::query!(
@@ -60,18 +145,25 @@ pub async fn claim_one_due_delivery(
                d.event_id,
                d.webhook_endpoint_id,
                d.attempt_count,
+               d.max_attempts,
                e.event_type,
                e.payload,
                e.created_at as event_created_at,
                w.url as endpoint_url,
-               w.secret as endpoint_secret
+               w.secret as endpoint_secret,
+               w.signature_scheme as endpoint_signature_scheme,
+               w.payload_template as endpoint_payload_template,
+               w.client_certificate as endpoint_client_certificate,
+               w.client_certificate_fingerprint as endpoint_client_certificate_fingerprint,
+               w.circuit_open_until
         FROM webhook_deliveries d
         JOIN events_outbox e ON e.id = d.event_id
         JOIN webhook_endpoints w ON w.id = d.webhook_endpoint_id
         WHERE d.status = 'pending'
           AND (d.next_attempt_at IS NULL OR d.next_attempt_at <= now())
           AND w.is_enabled = true
-        ORDER BY d.next_attempt_at NULLS FIRST, d.created_at ASC
+          AND (w.circuit_open_until IS NULL OR w.circuit_open_until <= now())
+        ORDER BY d.next_attempt_at NULLS FIRST, d.created_at ASC, e.sequence ASC
         FOR UPDATE SKIP LOCKED
         LIMIT 1
         "#
@@ -100,6 +192,22 @@ pub async fn claim_one_due_delivery(
     .execute(&mut **tx)
     .await?;
 
+    // Half-open: keep everything else for this endpoint parked until the probe reports back
+    let is_probe = r.circuit_open_until.is_some();
+    if is_probe {
+        sqlx::query!(
+            r#"
+            UPDATE webhook_endpoints
+            SET circuit_open_until = now() + make_interval(secs => $2)
+            WHERE id = $1
+            "#,
+            r.webhook_endpoint_id,
+            probe_timeout_secs
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
     Ok(Some(ClaimedDelivery {
         delivery_id: r.delivery_id,
         event_id: r.event_id,
@@ -109,10 +217,163 @@ pub async fn claim_one_due_delivery(
         endpoint_id: r.webhook_endpoint_id,
         endpoint_url: r.endpoint_url,
         endpoint_secret: r.endpoint_secret,
+        // The column's CHECK only allows known schemes
+        endpoint_signature_scheme: SignatureScheme::parse(&r.endpoint_signature_scheme)
+            .unwrap_or_default(),
+        endpoint_payload_template: r.endpoint_payload_template,
+        endpoint_client_certificate: r.endpoint_client_certificate,
+        endpoint_client_certificate_fingerprint: r.endpoint_client_certificate_fingerprint,
         attempt_count: new_attempt,
+        max_attempts: r.max_attempts,
+        is_probe,
     }))
 }
 
+// The receiver answered (any HTTP status): close the circuit
+// Whether claim_one_due_delivery had anything to find, locked or not
+pub async fn has_due_deliveries(db: &PgPool) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+          SELECT 1
+          FROM webhook_deliveries d
+          JOIN webhook_endpoints w ON w.id = d.webhook_endpoint_id
+          WHERE d.status = 'pending'
+            AND (d.next_attempt_at IS NULL OR d.next_attempt_at <= now())
+            AND w.is_enabled = true
+            AND (w.circuit_open_until IS NULL OR w.circuit_open_until <= now())
+        ) AS "due!"
+        "#
+    )
+    .fetch_one(db)
+    .await
+}
+
+// Sandbox fault injection: the unexpired webhook_timeout rule for this endpoint, if any, with
+// the delivery counted against it. The API writes these rules (POST /v1/admin/faults).
+pub async fn claim_delivery_fault(
+    db: &PgPool,
+    endpoint_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE webhook_delivery_faults
+        SET activations = activations + 1
+        WHERE id = (
+            SELECT id FROM webhook_delivery_faults
+            WHERE webhook_endpoint_id = $1 AND expires_at > now()
+            ORDER BY created_at
+            LIMIT 1
+        )
+        RETURNING id
+        "#,
+        endpoint_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn record_endpoint_reachable(db: &PgPool, endpoint_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE webhook_endpoints
+        SET consecutive_failures = 0,
+            circuit_open_until = NULL
+        WHERE id = $1
+        "#,
+        endpoint_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Connection-level failure: after `failure_threshold` in a row, open the circuit for `cooldown_secs`.
+// Returns true when the circuit is (still) open afterwards.
+pub async fn record_endpoint_unreachable(
+    db: &PgPool,
+    endpoint_id: Uuid,
+    failure_threshold: i32,
+    cooldown_secs: f64,
+) -> Result<bool, sqlx::Error> {
+    let open_until = sqlx::query_scalar!(
+        r#"
+        UPDATE webhook_endpoints
+        SET consecutive_failures = consecutive_failures + 1,
+            circuit_open_until = CASE
+              WHEN consecutive_failures + 1 >= $2 THEN now() + make_interval(secs => $3)
+              ELSE circuit_open_until
+            END
+        WHERE id = $1
+        RETURNING circuit_open_until
+        "#,
+        endpoint_id,
+        failure_threshold,
+        cooldown_secs
+    )
+    .fetch_optional(db)
+    .await?
+    .flatten();
+
+    Ok(open_until.is_some())
+}
+
+// What the receiver answered on this attempt (overwritten on each retry)
+pub async fn record_delivery_response(
+    db: &PgPool,
+    delivery_id: Uuid,
+    response: &WebhookResponse,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries
+        SET response_status = $2,
+            response_snippet = $3,
+            response_content_length = $4,
+            response_truncated = $5,
+            response_time_ms = $6
+        WHERE id = $1
+        "#,
+        delivery_id,
+        i32::from(response.status),
+        response.snippet,
+        response.content_length.map(|n| n as i64),
+        response.truncated,
+        response.latency.as_millis() as i32
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// A 2xx for the event saying an intent succeeded acknowledges that intent (first one wins):
+// payment_intent.succeeded, or payment_intent.captured for a manual-capture intent
+pub async fn record_payment_intent_acknowledged(
+    db: &PgPool,
+    event_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE payment_intents pi
+        SET webhook_acknowledged_at = now()
+        FROM events_outbox e
+        WHERE e.id = $1
+          AND e.event_type IN ($2, $3)
+          AND pi.id = (e.payload->'payment_intent'->>'id')::uuid
+          AND pi.webhook_acknowledged_at IS NULL
+        "#,
+        event_id,
+        PAYMENT_INTENT_SUCCEEDED,
+        PAYMENT_INTENT_CAPTURED
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
 // 3) Mark delivery result after HTTP attempt
 pub async fn mark_delivery_succeeded(db: &PgPool, delivery_id: Uuid) -> Result<(), sqlx::Error> {
     sqlx::query!(
@@ -134,13 +395,13 @@ pub async fn mark_delivery_succeeded(db: &PgPool, delivery_id: Uuid) -> Result<(
 
 pub async fn mark_delivery_failed(
     db: &PgPool,
-    delivery_id: Uuid,
+    policy: RetryPolicy,
     attempt_count: i32,
     error: String,
 ) -> Result<(), sqlx::Error> {
-    const MAX_ATTEMPTS: i32 = 10;
-
-    if attempt_count >= MAX_ATTEMPTS {
+    let delivery_id = policy.delivery_id;
+    // Shared with the API, which shows the schedule on delivery objects
+    let Some(delay) = retry_delay(policy, attempt_count) else {
         sqlx::query!(
             r#"
             UPDATE webhook_deliveries
@@ -157,22 +418,19 @@ pub async fn mark_delivery_failed(
         .await?;
 
         return Ok(());
-    }
-
-    // Simple exponential backoff with cap
-    let delay_secs = (2_i64.pow(attempt_count.min(10) as u32)).min(60);
+    };
 
     sqlx::query!(
         r#"
         UPDATE webhook_deliveries
         SET status = 'pending',
-            next_attempt_at = now() + ($2 || ' seconds')::interval,
+            next_attempt_at = now() + make_interval(secs => $2),
             last_error = $3,
             updated_at = now()
         WHERE id = $1
         "#,
         delivery_id,
-        delay_secs.to_string(),
+        delay.num_milliseconds() as f64 / 1000.0,
         error
     )
     .execute(db)
@@ -211,3 +469,922 @@ pub async fn maybe_mark_event_delivered(db: &PgPool, event_id: Uuid) -> Result<(
 
     Ok(())
 }
+
+// Digest limits: the counts always cover every failure, these only bound the lists
+const DIGEST_TOP_EVENT_TYPES: i64 = 5;
+const DIGEST_MAX_DELIVERY_IDS: i64 = 100;
+
+// Once per UTC day: summarizes the previous day's permanently failed deliveries as a
+// webhook_deliveries.daily_digest event, which then goes out like any other. `now` is passed in
+// so tests can step over midnight. The day is claimed in the same transaction as the event, so
+// restarts and concurrent workers never send it twice. Returns the event id, or None when the
+// day was already done or had no failures.
+pub async fn emit_daily_digest(
+    db: &PgPool,
+    now: DateTime<Utc>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let today = now.date_naive();
+    let Some(day) = today.pred_opt() else {
+        return Ok(None);
+    };
+    let window_start = day.and_time(NaiveTime::MIN).and_utc();
+    let window_end = today.and_time(NaiveTime::MIN).and_utc();
+
+    let mut tx = db.begin().await?;
+
+    let claimed = sqlx::query!(
+        r#"
+        UPDATE webhook_digest_state
+        SET last_digest_date = $1, updated_at = now()
+        WHERE last_digest_date IS NULL OR last_digest_date < $1
+        "#,
+        day
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        == 1;
+    if !claimed {
+        return Ok(None);
+    }
+
+    let endpoints = sqlx::query!(
+        r#"
+        SELECT webhook_endpoint_id, COUNT(*) AS "failed!"
+        FROM webhook_deliveries
+        WHERE status = 'failed' AND updated_at >= $1 AND updated_at < $2
+        GROUP BY webhook_endpoint_id
+        ORDER BY 2 DESC, webhook_endpoint_id
+        "#,
+        window_start,
+        window_end
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // Nothing to report; the day still counts as done
+    if endpoints.is_empty() {
+        tx.commit().await?;
+        return Ok(None);
+    }
+
+    let event_types = sqlx::query!(
+        r#"
+        SELECT e.event_type, COUNT(*) AS "failed!"
+        FROM webhook_deliveries d
+        JOIN events_outbox e ON e.id = d.event_id
+        WHERE d.status = 'failed' AND d.updated_at >= $1 AND d.updated_at < $2
+        GROUP BY e.event_type
+        ORDER BY 2 DESC, e.event_type
+        LIMIT $3
+        "#,
+        window_start,
+        window_end,
+        DIGEST_TOP_EVENT_TYPES
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let delivery_ids = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM webhook_deliveries
+        WHERE status = 'failed' AND updated_at >= $1 AND updated_at < $2
+        ORDER BY updated_at, id
+        LIMIT $3
+        "#,
+        window_start,
+        window_end,
+        DIGEST_MAX_DELIVERY_IDS
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let total: i64 = endpoints.iter().map(|e| e.failed).sum();
+    let payload = serde_json::json!({
+        "date": day,
+        "window_start": window_start,
+        "window_end": window_end,
+        "failed_deliveries": total,
+        "endpoints": endpoints
+            .iter()
+            .map(|e| serde_json::json!({
+                "webhook_endpoint_id": e.webhook_endpoint_id,
+                "failed": e.failed,
+            }))
+            .collect::<Vec<_>>(),
+        "top_event_types": event_types
+            .iter()
+            .map(|t| serde_json::json!({ "event_type": t.event_type, "failed": t.failed }))
+            .collect::<Vec<_>>(),
+        // Oldest first; see GET /v1/webhook_endpoints/{id}/deliveries for the rest
+        "failed_delivery_ids": delivery_ids,
+        "failed_delivery_ids_truncated": total > DIGEST_MAX_DELIVERY_IDS,
+    });
+
+    let event_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO events_outbox (id, event_type, payload)
+        VALUES ($1, 'webhook_deliveries.daily_digest', $2)
+        "#,
+        event_id,
+        payload
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(event_id))
+}
+
+// One webhook_endpoint.certificate_expiring event per endpoint whose client certificate expires
+// within CERTIFICATE_EXPIRY_WARNING_DAYS of `now`. Uploading a new certificate clears the marker,
+// so each certificate is warned about once. Returns the events' ids.
+pub async fn emit_certificate_expiry_warnings(
+    db: &PgPool,
+    now: DateTime<Utc>,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let expiring = sqlx::query!(
+        r#"
+        UPDATE webhook_endpoints
+        SET client_certificate_expiry_notified_at = $1
+        WHERE client_certificate_expires_at < $2
+          AND client_certificate_expiry_notified_at IS NULL
+        RETURNING id, url, client_certificate_fingerprint, client_certificate_subject,
+                  client_certificate_expires_at
+        "#,
+        now,
+        now + chrono::Duration::days(CERTIFICATE_EXPIRY_WARNING_DAYS)
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut event_ids = Vec::with_capacity(expiring.len());
+    for endpoint in expiring {
+        let payload = serde_json::json!({
+            "webhook_endpoint": { "id": endpoint.id, "url": endpoint.url },
+            "client_certificate": {
+                "fingerprint": endpoint.client_certificate_fingerprint,
+                "subject": endpoint.client_certificate_subject,
+                "expires_at": endpoint.client_certificate_expires_at,
+            },
+        });
+        let event_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO events_outbox (id, event_type, payload) VALUES ($1, $2, $3)",
+            event_id,
+            WEBHOOK_ENDPOINT_CERTIFICATE_EXPIRING,
+            payload
+        )
+        .execute(&mut *tx)
+        .await?;
+        event_ids.push(event_id);
+    }
+
+    tx.commit().await?;
+    Ok(event_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mini_stripe_types::delivery::{MAX_DELIVERY_ATTEMPTS, RetrySchedule, retry_schedule};
+
+    async fn insert_event_at(db: &PgPool, created_at: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO events_outbox (id, event_type, payload, created_at)
+            VALUES ($1, 'payment_intent.created', '{}'::jsonb, $2::text::timestamptz)
+            "#,
+            id,
+            created_at
+        )
+        .execute(db)
+        .await
+        .unwrap();
+        id
+    }
+
+    async fn delivered_event_ids(db: &PgPool) -> Vec<Uuid> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT event_id
+            FROM webhook_deliveries
+            ORDER BY created_at, event_id
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .unwrap()
+    }
+
+    async fn enqueue(db: &PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        enqueue_missing_deliveries(&mut tx, MAX_DELIVERY_ATTEMPTS)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn draining_only_enqueues_events_before_marker(pool: PgPool) {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (id, url, secret)
+            VALUES ($1, 'http://localhost:9000/webhook', 'secret')
+            "#,
+            Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let before = insert_event_at(&pool, "2026-01-01T00:00:00Z").await;
+        sqlx::query!("UPDATE outbox_state SET draining_since = '2026-01-01T00:00:01Z'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let after = insert_event_at(&pool, "2026-01-01T00:00:02Z").await;
+
+        enqueue(&pool).await;
+        assert_eq!(delivered_event_ids(&pool).await, vec![before]);
+
+        // Resume: the event created while draining is caught up
+        sqlx::query!("UPDATE outbox_state SET draining_since = NULL")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        enqueue(&pool).await;
+        let ids = delivered_event_ids(&pool).await;
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&after));
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn endpoints_only_get_their_enabled_events(pool: PgPool) {
+        let (all, some) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (id, url, secret, enabled_events)
+            VALUES ($1, 'http://localhost:9000/all', 'secret', DEFAULT),
+                   ($2, 'http://localhost:9000/some', 'secret', '{payment_intent.succeeded}')
+            "#,
+            all,
+            some
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let created = insert_pi_event_at(
+            &pool,
+            "payment_intent.created",
+            "pi",
+            "2026-01-01T00:00:00Z",
+        )
+        .await;
+        let succeeded = insert_pi_event_at(
+            &pool,
+            "payment_intent.succeeded",
+            "pi",
+            "2026-01-01T00:00:01Z",
+        )
+        .await;
+
+        enqueue(&pool).await;
+        let deliveries = sqlx::query!(
+            "SELECT event_id, webhook_endpoint_id FROM webhook_deliveries ORDER BY created_at, event_id"
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let to = |endpoint: Uuid| {
+            let mut ids: Vec<Uuid> = deliveries
+                .iter()
+                .filter(|d| d.webhook_endpoint_id == endpoint)
+                .map(|d| d.event_id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        let mut both = vec![created, succeeded];
+        both.sort();
+        assert_eq!(to(all), both);
+        assert_eq!(to(some), vec![succeeded]);
+    }
+
+    async fn insert_pi_event_at(db: &PgPool, event_type: &str, pi: &str, created_at: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO events_outbox (id, event_type, payload, created_at)
+            VALUES ($1, $2, jsonb_build_object('payment_intent', jsonb_build_object('id', $3::text)), $4::text::timestamptz)
+            "#,
+            id,
+            event_type,
+            pi,
+            created_at
+        )
+        .execute(db)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn compaction_keeps_only_latest_update_per_run(pool: PgPool) {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (id, url, secret)
+            VALUES ($1, 'http://localhost:9000/webhook', 'secret')
+            "#,
+            Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut updates = Vec::new();
+        for i in 0..5 {
+            let at = format!("2026-01-01T00:00:0{i}Z");
+            updates.push(insert_pi_event_at(&pool, "payment_intent.updated", "pi_1", &at).await);
+        }
+        let succeeded = insert_pi_event_at(
+            &pool,
+            "payment_intent.succeeded",
+            "pi_1",
+            "2026-01-01T00:00:06Z",
+        )
+        .await;
+        let other = insert_pi_event_at(
+            &pool,
+            "payment_intent.updated",
+            "pi_2",
+            "2026-01-01T00:00:01Z",
+        )
+        .await;
+
+        let mut tx = pool.begin().await.unwrap();
+        let superseded = compact_events(&mut tx, &["payment_intent.updated".to_string()])
+            .await
+            .unwrap();
+        enqueue_missing_deliveries(&mut tx, MAX_DELIVERY_ATTEMPTS)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(superseded, 4);
+        let mut delivered = delivered_event_ids(&pool).await;
+        delivered.sort();
+        let mut expected = vec![updates[4], succeeded, other];
+        expected.sort();
+        assert_eq!(delivered, expected);
+
+        let marked: Vec<Uuid> = sqlx::query_scalar!(
+            "SELECT id FROM events_outbox WHERE superseded_at IS NOT NULL ORDER BY created_at"
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(marked, updates[..4]);
+
+        // Non-compactable types are never collapsed; an intervening event breaks the run
+        let after = insert_pi_event_at(
+            &pool,
+            "payment_intent.updated",
+            "pi_1",
+            "2026-01-01T00:00:07Z",
+        )
+        .await;
+        let mut tx = pool.begin().await.unwrap();
+        assert_eq!(
+            compact_events(&mut tx, &["payment_intent.updated".to_string()])
+                .await
+                .unwrap(),
+            0
+        );
+        enqueue_missing_deliveries(&mut tx, MAX_DELIVERY_ATTEMPTS)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert!(delivered_event_ids(&pool).await.contains(&after));
+    }
+
+    async fn claim(db: &PgPool) -> Option<ClaimedDelivery> {
+        let mut tx = db.begin().await.unwrap();
+        let claimed = claim_one_due_delivery(&mut tx, 60.0).await.unwrap();
+        tx.commit().await.unwrap();
+        claimed
+    }
+
+    // Mock clock: move every schedule/circuit timestamp back instead of waiting
+    async fn advance_clock(db: &PgPool, secs: f64) {
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET next_attempt_at = next_attempt_at - make_interval(secs => $1)
+            "#,
+            secs
+        )
+        .execute(db)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            UPDATE webhook_endpoints
+            SET circuit_open_until = circuit_open_until - make_interval(secs => $1)
+            "#,
+            secs
+        )
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    async fn total_attempts(db: &PgPool) -> i64 {
+        sqlx::query_scalar!(
+            r#"SELECT SUM(attempt_count)::bigint AS "sum!" FROM webhook_deliveries"#
+        )
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn circuit_breaker_parks_deliveries_until_probe_succeeds(pool: PgPool) {
+        const THRESHOLD: i32 = 3;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (id, url, secret)
+            VALUES ($1, 'http://localhost:9/down', 'secret')
+            "#,
+            Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut events = Vec::new();
+        for i in 0..5 {
+            events.push(insert_event_at(&pool, &format!("2026-01-01T00:00:0{i}Z")).await);
+        }
+        enqueue(&pool).await;
+
+        // Outage: each attempt fails at the connection level until the circuit opens
+        for i in 0..THRESHOLD {
+            let job = claim(&pool).await.unwrap();
+            let open = record_endpoint_unreachable(&pool, job.endpoint_id, THRESHOLD, 60.0)
+                .await
+                .unwrap();
+            mark_delivery_failed(
+                &pool,
+                job.retry_policy(),
+                job.attempt_count,
+                "refused".into(),
+            )
+            .await
+            .unwrap();
+            advance_clock(&pool, 5.0).await;
+            assert_eq!(open, i == THRESHOLD - 1);
+        }
+
+        // Open: everything is due, but nothing is attempted
+        advance_clock(&pool, 30.0).await;
+        assert!(claim(&pool).await.is_none());
+        assert_eq!(total_attempts(&pool).await, THRESHOLD as i64);
+
+        // Cool-down over: exactly one probe goes out
+        advance_clock(&pool, 60.0).await;
+        let probe = claim(&pool).await.unwrap();
+        assert!(probe.is_probe);
+        assert!(claim(&pool).await.is_none());
+
+        record_endpoint_reachable(&pool, probe.endpoint_id)
+            .await
+            .unwrap();
+        mark_delivery_succeeded(&pool, probe.delivery_id)
+            .await
+            .unwrap();
+
+        // Closed: the backlog drains on its normal schedule
+        let mut drained = vec![probe.event_id];
+        while let Some(job) = claim(&pool).await {
+            assert!(!job.is_probe);
+            mark_delivery_succeeded(&pool, job.delivery_id)
+                .await
+                .unwrap();
+            drained.push(job.event_id);
+        }
+        drained.sort();
+        events.sort();
+        assert_eq!(drained, events);
+    }
+
+    // What the API shows for the only delivery, on the database clock
+    async fn shown_schedule(db: &PgPool) -> (RetrySchedule, DateTime<Utc>) {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, max_attempts, status, attempt_count, next_attempt_at, now() AS "now!"
+            FROM webhook_deliveries
+            "#
+        )
+        .fetch_one(db)
+        .await
+        .unwrap();
+        let policy = RetryPolicy {
+            delivery_id: row.id,
+            max_attempts: row.max_attempts,
+        };
+        let schedule = retry_schedule(
+            policy,
+            &row.status,
+            row.attempt_count,
+            row.next_attempt_at,
+            row.now,
+        );
+        (schedule, row.now)
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn retries_happen_when_the_shown_schedule_says(pool: PgPool) {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (id, url, secret)
+            VALUES ($1, 'http://localhost:9/down', 'secret')
+            "#,
+            Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        insert_event_at(&pool, "2026-01-01T00:00:00Z").await;
+        enqueue(&pool).await;
+
+        let job = claim(&pool).await.unwrap();
+        mark_delivery_failed(
+            &pool,
+            job.retry_policy(),
+            job.attempt_count,
+            "refused".into(),
+        )
+        .await
+        .unwrap();
+        let (mut shown, _) = shown_schedule(&pool).await;
+        assert_eq!(shown.attempts_remaining, MAX_DELIVERY_ATTEMPTS - 1);
+
+        while shown.attempts_remaining > 0 {
+            // Mock clock: jump to the next scheduled attempt, which fails again
+            let (_, now) = shown_schedule(&pool).await;
+            let wait = (shown.remaining_schedule[0] - now).num_milliseconds() as f64 / 1000.0;
+            advance_clock(&pool, wait.max(0.0)).await;
+            let job = claim(&pool).await.unwrap();
+            mark_delivery_failed(
+                &pool,
+                job.retry_policy(),
+                job.attempt_count,
+                "refused".into(),
+            )
+            .await
+            .unwrap();
+
+            let (next, now) = shown_schedule(&pool).await;
+            assert_eq!(next.attempts_remaining, shown.attempts_remaining - 1);
+            if let [promised_at, promised_next, ..] = shown.remaining_schedule[..] {
+                // The worker's real backoff is the gap the API showed
+                let gap = next.remaining_schedule[0] - now;
+                let promised_gap = promised_next - promised_at;
+                assert!(
+                    (gap - promised_gap).num_milliseconds().abs() < 1000,
+                    "{gap} vs {promised_gap}"
+                );
+            }
+            shown = next;
+        }
+
+        assert!(shown.will_not_retry);
+        assert!(shown.remaining_schedule.is_empty());
+        assert!(claim(&pool).await.is_none());
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn deliveries_keep_the_attempt_limit_they_were_enqueued_with(pool: PgPool) {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (id, url, secret)
+            VALUES ($1, 'http://localhost:9/down', 'secret')
+            "#,
+            Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        insert_event_at(&pool, "2026-01-01T00:00:00Z").await;
+        let mut tx = pool.begin().await.unwrap();
+        enqueue_missing_deliveries(&mut tx, 2).await.unwrap();
+        tx.commit().await.unwrap();
+
+        for _ in 0..2 {
+            let job = claim(&pool).await.unwrap();
+            assert_eq!(job.max_attempts, 2);
+            mark_delivery_failed(
+                &pool,
+                job.retry_policy(),
+                job.attempt_count,
+                "refused".into(),
+            )
+            .await
+            .unwrap();
+            advance_clock(&pool, 10.0).await;
+        }
+
+        let (shown, _) = shown_schedule(&pool).await;
+        assert!(shown.will_not_retry);
+        assert!(claim(&pool).await.is_none());
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn first_succeeded_delivery_acknowledges_the_intent(pool: PgPool) {
+        let pi_id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO payment_intents (id, amount, currency, status)
+            VALUES ($1, 1000, 'gbp', 'succeeded')
+            "#,
+            pi_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let acknowledged_at = |pool: PgPool| async move {
+            sqlx::query_scalar!(
+                "SELECT webhook_acknowledged_at FROM payment_intents WHERE id = $1",
+                pi_id
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+        };
+
+        let pi = pi_id.to_string();
+        let created =
+            insert_pi_event_at(&pool, "payment_intent.created", &pi, "2026-01-01T00:00:00Z").await;
+        record_payment_intent_acknowledged(&pool, created)
+            .await
+            .unwrap();
+        assert_eq!(acknowledged_at(pool.clone()).await, None);
+
+        let succeeded = insert_pi_event_at(
+            &pool,
+            "payment_intent.succeeded",
+            &pi,
+            "2026-01-01T00:00:01Z",
+        )
+        .await;
+        record_payment_intent_acknowledged(&pool, succeeded)
+            .await
+            .unwrap();
+        let first = acknowledged_at(pool.clone()).await;
+        assert!(first.is_some());
+
+        // A second endpoint acknowledging later does not move the timestamp
+        record_payment_intent_acknowledged(&pool, succeeded)
+            .await
+            .unwrap();
+        assert_eq!(acknowledged_at(pool.clone()).await, first);
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn captured_delivery_acknowledges_a_manual_capture_intent(pool: PgPool) {
+        let pi_id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO payment_intents (id, amount, currency, status, capture_method, amount_received)
+            VALUES ($1, 1000, 'gbp', 'succeeded', 'manual', 1000)
+            "#,
+            pi_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let pi = pi_id.to_string();
+        let captured = insert_pi_event_at(
+            &pool,
+            "payment_intent.captured",
+            &pi,
+            "2026-01-01T00:00:00Z",
+        )
+        .await;
+        record_payment_intent_acknowledged(&pool, captured)
+            .await
+            .unwrap();
+
+        let acknowledged_at = sqlx::query_scalar!(
+            "SELECT webhook_acknowledged_at FROM payment_intents WHERE id = $1",
+            pi_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(acknowledged_at.is_some());
+    }
+
+    async fn fail_deliveries(db: &PgPool, endpoint_id: Uuid, event_ids: &[Uuid], at: &str) {
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'failed', next_attempt_at = NULL, updated_at = $3::text::timestamptz
+            WHERE webhook_endpoint_id = $1 AND event_id = ANY($2)
+            "#,
+            endpoint_id,
+            event_ids,
+            at
+        )
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn daily_digest_is_sent_once_per_day_with_failures(pool: PgPool) {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        for (id, url) in [
+            (a, "http://localhost:9000/a"),
+            (b, "http://localhost:9000/b"),
+        ] {
+            sqlx::query!(
+                "INSERT INTO webhook_endpoints (id, url, secret) VALUES ($1, $2, 'secret')",
+                id,
+                url
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let created = insert_pi_event_at(
+            &pool,
+            "payment_intent.created",
+            "pi_1",
+            "2026-10-15T09:00:00Z",
+        )
+        .await;
+        let succeeded = [
+            insert_pi_event_at(
+                &pool,
+                "payment_intent.succeeded",
+                "pi_1",
+                "2026-10-15T09:00:01Z",
+            )
+            .await,
+            insert_pi_event_at(
+                &pool,
+                "payment_intent.succeeded",
+                "pi_2",
+                "2026-10-15T09:00:02Z",
+            )
+            .await,
+        ];
+        enqueue(&pool).await;
+
+        fail_deliveries(&pool, a, &[created], "2026-10-15T10:00:00Z").await;
+        fail_deliveries(&pool, a, &succeeded, "2026-10-15T10:00:00Z").await;
+        fail_deliveries(&pool, b, &[created], "2026-10-15T12:00:00Z").await;
+        // After midnight: tomorrow's digest
+        fail_deliveries(&pool, b, &succeeded[..1], "2026-10-16T00:00:01Z").await;
+
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+
+        // Just before midnight the previous day (no failures) is settled with no event
+        assert_eq!(
+            emit_daily_digest(&pool, at("2026-10-15T23:59:59Z"))
+                .await
+                .unwrap(),
+            None
+        );
+        let digest = emit_daily_digest(&pool, at("2026-10-16T00:00:05Z"))
+            .await
+            .unwrap()
+            .expect("digest for 2026-10-15");
+        // Later the same day, or after a restart: nothing more
+        assert_eq!(
+            emit_daily_digest(&pool, at("2026-10-16T09:00:00Z"))
+                .await
+                .unwrap(),
+            None
+        );
+
+        let digests = sqlx::query!(
+            "SELECT id, payload FROM events_outbox WHERE event_type = 'webhook_deliveries.daily_digest'"
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(digests.len(), 1);
+        assert_eq!(digests[0].id, digest);
+
+        let payload = &digests[0].payload;
+        assert_eq!(payload["date"], "2026-10-15");
+        assert_eq!(payload["failed_deliveries"], 4);
+        assert_eq!(
+            payload["endpoints"],
+            serde_json::json!([
+                { "webhook_endpoint_id": a, "failed": 3 },
+                { "webhook_endpoint_id": b, "failed": 1 },
+            ])
+        );
+        assert_eq!(
+            payload["top_event_types"],
+            serde_json::json!([
+                { "event_type": "payment_intent.created", "failed": 2 },
+                { "event_type": "payment_intent.succeeded", "failed": 2 },
+            ])
+        );
+        assert_eq!(payload["failed_delivery_ids"].as_array().unwrap().len(), 4);
+        assert_eq!(payload["failed_delivery_ids_truncated"], false);
+
+        // Delivered through the normal pipeline
+        enqueue(&pool).await;
+        assert_eq!(
+            delivered_event_ids(&pool)
+                .await
+                .iter()
+                .filter(|id| **id == digest)
+                .count(),
+            2
+        );
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn expiring_client_certificates_are_warned_about_once(pool: PgPool) {
+        let now: DateTime<Utc> = "2026-10-16T12:00:00Z".parse().unwrap();
+        let (soon, later) = (Uuid::new_v4(), Uuid::new_v4());
+        for (id, expires_at) in [
+            (soon, now + chrono::Duration::days(10)),
+            (later, now + chrono::Duration::days(60)),
+        ] {
+            sqlx::query!(
+                r#"
+                INSERT INTO webhook_endpoints (
+                  id, url, secret, client_certificate, client_certificate_fingerprint,
+                  client_certificate_subject, client_certificate_expires_at
+                )
+                VALUES ($1, $2, 'secret', 'pem', 'fp', 'CN=hooks', $3)
+                "#,
+                id,
+                format!("https://example.com/hooks/{id}"),
+                expires_at
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        // And one without a certificate at all
+        sqlx::query!(
+            "INSERT INTO webhook_endpoints (id, url, secret) VALUES ($1, 'https://example.com', 's')",
+            Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let emitted = emit_certificate_expiry_warnings(&pool, now).await.unwrap();
+        assert_eq!(emitted.len(), 1);
+        assert!(
+            emit_certificate_expiry_warnings(&pool, now)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let event = sqlx::query!(
+            "SELECT event_type, payload FROM events_outbox WHERE id = $1",
+            emitted[0]
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(event.event_type, WEBHOOK_ENDPOINT_CERTIFICATE_EXPIRING);
+        assert_eq!(event.payload["webhook_endpoint"]["id"], soon.to_string());
+        assert_eq!(event.payload["client_certificate"]["fingerprint"], "fp");
+        assert_eq!(event.payload["client_certificate"]["subject"], "CN=hooks");
+
+        // A month on, the other certificate is due
+        let emitted = emit_certificate_expiry_warnings(&pool, now + chrono::Duration::days(31))
+            .await
+            .unwrap();
+        assert_eq!(emitted.len(), 1);
+        let warned = sqlx::query_scalar!(
+            "SELECT payload #>> '{webhook_endpoint,id}' FROM events_outbox WHERE id = $1",
+            emitted[0]
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(warned, Some(later.to_string()));
+    }
+}
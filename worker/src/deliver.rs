@@ -1,27 +1,586 @@
-use reqwest::Client;
+use chrono::Utc;
+use mini_stripe_types::payload_template::{self, PayloadTemplate};
+use mini_stripe_types::signature::{self, SIGNATURE_HEADER, SignatureScheme};
+use reqwest::{Certificate, Client, Identity};
 use serde_json::Value;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use crate::signature;
+use uuid::Uuid;
+
+const ECHO_URL: &str = "internal://echo";
+// Clients kept for endpoints with a client certificate; the cache starts over when it fills up
+const MAX_CERTIFICATE_CLIENTS: usize = 256;
+
+// internal://echo endpoints are received in-process by the API (sandbox mode only). The worker
+// doesn't link the api crate, so this mirrors api::routes::SANDBOX_ECHO by hand
+pub fn target_url(endpoint_url: &str, endpoint_id: Uuid, api_base_url: &str) -> String {
+    if endpoint_url == ECHO_URL {
+        return format!(
+            "{}/v1/sandbox/echo/{endpoint_id}",
+            api_base_url.trim_end_matches('/')
+        );
+    }
+    endpoint_url.to_string()
+}
+
+// Oversized events go out "thin": consumers fetch the full object from the API
+pub fn delivery_body(event: Value, max_bytes: usize) -> Value {
+    if event.to_string().len() <= max_bytes {
+        return event;
+    }
+
+    // Payloads look like {"payment_intent": {...}}; the single object's id is enough to refetch
+    let object_id = event["data"]
+        .as_object()
+        .and_then(|data| data.values().find_map(|object| object.get("id")))
+        .cloned()
+        .unwrap_or(Value::Null);
+
+    serde_json::json!({
+        "id": event["id"],
+        "type": event["type"],
+        "data": { "object": { "id": object_id } },
+        "payload_truncated": true
+    })
+}
+
+// The body to post: the endpoint's payload template applied to a payment_intent event, else the
+// standard envelope. A template that doesn't parse or render is an error, so the delivery fails
+// (and retries, picking up a fixed template) rather than going out in a shape the receiver
+// doesn't expect. Templated bodies are built from the full event and are never thin.
+pub fn render_body(
+    event: Value,
+    template: Option<&Value>,
+    max_bytes: usize,
+) -> Result<Value, String> {
+    let event_type = event["type"].as_str().unwrap_or_default();
+    let Some(spec) = template.filter(|_| payload_template::applies_to(event_type)) else {
+        return Ok(delivery_body(event, max_bytes));
+    };
+    PayloadTemplate::parse(spec)
+        .and_then(|template| template.render(&event))
+        .map_err(|e| format!("payload template: {e}"))
+}
+
+// HTTP clients for deliveries. Endpoints with a client certificate (mutual TLS) each get their
+// own client presenting it, cached by certificate fingerprint so connections are reused and the
+// certificate is only decrypted and parsed again after it changes.
+pub struct Clients {
+    default: Client,
+    // Trusted in addition to the public roots, by every client (WEBHOOK_CA_CERTS)
+    root_certificates: Vec<Certificate>,
+    with_certificate: Mutex<HashMap<String, Client>>,
+}
+
+impl Clients {
+    pub fn new(root_certificates: Vec<Certificate>) -> Result<Self, reqwest::Error> {
+        let default = root_certificates
+            .iter()
+            .cloned()
+            .fold(Client::builder(), |builder, cert| {
+                builder.add_root_certificate(cert)
+            })
+            .build()?;
+        Ok(Self {
+            default,
+            root_certificates,
+            with_certificate: Mutex::default(),
+        })
+    }
+
+    pub fn default_client(&self) -> &Client {
+        &self.default
+    }
+
+    // The client presenting the certificate with `fingerprint`. `bundle` (certificate chain and
+    // key as PEM) is only asked for when there is no cached client for it.
+    pub fn with_certificate(
+        &self,
+        fingerprint: &str,
+        bundle: impl FnOnce() -> Result<String, String>,
+    ) -> Result<Client, String> {
+        if let Some(client) = self.with_certificate.lock().unwrap().get(fingerprint) {
+            return Ok(client.clone());
+        }
+        let identity = Identity::from_pem(bundle()?.as_bytes())
+            .map_err(|e| format!("client certificate: {e}"))?;
+        let client = self
+            .root_certificates
+            .iter()
+            .cloned()
+            .fold(Client::builder().use_rustls_tls(), |builder, cert| {
+                builder.add_root_certificate(cert)
+            })
+            .identity(identity)
+            .build()
+            .map_err(|e| format!("client certificate: {e}"))?;
+
+        let mut cache = self.with_certificate.lock().unwrap();
+        if cache.len() >= MAX_CERTIFICATE_CLIENTS {
+            cache.clear();
+        }
+        cache.insert(fingerprint.to_string(), client.clone());
+        Ok(client)
+    }
+
+    #[cfg(test)]
+    pub fn cached_certificate_clients(&self) -> usize {
+        self.with_certificate.lock().unwrap().len()
+    }
+}
+
+pub struct WebhookResponse {
+    pub status: u16,
+    // At most `max_snippet_bytes` of the body, valid UTF-8 with control characters removed
+    pub snippet: String,
+    // Content-Length the receiver advertised, if any
+    pub content_length: Option<u64>,
+    pub truncated: bool,
+    // Time until the receiver's response headers arrived
+    pub latency: Duration,
+}
+
+// Receivers control these bytes: keep them printable so they are safe to store and echo back as JSON
+pub fn sanitize_snippet(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect()
+}
 
 pub async fn post_webhook(
     client: &Client,
     url: &str,
     secret: &str,
+    scheme: SignatureScheme,
     body: &Value,
-) -> Result<u16, String> {
+    max_snippet_bytes: usize,
+) -> Result<WebhookResponse, String> {
     let bytes = serde_json::to_vec(body).map_err(|e| format!("json encode: {e}"))?;
-    let sig = signature::sign(secret, &bytes);
+    let sig = signature::sign_webhook(scheme, secret, Utc::now().timestamp(), &bytes);
 
-    let res = client
+    let started = Instant::now();
+    let mut res = client
         .post(url)
         .timeout(Duration::from_secs(5))
         .header("content-type", "application/json")
-        .header("x-ministripe-signature", sig)
+        .header(SIGNATURE_HEADER, sig)
         .body(bytes)
         .send()
         .await
         .map_err(|e| format!("http error: {e}"))?;
+    let latency = started.elapsed();
+
+    let status = res.status().as_u16();
+    let content_length = res.content_length();
+
+    // Read chunk by chunk so a huge body is never buffered; stop as soon as we have enough
+    let mut snippet = Vec::new();
+    let mut truncated = false;
+    loop {
+        match res.chunk().await {
+            Ok(Some(chunk)) => {
+                let room = max_snippet_bytes - snippet.len();
+                if chunk.len() > room {
+                    snippet.extend_from_slice(&chunk[..room]);
+                    truncated = true;
+                    break;
+                }
+                snippet.extend_from_slice(&chunk);
+            }
+            Ok(None) => break,
+            // The status is what matters; a body that fails midway is just cut short
+            Err(_) => {
+                truncated = true;
+                break;
+            }
+        }
+    }
+
+    Ok(WebhookResponse {
+        status,
+        snippet: sanitize_snippet(&snippet),
+        content_length,
+        truncated,
+        latency,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn small_events_are_delivered_in_full() {
+        let event = serde_json::json!({
+            "id": "evt",
+            "type": "payment_intent.created",
+            "data": { "payment_intent": { "id": "pi", "amount": 100 } }
+        });
+        assert_eq!(delivery_body(event.clone(), 1024), event);
+    }
+
+    #[test]
+    fn oversized_events_are_delivered_thin() {
+        let event = serde_json::json!({
+            "id": "evt",
+            "type": "payment_intent.created",
+            "created_at": "2026-01-01T00:00:00Z",
+            "data": { "payment_intent": { "id": "pi", "note": "x".repeat(2048) } }
+        });
+
+        assert_eq!(
+            delivery_body(event, 1024),
+            serde_json::json!({
+                "id": "evt",
+                "type": "payment_intent.created",
+                "data": { "object": { "id": "pi" } },
+                "payload_truncated": true
+            })
+        );
+    }
+
+    #[test]
+    fn templates_reshape_payment_intent_events_only() {
+        let template = serde_json::json!({ "fields": {
+            "txn": { "path": "$.data.payment_intent.id" },
+            "source": { "const": "mini-stripe" }
+        }});
+        let event = serde_json::json!({
+            "id": "evt",
+            "type": "payment_intent.succeeded",
+            "data": { "payment_intent": { "id": "pi", "amount": 100 } }
+        });
+        assert_eq!(
+            render_body(event, Some(&template), 1024).unwrap(),
+            serde_json::json!({ "txn": "pi", "source": "mini-stripe" })
+        );
+
+        let other = serde_json::json!({ "id": "evt", "type": "webhook_endpoint.secret_revealed" });
+        assert_eq!(
+            render_body(other.clone(), Some(&template), 1024).unwrap(),
+            other
+        );
+    }
+
+    #[test]
+    fn malformed_templates_fail_closed() {
+        let event = serde_json::json!({
+            "id": "evt",
+            "type": "payment_intent.succeeded",
+            "data": { "payment_intent": { "id": "pi" } }
+        });
+        let template = serde_json::json!({ "fields": { "txn": { "eval": "data.id" } } });
+        let err = render_body(event, Some(&template), 1024).unwrap_err();
+        assert!(err.starts_with("payload template: template.txn"), "{err}");
+    }
+
+    #[test]
+    fn sanitize_keeps_text_and_drops_control_characters() {
+        assert_eq!(
+            sanitize_snippet("ok 👍\n\tdone".as_bytes()),
+            "ok 👍\n\tdone"
+        );
+        assert_eq!(sanitize_snippet(b"a\0b\x1b[31mc\r"), "ab[31mc");
+        assert_eq!(
+            sanitize_snippet(&[0x66, 0xff, 0xfe, 0x6f]),
+            "f\u{fffd}\u{fffd}o"
+        );
+        // A multi-byte character cut in half by the byte limit
+        assert_eq!(sanitize_snippet(&"👍".as_bytes()[..2]), "\u{fffd}");
+    }
+
+    // Minimal HTTP receiver that answers every request with `body`
+    async fn stub_receiver(body: Vec<u8>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let body = body.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    let _ = socket.read(&mut buf).await;
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(&body).await;
+                });
+            }
+        });
+        format!("http://{addr}/webhook")
+    }
+
+    // Reads one request off `socket`, answers 200 and sends on its signature header and body
+    async fn answer_and_capture<S>(mut socket: S, tx: &tokio::sync::mpsc::Sender<(String, Vec<u8>)>)
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        // Headers, then as much body as content-length says
+        let (head, body_len) = loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                let len = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("content-length:"))
+                    .map_or(0, |v| v.trim().parse().unwrap());
+                request.drain(..end + 4);
+                break (head, len);
+            }
+        };
+        while request.len() < body_len {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        let signature = head
+            .lines()
+            .find_map(|l| l.strip_prefix(&format!("{SIGNATURE_HEADER}:")))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let _ = socket
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+            .await;
+        let _ = socket.shutdown().await;
+        tx.send((signature, request)).await.ok();
+    }
+
+    // Receiver that answers 200 and hands back each request's signature header and body. Also
+    // used by the worker tests.
+    pub(crate) async fn capturing_receiver()
+    -> (String, tokio::sync::mpsc::Receiver<(String, Vec<u8>)>) {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                answer_and_capture(socket, &tx).await;
+            }
+        });
+        (format!("http://{addr}/webhook"), rx)
+    }
+
+    // A private CA, a server certificate for localhost and a client certificate, all PEM
+    pub(crate) struct TestPki {
+        pub ca: String,
+        pub server_certificate: String,
+        pub server_key: String,
+        pub client_certificate: String,
+        pub client_key: String,
+    }
+
+    pub(crate) fn test_pki() -> TestPki {
+        use rcgen::{
+            BasicConstraints, CertificateParams, CertifiedIssuer, DnType, ExtendedKeyUsagePurpose,
+            IsCa, KeyPair,
+        };
+
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "test receiver CA");
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca)
+            .unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let mut client_params = CertificateParams::new(Vec::new()).unwrap();
+        client_params
+            .distinguished_name
+            .push(DnType::CommonName, "mini-stripe webhooks");
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client = client_params.signed_by(&client_key, &ca).unwrap();
+
+        TestPki {
+            ca: ca.pem(),
+            server_certificate: server.pem(),
+            server_key: server_key.serialize_pem(),
+            client_certificate: client.pem(),
+            client_key: client_key.serialize_pem(),
+        }
+    }
+
+    // Like capturing_receiver, but over TLS and only for clients presenting a certificate issued
+    // by the test CA. Handshakes that fail are dropped.
+    pub(crate) async fn mtls_receiver(
+        pki: &TestPki,
+    ) -> (String, tokio::sync::mpsc::Receiver<(String, Vec<u8>)>) {
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+        use rustls::server::WebPkiClientVerifier;
+        use rustls::{RootCertStore, ServerConfig};
+        use std::sync::Arc;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_slice(pki.ca.as_bytes()).unwrap())
+            .unwrap();
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .unwrap();
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![CertificateDer::from_pem_slice(pki.server_certificate.as_bytes()).unwrap()],
+                PrivateKeyDer::from_pem_slice(pki.server_key.as_bytes()).unwrap(),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                if let Ok(tls) = acceptor.accept(socket).await {
+                    answer_and_capture(tls, &tx).await;
+                }
+            }
+        });
+        (format!("https://localhost:{port}/webhook"), rx)
+    }
+
+    #[tokio::test]
+    async fn client_certificates_are_presented_and_their_clients_reused() {
+        let pki = test_pki();
+        let (url, mut received) = mtls_receiver(&pki).await;
+        let clients =
+            Clients::new(vec![Certificate::from_pem(pki.ca.as_bytes()).unwrap()]).unwrap();
+        let body = serde_json::json!({ "id": "evt" });
+        let bundle = format!("{}{}", pki.client_certificate, pki.client_key);
+
+        let err = post_webhook(
+            clients.default_client(),
+            &url,
+            "secret",
+            SignatureScheme::V1,
+            &body,
+            1024,
+        )
+        .await
+        .err()
+        .expect("the receiver requires a client certificate");
+        assert!(err.starts_with("http error:"), "{err}");
+
+        for _ in 0..2 {
+            let client = clients
+                .with_certificate("fp", || Ok(bundle.clone()))
+                .unwrap();
+            let res = post_webhook(&client, &url, "secret", SignatureScheme::V1, &body, 1024)
+                .await
+                .unwrap();
+            assert_eq!(res.status, 200);
+            received.recv().await.unwrap();
+        }
+        // The second delivery used the cached client without asking for the bundle
+        clients
+            .with_certificate("fp", || panic!("decrypted again"))
+            .unwrap();
+        assert_eq!(clients.cached_certificate_clients(), 1);
+
+        let err = clients
+            .with_certificate("other", || Ok("not pem".to_string()))
+            .err()
+            .unwrap();
+        assert!(err.starts_with("client certificate:"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn the_signature_covers_the_templated_body() {
+        let (url, mut received) = capturing_receiver().await;
+        let template = serde_json::json!({ "fields": {
+            "txn": { "path": "$.data.payment_intent.id" }
+        }});
+        let event = serde_json::json!({
+            "id": "evt",
+            "type": "payment_intent.succeeded",
+            "data": { "payment_intent": { "id": "pi", "amount": 100 } }
+        });
+        let body = render_body(event, Some(&template), 1024).unwrap();
+
+        for scheme in [SignatureScheme::V1, SignatureScheme::V2] {
+            post_webhook(&Client::new(), &url, "secret", scheme, &body, 1024)
+                .await
+                .unwrap();
+            let (header, bytes) = received.recv().await.unwrap();
+            assert_eq!(
+                serde_json::from_slice::<Value>(&bytes).unwrap(),
+                serde_json::json!({ "txn": "pi" })
+            );
+            assert!(signature::verify_signature(
+                scheme, "secret", &header, &bytes
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn response_snippets_are_bounded_and_sanitized() {
+        let client = Client::new();
+        let event = serde_json::json!({ "id": "evt" });
+
+        let big = vec![b'x'; 10 * 1024 * 1024];
+        let url = stub_receiver(big).await;
+        let res = post_webhook(&client, &url, "secret", SignatureScheme::V1, &event, 1024)
+            .await
+            .unwrap();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.snippet.len(), 1024);
+        assert!(res.truncated);
+        assert_eq!(res.content_length, Some(10 * 1024 * 1024));
+
+        let garbage: Vec<u8> = (0..=255u8).cycle().take(600).collect();
+        let url = stub_receiver(garbage).await;
+        let res = post_webhook(&client, &url, "secret", SignatureScheme::V1, &event, 1024)
+            .await
+            .unwrap();
+        assert!(!res.truncated);
+        assert!(
+            res.snippet
+                .chars()
+                .all(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        );
+        assert!(
+            serde_json::from_str::<Value>(&serde_json::to_string(&res.snippet).unwrap()).is_ok()
+        );
+
+        let url = stub_receiver("received ✅ 🎉".as_bytes().to_vec()).await;
+        let res = post_webhook(&client, &url, "secret", SignatureScheme::V1, &event, 1024)
+            .await
+            .unwrap();
+        assert_eq!(res.snippet, "received ✅ 🎉");
+        assert!(!res.truncated);
+    }
 
-    Ok(res.status().as_u16())
+    #[test]
+    fn echo_endpoints_target_the_api() {
+        let id = Uuid::nil();
+        assert_eq!(
+            target_url("internal://echo", id, "http://localhost:3000/"),
+            format!("http://localhost:3000/v1/sandbox/echo/{id}")
+        );
+        assert_eq!(
+            target_url("https://example.com/hook", id, "http://localhost:3000"),
+            "https://example.com/hook"
+        );
+    }
 }
@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use mini_stripe_types::events::WebhookEvent;
+use object_store::aws::AmazonS3Builder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use sqlx::{PgConnection, PgPool};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+// Copies every outbox event, superseded ones included, into NDJSON files: one directory per UTC
+// day of created_at (`dt=2026-10-16/`), one file per day per batch. It follows its own cursor
+// (event_export_watermarks) and never touches the delivery columns, so it runs beside delivery
+// without slowing it down.
+pub struct Settings {
+    // file:///some/dir, or s3://bucket/prefix for S3 and S3-compatible stores (AWS_ENDPOINT etc.)
+    pub url: String,
+    pub store: Arc<dyn ObjectStore>,
+    // Inside the store
+    pub prefix: Path,
+    // Events per batch; each batch is one transaction
+    pub batch_size: i64,
+    // How long to wait once caught up
+    pub interval: Duration,
+}
+
+impl Settings {
+    // None unless EVENT_EXPORT_URL is set
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("EVENT_EXPORT_URL").ok()?;
+        let (store, prefix) = open_store(&url).expect("EVENT_EXPORT_URL is not usable");
+        Some(Self {
+            url,
+            store,
+            prefix,
+            batch_size: std::env::var("EVENT_EXPORT_BATCH_SIZE")
+                .ok()
+                .map(|v| v.parse().expect("EVENT_EXPORT_BATCH_SIZE must be a number"))
+                .unwrap_or(1000),
+            interval: std::env::var("EVENT_EXPORT_INTERVAL_SECS")
+                .ok()
+                .map(|v| {
+                    v.parse()
+                        .expect("EVENT_EXPORT_INTERVAL_SECS must be a number")
+                })
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(10)),
+        })
+    }
+}
+
+pub fn open_store(url: &str) -> Result<(Arc<dyn ObjectStore>, Path), String> {
+    if let Some(dir) = url.strip_prefix("file://") {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{dir}: {e}"))?;
+        let store = LocalFileSystem::new_with_prefix(dir).map_err(|e| e.to_string())?;
+        return Ok((Arc::new(store), Path::default()));
+    }
+    if let Some(location) = url.strip_prefix("s3://") {
+        let prefix = location.split_once('/').map_or("", |(_, prefix)| prefix);
+        let store = AmazonS3Builder::from_env()
+            .with_url(url)
+            .build()
+            .map_err(|e| e.to_string())?;
+        return Ok((Arc::new(store), Path::from(prefix)));
+    }
+    Err(format!("expected file:// or s3://, got {url}"))
+}
+
+pub async fn run(db: PgPool, settings: Settings) {
+    info!("exporting events to {}", settings.url);
+    loop {
+        loop {
+            match export_batch(&db, &settings).await {
+                Ok(exported) if exported as i64 == settings.batch_size => {}
+                Ok(_) => break,
+                Err(e) => {
+                    warn!("event export failed: {e}");
+                    break;
+                }
+            }
+        }
+        tokio::time::sleep(settings.interval).await;
+    }
+}
+
+struct ExportedEvent {
+    id: Uuid,
+    event_type: String,
+    payload: serde_json::Value,
+    created_at: DateTime<Utc>,
+    txid: i64,
+    sequence: i64,
+}
+
+// Exports the next batch and moves the watermarks past it; Ok(0) when there was nothing to do
+// or another exporter holds the lock.
+//
+// Files are written before the watermarks commit, so a crash in between leaves files behind
+// for events that will be exported again. Each file is named after its first event, and the
+// next export of that day necessarily starts at the same event, so it overwrites them.
+pub async fn export_batch(db: &PgPool, settings: &Settings) -> Result<usize, String> {
+    let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_xact_lock(hashtext('event_export')) AS "locked!""#
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    if !locked {
+        return Ok(0);
+    }
+
+    let events = next_batch(&mut tx, settings.batch_size)
+        .await
+        .map_err(|e| e.to_string())?;
+    let partitions = write_files(settings, &events).await?;
+    advance_watermarks(&mut tx, &partitions)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(events.len())
+}
+
+// The events after the cursor, in (txid, sequence) order. Only transactions older than the
+// snapshot's xmin are read: a younger one may still commit an event behind the cursor.
+async fn next_batch(
+    conn: &mut PgConnection,
+    batch_size: i64,
+) -> Result<Vec<ExportedEvent>, sqlx::Error> {
+    let after = sqlx::query!(
+        r#"
+        SELECT last_txid, last_sequence
+        FROM event_export_watermarks
+        ORDER BY last_txid DESC, last_sequence DESC
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let (after_txid, after_sequence) = after.map_or((0, 0), |w| (w.last_txid, w.last_sequence));
+
+    sqlx::query_as!(
+        ExportedEvent,
+        r#"
+        SELECT id, event_type, payload, created_at, txid, sequence
+        FROM events_outbox
+        WHERE (txid, sequence) > ($1, $2)
+          AND txid < pg_snapshot_xmin(pg_current_snapshot())::text::bigint
+        ORDER BY txid, sequence
+        LIMIT $3
+        "#,
+        after_txid,
+        after_sequence,
+        batch_size
+    )
+    .fetch_all(conn)
+    .await
+}
+
+// The files a batch writes: per day, the events in cursor order
+fn group_by_day(events: &[ExportedEvent]) -> BTreeMap<NaiveDate, Vec<&ExportedEvent>> {
+    let mut days: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for event in events {
+        days.entry(event.created_at.date_naive())
+            .or_default()
+            .push(event);
+    }
+    days
+}
+
+pub fn file_path(prefix: &Path, day: NaiveDate, txid: i64, sequence: i64) -> Path {
+    prefix
+        .child(format!("dt={day}"))
+        .child(format!("{txid:020}-{sequence:020}.ndjson"))
+}
+
+async fn write_files<'a>(
+    settings: &Settings,
+    events: &'a [ExportedEvent],
+) -> Result<Vec<(NaiveDate, Vec<&'a ExportedEvent>)>, String> {
+    let days = group_by_day(events);
+    for (day, events) in &days {
+        let mut body = Vec::new();
+        for event in events {
+            let line = WebhookEvent {
+                id: event.id,
+                event_type: event.event_type.clone(),
+                created_at: Some(event.created_at),
+                data: event.payload.clone(),
+                payload_truncated: false,
+            };
+            serde_json::to_writer(&mut body, &line).map_err(|e| e.to_string())?;
+            body.push(b'\n');
+        }
+        let path = file_path(&settings.prefix, *day, events[0].txid, events[0].sequence);
+        settings
+            .store
+            .put(&path, PutPayload::from(body))
+            .await
+            .map_err(|e| format!("{path}: {e}"))?;
+    }
+    Ok(days.into_iter().collect())
+}
+
+async fn advance_watermarks(
+    conn: &mut PgConnection,
+    partitions: &[(NaiveDate, Vec<&ExportedEvent>)],
+) -> Result<(), sqlx::Error> {
+    for (day, events) in partitions {
+        let last = events.last().expect("a partition has events");
+        sqlx::query!(
+            r#"
+            INSERT INTO event_export_watermarks
+              (partition_date, last_event_id, last_txid, last_sequence, exported_count)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (partition_date) DO UPDATE
+            SET last_event_id = EXCLUDED.last_event_id,
+                last_txid = EXCLUDED.last_txid,
+                last_sequence = EXCLUDED.last_sequence,
+                exported_count = event_export_watermarks.exported_count + EXCLUDED.exported_count,
+                updated_at = now()
+            "#,
+            day,
+            last.id,
+            last.txid,
+            last.sequence,
+            events.len() as i64
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn local_settings(dir: &std::path::Path, batch_size: i64) -> Settings {
+        let url = format!("file://{}", dir.display());
+        let (store, prefix) = open_store(&url).unwrap();
+        Settings {
+            url,
+            store,
+            prefix,
+            batch_size,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("event-export-{}", Uuid::new_v4()))
+    }
+
+    async fn insert_event(
+        db: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+        created_at: DateTime<Utc>,
+    ) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO events_outbox (id, event_type, payload, created_at)
+            VALUES ($1, 'payment_intent.created', '{}'::jsonb, $2)
+            "#,
+            id,
+            created_at
+        )
+        .execute(db)
+        .await
+        .unwrap();
+        id
+    }
+
+    // Every exported event id per day, files read in name order
+    fn exported(dir: &std::path::Path) -> BTreeMap<String, Vec<Uuid>> {
+        let mut days = BTreeMap::new();
+        for day in std::fs::read_dir(dir).unwrap() {
+            let day = day.unwrap().path();
+            let mut files: Vec<_> = std::fs::read_dir(&day)
+                .unwrap()
+                .map(|f| f.unwrap().path())
+                .collect();
+            files.sort();
+            let ids: &mut Vec<Uuid> = days
+                .entry(day.file_name().unwrap().to_string_lossy().into_owned())
+                .or_default();
+            for file in files {
+                for line in std::fs::read_to_string(file).unwrap().lines() {
+                    let event: WebhookEvent = serde_json::from_str(line).unwrap();
+                    ids.push(event.id);
+                }
+            }
+        }
+        days
+    }
+
+    async fn export_all(db: &PgPool, settings: &Settings) {
+        while export_batch(db, settings).await.unwrap() > 0 {}
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn a_restarted_export_writes_every_event_exactly_once(pool: PgPool) {
+        // 25 events over three days, interleaved so most batches span several days
+        let start = "2026-10-14T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let mut expected: BTreeMap<String, Vec<Uuid>> = BTreeMap::new();
+        for n in 0..25 {
+            let created_at = start + chrono::Duration::days(n % 3);
+            let id = insert_event(&pool, created_at).await;
+            expected
+                .entry(format!("dt={}", created_at.date_naive()))
+                .or_default()
+                .push(id);
+        }
+        let dir = temp_dir();
+
+        // Stopped after two batches
+        let settings = local_settings(&dir, 4);
+        assert_eq!(export_batch(&pool, &settings).await.unwrap(), 4);
+        assert_eq!(export_batch(&pool, &settings).await.unwrap(), 4);
+
+        // Then killed between writing a batch's files and moving the watermarks
+        let mut tx = pool.begin().await.unwrap();
+        let events = next_batch(&mut tx, 10).await.unwrap();
+        write_files(&settings, &events).await.unwrap();
+        drop(tx);
+
+        // Restarted with smaller batches, so the retried files end at different events
+        export_all(&pool, &local_settings(&dir, 7)).await;
+
+        assert_eq!(exported(&dir), expected);
+        let counts: HashMap<NaiveDate, i64> =
+            sqlx::query!("SELECT partition_date, exported_count FROM event_export_watermarks")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|w| (w.partition_date, w.exported_count))
+                .collect();
+        assert_eq!(counts.values().sum::<i64>(), 25);
+        assert_eq!(counts.len(), 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Ids are taken in insert order but can commit in any order. The exporter must wait for the
+    // older transaction rather than move its cursor past it.
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn an_event_committing_late_is_not_skipped(pool: PgPool) {
+        let now = Utc::now();
+        let mut slow = pool.begin().await.unwrap();
+        let late = insert_event(&mut *slow, now).await;
+        let early = insert_event(&pool, now).await;
+
+        let dir = temp_dir();
+        let settings = local_settings(&dir, 100);
+        assert_eq!(export_batch(&pool, &settings).await.unwrap(), 0);
+
+        slow.commit().await.unwrap();
+        export_all(&pool, &settings).await;
+        let exported: Vec<Uuid> = exported(&dir).into_values().flatten().collect();
+        assert_eq!(exported, [late, early]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
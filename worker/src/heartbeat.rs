@@ -0,0 +1,199 @@
+use std::time::{Duration, Instant};
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// What this process has done since it started, written out with each heartbeat
+#[derive(Debug, Default)]
+pub struct LoopStats {
+    pub deliveries_claimed: i64,
+    pub deliveries_succeeded: i64,
+    pub deliveries_failed: i64,
+    pub claim_contention: i64,
+    pub last_loop: Duration,
+    pub max_loop: Duration,
+}
+
+impl LoopStats {
+    pub fn record_loop(&mut self, elapsed: Duration) {
+        self.last_loop = elapsed;
+        self.max_loop = self.max_loop.max(elapsed);
+    }
+}
+
+// This process's row in worker_heartbeats. It is written from the delivery loop itself, so a
+// loop that hangs goes stale just like a process that died.
+pub struct Heartbeat {
+    pub instance_id: Uuid,
+    interval: Duration,
+    // Rows (any worker's) this long without a heartbeat are deleted
+    ttl: Duration,
+    last_beat: Instant,
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+impl Heartbeat {
+    pub async fn register(
+        db: &PgPool,
+        interval: Duration,
+        ttl: Duration,
+    ) -> Result<Self, sqlx::Error> {
+        let instance_id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO worker_heartbeats (instance_id, hostname, pid)
+            VALUES ($1, $2, $3)
+            "#,
+            instance_id,
+            hostname(),
+            std::process::id() as i32
+        )
+        .execute(db)
+        .await?;
+
+        Ok(Self {
+            instance_id,
+            interval,
+            ttl,
+            last_beat: Instant::now(),
+        })
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Writes the heartbeat once the interval has passed; Ok(true) when it did
+    pub async fn beat_if_due(
+        &mut self,
+        db: &PgPool,
+        stats: &LoopStats,
+    ) -> Result<bool, sqlx::Error> {
+        if self.last_beat.elapsed() < self.interval {
+            return Ok(false);
+        }
+        self.beat(db, stats).await?;
+        Ok(true)
+    }
+
+    pub async fn beat(&mut self, db: &PgPool, stats: &LoopStats) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE worker_heartbeats
+            SET last_heartbeat_at = now(),
+                deliveries_claimed = $2,
+                deliveries_succeeded = $3,
+                deliveries_failed = $4,
+                claim_contention = $5,
+                last_loop_ms = $6,
+                max_loop_ms = $7
+            WHERE instance_id = $1
+            "#,
+            self.instance_id,
+            stats.deliveries_claimed,
+            stats.deliveries_succeeded,
+            stats.deliveries_failed,
+            stats.claim_contention,
+            stats.last_loop.as_millis() as i64,
+            stats.max_loop.as_millis() as i64
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM worker_heartbeats
+            WHERE last_heartbeat_at < now() - make_interval(secs => $1)
+            "#,
+            self.ttl.as_secs_f64()
+        )
+        .execute(db)
+        .await?;
+
+        self.last_beat = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn row(db: &PgPool, id: Uuid) -> Option<(i64, i64, f64)> {
+        sqlx::query!(
+            r#"
+            SELECT deliveries_claimed, claim_contention,
+                   EXTRACT(EPOCH FROM last_heartbeat_at)::float8 AS "beat_at!"
+            FROM worker_heartbeats
+            WHERE instance_id = $1
+            "#,
+            id
+        )
+        .fetch_optional(db)
+        .await
+        .unwrap()
+        .map(|r| (r.deliveries_claimed, r.claim_contention, r.beat_at))
+    }
+
+    // Mock clock: move heartbeats back instead of waiting
+    async fn advance_clock(db: &PgPool, secs: f64) {
+        sqlx::query!(
+            r#"
+            UPDATE worker_heartbeats
+            SET started_at = started_at - make_interval(secs => $1),
+                last_heartbeat_at = last_heartbeat_at - make_interval(secs => $1)
+            "#,
+            secs
+        )
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn beats_write_counters_and_clear_out_dead_workers(pool: PgPool) {
+        let ttl = Duration::from_secs(3600);
+        let dead = Heartbeat::register(&pool, Duration::ZERO, ttl)
+            .await
+            .unwrap();
+        let mut live = Heartbeat::register(&pool, Duration::ZERO, ttl)
+            .await
+            .unwrap();
+        advance_clock(&pool, 7200.0).await;
+
+        let before = row(&pool, live.instance_id).await.unwrap().2;
+        let stats = LoopStats {
+            deliveries_claimed: 4,
+            claim_contention: 1,
+            ..Default::default()
+        };
+        assert!(live.beat_if_due(&pool, &stats).await.unwrap());
+
+        let (claimed, contention, beat_at) = row(&pool, live.instance_id).await.unwrap();
+        assert_eq!((claimed, contention), (4, 1));
+        assert!(beat_at > before + 7000.0);
+        assert!(row(&pool, dead.instance_id).await.is_none());
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn beats_wait_for_the_interval(pool: PgPool) {
+        let mut heartbeat =
+            Heartbeat::register(&pool, Duration::from_secs(60), Duration::from_secs(3600))
+                .await
+                .unwrap();
+        assert!(
+            !heartbeat
+                .beat_if_due(&pool, &LoopStats::default())
+                .await
+                .unwrap()
+        );
+    }
+}
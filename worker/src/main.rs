@@ -1,6 +1,9 @@
 mod db;
 mod deliver;
-mod signature;
+#[cfg(feature = "event-export")]
+mod export;
+mod heartbeat;
+mod wakeup;
 mod worker;
 
 use sqlx::PgPool;
@@ -19,5 +22,11 @@ async fn main() {
         .await
         .expect("failed to connect to Postgres");
 
+    // Its own task and cursor: a slow or failing export never holds up delivery
+    #[cfg(feature = "event-export")]
+    if let Some(settings) = export::Settings::from_env() {
+        tokio::spawn(export::run(db.clone(), settings));
+    }
+
     worker::run(db).await;
 }
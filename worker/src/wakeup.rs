@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use tracing::{info, warn};
+
+// Postgres channel notified by the events_outbox insert trigger
+const CHANNEL: &str = "outbox_new_event";
+
+// Waits for the next outbox insert, or the poll interval as a safety net for missed
+// notifications (listener down, other instances, deliveries whose backoff just expired)
+pub struct OutboxWakeup {
+    db: PgPool,
+    listener: Option<PgListener>,
+}
+
+impl OutboxWakeup {
+    pub async fn connect(db: PgPool) -> Self {
+        let listener = listen(&db).await;
+        Self { db, listener }
+    }
+
+    pub async fn wait(&mut self, poll_interval: Duration) {
+        // Lost listener: try again each round, polling meanwhile
+        if self.listener.is_none() {
+            self.listener = listen(&self.db).await;
+            if self.listener.is_some() {
+                info!("outbox listener reconnected");
+            }
+        }
+
+        let Some(listener) = self.listener.as_mut() else {
+            tokio::time::sleep(poll_interval).await;
+            return;
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(poll_interval) => {}
+            notification = listener.recv() => {
+                if let Err(e) = notification {
+                    warn!("outbox listener failed, falling back to polling: {e}");
+                    self.listener = None;
+                }
+            }
+        }
+    }
+}
+
+async fn listen(db: &PgPool) -> Option<PgListener> {
+    let result = async {
+        let mut listener = PgListener::connect_with(db).await?;
+        listener.listen(CHANNEL).await?;
+        Ok::<_, sqlx::Error>(listener)
+    }
+    .await;
+
+    result
+        .inspect_err(|e| warn!("could not LISTEN on {CHANNEL}: {e}"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    async fn insert_event(db: impl sqlx::Executor<'_, Database = sqlx::Postgres>) {
+        sqlx::query!(
+            r#"
+            INSERT INTO events_outbox (id, event_type, payload)
+            VALUES ($1, 'payment_intent.created', '{}'::jsonb)
+            "#,
+            uuid::Uuid::new_v4()
+        )
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    // Insert shortly after the waiter goes idle, so the wake-up has to come from NOTIFY
+    async fn wait_for_insert(wakeup: &mut OutboxWakeup, db: &PgPool) -> Duration {
+        let db = db.clone();
+        let insert = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            insert_event(&db).await;
+        });
+
+        let started = Instant::now();
+        wakeup.wait(Duration::from_secs(30)).await;
+        insert.await.unwrap();
+        started.elapsed()
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn insert_wakes_the_worker_before_the_poll_interval(db: PgPool) {
+        let mut wakeup = OutboxWakeup::connect(db.clone()).await;
+        assert!(wakeup.listener.is_some());
+
+        assert!(wait_for_insert(&mut wakeup, &db).await < Duration::from_secs(5));
+
+        // A rolled-back insert never wakes anyone
+        let mut tx = db.begin().await.unwrap();
+        insert_event(&mut *tx).await;
+        tx.rollback().await.unwrap();
+        let started = Instant::now();
+        wakeup.wait(Duration::from_millis(300)).await;
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn killed_listener_recovers(db: PgPool) {
+        let mut wakeup = OutboxWakeup::connect(db.clone()).await;
+
+        let killed: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(pg_terminate_backend(pid))
+            FROM pg_stat_activity
+            WHERE datname = current_database() AND query ILIKE 'LISTEN%'
+            "#,
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(killed, 1);
+
+        // Either sqlx reconnects inside recv or we drop the listener and poll, then reconnect:
+        // within a few short rounds an insert must wake us via NOTIFY again
+        for _ in 0..3 {
+            wakeup.wait(Duration::from_millis(200)).await;
+        }
+        assert!(wait_for_insert(&mut wakeup, &db).await < Duration::from_secs(5));
+    }
+}
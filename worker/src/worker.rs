@@ -1,62 +1,359 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use reqwest::Client;
+use chrono::{NaiveDate, Utc};
+use mini_stripe_types::crypto::{
+    KeyRing, WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE, WEBHOOK_ENDPOINT_SECRET,
+};
+use mini_stripe_types::delivery::MAX_DELIVERY_ATTEMPTS;
+use mini_stripe_types::events::WebhookEvent;
+use reqwest::Certificate;
 use sqlx::PgPool;
 use tracing::{info, warn};
 
+use crate::heartbeat::{Heartbeat, LoopStats};
+use crate::wakeup::OutboxWakeup;
 use crate::{db, deliver};
 
-const MAX_ATTEMPTS: i32 = 10;
+// Safety-net poll; normally the worker is woken by NOTIFY as soon as an event commits
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// How often endpoints' client certificates are checked for upcoming expiry
+const CERTIFICATE_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+// Attempts each new delivery gets unless WEBHOOK_MAX_DELIVERY_ATTEMPTS says otherwise
+const MAX_ATTEMPTS: i32 = MAX_DELIVERY_ATTEMPTS;
+
+struct Settings {
+    // Where internal://echo deliveries are sent
+    api_base_url: String,
+    // Events bigger than this are delivered thin (see deliver::delivery_body)
+    max_delivery_payload_bytes: usize,
+    // Attempts each new delivery gets before it is marked failed for good
+    max_delivery_attempts: i32,
+    // Consecutive connection failures before an endpoint's circuit opens
+    circuit_failure_threshold: i32,
+    // How long an open circuit stays open before a single probe is sent
+    circuit_cooldown: Duration,
+    // Event types where only the latest undelivered event per resource is delivered
+    compactable_event_types: Vec<String>,
+    // How much of each receiver response body is kept for the delivery history
+    max_response_snippet_bytes: usize,
+    // How often this process updates its worker_heartbeats row
+    heartbeat_interval: Duration,
+    // Heartbeat rows this old are deleted
+    heartbeat_ttl: Duration,
+    // Decrypts endpoint secrets; must hold every key the API has encrypted with
+    encryption_keys: KeyRing,
+    // Honour the sandbox webhook_timeout faults set through the API (off by default)
+    sandbox_mode: bool,
+    // Extra CAs trusted for receivers' server certificates, e.g. a private CA used with mutual TLS
+    root_certificates: Vec<Certificate>,
+}
+
+impl Settings {
+    fn from_env() -> Self {
+        Self {
+            api_base_url: std::env::var("API_BASE_URL")
+                .unwrap_or_else(|_| "http://localhost:3000".to_string()),
+            max_delivery_payload_bytes: std::env::var("MAX_DELIVERY_PAYLOAD_BYTES")
+                .ok()
+                .map(|v| {
+                    v.parse()
+                        .expect("MAX_DELIVERY_PAYLOAD_BYTES must be a number")
+                })
+                .unwrap_or(64 * 1024),
+            max_delivery_attempts: std::env::var("WEBHOOK_MAX_DELIVERY_ATTEMPTS")
+                .ok()
+                .map(|v| {
+                    v.trim()
+                        .parse()
+                        .ok()
+                        .filter(|max| *max >= 1)
+                        .expect("WEBHOOK_MAX_DELIVERY_ATTEMPTS must be a number of at least 1")
+                })
+                .unwrap_or(MAX_ATTEMPTS),
+            circuit_failure_threshold: std::env::var("CIRCUIT_FAILURE_THRESHOLD")
+                .ok()
+                .map(|v| {
+                    v.parse()
+                        .expect("CIRCUIT_FAILURE_THRESHOLD must be a number")
+                })
+                .unwrap_or(5),
+            circuit_cooldown: std::env::var("CIRCUIT_COOLDOWN_SECS")
+                .ok()
+                .map(|v| v.parse().expect("CIRCUIT_COOLDOWN_SECS must be a number"))
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(60)),
+            compactable_event_types: std::env::var("COMPACTABLE_EVENT_TYPES")
+                .unwrap_or_else(|_| "payment_intent.updated".to_string())
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .collect(),
+            max_response_snippet_bytes: std::env::var("MAX_RESPONSE_SNIPPET_BYTES")
+                .ok()
+                .map(|v| {
+                    v.parse()
+                        .expect("MAX_RESPONSE_SNIPPET_BYTES must be a number")
+                })
+                .unwrap_or(1024),
+            heartbeat_interval: std::env::var("WORKER_HEARTBEAT_SECS")
+                .ok()
+                .map(|v| v.parse().expect("WORKER_HEARTBEAT_SECS must be a number"))
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(10)),
+            heartbeat_ttl: std::env::var("WORKER_HEARTBEAT_TTL_SECS")
+                .ok()
+                .map(|v| {
+                    v.parse()
+                        .expect("WORKER_HEARTBEAT_TTL_SECS must be a number")
+                })
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(24 * 3600)),
+            encryption_keys: KeyRing::from_env().expect(
+                "ENCRYPTION_KEYS must be `id:<64 hex chars>,...` and ENCRYPTION_KEY_ID one of the ids",
+            ),
+            sandbox_mode: std::env::var("SANDBOX_MODE")
+                .is_ok_and(|v| matches!(v.trim(), "1" | "true")),
+            root_certificates: std::env::var("WEBHOOK_CA_CERTS")
+                .ok()
+                .map(|path| {
+                    let pem = std::fs::read(&path)
+                        .unwrap_or_else(|e| panic!("WEBHOOK_CA_CERTS {path}: {e}"));
+                    Certificate::from_pem_bundle(&pem)
+                        .expect("WEBHOOK_CA_CERTS must be a file of PEM certificates")
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
 
 pub async fn run(db_pool: PgPool) {
-    info!("worker started");
+    run_with(db_pool, Settings::from_env()).await
+}
+
+async fn run_with(db_pool: PgPool, settings: Settings) {
+    let clients = deliver::Clients::new(settings.root_certificates.clone())
+        .expect("failed to build the webhook HTTP client");
+    let mut heartbeat = Heartbeat::register(
+        &db_pool,
+        settings.heartbeat_interval,
+        settings.heartbeat_ttl,
+    )
+    .await
+    .expect("failed to register worker heartbeat");
+    let mut stats = LoopStats::default();
+    info!("worker {} started", heartbeat.instance_id);
 
-    let client = Client::new();
-    let mut interval = tokio::time::interval(Duration::from_secs(2));
+    let mut wakeup = OutboxWakeup::connect(db_pool.clone()).await;
+    // The UTC day the digest was last settled for by this process (the DB marker is the real guard)
+    let mut digest_checked_on: Option<NaiveDate> = None;
+    let mut certificates_checked_at: Option<Instant> = None;
 
     loop {
-        interval.tick().await;
+        let today = Utc::now().date_naive();
+        if digest_checked_on != Some(today) {
+            match db::emit_daily_digest(&db_pool, Utc::now()).await {
+                Ok(emitted) => {
+                    if let Some(event_id) = emitted {
+                        info!("emitted daily webhook digest event {event_id}");
+                    }
+                    digest_checked_on = Some(today);
+                }
+                Err(e) => warn!("daily webhook digest failed: {e}"),
+            }
+        }
+
+        if certificates_checked_at.is_none_or(|at| at.elapsed() >= CERTIFICATE_CHECK_INTERVAL) {
+            match db::emit_certificate_expiry_warnings(&db_pool, Utc::now()).await {
+                Ok(events) => {
+                    for event_id in events {
+                        info!("emitted client certificate expiry event {event_id}");
+                    }
+                    certificates_checked_at = Some(Instant::now());
+                }
+                Err(e) => warn!("client certificate expiry check failed: {e}"),
+            }
+        }
 
-        if let Err(e) = poll_once(&db_pool, &client).await {
-            warn!("poll_once failed: {e}");
+        // Work through everything that is due, then sleep until the next event or poll
+        let started = Instant::now();
+        loop {
+            match poll_once(&db_pool, &clients, &settings, &mut stats).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    warn!("poll_once failed: {e}");
+                    break;
+                }
+            }
+            // A long backlog shouldn't look like a dead worker
+            if let Err(e) = heartbeat.beat_if_due(&db_pool, &stats).await {
+                warn!("worker heartbeat failed: {e}");
+            }
+        }
+        stats.record_loop(started.elapsed());
+        if let Err(e) = heartbeat.beat_if_due(&db_pool, &stats).await {
+            warn!("worker heartbeat failed: {e}");
         }
+
+        wakeup.wait(POLL_INTERVAL.min(heartbeat.interval())).await;
     }
 }
 
-async fn poll_once(db_pool: &PgPool, client: &Client) -> Result<(), String> {
+// Delivers at most one due webhook; Ok(false) when there was nothing to do
+async fn poll_once(
+    db_pool: &PgPool,
+    clients: &deliver::Clients,
+    settings: &Settings,
+    stats: &mut LoopStats,
+) -> Result<bool, String> {
     // Enqueue + claim inside one transaction
     let mut tx = db_pool.begin().await.map_err(|e| e.to_string())?;
 
-    db::enqueue_missing_deliveries(&mut tx)
+    db::compact_events(&mut tx, &settings.compactable_event_types)
         .await
         .map_err(|e| e.to_string())?;
 
-    let claimed = db::claim_one_due_delivery(&mut tx)
+    db::enqueue_missing_deliveries(&mut tx, settings.max_delivery_attempts)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let claimed = db::claim_one_due_delivery(&mut tx, settings.circuit_cooldown.as_secs_f64())
         .await
         .map_err(|e| e.to_string())?;
 
     tx.commit().await.map_err(|e| e.to_string())?;
 
     let Some(job) = claimed else {
-        return Ok(()); // nothing to do this tick
+        // Something was due but every row was locked: another worker got there first
+        if db::has_due_deliveries(db_pool)
+            .await
+            .map_err(|e| e.to_string())?
+        {
+            stats.claim_contention += 1;
+        }
+        return Ok(false); // nothing to do this tick
     };
+    stats.deliveries_claimed += 1;
+
+    let retry = job.retry_policy();
 
     // Build event payload to send (Stripe-ish)
-    let event = serde_json::json!({
-        "id": job.event_id,
-        "type": job.event_type,
-        "created_at": job.event_created_at,
-        "data": job.event_payload
+    let event = serde_json::json!(WebhookEvent {
+        id: job.event_id,
+        event_type: job.event_type,
+        created_at: Some(job.event_created_at),
+        data: job.event_payload,
+        payload_truncated: false,
     });
+    // The outbox row keeps the original envelope; only the posted body is reshaped. Neither a
+    // broken template nor a secret or client certificate this worker can't decrypt gets anything
+    // sent.
+    let prepared = deliver::render_body(
+        event,
+        job.endpoint_payload_template.as_ref(),
+        settings.max_delivery_payload_bytes,
+    )
+    .and_then(|body| {
+        let secret = WEBHOOK_ENDPOINT_SECRET
+            .decrypt(&settings.encryption_keys, &job.endpoint_secret)
+            .map_err(|e| format!("endpoint secret: {e}; is its key in ENCRYPTION_KEYS?"))?;
+        let client = match (
+            &job.endpoint_client_certificate_fingerprint,
+            &job.endpoint_client_certificate,
+        ) {
+            (Some(fingerprint), Some(stored)) => clients.with_certificate(fingerprint, || {
+                WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE
+                    .decrypt(&settings.encryption_keys, stored)
+                    .map_err(|e| format!("client certificate: {e}; is its key in ENCRYPTION_KEYS?"))
+            })?,
+            _ => clients.default_client().clone(),
+        };
+        Ok((body, secret, client))
+    });
+    let (event, secret, client) = match prepared {
+        Ok(prepared) => prepared,
+        Err(err) => {
+            db::mark_delivery_failed(db_pool, retry, job.attempt_count, err.clone())
+                .await
+                .map_err(|e| e.to_string())?;
+            stats.deliveries_failed += 1;
+            db::maybe_mark_event_delivered(db_pool, job.event_id)
+                .await
+                .map_err(|e| e.to_string())?;
+            warn!(
+                "delivery failed event {} to endpoint {} ({})",
+                job.event_id, job.endpoint_id, err
+            );
+            return Ok(true);
+        }
+    };
 
-    let status =
-        deliver::post_webhook(&client, &job.endpoint_url, &job.endpoint_secret, &event).await;
+    // An injected timeout is recorded like a real one (breaker included), but nothing is sent
+    let fault = if settings.sandbox_mode {
+        db::claim_delivery_fault(db_pool, job.endpoint_id)
+            .await
+            .map_err(|e| e.to_string())?
+    } else {
+        None
+    };
+    let url = deliver::target_url(&job.endpoint_url, job.endpoint_id, &settings.api_base_url);
+    let status = match fault {
+        Some(fault_id) => Err(format!("http error: timed out (sandbox fault {fault_id})")),
+        None => {
+            deliver::post_webhook(
+                &client,
+                &url,
+                &secret,
+                job.endpoint_signature_scheme,
+                &event,
+                settings.max_response_snippet_bytes,
+            )
+            .await
+        }
+    };
 
-    match status {
+    // Any HTTP answer means the receiver is up; only connection-level errors feed the breaker
+    match &status {
+        Ok(response) => {
+            db::record_endpoint_reachable(db_pool, job.endpoint_id)
+                .await
+                .map_err(|e| e.to_string())?;
+            db::record_delivery_response(db_pool, job.delivery_id, response)
+                .await
+                .map_err(|e| e.to_string())?;
+            if job.is_probe {
+                info!("circuit closed for endpoint {}", job.endpoint_id);
+            }
+        }
+        Err(_) => {
+            let open = db::record_endpoint_unreachable(
+                db_pool,
+                job.endpoint_id,
+                settings.circuit_failure_threshold,
+                settings.circuit_cooldown.as_secs_f64(),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            if open {
+                warn!(
+                    "circuit open for endpoint {} ({}s cool-down)",
+                    job.endpoint_id,
+                    settings.circuit_cooldown.as_secs()
+                );
+            }
+        }
+    }
+
+    match status.map(|response| response.status) {
         Ok(code) if (200..300).contains(&code) => {
             db::mark_delivery_succeeded(db_pool, job.delivery_id)
                 .await
                 .map_err(|e| e.to_string())?;
+            stats.deliveries_succeeded += 1;
+            db::record_payment_intent_acknowledged(db_pool, job.event_id)
+                .await
+                .map_err(|e| e.to_string())?;
             db::maybe_mark_event_delivered(db_pool, job.event_id)
                 .await
                 .map_err(|e| e.to_string())?;
@@ -68,12 +365,13 @@ async fn poll_once(db_pool: &PgPool, client: &Client) -> Result<(), String> {
         Ok(code) => {
             db::mark_delivery_failed(
                 db_pool,
-                job.delivery_id,
+                retry,
                 job.attempt_count,
                 format!("non-2xx status: {code}"),
             )
             .await
             .map_err(|e| e.to_string())?;
+            stats.deliveries_failed += 1;
             db::maybe_mark_event_delivered(db_pool, job.event_id)
                 .await
                 .map_err(|e| e.to_string())?;
@@ -83,9 +381,10 @@ async fn poll_once(db_pool: &PgPool, client: &Client) -> Result<(), String> {
             );
         }
         Err(err) => {
-            db::mark_delivery_failed(db_pool, job.delivery_id, job.attempt_count, err.clone())
+            db::mark_delivery_failed(db_pool, retry, job.attempt_count, err.clone())
                 .await
                 .map_err(|e| e.to_string())?;
+            stats.deliveries_failed += 1;
             db::maybe_mark_event_delivered(db_pool, job.event_id)
                 .await
                 .map_err(|e| e.to_string())?;
@@ -97,5 +396,353 @@ async fn poll_once(db_pool: &PgPool, client: &Client) -> Result<(), String> {
         }
     }
 
-    Ok(())
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use mini_stripe_types::signature::{self, SignatureScheme};
+
+    use super::*;
+
+    fn clients() -> deliver::Clients {
+        deliver::Clients::new(Vec::new()).unwrap()
+    }
+
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn the_loop_keeps_its_heartbeat_fresh(pool: PgPool) {
+        let settings = Settings {
+            heartbeat_interval: Duration::from_millis(50),
+            ..Settings::from_env()
+        };
+        let worker = tokio::spawn(run_with(pool.clone(), settings));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        worker.abort();
+
+        let row = sqlx::query!(
+            r#"
+            SELECT started_at, last_heartbeat_at
+            FROM worker_heartbeats
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(row.last_heartbeat_at > row.started_at);
+    }
+
+    // The API refuses such templates, but one written some other way must not be posted unshaped
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn a_broken_payload_template_fails_the_delivery(pool: PgPool) {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (id, url, secret, payload_template)
+            VALUES ($1, 'http://localhost:9/hooks', 'secret', '{"fields": {"txn": {"eval": "1"}}}')
+            "#,
+            uuid::Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let payload = serde_json::json!({ "payment_intent": { "id": "pi" } });
+        sqlx::query!(
+            r#"
+            INSERT INTO events_outbox (id, event_type, payload)
+            VALUES ($1, 'payment_intent.succeeded', $2)
+            "#,
+            uuid::Uuid::new_v4(),
+            payload
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut stats = LoopStats::default();
+        let delivered = poll_once(&pool, &clients(), &Settings::from_env(), &mut stats)
+            .await
+            .unwrap();
+        assert!(delivered);
+        assert_eq!(stats.deliveries_failed, 1);
+
+        let row = sqlx::query!(
+            r#"
+            SELECT d.status, d.last_error, d.response_status, e.payload
+            FROM webhook_deliveries d
+            JOIN events_outbox e ON e.id = d.event_id
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(row.status, "pending");
+        assert!(
+            row.last_error
+                .as_deref()
+                .is_some_and(|e| e.starts_with("payload template: template.txn")),
+            "{:?}",
+            row.last_error
+        );
+        // Nothing was sent, and the outbox still has the original event
+        assert_eq!(row.response_status, None);
+        assert_eq!(row.payload, payload);
+    }
+
+    fn key_ring(keys: &[(&str, &str)], encrypt_with: &str) -> KeyRing {
+        let keys = keys
+            .iter()
+            .map(|(id, byte)| format!("{id}:{}", byte.repeat(32)))
+            .collect::<Vec<_>>()
+            .join(",");
+        KeyRing::parse(&keys, Some(encrypt_with)).unwrap()
+    }
+
+    async fn insert_endpoint(pool: &PgPool, url: &str, stored_secret: &str) {
+        sqlx::query!(
+            "INSERT INTO webhook_endpoints (id, url, secret) VALUES ($1, $2, $3)",
+            uuid::Uuid::new_v4(),
+            url,
+            stored_secret
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn insert_succeeded_event(pool: &PgPool) {
+        sqlx::query!(
+            r#"
+            INSERT INTO events_outbox (id, event_type, payload)
+            VALUES ($1, 'payment_intent.succeeded', '{"payment_intent": {"id": "pi"}}')
+            "#,
+            uuid::Uuid::new_v4()
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    // Mid-rotation some secrets are still under the old key (or plaintext from before keys),
+    // while the API writes new ones under the new key. A worker holding both keys signs each
+    // delivery with the endpoint's real secret.
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn deliveries_sign_correctly_mid_rotation(pool: PgPool) {
+        let old = key_ring(&[("k1", "11")], "k1");
+        let rotating = key_ring(&[("k1", "11"), ("k2", "22")], "k2");
+        let (url, mut received) = crate::deliver::tests::capturing_receiver().await;
+
+        let secrets = ["whsec_under_k1", "whsec_under_k2", "whsec_plaintext"];
+        let stored = [
+            WEBHOOK_ENDPOINT_SECRET.encrypt(&old, secrets[0]),
+            WEBHOOK_ENDPOINT_SECRET.encrypt(&rotating, secrets[1]),
+            secrets[2].to_string(),
+        ];
+        for (i, stored) in stored.iter().enumerate() {
+            insert_endpoint(&pool, &format!("{url}?endpoint={i}"), stored).await;
+        }
+        insert_succeeded_event(&pool).await;
+
+        let settings = Settings {
+            encryption_keys: rotating,
+            ..Settings::from_env()
+        };
+        let mut stats = LoopStats::default();
+        for _ in 0..3 {
+            poll_once(&pool, &clients(), &settings, &mut stats)
+                .await
+                .unwrap();
+        }
+        assert_eq!(stats.deliveries_succeeded, 3);
+
+        let mut verified_with = Vec::new();
+        for _ in 0..3 {
+            let (signature, body) = received.recv().await.unwrap();
+            let secret = secrets.iter().find(|secret| {
+                signature::verify_signature(SignatureScheme::V1, secret, &signature, &body)
+            });
+            verified_with.push(*secret.expect("signed with one of the endpoint secrets"));
+        }
+        verified_with.sort();
+        let mut expected = secrets.to_vec();
+        expected.sort();
+        assert_eq!(verified_with, expected);
+    }
+
+    // A worker rolled out without the new key must not sign with the ciphertext
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn a_secret_under_an_unknown_key_fails_the_delivery(pool: PgPool) {
+        let api = key_ring(&[("k1", "11"), ("k2", "22")], "k2");
+        let stored = WEBHOOK_ENDPOINT_SECRET.encrypt(&api, "whsec_under_k2");
+        insert_endpoint(&pool, "http://localhost:9/hooks", &stored).await;
+        insert_succeeded_event(&pool).await;
+
+        let settings = Settings {
+            encryption_keys: key_ring(&[("k1", "11")], "k1"),
+            ..Settings::from_env()
+        };
+        let mut stats = LoopStats::default();
+        poll_once(&pool, &clients(), &settings, &mut stats)
+            .await
+            .unwrap();
+        assert_eq!(stats.deliveries_failed, 1);
+
+        let row =
+            sqlx::query!("SELECT status, last_error, response_status FROM webhook_deliveries")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(row.status, "pending");
+        assert!(
+            row.last_error
+                .as_deref()
+                .is_some_and(|e| e.starts_with("endpoint secret:")),
+            "{:?}",
+            row.last_error
+        );
+        assert_eq!(row.response_status, None);
+    }
+
+    // A webhook_timeout fault from POST /v1/admin/faults: deliveries to its endpoint fail as
+    // timed out without being sent, until it expires. Workers outside sandbox mode ignore it.
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn an_injected_timeout_fails_deliveries_until_it_expires(pool: PgPool) {
+        let (url, mut received) = crate::deliver::tests::capturing_receiver().await;
+        insert_endpoint(&pool, &url, "secret").await;
+        let fault_id = uuid::Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_delivery_faults (id, webhook_endpoint_id, expires_at)
+            SELECT $1, id, now() + interval '1 hour' FROM webhook_endpoints
+            "#,
+            fault_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        insert_succeeded_event(&pool).await;
+
+        let sandbox = Settings {
+            sandbox_mode: true,
+            ..Settings::from_env()
+        };
+        let mut stats = LoopStats::default();
+        poll_once(&pool, &clients(), &sandbox, &mut stats)
+            .await
+            .unwrap();
+        assert_eq!(stats.deliveries_failed, 1);
+        assert!(received.try_recv().is_err(), "nothing is sent");
+        let row = sqlx::query!(
+            r#"
+            SELECT d.status, d.last_error, e.consecutive_failures
+            FROM webhook_deliveries d
+            JOIN webhook_endpoints e ON e.id = d.webhook_endpoint_id
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(row.status, "pending");
+        assert_eq!(
+            row.last_error.as_deref(),
+            Some(format!("http error: timed out (sandbox fault {fault_id})").as_str())
+        );
+        // Counted like a real timeout
+        assert_eq!(row.consecutive_failures, 1);
+        let activations = sqlx::query_scalar!("SELECT activations FROM webhook_delivery_faults")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(activations, 1);
+
+        // Once expired, the retry goes out as normal
+        sqlx::query!("UPDATE webhook_delivery_faults SET expires_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query!("UPDATE webhook_deliveries SET next_attempt_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        poll_once(&pool, &clients(), &sandbox, &mut stats)
+            .await
+            .unwrap();
+        assert_eq!(stats.deliveries_succeeded, 1);
+        received.recv().await.unwrap();
+
+        // An unexpired fault means nothing to a worker outside sandbox mode
+        sqlx::query!("UPDATE webhook_delivery_faults SET expires_at = now() + interval '1 hour'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        insert_succeeded_event(&pool).await;
+        poll_once(&pool, &clients(), &Settings::from_env(), &mut stats)
+            .await
+            .unwrap();
+        assert_eq!(stats.deliveries_succeeded, 2);
+        received.recv().await.unwrap();
+    }
+
+    // The receiver requires mutual TLS: the endpoint with a certificate gets its delivery, the
+    // one without fails the handshake
+    #[sqlx::test(migrations = "../api/migrations")]
+    async fn endpoints_with_a_client_certificate_pass_mutual_tls(pool: PgPool) {
+        let pki = crate::deliver::tests::test_pki();
+        let (url, mut received) = crate::deliver::tests::mtls_receiver(&pki).await;
+        let keys = key_ring(&[("k1", "11")], "k1");
+        let bundle = format!("{}{}", pki.client_certificate, pki.client_key);
+        let with_certificate = uuid::Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_endpoints (
+              id, url, secret, client_certificate, client_certificate_fingerprint,
+              client_certificate_expires_at
+            )
+            VALUES ($1, $2, 'secret', $3, 'fp', now() + interval '1 year')
+            "#,
+            with_certificate,
+            format!("{url}?cert=1"),
+            WEBHOOK_ENDPOINT_CLIENT_CERTIFICATE.encrypt(&keys, &bundle)
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        insert_endpoint(&pool, &url, "secret").await;
+        insert_succeeded_event(&pool).await;
+
+        let settings = Settings {
+            encryption_keys: keys,
+            root_certificates: vec![Certificate::from_pem(pki.ca.as_bytes()).unwrap()],
+            ..Settings::from_env()
+        };
+        let clients = deliver::Clients::new(settings.root_certificates.clone()).unwrap();
+        let mut stats = LoopStats::default();
+        for _ in 0..2 {
+            poll_once(&pool, &clients, &settings, &mut stats)
+                .await
+                .unwrap();
+        }
+        assert_eq!(stats.deliveries_succeeded, 1);
+        assert_eq!(stats.deliveries_failed, 1);
+        received.recv().await.unwrap();
+
+        let rows =
+            sqlx::query!("SELECT webhook_endpoint_id, status, last_error FROM webhook_deliveries")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        for row in rows {
+            if row.webhook_endpoint_id == with_certificate {
+                assert_eq!(row.status, "succeeded");
+            } else {
+                assert_eq!(row.status, "pending");
+                assert!(
+                    row.last_error
+                        .as_deref()
+                        .is_some_and(|e| e.starts_with("http error:")),
+                    "{:?}",
+                    row.last_error
+                );
+            }
+        }
+        assert_eq!(clients.cached_certificate_clients(), 1);
+    }
 }